@@ -0,0 +1,128 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use cid::Cid;
+
+use ipfs_block::{Block, IpfsBlock};
+use ipfs_blockstore::{BlockStore, BlockStoreError};
+use plum_types::Gas;
+
+/// The exit code of the message which runs out of gas.
+pub const SYS_ERR_OUT_OF_GAS: u8 = 7;
+
+/// The error returned when the gas budget is exhausted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutOfGas {
+    /// The name of the operation that exhausted the gas budget.
+    pub operation: &'static str,
+    /// The gas required by the operation.
+    pub required: Gas,
+    /// The gas available before the operation.
+    pub available: Gas,
+}
+
+impl OutOfGas {
+    /// Return the exit code of the aborted message.
+    pub fn exit_code(&self) -> u8 {
+        SYS_ERR_OUT_OF_GAS
+    }
+}
+
+impl fmt::Display for OutOfGas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not enough gas for {}: required {}, available {}",
+            self.operation, self.required, self.available
+        )
+    }
+}
+
+impl std::error::Error for OutOfGas {}
+
+/// ChargeGas charges the gas of the operations of the actor methods against the gas budget
+/// of the invoking message, it's implemented by the gas charger of the VM.
+pub trait ChargeGas {
+    /// Charge the gas of loading an object of `data_size` bytes from the IPLD store.
+    fn charge_ipld_get(&mut self, data_size: usize) -> Result<(), OutOfGas>;
+
+    /// Charge the gas of storing an object of `data_size` bytes into the IPLD store.
+    fn charge_ipld_put(&mut self, data_size: usize) -> Result<(), OutOfGas>;
+}
+
+/// GasBlockStore charges the gas of every block loaded from or stored into the wrapped block
+/// store before the I/O, so the IPLD structures on it, like the AMT, are charged per node.
+///
+/// The block store errors can't carry the `OutOfGas`, the operation exhausting the gas budget
+/// fails with `BlockStoreError::Custom`, and the `OutOfGas` is kept for `take_out_of_gas`.
+pub struct GasBlockStore<'a, BS, G> {
+    store: &'a mut BS,
+    gas: RefCell<&'a mut G>,
+    out_of_gas: RefCell<Option<OutOfGas>>,
+}
+
+impl<'a, BS: BlockStore, G: ChargeGas> GasBlockStore<'a, BS, G> {
+    /// Create a new GasBlockStore charging the blocks of the `store` to the `gas` budget.
+    pub fn new(store: &'a mut BS, gas: &'a mut G) -> Self {
+        Self {
+            store,
+            gas: RefCell::new(gas),
+            out_of_gas: RefCell::new(None),
+        }
+    }
+
+    /// Take the error of the operation which exhausted the gas budget, if any.
+    pub fn take_out_of_gas(&self) -> Option<OutOfGas> {
+        self.out_of_gas.borrow_mut().take()
+    }
+
+    fn charge<F>(&self, f: F) -> Result<(), BlockStoreError>
+    where
+        F: FnOnce(&mut G) -> Result<(), OutOfGas>,
+    {
+        let mut gas = self.gas.borrow_mut();
+        f(&mut **gas).map_err(|err| {
+            let custom = BlockStoreError::Custom(err.to_string());
+            *self.out_of_gas.borrow_mut() = Some(err);
+            custom
+        })
+    }
+}
+
+impl<'a, BS: BlockStore, G: ChargeGas> BlockStore for GasBlockStore<'a, BS, G> {
+    fn delete_block(&mut self, cid: &Cid) -> Result<(), BlockStoreError> {
+        self.store.delete_block(cid)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool, BlockStoreError> {
+        self.store.has(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<IpfsBlock, BlockStoreError> {
+        let size = self.store.get_size(cid)?;
+        self.charge(|gas| gas.charge_ipld_get(size))?;
+        self.store.get(cid)
+    }
+
+    fn get_size(&self, cid: &Cid) -> Result<usize, BlockStoreError> {
+        self.store.get_size(cid)
+    }
+
+    fn put<B: Block>(&mut self, block: B) -> Result<(), BlockStoreError> {
+        self.charge(|gas| gas.charge_ipld_put(block.data().len()))?;
+        self.store.put(block)
+    }
+
+    fn put_many<B: Block>(&mut self, blocks: &[B]) -> Result<(), BlockStoreError> {
+        for block in blocks {
+            self.charge(|gas| gas.charge_ipld_put(block.data().len()))?;
+        }
+        self.store.put_many(blocks)
+    }
+
+    fn hash_on_read(&mut self, enabled: bool) {
+        self.store.hash_on_read(enabled)
+    }
+}
@@ -1,8 +1,31 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+use std::collections::BTreeSet;
+
+use cid::Cid;
 use minicbor::{decode, encode, Decoder, Encoder};
 
-use plum_sector::SectorNumber;
+use ipfs_blockstore::BlockStore;
+use ipld_adt::{AdtError, Amt};
+use plum_bitfield::BitField;
+use plum_sector::{SectorNumber, StoragePower};
+use plum_types::{ChainEpoch, TokenAmount};
+
+use super::policy::pledge_penalty_for_sector_termination;
+use super::state::{SectorOnChainInfo, State};
+use crate::builtin::gas::{ChargeGas, GasBlockStore, OutOfGas};
+
+/// The error type of the miner actor methods.
+#[doc(hidden)]
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum MinerError {
+    #[error("sector {0} is not active, it's terminated or doesn't exist")]
+    SectorNotActive(SectorNumber),
+    #[error("state error: {0}")]
+    State(#[from] AdtError),
+    #[error("{0}")]
+    OutOfGas(#[from] OutOfGas),
+}
 
 #[doc(hidden)]
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -30,3 +53,123 @@ impl<'b> decode::Decode<'b> for ProveCommitSectorParams {
         })
     }
 }
+
+#[doc(hidden)]
+#[derive(Clone, Debug, PartialEq, Eq, minicbor::Encode, minicbor::Decode)]
+#[cbor(array)]
+pub struct TerminateSectorsParams {
+    #[n(0)]
+    pub sectors: BitField,
+}
+
+/// The outcome of terminating the sectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectorTermination {
+    /// The change of the raw byte power of the miner, which is negative.
+    pub power_delta: StoragePower,
+    /// The penalty charged from the balance of the miner, to be burnt.
+    pub penalty: TokenAmount,
+}
+
+/// Terminate the active sectors of the miner early at `current_epoch`.
+///
+/// The sectors are removed from the sectors, expirations and faults of the state and from
+/// the deadlines, then the termination penalty is deducted from the `balance` of the miner,
+/// unlocking the locked funds first. Nothing is changed if any sector is not active.
+///
+/// Every block of the state loaded from or stored into the `store` is charged to the `gas`
+/// budget before the I/O, the method aborts with `MinerError::OutOfGas` once it's exhausted,
+/// and the partial changes are to be discarded by the caller.
+pub fn terminate_sectors<BS: BlockStore, G: ChargeGas>(
+    store: &mut BS,
+    gas: &mut G,
+    state: &mut State,
+    balance: &mut TokenAmount,
+    params: &TerminateSectorsParams,
+    current_epoch: ChainEpoch,
+) -> Result<SectorTermination, MinerError> {
+    let mut store = GasBlockStore::new(store, gas);
+    terminate(&mut store, state, balance, params, current_epoch).map_err(|err| {
+        match store.take_out_of_gas() {
+            Some(out_of_gas) => MinerError::OutOfGas(out_of_gas),
+            None => err,
+        }
+    })
+}
+
+fn terminate<BS: BlockStore>(
+    store: &mut BS,
+    state: &mut State,
+    balance: &mut TokenAmount,
+    params: &TerminateSectorsParams,
+    current_epoch: ChainEpoch,
+) -> Result<SectorTermination, MinerError> {
+    let terminated: &BTreeSet<SectorNumber> = &params.sectors;
+    let mut sectors = Amt::<_, SectorOnChainInfo>::load(&mut *store, &state.sectors)?;
+    let mut penalty = TokenAmount::default();
+    for sector_number in terminated {
+        let sector = sectors
+            .get(*sector_number)?
+            .ok_or(MinerError::SectorNotActive(*sector_number))?;
+        penalty +=
+            pledge_penalty_for_sector_termination(&sector, state.info.sector_size, current_epoch);
+    }
+    for sector_number in terminated {
+        sectors.delete(*sector_number)?;
+    }
+    state.sectors = sectors.flush()?;
+
+    state.sector_expirations = remove_from_bitfields(store, &state.sector_expirations, terminated)?;
+    state.fault_epochs = remove_from_bitfields(store, &state.fault_epochs, terminated)?;
+    let mut deadlines = state.load_deadlines(store)?;
+    for sector_number in terminated {
+        state.new_sectors.remove(sector_number);
+        state.faults.remove(sector_number);
+        state.recoveries.remove(sector_number);
+        deadlines.due.remove(sector_number);
+    }
+    state.save_deadlines(store, &deadlines)?;
+
+    // the penalty can't exceed the balance, the locked funds are unlocked to pay it first.
+    let penalty = penalty.min(balance.clone());
+    let unlocked = state.locked_funds.clone().min(penalty.clone());
+    state.locked_funds -= unlocked;
+    *balance -= &penalty;
+
+    let power_delta = -(StoragePower::from(state.info.sector_size) * terminated.len() as u64);
+    Ok(SectorTermination {
+        power_delta,
+        penalty,
+    })
+}
+
+// Remove the sectors from the bitfields of the AMT, the emptied bitfields are deleted.
+fn remove_from_bitfields<BS: BlockStore>(
+    store: &mut BS,
+    root: &Cid,
+    sectors: &BTreeSet<SectorNumber>,
+) -> Result<Cid, MinerError> {
+    let mut bitfields = Amt::<_, BitField>::load(&mut *store, root)?;
+    let mut changed = vec![];
+    bitfields.for_each(|i, bitfield| {
+        if bitfield
+            .iter()
+            .any(|sector_number| sectors.contains(sector_number))
+        {
+            let bitfield = bitfield
+                .difference(sectors)
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            changed.push((i, BitField::from(bitfield)));
+        }
+        Ok(())
+    })?;
+    for (i, bitfield) in changed {
+        if bitfield.is_empty() {
+            bitfields.delete(i)?;
+        } else {
+            bitfields.set(i, bitfield)?;
+        }
+    }
+    Ok(bitfields.flush()?)
+}
@@ -2,8 +2,14 @@
 
 use serde::{Deserialize, Serialize};
 
+use plum_address::Address;
 use plum_types::ChainEpoch;
 
+use super::policy::{
+    FAULT_DECLARATION_CUTOFF, W_POST_CHALLENGE_LOOKBACK, W_POST_CHALLENGE_WINDOW,
+    W_POST_PERIOD_DEADLINES, W_POST_PROVING_PERIOD,
+};
+
 /// Deadline calculations with respect to a current epoch.
 /// "Deadline" refers to the window during which proofs may be submitted.
 /// Windows are non-overlapping ranges [open, close), but the challenge epoch for a window occurs
@@ -21,3 +27,83 @@ pub struct DeadlineInfo {
     pub challenge: ChainEpoch, // Epoch at which to sample the chain for challenge (< open).
     pub fault_cutoff: ChainEpoch, // First epoch at which a fault declaration is rejected (< open).
 }
+
+impl DeadlineInfo {
+    /// Calculate the deadline info of the `index` deadline of the proving period starting at `period_start`.
+    pub fn new(period_start: ChainEpoch, index: u64, current_epoch: ChainEpoch) -> Self {
+        let open = period_start + (index * W_POST_CHALLENGE_WINDOW) as ChainEpoch;
+        Self {
+            current_epoch,
+            period_start,
+            index,
+            open,
+            close: open + W_POST_CHALLENGE_WINDOW as ChainEpoch,
+            challenge: open - W_POST_CHALLENGE_LOOKBACK,
+            fault_cutoff: open - FAULT_DECLARATION_CUTOFF,
+        }
+    }
+
+    /// Whether the proving period has begun.
+    pub fn period_started(&self) -> bool {
+        self.current_epoch >= self.period_start
+    }
+
+    /// Whether the proving period has elapsed.
+    pub fn period_elapsed(&self) -> bool {
+        self.current_epoch > self.period_end()
+    }
+
+    /// The last epoch in the proving period.
+    pub fn period_end(&self) -> ChainEpoch {
+        self.period_start + W_POST_PROVING_PERIOD as ChainEpoch - 1
+    }
+}
+
+/// Calculate the deadline at some epoch for a proving period starting at `period_start`.
+pub fn compute_proving_period_deadline(
+    period_start: ChainEpoch,
+    current_epoch: ChainEpoch,
+) -> DeadlineInfo {
+    let period_progress = current_epoch - period_start;
+    if period_progress >= W_POST_PROVING_PERIOD as ChainEpoch {
+        // Proving period has completely elapsed.
+        return DeadlineInfo::new(period_start, W_POST_PERIOD_DEADLINES, current_epoch);
+    }
+    let index = if period_progress < 0 {
+        // Period not yet started.
+        0
+    } else {
+        period_progress as u64 / W_POST_CHALLENGE_WINDOW
+    };
+    DeadlineInfo::new(period_start, index, current_epoch)
+}
+
+/// Compute the offset of the proving period of the miner `addr` created at `current_epoch`,
+/// in [0, W_POST_PROVING_PERIOD).
+///
+/// The offset is derived from the hash of the address and the epoch, so that the proving
+/// periods (and thus the deadlines) of the miners are spread across the whole period
+/// instead of all falling due at the same epochs.
+pub fn assign_proving_period_offset(addr: &Address, current_epoch: ChainEpoch) -> ChainEpoch {
+    let mut seed =
+        minicbor::to_vec(addr).expect("CBOR serialization of Address shouldn't be failed");
+    seed.extend_from_slice(&current_epoch.to_be_bytes());
+    let digest = plum_hashing::blake2b_256(&seed);
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(offset) % W_POST_PROVING_PERIOD) as ChainEpoch
+}
+
+/// Compute the first epoch of the proving period with the given `offset` that starts
+/// strictly after `current_epoch`.
+pub fn next_proving_period_start(current_epoch: ChainEpoch, offset: ChainEpoch) -> ChainEpoch {
+    let period = W_POST_PROVING_PERIOD as ChainEpoch;
+    let current_modulus = current_epoch.rem_euclid(period);
+    // How far ahead is current epoch from previous offset boundary.
+    let period_progress = if current_modulus >= offset {
+        current_modulus - offset
+    } else {
+        period - (offset - current_modulus)
+    };
+    current_epoch - period_progress + period
+}
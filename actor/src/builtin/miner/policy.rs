@@ -1,7 +1,10 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use crate::builtin::network::{EPOCH_DURATION_SECONDS, EPOCH_IN_DAY};
-use plum_types::ChainEpoch;
+use plum_sector::SectorSize;
+use plum_types::{ChainEpoch, TokenAmount};
+
+use super::state::SectorOnChainInfo;
 
 /// The period over which all a miner's active sectors will be challenged.
 pub const W_POST_PROVING_PERIOD: u64 = EPOCH_IN_DAY; // 24 hours
@@ -9,9 +12,31 @@ pub const W_POST_PROVING_PERIOD: u64 = EPOCH_IN_DAY; // 24 hours
 pub const W_POST_CHALLENGE_WINDOW: u64 = 3600 / EPOCH_DURATION_SECONDS; // An hour (=24 per day)
 /// The number of non-overlapping PoSt deadlines in each proving period.
 pub const W_POST_PERIOD_DEADLINES: u64 = W_POST_PROVING_PERIOD / W_POST_CHALLENGE_WINDOW;
+/// Lookback from the deadline's challenge window opening from which to sample chain randomness for the challenge seed.
+pub const W_POST_CHALLENGE_LOOKBACK: ChainEpoch = 20;
+/// Minimum period before a deadline's challenge window opens that a fault must be declared for that deadline.
+pub const FAULT_DECLARATION_CUTOFF: ChainEpoch = W_POST_CHALLENGE_LOOKBACK + 50;
 
 /// The maximum number of new sectors that may be staged by a miner during a single proving period.
 pub const NEW_SECTORS_PER_PERIOD_MAX: u64 = 128 << 10;
 
 /// An approximation to chain state finality (should include message propagation time as well).
 pub const CHAIN_FINALITYISH: ChainEpoch = 500; // PARAM_FINISH
+
+/// The maximum number of the remaining epochs of a sector charged by the termination penalty.
+pub const TERMINATION_PENALTY_LIFETIME_CAP: ChainEpoch = 70 * EPOCH_IN_DAY as ChainEpoch;
+/// The termination penalty charged per byte of the sector per remaining epoch (attoFIL).
+pub const TERMINATION_PENALTY_PER_BYTE_EPOCH: u64 = 1;
+
+/// Compute the penalty of terminating the `sector` of `sector_size` at `current_epoch`,
+/// which is proportional to the size and the remaining lifetime (capped) of the sector.
+pub fn pledge_penalty_for_sector_termination(
+    sector: &SectorOnChainInfo,
+    sector_size: SectorSize,
+    current_epoch: ChainEpoch,
+) -> TokenAmount {
+    let remaining = (sector.info.expiration - current_epoch)
+        .max(0)
+        .min(TERMINATION_PENALTY_LIFETIME_CAP) as u64;
+    TokenAmount::from(sector_size) * remaining * TERMINATION_PENALTY_PER_BYTE_EPOCH
+}
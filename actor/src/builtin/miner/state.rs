@@ -3,14 +3,23 @@
 use serde::{Deserialize, Serialize};
 
 use cid::Cid;
+use ipfs_block::IpfsBlock;
+use ipfs_blockstore::BlockStore;
+use ipld_adt::{AdtError, Amt};
 use minicbor::{decode, encode, Decoder, Encoder};
 use plum_address::Address;
 use plum_bigint::bigint_json;
 use plum_bitfield::BitField;
 use plum_peerid::PeerId;
-use plum_sector::{RegisteredProof, SectorNumber, SectorSize};
+use plum_sector::{RegisteredProof, SectorNumber, SectorSize, StoragePower};
 use plum_types::{ChainEpoch, DealId, DealWeight, TokenAmount};
 
+use super::deadlines::{
+    assign_proving_period_offset, compute_proving_period_deadline, next_proving_period_start,
+    DeadlineInfo,
+};
+use super::policy::W_POST_PERIOD_DEADLINES;
+
 // Balance of Miner Actor should be greater than or equal to
 // the sum of pre_commit_deposits and locked_funds.
 // Excess balance as computed by st.GetAvailableBalance will be
@@ -119,6 +128,84 @@ impl<'b> minicbor::Decode<'b> for State {
         })
     }
 }
+
+impl State {
+    /// Create the state of the miner `miner` constructed at `current_epoch`.
+    ///
+    /// The first proving period starts at the per-miner offset assigned by the address,
+    /// see `assign_proving_period_offset`.
+    pub fn new(
+        miner: &Address,
+        info: MinerInfo,
+        current_epoch: ChainEpoch,
+        empty_array: Cid,
+        empty_map: Cid,
+        empty_deadlines: Cid,
+    ) -> Self {
+        let offset = assign_proving_period_offset(miner, current_epoch);
+        let proving_period_start = next_proving_period_start(current_epoch, offset);
+        State {
+            info,
+            pre_commit_deposits: TokenAmount::default(),
+            locked_funds: TokenAmount::default(),
+            vesting_funds: empty_array.clone(),
+            pre_committed_sectors: empty_map,
+            sectors: empty_array.clone(),
+            proving_period_start,
+            new_sectors: BitField::new(),
+            sector_expirations: empty_array.clone(),
+            deadlines: empty_deadlines,
+            faults: BitField::new(),
+            fault_epochs: empty_array,
+            recoveries: BitField::new(),
+            post_submissions: BitField::new(),
+        }
+    }
+
+    /// Return the deadline of the current proving period of the miner at `current_epoch`.
+    pub fn deadline_info(&self, current_epoch: ChainEpoch) -> DeadlineInfo {
+        compute_proving_period_deadline(self.proving_period_start, current_epoch)
+    }
+
+    /// Return the deadline at which the sector `sector_number` is due for PoSt in the
+    /// current proving period of the miner.
+    pub fn assign_deadline(
+        &self,
+        sector_number: SectorNumber,
+        current_epoch: ChainEpoch,
+    ) -> DeadlineInfo {
+        DeadlineInfo::new(
+            self.proving_period_start,
+            sector_number % W_POST_PERIOD_DEADLINES,
+            current_epoch,
+        )
+    }
+
+    /// Return the raw byte power of the active sectors of the miner.
+    pub fn raw_byte_power<BS: BlockStore>(&self, store: &mut BS) -> Result<StoragePower, AdtError> {
+        let sectors = Amt::<_, SectorOnChainInfo>::load(store, &self.sectors)?;
+        Ok(StoragePower::from(self.info.sector_size) * sectors.count())
+    }
+
+    /// Load the deadlines of the miner from the block store.
+    pub fn load_deadlines<BS: BlockStore>(&self, store: &BS) -> Result<Deadlines, AdtError> {
+        let block = store.get(&self.deadlines)?;
+        minicbor::decode(block.data())
+            .map_err(|err| AdtError::Decode(self.deadlines.clone(), err.to_string()))
+    }
+
+    /// Store the deadlines of the miner into the block store.
+    pub fn save_deadlines<BS: BlockStore>(
+        &mut self,
+        store: &mut BS,
+        deadlines: &Deadlines,
+    ) -> Result<(), AdtError> {
+        let block = IpfsBlock::new(deadlines);
+        self.deadlines = block.cid().clone();
+        store.put(block)?;
+        Ok(())
+    }
+}
 ///
 #[doc(hidden)]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -248,12 +335,42 @@ pub struct SectorOnChainInfo {
     pub verified_deal_weight: DealWeight,
 }
 
+impl minicbor::Encode for SectorOnChainInfo {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(4)?
+            .encode(&self.info)?
+            .encode(&self.activation_epoch)?
+            .encode(&plum_bigint::BigIntRefWrapper::from(&self.deal_weight))?
+            .encode(&plum_bigint::BigIntRefWrapper::from(
+                &self.verified_deal_weight,
+            ))?
+            .ok()
+    }
+}
+
+impl<'b> minicbor::Decode<'b> for SectorOnChainInfo {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        let array_len = d.array()?;
+        assert_eq!(array_len, Some(4));
+        Ok(SectorOnChainInfo {
+            info: d.decode::<SectorPreCommitInfo>()?,
+            activation_epoch: d.decode::<ChainEpoch>()?,
+            deal_weight: d.decode::<plum_bigint::BigIntWrapper>()?.into_inner(),
+            verified_deal_weight: d.decode::<plum_bigint::BigIntWrapper>()?.into_inner(),
+        })
+    }
+}
+
 ///
 #[doc(hidden)]
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, minicbor::Encode, minicbor::Decode,
+)]
 #[serde(rename_all = "PascalCase")]
+#[cbor(array)]
 pub struct Deadlines {
     // A bitfield of sector numbers due at each deadline.
     // The sectors for each deadline are logically grouped into sequential partitions for proving.
+    #[n(0)]
     pub due: BitField, // [WPoStPeriodDeadlines]*abi.BitField
 }
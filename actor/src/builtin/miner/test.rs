@@ -1 +1,260 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use cid::Cid;
+use ipfs_blockstore::MemoryBlockStore;
+use ipld_adt::Amt;
+use plum_address::Address;
+use plum_bigint::BigInt;
+use plum_bitfield::BitField;
+use plum_peerid::PeerId;
+use plum_sector::RegisteredProof;
+use plum_types::{ChainEpoch, Gas};
+
+use super::*;
+use crate::builtin::gas::{ChargeGas, OutOfGas, SYS_ERR_OUT_OF_GAS};
+
+fn new_miner(id: u64, current_epoch: ChainEpoch) -> State {
+    let cid: Cid = "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+        .parse()
+        .unwrap();
+    let owner = Address::new_id_addr(100).unwrap();
+    let info = MinerInfo {
+        owner: owner.clone(),
+        worker: owner.clone(),
+        pending_worker_key: WorkerKeyChange {
+            new_worker: owner,
+            effective_at: 0,
+        },
+        peer_id: PeerId::random(),
+        seal_proof_type: RegisteredProof::StackedDRG2KiBSeal,
+        sector_size: 2048,
+        window_post_partition_sectors: 2,
+    };
+    let miner = Address::new_id_addr(id).unwrap();
+    State::new(&miner, info, current_epoch, cid.clone(), cid.clone(), cid)
+}
+
+#[test]
+fn test_proving_period_offset_per_miner() {
+    let current_epoch = 0;
+    let (miner1, miner2) = (1000, 1001);
+    let offset1 = assign_proving_period_offset(&Address::new_id_addr(miner1).unwrap(), 0);
+    let offset2 = assign_proving_period_offset(&Address::new_id_addr(miner2).unwrap(), 0);
+    assert_ne!(offset1, offset2);
+
+    let state1 = new_miner(miner1, current_epoch);
+    let state2 = new_miner(miner2, current_epoch);
+    let period = W_POST_PROVING_PERIOD as ChainEpoch;
+    // the first proving period starts strictly after the current epoch.
+    let period_start = |offset| if offset == 0 { period } else { offset };
+    assert_eq!(state1.proving_period_start, period_start(offset1));
+    assert_eq!(state2.proving_period_start, period_start(offset2));
+
+    // the same deadline of the two miners opens at the epochs differing by the offsets.
+    let expected = period_start(offset2) - period_start(offset1);
+    for sector in 0..W_POST_PERIOD_DEADLINES {
+        let deadline1 = state1.assign_deadline(sector, current_epoch);
+        let deadline2 = state2.assign_deadline(sector, current_epoch);
+        assert_eq!(deadline1.index, deadline2.index);
+        assert_eq!(deadline2.open - deadline1.open, expected);
+        assert_eq!(
+            deadline1.close - deadline1.open,
+            W_POST_CHALLENGE_WINDOW as ChainEpoch
+        );
+    }
+
+    // the first deadline of the miner opens at the start of its proving period.
+    let deadline = state1.deadline_info(state1.proving_period_start);
+    assert_eq!(deadline.index, 0);
+    assert_eq!(deadline.open, state1.proving_period_start);
+    assert!(deadline.period_started());
+    assert!(!state1.deadline_info(current_epoch).period_started());
+    assert!(state1
+        .deadline_info(state1.proving_period_start + period)
+        .period_elapsed());
+}
+
+fn sector(sector_number: u64, expiration: ChainEpoch) -> SectorOnChainInfo {
+    SectorOnChainInfo {
+        info: SectorPreCommitInfo {
+            registered_proof: RegisteredProof::StackedDRG2KiBSeal,
+            sector_number,
+            sealed_cid: "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+                .parse()
+                .unwrap(),
+            seal_rand_epoch: 0,
+            deal_ids: vec![],
+            expiration,
+        },
+        activation_epoch: 0,
+        deal_weight: BigInt::from(0),
+        verified_deal_weight: BigInt::from(0),
+    }
+}
+
+// Charge a unit of gas per operation within the `limit`, and record the charged operations.
+struct UnitGas {
+    limit: usize,
+    charged: Vec<&'static str>,
+}
+
+impl UnitGas {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            charged: vec![],
+        }
+    }
+
+    fn charge(&mut self, operation: &'static str) -> Result<(), OutOfGas> {
+        if self.charged.len() >= self.limit {
+            return Err(OutOfGas {
+                operation,
+                required: Gas::from(1),
+                available: Gas::from(0),
+            });
+        }
+        self.charged.push(operation);
+        Ok(())
+    }
+}
+
+impl ChargeGas for UnitGas {
+    fn charge_ipld_get(&mut self, _data_size: usize) -> Result<(), OutOfGas> {
+        self.charge("OnIpldGet")
+    }
+
+    fn charge_ipld_put(&mut self, _data_size: usize) -> Result<(), OutOfGas> {
+        self.charge("OnIpldPut")
+    }
+}
+
+// Return the state of a miner with the active sectors 1 and 2, both in the deadlines.
+fn miner_with_sectors(store: &mut MemoryBlockStore, expiration: ChainEpoch) -> State {
+    let mut sectors = Amt::new(&mut *store);
+    sectors.set(1, sector(1, expiration)).unwrap();
+    sectors.set(2, sector(2, expiration)).unwrap();
+    let sectors = sectors.flush().unwrap();
+    let mut expirations = Amt::new(&mut *store);
+    expirations
+        .set(expiration as u64, BitField::from(vec![1, 2]))
+        .unwrap();
+    let expirations = expirations.flush().unwrap();
+    let fault_epochs = Amt::<_, BitField>::new(&mut *store).flush().unwrap();
+
+    let mut state = new_miner(1000, 0);
+    state.sectors = sectors;
+    state.sector_expirations = expirations;
+    state.fault_epochs = fault_epochs;
+    state.faults = BitField::from(vec![1]);
+    state.recoveries = BitField::from(vec![1]);
+    state.locked_funds = BigInt::from(1000);
+    let deadlines = Deadlines {
+        due: BitField::from(vec![1, 2]),
+    };
+    state.save_deadlines(store, &deadlines).unwrap();
+    state
+}
+
+#[test]
+fn test_terminate_sectors() {
+    let mut store = MemoryBlockStore::new();
+    let expiration = 1000;
+    let mut state = miner_with_sectors(&mut store, expiration);
+    let power = state.raw_byte_power(&mut store).unwrap();
+    assert_eq!(power, BigInt::from(2 * 2048));
+
+    let current_epoch = 100;
+    let mut balance = BigInt::from(10_000_000);
+    let params = TerminateSectorsParams {
+        sectors: BitField::from(vec![1]),
+    };
+    let mut gas = UnitGas::new(usize::MAX);
+    let termination = terminate_sectors(
+        &mut store,
+        &mut gas,
+        &mut state,
+        &mut balance,
+        &params,
+        current_epoch,
+    )
+    .unwrap();
+    // every node is charged: the sectors AMT and the empty fault epochs AMT are single roots,
+    // the expirations AMT has 3 nodes under its root loaded by both `for_each` and `set`.
+    let count = |operation| gas.charged.iter().filter(|op| **op == operation).count();
+    assert_eq!(count("OnIpldGet"), 1 + (1 + 3 + 3) + 1 + 1);
+    assert_eq!(count("OnIpldPut"), 1 + (1 + 3) + 1 + 1);
+
+    // the power drops and the penalty is charged.
+    assert_eq!(termination.power_delta, BigInt::from(-2048));
+    assert_eq!(
+        state.raw_byte_power(&mut store).unwrap(),
+        power + &termination.power_delta
+    );
+    let penalty = BigInt::from(2048 * (expiration - current_epoch));
+    assert_eq!(termination.penalty, penalty);
+    assert_eq!(balance, BigInt::from(10_000_000) - &penalty);
+    assert_eq!(state.locked_funds, BigInt::from(0));
+
+    // the sector is gone from all the bitfields.
+    let sectors = Amt::<_, SectorOnChainInfo>::load(&mut store, &state.sectors).unwrap();
+    assert_eq!(sectors.get(1).unwrap(), None);
+    assert_eq!(sectors.get(2).unwrap(), Some(sector(2, expiration)));
+    let expirations = Amt::<_, BitField>::load(&mut store, &state.sector_expirations).unwrap();
+    assert_eq!(
+        expirations.get(expiration as u64).unwrap(),
+        Some(BitField::from(vec![2]))
+    );
+    assert!(state.faults.is_empty());
+    assert!(state.recoveries.is_empty());
+    assert_eq!(
+        state.load_deadlines(&store).unwrap().due,
+        BitField::from(vec![2])
+    );
+
+    // terminating the terminated or nonexistent sectors fails.
+    for sector_number in &[1, 9] {
+        let params = TerminateSectorsParams {
+            sectors: BitField::from(vec![*sector_number]),
+        };
+        match terminate_sectors(
+            &mut store,
+            &mut gas,
+            &mut state,
+            &mut balance,
+            &params,
+            current_epoch,
+        ) {
+            Err(MinerError::SectorNotActive(n)) => assert_eq!(n, *sector_number),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_terminate_sectors_out_of_gas() {
+    let mut store = MemoryBlockStore::new();
+    let mut state = miner_with_sectors(&mut store, 1000);
+    let mut balance = BigInt::from(10_000_000);
+    let params = TerminateSectorsParams {
+        sectors: BitField::from(vec![1]),
+    };
+
+    // a far sector grows the sectors AMT, so the sector 1 is under an interior node.
+    let mut sectors = Amt::load(&mut store, &state.sectors).unwrap();
+    sectors.set(100, sector(100, 1000)).unwrap();
+    state.sectors = sectors.flush().unwrap();
+
+    // the budget covers the load of the root, but not of the interior node under it.
+    let mut gas = UnitGas::new(1);
+    match terminate_sectors(&mut store, &mut gas, &mut state, &mut balance, &params, 100) {
+        Err(MinerError::OutOfGas(err)) => {
+            assert_eq!(err.operation, "OnIpldGet");
+            assert_eq!(err.exit_code(), SYS_ERR_OUT_OF_GAS);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(gas.charged, vec!["OnIpldGet"]);
+    // the method aborts before deducting the penalty.
+    assert_eq!(balance, BigInt::from(10_000_000));
+}
@@ -9,6 +9,8 @@ pub mod account;
 ///
 pub mod cron;
 ///
+pub mod gas;
+///
 pub mod init;
 ///
 pub mod market;
@@ -3,6 +3,6 @@
 mod builtin;
 
 pub use self::builtin::{
-    account, cron, init, market, methods::*, miner, multisig, network::*, paych, power, reward,
-    system, verifreg,
+    account, cron, gas::*, init, market, methods::*, miner, multisig, network::*, paych, power,
+    reward, system, verifreg,
 };
@@ -5,9 +5,48 @@ pub enum RpcError {
     #[error("{0}")]
     Json(#[from] serde_json::Error),
     #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
     Http(#[from] reqwest::Error),
     #[error("{0}")]
     WebSocket(#[from] async_tungstenite::tungstenite::Error),
-    #[error("{0}")]
-    RpcResponse(#[from] crate::types::Error),
+    #[error("JSON-RPC error {code}: {message}")]
+    JsonRpc {
+        /// The error code, e.g. -32601 for "method not found".
+        code: i64,
+        /// The short description of the error.
+        message: String,
+        /// The additional information about the error.
+        data: Option<crate::types::Value>,
+    },
+    #[error("rate limit exceeded for method `{0}`")]
+    RateLimited(String),
+    #[error("invalid handshake header: {0}")]
+    InvalidHeader(String),
+    #[error("invalid TLS config: {0}")]
+    InvalidTlsConfig(String),
+    #[error("transport disconnected: {0}")]
+    Disconnected(String),
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(String),
+    #[error("request timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("the WebSocket connection was reset before the response")]
+    ConnectionReset,
+    #[error("the transport doesn't support pub-sub subscriptions")]
+    PubsubUnsupported,
+    #[error("the transport is closed")]
+    Closed,
+    #[error("the transport is not connected yet")]
+    NotConnected,
+}
+
+impl From<crate::types::Error> for RpcError {
+    fn from(err: crate::types::Error) -> Self {
+        RpcError::JsonRpc {
+            code: err.code.code(),
+            message: err.message,
+            data: err.data,
+        }
+    }
 }
@@ -6,6 +6,19 @@ mod transports;
 mod types;
 
 pub use self::errors::{Result, RpcError};
+#[cfg(all(unix, feature = "ipc"))]
+pub use self::transports::IpcTransport;
+pub use self::transports::DEFAULT_UNSUBSCRIBE_METHOD;
 pub use self::transports::{BatchTransport, PubsubTransport, Transport};
-pub use self::transports::{HttpTransport, NotificationStream, WebSocketTransport};
+pub use self::transports::{CloseReason, EventStream, NotificationStream, StreamEvent};
+pub use self::transports::{ConnectionState, ConnectionStateStream};
+pub use self::transports::{Heartbeat, PreConnectPolicy, ReconnectPolicy, TlsConfig};
+pub use self::transports::{HttpTransport, OverflowPolicy};
+#[cfg(feature = "rate-limit")]
+pub use self::transports::{RateLimit, RateLimitPolicy, RateLimitedTransport};
+pub use self::transports::{ReplaySubscription, DEFAULT_REPLAY_CAPACITY};
+pub use self::transports::{RequestHandler, WebSocketTransport, WebSocketTransportBuilder};
+pub use self::transports::{TimeoutTransport, WithTimeout};
 pub use self::types::*;
+
+pub use async_tungstenite::tungstenite::http::HeaderMap;
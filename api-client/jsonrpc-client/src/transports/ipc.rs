@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_tungstenite::tungstenite::protocol::Message;
+use futures::channel::mpsc;
+use futures::future;
+use futures::stream::StreamExt;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::task;
+
+use crate::errors::Result;
+use crate::transports::ws::{
+    close_subscription, decode_events, decode_notifications, handle_incoming_msg, reset_pendings,
+    send_request, subscription_stream, unsubscribe, Handler, Methods, Pendings, RecentMethods,
+    Renewals, Subscriptions, WebSocketReceiver, WebSocketSender,
+};
+use crate::transports::{BatchTransport, EventStream, NotificationStream, PubsubTransport};
+use crate::transports::{CloseReason, TimeoutTransport, Transport};
+use crate::transports::{
+    OverflowPolicy, DEFAULT_METHOD_HISTORY_CAPACITY, DEFAULT_REQUEST_TIMEOUT,
+    DEFAULT_SUBSCRIPTION_CAPACITY,
+};
+use crate::types::{
+    Call, Error, MethodCall, Params, Request, RequestId, Response, SubscriptionId, Value, Version,
+};
+
+/// IPC transport, which exchanges newline-delimited JSON-RPC frames over a Unix socket,
+/// e.g. the IPC endpoint of lotus.
+///
+/// Unlike `WebSocketTransport`, it doesn't reconnect: once the socket is closed, the in-flight
+/// requests fail with `RpcError::ConnectionReset` and the subscriptions are closed.
+pub struct IpcTransport {
+    id: Arc<AtomicUsize>,
+    pendings: Pendings,
+    methods: Methods,
+    subscriptions: Subscriptions,
+    renewals: Renewals,
+    handler: Handler,
+    subscription_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    request_timeout: Duration,
+    sender: WebSocketSender,
+    _handle: task::JoinHandle<()>,
+}
+
+impl IpcTransport {
+    /// Connect to the Unix socket at `path`.
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Create a transport over the connected Unix socket `stream`.
+    pub fn from_stream(stream: UnixStream) -> Self {
+        let id = Arc::new(AtomicUsize::new(1));
+        let pendings = Arc::new(Mutex::new(BTreeMap::new()));
+        let methods = Arc::new(Mutex::new(RecentMethods::new(
+            DEFAULT_METHOD_HISTORY_CAPACITY,
+        )));
+        let subscriptions = Arc::new(Mutex::new(BTreeMap::new()));
+        let renewals = Renewals::default();
+        let handler = Handler::default();
+        let (writer_tx, writer_rx) = mpsc::unbounded();
+
+        let handle = task::spawn(ipc_task(
+            stream,
+            pendings.clone(),
+            methods.clone(),
+            subscriptions.clone(),
+            renewals.clone(),
+            handler.clone(),
+            writer_tx.clone(),
+            writer_rx,
+        ));
+
+        Self {
+            id,
+            pendings,
+            methods,
+            subscriptions,
+            renewals,
+            handler,
+            subscription_capacity: DEFAULT_SUBSCRIPTION_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            sender: writer_tx,
+            _handle: handle,
+        }
+    }
+
+    /// Set the default timeout of the requests, which can be overridden per call
+    /// by `TimeoutTransport::with_timeout`.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set the number of the notifications buffered per subscription and the policy applied
+    /// when a consumer lags behind, see `WebSocketTransport::with_subscription_buffer`.
+    pub fn with_subscription_buffer(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.subscription_capacity = capacity;
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Set the method of the notification sent to the server by `unsubscribe`,
+    /// see `WebSocketTransport::with_unsubscribe_method`.
+    pub fn with_unsubscribe_method<M: Into<String>>(self, method: M) -> Self {
+        self.renewals.lock().unsubscribe_method = method.into();
+        self
+    }
+
+    /// Register the `handler` of the method calls initiated by the server,
+    /// see `WebSocketTransport::on_request`.
+    pub fn on_request<F>(&self, handler: F)
+    where
+        F: Fn(&str, Params) -> std::result::Result<Value, Error> + Send + Sync + 'static,
+    {
+        *self.handler.lock() = Some(Arc::new(handler));
+    }
+}
+
+async fn ipc_task(
+    stream: UnixStream,
+    pendings: Pendings,
+    methods: Methods,
+    subscriptions: Subscriptions,
+    renewals: Renewals,
+    handler: Handler,
+    tx: WebSocketSender,
+    mut rx: WebSocketReceiver,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    // write every request as a line.
+    let write_to_socket = async {
+        while let Some(msg) = rx.next().await {
+            match msg {
+                Message::Text(msg) => {
+                    writer.write_all(msg.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                }
+                Message::Close(_) => break,
+                msg => warn!("Skip non-text IPC message: {:?}", msg),
+            }
+        }
+        Ok::<_, io::Error>(())
+    };
+    // read the incoming messages line by line.
+    let read_from_socket = async {
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        while reader.read_line(&mut line).await? > 0 {
+            let msg = line.trim();
+            if !msg.is_empty() {
+                handle_incoming_msg(
+                    Message::Text(msg.to_string()),
+                    pendings.clone(),
+                    methods.clone(),
+                    subscriptions.clone(),
+                    renewals.clone(),
+                    handler.clone(),
+                    tx.clone(),
+                );
+            }
+            line.clear();
+        }
+        Ok::<_, io::Error>(())
+    };
+
+    futures::pin_mut!(write_to_socket, read_from_socket);
+    match future::select(write_to_socket, read_from_socket).await {
+        future::Either::Left((Err(err), _)) | future::Either::Right((Err(err), _)) => {
+            error!("IPC connection failed: {}", err)
+        }
+        _ => info!("IPC connection closed"),
+    }
+    reset_pendings(&pendings);
+    let ids = subscriptions.lock().keys().cloned().collect::<Vec<_>>();
+    for id in ids {
+        let reason = CloseReason::Error("the IPC connection is closed".into());
+        close_subscription(&subscriptions, &renewals, id, reason);
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for IpcTransport {
+    fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        let call = Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            id,
+            method: method.into(),
+            params,
+        });
+        (id, call)
+    }
+
+    async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
+        self.execute_with_timeout(id, request, self.request_timeout)
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl BatchTransport for IpcTransport {}
+
+#[async_trait::async_trait]
+impl TimeoutTransport for IpcTransport {
+    async fn execute_with_timeout(
+        &self,
+        id: RequestId,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response> {
+        send_request(
+            &self.pendings,
+            &self.methods,
+            &self.sender,
+            id,
+            request,
+            timeout,
+        )
+        .await
+    }
+}
+
+impl PubsubTransport for IpcTransport {
+    fn subscribe<T>(&self, id: SubscriptionId) -> NotificationStream<T>
+    where
+        T: DeserializeOwned,
+    {
+        let stream = subscription_stream(
+            &self.subscriptions,
+            self.subscription_capacity,
+            self.overflow_policy,
+            id.clone(),
+        );
+        decode_notifications(id, stream)
+    }
+
+    fn subscribe_events<T>(&self, id: SubscriptionId) -> EventStream<T>
+    where
+        T: DeserializeOwned,
+    {
+        let stream = subscription_stream(
+            &self.subscriptions,
+            self.subscription_capacity,
+            self.overflow_policy,
+            id.clone(),
+        );
+        decode_events(id, stream)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) {
+        unsubscribe(&self.subscriptions, &self.renewals, &self.sender, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::RpcError;
+    use crate::transports::StreamEvent;
+    use crate::types::Value;
+
+    #[tokio::test]
+    async fn test_ipc_echo() {
+        let (client, server) = UnixStream::pair().unwrap();
+        // the server answers every call with a canned response, then pushes a notification
+        // of the subscription 1 and closes the socket.
+        task::spawn(async move {
+            let (reader, mut writer) = tokio::io::split(server);
+            let mut reader = BufReader::new(reader);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let call = serde_json::from_str::<MethodCall>(line.trim()).unwrap();
+            assert_eq!(call.method, "Filecoin.Version");
+            let response = format!(r#"{{"jsonrpc":"2.0","result":"1.0.0","id":{}}}"#, call.id);
+            writer.write_all(response.as_bytes()).await.unwrap();
+            writer.write_all(b"\n").await.unwrap();
+            let notification = r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,100]}"#;
+            writer.write_all(notification.as_bytes()).await.unwrap();
+            writer.write_all(b"\n").await.unwrap();
+        });
+
+        let ipc = IpcTransport::from_stream(client);
+        let mut stream = ipc.subscribe_events::<u64>(SubscriptionId::Number(1));
+        let version: String = ipc
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(version, "1.0.0");
+        assert_eq!(stream.next().await, Some(StreamEvent::Item(100)));
+        // the socket is closed by the server.
+        assert!(matches!(
+            stream.next().await,
+            Some(StreamEvent::Closed(CloseReason::Error(_)))
+        ));
+        let result = ipc
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(
+            result,
+            Err(RpcError::Disconnected(_)) | Err(RpcError::ConnectionReset)
+        ));
+    }
+}
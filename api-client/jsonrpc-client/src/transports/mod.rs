@@ -1,16 +1,28 @@
 #[cfg(feature = "http")]
 mod http;
+#[cfg(all(unix, feature = "ipc"))]
+mod ipc;
+#[cfg(feature = "rate-limit")]
+mod rate_limit;
+mod replay;
 #[cfg(feature = "ws")]
 mod ws;
 
 #[cfg(feature = "http")]
 pub use self::http::*;
+#[cfg(all(unix, feature = "ipc"))]
+pub use self::ipc::IpcTransport;
+#[cfg(feature = "rate-limit")]
+pub use self::rate_limit::*;
+pub use self::replay::{ReplaySubscription, DEFAULT_REPLAY_CAPACITY};
 #[cfg(feature = "ws")]
 pub use self::ws::*;
 
+use std::time::Duration;
+
 use serde::de::DeserializeOwned;
 
-use crate::errors::Result;
+use crate::errors::{Result, RpcError};
 use crate::types::*;
 
 /// Transport implementation.
@@ -45,7 +57,9 @@ pub trait Transport {
                 Ok(serde_json::from_value(success.result)?)
             }
             Response::Single(ResponseOutput::Failure(failure)) => Err(failure.error.into()),
-            Response::Batch(_) => panic!("Expected single, got batch"),
+            Response::Batch(_) => Err(RpcError::UnexpectedResponse(
+                "expected single, got batch".into(),
+            )),
         }
     }
 }
@@ -94,7 +108,9 @@ pub trait BatchTransport: Transport {
             serde_json::to_string(&response).expect("Serialize `Response` never fails")
         );
         match response {
-            Response::Single(_) => panic!("Expected batch, got single"),
+            Response::Single(_) => Err(RpcError::UnexpectedResponse(
+                "expected batch, got single".into(),
+            )),
             Response::Batch(outputs) => Ok(outputs
                 .into_iter()
                 .map(|output| match output {
@@ -125,7 +141,11 @@ pub trait BatchTransport: Transport {
             serde_json::to_string(&response).expect("Serialize `Response` never fails")
         );
         let values = match response {
-            Response::Single(_) => panic!("Expected batch, got single"),
+            Response::Single(_) => {
+                return Err(RpcError::UnexpectedResponse(
+                    "expected batch, got single".into(),
+                ))
+            }
             Response::Batch(outputs) => outputs,
         };
         let mut results = Vec::with_capacity(values.len());
@@ -134,16 +154,93 @@ pub trait BatchTransport: Transport {
                 ResponseOutput::Success(success) => success.result,
                 ResponseOutput::Failure(failure) => return Err(failure.error.into()),
             };
-            let result = serde_json::from_value(value).expect("Deserialize `Value` never fails");
-            results.push(result);
+            results.push(serde_json::from_value(value)?);
         }
         Ok(results)
     }
 }
 
+/// A transport implementation supporting the per-request timeout.
+#[async_trait::async_trait]
+pub trait TimeoutTransport: Transport {
+    /// Execute prepared RPC call, which fails with `RpcError::Timeout` if the response
+    /// doesn't arrive within the `timeout`.
+    async fn execute_with_timeout(
+        &self,
+        id: RequestId,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response>;
+
+    /// Return a transport overriding the default request timeout with `timeout`,
+    /// for both the single and the batch calls.
+    fn with_timeout(&self, timeout: Duration) -> WithTimeout<'_, Self>
+    where
+        Self: Sized,
+    {
+        WithTimeout {
+            transport: self,
+            timeout,
+        }
+    }
+}
+
+/// The transport overriding the request timeout, see `TimeoutTransport::with_timeout`.
+pub struct WithTimeout<'a, T> {
+    transport: &'a T,
+    timeout: Duration,
+}
+
+#[async_trait::async_trait]
+impl<'a, T: TimeoutTransport + Sync> Transport for WithTimeout<'a, T> {
+    fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+        self.transport.prepare(method, params)
+    }
+
+    async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
+        self.transport
+            .execute_with_timeout(id, request, self.timeout)
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, T: BatchTransport + TimeoutTransport + Sync> BatchTransport for WithTimeout<'a, T> {}
+
 /// The type of stream pub-sub transport returns.
 pub type NotificationStream<T> = futures::stream::BoxStream<'static, T>;
 
+/// The event of a subscription stream which reports the reconnections of the transport.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamEvent<T> {
+    /// A notification of the subscription.
+    Item(T),
+    /// The transport reconnected and renewed the subscription, the notifications sent
+    /// during the outage are lost, so the consumer should resync from the current state.
+    Reconnected,
+    /// The subscription is finished for good, it's the last event of the stream.
+    Closed(CloseReason),
+    /// A notification which couldn't be decoded, the subscription goes on.
+    Malformed(String),
+}
+
+/// The reason why a subscription is finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The subscription is removed by the consumer.
+    Unsubscribed,
+    /// The server cancelled the subscription.
+    ServerCancelled,
+    /// The consumer didn't keep up with the notifications and the subscription is
+    /// disconnected by the overflow policy.
+    Lagged,
+    /// The subscription failed unrecoverably, e.g. it couldn't be renewed after reconnecting.
+    Error(String),
+}
+
+/// The type of stream of the subscription events pub-sub transport returns.
+pub type EventStream<T> = futures::stream::BoxStream<'static, StreamEvent<T>>;
+
 /// A transport implementation supporting pub sub subscriptions.
 pub trait PubsubTransport: Transport {
     /// Add a subscription to this transport
@@ -151,6 +248,139 @@ pub trait PubsubTransport: Transport {
     where
         T: DeserializeOwned;
 
+    /// Add a subscription to this transport, whose stream reports the reconnections
+    /// of the transport along with the notifications, and ends with the reason why
+    /// the subscription is finished.
+    fn subscribe_events<T>(&self, id: SubscriptionId) -> EventStream<T>
+    where
+        T: DeserializeOwned;
+
     /// Remove a subscription from this transport
     fn unsubscribe(&self, id: SubscriptionId);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A transport replying every request with the same response.
+    struct CannedTransport {
+        response: Response,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for CannedTransport {
+        fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: Some(Version::V2),
+                id: 1,
+                method: method.into(),
+                params,
+            });
+            (1, call)
+        }
+
+        async fn execute(&self, _id: RequestId, _request: &Request) -> Result<Response> {
+            Ok(self.response.clone())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BatchTransport for CannedTransport {}
+
+    fn output(result: Value) -> ResponseOutput {
+        ResponseOutput::from(Some(Version::V2), 1, Ok(result))
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_response() {
+        // the batch response to a single call.
+        let transport = CannedTransport {
+            response: Response::Batch(vec![output(Value::from(1))]),
+        };
+        let result = transport
+            .send::<_, u64>("Filecoin.Version", Params::None)
+            .await;
+        assert!(matches!(result, Err(RpcError::UnexpectedResponse(_))));
+
+        // the single response to a batch call.
+        let transport = CannedTransport {
+            response: Response::Single(output(Value::from(1))),
+        };
+        let result = transport
+            .send_batch(vec![("Filecoin.Version", Params::None)])
+            .await;
+        assert!(matches!(result, Err(RpcError::UnexpectedResponse(_))));
+        let result = transport
+            .send_batch_same::<_, _, u64>("Filecoin.Version", vec![Params::None])
+            .await;
+        assert!(matches!(result, Err(RpcError::UnexpectedResponse(_))));
+
+        // the result that can't be decoded.
+        let transport = CannedTransport {
+            response: Response::Batch(vec![output(Value::from("one"))]),
+        };
+        let result = transport
+            .send_batch_same::<_, _, u64>("Filecoin.Version", vec![Params::None])
+            .await;
+        assert!(matches!(result, Err(RpcError::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_error() {
+        let error = Error {
+            code: ErrorCode::ServerError(403),
+            message: "permission denied".into(),
+            data: Some(Value::from("admin")),
+        };
+        let failure = ResponseOutput::from(Some(Version::V2), 1, Err(error));
+
+        let transport = CannedTransport {
+            response: Response::Single(failure.clone()),
+        };
+        let result = transport
+            .send::<_, u64>("Filecoin.AuthNew", Params::None)
+            .await;
+        match result {
+            Err(RpcError::JsonRpc {
+                code,
+                message,
+                data,
+            }) => {
+                assert_eq!(code, 403);
+                assert_eq!(message, "permission denied");
+                assert_eq!(data, Some(Value::from("admin")));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // the failed call of a batch.
+        let transport = CannedTransport {
+            response: Response::Batch(vec![output(Value::from(1)), failure]),
+        };
+        let mut results = transport
+            .send_batch(vec![
+                ("Filecoin.Version", Params::None),
+                ("Filecoin.AuthNew", Params::None),
+            ])
+            .await
+            .unwrap();
+        assert!(matches!(
+            results.pop(),
+            Some(Err(RpcError::JsonRpc { code: 403, .. }))
+        ));
+        assert_eq!(results.pop().unwrap().unwrap(), Value::from(1));
+
+        // the standard codes keep their values.
+        let error = RpcError::from(Error::method_not_found());
+        assert!(matches!(
+            error,
+            RpcError::JsonRpc {
+                code: -32601,
+                data: None,
+                ..
+            }
+        ));
+        assert_eq!(error.to_string(), "JSON-RPC error -32601: Method not found");
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::errors::{Result, RpcError};
+use crate::transports::{BatchTransport, Transport};
+use crate::types::{Call, Params, Request, RequestId, Response};
+
+/// The token-bucket rate limit applied to a method.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// The maximum number of calls that can be fired in a burst.
+    pub burst: u32,
+    /// The number of calls refilled per second.
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    /// Create a new rate limit with the given burst size and refill rate.
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        assert!(burst > 0, "burst of rate limit must be greater than zero");
+        assert!(
+            per_second > 0.0,
+            "rate of rate limit must be greater than zero"
+        );
+        Self { burst, per_second }
+    }
+}
+
+/// What to do with calls that exceed the rate limit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Delay the call until a token is available.
+    /// The call is rejected if the delay would exceed the deadline.
+    Queue {
+        /// The maximum time a call may be queued, `None` means waiting forever.
+        deadline: Option<Duration>,
+    },
+    /// Reject the call immediately.
+    Reject,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        RateLimitPolicy::Queue { deadline: None }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    // may be negative, which means calls are queued to take future tokens.
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        self.last = now;
+    }
+
+    // Reserve a token, return the duration the caller need to wait for the token.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.limit.per_second)
+        }
+    }
+
+    // Give back a reserved token.
+    fn cancel(&mut self) {
+        self.tokens += 1.0;
+    }
+}
+
+/// A transport middleware that applies a per-method token-bucket rate limit to outbound calls.
+///
+/// Methods without an explicit limit fall back to the default limit (if any),
+/// each method owns its own bucket.
+pub struct RateLimitedTransport<T> {
+    transport: T,
+    policy: RateLimitPolicy,
+    default_limit: Option<RateLimit>,
+    limits: HashMap<String, RateLimit>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl<T: Transport> RateLimitedTransport<T> {
+    /// Create a new rate limited transport without any limit.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            policy: RateLimitPolicy::default(),
+            default_limit: None,
+            limits: HashMap::new(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the policy for the calls exceeding the rate limit.
+    pub fn with_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set the rate limit for the methods that don't have an explicit limit.
+    pub fn with_default_limit(mut self, limit: RateLimit) -> Self {
+        self.default_limit = Some(limit);
+        self
+    }
+
+    /// Set the rate limit for the given `method`.
+    pub fn with_limit<M: Into<String>>(mut self, method: M, limit: RateLimit) -> Self {
+        self.limits.insert(method.into(), limit);
+        self
+    }
+
+    /// Return the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+
+    fn limit_of(&self, method: &str) -> Option<RateLimit> {
+        self.limits.get(method).copied().or(self.default_limit)
+    }
+
+    // Reserve a token for every method, return the longest duration to wait.
+    fn reserve(&self, methods: &[&str]) -> Result<Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
+        let mut reserved = Vec::with_capacity(methods.len());
+        let mut wait = Duration::from_secs(0);
+        for method in methods {
+            let limit = match self.limit_of(method) {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let bucket = buckets
+                .entry((*method).to_string())
+                .or_insert_with(|| TokenBucket::new(limit));
+            let delay = bucket.reserve(now);
+            reserved.push(*method);
+
+            let exceeded = match self.policy {
+                RateLimitPolicy::Reject => delay > Duration::from_secs(0),
+                RateLimitPolicy::Queue { deadline } => deadline.map_or(false, |d| delay > d),
+            };
+            if exceeded {
+                for method in reserved {
+                    if let Some(bucket) = buckets.get_mut(method) {
+                        bucket.cancel();
+                    }
+                }
+                return Err(RpcError::RateLimited(method.to_string()));
+            }
+            wait = wait.max(delay);
+        }
+        Ok(wait)
+    }
+}
+
+fn methods_of(request: &Request) -> Vec<&str> {
+    fn method_of(call: &Call) -> &str {
+        match call {
+            Call::MethodCall(call) => call.method.as_str(),
+            Call::Notification(notification) => notification.method.as_str(),
+        }
+    }
+    match request {
+        Request::Single(call) => vec![method_of(call)],
+        Request::Batch(calls) => calls.iter().map(method_of).collect(),
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Transport for RateLimitedTransport<T>
+where
+    T: Transport + Send + Sync,
+{
+    fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+        self.transport.prepare(method, params)
+    }
+
+    async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
+        let wait = self.reserve(&methods_of(request))?;
+        if wait > Duration::from_secs(0) {
+            debug!("Rate limited request (id: {}), delay {:?}", id, wait);
+            tokio::time::delay_for(wait).await;
+        }
+        self.transport.execute(id, request).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> BatchTransport for RateLimitedTransport<T> where T: BatchTransport + Send + Sync {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::types::{MethodCall, ResponseOutput, Value, Version};
+
+    #[derive(Default)]
+    struct EchoTransport {
+        id: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for EchoTransport {
+        fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+            let id = self.id.fetch_add(1, Ordering::AcqRel);
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: Some(Version::V2),
+                id,
+                method: method.into(),
+                params,
+            });
+            (id, call)
+        }
+
+        async fn execute(&self, id: RequestId, _request: &Request) -> Result<Response> {
+            Ok(Response::Single(ResponseOutput::from(
+                Some(Version::V2),
+                id,
+                Ok(Value::Null),
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_delay() {
+        let transport = RateLimitedTransport::new(EchoTransport::default())
+            .with_limit("Filecoin.StateCall", RateLimit::new(1, 10.0));
+
+        // the method without limit is not delayed.
+        let start = Instant::now();
+        for _ in 0..5 {
+            let _: Value = transport
+                .send("Filecoin.Version", Params::Array(vec![]))
+                .await
+                .unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // the first call takes the burst token, the other two wait 100ms each.
+        let start = Instant::now();
+        let calls = (0..3).map(|_| transport.send::<_, Value>("Filecoin.StateCall", Params::None));
+        for result in futures::future::join_all(calls).await {
+            assert!(result.is_ok());
+        }
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_reject() {
+        let transport = RateLimitedTransport::new(EchoTransport::default())
+            .with_policy(RateLimitPolicy::Reject)
+            .with_default_limit(RateLimit::new(2, 1.0));
+
+        for _ in 0..2 {
+            let result: Result<Value> = transport.send("Filecoin.StateCall", Params::None).await;
+            assert!(result.is_ok());
+        }
+        let result: Result<Value> = transport.send("Filecoin.StateCall", Params::None).await;
+        assert!(matches!(result, Err(RpcError::RateLimited(_))));
+        // other method has its own bucket.
+        let result: Result<Value> = transport.send("Filecoin.Version", Params::None).await;
+        assert!(result.is_ok());
+    }
+}
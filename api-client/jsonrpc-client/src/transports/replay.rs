@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::stream::{Stream, StreamExt};
+
+use crate::transports::NotificationStream;
+
+/// The default number of the recent notifications retained for replaying.
+pub const DEFAULT_REPLAY_CAPACITY: usize = 16;
+
+struct ReplayState<T> {
+    capacity: usize,
+    buffer: VecDeque<T>,
+    consumers: Vec<mpsc::UnboundedSender<T>>,
+}
+
+/// A subscription fanning out the notifications to multiple consumers, which retains the
+/// last notifications in a bounded buffer so that a consumer attached later (or a lagging
+/// consumer re-attaching) catches up before receiving the live notifications.
+///
+/// It doesn't depend on any transport, the notifications are fed by `push` or `forward`.
+pub struct ReplaySubscription<T> {
+    state: Arc<Mutex<ReplayState<T>>>,
+}
+
+impl<T> Clone for ReplaySubscription<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for ReplaySubscription<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_CAPACITY)
+    }
+}
+
+impl<T: Clone + Send + 'static> ReplaySubscription<T> {
+    /// Create a subscription retaining the last `capacity` notifications,
+    /// nothing is replayed if the `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ReplayState {
+                capacity,
+                buffer: VecDeque::with_capacity(capacity),
+                consumers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Return the number of the retained notifications.
+    pub fn buffered_len(&self) -> usize {
+        self.state
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .buffer
+            .len()
+    }
+
+    /// Push the notification to the buffer and the attached consumers,
+    /// the consumers whose stream is dropped are detached.
+    pub fn push(&self, item: T) {
+        let mut state = self.state.lock().expect("lock shouldn't be poisoned");
+        if state.capacity > 0 {
+            if state.buffer.len() == state.capacity {
+                state.buffer.pop_front();
+            }
+            state.buffer.push_back(item.clone());
+        }
+        state
+            .consumers
+            .retain(|consumer| consumer.unbounded_send(item.clone()).is_ok());
+    }
+
+    /// Push every notification of the `stream`, e.g. the `NotificationStream` returned by
+    /// the transport, the returned future should be spawned on the runtime of the caller.
+    pub async fn forward<S>(self, stream: S)
+    where
+        S: Stream<Item = T>,
+    {
+        futures::pin_mut!(stream);
+        while let Some(item) = stream.next().await {
+            self.push(item);
+        }
+    }
+
+    /// Attach a consumer, whose stream yields the retained notifications first and then
+    /// the live ones.
+    pub fn subscribe(&self) -> NotificationStream<T> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = self.state.lock().expect("lock shouldn't be poisoned");
+        for item in &state.buffer {
+            tx.unbounded_send(item.clone())
+                .expect("the receiver is alive; qed");
+        }
+        state.consumers.push(tx);
+        Box::pin(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn test_replay_buffered_then_live() {
+        let subscription = ReplaySubscription::new(3);
+        // the oldest notification is evicted by the bounded buffer.
+        for i in 0..4 {
+            subscription.push(i);
+        }
+        assert_eq!(subscription.buffered_len(), 3);
+
+        let stream = subscription.subscribe();
+        let (tx, rx) = mpsc::unbounded();
+        let forward = subscription.clone().forward(rx);
+        tx.unbounded_send(4).unwrap();
+        tx.unbounded_send(5).unwrap();
+        drop(tx);
+        forward.now_or_never().unwrap();
+
+        let items = stream.take(5).collect::<Vec<_>>().now_or_never().unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4, 5]);
+        assert_eq!(subscription.buffered_len(), 3);
+    }
+}
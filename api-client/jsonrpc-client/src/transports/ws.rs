@@ -1,259 +1,2868 @@
-use std::collections::BTreeMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
-use async_tungstenite::tokio::connect_async;
+use async_tungstenite::tokio::connect_async_with_tls_connector;
 use async_tungstenite::tungstenite::handshake::client::Request as HandShakeRequest;
-use async_tungstenite::tungstenite::http::header;
+use async_tungstenite::tungstenite::http::{
+    self,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+};
 use async_tungstenite::tungstenite::protocol::Message;
 use futures::channel::{mpsc, oneshot};
 use futures::future;
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use tokio::task;
 
-use crate::errors::Result;
-use crate::transports::{BatchTransport, NotificationStream, PubsubTransport, Transport};
+use crate::errors::{Result, RpcError};
+use crate::transports::{BatchTransport, EventStream, NotificationStream, PubsubTransport};
+use crate::transports::{CloseReason, StreamEvent, TimeoutTransport, Transport};
 use crate::types::{
-    Call, MethodCall, Notification, Params, Request, RequestId, Response, SubscriptionId, Value,
-    Version,
+    Call, Error, MethodCall, Notification, Params, Request, RequestId, Response, ResponseOutput,
+    SubscriptionId, Value, Version,
 };
 
 type Pending = oneshot::Sender<Result<Response>>;
-type Pendings = Arc<Mutex<BTreeMap<RequestId, Pending>>>;
-type Subscription = mpsc::UnboundedSender<Value>;
-type Subscriptions = Arc<Mutex<BTreeMap<SubscriptionId, Subscription>>>;
+pub(crate) type Pendings = Arc<Mutex<BTreeMap<RequestId, Pending>>>;
+pub(crate) type Methods = Arc<Mutex<RecentMethods>>;
+pub(crate) type Subscriptions = Arc<Mutex<BTreeMap<SubscriptionId, Subscription>>>;
+pub(crate) type Renewals = Arc<Mutex<SubscriptionRenewals>>;
+pub(crate) type Handler = Arc<Mutex<Option<RequestHandler>>>;
+type States = Arc<Mutex<StateWatch>>;
+
+/// The handler of the method calls initiated by the server, whose return value is sent back
+/// as the response of the call.
+pub type RequestHandler =
+    Arc<dyn Fn(&str, Params) -> std::result::Result<Value, Error> + Send + Sync>;
+
+pub(crate) type WebSocketSender = mpsc::UnboundedSender<Message>;
+pub(crate) type WebSocketReceiver = mpsc::UnboundedReceiver<Message>;
+
+/// The default number of the recently sent request ids whose method names are remembered.
+pub const DEFAULT_METHOD_HISTORY_CAPACITY: usize = 256;
+
+/// The method of the notification by which the server cancels a subscription,
+/// whose params are the id of the subscription.
+pub const SUBSCRIPTION_CLOSE_METHOD: &str = "xrpc.ch.close";
+
+/// The default method of the notification by which the client tells the server to stop
+/// sending the notifications of a subscription, whose params are the id of the subscription.
+pub const DEFAULT_UNSUBSCRIBE_METHOD: &str = SUBSCRIPTION_CLOSE_METHOD;
+
+/// The default number of the notifications buffered per subscription.
+pub const DEFAULT_SUBSCRIPTION_CAPACITY: usize = 1024;
+
+/// The default timeout of a request, after which the pending request is removed.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The time to wait for the closing handshake before the connection task is aborted.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The delay before the first attempt to reconnect the WebSocket.
+pub const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The upper bound of the delay between the attempts to reconnect the WebSocket.
+pub const DEFAULT_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// The policy of reconnecting the WebSocket once the connection drops.
+///
+/// The delay doubles after every failed attempt, up to `max_delay`, and is reset once
+/// the handshake succeeds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// The delay before the first attempt.
+    pub initial_delay: Duration,
+    /// The upper bound of the delay between the attempts.
+    pub max_delay: Duration,
+    /// The number of the consecutive failed attempts before giving up, `None` never gives up.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: DEFAULT_RECONNECT_DELAY,
+            max_delay: DEFAULT_MAX_RECONNECT_DELAY,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    // The delay after `failures` consecutive failed attempts.
+    fn delay(&self, failures: usize) -> Duration {
+        let factor = 1u32.checked_shl(failures as u32).unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+
+    fn gives_up(&self, failures: usize) -> bool {
+        self.max_attempts.map_or(false, |max| failures >= max)
+    }
+}
+
+/// What to do with the requests sent before the handshake completes, e.g. right after
+/// the transport is created or while it's reconnecting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PreConnectPolicy {
+    /// Buffer the requests and send them once connected, they fail if the handshake fails.
+    Buffer,
+    /// Reject the requests with `RpcError::NotConnected`.
+    Reject,
+}
+
+impl Default for PreConnectPolicy {
+    fn default() -> Self {
+        PreConnectPolicy::Buffer
+    }
+}
+
+/// The state of the WebSocket connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The first handshake is in progress.
+    Connecting,
+    /// The handshake is completed, the requests can be sent.
+    Connected,
+    /// The connection dropped, the transport is reconnecting.
+    Reconnecting,
+    /// The transport is closed or gave up reconnecting, the requests fail.
+    Closed,
+}
+
+/// The type of stream of the connection states, which starts with the current state.
+pub type ConnectionStateStream = futures::stream::BoxStream<'static, ConnectionState>;
+
+// The current state of the connection and the streams watching it.
+struct StateWatch {
+    state: ConnectionState,
+    watchers: Vec<mpsc::UnboundedSender<ConnectionState>>,
+}
+
+impl StateWatch {
+    fn new() -> Self {
+        Self {
+            state: ConnectionState::Connecting,
+            watchers: Vec::new(),
+        }
+    }
+
+    fn watch(&mut self) -> ConnectionStateStream {
+        let (tx, rx) = mpsc::unbounded();
+        if self.state != ConnectionState::Closed {
+            // can't fail, the receiver is alive.
+            let _ = tx.unbounded_send(self.state);
+            self.watchers.push(tx);
+            Box::pin(rx)
+        } else {
+            Box::pin(futures::stream::once(future::ready(self.state)))
+        }
+    }
+
+    fn set(&mut self, state: ConnectionState) {
+        // the closed state is terminal, e.g. the handshake completed after `close`.
+        if self.state == state || self.state == ConnectionState::Closed {
+            return;
+        }
+        debug!("WebSocket connection state: {:?}", state);
+        self.state = state;
+        // the streams dropped by their consumers are removed.
+        self.watchers
+            .retain(|watcher| watcher.unbounded_send(state).is_ok());
+        if state == ConnectionState::Closed {
+            // the streams end after the terminal state.
+            self.watchers.clear();
+        }
+    }
+}
+
+/// The default interval between the keepalive pings.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default time to wait for a message after a keepalive ping.
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The keepalive of the WebSocket connection.
+///
+/// A ping is sent every `interval`, and the connection is deemed dead if nothing, not even
+/// the pong, is received within `timeout` after the ping. The dead connection is then
+/// reconnected like a dropped one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// The interval between the pings.
+    pub interval: Duration,
+    /// The time to wait for a message after a ping.
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+/// The TLS configuration of the `wss://` connections, e.g. the root certificate of a node
+/// behind a self-signed certificate.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    root_certificates: Vec<native_tls::Certificate>,
+    identity: Option<native_tls::Identity>,
+    disable_built_in_roots: bool,
+}
+
+impl TlsConfig {
+    /// Create a new TlsConfig trusting the system root certificates.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the PEM or DER encoded root `certificate`, in addition to the system ones.
+    pub fn root_certificate(mut self, certificate: &[u8]) -> Result<Self> {
+        let certificate = native_tls::Certificate::from_pem(certificate)
+            .or_else(|_| native_tls::Certificate::from_der(certificate))
+            .map_err(|err| RpcError::InvalidTlsConfig(err.to_string()))?;
+        self.root_certificates.push(certificate);
+        Ok(self)
+    }
+
+    /// Authenticate with the client identity, a DER encoded PKCS #12 archive decrypted
+    /// with the `password`.
+    pub fn identity(mut self, pkcs12: &[u8], password: &str) -> Result<Self> {
+        let identity = native_tls::Identity::from_pkcs12(pkcs12, password)
+            .map_err(|err| RpcError::InvalidTlsConfig(err.to_string()))?;
+        self.identity = Some(identity);
+        Ok(self)
+    }
+
+    /// Trust only the root certificates added by `root_certificate`.
+    pub fn disable_built_in_roots(mut self, disable: bool) -> Self {
+        self.disable_built_in_roots = disable;
+        self
+    }
+
+    fn connector(&self) -> std::result::Result<tokio_native_tls::TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for certificate in &self.root_certificates {
+            builder.add_root_certificate(certificate.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder.identity(identity.clone());
+        }
+        builder.disable_built_in_roots(self.disable_built_in_roots);
+        Ok(builder.build()?.into())
+    }
+}
+
+/// A bounded ring buffer mapping the recently sent request ids to their method names,
+/// used for logging the responses whose pending request is gone.
+pub(crate) struct RecentMethods {
+    capacity: usize,
+    methods: VecDeque<(RequestId, String)>,
+}
+
+impl RecentMethods {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            methods: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.methods.len() > capacity {
+            self.methods.pop_front();
+        }
+    }
+
+    fn record(&mut self, id: RequestId, method: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.methods.len() == self.capacity {
+            self.methods.pop_front();
+        }
+        self.methods.push_back((id, method.to_string()));
+    }
+
+    fn get(&self, id: RequestId) -> Option<&str> {
+        self.methods
+            .iter()
+            .rev()
+            .find(|(recent, _)| *recent == id)
+            .map(|(_, method)| method.as_str())
+    }
+}
+
+/// What to do with a notification when the buffer of a subscription is full,
+/// i.e. the consumer doesn't keep up with the notifications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered notification to make room for the incoming one.
+    DropOldest,
+    /// Drop the incoming notification.
+    DropNewest,
+    /// Close the subscription with `CloseReason::Lagged`.
+    Disconnect,
+}
+
+// The outcome of sending an event to a subscription.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Delivery {
+    Queued,
+    // a notification is dropped by the overflow policy.
+    Dropped,
+    // the subscription should be disconnected by the overflow policy.
+    Overflowed,
+    // the consumer dropped the stream.
+    Detached,
+}
+
+#[derive(Default)]
+struct SubscriptionQueue {
+    events: VecDeque<StreamEvent<Value>>,
+    // the number of the buffered notifications, the other events aren't limited.
+    items: usize,
+    lagged: u64,
+    closed: bool,
+    detached: bool,
+    waker: Option<Waker>,
+}
+
+/// The sending half of a subscription, whose notifications are buffered up to the capacity.
+/// The stream ends once it's dropped.
+pub(crate) struct Subscription {
+    queue: Arc<Mutex<SubscriptionQueue>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Subscription {
+    fn channel(capacity: usize, policy: OverflowPolicy) -> (Self, SubscriptionStream) {
+        let queue = Arc::new(Mutex::new(SubscriptionQueue::default()));
+        let subscription = Self {
+            queue: queue.clone(),
+            capacity: capacity.max(1),
+            policy,
+        };
+        (subscription, SubscriptionStream { queue })
+    }
+
+    fn send(&self, event: StreamEvent<Value>) -> Delivery {
+        let mut queue = self.queue.lock();
+        if queue.detached {
+            return Delivery::Detached;
+        }
+        let mut delivery = Delivery::Queued;
+        if let StreamEvent::Item(_) = event {
+            if queue.items >= self.capacity {
+                queue.lagged += 1;
+                match self.policy {
+                    OverflowPolicy::DropOldest => {
+                        let oldest = queue
+                            .events
+                            .iter()
+                            .position(|event| matches!(event, StreamEvent::Item(_)));
+                        if let Some(oldest) = oldest {
+                            queue.events.remove(oldest);
+                            queue.items -= 1;
+                        }
+                        delivery = Delivery::Dropped;
+                    }
+                    OverflowPolicy::DropNewest => return Delivery::Dropped,
+                    OverflowPolicy::Disconnect => return Delivery::Overflowed,
+                }
+            }
+            queue.items += 1;
+        }
+        queue.events.push_back(event);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        delivery
+    }
+
+    fn lagged(&self) -> u64 {
+        self.queue.lock().lagged
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The receiving half of a subscription.
+pub(crate) struct SubscriptionStream {
+    queue: Arc<Mutex<SubscriptionQueue>>,
+}
+
+impl Stream for SubscriptionStream {
+    type Item = StreamEvent<Value>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.lock();
+        match queue.events.pop_front() {
+            Some(event) => {
+                if let StreamEvent::Item(_) = event {
+                    queue.items -= 1;
+                }
+                Poll::Ready(Some(event))
+            }
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock();
+        queue.detached = true;
+        queue.events.clear();
+        queue.items = 0;
+    }
+}
+
+/// The subscribe calls to renew after the WebSocket reconnects, keyed by the current
+/// subscription id, and the current ids of the subscriptions keyed by their original ids.
+///
+/// It also tracks the ids unsubscribed over the current connection, whose late notifications
+/// are expected and dropped quietly, and the method by which they're unsubscribed.
+pub(crate) struct SubscriptionRenewals {
+    calls: BTreeMap<SubscriptionId, (String, Params)>,
+    current: BTreeMap<SubscriptionId, SubscriptionId>,
+    unsubscribed: BTreeSet<SubscriptionId>,
+    unsubscribe_method: String,
+}
+
+impl Default for SubscriptionRenewals {
+    fn default() -> Self {
+        Self {
+            calls: BTreeMap::new(),
+            current: BTreeMap::new(),
+            unsubscribed: BTreeSet::new(),
+            unsubscribe_method: DEFAULT_UNSUBSCRIBE_METHOD.into(),
+        }
+    }
+}
+
+// Remove the pending request once the caller stops waiting for the response (e.g. timed out),
+// so that a late response is reported as an orphan.
+struct PendingGuard {
+    id: RequestId,
+    pendings: Pendings,
+}
 
-type WebSocketSender = mpsc::UnboundedSender<Message>;
-type WebSocketReceiver = mpsc::UnboundedReceiver<Message>;
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pendings.lock().remove(&self.id);
+    }
+}
 
 pub struct WebSocketTransport {
     id: Arc<AtomicUsize>,
     _url: String,
     _bearer_auth_token: Option<String>,
     pendings: Pendings,
+    methods: Methods,
     subscriptions: Subscriptions,
+    renewals: Renewals,
+    handler: Handler,
+    subscription_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    request_timeout: Duration,
+    pre_connect_policy: PreConnectPolicy,
     sender: WebSocketSender,
-    _handle: task::JoinHandle<()>,
+    closed: Arc<AtomicBool>,
+    states: States,
+    shutdown: Mutex<Option<oneshot::Sender<()>>>,
+    handle: Mutex<Option<task::JoinHandle<()>>>,
+}
+
+/// WebSocketTransportBuilder configures the handshake request of a `WebSocketTransport`,
+/// e.g. the extra headers required by a gateway in front of the node.
+pub struct WebSocketTransportBuilder {
+    url: String,
+    bearer_auth_token: Option<String>,
+    headers: HeaderMap,
+    reconnect_policy: ReconnectPolicy,
+    request_timeout: Duration,
+    tls: Option<TlsConfig>,
+    heartbeat: Option<Heartbeat>,
+    pre_connect_policy: PreConnectPolicy,
+}
+
+impl WebSocketTransportBuilder {
+    /// Authenticate the handshake with the bearer `token`.
+    pub fn bearer_auth<T: Into<String>>(mut self, token: T) -> Self {
+        self.bearer_auth_token = Some(token.into());
+        self
+    }
+
+    /// Add the header into the handshake request, replacing the header of the same name.
+    /// The malformed header name or value is rejected with `RpcError::InvalidHeader`.
+    pub fn header<K: AsRef<str>, V: AsRef<str>>(mut self, name: K, value: V) -> Result<Self> {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|err| RpcError::InvalidHeader(format!("{}: {}", name, err)))?;
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|err| RpcError::InvalidHeader(format!("{}: {}", name, err)))?;
+        self.headers.insert(header_name, header_value);
+        Ok(self)
+    }
+
+    /// Add the parsed headers into the handshake request, replacing the headers of the same
+    /// names, e.g. the cookies or a non-bearer auth scheme. All the values of a repeated
+    /// header are kept.
+    pub fn header_map(mut self, headers: HeaderMap) -> Self {
+        replace_headers(&mut self.headers, &headers);
+        self
+    }
+
+    /// Add the headers into the handshake request, see `header`.
+    pub fn headers<I, K, V>(self, headers: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        headers
+            .into_iter()
+            .try_fold(self, |builder, (name, value)| builder.header(name, value))
+    }
+
+    /// Set the policy of reconnecting the WebSocket once the connection drops.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = policy;
+        self
+    }
+
+    /// Set what to do with the requests sent before the handshake completes.
+    pub fn pre_connect_policy(mut self, policy: PreConnectPolicy) -> Self {
+        self.pre_connect_policy = policy;
+        self
+    }
+
+    /// Send a keepalive ping every `interval`, and reconnect if nothing is received within
+    /// `timeout` after the ping.
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat = Some(Heartbeat { interval, timeout });
+        self
+    }
+
+    /// Don't send the keepalive pings, a dead connection is then only detected by
+    /// the operating system.
+    pub fn disable_heartbeat(mut self) -> Self {
+        self.heartbeat = None;
+        self
+    }
+
+    /// Set the TLS configuration of the `wss://` connection, the system root certificates
+    /// are trusted by default.
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Set the default timeout of the requests, which can be overridden per call
+    /// by `TimeoutTransport::with_timeout`.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Build the transport and start connecting, the handshake request is rebuilt with
+    /// the same headers on every reconnection.
+    pub fn build(self) -> WebSocketTransport {
+        let mut transport = WebSocketTransport::connect(
+            self.url,
+            self.bearer_auth_token,
+            self.headers,
+            self.reconnect_policy,
+            self.tls,
+            self.heartbeat,
+        );
+        transport.request_timeout = self.request_timeout;
+        transport.pre_connect_policy = self.pre_connect_policy;
+        transport
+    }
 }
 
 impl WebSocketTransport {
     pub fn new<U: Into<String>>(url: U) -> Self {
-        let url = url.into();
-        let handshake_request = HandShakeRequest::get(&url)
-            .body(())
-            .expect("Handshake HTTP request should be valid");
+        Self::builder(url).build()
+    }
 
-        let pending = Arc::new(Mutex::new(BTreeMap::new()));
-        let subscriptions = Arc::new(Mutex::new(BTreeMap::new()));
-        let (writer_tx, writer_rx) = mpsc::unbounded();
+    pub fn new_with_bearer_auth<U: Into<String>, T: Into<String>>(url: U, token: T) -> Self {
+        Self::builder(url).bearer_auth(token).build()
+    }
 
-        let handle = task::spawn(ws_task(
-            handshake_request,
-            pending.clone(),
-            subscriptions.clone(),
-            writer_tx.clone(),
-            writer_rx,
-        ));
+    /// Create a transport whose handshake request carries the `headers`.
+    pub fn new_with_headers<U: Into<String>>(url: U, headers: HeaderMap) -> Self {
+        Self::builder(url).header_map(headers).build()
+    }
 
-        Self {
-            id: Arc::new(AtomicUsize::new(1)),
-            _url: url,
-            _bearer_auth_token: None,
-            pendings: pending,
-            subscriptions,
-            sender: writer_tx,
-            _handle: handle,
-        }
+    /// Create a transport connecting to the `wss://` url with the TLS `config`.
+    pub fn new_with_tls<U: Into<String>>(url: U, config: TlsConfig) -> Self {
+        Self::builder(url).tls(config).build()
     }
 
-    pub fn new_with_bearer_auth<U: Into<String>, T: Into<String>>(url: U, token: T) -> Self {
-        let url = url.into();
-        let token = token.into();
+    /// Create a transport sending a keepalive ping every `interval`, which reconnects if
+    /// nothing is received within `timeout` after the ping.
+    pub fn new_with_heartbeat<U: Into<String>>(
+        url: U,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Self {
+        Self::builder(url).heartbeat(interval, timeout).build()
+    }
+
+    /// Create a transport applying the `policy` to the requests sent before the handshake
+    /// completes.
+    pub fn new_with_pre_connect_policy<U: Into<String>>(url: U, policy: PreConnectPolicy) -> Self {
+        Self::builder(url).pre_connect_policy(policy).build()
+    }
+
+    /// Create a transport reconnecting the WebSocket with the given `policy`.
+    pub fn new_with_reconnect_policy<U: Into<String>>(url: U, policy: ReconnectPolicy) -> Self {
+        Self::builder(url).reconnect_policy(policy).build()
+    }
 
-        let bearer_auth_header_value = format!("Bearer {}", token);
-        let handshake_request = HandShakeRequest::get(&url)
-            .header(header::AUTHORIZATION, bearer_auth_header_value)
-            .body(())
-            .expect("Handshake HTTP request should be valid");
+    /// Return a builder of the transport connecting to the `url`.
+    pub fn builder<U: Into<String>>(url: U) -> WebSocketTransportBuilder {
+        WebSocketTransportBuilder {
+            url: url.into(),
+            bearer_auth_token: None,
+            headers: HeaderMap::new(),
+            reconnect_policy: ReconnectPolicy::default(),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            tls: None,
+            heartbeat: Some(Heartbeat::default()),
+            pre_connect_policy: PreConnectPolicy::default(),
+        }
+    }
 
+    fn connect(
+        url: String,
+        bearer_auth_token: Option<String>,
+        headers: HeaderMap,
+        reconnect_policy: ReconnectPolicy,
+        tls: Option<TlsConfig>,
+        heartbeat: Option<Heartbeat>,
+    ) -> Self {
+        let id = Arc::new(AtomicUsize::new(1));
         let pending = Arc::new(Mutex::new(BTreeMap::new()));
+        let methods = Arc::new(Mutex::new(RecentMethods::new(
+            DEFAULT_METHOD_HISTORY_CAPACITY,
+        )));
         let subscriptions = Arc::new(Mutex::new(BTreeMap::new()));
+        let renewals = Arc::new(Mutex::new(SubscriptionRenewals::default()));
+        let handler = Handler::default();
+        let closed = Arc::new(AtomicBool::new(false));
+        let states = Arc::new(Mutex::new(StateWatch::new()));
         let (writer_tx, writer_rx) = mpsc::unbounded();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
-        let handle = task::spawn(ws_task(
-            handshake_request,
+        let ws_task = ws_task(
+            url.clone(),
+            bearer_auth_token.clone(),
+            headers,
+            reconnect_policy,
+            tls,
+            heartbeat,
+            id.clone(),
             pending.clone(),
+            methods.clone(),
             subscriptions.clone(),
+            renewals.clone(),
+            handler.clone(),
+            closed.clone(),
+            states.clone(),
             writer_tx.clone(),
             writer_rx,
-        ));
+        );
+        // the task is aborted by `close` if it doesn't stop in time.
+        let task_states = states.clone();
+        let handle = task::spawn(async move {
+            futures::pin_mut!(ws_task);
+            future::select(ws_task, shutdown_rx).await;
+            task_states.lock().set(ConnectionState::Closed);
+        });
 
         Self {
-            id: Arc::new(AtomicUsize::new(1)),
+            id,
             _url: url,
-            _bearer_auth_token: Some(token),
+            _bearer_auth_token: bearer_auth_token,
             pendings: pending,
+            methods,
             subscriptions,
+            renewals,
+            handler,
+            subscription_capacity: DEFAULT_SUBSCRIPTION_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            pre_connect_policy: PreConnectPolicy::default(),
             sender: writer_tx,
-            _handle: handle,
+            closed,
+            states,
+            shutdown: Mutex::new(Some(shutdown_tx)),
+            handle: Mutex::new(Some(handle)),
         }
     }
 
-    async fn send_request(&self, id: RequestId, request: &Request) -> Result<Response> {
-        let request = serde_json::to_string(request)?;
-        debug!("Calling: {}", request);
-
-        let (tx, rx) = oneshot::channel();
-        self.pendings.lock().insert(id, tx);
-        self.sender
-            .unbounded_send(Message::Text(request))
-            .expect("Sending `Text` Message should be successful");
-
-        rx.await.unwrap()
+    /// Return the current state of the connection.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.states.lock().state
     }
-}
 
-async fn ws_task(
-    handshake_request: HandShakeRequest,
-    pendings: Pendings,
-    sub: Subscriptions,
-    tx: WebSocketSender,
-    rx: WebSocketReceiver,
-) {
-    let (ws_stream, _) = connect_async(handshake_request)
-        .await
-        .expect("Handshake request is valid, but failed to connect");
-    info!("WebSocket handshake has been successfully completed");
-    let (sink, stream) = ws_stream.split();
+    /// Return the stream of the connection states, which starts with the current state
+    /// and ends after `ConnectionState::Closed`.
+    pub fn connection_states(&self) -> ConnectionStateStream {
+        self.states.lock().watch()
+    }
 
-    // receive request from WebSocketSender,
-    // and forward the request to sink that will send message to websocket stream.
-    let write_to_ws = rx.map(Ok).forward(sink);
-    // read websocket message from websocket stream, and handle the incoming message.
-    let read_from_ws = stream.for_each(|msg| async {
-        match msg {
-            Ok(msg) => handle_incoming_msg(msg, pendings.clone(), sub.clone(), tx.clone()),
-            Err(err) => error!("WebSocket stream read error: {}", err),
+    /// Wait until the handshake is completed, so that the first request doesn't race
+    /// the connection. It fails with `RpcError::Closed` once the transport is closed
+    /// or gives up connecting.
+    pub async fn ready(&self) -> Result<()> {
+        let mut states = self.connection_states();
+        while let Some(state) = states.next().await {
+            match state {
+                ConnectionState::Connected => return Ok(()),
+                ConnectionState::Closed => break,
+                ConnectionState::Connecting | ConnectionState::Reconnecting => {}
+            }
         }
-    });
-
-    futures::pin_mut!(write_to_ws, read_from_ws);
-    future::select(write_to_ws, read_from_ws).await;
-}
+        Err(RpcError::Closed)
+    }
 
-fn handle_incoming_msg(
-    msg: Message,
-    pendings: Pendings,
-    subscriptions: Subscriptions,
-    tx: WebSocketSender,
-) {
-    match msg {
-        Message::Text(msg) => {
-            handle_subscription(subscriptions, &msg);
-            handle_pending_response(pendings, &msg);
+    /// Close the transport: send the closing handshake, fail the pending requests with
+    /// `RpcError::Closed`, close the subscriptions, and wait for the connection task to stop.
+    ///
+    /// The requests sent afterwards fail with `RpcError::Closed`.
+    pub async fn close(&self) {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return;
         }
-        Message::Binary(msg) => warn!("Receive `Binary` Message: {:?}", msg),
-        Message::Close(msg) => {
-            warn!("Receive `Close` Message: {:?}", msg);
-            tx.unbounded_send(Message::Close(msg))
-                .expect("Sending `Close` Message should be successful")
+        self.states.lock().set(ConnectionState::Closed);
+        // the connection task forwards the closing handshake, then stops as the channel ends.
+        let _ = self.sender.unbounded_send(Message::Close(None));
+        self.sender.close_channel();
+        let pendings = std::mem::take(&mut *self.pendings.lock());
+        for (_, pending) in pendings {
+            // the caller may have stopped waiting.
+            let _ = pending.send(Err(RpcError::Closed));
         }
-        Message::Ping(msg) => {
-            warn!("Receive `Ping` Message: {:?}", msg);
-            tx.unbounded_send(Message::Pong(msg))
-                .expect("Sending `Pong` Message should be successful")
+        let ids = self
+            .subscriptions
+            .lock()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in ids {
+            close_subscription(
+                &self.subscriptions,
+                &self.renewals,
+                id,
+                CloseReason::Unsubscribed,
+            );
         }
-        Message::Pong(msg) => warn!("Receive `Pong` Message: {:?}", msg),
-    }
-}
 
-fn handle_subscription(subscriptions: Subscriptions, msg: &str) {
-    if let Ok(notification) = serde_json::from_str::<Notification>(msg) {
-        if let Params::Array(params) = notification.params {
-            let id = params.get(0);
-            let result = params.get(1);
-            if let (Some(Value::Number(id)), Some(result)) = (id, result) {
-                let id = id.as_u64().unwrap() as usize;
-                if let Some(stream) = subscriptions.lock().get(&id) {
-                    stream
-                        .unbounded_send(result.clone())
-                        .expect("Sending subscription result to the user should be successful");
-                } else {
-                    warn!("Got notification for unknown subscription (id: {})", id);
+        let handle = self.handle.lock().take();
+        if let Some(mut handle) = handle {
+            if tokio::time::timeout(CLOSE_TIMEOUT, &mut handle)
+                .await
+                .is_err()
+            {
+                // e.g. the task is waiting to reconnect.
+                if let Some(shutdown) = self.shutdown.lock().take() {
+                    let _ = shutdown.send(());
                 }
-            } else {
-                error!("Got unsupported notification (id: {:?})", id);
+                let _ = handle.await;
             }
-        } else {
-            error!(
-                "The Notification Params is not JSON array type: {}",
-                serde_json::to_string(&notification.params)
-                    .expect("Serialize `Params` never fails")
-            );
         }
+        info!("WebSocket transport closed");
     }
-}
 
-fn handle_pending_response(pendings: Pendings, msg: &str) {
-    let response = serde_json::from_str::<Response>(msg).map_err(Into::into);
-    let id = match &response {
-        Ok(Response::Single(output)) => output.id(),
-        Ok(Response::Batch(outputs)) => outputs.get(0).map_or(0, |output| output.id()),
-        Err(_) => 0,
-    };
-    if let Some(request) = pendings.lock().remove(&id) {
-        if let Err(err) = request.send(response) {
-            error!("Sending a response to deallocated channel: {:?}", err);
-        }
+    /// Set the number of the recently sent request ids whose method names are remembered
+    /// for logging the orphan responses.
+    pub fn with_method_history(self, capacity: usize) -> Self {
+        self.methods.lock().set_capacity(capacity);
+        self
     }
-}
 
-#[async_trait::async_trait]
-impl Transport for WebSocketTransport {
-    fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
-        let id = self.id.fetch_add(1, Ordering::AcqRel);
-        let call = Call::MethodCall(MethodCall {
-            jsonrpc: Some(Version::V2),
-            id,
-            method: method.into(),
-            params,
-        });
-        (id, call)
+    /// Set the method of the notification sent to the server by `unsubscribe`, which varies
+    /// by namespace, `DEFAULT_UNSUBSCRIBE_METHOD` by default.
+    pub fn with_unsubscribe_method<M: Into<String>>(self, method: M) -> Self {
+        self.renewals.lock().unsubscribe_method = method.into();
+        self
     }
 
-    async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
-        self.send_request(id, request).await
+    /// Set the number of the notifications buffered per subscription and the policy applied
+    /// when a consumer lags behind, so that a slow consumer can't grow its buffer unbounded.
+    /// It applies to the subscriptions added afterwards.
+    pub fn with_subscription_buffer(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.subscription_capacity = capacity;
+        self.overflow_policy = policy;
+        self
     }
-}
 
-#[async_trait::async_trait]
-impl BatchTransport for WebSocketTransport {}
+    /// Return the number of the notifications of the subscription `id` dropped or overflowed
+    /// because the consumer lagged behind, `None` if the subscription doesn't exist.
+    pub fn lagged(&self, id: SubscriptionId) -> Option<u64> {
+        let current = self.renewals.lock().current.get(&id).cloned().unwrap_or(id);
+        self.subscriptions
+            .lock()
+            .get(&current)
+            .map(Subscription::lagged)
+    }
 
-impl PubsubTransport for WebSocketTransport {
-    fn subscribe<T>(&self, id: SubscriptionId) -> NotificationStream<T>
-    where
-        T: DeserializeOwned,
-    {
-        let (tx, rx) = mpsc::unbounded();
-        if self.subscriptions.lock().insert(id, tx).is_some() {
-            warn!("Replacing already-registered subscription with id {:?}", id);
+    async fn send_request(
+        &self,
+        id: RequestId,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(RpcError::Closed);
+        }
+        if self.pre_connect_policy == PreConnectPolicy::Reject
+            && self.connection_state() != ConnectionState::Connected
+        {
+            return Err(RpcError::NotConnected);
+        }
+        send_request(
+            &self.pendings,
+            &self.methods,
+            &self.sender,
+            id,
+            request,
+            timeout,
+        )
+        .await
+    }
+
+    /// Register the `handler` of the method calls initiated by the server, replacing the
+    /// previous one. Without a handler, the calls are answered with the "method not found" error.
+    pub fn on_request<F>(&self, handler: F)
+    where
+        F: Fn(&str, Params) -> std::result::Result<Value, Error> + Send + Sync + 'static,
+    {
+        *self.handler.lock() = Some(Arc::new(handler));
+    }
+
+    /// Renew the subscription `id` by calling the subscribe `method` with `params` again
+    /// after the WebSocket reconnects, the event stream of the subscription then yields
+    /// `StreamEvent::Reconnected` to report the notifications may be lost during the outage.
+    pub fn renew_on_reconnect<M: Into<String>>(
+        &self,
+        id: SubscriptionId,
+        method: M,
+        params: Params,
+    ) {
+        let mut renewals = self.renewals.lock();
+        renewals.calls.insert(id.clone(), (method.into(), params));
+        renewals.current.insert(id.clone(), id);
+    }
+}
+
+fn register_pending(
+    pendings: &Pendings,
+    methods: &Methods,
+    id: RequestId,
+    request: &Request,
+) -> oneshot::Receiver<Result<Response>> {
+    record_methods(methods, request);
+    insert_pending(pendings, id)
+}
+
+fn record_methods(methods: &Methods, request: &Request) {
+    let calls = match request {
+        Request::Single(call) => std::slice::from_ref(call),
+        Request::Batch(calls) => calls.as_slice(),
+    };
+    let mut methods = methods.lock();
+    for call in calls {
+        if let Call::MethodCall(call) = call {
+            methods.record(call.id, &call.method);
+        }
+    }
+}
+
+fn insert_pending(pendings: &Pendings, id: RequestId) -> oneshot::Receiver<Result<Response>> {
+    let (tx, rx) = oneshot::channel();
+    pendings.lock().insert(id, tx);
+    rx
+}
+
+// Send the request and wait for its response, the calls of a batch are resolved by
+// the outputs of their own ids.
+pub(crate) async fn send_request(
+    pendings: &Pendings,
+    methods: &Methods,
+    sender: &WebSocketSender,
+    id: RequestId,
+    request: &Request,
+    timeout: Duration,
+) -> Result<Response> {
+    let ids = match request {
+        Request::Single(_) => vec![id],
+        // every call of the batch is resolved by the output of its own id.
+        Request::Batch(calls) => calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::MethodCall(call) => Some(call.id),
+                _ => None,
+            })
+            .collect(),
+    };
+    record_methods(methods, request);
+    let receivers = ids
+        .iter()
+        .map(|id| insert_pending(pendings, *id))
+        .collect::<Vec<_>>();
+    let _guards = ids
+        .iter()
+        .map(|id| PendingGuard {
+            id: *id,
+            pendings: pendings.clone(),
+        })
+        .collect::<Vec<_>>();
+    let message = serde_json::to_string(request)?;
+    debug!("Calling {:?}: {}", ids, message);
+
+    if sender.unbounded_send(Message::Text(message)).is_err() {
+        return Err(RpcError::Disconnected(
+            "the connection task has stopped".into(),
+        ));
+    }
+    let sent = Instant::now();
+    for id in &ids {
+        trace!("[request {}] sent", id);
+    }
+
+    // the guards remove the pending requests on timeout, the late responses are then
+    // reported as orphans.
+    let responses = match tokio::time::timeout(timeout, future::join_all(receivers)).await {
+        Ok(responses) => responses,
+        Err(_) => return Err(RpcError::Timeout(timeout)),
+    };
+    let mut outputs = Vec::with_capacity(responses.len());
+    for (id, response) in ids.iter().zip(responses) {
+        trace!("[request {}] resolved in {:?}", id, sent.elapsed());
+        let response = response.unwrap_or_else(|_| {
+            Err(RpcError::Disconnected(
+                "the connection task dropped the request".into(),
+            ))
+        })?;
+        match (request, response) {
+            (Request::Single(_), response) => return Ok(response),
+            (Request::Batch(_), Response::Single(output)) => outputs.push(output),
+            (Request::Batch(_), Response::Batch(_)) => {
+                return Err(RpcError::UnexpectedResponse(
+                    "expected single output, got batch".into(),
+                ))
+            }
+        }
+    }
+    Ok(Response::Batch(outputs))
+}
+
+// Build the handshake request with the bearer auth and the extra headers, the malformed
+// url or bearer token is reported as an error.
+fn handshake_request(
+    url: &str,
+    bearer_auth_token: Option<&str>,
+    headers: &HeaderMap,
+) -> std::result::Result<HandShakeRequest, http::Error> {
+    let mut handshake_request = HandShakeRequest::get(url);
+    if let Some(token) = bearer_auth_token {
+        handshake_request =
+            handshake_request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    if let Some(request_headers) = handshake_request.headers_mut() {
+        replace_headers(request_headers, headers);
+    }
+    handshake_request.body(())
+}
+
+// Replace the headers of the same names in `to` with all the values of `headers`.
+fn replace_headers(to: &mut HeaderMap, headers: &HeaderMap) {
+    for name in headers.keys() {
+        to.remove(name);
+    }
+    for (name, value) in headers {
+        to.append(name.clone(), value.clone());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn ws_task(
+    url: String,
+    bearer_auth_token: Option<String>,
+    headers: HeaderMap,
+    reconnect_policy: ReconnectPolicy,
+    tls: Option<TlsConfig>,
+    heartbeat: Option<Heartbeat>,
+    id: Arc<AtomicUsize>,
+    pendings: Pendings,
+    methods: Methods,
+    sub: Subscriptions,
+    renewals: Renewals,
+    handler: Handler,
+    closed: Arc<AtomicBool>,
+    states: States,
+    tx: WebSocketSender,
+    mut rx: WebSocketReceiver,
+) {
+    let connector = match tls.as_ref().map(TlsConfig::connector).transpose() {
+        Ok(connector) => connector,
+        Err(err) => {
+            // retrying can't fix it, fail the requests with `RpcError::Disconnected`.
+            error!("Invalid TLS config: {}", err);
+            rx.close();
+            pendings.lock().clear();
+            return;
+        }
+    };
+    let mut reconnecting = false;
+    let mut failures = 0;
+    // stop reconnecting once the transport is dropped.
+    while Arc::strong_count(&pendings) > 1 && !closed.load(Ordering::Acquire) {
+        let handshake_request =
+            match handshake_request(&url, bearer_auth_token.as_deref(), &headers) {
+                Ok(handshake_request) => handshake_request,
+                Err(err) => {
+                    // retrying can't fix it, fail the requests with `RpcError::Disconnected`.
+                    error!("Invalid WebSocket handshake request: {}", err);
+                    rx.close();
+                    pendings.lock().clear();
+                    return;
+                }
+            };
+        // an invalid server certificate fails the handshake, which is retried by the policy.
+        let ws_stream = match connect_async_with_tls_connector(handshake_request, connector.clone())
+            .await
+        {
+            Ok((ws_stream, _)) => ws_stream,
+            Err(err) => {
+                error!("WebSocket handshake failed: {}", err);
+                failures += 1;
+                let gives_up = reconnect_policy.gives_up(failures);
+                let reason = if gives_up {
+                    format!("gave up reconnecting after {} attempts: {}", failures, err)
+                } else {
+                    format!("handshake failed: {}", err)
+                };
+                fail_buffered(&pendings, &mut rx, &reason);
+                if gives_up {
+                    error!(
+                        "Gave up reconnecting the WebSocket after {} attempts",
+                        failures
+                    );
+                    rx.close();
+                    let ids = sub.lock().keys().cloned().collect::<Vec<_>>();
+                    for id in ids {
+                        close_subscription(&sub, &renewals, id, CloseReason::Error(reason.clone()));
+                    }
+                    return;
+                }
+                tokio::time::delay_for(reconnect_policy.delay(failures - 1)).await;
+                continue;
+            }
+        };
+        failures = 0;
+        info!("WebSocket handshake has been successfully completed");
+        states.lock().set(ConnectionState::Connected);
+        let (sink, stream) = ws_stream.split();
+        if reconnecting {
+            task::spawn(renew_subscriptions(
+                id.clone(),
+                pendings.clone(),
+                methods.clone(),
+                sub.clone(),
+                renewals.clone(),
+                tx.clone(),
+            ));
+        }
+
+        // receive request from WebSocketSender,
+        // and forward the request to sink that will send message to websocket stream.
+        let write_to_ws = (&mut rx).map(Ok).forward(sink);
+        // any message received proves the connection is alive.
+        let last_received = Mutex::new(Instant::now());
+        // read websocket message from websocket stream, and handle the incoming message.
+        let read_from_ws = stream.for_each(|msg| async {
+            *last_received.lock() = Instant::now();
+            match msg {
+                Ok(msg) => handle_incoming_msg(
+                    msg,
+                    pendings.clone(),
+                    methods.clone(),
+                    sub.clone(),
+                    renewals.clone(),
+                    handler.clone(),
+                    tx.clone(),
+                ),
+                Err(err) => error!("WebSocket stream read error: {}", err),
+            }
+        });
+
+        // ping periodically, and give up the connection if it doesn't answer.
+        let keepalive = async {
+            let heartbeat = match heartbeat {
+                Some(heartbeat) => heartbeat,
+                None => return future::pending().await,
+            };
+            loop {
+                tokio::time::delay_for(heartbeat.interval).await;
+                let ping = Instant::now();
+                if tx.unbounded_send(Message::Ping(vec![])).is_err() {
+                    return;
+                }
+                tokio::time::delay_for(heartbeat.timeout).await;
+                if *last_received.lock() < ping {
+                    warn!(
+                        "WebSocket didn't answer the ping within {:?}",
+                        heartbeat.timeout
+                    );
+                    return;
+                }
+            }
+        };
+
+        futures::pin_mut!(write_to_ws, read_from_ws, keepalive);
+        future::select(future::select(write_to_ws, read_from_ws), keepalive).await;
+        if closed.load(Ordering::Acquire) {
+            return;
+        }
+        warn!("WebSocket disconnected, reconnecting");
+        states.lock().set(ConnectionState::Reconnecting);
+        reset_pendings(&pendings);
+        // the notifications of the old connection can't arrive anymore.
+        renewals.lock().unsubscribed.clear();
+        reconnecting = true;
+        tokio::time::delay_for(reconnect_policy.initial_delay).await;
+    }
+}
+
+// The requests buffered for the connection can't be sent once the handshake fails, fail them
+// rather than leaving them waiting for the next attempt.
+fn fail_buffered(pendings: &Pendings, rx: &mut WebSocketReceiver, reason: &str) {
+    while let Ok(Some(_)) = rx.try_next() {}
+    let failed = std::mem::take(&mut *pendings.lock());
+    for (_, pending) in failed {
+        // the caller may have stopped waiting.
+        let _ = pending.send(Err(RpcError::Disconnected(reason.into())));
+    }
+}
+
+// The responses of the in-flight requests are lost with the connection, fail them rather
+// than leaving the callers waiting forever.
+pub(crate) fn reset_pendings(pendings: &Pendings) {
+    let pendings = std::mem::take(&mut *pendings.lock());
+    for (_, pending) in pendings {
+        // the caller may have stopped waiting.
+        let _ = pending.send(Err(RpcError::ConnectionReset));
+    }
+}
+
+// Call the subscribe methods again on the reconnected WebSocket, move the subscriptions to
+// their new ids and notify them of the reconnection.
+async fn renew_subscriptions(
+    id: Arc<AtomicUsize>,
+    pendings: Pendings,
+    methods: Methods,
+    subscriptions: Subscriptions,
+    renewals: Renewals,
+    tx: WebSocketSender,
+) {
+    let calls = renewals
+        .lock()
+        .calls
+        .iter()
+        .map(|(old, (method, params))| (old.clone(), method.clone(), params.clone()))
+        .collect::<Vec<_>>();
+    for (old, method, params) in calls {
+        let request_id = id.fetch_add(1, Ordering::AcqRel);
+        let request = Request::Single(Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            id: request_id,
+            method: method.clone(),
+            params,
+        }));
+        let rx = register_pending(&pendings, &methods, request_id, &request);
+        let sent = serde_json::to_string(&request)
+            .map_err(|err| err.to_string())
+            .and_then(|request| {
+                debug!("Renewing subscription {}: {}", old, request);
+                tx.unbounded_send(Message::Text(request))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = sent {
+            pendings.lock().remove(&request_id);
+            warn!(
+                "Failed to send the renewal of subscription {}: {}",
+                old, err
+            );
+            let reason = format!("failed to renew subscription by {}: {}", method, err);
+            close_subscription(&subscriptions, &renewals, old, CloseReason::Error(reason));
+            continue;
+        }
+
+        let new = match rx.await {
+            Ok(Ok(Response::Single(ResponseOutput::Success(success)))) => {
+                serde_json::from_value::<SubscriptionId>(success.result).ok()
+            }
+            _ => None,
+        };
+        match new {
+            Some(new) => {
+                if renew_subscription(&subscriptions, &renewals, old, new.clone()) {
+                    // the consumer dropped the stream during the outage.
+                    unsubscribe(&subscriptions, &renewals, &tx, new);
+                }
+            }
+            None => {
+                warn!(
+                    "Failed to renew subscription (id: {}, method: {})",
+                    old, method
+                );
+                let reason = format!("failed to renew subscription by {}", method);
+                close_subscription(&subscriptions, &renewals, old, CloseReason::Error(reason));
+            }
+        }
+    }
+}
+
+// Move the subscription `old` to its renewed id `new`, return whether its stream is dropped
+// by the consumer, which should then be unsubscribed.
+fn renew_subscription(
+    subscriptions: &Subscriptions,
+    renewals: &Renewals,
+    old: SubscriptionId,
+    new: SubscriptionId,
+) -> bool {
+    let mut renewals = renewals.lock();
+    // unsubscribed during the renewal.
+    let call = match renewals.calls.remove(&old) {
+        Some(call) => call,
+        None => return false,
+    };
+    renewals.calls.insert(new.clone(), call);
+    for current in renewals.current.values_mut() {
+        if *current == old {
+            *current = new.clone();
+        }
+    }
+
+    let mut subscriptions = subscriptions.lock();
+    match subscriptions.remove(&old) {
+        Some(stream) => {
+            let detached = stream.send(StreamEvent::Reconnected) == Delivery::Detached;
+            subscriptions.insert(new, stream);
+            detached
+        }
+        None => false,
+    }
+}
+
+// Remove the subscription whose current id is `id` and send the terminal event to its stream,
+// return whether the subscription existed.
+pub(crate) fn close_subscription(
+    subscriptions: &Subscriptions,
+    renewals: &Renewals,
+    id: SubscriptionId,
+    reason: CloseReason,
+) -> bool {
+    let mut renewals = renewals.lock();
+    renewals.calls.remove(&id);
+    let aliases = renewals
+        .current
+        .iter()
+        .filter(|(_, current)| **current == id)
+        .map(|(original, _)| original.clone())
+        .collect::<Vec<_>>();
+    for original in aliases {
+        renewals.current.remove(&original);
+    }
+
+    match subscriptions.lock().remove(&id) {
+        Some(stream) => {
+            // the consumer may have dropped the stream.
+            stream.send(StreamEvent::Closed(reason));
+            true
+        }
+        None => false,
+    }
+}
+
+// Close the subscription `id` (or the subscription renewing it), and tell the server
+// to stop sending its notifications.
+pub(crate) fn unsubscribe(
+    subscriptions: &Subscriptions,
+    renewals: &Renewals,
+    sender: &WebSocketSender,
+    id: SubscriptionId,
+) {
+    let current = renewals.lock().current.get(&id).cloned().unwrap_or(id);
+    let reason = CloseReason::Unsubscribed;
+    if !close_subscription(subscriptions, renewals, current.clone(), reason) {
+        return;
+    }
+    let method = {
+        let mut renewals = renewals.lock();
+        renewals.unsubscribed.insert(current.clone());
+        renewals.unsubscribe_method.clone()
+    };
+
+    // no response is expected, so it's sent as a notification.
+    let notification = Request::Single(Call::Notification(Notification {
+        jsonrpc: Some(Version::V2),
+        method,
+        params: Params::Array(vec![subscription_id_value(current)]),
+    }));
+    let request = match serde_json::to_string(&notification) {
+        Ok(request) => request,
+        Err(err) => {
+            error!("Failed to serialize the unsubscribe notification: {}", err);
+            return;
+        }
+    };
+    // the connection task may have stopped, then the server won't send anything anyway.
+    if let Err(err) = sender.unbounded_send(Message::Text(request)) {
+        debug!("Failed to send the unsubscribe notification: {}", err);
+    }
+}
+
+pub(crate) fn handle_incoming_msg(
+    msg: Message,
+    pendings: Pendings,
+    methods: Methods,
+    subscriptions: Subscriptions,
+    renewals: Renewals,
+    handler: Handler,
+    tx: WebSocketSender,
+) {
+    match msg {
+        Message::Text(msg) => {
+            handle_subscription(subscriptions, renewals, &tx, &msg);
+            handle_pending_response(pendings, methods, &msg);
+            handle_method_call(handler, &tx, &msg);
+        }
+        Message::Binary(msg) => warn!("Receive `Binary` Message: {:?}", msg),
+        Message::Close(msg) => {
+            warn!("Receive `Close` Message: {:?}", msg);
+            if let Err(err) = tx.unbounded_send(Message::Close(msg)) {
+                error!("Failed to send `Close` Message: {}", err);
+            }
+        }
+        Message::Ping(msg) => {
+            warn!("Receive `Ping` Message: {:?}", msg);
+            if let Err(err) = tx.unbounded_send(Message::Pong(msg)) {
+                error!("Failed to send `Pong` Message: {}", err);
+            }
+        }
+        Message::Pong(msg) => debug!("Receive `Pong` Message: {:?}", msg),
+    }
+}
+
+// The id of a subscription may be a number or a string, depending on the server.
+fn subscription_id(value: Option<&Value>) -> Option<SubscriptionId> {
+    match value? {
+        Value::Number(id) => id.as_u64().map(SubscriptionId::Number),
+        Value::String(id) => Some(SubscriptionId::String(id.clone())),
+        _ => None,
+    }
+}
+
+fn subscription_id_value(id: SubscriptionId) -> Value {
+    match id {
+        SubscriptionId::Number(id) => Value::from(id),
+        SubscriptionId::String(id) => Value::from(id),
+    }
+}
+
+fn handle_subscription(
+    subscriptions: Subscriptions,
+    renewals: Renewals,
+    tx: &WebSocketSender,
+    msg: &str,
+) {
+    if let Ok(notification) = serde_json::from_str::<Notification>(msg) {
+        if notification.method == SUBSCRIPTION_CLOSE_METHOD {
+            match &notification.params {
+                Params::Array(params) => match subscription_id(params.get(0)) {
+                    Some(id) => {
+                        close_subscription(
+                            &subscriptions,
+                            &renewals,
+                            id,
+                            CloseReason::ServerCancelled,
+                        );
+                    }
+                    None => error!("Got unsupported cancellation (params: {:?})", params),
+                },
+                params => error!("Got unsupported cancellation (params: {:?})", params),
+            }
+            return;
+        }
+        if let Params::Array(params) = notification.params {
+            let id = params.get(0);
+            let result = params.get(1);
+            if let (Some(id), Some(result)) = (subscription_id(id), result) {
+                let delivery = subscriptions
+                    .lock()
+                    .get(&id)
+                    .map(|stream| stream.send(StreamEvent::Item(result.clone())));
+                match delivery {
+                    Some(Delivery::Queued) => {}
+                    Some(Delivery::Dropped) => {
+                        debug!("Subscription {} lagged, dropped a notification", id)
+                    }
+                    Some(Delivery::Overflowed) => {
+                        warn!("Subscription {} lagged, disconnecting", id);
+                        close_subscription(&subscriptions, &renewals, id, CloseReason::Lagged);
+                    }
+                    // the consumer dropped the stream, stop the notifications.
+                    Some(Delivery::Detached) => {
+                        debug!("Subscription {} dropped by its consumer, unsubscribing", id);
+                        unsubscribe(&subscriptions, &renewals, tx, id);
+                    }
+                    None if renewals.lock().unsubscribed.contains(&id) => {
+                        debug!(
+                            "Got late notification for unsubscribed subscription (id: {})",
+                            id
+                        )
+                    }
+                    None => warn!("Got notification for unknown subscription (id: {})", id),
+                }
+            } else {
+                error!("Got unsupported notification (id: {:?})", id);
+            }
+        } else {
+            error!(
+                "The Notification Params is not JSON array type: {:?}",
+                notification.params
+            );
+        }
+    }
+}
+
+// Answer the method call initiated by the server over the same connection.
+fn handle_method_call(handler: Handler, tx: &WebSocketSender, msg: &str) {
+    let call = match serde_json::from_str::<MethodCall>(msg) {
+        Ok(call) => call,
+        // not a method call, e.g. a response.
+        Err(_) => return,
+    };
+    // don't hold the lock while handling, the handler may replace itself.
+    let handler = handler.lock().clone();
+    let result = match handler {
+        Some(handler) => handler(&call.method, call.params),
+        None => {
+            warn!("Got method call without handler (method: {})", call.method);
+            Err(Error::method_not_found())
+        }
+    };
+    let output = ResponseOutput::from(call.jsonrpc, call.id, result);
+    let response = match serde_json::to_string(&Response::Single(output)) {
+        Ok(response) => response,
+        Err(err) => {
+            error!("Failed to serialize the response of method call: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = tx.unbounded_send(Message::Text(response)) {
+        error!("Failed to send the response of method call: {}", err);
+    }
+}
+
+fn handle_pending_response(pendings: Pendings, methods: Methods, msg: &str) {
+    match serde_json::from_str::<Response>(msg) {
+        Ok(Response::Single(output)) => resolve_pending(&pendings, &methods, output),
+        // the outputs of a batch may be in any order, each resolves the call of its own id.
+        Ok(Response::Batch(outputs)) => {
+            for output in outputs {
+                resolve_pending(&pendings, &methods, output);
+            }
         }
-        Box::pin(
-            rx.map(|value| serde_json::from_value(value).expect("Deserialize `Value` never fails")),
+        // not a response, e.g. a subscription notification.
+        Err(_) => {}
+    }
+}
+
+fn resolve_pending(pendings: &Pendings, methods: &Methods, output: ResponseOutput) {
+    let id = output.id();
+    trace!("[request {}] received", id);
+    let pending = pendings.lock().remove(&id);
+    match pending {
+        Some(request) => {
+            if let Err(err) = request.send(Ok(Response::Single(output))) {
+                error!("Sending a response to deallocated channel: {:?}", err);
+            }
+        }
+        None => warn!("{}", orphan_response_log(methods, id)),
+    }
+}
+
+fn orphan_response_log(methods: &Methods, id: RequestId) -> String {
+    match methods.lock().get(id) {
+        Some(method) => format!("Orphan response for id {} (method {})", id, method),
+        None => format!("Orphan response for id {} (method unknown)", id),
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for WebSocketTransport {
+    fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        let call = Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            id,
+            method: method.into(),
+            params,
+        });
+        (id, call)
+    }
+
+    async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
+        self.send_request(id, request, self.request_timeout).await
+    }
+}
+
+// The batch is sent in a single `Request::Batch`, and every output of the batch response
+// resolves the pending call of its own id.
+#[async_trait::async_trait]
+impl BatchTransport for WebSocketTransport {}
+
+#[async_trait::async_trait]
+impl TimeoutTransport for WebSocketTransport {
+    async fn execute_with_timeout(
+        &self,
+        id: RequestId,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response> {
+        self.send_request(id, request, timeout).await
+    }
+}
+
+impl WebSocketTransport {
+    fn event_stream(&self, id: SubscriptionId) -> SubscriptionStream {
+        subscription_stream(
+            &self.subscriptions,
+            self.subscription_capacity,
+            self.overflow_policy,
+            id,
         )
     }
+}
+
+pub(crate) fn subscription_stream(
+    subscriptions: &Subscriptions,
+    capacity: usize,
+    policy: OverflowPolicy,
+    id: SubscriptionId,
+) -> SubscriptionStream {
+    let (tx, rx) = Subscription::channel(capacity, policy);
+    if subscriptions.lock().insert(id.clone(), tx).is_some() {
+        warn!("Replacing already-registered subscription with id {}", id);
+    }
+    rx
+}
+
+// Decode the notification of the subscription `id`, the malformed one is logged.
+fn decode_notification<T: DeserializeOwned>(
+    id: &SubscriptionId,
+    value: Value,
+) -> std::result::Result<T, String> {
+    serde_json::from_value(value).map_err(|err| {
+        error!("Malformed notification of subscription {}: {}", id, err);
+        err.to_string()
+    })
+}
+
+// Decode the notifications of the subscription `id`, the malformed ones are skipped.
+pub(crate) fn decode_notifications<T: DeserializeOwned>(
+    id: SubscriptionId,
+    stream: SubscriptionStream,
+) -> NotificationStream<T> {
+    Box::pin(stream.filter_map(move |event| {
+        future::ready(match event {
+            StreamEvent::Item(value) => decode_notification(&id, value).ok(),
+            StreamEvent::Reconnected | StreamEvent::Closed(_) | StreamEvent::Malformed(_) => None,
+        })
+    }))
+}
+
+// Decode the events of the subscription `id`, the malformed notifications are surfaced
+// as `StreamEvent::Malformed`.
+pub(crate) fn decode_events<T: DeserializeOwned>(
+    id: SubscriptionId,
+    stream: SubscriptionStream,
+) -> EventStream<T> {
+    Box::pin(stream.filter_map(move |event| {
+        future::ready(match event {
+            StreamEvent::Item(value) => match decode_notification(&id, value) {
+                Ok(item) => Some(StreamEvent::Item(item)),
+                Err(err) => Some(StreamEvent::Malformed(err)),
+            },
+            StreamEvent::Reconnected => Some(StreamEvent::Reconnected),
+            StreamEvent::Closed(reason) => Some(StreamEvent::Closed(reason)),
+            StreamEvent::Malformed(err) => Some(StreamEvent::Malformed(err)),
+        })
+    }))
+}
+
+impl PubsubTransport for WebSocketTransport {
+    fn subscribe<T>(&self, id: SubscriptionId) -> NotificationStream<T>
+    where
+        T: DeserializeOwned,
+    {
+        decode_notifications(id.clone(), self.event_stream(id))
+    }
+
+    fn subscribe_events<T>(&self, id: SubscriptionId) -> EventStream<T>
+    where
+        T: DeserializeOwned,
+    {
+        decode_events(id.clone(), self.event_stream(id))
+    }
 
     fn unsubscribe(&self, id: SubscriptionId) {
-        self.subscriptions.lock().remove(&id);
+        unsubscribe(&self.subscriptions, &self.renewals, &self.sender, id)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::FutureExt;
+
     use super::*;
 
+    fn method_call(id: RequestId, method: &str) -> Request {
+        Request::Single(Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            id,
+            method: method.into(),
+            params: Params::Array(vec![]),
+        }))
+    }
+
+    #[test]
+    fn test_handshake_headers() {
+        let builder = WebSocketTransport::builder("ws://127.0.0.1:1234/rpc/v0")
+            .bearer_auth("token")
+            .headers(vec![("X-Api-Key", "secret"), ("X-Tenant-Id", "plum")])
+            .unwrap()
+            .header("User-Agent", "plum/0.1")
+            .unwrap();
+        let request = handshake_request(
+            &builder.url,
+            builder.bearer_auth_token.as_deref(),
+            &builder.headers,
+        )
+        .unwrap();
+        let headers = request.headers();
+        assert_eq!(headers[header::AUTHORIZATION], "Bearer token");
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["x-tenant-id"], "plum");
+        assert_eq!(headers[header::USER_AGENT], "plum/0.1");
+
+        // the malformed headers are rejected rather than panicking on connect.
+        let builder = WebSocketTransport::builder("ws://127.0.0.1:1234/rpc/v0");
+        let err = builder.header("X Api Key", "secret").err().unwrap();
+        assert!(matches!(err, RpcError::InvalidHeader(_)));
+        let builder = WebSocketTransport::builder("ws://127.0.0.1:1234/rpc/v0");
+        let err = builder.header("X-Api-Key", "sec\nret").err().unwrap();
+        assert!(matches!(err, RpcError::InvalidHeader(_)));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_header_map() {
+        use async_tungstenite::tungstenite::handshake::server::{
+            ErrorResponse, Request as ServerRequest, Response as ServerResponse,
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", HeaderValue::from_static("42"));
+        headers.insert(header::COOKIE, HeaderValue::from_static("session=plum"));
+        headers.append(header::COOKIE, HeaderValue::from_static("theme=dark"));
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic cGx1bQ=="),
+        );
+        let cookies = vec!["session=plum", "theme=dark"];
+
+        let builder = WebSocketTransport::builder("ws://127.0.0.1:1/rpc/v0")
+            .header("Cookie", "stale=1")
+            .unwrap()
+            .header_map(headers.clone());
+        let request = handshake_request(
+            &builder.url,
+            builder.bearer_auth_token.as_deref(),
+            &builder.headers,
+        )
+        .unwrap();
+        assert_eq!(request.headers().len(), headers.len());
+        for name in headers.keys() {
+            assert_eq!(
+                request.headers().get_all(name).iter().collect::<Vec<_>>(),
+                headers.get_all(name).iter().collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(
+            request
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .collect::<Vec<_>>(),
+            cookies
+        );
+
+        // the server receives the same headers in the handshake of the transport.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (received_tx, received_rx) = oneshot::channel();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let callback = |request: &ServerRequest, response: ServerResponse| {
+                let _ = received_tx.send(request.headers().clone());
+                Ok::<_, ErrorResponse>(response)
+            };
+            let _ws = async_tungstenite::tokio::accept_hdr_async(socket, callback)
+                .await
+                .unwrap();
+            future::pending::<()>().await;
+        });
+        let ws = WebSocketTransport::new_with_headers(format!("ws://{}/rpc/v0", addr), headers);
+        let received = received_rx.await.unwrap();
+        assert_eq!(received["x-request-id"], "42");
+        assert_eq!(received[header::AUTHORIZATION], "Basic cGx1bQ==");
+        assert_eq!(
+            received.get_all(header::COOKIE).iter().collect::<Vec<_>>(),
+            cookies
+        );
+        ws.ready().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_notifies_server() {
+        use futures::SinkExt;
+
+        // the server replies the subscribe call with the subscription 5, then reports
+        // the unsubscribe notification and sends a late notification.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (calls_tx, mut calls_rx) = mpsc::unbounded();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                let msg = match msg {
+                    Message::Text(msg) => msg,
+                    _ => continue,
+                };
+                let call = serde_json::from_str::<Call>(&msg).unwrap();
+                if let Call::MethodCall(call) = &call {
+                    let response = format!(r#"{{"jsonrpc":"2.0","result":5,"id":{}}}"#, call.id);
+                    ws.send(Message::Text(response)).await.unwrap();
+                } else {
+                    let late = r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[5,1]}"#;
+                    ws.send(Message::Text(late.into())).await.unwrap();
+                }
+                calls_tx.unbounded_send(call).unwrap();
+            }
+        });
+
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr))
+            .with_unsubscribe_method("Filecoin.ChainNotifyCancel");
+        let id = ws
+            .send::<SubscriptionId>("Filecoin.ChainNotify", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(id, SubscriptionId::Number(5));
+        let mut stream = ws.subscribe_events::<u64>(id.clone());
+        assert!(matches!(calls_rx.next().await, Some(Call::MethodCall(_))));
+
+        ws.unsubscribe(id.clone());
+        assert_eq!(
+            stream.next().await,
+            Some(StreamEvent::Closed(CloseReason::Unsubscribed))
+        );
+        assert_eq!(stream.next().await, None);
+        assert_eq!(
+            calls_rx.next().await,
+            Some(Call::Notification(Notification {
+                jsonrpc: Some(Version::V2),
+                method: "Filecoin.ChainNotifyCancel".into(),
+                params: Params::Array(vec![Value::from(5)]),
+            }))
+        );
+        // the late notification is expected, and the unknown subscription is not closed again.
+        assert!(ws
+            .renewals
+            .lock()
+            .unsubscribed
+            .contains(&SubscriptionId::Number(5)));
+        ws.unsubscribe(id);
+        assert!(ws.subscriptions.lock().is_empty());
+    }
+
+    // A server answering every call of its first connection with the version "1.0.0".
+    async fn version_server(mut listener: tokio::net::TcpListener) {
+        use futures::SinkExt;
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut ws = async_tungstenite::tokio::accept_async(socket)
+            .await
+            .unwrap();
+        while let Some(Ok(Message::Text(msg))) = ws.next().await {
+            let call = serde_json::from_str::<MethodCall>(&msg).unwrap();
+            let response = format!(r#"{{"jsonrpc":"2.0","result":"1.0.0","id":{}}}"#, call.id);
+            ws.send(Message::Text(response)).await.unwrap();
+        }
+    }
+
+    // The number of the messages captured per thread, the oldest are dropped beyond it.
+    const LOG_CAPACITY: usize = 1024;
+
+    thread_local! {
+        static LOGS: std::cell::RefCell<VecDeque<String>> = Default::default();
+    }
+
+    // A logger printing the messages like env_logger and capturing them per thread,
+    // so that the tests running in parallel only read their own.
+    struct CaptureLogger(env_logger::Logger);
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGS.with(|logs| {
+                let mut logs = logs.borrow_mut();
+                if logs.len() == LOG_CAPACITY {
+                    logs.pop_front();
+                }
+                logs.push_back(record.args().to_string());
+            });
+            self.0.log(record);
+        }
+
+        fn flush(&self) {
+            self.0.flush();
+        }
+    }
+
+    // Install the capturing logger, once for all the tests.
+    fn init_logger() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            let logger = CaptureLogger(env_logger::Builder::from_default_env().build());
+            log::set_boxed_logger(Box::new(logger)).expect("the logger is only set here");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    // Take the messages captured on the current thread.
+    fn take_logs() -> Vec<String> {
+        LOGS.with(|logs| logs.borrow_mut().drain(..).collect())
+    }
+
+    #[tokio::test]
+    async fn test_request_correlation_logs() {
+        init_logger();
+        take_logs();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(version_server(listener));
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        let (first, second) = future::join(
+            ws.send::<String>("Filecoin.Version", Params::Array(vec![])),
+            ws.send::<String>("Filecoin.Version", Params::Array(vec![])),
+        )
+        .await;
+        assert!(first.is_ok() && second.is_ok());
+
+        // the thread runs the tasks of the test only.
+        let logs = take_logs();
+        for id in 1..=2 {
+            let tag = format!("[request {}] ", id);
+            let lines = logs
+                .iter()
+                .filter_map(|msg| msg.strip_prefix(&tag))
+                .collect::<Vec<_>>();
+            assert_eq!(lines.len(), 3, "{:?}", logs);
+            assert_eq!(lines[0], "sent");
+            assert_eq!(lines[1], "received");
+            assert!(lines[2].starts_with("resolved in "));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(version_server(listener));
+
+        // the transport connects in the background.
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        assert_eq!(ws.connection_state(), ConnectionState::Connecting);
+        let mut states = ws.connection_states();
+        assert_eq!(states.next().await, Some(ConnectionState::Connecting));
+
+        ws.ready().await.unwrap();
+        assert_eq!(ws.connection_state(), ConnectionState::Connected);
+        assert_eq!(states.next().await, Some(ConnectionState::Connected));
+        let version: String = ws
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(version, "1.0.0");
+
+        // the stream ends after the transport is closed.
+        ws.close().await;
+        assert_eq!(ws.connection_state(), ConnectionState::Closed);
+        assert_eq!(
+            states.collect::<Vec<_>>().await,
+            vec![ConnectionState::Closed]
+        );
+        assert!(matches!(ws.ready().await, Err(RpcError::Closed)));
+        assert_eq!(
+            ws.connection_states().collect::<Vec<_>>().await,
+            vec![ConnectionState::Closed]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pre_connect_policy() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(version_server(listener));
+
+        // the request sent right after the construction is buffered until connected.
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        assert_eq!(ws.connection_state(), ConnectionState::Connecting);
+        let version: String = ws
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(version, "1.0.0");
+
+        // or rejected until connected.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(version_server(listener));
+        let ws = WebSocketTransport::new_with_pre_connect_policy(
+            format!("ws://{}/rpc/v0", addr),
+            PreConnectPolicy::Reject,
+        );
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::NotConnected)));
+        ws.ready().await.unwrap();
+        let version: String = ws
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(version, "1.0.0");
+
+        // the buffered request fails with the handshake, rather than waiting for the retry.
+        let ws = WebSocketTransport::builder("ws://127.0.0.1:1/rpc/v0")
+            .reconnect_policy(ReconnectPolicy {
+                initial_delay: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build();
+        let start = Instant::now();
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        match result {
+            Err(RpcError::Disconnected(reason)) => assert!(reason.starts_with("handshake failed")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(ws.pendings.lock().is_empty());
+        assert_eq!(ws.connection_state(), ConnectionState::Connecting);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        // the server never replies, and reports the closing handshake.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed_tx, mut closed_rx) = mpsc::unbounded();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Close(_) = msg {
+                    closed_tx.unbounded_send(()).unwrap();
+                }
+            }
+        });
+
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        let mut stream = ws.subscribe_events::<u64>(SubscriptionId::Number(1));
+        let (result, _) = future::join(
+            ws.send::<Value>("Filecoin.Version", Params::Array(vec![])),
+            async {
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+                ws.close().await;
+            },
+        )
+        .await;
+
+        // the in-flight request and the subscription are finished by the close.
+        assert!(matches!(result, Err(RpcError::Closed)));
+        assert!(ws.pendings.lock().is_empty());
+        assert_eq!(
+            stream.next().await,
+            Some(StreamEvent::Closed(CloseReason::Unsubscribed))
+        );
+        assert_eq!(closed_rx.next().await, Some(()));
+        assert!(ws.handle.lock().is_none());
+
+        // the calls after the close fail instead of being enqueued.
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Closed)));
+        // closing again is a no-op.
+        ws.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_server_method_call() {
+        use futures::SinkExt;
+
+        let output = |id: RequestId, result| {
+            Response::Single(ResponseOutput::from(Some(Version::V2), id, result))
+        };
+
+        // the server calls two methods of the client, and reports the responses.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (responses_tx, mut responses_rx) = mpsc::unbounded();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            let calls = vec![
+                r#"{"jsonrpc":"2.0","method":"Client.Add","params":[1,2],"id":7}"#,
+                r#"{"jsonrpc":"2.0","method":"Client.Unknown","params":[],"id":8}"#,
+            ];
+            for call in calls {
+                ws.send(Message::Text(call.into())).await.unwrap();
+                while let Some(Ok(msg)) = ws.next().await {
+                    if let Message::Text(msg) = msg {
+                        let response = serde_json::from_str::<Response>(&msg).unwrap();
+                        responses_tx.unbounded_send(response).unwrap();
+                        break;
+                    }
+                }
+            }
+        });
+
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        ws.on_request(|method, params| match method {
+            "Client.Add" => {
+                let (a, b) = params.parse::<(u64, u64)>()?;
+                Ok(Value::from(a + b))
+            }
+            _ => Err(Error::method_not_found()),
+        });
+        assert_eq!(
+            responses_rx.next().await,
+            Some(output(7, Ok(Value::from(3))))
+        );
+        assert_eq!(
+            responses_rx.next().await,
+            Some(output(8, Err(Error::method_not_found())))
+        );
+
+        // without a handler, the calls are answered with "method not found".
+        let ws = WebSocketTransport::new("ws://127.0.0.1:1/rpc/v0");
+        let (tx, rx) = mpsc::unbounded();
+        let call = r#"{"jsonrpc":"2.0","method":"Client.Add","params":[1,2],"id":9}"#;
+        handle_method_call(ws.handler.clone(), &tx, call);
+        // the responses and the notifications are not method calls.
+        handle_method_call(
+            ws.handler.clone(),
+            &tx,
+            r#"{"jsonrpc":"2.0","result":3,"id":9}"#,
+        );
+        handle_method_call(
+            ws.handler.clone(),
+            &tx,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,7]}"#,
+        );
+        drop(tx);
+        let replies = rx.collect::<Vec<_>>().await;
+        assert_eq!(replies.len(), 1);
+        match &replies[0] {
+            Message::Text(msg) => assert_eq!(
+                serde_json::from_str::<Response>(msg).unwrap(),
+                output(9, Err(Error::method_not_found()))
+            ),
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_frames() {
+        let ws = WebSocketTransport::new("ws://127.0.0.1:1/rpc/v0");
+        let mut stream = ws.subscribe::<u64>(SubscriptionId::Number(1));
+        let frames = vec![
+            "not json",
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[-1,5]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1.5,5]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":{"id":1}}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,"five"]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.close","params":["one"]}"#,
+            r#"{"jsonrpc":"2.0","result":"orphan","id":"one"}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,7]}"#,
+        ];
+        for frame in frames {
+            handle_incoming_msg(
+                Message::Text(frame.into()),
+                ws.pendings.clone(),
+                ws.methods.clone(),
+                ws.subscriptions.clone(),
+                ws.renewals.clone(),
+                ws.handler.clone(),
+                ws.sender.clone(),
+            );
+        }
+        ws.unsubscribe(SubscriptionId::Number(1));
+
+        // the malformed notifications are skipped.
+        assert_eq!(stream.next().await, Some(7));
+        assert_eq!(stream.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_events() {
+        let ws = WebSocketTransport::new("ws://127.0.0.1:1/rpc/v0");
+        let stream = ws.subscribe_events::<u64>(SubscriptionId::Number(1));
+        let pending = register_pending(
+            &ws.pendings,
+            &ws.methods,
+            2,
+            &method_call(2, "Filecoin.Version"),
+        );
+        let frames = vec![
+            "",
+            "[]",
+            "null",
+            r#"{"jsonrpc":"2.0"}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val"}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,"five"]}"#,
+            r#"{"jsonrpc":"2.0","result":1}"#,
+            r#"{"jsonrpc":"2.0","error":{"code":"x"},"id":2}"#,
+            r#"[{"jsonrpc":"2.0","result":1,"id":3},{"id":"x"}]"#,
+            r#"{"jsonrpc":"2.0","result":"1.0.0","id":2}"#,
+            r#"{"jsonrpc":"2.0","result":"1.0.0","id":2}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[1,7]}"#,
+        ];
+        for frame in frames {
+            handle_incoming_msg(
+                Message::Text(frame.into()),
+                ws.pendings.clone(),
+                ws.methods.clone(),
+                ws.subscriptions.clone(),
+                ws.renewals.clone(),
+                ws.handler.clone(),
+                ws.sender.clone(),
+            );
+        }
+        handle_incoming_msg(
+            Message::Binary(vec![0xff, 0x00]),
+            ws.pendings.clone(),
+            ws.methods.clone(),
+            ws.subscriptions.clone(),
+            ws.renewals.clone(),
+            ws.handler.clone(),
+            ws.sender.clone(),
+        );
+        ws.unsubscribe(SubscriptionId::Number(1));
+
+        // the pending request gets a response, the duplicate one is an orphan.
+        assert!(pending.await.unwrap().is_ok());
+        // the undecodable notification is surfaced on the event stream.
+        let events = stream.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], StreamEvent::Malformed(_)));
+        assert_eq!(events[1], StreamEvent::Item(7));
+        assert_eq!(events[2], StreamEvent::Closed(CloseReason::Unsubscribed));
+    }
+
+    #[tokio::test]
+    async fn test_send_after_close() {
+        let ws = WebSocketTransport::new("ws://127.0.0.1:1/rpc/v0");
+        // the connection task is gone.
+        ws.sender.close_channel();
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Disconnected(_))));
+        assert!(ws.pendings.lock().is_empty());
+
+        // the malformed bearer token stops the connection task instead of panicking.
+        let ws = WebSocketTransport::new_with_bearer_auth("ws://127.0.0.1:1/rpc/v0", "bad\ntoken");
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Disconnected(_))));
+    }
+
+    #[test]
+    fn test_orphan_response_log() {
+        let pendings = Pendings::default();
+        let methods = Arc::new(Mutex::new(RecentMethods::new(2)));
+
+        // the caller stops waiting for the response, as if the call timed out.
+        let request = method_call(1, "Filecoin.Version");
+        let call = async {
+            let rx = register_pending(&pendings, &methods, 1, &request);
+            let _guard = PendingGuard {
+                id: 1,
+                pendings: pendings.clone(),
+            };
+            rx.await
+        };
+        assert!(call.now_or_never().is_none());
+        assert!(pendings.lock().is_empty());
+
+        // the late response.
+        let response = r#"{"jsonrpc":"2.0","result":"1.0.0","id":1}"#;
+        handle_pending_response(pendings.clone(), methods.clone(), response);
+        assert_eq!(
+            orphan_response_log(&methods, 1),
+            "Orphan response for id 1 (method Filecoin.Version)"
+        );
+
+        // only the recently sent ids are remembered.
+        for id in 2..4 {
+            drop(register_pending(
+                &pendings,
+                &methods,
+                id,
+                &method_call(id, "Filecoin.ChainHead"),
+            ));
+        }
+        assert_eq!(
+            orphan_response_log(&methods, 1),
+            "Orphan response for id 1 (method unknown)"
+        );
+        assert_eq!(
+            orphan_response_log(&methods, 3),
+            "Orphan response for id 3 (method Filecoin.ChainHead)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconnected_event() {
+        let id = Arc::new(AtomicUsize::new(10));
+        let pendings = Pendings::default();
+        let methods = Arc::new(Mutex::new(RecentMethods::new(2)));
+        let subscriptions = Subscriptions::default();
+        let renewals = Renewals::default();
+        // the messages sent to the mock WebSocket.
+        let (tx, mut rx) = mpsc::unbounded();
+
+        let (stream_tx, stream) = Subscription::channel(16, OverflowPolicy::DropOldest);
+        subscriptions
+            .lock()
+            .insert(SubscriptionId::Number(1), stream_tx);
+        {
+            let mut renewals = renewals.lock();
+            let params = Params::Array(vec![]);
+            renewals.calls.insert(
+                SubscriptionId::Number(1),
+                ("Filecoin.ChainNotify".into(), params),
+            );
+            renewals
+                .current
+                .insert(SubscriptionId::Number(1), SubscriptionId::Number(1));
+        }
+        let notification = |id: u64, height: u64| {
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[{},{}]}}"#,
+                id, height
+            )
+        };
+        handle_subscription(
+            subscriptions.clone(),
+            renewals.clone(),
+            &tx,
+            &notification(1, 100),
+        );
+
+        // the WebSocket reconnects and the subscription is renewed with a new id.
+        let renew = task::spawn(renew_subscriptions(
+            id,
+            pendings.clone(),
+            methods.clone(),
+            subscriptions.clone(),
+            renewals.clone(),
+            tx.clone(),
+        ));
+        let request = match rx.next().await {
+            Some(Message::Text(request)) => request,
+            msg => panic!("unexpected message: {:?}", msg),
+        };
+        assert!(request.contains(r#""method":"Filecoin.ChainNotify""#));
+        assert!(request.contains(r#""id":10"#));
+        let response = r#"{"jsonrpc":"2.0","result":7,"id":10}"#;
+        handle_pending_response(pendings.clone(), methods, response);
+        renew.await.unwrap();
+        handle_subscription(
+            subscriptions.clone(),
+            renewals.clone(),
+            &tx,
+            &notification(7, 101),
+        );
+
+        let events = stream.take(3).collect::<Vec<_>>().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Item(Value::from(100)),
+                StreamEvent::Reconnected,
+                StreamEvent::Item(Value::from(101)),
+            ]
+        );
+        assert_eq!(
+            renewals.lock().current.get(&SubscriptionId::Number(1)),
+            Some(&SubscriptionId::Number(7))
+        );
+        assert!(renewals
+            .lock()
+            .calls
+            .contains_key(&SubscriptionId::Number(7)));
+        assert!(subscriptions
+            .lock()
+            .contains_key(&SubscriptionId::Number(7)));
+    }
+
+    #[tokio::test]
+    async fn test_string_subscription_id() {
+        let subscriptions = Subscriptions::default();
+        let renewals = Renewals::default();
+        let (string_tx, string_stream) = Subscription::channel(16, OverflowPolicy::DropOldest);
+        let (number_tx, number_stream) = Subscription::channel(16, OverflowPolicy::DropOldest);
+        subscriptions.lock().insert("0x1a".into(), string_tx);
+        subscriptions
+            .lock()
+            .insert(SubscriptionId::Number(26), number_tx);
+
+        let frames = vec![
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":["0x1a",100]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[26,200]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":["26",300]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.close","params":["0x1a"]}"#,
+            r#"{"jsonrpc":"2.0","method":"xrpc.ch.close","params":[26]}"#,
+        ];
+        let (tx, _rx) = mpsc::unbounded();
+        for frame in frames {
+            handle_subscription(subscriptions.clone(), renewals.clone(), &tx, frame);
+        }
+
+        // the string id doesn't match the number of the same digits.
+        assert_eq!(
+            string_stream.collect::<Vec<_>>().await,
+            vec![
+                StreamEvent::Item(Value::from(100)),
+                StreamEvent::Closed(CloseReason::ServerCancelled),
+            ]
+        );
+        assert_eq!(
+            number_stream.collect::<Vec<_>>().await,
+            vec![
+                StreamEvent::Item(Value::from(200)),
+                StreamEvent::Closed(CloseReason::ServerCancelled),
+            ]
+        );
+        assert!(subscriptions.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_server_cancelled_event() {
+        let subscriptions = Subscriptions::default();
+        let renewals = Renewals::default();
+        let (stream_tx, stream) = Subscription::channel(16, OverflowPolicy::DropOldest);
+        subscriptions
+            .lock()
+            .insert(SubscriptionId::Number(3), stream_tx);
+        {
+            let mut renewals = renewals.lock();
+            let params = Params::Array(vec![]);
+            renewals.calls.insert(
+                SubscriptionId::Number(3),
+                ("Filecoin.ChainNotify".into(), params),
+            );
+            renewals
+                .current
+                .insert(SubscriptionId::Number(1), SubscriptionId::Number(3));
+        }
+
+        let (tx, _rx) = mpsc::unbounded();
+        let notification = r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[3,100]}"#;
+        handle_subscription(subscriptions.clone(), renewals.clone(), &tx, notification);
+        let cancellation = r#"{"jsonrpc":"2.0","method":"xrpc.ch.close","params":[3]}"#;
+        handle_subscription(subscriptions.clone(), renewals.clone(), &tx, cancellation);
+
+        // the stream ends after the terminal event.
+        let events = stream.collect::<Vec<_>>().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Item(Value::from(100)),
+                StreamEvent::Closed(CloseReason::ServerCancelled),
+            ]
+        );
+        assert!(subscriptions.lock().is_empty());
+        assert!(renewals.lock().calls.is_empty());
+        assert!(renewals.lock().current.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_subscription_unsubscribes() {
+        let subscriptions = Subscriptions::default();
+        let renewals = Renewals::default();
+        renewals.lock().unsubscribe_method = "Filecoin.ChainNotifyCancel".into();
+        // the messages sent to the mock WebSocket.
+        let (tx, mut rx) = mpsc::unbounded();
+        let (subscription, stream) = Subscription::channel(16, OverflowPolicy::DropOldest);
+        subscriptions
+            .lock()
+            .insert(SubscriptionId::Number(4), subscription);
+        drop(stream);
+
+        let notification = r#"{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[4,100]}"#;
+        handle_subscription(subscriptions.clone(), renewals.clone(), &tx, notification);
+        assert!(subscriptions.lock().is_empty());
+        assert!(renewals
+            .lock()
+            .unsubscribed
+            .contains(&SubscriptionId::Number(4)));
+        let unsubscribe = match rx.next().await {
+            Some(Message::Text(unsubscribe)) => unsubscribe,
+            msg => panic!("unexpected message: {:?}", msg),
+        };
+        assert_eq!(
+            serde_json::from_str::<Notification>(&unsubscribe).unwrap(),
+            Notification {
+                jsonrpc: Some(Version::V2),
+                method: "Filecoin.ChainNotifyCancel".into(),
+                params: Params::Array(vec![Value::from(4)]),
+            }
+        );
+
+        // the late notifications are dropped quietly, nothing else is sent.
+        handle_subscription(subscriptions.clone(), renewals.clone(), &tx, notification);
+        assert!(rx.try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_overflow() {
+        // the consumer never reads while the notifications arrive.
+        for (policy, expected) in vec![
+            (OverflowPolicy::DropOldest, vec![3, 4]),
+            (OverflowPolicy::DropNewest, vec![0, 1]),
+        ] {
+            let (subscription, stream) = Subscription::channel(2, policy);
+            let deliveries = (0..5)
+                .map(|i| subscription.send(StreamEvent::Item(Value::from(i))))
+                .collect::<Vec<_>>();
+            assert_eq!(deliveries[..2], [Delivery::Queued, Delivery::Queued]);
+            assert!(deliveries[2..].iter().all(|d| *d == Delivery::Dropped));
+            assert_eq!(subscription.lagged(), 3);
+            assert_eq!(subscription.queue.lock().events.len(), 2);
+            drop(subscription);
+            let events = stream.collect::<Vec<_>>().await;
+            let expected = expected
+                .into_iter()
+                .map(|i| StreamEvent::Item(Value::from(i)))
+                .collect::<Vec<_>>();
+            assert_eq!(events, expected);
+        }
+
+        // the lagging subscription is disconnected.
+        let subscriptions = Subscriptions::default();
+        let renewals = Renewals::default();
+        let (subscription, stream) = Subscription::channel(2, OverflowPolicy::Disconnect);
+        subscriptions
+            .lock()
+            .insert(SubscriptionId::Number(5), subscription);
+        let (tx, _rx) = mpsc::unbounded();
+        for i in 0..4 {
+            let notification = format!(
+                r#"{{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[5,{}]}}"#,
+                i
+            );
+            handle_subscription(subscriptions.clone(), renewals.clone(), &tx, &notification);
+        }
+        assert!(subscriptions.lock().is_empty());
+        let events = stream.collect::<Vec<_>>().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Item(Value::from(0)),
+                StreamEvent::Item(Value::from(1)),
+                StreamEvent::Closed(CloseReason::Lagged),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_attempts: Some(3),
+        };
+        let delays = (0..6).map(|i| policy.delay(i)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000]
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(policy.delay(usize::MAX), Duration::from_secs(1));
+        assert!(!policy.gives_up(2));
+        assert!(policy.gives_up(3));
+        assert!(!ReconnectPolicy::default().gives_up(usize::MAX));
+    }
+
+    // A server answering `Filecoin.ChainNotify` with the subscription id `n` on its n-th
+    // connection, `Filecoin.Hang` pushes a notification of the first subscription and
+    // then kills the connection without responding, `Filecoin.Ping` pushes a notification
+    // of the second subscription.
+    async fn mock_server(mut listener: tokio::net::TcpListener) {
+        use futures::SinkExt;
+
+        let text = |msg: String| Message::Text(msg);
+        let notification = |id: u64, height: u64| {
+            text(format!(
+                r#"{{"jsonrpc":"2.0","method":"xrpc.ch.val","params":[{},{}]}}"#,
+                id, height
+            ))
+        };
+        for conn in 1.. {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            while let Some(Ok(Message::Text(msg))) = ws.next().await {
+                let call = serde_json::from_str::<MethodCall>(&msg).unwrap();
+                let response = |result: Value| {
+                    text(format!(
+                        r#"{{"jsonrpc":"2.0","result":{},"id":{}}}"#,
+                        result, call.id
+                    ))
+                };
+                match call.method.as_str() {
+                    "Filecoin.ChainNotify" => ws.send(response(Value::from(conn))).await.unwrap(),
+                    "Filecoin.Hang" => {
+                        ws.send(notification(1, 100)).await.unwrap();
+                        break;
+                    }
+                    "Filecoin.Ping" => {
+                        ws.send(notification(2, 101)).await.unwrap();
+                        ws.send(response(Value::from("pong"))).await.unwrap();
+                    }
+                    method => panic!("unexpected method: {}", method),
+                }
+            }
+            // drop the connection without the closing handshake.
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resumes_subscription() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(mock_server(listener));
+
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let ws =
+            WebSocketTransport::new_with_reconnect_policy(format!("ws://{}/rpc/v0", addr), policy);
+        let method = "Filecoin.ChainNotify";
+        let id: SubscriptionId = ws.send(method, Params::Array(vec![])).await.unwrap();
+        assert_eq!(id, SubscriptionId::Number(1));
+        ws.renew_on_reconnect(id.clone(), method, Params::Array(vec![]));
+        let mut stream = ws.subscribe_events::<u64>(id);
+
+        // the server dies before responding, the request fails instead of hanging.
+        let result = ws
+            .send::<Value>("Filecoin.Hang", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::ConnectionReset)));
+        assert!(ws.pendings.lock().is_empty());
+        assert_eq!(stream.next().await, Some(StreamEvent::Item(100)));
+
+        // the client reconnects and renews the subscription with the new id.
+        assert_eq!(stream.next().await, Some(StreamEvent::Reconnected));
+        assert_eq!(
+            ws.renewals.lock().current.get(&SubscriptionId::Number(1)),
+            Some(&SubscriptionId::Number(2))
+        );
+        let pong: String = ws
+            .send("Filecoin.Ping", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(pong, "pong");
+        assert_eq!(stream.next().await, Some(StreamEvent::Item(101)));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        // the server reads the requests but never replies.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            while let Some(Ok(_)) = ws.next().await {}
+        });
+
+        let timeout = Duration::from_millis(200);
+        let ws = WebSocketTransport::builder(format!("ws://{}/rpc/v0", addr))
+            .request_timeout(timeout)
+            .build();
+        let start = Instant::now();
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Timeout(t)) if t == timeout));
+        assert!(start.elapsed() >= timeout && start.elapsed() < Duration::from_secs(5));
+        assert!(ws.pendings.lock().is_empty());
+
+        // the timeout is overridden per call, batches included.
+        let timeout = Duration::from_millis(50);
+        let result = ws
+            .with_timeout(timeout)
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Timeout(t)) if t == timeout));
+        let result = ws
+            .with_timeout(timeout)
+            .send_batch(vec![
+                ("Filecoin.Version", Params::Array(vec![])),
+                ("Filecoin.ChainHead", Params::Array(vec![])),
+            ])
+            .await;
+        assert!(matches!(result, Err(RpcError::Timeout(t)) if t == timeout));
+        assert!(ws.pendings.lock().is_empty());
+
+        // the response arriving after the timeout is an orphan.
+        let response = r#"{"jsonrpc":"2.0","result":"1.0.0","id":1}"#;
+        handle_pending_response(ws.pendings.clone(), ws.methods.clone(), response);
+    }
+
+    #[tokio::test]
+    async fn test_batch_correlation() {
+        use futures::SinkExt;
+
+        // the server replies every batch with the outputs in the reverse order,
+        // the result of a call is its method name.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = async_tungstenite::tokio::accept_async(socket)
+                .await
+                .unwrap();
+            while let Some(Ok(Message::Text(msg))) = ws.next().await {
+                let calls = match serde_json::from_str::<Request>(&msg).unwrap() {
+                    Request::Batch(calls) => calls,
+                    Request::Single(call) => vec![call],
+                };
+                let outputs = calls
+                    .into_iter()
+                    .rev()
+                    .map(|call| match call {
+                        Call::MethodCall(call) => ResponseOutput::from(
+                            Some(Version::V2),
+                            call.id,
+                            Ok(Value::from(call.method)),
+                        ),
+                        call => panic!("unexpected call: {:?}", call),
+                    })
+                    .collect::<Vec<_>>();
+                let response = serde_json::to_string(&Response::Batch(outputs)).unwrap();
+                ws.send(Message::Text(response)).await.unwrap();
+            }
+        });
+
+        let ws = WebSocketTransport::new(format!("ws://{}/rpc/v0", addr));
+        let methods = vec!["Filecoin.Version", "Filecoin.ChainHead", "Filecoin.ID"];
+        let results = ws
+            .send_batch(
+                methods
+                    .iter()
+                    .map(|method| (*method, Params::Array(vec![]))),
+            )
+            .await
+            .unwrap();
+        let results = results
+            .into_iter()
+            .map(|result| result.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            methods
+                .iter()
+                .map(|method| Value::from(*method))
+                .collect::<Vec<_>>()
+        );
+        assert!(ws.pendings.lock().is_empty());
+
+        // the single call is resolved by the output of its id as well.
+        let result: String = ws
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(result, "Filecoin.Version");
+    }
+
+    #[tokio::test]
+    async fn test_tls_self_signed() {
+        use futures::SinkExt;
+
+        const CA: &[u8] = include_bytes!("../../testdata/ca.pem");
+        const IDENTITY: &[u8] = include_bytes!("../../testdata/server.p12");
+
+        // the server behind a certificate for `localhost` signed by the test CA,
+        // answering every call with "1.0.0".
+        let identity = native_tls::Identity::from_pkcs12(IDENTITY, "plum").unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let acceptor = Arc::new(tokio_native_tls::TlsAcceptor::from(acceptor));
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                task::spawn(async move {
+                    // the client distrusting the certificate aborts the handshake.
+                    let socket = match acceptor.accept(socket).await {
+                        Ok(socket) => socket,
+                        Err(_) => return,
+                    };
+                    let mut ws = async_tungstenite::tokio::accept_async(socket)
+                        .await
+                        .unwrap();
+                    while let Some(Ok(Message::Text(msg))) = ws.next().await {
+                        let call = serde_json::from_str::<MethodCall>(&msg).unwrap();
+                        let response =
+                            format!(r#"{{"jsonrpc":"2.0","result":"1.0.0","id":{}}}"#, call.id);
+                        ws.send(Message::Text(response)).await.unwrap();
+                    }
+                });
+            }
+        });
+        let url = format!("wss://localhost:{}/rpc/v0", port);
+
+        let tls = TlsConfig::new().root_certificate(CA).unwrap();
+        let ws = WebSocketTransport::new_with_tls(url.clone(), tls);
+        let version: String = ws
+            .send("Filecoin.Version", Params::Array(vec![]))
+            .await
+            .unwrap();
+        assert_eq!(version, "1.0.0");
+
+        // without the CA, the invalid server certificate is a connection error.
+        let policy = ReconnectPolicy {
+            max_attempts: Some(1),
+            ..Default::default()
+        };
+        let ws = WebSocketTransport::builder(url)
+            .reconnect_policy(policy)
+            .build();
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::Disconnected(_))));
+
+        let result = TlsConfig::new().root_certificate(b"not a certificate");
+        assert!(matches!(result, Err(RpcError::InvalidTlsConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_dead_link() {
+        // the server accepts the connections but never reads them, so the pings are
+        // never answered, as if the link were silently dead.
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let mut connections = Vec::new();
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let ws = async_tungstenite::tokio::accept_async(socket)
+                    .await
+                    .unwrap();
+                connections.push(ws);
+            }
+        });
+
+        let (interval, timeout) = (Duration::from_millis(100), Duration::from_millis(100));
+        let ws = WebSocketTransport::builder(format!("ws://{}/rpc/v0", addr))
+            .heartbeat(interval, timeout)
+            .reconnect_policy(ReconnectPolicy {
+                initial_delay: Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build();
+
+        // the request is failed once the dead link is detected, long before it times out.
+        let start = Instant::now();
+        let result = ws
+            .send::<Value>("Filecoin.Version", Params::Array(vec![]))
+            .await;
+        assert!(matches!(result, Err(RpcError::ConnectionReset)));
+        assert!(start.elapsed() >= interval + timeout);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
     #[tokio::test]
     async fn test_version() {
         let ws = WebSocketTransport::new("ws://127.0.0.1:1234/rpc/v0");
@@ -280,14 +2889,14 @@ mod tests {
 
     #[tokio::test]
     async fn test_sync_incoming_blocks() {
-        env_logger::init();
+        init_logger();
         let ws = WebSocketTransport::new("ws://127.0.0.1:1234/rpc/v0");
-        let id: usize = ws
+        let id: SubscriptionId = ws
             .send("Filecoin.SyncIncomingBlocks", Params::Array(vec![]))
             .await
             .unwrap();
         println!("Subscription Id: {}", id);
-        let mut stream = ws.subscribe::<Value>(id);
+        let mut stream = ws.subscribe::<Value>(id.clone());
         while let Some(value) = stream.next().await {
             println!("Block: {:?}", value);
         }
@@ -1,2 +1,40 @@
-/// Subscription Id
-pub type SubscriptionId = usize;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Subscription Id, which can be a number or a string.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SubscriptionId {
+    /// Numeric id
+    Number(u64),
+    /// String id
+    String(String),
+}
+
+impl From<u64> for SubscriptionId {
+    fn from(id: u64) -> Self {
+        SubscriptionId::Number(id)
+    }
+}
+
+impl From<String> for SubscriptionId {
+    fn from(id: String) -> Self {
+        SubscriptionId::String(id)
+    }
+}
+
+impl<'a> From<&'a str> for SubscriptionId {
+    fn from(id: &'a str) -> Self {
+        SubscriptionId::String(id.into())
+    }
+}
+
+impl fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionId::Number(id) => write!(f, "{}", id),
+            SubscriptionId::String(id) => write!(f, "{}", id),
+        }
+    }
+}
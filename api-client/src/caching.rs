@@ -0,0 +1,298 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use jsonrpc_client::{NotificationStream, SubscriptionId, Value};
+use log::debug;
+use plum_types::ChainEpoch;
+
+use crate::client::RpcClient;
+use crate::errors::Result;
+
+/// The default number of epochs after which a tipset is considered final.
+pub const DEFAULT_FINALITY: ChainEpoch = 900;
+/// The default time the reads of the non-final tipsets are cached for.
+pub const DEFAULT_RECENT_TTL: Duration = Duration::from_secs(5);
+
+/// The source of the current epoch, which is usually the height of the chain head.
+pub type EpochSource = Arc<dyn Fn() -> ChainEpoch + Send + Sync>;
+
+// How the epoch of a cached read is known.
+#[derive(Clone, Copy)]
+enum EpochOf {
+    // the `Height` of the response, e.g. a tipset or a block header.
+    Response,
+    // the height of the tipset given by the tipset key param at the index.
+    TipsetKey(usize),
+}
+
+// The read-only methods whose results are cached.
+const CACHED_METHODS: &[(&str, EpochOf)] = &[
+    ("ChainGetBlock", EpochOf::Response),
+    ("ChainGetTipSet", EpochOf::Response),
+    ("ChainGetTipSetByHeight", EpochOf::Response),
+    ("StateGetActor", EpochOf::TipsetKey(1)),
+    ("StateReadState", EpochOf::TipsetKey(1)),
+    ("StateMinerPower", EpochOf::TipsetKey(1)),
+    ("StateMinerInfo", EpochOf::TipsetKey(1)),
+    ("StateMinerFaults", EpochOf::TipsetKey(1)),
+    ("StateGetReceipt", EpochOf::TipsetKey(1)),
+    ("StateListMiners", EpochOf::TipsetKey(0)),
+    ("StateListActors", EpochOf::TipsetKey(0)),
+    ("StateMarketBalance", EpochOf::TipsetKey(1)),
+    ("StateMarketStorageDeal", EpochOf::TipsetKey(1)),
+    ("StateLookupID", EpochOf::TipsetKey(1)),
+    ("StateAccountKey", EpochOf::TipsetKey(1)),
+];
+
+struct Cached {
+    value: Value,
+    // `None` means the value never expires.
+    expires: Option<Instant>,
+}
+
+/// CachingApi caches the results of the read-only chain and state methods according to
+/// the finality of the tipset they are read at.
+///
+/// The reads of the tipsets at least `finality` epochs below the current epoch are immutable
+/// and cached forever, the other reads, including those whose epoch is unknown, expire after
+/// `recent_ttl`. The other methods are always forwarded to the inner client.
+#[derive(Clone)]
+pub struct CachingApi<C> {
+    client: C,
+    current_epoch: EpochSource,
+    finality: ChainEpoch,
+    recent_ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+impl<C: RpcClient> CachingApi<C> {
+    /// Create a new CachingApi with the default finality and TTL of the recent reads.
+    pub fn new(client: C, current_epoch: EpochSource) -> Self {
+        Self::with_config(client, current_epoch, DEFAULT_FINALITY, DEFAULT_RECENT_TTL)
+    }
+
+    /// Create a new CachingApi with the given `finality` depth and TTL of the recent reads.
+    pub fn with_config(
+        client: C,
+        current_epoch: EpochSource,
+        finality: ChainEpoch,
+        recent_ttl: Duration,
+    ) -> Self {
+        Self {
+            client,
+            current_epoch,
+            finality,
+            recent_ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Return the inner client.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    /// Return the number of the cached results, including the expired ones not evicted yet.
+    pub fn cached_len(&self) -> usize {
+        self.cache.lock().expect("lock shouldn't be poisoned").len()
+    }
+
+    /// Remove all the cached results.
+    pub fn clear(&self) {
+        self.cache
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .clear();
+    }
+
+    fn lookup(&self, key: &str) -> Option<Value> {
+        let mut cache = self.cache.lock().expect("lock shouldn't be poisoned");
+        match cache.get(key) {
+            Some(cached) if cached.expires.map_or(true, |at| Instant::now() < at) => {
+                Some(cached.value.clone())
+            }
+            Some(_) => {
+                cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, key: String, value: Value, epoch: Option<ChainEpoch>) {
+        let finalized = epoch.map_or(false, |epoch| {
+            epoch <= (self.current_epoch)() - self.finality
+        });
+        let expires = if finalized {
+            None
+        } else {
+            Some(Instant::now() + self.recent_ttl)
+        };
+        debug!("Cache {} at epoch {:?}, expires: {:?}", key, epoch, expires);
+        self.cache
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .insert(key, Cached { value, expires });
+    }
+
+    // Return the tipset of the `key`, which is cached as well.
+    async fn tipset(&self, key: Value) -> Result<Value> {
+        let cache_key = cache_key("ChainGetTipSet", &[key.clone()]);
+        if let Some(tipset) = self.lookup(&cache_key) {
+            return Ok(tipset);
+        }
+        let tipset: Value = self.client.request("ChainGetTipSet", vec![key]).await?;
+        self.store(cache_key, tipset.clone(), height(&tipset));
+        Ok(tipset)
+    }
+
+    async fn fetch(&self, method: &str, params: Vec<Value>, epoch_of: EpochOf) -> Result<Value> {
+        let cache_key = cache_key(method, &params);
+        if let Some(value) = self.lookup(&cache_key) {
+            return Ok(value);
+        }
+        let value: Value = self.client.request(method, params.clone()).await?;
+        let epoch = match epoch_of {
+            EpochOf::Response => height(&value),
+            EpochOf::TipsetKey(index) => match params.get(index) {
+                // the empty tipset key means the chain head.
+                Some(Value::Array(cids)) if !cids.is_empty() => {
+                    height(&self.tipset(params[index].clone()).await?)
+                }
+                _ => None,
+            },
+        };
+        self.store(cache_key, value.clone(), epoch);
+        Ok(value)
+    }
+}
+
+fn cache_key(method: &str, params: &[Value]) -> String {
+    format!("{}{}", method, Value::Array(params.to_vec()))
+}
+
+fn height(value: &Value) -> Option<ChainEpoch> {
+    value.get("Height").and_then(Value::as_i64)
+}
+
+#[async_trait::async_trait]
+impl<C: RpcClient> RpcClient for CachingApi<C> {
+    async fn request<M, T>(&self, method: M, params: Vec<Value>) -> Result<T>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        let method = method.as_ref();
+        match CACHED_METHODS.iter().find(|(name, _)| *name == method) {
+            Some((_, epoch_of)) => {
+                let value = self.fetch(method, params, *epoch_of).await?;
+                Ok(serde_json::from_value(value)?)
+            }
+            None => self.client.request(method, params).await,
+        }
+    }
+
+    async fn subscribe<M, T>(
+        &self,
+        subscribe_method: M,
+        params: Vec<Value>,
+    ) -> Result<(SubscriptionId, NotificationStream<T>)>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        self.client.subscribe(subscribe_method, params).await
+    }
+
+    fn unsubscribe(&self, subscription_id: SubscriptionId) {
+        self.client.unsubscribe(subscription_id)
+    }
+}
+
+mod impls {
+    use super::CachingApi;
+    use crate::client::RpcClient;
+    use crate::interface::*;
+
+    impl<C: RpcClient> CommonApi for CachingApi<C> {}
+    impl<C: RpcClient> FullNodeApi for CachingApi<C> {}
+    impl<C: RpcClient> StorageMinerApi for CachingApi<C> {}
+
+    impl<C: RpcClient> ChainApi for CachingApi<C> {}
+    impl<C: RpcClient> ClientApi for CachingApi<C> {}
+    impl<C: RpcClient> MarketApi for CachingApi<C> {}
+    impl<C: RpcClient> MinerApi for CachingApi<C> {}
+    impl<C: RpcClient> MpoolApi for CachingApi<C> {}
+    impl<C: RpcClient> MultiSigApi for CachingApi<C> {}
+    impl<C: RpcClient> PaychApi for CachingApi<C> {}
+    impl<C: RpcClient> StateApi for CachingApi<C> {}
+    impl<C: RpcClient> SyncApi for CachingApi<C> {}
+    impl<C: RpcClient> WalletApi for CachingApi<C> {}
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::MockClient;
+
+    // A node answering the tipset reads.
+    fn mock_client() -> MockClient {
+        MockClient::new(|method, params| {
+            Ok(match method {
+                "ChainGetTipSetByHeight" => json!({ "Height": params[0] }),
+                // the tipset keys of the tests are `[height]`.
+                "ChainGetTipSet" => json!({ "Height": params[0][0] }),
+                "StateNetworkName" => json!("testnet"),
+                method => panic!("unexpected method {}", method),
+            })
+        })
+    }
+
+    async fn tipset_by_height(api: &CachingApi<MockClient>, height: ChainEpoch) -> Value {
+        api.request("ChainGetTipSetByHeight", vec![json!(height), json!([])])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_finality_aware_caching() {
+        let client = mock_client();
+        let current_epoch: EpochSource = Arc::new(|| 1000);
+        let ttl = Duration::from_millis(50);
+        let api = CachingApi::with_config(client.clone(), current_epoch, 900, ttl);
+
+        // the finalized read is served from the cache, even after the TTL.
+        assert_eq!(tipset_by_height(&api, 100).await, json!({ "Height": 100 }));
+        assert_eq!(tipset_by_height(&api, 100).await, json!({ "Height": 100 }));
+        assert_eq!(client.count("ChainGetTipSetByHeight"), 1);
+
+        // the near-head read is cached for the TTL only.
+        assert_eq!(tipset_by_height(&api, 990).await, json!({ "Height": 990 }));
+        assert_eq!(tipset_by_height(&api, 990).await, json!({ "Height": 990 }));
+        assert_eq!(client.count("ChainGetTipSetByHeight"), 2);
+        tokio::time::delay_for(ttl * 2).await;
+        assert_eq!(tipset_by_height(&api, 990).await, json!({ "Height": 990 }));
+        assert_eq!(tipset_by_height(&api, 100).await, json!({ "Height": 100 }));
+        assert_eq!(client.count("ChainGetTipSetByHeight"), 3);
+
+        // the epoch of the tipset key is resolved, the tipset itself is cached as well.
+        let key = json!([50]);
+        assert_eq!(
+            api.tipset(key.clone()).await.unwrap(),
+            json!({ "Height": 50 })
+        );
+        assert_eq!(api.tipset(key).await.unwrap(), json!({ "Height": 50 }));
+        assert_eq!(client.count("ChainGetTipSet"), 1);
+
+        // the methods not cached are always forwarded.
+        let name: String = api.request("StateNetworkName", vec![]).await.unwrap();
+        assert_eq!(name, "testnet");
+        let _: String = api.request("StateNetworkName", vec![]).await.unwrap();
+        assert_eq!(client.count("StateNetworkName"), 2);
+    }
+}
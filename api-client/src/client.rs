@@ -3,7 +3,7 @@
 pub use jsonrpc_client::{HttpTransport, NotificationStream, SubscriptionId, WebSocketTransport};
 use jsonrpc_client::{Params, PubsubTransport, Transport, Value};
 
-use crate::errors::Result;
+use crate::errors::{ApiError, Result};
 
 /// A abstract interface of Rpc Client.
 #[async_trait::async_trait]
@@ -29,6 +29,58 @@ pub trait RpcClient: Send + Sync + 'static {
     fn unsubscribe(&self, subscription_id: SubscriptionId);
 }
 
+/// FilecoinApi exposes the typed Filecoin API over any JSON-RPC transport,
+/// e.g. a transport wrapped by `RateLimitedTransport`.
+///
+/// The subscriptions are not supported, use `WebSocketTransport` for them.
+#[derive(Clone, Debug)]
+pub struct FilecoinApi<T> {
+    transport: T,
+}
+
+impl<T> FilecoinApi<T> {
+    /// Create a new FilecoinApi over the `transport`.
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Return the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: Transport + Send + Sync + 'static> RpcClient for FilecoinApi<C> {
+    async fn request<M, T>(&self, method: M, params: Vec<Value>) -> Result<T>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        Ok(self
+            .transport
+            .send(
+                format!("Filecoin.{}", method.as_ref()),
+                Params::Array(params),
+            )
+            .await?)
+    }
+
+    async fn subscribe<M, T>(
+        &self,
+        _subscribe_method: M,
+        _params: Vec<Value>,
+    ) -> Result<(SubscriptionId, NotificationStream<T>)>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        Err(ApiError::PubsubUnsupported)
+    }
+
+    fn unsubscribe(&self, _subscription_id: SubscriptionId) {}
+}
+
 #[async_trait::async_trait]
 impl RpcClient for HttpTransport {
     async fn request<M, T>(&self, method: M, params: Vec<Value>) -> Result<T>
@@ -53,11 +105,11 @@ impl RpcClient for HttpTransport {
         M: AsRef<str> + Send,
         T: serde::de::DeserializeOwned,
     {
-        unimplemented!("HTTP doesn't support `pub-sub` mode")
+        Err(ApiError::PubsubUnsupported)
     }
 
     fn unsubscribe(&self, _subscription_id: SubscriptionId) {
-        unimplemented!("HTTP doesn't support `pub-sub` mode")
+        // no subscription can be made over HTTP, so there is nothing to remove.
     }
 }
 
@@ -85,14 +137,12 @@ impl RpcClient for WebSocketTransport {
         M: AsRef<str> + Send,
         T: serde::de::DeserializeOwned,
     {
-        let subscription_id: SubscriptionId = self
-            .send(
-                format!("Filecoin.{}", subscribe_method.as_ref()),
-                Params::Array(params),
-            )
-            .await?;
+        let method = format!("Filecoin.{}", subscribe_method.as_ref());
+        let params = Params::Array(params);
+        let subscription_id: SubscriptionId = self.send(method.clone(), params.clone()).await?;
+        self.renew_on_reconnect(subscription_id.clone(), method, params);
         Ok((
-            subscription_id,
+            subscription_id.clone(),
             PubsubTransport::subscribe(self, subscription_id),
         ))
     }
@@ -103,10 +153,29 @@ impl RpcClient for WebSocketTransport {
 }
 
 mod impls {
-    use super::{HttpTransport, WebSocketTransport};
+    use jsonrpc_client::Transport;
+
+    use super::{FilecoinApi, HttpTransport, WebSocketTransport};
     use crate::interface::*;
     use crate::MultiSigApi;
 
+    // Any transport
+    // async version
+    impl<T: Transport + Send + Sync + 'static> CommonApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> FullNodeApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> StorageMinerApi for FilecoinApi<T> {}
+
+    impl<T: Transport + Send + Sync + 'static> ChainApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> ClientApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> MarketApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> MinerApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> MpoolApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> MultiSigApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> PaychApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> StateApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> SyncApi for FilecoinApi<T> {}
+    impl<T: Transport + Send + Sync + 'static> WalletApi for FilecoinApi<T> {}
+
     // HTTP
     // async version
     impl CommonApi for HttpTransport {}
@@ -180,3 +249,84 @@ async fn test_subscription() {
         println!("chain_notify: {:?}", head_changes);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use jsonrpc_client::{Call, MethodCall, Request, RequestId, Response, ResponseOutput};
+    use serde_json::json;
+
+    use super::*;
+    use crate::interface::{BuildVersion, CommonApi, Permission};
+
+    // A node answering the calls with the canned results.
+    #[derive(Default)]
+    struct MockTransport {
+        id: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for MockTransport {
+        fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+            let id = self.id.fetch_add(1, Ordering::AcqRel);
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: Some(jsonrpc_client::Version::V2),
+                id,
+                method: method.into(),
+                params,
+            });
+            (id, call)
+        }
+
+        async fn execute(&self, id: RequestId, request: &Request) -> Result<Response> {
+            let call = match request {
+                Request::Single(Call::MethodCall(call)) => call,
+                request => panic!("unexpected request {:?}", request),
+            };
+            let result = match (call.method.as_str(), &call.params) {
+                ("Filecoin.Version", _) => json!({
+                    "Version": "0.4.1+git.5d0ae6a4",
+                    "APIVersion": 1792,
+                    "BlockDelay": 30,
+                }),
+                ("Filecoin.LogList", _) => json!(["chain", "rpc"]),
+                ("Filecoin.ID", _) => json!("QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N"),
+                ("Filecoin.AuthVerify", Params::Array(params)) => {
+                    assert_eq!(params, &vec![json!("token")]);
+                    json!(["read", "write"])
+                }
+                (method, _) => panic!("unexpected method {}", method),
+            };
+            Ok(Response::Single(ResponseOutput::from(
+                Some(jsonrpc_client::Version::V2),
+                id,
+                Ok(result),
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typed_api() {
+        let api = FilecoinApi::new(MockTransport::default());
+
+        let version = api.version().await.unwrap();
+        assert_eq!(version.version, "0.4.1+git.5d0ae6a4");
+        assert_eq!(version.api_version, BuildVersion::new((0, 7, 0)));
+        assert_eq!(version.block_delay, 30);
+
+        assert_eq!(api.log_list().await.unwrap(), vec!["chain", "rpc"]);
+        assert_eq!(
+            api.id().await.unwrap().to_base58(),
+            "QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N"
+        );
+        assert_eq!(
+            api.auth_verify("token").await.unwrap(),
+            vec![Permission::Read, Permission::Write]
+        );
+
+        // the subscriptions need a pub-sub transport.
+        let result = api.subscribe::<_, Value>("ChainNotify", vec![]).await;
+        assert!(matches!(result, Err(ApiError::PubsubUnsupported)));
+    }
+}
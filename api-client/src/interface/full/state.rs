@@ -246,7 +246,8 @@ pub trait StateApi: RpcClient {
             .await
     }
 
-    async fn state_search_msg(&self, cid: &Cid) -> Result<MsgLookup> {
+    // return `None` if the message is not found on chain.
+    async fn state_search_msg(&self, cid: &Cid) -> Result<Option<MsgLookup>> {
         self.request("StateSearchMsg", vec![helper::serialize(cid)])
             .await
     }
@@ -1,10 +1,11 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use plum_address::Address;
-use plum_bigint::{BigInt, BigIntWrapper};
+use plum_bigint::BigIntWrapper;
 use plum_bytes::BytesRef;
 use plum_crypto::{Signature, SignatureType};
 use plum_message::{SignedMessage, UnsignedMessage};
+use plum_types::TokenAmount;
 use plum_wallet::KeyInfo;
 
 use crate::client::RpcClient;
@@ -29,7 +30,7 @@ pub trait WalletApi: RpcClient {
         self.request("WalletList", vec![]).await
     }
 
-    async fn wallet_balance(&self, addr: &Address) -> Result<BigInt> {
+    async fn wallet_balance(&self, addr: &Address) -> Result<TokenAmount> {
         let bigint: BigIntWrapper = self
             .request("WalletBalance", vec![helper::serialize(addr)])
             .await?;
@@ -95,3 +96,68 @@ pub trait WalletApi: RpcClient {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_client::Value;
+
+    use super::*;
+    use crate::mock::MockClient;
+
+    // A transport replaying the responses captured from a Lotus node.
+    fn mock_client() -> MockClient {
+        MockClient::new(|method, _params| {
+            let response = match method {
+                "WalletList" => {
+                    r#"["t1wbxhu3ypkuo6eyp6hjx6davuelxaxrvwb2kuwva","t3q22fijmmlckhl56rn5nkyamkph3mcfu5ed6dheq53c244hfmnq2i7efdma3cj5voxenwiummf2ajlsbxc65a"]"#
+                }
+                "WalletBalance" => r#""49999999999999999996000""#,
+                "WalletSign" => r#"{"Type":1,"Data":"Ym9vISBpbSBhIHNpZ25hdHVyZQ=="}"#,
+                "WalletNew" => {
+                    r#""t3q22fijmmlckhl56rn5nkyamkph3mcfu5ed6dheq53c244hfmnq2i7efdma3cj5voxenwiummf2ajlsbxc65a""#
+                }
+                method => panic!("unexpected method {}", method),
+            };
+            Ok(serde_json::from_str(response).unwrap())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_wallet_api() {
+        let client = mock_client();
+        let secp: Address = "t1wbxhu3ypkuo6eyp6hjx6davuelxaxrvwb2kuwva".parse().unwrap();
+        let bls: Address = "t3q22fijmmlckhl56rn5nkyamkph3mcfu5ed6dheq53c244hfmnq2i7efdma3cj5voxenwiummf2ajlsbxc65a"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            client.wallet_list().await.unwrap(),
+            vec![secp.clone(), bls.clone()]
+        );
+
+        let balance = client.wallet_balance(&secp).await.unwrap();
+        assert_eq!(
+            balance,
+            "49999999999999999996000".parse::<TokenAmount>().unwrap()
+        );
+
+        let signature = client.wallet_sign(&secp, b"hello").await.unwrap();
+        assert_eq!(
+            signature,
+            Signature::new_secp256k1(b"boo! im a signature".to_vec())
+        );
+
+        assert_eq!(client.wallet_new(SignatureType::Bls).await.unwrap(), bls);
+
+        let requests = client.requests();
+        assert_eq!(requests[1].1, vec![Value::String(secp.to_string())]);
+        assert_eq!(
+            requests[2].1,
+            vec![
+                Value::String(secp.to_string()),
+                Value::String("aGVsbG8=".into())
+            ]
+        );
+        assert_eq!(requests[3].1, vec![Value::String("bls".into())]);
+    }
+}
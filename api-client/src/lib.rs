@@ -4,11 +4,19 @@
 
 #![deny(missing_docs)]
 
+mod caching;
 mod client;
 mod errors;
 mod helper;
 mod interface;
+#[cfg(test)]
+mod mock;
+mod remote;
+mod submitter;
 
-pub use self::client::{HttpTransport, WebSocketTransport};
+pub use self::caching::{CachingApi, EpochSource, DEFAULT_FINALITY, DEFAULT_RECENT_TTL};
+pub use self::client::{FilecoinApi, HttpTransport, WebSocketTransport};
 pub use self::errors::{ApiError, Result};
 pub use self::interface::*;
+pub use self::remote::{RemoteBatchDataStore, RemoteDataStore};
+pub use self::submitter::{FeeBumpConfig, MessageSubmitter, SubmitEvent};
@@ -0,0 +1,98 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! The mock client of the tests, answering the requests with a handler.
+
+use std::sync::{Arc, Mutex};
+
+use jsonrpc_client::{NotificationStream, SubscriptionId, Value};
+
+use crate::client::RpcClient;
+use crate::errors::Result;
+
+type Handler = dyn Fn(&str, &[Value]) -> Result<Value> + Send + Sync;
+
+/// MockClient answers each request with the result of its handler and records the requests.
+///
+/// The `pub-sub` mode isn't supported.
+#[derive(Clone)]
+pub struct MockClient {
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<(String, Vec<Value>)>>>,
+}
+
+impl MockClient {
+    /// Create a new MockClient answering the requests with `handler(method, params)`.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        Self {
+            handler: Arc::new(handler),
+            requests: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Return the methods and the params of the requests, in the order they were sent.
+    pub fn requests(&self) -> Vec<(String, Vec<Value>)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Return the number of the requests of the `method`.
+    pub fn count(&self, method: &str) -> usize {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().filter(|(name, _)| name == method).count()
+    }
+}
+
+#[async_trait::async_trait]
+impl RpcClient for MockClient {
+    async fn request<M, T>(&self, method: M, params: Vec<Value>) -> Result<T>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        let method = method.as_ref();
+        self.requests
+            .lock()
+            .unwrap()
+            .push((method.to_string(), params.clone()));
+        let value = (self.handler)(method, &params)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    async fn subscribe<M, T>(
+        &self,
+        _subscribe_method: M,
+        _params: Vec<Value>,
+    ) -> Result<(SubscriptionId, NotificationStream<T>)>
+    where
+        M: AsRef<str> + Send,
+        T: serde::de::DeserializeOwned,
+    {
+        unimplemented!("the mock client doesn't support `pub-sub` mode")
+    }
+
+    fn unsubscribe(&self, _subscription_id: SubscriptionId) {
+        unimplemented!("the mock client doesn't support `pub-sub` mode")
+    }
+}
+
+mod impls {
+    use super::MockClient;
+    use crate::interface::*;
+
+    impl CommonApi for MockClient {}
+    impl FullNodeApi for MockClient {}
+    impl StorageMinerApi for MockClient {}
+
+    impl ChainApi for MockClient {}
+    impl ClientApi for MockClient {}
+    impl MarketApi for MockClient {}
+    impl MinerApi for MockClient {}
+    impl MpoolApi for MockClient {}
+    impl MultiSigApi for MockClient {}
+    impl PaychApi for MockClient {}
+    impl StateApi for MockClient {}
+    impl SyncApi for MockClient {}
+    impl WalletApi for MockClient {}
+}
@@ -0,0 +1,417 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::executor::block_on;
+use ipfs_datastore::{BasicTxnDataStore, DataStoreError, Key, ToBatch, ToTxn};
+use ipfs_datastore::{DataStore, DataStoreBatch, DataStoreRead, DataStoreWrite};
+use jsonrpc_client::{BatchTransport, Params, RpcError, Value};
+use serde::de::DeserializeOwned;
+use tokio::runtime::{Builder, Runtime};
+
+type DsResult<T> = std::result::Result<T, DataStoreError>;
+
+// Convert the RPC error into the datastore error, the transport errors are retryable.
+fn datastore_error(err: RpcError) -> DataStoreError {
+    match err {
+        RpcError::Json(err) => DataStoreError::Corruption(err.to_string()),
+        RpcError::JsonRpc { message, .. } => DataStoreError::Custom(message),
+        RpcError::RateLimited(method) => {
+            DataStoreError::Custom(format!("rate limit exceeded for method `{}`", method))
+        }
+        err => DataStoreError::Disconnected(err.to_string()),
+    }
+}
+
+fn decode_value(key: &Key, value: &str) -> DsResult<Vec<u8>> {
+    base64::decode(value)
+        .map_err(|err| DataStoreError::Corruption(format!("the value of {}: {}", key, err)))
+}
+
+fn put_params(key: Key, value: Vec<u8>) -> Params {
+    Params::Array(vec![
+        Value::from(key.to_string()),
+        base64::encode(value).into(),
+    ])
+}
+
+fn key_params(key: &Key) -> Params {
+    Params::Array(vec![Value::from(key.to_string())])
+}
+
+/// RemoteDataStore proxies the DataStore methods to the datastore hosted by a remote node,
+/// by calling the `Plum.Ds*` methods over the JSON-RPC transport, so that multiple processes
+/// can share one backing datastore.
+///
+/// The keys are sent as strings and the values as base64 strings, an absent key is reported
+/// by the `null` result of `Plum.DsGet` and `Plum.DsGetSize`.
+///
+/// The asynchronous calls are driven by a runtime owned by the datastore (and shared by its
+/// clones), so the transports needing a reactor work without the caller entering a runtime.
+/// The calling thread is blocked until the call completes.
+pub struct RemoteDataStore<T> {
+    transport: Arc<T>,
+    runtime: Arc<Runtime>,
+}
+
+impl<T> Clone for RemoteDataStore<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> RemoteDataStore<T> {
+    /// Create a new RemoteDataStore over the transport connected to the remote node.
+    pub fn new(transport: T) -> DsResult<Self> {
+        let runtime = Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .thread_name("remote-datastore")
+            .enable_all()
+            .build()
+            .map_err(|err| DataStoreError::Custom(err.to_string()))?;
+        Ok(Self {
+            transport: Arc::new(transport),
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    // Block on the future within the context of the owned runtime, whose worker drives
+    // the I/O and the timers of the transport.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.handle().enter(|| block_on(future))
+    }
+
+    fn call<R: DeserializeOwned>(&self, method: &str, params: Params) -> DsResult<R> {
+        self.block_on(self.transport.send(format!("Plum.{}", method), params))
+            .map_err(datastore_error)
+    }
+
+    // Send the calls as a single batch, fail with the first failed call.
+    fn call_batch(&self, calls: Vec<(&'static str, Params)>) -> DsResult<()> {
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let calls = calls
+            .into_iter()
+            .map(|(method, params)| (format!("Plum.{}", method), params));
+        let results = self
+            .block_on(self.transport.send_batch(calls))
+            .map_err(datastore_error)?;
+        results
+            .into_iter()
+            .try_for_each(|result| result.map(|_| ()).map_err(datastore_error))
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> DataStore for RemoteDataStore<T> {
+    fn sync<K>(&mut self, prefix: &K) -> DsResult<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.call("DsSync", key_params(prefix.borrow()))
+    }
+
+    fn close(&mut self) -> DsResult<()> {
+        // the remote datastore is shared with the other clients.
+        Ok(())
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> DataStoreRead for RemoteDataStore<T> {
+    fn get<K>(&self, key: &K) -> DsResult<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.call::<Option<String>>("DsGet", key_params(key))? {
+            Some(value) => decode_value(key, &value),
+            None => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> DsResult<bool>
+    where
+        K: Borrow<Key>,
+    {
+        self.call("DsHas", key_params(key.borrow()))
+    }
+
+    fn size<K>(&self, key: &K) -> DsResult<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.call::<Option<usize>>("DsGetSize", key_params(key))?
+            .ok_or_else(|| DataStoreError::NotFound(key.to_string()))
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> DataStoreWrite for RemoteDataStore<T> {
+    fn put<K, V>(&mut self, key: K, value: V) -> DsResult<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.call("DsPut", put_params(key.into(), value.into()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> DsResult<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.call("DsDelete", key_params(key.borrow()))
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> ToBatch for RemoteDataStore<T> {
+    type Batch = RemoteBatchDataStore<T>;
+
+    fn batch(&self) -> DsResult<Self::Batch> {
+        Ok(RemoteBatchDataStore {
+            datastore: self.clone(),
+            calls: Vec::new(),
+        })
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> ToTxn for RemoteDataStore<T> {
+    type Txn = BasicTxnDataStore<Self>;
+
+    fn txn(&self, _read_only: bool) -> DsResult<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+/// RemoteBatchDataStore records the writes and sends them to the remote node
+/// as a single batched RPC on commit.
+pub struct RemoteBatchDataStore<T> {
+    datastore: RemoteDataStore<T>,
+    calls: Vec<(&'static str, Params)>,
+}
+
+impl<T: BatchTransport + Send + Sync> DataStoreWrite for RemoteBatchDataStore<T> {
+    fn put<K, V>(&mut self, key: K, value: V) -> DsResult<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.calls
+            .push(("DsPut", put_params(key.into(), value.into())));
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> DsResult<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.calls.push(("DsDelete", key_params(key.borrow())));
+        Ok(())
+    }
+}
+
+impl<T: BatchTransport + Send + Sync> DataStoreBatch for RemoteBatchDataStore<T> {
+    fn commit(&mut self) -> DsResult<()> {
+        let calls = std::mem::take(&mut self.calls);
+        self.datastore.call_batch(calls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    use jsonrpc_client::{Call, MethodCall, Request, RequestId, Response, ResponseOutput};
+    use jsonrpc_client::{Error, ErrorCode, HttpTransport, Transport, Version};
+
+    use super::*;
+
+    // A node hosting a map, counting the requests reaching it.
+    #[derive(Default)]
+    struct MockTransport {
+        id: AtomicUsize,
+        values: Mutex<BTreeMap<String, Vec<u8>>>,
+        requests: AtomicUsize,
+    }
+
+    impl MockTransport {
+        fn handle(&self, call: &MethodCall) -> std::result::Result<Value, Error> {
+            let params = match &call.params {
+                Params::Array(params) => params,
+                _ => return Err(Error::new(ErrorCode::InvalidParams)),
+            };
+            let key = params[0].as_str().unwrap().to_string();
+            let mut values = self.values.lock().unwrap();
+            Ok(match call.method.as_str() {
+                "Plum.DsGet" => values.get(&key).map(base64::encode).into(),
+                "Plum.DsHas" => values.contains_key(&key).into(),
+                "Plum.DsGetSize" => values.get(&key).map(|value| value.len()).into(),
+                "Plum.DsPut" => {
+                    let value = base64::decode(params[1].as_str().unwrap()).unwrap();
+                    values.insert(key, value);
+                    Value::Null
+                }
+                "Plum.DsDelete" => {
+                    values.remove(&key);
+                    Value::Null
+                }
+                "Plum.DsSync" => Value::Null,
+                _ => return Err(Error::new(ErrorCode::MethodNotFound)),
+            })
+        }
+
+        fn output(&self, call: &Call) -> ResponseOutput {
+            match call {
+                Call::MethodCall(call) => {
+                    ResponseOutput::from(Some(Version::V2), call.id, self.handle(call))
+                }
+                _ => ResponseOutput::invalid_request(Some(Version::V2), 0),
+            }
+        }
+
+        fn respond(&self, request: &Request) -> Response {
+            match request {
+                Request::Single(call) => Response::Single(self.output(call)),
+                Request::Batch(calls) => {
+                    Response::Batch(calls.iter().map(|call| self.output(call)).collect())
+                }
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for MockTransport {
+        fn prepare<M: Into<String>>(&self, method: M, params: Params) -> (RequestId, Call) {
+            let id = self.id.fetch_add(1, Ordering::SeqCst);
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: Some(Version::V2),
+                id,
+                method: method.into(),
+                params,
+            });
+            (id, call)
+        }
+
+        async fn execute(
+            &self,
+            _id: RequestId,
+            request: &Request,
+        ) -> jsonrpc_client::Result<Response> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Ok(self.respond(request))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BatchTransport for MockTransport {}
+
+    #[test]
+    fn test_remote_datastore() {
+        let mut store = RemoteDataStore::new(MockTransport::default()).unwrap();
+        let (a, b, c) = (Key::new("/a"), Key::new("/b"), Key::new("/c"));
+
+        assert!(store.get(&a).unwrap_err().is_not_found());
+        assert!(store.size(&a).unwrap_err().is_not_found());
+        assert!(!store.has(&a).unwrap());
+        store.put(a.clone(), b"hello".to_vec()).unwrap();
+        assert_eq!(store.get(&a).unwrap(), b"hello".to_vec());
+        assert_eq!(store.size(&a).unwrap(), 5);
+        assert!(store.has(&a).unwrap());
+        store.sync(&Key::new("/")).unwrap();
+
+        // the clones share the remote datastore.
+        let mut other = store.clone();
+        other.delete(&a).unwrap();
+        assert!(store.get(&a).unwrap_err().is_not_found());
+        let requests = store.transport.requests.load(Ordering::SeqCst);
+
+        // the batch is sent as a single request on commit.
+        store.put(c.clone(), vec![3]).unwrap();
+        let mut batch = store.batch().unwrap();
+        batch.put(a.clone(), vec![1]).unwrap();
+        batch.put(b.clone(), vec![2]).unwrap();
+        batch.delete(&c).unwrap();
+        assert!(!store.has(&a).unwrap());
+        let requests = requests + 2;
+        assert_eq!(store.transport.requests.load(Ordering::SeqCst), requests);
+        batch.commit().unwrap();
+        assert_eq!(
+            store.transport.requests.load(Ordering::SeqCst),
+            requests + 1
+        );
+        assert_eq!(store.get(&a).unwrap(), vec![1]);
+        assert_eq!(store.get(&b).unwrap(), vec![2]);
+        assert!(!store.has(&c).unwrap());
+
+        // the errors of the remote node are reported.
+        let err = store
+            .call::<Value>("DsUnknown", key_params(&a))
+            .unwrap_err();
+        assert!(matches!(err, DataStoreError::Custom(_)));
+    }
+
+    // Serve the HTTP requests with the mock node, one request per connection.
+    fn serve_http(node: MockTransport) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if line.starts_with("content-length:") {
+                        len = line["content-length:".len()..].trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                let request = serde_json::from_slice::<Request>(&body).unwrap();
+                let output = serde_json::to_vec(&node.respond(&request)).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    output.len()
+                )
+                .unwrap();
+                stream.write_all(&output).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_remote_datastore_over_http() {
+        // the HTTP transport needs a reactor, which the caller doesn't provide.
+        let url = serve_http(MockTransport::default());
+        let mut store = RemoteDataStore::new(HttpTransport::new(url)).unwrap();
+        let a = Key::new("/a");
+        assert!(!store.has(&a).unwrap());
+        store.put(a.clone(), b"hello".to_vec()).unwrap();
+        assert_eq!(store.get(&a).unwrap(), b"hello".to_vec());
+
+        let mut batch = store.batch().unwrap();
+        batch.put(Key::new("/b"), vec![2]).unwrap();
+        batch.delete(&a).unwrap();
+        batch.commit().unwrap();
+        assert!(!store.has(&a).unwrap());
+        assert_eq!(store.get(&Key::new("/b")).unwrap(), vec![2]);
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use cid::Cid;
+use log::{debug, warn};
+use plum_bigint::BigInt;
+use plum_message::UnsignedMessage;
+use plum_types::ChainEpoch;
+
+use crate::errors::Result;
+use crate::interface::{ChainApi, MpoolApi, StateApi, WalletApi};
+
+/// The configuration of the fee bump of the `MessageSubmitter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeBumpConfig {
+    /// The number of epochs to wait for a message to be mined before replacing it.
+    pub timeout_epochs: ChainEpoch,
+    /// The percentage the gas price is increased by on each replacement,
+    /// the message pool rejects replacements bumped less than 25%.
+    pub bump_percent: u64,
+    /// The maximum number of replacements of a message.
+    pub max_bumps: u32,
+    /// The interval of polling the chain head in `run`.
+    pub poll_interval: Duration,
+}
+
+impl Default for FeeBumpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_epochs: 10,
+            bump_percent: 25,
+            max_bumps: 5,
+            poll_interval: Duration::from_secs(25),
+        }
+    }
+}
+
+/// The outcome of a tracked message observed by the `MessageSubmitter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubmitEvent {
+    /// The message has been mined, as the original or one of its replacements.
+    Landed(Cid),
+    /// The stuck message has been replaced by the one with a bumped gas price.
+    Replaced {
+        /// The CID of the replaced message.
+        old: Cid,
+        /// The CID of the replacement.
+        new: Cid,
+    },
+    /// The message is still not mined after the maximum number of replacements, stop tracking it.
+    GaveUp(Cid),
+    /// Checking or replacing the message failed, it's still tracked and retried on the next poll.
+    Failed {
+        /// The CID of the tracked message.
+        cid: Cid,
+        /// The description of the error.
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Tracked {
+    message: UnsignedMessage,
+    submitted_at: ChainEpoch,
+    bumps: u32,
+    // the CIDs of the original message and all its replacements, any of them may land.
+    submitted: Vec<Cid>,
+}
+
+/// MessageSubmitter signs and pushes messages into the message pool, and replaces the messages
+/// that aren't mined within `timeout_epochs` with the same nonce and a bumped gas price,
+/// until the message lands or the maximum number of bumps is reached.
+pub struct MessageSubmitter<C> {
+    client: C,
+    config: FeeBumpConfig,
+    tracked: HashMap<Cid, Tracked>,
+}
+
+impl<C> MessageSubmitter<C>
+where
+    C: ChainApi + MpoolApi + StateApi + WalletApi,
+{
+    /// Create a new MessageSubmitter with the given fee bump configuration.
+    pub fn new(client: C, config: FeeBumpConfig) -> Self {
+        Self {
+            client,
+            config,
+            tracked: HashMap::new(),
+        }
+    }
+
+    /// Return the CIDs of the messages being tracked.
+    pub fn tracked(&self) -> Vec<Cid> {
+        self.tracked.keys().cloned().collect()
+    }
+
+    /// Sign the message with the wallet of the sender, push it into the message pool
+    /// and track it until it lands.
+    pub async fn submit(&mut self, message: UnsignedMessage) -> Result<Cid> {
+        let height = self.client.chain_head().await?.height();
+        let cid = self.push(&message).await?;
+        self.tracked.insert(
+            cid.clone(),
+            Tracked {
+                message,
+                submitted_at: height,
+                bumps: 0,
+                submitted: vec![cid.clone()],
+            },
+        );
+        Ok(cid)
+    }
+
+    /// Check the tracked messages against the current chain head once,
+    /// replace the stuck messages and return what happened.
+    ///
+    /// The failure of a message is reported as `SubmitEvent::Failed` without losing
+    /// the events of the other messages.
+    pub async fn poll(&mut self) -> Result<Vec<SubmitEvent>> {
+        let height = self.client.chain_head().await?.height();
+        let mut events = Vec::new();
+        let cids = self.tracked();
+        for cid in cids {
+            match self.check(&cid, height).await {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!("Failed to check message {}: {}", cid, err);
+                    events.push(SubmitEvent::Failed {
+                        cid,
+                        error: err.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Poll the chain head every `poll_interval` until all the tracked messages
+    /// land or are given up, return all the events.
+    pub async fn run(&mut self) -> Result<Vec<SubmitEvent>> {
+        let mut events = Vec::new();
+        while !self.tracked.is_empty() {
+            events.extend(self.poll().await?);
+            if !self.tracked.is_empty() {
+                tokio::time::delay_for(self.config.poll_interval).await;
+            }
+        }
+        Ok(events)
+    }
+
+    // Check the tracked message `cid` at `height`, replace it if it's stuck.
+    // The message is left tracked as is if it fails.
+    async fn check(&mut self, cid: &Cid, height: ChainEpoch) -> Result<Option<SubmitEvent>> {
+        if let Some(landed) = self.landed(&self.tracked[cid].submitted).await? {
+            self.tracked.remove(cid);
+            return Ok(Some(SubmitEvent::Landed(landed)));
+        }
+
+        let tracked = &self.tracked[cid];
+        if height - tracked.submitted_at < self.config.timeout_epochs {
+            return Ok(None);
+        }
+        if tracked.bumps >= self.config.max_bumps {
+            warn!(
+                "Message {} is still not mined after {} bumps",
+                cid, tracked.bumps
+            );
+            self.tracked.remove(cid);
+            return Ok(Some(SubmitEvent::GaveUp(cid.clone())));
+        }
+
+        let mut message = tracked.message.clone();
+        message.gas_price = bump_gas_price(&message.gas_price, self.config.bump_percent);
+        let bumps = tracked.bumps + 1;
+        let mut submitted = tracked.submitted.clone();
+        let new = self.push(&message).await?;
+        submitted.push(new.clone());
+        debug!(
+            "Replace message {} by {} with gas price {}",
+            cid, new, message.gas_price
+        );
+        self.tracked.remove(cid);
+        self.tracked.insert(
+            new.clone(),
+            Tracked {
+                message,
+                submitted_at: height,
+                bumps,
+                submitted,
+            },
+        );
+        Ok(Some(SubmitEvent::Replaced {
+            old: cid.clone(),
+            new,
+        }))
+    }
+
+    // Return the CID of the submitted message that has been mined, if any.
+    async fn landed(&self, submitted: &[Cid]) -> Result<Option<Cid>> {
+        for cid in submitted {
+            if self.client.state_search_msg(cid).await?.is_some() {
+                return Ok(Some(cid.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn push(&self, message: &UnsignedMessage) -> Result<Cid> {
+        let signed = self
+            .client
+            .wallet_sign_message(&message.from, message)
+            .await?;
+        self.client.mpool_push(&signed).await
+    }
+}
+
+// Increase the gas price by `percent`, at least by one.
+fn bump_gas_price(gas_price: &BigInt, percent: u64) -> BigInt {
+    let bumped = gas_price * BigInt::from(100 + percent) / BigInt::from(100);
+    if &bumped > gas_price {
+        bumped
+    } else {
+        gas_price + BigInt::from(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use jsonrpc_client::Value;
+    use plum_address::Address;
+    use plum_block::{BlockHeader, ElectionProof, Ticket};
+    use plum_crypto::Signature;
+    use plum_message::{MessageReceipt, SignedMessage};
+    use plum_tipset::Tipset;
+
+    use super::*;
+    use crate::errors::ApiError;
+    use crate::interface::MsgLookup;
+    use crate::mock::MockClient;
+
+    // A node whose message pool only gets the `landed` messages mined,
+    // and rejects the pushes while `rejecting`.
+    #[derive(Clone, Default)]
+    struct Node {
+        height: Arc<Mutex<ChainEpoch>>,
+        pushed: Arc<Mutex<Vec<SignedMessage>>>,
+        landed: Arc<Mutex<Vec<Cid>>>,
+        rejecting: Arc<Mutex<bool>>,
+    }
+
+    impl Node {
+        fn client(&self) -> MockClient {
+            let node = self.clone();
+            MockClient::new(move |method, params| node.handle(method, params))
+        }
+
+        fn handle(&self, method: &str, params: &[Value]) -> Result<Value> {
+            let value = match method {
+                "ChainHead" => serde_json::to_value(tipset(*self.height.lock().unwrap())).unwrap(),
+                "WalletSignMessage" => {
+                    let message: UnsignedMessage =
+                        serde_json::from_value(params[1].clone()).unwrap();
+                    let signed = SignedMessage {
+                        message,
+                        signature: Signature::new_secp256k1(vec![0u8; 65]),
+                    };
+                    serde_json::to_value(signed).unwrap()
+                }
+                "MpoolPush" if *self.rejecting.lock().unwrap() => {
+                    return Err(ApiError::JsonRpc {
+                        code: 1,
+                        message: "message pool is full".to_string(),
+                        data: None,
+                    });
+                }
+                "MpoolPush" => {
+                    let signed: SignedMessage = serde_json::from_value(params[0].clone()).unwrap();
+                    let cid = signed.cid();
+                    self.pushed.lock().unwrap().push(signed);
+                    serde_json::to_value(cid).unwrap()
+                }
+                "StateSearchMsg" => {
+                    let cid: Cid = serde_json::from_value(params[0].clone()).unwrap();
+                    if self.landed.lock().unwrap().contains(&cid) {
+                        let lookup = MsgLookup {
+                            receipt: MessageReceipt {
+                                exit_code: 0,
+                                r#return: vec![],
+                                gas_used: BigInt::from(0),
+                            },
+                            tipset: tipset(*self.height.lock().unwrap()),
+                        };
+                        serde_json::to_value(lookup).unwrap()
+                    } else {
+                        Value::Null
+                    }
+                }
+                method => panic!("unexpected method {}", method),
+            };
+            Ok(value)
+        }
+    }
+
+    fn tipset(height: ChainEpoch) -> Tipset {
+        let cid: Cid = "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+            .parse()
+            .unwrap();
+        let header = BlockHeader {
+            miner: Address::new_id_addr(1000).unwrap(),
+            ticket: Ticket {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            election_proof: ElectionProof {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            beacon_entries: vec![],
+            win_post_proof: vec![],
+            parents: vec![cid.clone()],
+            parent_message_receipts: cid.clone(),
+            bls_aggregate: Signature::new_bls("boo! im a signature"),
+            parent_weight: 0u64.into(),
+            messages: cid.clone(),
+            height,
+            parent_state_root: cid,
+            timestamp: 0u64,
+            block_sig: Signature::new_bls("boo! im a signature"),
+            fork_signaling: 0u64,
+        };
+        Tipset::new(vec![header]).unwrap()
+    }
+
+    fn config() -> FeeBumpConfig {
+        FeeBumpConfig {
+            timeout_epochs: 3,
+            bump_percent: 25,
+            max_bumps: 1,
+            poll_interval: Duration::from_millis(1),
+        }
+    }
+
+    fn message() -> UnsignedMessage {
+        UnsignedMessage {
+            version: 0,
+            to: Address::new_id_addr(1).unwrap(),
+            from: Address::new_id_addr(2).unwrap(),
+            nonce: 7,
+            value: BigInt::from(10),
+            gas_price: BigInt::from(100),
+            gas_limit: BigInt::from(10000),
+            method: 0,
+            params: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fee_bump() {
+        let node = Node::default();
+        let mut submitter = MessageSubmitter::new(node.client(), config());
+
+        let message = message();
+        let first = submitter.submit(message.clone()).await.unwrap();
+
+        // not replaced before the timeout.
+        *node.height.lock().unwrap() = 2;
+        assert!(submitter.poll().await.unwrap().is_empty());
+
+        *node.height.lock().unwrap() = 3;
+        let events = submitter.poll().await.unwrap();
+        let pushed = node.pushed.lock().unwrap().clone();
+        assert_eq!(pushed.len(), 2);
+        let replacement = &pushed[1].message;
+        assert_eq!(replacement.nonce, message.nonce);
+        assert_eq!(replacement.gas_price, BigInt::from(125));
+        assert_eq!(
+            events,
+            vec![SubmitEvent::Replaced {
+                old: first,
+                new: pushed[1].cid(),
+            }]
+        );
+
+        // give up once the maximum number of bumps is reached.
+        *node.height.lock().unwrap() = 6;
+        let events = submitter.poll().await.unwrap();
+        assert_eq!(events, vec![SubmitEvent::GaveUp(pushed[1].cid())]);
+        assert!(submitter.tracked().is_empty());
+        assert_eq!(node.pushed.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_replaced_message_lands() {
+        let node = Node::default();
+        let mut submitter = MessageSubmitter::new(node.client(), config());
+        let first = submitter.submit(message()).await.unwrap();
+        *node.height.lock().unwrap() = 3;
+        let events = submitter.poll().await.unwrap();
+        assert!(matches!(events[..], [SubmitEvent::Replaced { .. }]));
+
+        // the original message lands instead of its replacement, after the maximum bumps.
+        node.landed.lock().unwrap().push(first.clone());
+        *node.height.lock().unwrap() = 6;
+        let events = submitter.poll().await.unwrap();
+        assert_eq!(events, vec![SubmitEvent::Landed(first)]);
+        assert!(submitter.tracked().is_empty());
+        assert_eq!(node.pushed.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_replacement_keeps_events() {
+        let node = Node::default();
+        let mut submitter = MessageSubmitter::new(node.client(), config());
+        let landed = submitter.submit(message()).await.unwrap();
+        let stuck = submitter
+            .submit(UnsignedMessage {
+                nonce: 8,
+                ..message()
+            })
+            .await
+            .unwrap();
+
+        // the landed message is reported whether or not the other replacement fails first.
+        node.landed.lock().unwrap().push(landed.clone());
+        *node.rejecting.lock().unwrap() = true;
+        *node.height.lock().unwrap() = 3;
+        let events = submitter.poll().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&SubmitEvent::Landed(landed)));
+        assert!(events.contains(&SubmitEvent::Failed {
+            cid: stuck.clone(),
+            error: "JSON-RPC error 1: message pool is full".to_string(),
+        }));
+        assert_eq!(submitter.tracked(), vec![stuck.clone()]);
+
+        // the failed message is replaced on the next poll.
+        *node.rejecting.lock().unwrap() = false;
+        let events = submitter.poll().await.unwrap();
+        let pushed = node.pushed.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            vec![SubmitEvent::Replaced {
+                old: stuck,
+                new: pushed[2].cid(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use anyhow::{bail, ensure, Result};
+use plum_block::{BeaconEntry, BlockHeader};
+
+/// The randomness beacon of the network (e.g. drand).
+///
+/// The signature verification is pluggable so that the different networks
+/// (and tests) can provide their own beacon.
+pub trait Beacon {
+    /// Verify that the signature of the `curr` entry chains from the `prev` entry.
+    fn verify_entry(&self, curr: &BeaconEntry, prev: &BeaconEntry) -> Result<()>;
+}
+
+/// Verify the beacon entries of the block header.
+///
+/// Every entry must have a round greater than the previous entry,
+/// and its signature must chain from the previous entry,
+/// the first entry chains from the `prev_entry` of the parent.
+pub fn verify_beacon_entries<B: Beacon>(
+    header: &BlockHeader,
+    prev_entry: &BeaconEntry,
+    beacon: &B,
+) -> Result<()> {
+    verify_entries(&header.beacon_entries, prev_entry, beacon)
+}
+
+pub(crate) fn verify_entries<B: Beacon>(
+    entries: &[BeaconEntry],
+    prev_entry: &BeaconEntry,
+    beacon: &B,
+) -> Result<()> {
+    let mut prev = prev_entry;
+    for entry in entries {
+        ensure!(
+            entry.round > prev.round,
+            "beacon entry round {} isn't greater than the previous round {}",
+            entry.round,
+            prev.round
+        );
+        if let Err(err) = beacon.verify_entry(entry, prev) {
+            bail!(
+                "beacon entry {} failed to chain from round {}: {}",
+                entry.round,
+                prev.round,
+                err
+            );
+        }
+        prev = entry;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use plum_hashing::blake2b_256;
+
+    use super::*;
+
+    // The signature of the mock beacon is the hash of the previous signature and the round.
+    struct MockBeacon;
+
+    impl MockBeacon {
+        fn sign(prev: &BeaconEntry, round: u64) -> Vec<u8> {
+            let mut data = prev.data.clone();
+            data.extend_from_slice(&round.to_be_bytes());
+            blake2b_256(data).to_vec()
+        }
+
+        fn next(prev: &BeaconEntry) -> BeaconEntry {
+            BeaconEntry::new(prev.round + 1, Self::sign(prev, prev.round + 1))
+        }
+    }
+
+    impl Beacon for MockBeacon {
+        fn verify_entry(&self, curr: &BeaconEntry, prev: &BeaconEntry) -> Result<()> {
+            ensure!(
+                curr.data == Self::sign(prev, curr.round),
+                "invalid beacon signature"
+            );
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_verify_beacon_entries() {
+        let genesis = BeaconEntry::new(1, b"genesis".to_vec());
+        let first = MockBeacon::next(&genesis);
+        let second = MockBeacon::next(&first);
+
+        // valid chain
+        let entries = vec![first.clone(), second.clone()];
+        assert!(verify_entries(&entries, &genesis, &MockBeacon).is_ok());
+        assert!(verify_entries(&[], &genesis, &MockBeacon).is_ok());
+
+        // broken chain: the second entry doesn't chain from the first one.
+        let forged = BeaconEntry::new(second.round, MockBeacon::sign(&genesis, second.round));
+        let entries = vec![first.clone(), forged];
+        assert!(verify_entries(&entries, &genesis, &MockBeacon).is_err());
+
+        // broken chain: the rounds are not increasing.
+        let entries = vec![second, first];
+        assert!(verify_entries(&entries, &genesis, &MockBeacon).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use cid::Cid;
+
+use ipfs_block::IpfsBlock;
+use ipfs_datastore::{DataStore, DataStoreError, DataStoreRead, DataStoreWrite, Key, PrefixScan};
+use ipld::IpldValue;
+use plum_block::BlockHeader;
+use plum_message::SignedMessage;
+use plum_types::{decode_versioned, encode_versioned, ChainEpoch, Versioned};
+
+const BLOCKS_PREFIX: &str = "/chain/blocks";
+const MESSAGES_PREFIX: &str = "/chain/messages";
+const OBJECTS_PREFIX: &str = "/chain/objects";
+const EPOCHS_PREFIX: &str = "/chain/epochs";
+const HORIZON_KEY: &str = "/chain/horizon";
+
+/// The error type of the chain store.
+#[derive(Debug, thiserror::Error)]
+pub enum ChainStoreError {
+    /// The requested epoch is below the prune horizon and its data is gone.
+    #[error("epoch {epoch} has been pruned, the prune horizon is {horizon}")]
+    Pruned {
+        /// The requested epoch.
+        epoch: ChainEpoch,
+        /// The lowest epoch whose data is still available.
+        horizon: ChainEpoch,
+    },
+    /// The stored data cannot be decoded.
+    #[error("invalid data stored under {0}: {1}")]
+    Decode(Key, String),
+    /// The underlying datastore error.
+    #[error("datastore error: {0}")]
+    DataStore(#[from] DataStoreError),
+}
+
+/// ChainStore persists block headers and the IPLD objects (messages, receipts and state)
+/// they refer to, indexed by the epoch they were produced at.
+///
+/// The block headers and the signed messages are persisted in the versioned envelope,
+/// their CIDs are still computed from the bare encoding.
+///
+/// Data below the prune horizon is removed by `prune`, except for the data which is
+/// reachable from the kept roots or from the unpruned epochs.
+#[derive(Clone)]
+pub struct ChainStore<DS: DataStore> {
+    datastore: DS,
+    horizon: ChainEpoch,
+}
+
+impl<DS: DataStore> ChainStore<DS> {
+    /// Create a chain store backed by the `datastore`, loading the prune horizon if any.
+    pub fn new(datastore: DS) -> Result<Self, ChainStoreError> {
+        let horizon_key = Key::new(HORIZON_KEY);
+        let horizon = if datastore.has(&horizon_key)? {
+            let bytes = datastore.get(&horizon_key)?;
+            let bytes: [u8; 8] = bytes[..]
+                .try_into()
+                .map_err(|_| ChainStoreError::Decode(horizon_key, "bad horizon".into()))?;
+            ChainEpoch::from_be_bytes(bytes)
+        } else {
+            0
+        };
+        Ok(Self { datastore, horizon })
+    }
+
+    /// Return the lowest epoch whose data is still available.
+    pub fn horizon(&self) -> ChainEpoch {
+        self.horizon
+    }
+
+    /// Persist the block header and index it by its height.
+    pub fn put_header(&mut self, header: &BlockHeader) -> Result<Cid, ChainStoreError> {
+        let cid = header.cid();
+        self.datastore
+            .put(block_key(&cid), encode_versioned(header))?;
+        self.datastore
+            .put(epoch_key(header.height, &cid), Vec::new())?;
+        Ok(cid)
+    }
+
+    /// Persist the signed message included at the `epoch`.
+    pub fn put_message(
+        &mut self,
+        epoch: ChainEpoch,
+        message: &SignedMessage,
+    ) -> Result<Cid, ChainStoreError> {
+        let cid = message.cid();
+        self.datastore
+            .put(message_key(&cid), encode_versioned(message))?;
+        self.datastore.put(epoch_key(epoch, &cid), Vec::new())?;
+        Ok(cid)
+    }
+
+    /// Persist the IPLD object produced at the `epoch`.
+    pub fn put_object(
+        &mut self,
+        epoch: ChainEpoch,
+        object: IpldValue,
+    ) -> Result<Cid, ChainStoreError> {
+        let block = IpfsBlock::new(object);
+        let cid = block.cid().clone();
+        self.datastore
+            .put(object_key(&cid), block.data().to_vec())?;
+        self.datastore.put(epoch_key(epoch, &cid), Vec::new())?;
+        Ok(cid)
+    }
+
+    /// Return whether the block header of the `cid` is stored.
+    pub fn has_header(&self, cid: &Cid) -> Result<bool, ChainStoreError> {
+        Ok(self.datastore.has(&block_key(cid))?)
+    }
+
+    /// Return whether the signed message of the `cid` is stored.
+    pub fn has_message(&self, cid: &Cid) -> Result<bool, ChainStoreError> {
+        Ok(self.datastore.has(&message_key(cid))?)
+    }
+
+    /// Return whether the IPLD object of the `cid` is stored.
+    pub fn has_object(&self, cid: &Cid) -> Result<bool, ChainStoreError> {
+        Ok(self.datastore.has(&object_key(cid))?)
+    }
+
+    /// Load the block header of the `cid`.
+    pub fn get_header(&self, cid: &Cid) -> Result<BlockHeader, ChainStoreError> {
+        self.get_versioned(block_key(cid))
+    }
+
+    /// Load the signed message of the `cid`.
+    pub fn get_message(&self, cid: &Cid) -> Result<SignedMessage, ChainStoreError> {
+        self.get_versioned(message_key(cid))
+    }
+
+    /// Load the IPLD object of the `cid`.
+    pub fn get_object(&self, cid: &Cid) -> Result<IpldValue, ChainStoreError> {
+        let key = object_key(cid);
+        let data = self.datastore.get(&key)?;
+        minicbor::decode(&data).map_err(|e| ChainStoreError::Decode(key, e.to_string()))
+    }
+
+    /// Load the block headers at the `epoch` the chain is reverted to.
+    ///
+    /// Reverting past the prune horizon fails with `ChainStoreError::Pruned`.
+    pub fn revert_to(&self, epoch: ChainEpoch) -> Result<Vec<BlockHeader>, ChainStoreError> {
+        if epoch < self.horizon {
+            return Err(ChainStoreError::Pruned {
+                epoch,
+                horizon: self.horizon,
+            });
+        }
+        let mut headers = Vec::new();
+        for (_, cid) in self.indexed(&Key::new(format!("{}/{:020}", EPOCHS_PREFIX, epoch)))? {
+            if self.has_header(&cid)? {
+                headers.push(self.get_header(&cid)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Delete the block headers and objects produced before the `before` epoch,
+    /// return the number of deleted entries.
+    ///
+    /// Anything reachable from the `keep_roots`, or from the block headers at or after
+    /// the `before` epoch, is never deleted. The retained entries stay indexed, so that
+    /// a later `prune` deletes them once they're no longer kept.
+    pub fn prune(
+        &mut self,
+        before: ChainEpoch,
+        keep_roots: &[Cid],
+    ) -> Result<usize, ChainStoreError> {
+        let mut roots = keep_roots.to_vec();
+        let mut stale = Vec::new();
+        // the index is scanned once, the stale entries are deleted by their index keys.
+        for (epoch, cid) in self.indexed(&Key::new(EPOCHS_PREFIX))? {
+            if epoch >= before {
+                if self.has_header(&cid)? {
+                    roots.push(cid);
+                }
+            } else {
+                stale.push((epoch, cid));
+            }
+        }
+        let keep = self.reachable(roots)?;
+
+        let mut deleted = 0;
+        for (epoch, cid) in stale.iter().filter(|(_, cid)| !keep.contains(cid)) {
+            for key in &[block_key(cid), message_key(cid), object_key(cid)] {
+                if self.datastore.has(key)? {
+                    self.datastore.delete(key)?;
+                    deleted += 1;
+                }
+            }
+            self.datastore.delete(&epoch_key(*epoch, cid))?;
+        }
+
+        if before > self.horizon {
+            self.horizon = before;
+            self.datastore
+                .put(Key::new(HORIZON_KEY), before.to_be_bytes().to_vec())?;
+        }
+        Ok(deleted)
+    }
+
+    // Load the value persisted in the versioned envelope, the unknown versions are rejected.
+    fn get_versioned<T: Versioned>(&self, key: Key) -> Result<T, ChainStoreError> {
+        let data = self.datastore.get(&key)?;
+        decode_versioned(&data).map_err(|e| ChainStoreError::Decode(key, e.to_string()))
+    }
+
+    // Return the (epoch, cid) pairs indexed under the `prefix`.
+    fn indexed(&self, prefix: &Key) -> Result<Vec<(ChainEpoch, Cid)>, ChainStoreError> {
+        let mut indexed = Vec::new();
+        for key in self.datastore.keys_with_prefix(prefix)? {
+            let namespaces = key.list();
+            let parsed = match namespaces.as_slice() {
+                [_, _, epoch, cid] => epoch.parse().ok().zip(cid.parse().ok()),
+                _ => None,
+            };
+            match parsed {
+                Some(entry) => indexed.push(entry),
+                None => return Err(ChainStoreError::Decode(key, "bad epoch index".into())),
+            }
+        }
+        Ok(indexed)
+    }
+
+    // Walk the DAG from the `roots`, following the messages, receipts and state of
+    // block headers and the links of IPLD objects, but not the parents of headers.
+    fn reachable(&self, roots: Vec<Cid>) -> Result<HashSet<Cid>, ChainStoreError> {
+        let mut seen = HashSet::new();
+        let mut stack = roots;
+        while let Some(cid) = stack.pop() {
+            if !seen.insert(cid.clone()) {
+                continue;
+            }
+            if self.has_header(&cid)? {
+                let header = self.get_header(&cid)?;
+                stack.push(header.messages);
+                stack.push(header.parent_message_receipts);
+                stack.push(header.parent_state_root);
+            } else if self.has_object(&cid)? {
+                collect_links(self.get_object(&cid)?, &mut stack);
+            }
+        }
+        Ok(seen)
+    }
+}
+
+fn collect_links(value: IpldValue, links: &mut Vec<Cid>) {
+    match value {
+        IpldValue::Link(cid) => links.push(cid),
+        IpldValue::List(list) => list.into_iter().for_each(|v| collect_links(v, links)),
+        IpldValue::Map(map) => map.into_iter().for_each(|(_, v)| collect_links(v, links)),
+        _ => {}
+    }
+}
+
+fn block_key(cid: &Cid) -> Key {
+    Key::new(format!("{}/{}", BLOCKS_PREFIX, cid))
+}
+
+fn message_key(cid: &Cid) -> Key {
+    Key::new(format!("{}/{}", MESSAGES_PREFIX, cid))
+}
+
+fn object_key(cid: &Cid) -> Key {
+    Key::new(format!("{}/{}", OBJECTS_PREFIX, cid))
+}
+
+fn epoch_key(epoch: ChainEpoch, cid: &Cid) -> Key {
+    Key::new(format!("{}/{:020}/{}", EPOCHS_PREFIX, epoch, cid))
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs_datastore::{MapDataStore, SyncDataStore};
+    use plum_address::Address;
+    use plum_bigint::BigInt;
+    use plum_block::{ElectionProof, Ticket};
+    use plum_crypto::Signature;
+    use plum_message::UnsignedMessage;
+
+    use super::*;
+
+    fn header(height: ChainEpoch, state: Cid, messages: Cid) -> BlockHeader {
+        BlockHeader {
+            miner: Address::new_id_addr(1000).unwrap(),
+            ticket: Ticket {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            election_proof: ElectionProof {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            beacon_entries: vec![],
+            win_post_proof: vec![],
+            parents: vec![],
+            parent_message_receipts: messages.clone(),
+            bls_aggregate: Signature::new_bls("boo! im a signature"),
+            parent_weight: 0u64.into(),
+            messages,
+            height,
+            parent_state_root: state,
+            timestamp: 0u64,
+            block_sig: Signature::new_bls("boo! im a signature"),
+            fork_signaling: 0u64,
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut store = ChainStore::new(SyncDataStore::new(MapDataStore::new())).unwrap();
+
+        // an actor code object created at genesis and referenced by every state.
+        let code = store
+            .put_object(0, IpldValue::String("code".into()))
+            .unwrap();
+
+        let mut headers = Vec::new();
+        let mut objects = Vec::new();
+        for epoch in 0..10 {
+            let messages = store
+                .put_object(
+                    epoch,
+                    IpldValue::List(vec![IpldValue::Integer(epoch.into())]),
+                )
+                .unwrap();
+            let state = store
+                .put_object(
+                    epoch,
+                    IpldValue::List(vec![
+                        IpldValue::Integer(epoch.into()),
+                        IpldValue::Link(code.clone()),
+                    ]),
+                )
+                .unwrap();
+            headers.push(
+                store
+                    .put_header(&header(epoch, state.clone(), messages.clone()))
+                    .unwrap(),
+            );
+            objects.push((messages, state));
+        }
+
+        // keep a checkpoint at epoch 3 and prune everything else before epoch 8.
+        let checkpoint = headers[3].clone();
+        let deleted = store.prune(8, &[checkpoint]).unwrap();
+        assert_eq!(deleted, 7 * 3);
+        assert_eq!(store.horizon(), 8);
+
+        for (epoch, (messages, state)) in objects.iter().enumerate() {
+            let kept = epoch == 3 || epoch >= 8;
+            assert_eq!(store.has_header(&headers[epoch]).unwrap(), kept);
+            assert_eq!(store.has_object(messages).unwrap(), kept);
+            assert_eq!(store.has_object(state).unwrap(), kept);
+        }
+        assert!(store.has_object(&code).unwrap());
+        // only the retained entries, the checkpoint and the code, stay indexed below
+        // the horizon.
+        let indexed = store.indexed(&Key::new(EPOCHS_PREFIX)).unwrap();
+        assert!(indexed
+            .iter()
+            .all(|(epoch, _)| *epoch >= 8 || *epoch == 3 || *epoch == 0));
+        assert_eq!(indexed.iter().filter(|(epoch, _)| *epoch == 3).count(), 3);
+
+        assert_eq!(store.revert_to(9).unwrap().len(), 1);
+        match store.revert_to(5) {
+            Err(ChainStoreError::Pruned { epoch, horizon }) => {
+                assert_eq!(epoch, 5);
+                assert_eq!(horizon, 8);
+            }
+            _ => panic!("reverting past the prune horizon should fail"),
+        }
+
+        // the checkpoint is pruned once it's no longer kept, the code is still reachable.
+        assert_eq!(store.prune(8, &[]).unwrap(), 3);
+        assert!(!store.has_header(&headers[3]).unwrap());
+        assert!(store.has_object(&code).unwrap());
+        let indexed = store.indexed(&Key::new(EPOCHS_PREFIX)).unwrap();
+        assert!(indexed.iter().all(|(epoch, _)| *epoch >= 8 || *epoch == 0));
+    }
+
+    #[test]
+    fn test_versioned_envelope() {
+        let datastore = SyncDataStore::new(MapDataStore::new());
+        let mut store = ChainStore::new(datastore.clone()).unwrap();
+
+        let state = store
+            .put_object(1, IpldValue::String("state".into()))
+            .unwrap();
+        let header = header(1, state.clone(), state);
+        let header_cid = store.put_header(&header).unwrap();
+        assert_eq!(header_cid, header.cid());
+        assert_eq!(store.get_header(&header_cid).unwrap(), header);
+
+        let message = SignedMessage {
+            message: UnsignedMessage {
+                version: 0,
+                to: Address::new_id_addr(100).unwrap(),
+                from: Address::new_id_addr(1000).unwrap(),
+                nonce: 0,
+                value: BigInt::from(0),
+                gas_price: BigInt::from(0),
+                gas_limit: BigInt::from(0),
+                method: 0,
+                params: vec![],
+            },
+            signature: Signature::new_secp256k1(vec![0; 65]),
+        };
+        let message_cid = store.put_message(1, &message).unwrap();
+        assert_eq!(message_cid, message.cid());
+        assert_eq!(store.get_message(&message_cid).unwrap(), message);
+
+        // the values are persisted as `[version, value]`, the unknown versions are rejected.
+        let mut persisted = datastore.get(&block_key(&header_cid)).unwrap();
+        assert_eq!(persisted[..2], [0x82, BlockHeader::VERSION as u8]);
+        persisted[1] = 0;
+        datastore
+            .clone()
+            .put(block_key(&header_cid), persisted)
+            .unwrap();
+        assert!(matches!(
+            store.get_header(&header_cid),
+            Err(ChainStoreError::Decode(..))
+        ));
+    }
+}
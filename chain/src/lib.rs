@@ -1,5 +1,13 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+mod beacon;
+mod chain_store;
+mod select;
 mod store;
+mod validate;
 
+pub use beacon::*;
+pub use chain_store::*;
+pub use select::*;
 pub use store::*;
+pub use validate::*;
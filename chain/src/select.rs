@@ -0,0 +1,277 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use plum_address::Address;
+use plum_bigint::BigInt;
+use plum_message::SignedMessage;
+use plum_types::Gas;
+
+// The next message of a sender that can be selected.
+#[derive(Eq, PartialEq)]
+struct Candidate {
+    gas_price: BigInt,
+    // CID bytes of the message, used to break ties deterministically.
+    cid: Vec<u8>,
+    sender: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the higher gas price first, then the smaller CID first.
+        self.gas_price
+            .cmp(&other.gas_price)
+            .then_with(|| other.cid.cmp(&self.cid))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Candidate {
+    fn new(msg: &SignedMessage, sender: usize) -> Self {
+        Self {
+            gas_price: msg.message.gas_price.clone(),
+            cid: msg.cid().to_bytes(),
+            sender,
+        }
+    }
+}
+
+/// Select the fee-paying messages fitting the block gas limit from the pending messages,
+/// and return them in the canonical order.
+///
+/// The messages of each sender must have contiguous nonces starting from the nonce of the
+/// sender's actor in `nonces` (0 if it's missing), so the messages already applied are
+/// skipped, and a sender's messages after a nonce gap, a message paying less than the
+/// `base_fee` or a message that doesn't fit the remaining gas are all excluded. When there
+/// are duplicated nonces, the message with the highest gas price is kept, ties are broken
+/// by the smaller CID.
+///
+/// Across senders, the message with the highest gas price is picked first,
+/// ties are broken by the CID of the message, so that every miner produces the same order.
+pub fn select_and_order_messages(
+    pending: Vec<SignedMessage>,
+    nonces: &HashMap<Address, u64>,
+    base_fee: &BigInt,
+    block_gas_limit: &Gas,
+) -> Vec<SignedMessage> {
+    let mut by_sender = HashMap::<Address, Vec<SignedMessage>>::new();
+    for msg in pending {
+        by_sender
+            .entry(msg.message.from.clone())
+            .or_default()
+            .push(msg);
+    }
+
+    let mut chains = by_sender
+        .into_iter()
+        .map(|(sender, msgs)| {
+            let nonce = nonces.get(&sender).copied().unwrap_or_default();
+            contiguous_chain(msgs, nonce, base_fee)
+        })
+        .filter(|chain| !chain.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut candidates = chains
+        .iter()
+        .enumerate()
+        .map(|(sender, chain)| Candidate::new(&chain[0], sender))
+        .collect::<BinaryHeap<_>>();
+
+    let mut remaining = block_gas_limit.clone();
+    let mut selected = Vec::new();
+    while let Some(candidate) = candidates.pop() {
+        let chain = &mut chains[candidate.sender];
+        let msg = chain
+            .pop_front()
+            .expect("candidate is the head of chain; qed");
+        if msg.message.gas_limit > remaining {
+            // the rest messages of the sender can't be included without this one.
+            chain.clear();
+            continue;
+        }
+        remaining -= &msg.message.gas_limit;
+        selected.push(msg);
+        if let Some(next) = chain.front() {
+            candidates.push(Candidate::new(next, candidate.sender));
+        }
+    }
+    selected
+}
+
+// Return the messages of a sender with contiguous nonces from the state `nonce` that pay at
+// least the base fee.
+fn contiguous_chain(
+    mut msgs: Vec<SignedMessage>,
+    nonce: u64,
+    base_fee: &BigInt,
+) -> VecDeque<SignedMessage> {
+    msgs.retain(|msg| msg.message.nonce >= nonce);
+    msgs.sort_by_cached_key(|msg| {
+        (
+            msg.message.nonce,
+            Reverse(msg.message.gas_price.clone()),
+            msg.cid().to_bytes(),
+        )
+    });
+    msgs.dedup_by_key(|msg| msg.message.nonce);
+
+    let mut chain = VecDeque::with_capacity(msgs.len());
+    let mut next = nonce;
+    for msg in msgs {
+        if msg.message.gas_price < *base_fee || msg.message.nonce != next {
+            break;
+        }
+        next += 1;
+        chain.push_back(msg);
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use plum_crypto::Signature;
+    use plum_message::UnsignedMessage;
+
+    use super::*;
+
+    fn new_msg(from: u64, nonce: u64, gas_price: u64, gas_limit: u64) -> SignedMessage {
+        SignedMessage {
+            message: UnsignedMessage {
+                version: 0,
+                to: Address::new_id_addr(100).unwrap(),
+                from: Address::new_id_addr(from).unwrap(),
+                nonce,
+                value: BigInt::from(0),
+                gas_price: BigInt::from(gas_price),
+                gas_limit: BigInt::from(gas_limit),
+                method: 0,
+                params: vec![],
+            },
+            signature: Signature::new_secp256k1(vec![0u8; 65]),
+        }
+    }
+
+    fn summary(msgs: &[SignedMessage]) -> Vec<(Address, u64)> {
+        msgs.iter()
+            .map(|msg| (msg.message.from.clone(), msg.message.nonce))
+            .collect()
+    }
+
+    #[test]
+    fn test_select_and_order_messages() {
+        let a = Address::new_id_addr(1).unwrap();
+        let b = Address::new_id_addr(2).unwrap();
+        let c = Address::new_id_addr(3).unwrap();
+        let pending = vec![
+            new_msg(1, 2, 10, 100),
+            new_msg(2, 1, 5, 100), // below the base fee, blocks b's nonce 1 and later.
+            new_msg(1, 0, 10, 100),
+            new_msg(2, 3, 30, 100), // nonce gap
+            new_msg(3, 1, 50, 100), // c's nonce 0 has been applied
+            new_msg(2, 0, 20, 100),
+            new_msg(1, 1, 10, 100),
+            new_msg(3, 2, 50, 100),
+        ];
+
+        let nonces = vec![(c.clone(), 1)].into_iter().collect();
+        let base_fee = BigInt::from(6);
+        let selected =
+            select_and_order_messages(pending.clone(), &nonces, &base_fee, &BigInt::from(350));
+        // c's messages are contiguous from its state nonce, b's nonce 0 pays more than a's.
+        assert_eq!(
+            summary(&selected),
+            vec![(c.clone(), 1), (c.clone(), 2), (b.clone(), 0)]
+        );
+
+        let selected =
+            select_and_order_messages(pending.clone(), &nonces, &base_fee, &BigInt::from(1000));
+        assert_eq!(
+            summary(&selected),
+            vec![
+                (c.clone(), 1),
+                (c, 2),
+                (b, 0),
+                (a.clone(), 0),
+                (a.clone(), 1),
+                (a, 2)
+            ]
+        );
+        let gas = selected
+            .iter()
+            .fold(BigInt::from(0), |acc, msg| acc + &msg.message.gas_limit);
+        assert!(gas <= BigInt::from(1000));
+
+        // the selection is deterministic regardless of the pending order.
+        let mut reversed = pending;
+        reversed.reverse();
+        assert_eq!(
+            select_and_order_messages(reversed, &nonces, &base_fee, &BigInt::from(1000)),
+            selected
+        );
+    }
+
+    #[test]
+    fn test_select_respects_gas_limit() {
+        let pending = (0..5).map(|nonce| new_msg(1, nonce, 10, 100)).collect();
+        let nonces = HashMap::new();
+        let selected =
+            select_and_order_messages(pending, &nonces, &BigInt::from(0), &BigInt::from(250));
+        assert_eq!(
+            selected
+                .iter()
+                .map(|msg| msg.message.nonce)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_select_from_state_nonce() {
+        let a = Address::new_id_addr(1).unwrap();
+        let nonces = vec![(a.clone(), 2)].into_iter().collect();
+        let limit = BigInt::from(1000);
+
+        // the chain starting below the state nonce isn't taken as a gapped one.
+        let pending = vec![new_msg(1, 0, 10, 100), new_msg(1, 1, 10, 100)];
+        let selected = select_and_order_messages(pending, &nonces, &BigInt::from(0), &limit);
+        assert!(selected.is_empty());
+        let pending = vec![new_msg(1, 1, 10, 100), new_msg(1, 3, 10, 100)];
+        let selected = select_and_order_messages(pending, &nonces, &BigInt::from(0), &limit);
+        assert!(selected.is_empty());
+
+        let pending = (0..5).map(|nonce| new_msg(1, nonce, 10, 100)).collect();
+        let selected = select_and_order_messages(pending, &nonces, &BigInt::from(0), &limit);
+        assert_eq!(
+            summary(&selected),
+            vec![(a.clone(), 2), (a.clone(), 3), (a, 4)]
+        );
+    }
+
+    #[test]
+    fn test_select_duplicated_nonce() {
+        // the same nonce and gas price, the smaller CID is kept whatever the pending order.
+        let mut pending = vec![new_msg(1, 0, 10, 100), new_msg(1, 0, 10, 200)];
+        let kept = pending
+            .iter()
+            .min_by_key(|msg| msg.cid().to_bytes())
+            .cloned();
+        let nonces = HashMap::new();
+        for _ in 0..2 {
+            let selected = select_and_order_messages(
+                pending.clone(),
+                &nonces,
+                &BigInt::from(0),
+                &BigInt::from(1000),
+            );
+            assert_eq!(selected.first(), kept.as_ref());
+            assert_eq!(selected.len(), 1);
+            pending.reverse();
+        }
+    }
+}
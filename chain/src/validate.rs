@@ -0,0 +1,387 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use anyhow::Result;
+use cid::Cid;
+
+use ipfs_block::IpfsBlock;
+use ipfs_datastore::DataStore;
+use plum_bigint::BigInt;
+use plum_block::{Block, BlockHeader, MsgMeta};
+use plum_tipset::Tipset;
+use plum_types::{ChainEpoch, BLOCK_DELAY, BLOCK_MESSAGE_LIMIT};
+
+use crate::beacon::{verify_beacon_entries, verify_entries, Beacon};
+use crate::chain_store::{ChainStore, ChainStoreError};
+
+/// The error of the first failed check of `validate_block`.
+#[derive(Debug, thiserror::Error)]
+pub enum BlockValidationError {
+    /// The parents of the block aren't the blocks of the parent tipset.
+    #[error("the parents of the block don't match the parent tipset")]
+    ParentMismatch,
+    /// The block isn't above the parent tipset.
+    #[error("block height {height} isn't above the parent height {parent_height}")]
+    InvalidHeight {
+        /// The height of the block.
+        height: ChainEpoch,
+        /// The height of the parent tipset.
+        parent_height: ChainEpoch,
+    },
+    /// The timestamp doesn't match the epochs elapsed since the parent tipset.
+    #[error("block timestamp {found} doesn't match the expected {expected}")]
+    InvalidTimestamp {
+        /// The timestamp derived from the parent tipset, `u64::MAX` if it overflows.
+        expected: u64,
+        /// The timestamp of the block.
+        found: u64,
+    },
+    /// The block includes more messages than the limit.
+    #[error("block has {0} messages, exceeding the limit {}", BLOCK_MESSAGE_LIMIT)]
+    TooManyMessages(usize),
+    /// The messages root of the header doesn't commit to the messages of the block.
+    #[error("messages root {found} doesn't match the computed {expected}")]
+    MessagesRoot {
+        /// The root computed from the messages.
+        expected: Cid,
+        /// The root of the header.
+        found: Cid,
+    },
+    /// The parent state isn't in the chain store.
+    #[error("parent state {0} is missing from the chain store")]
+    MissingParentState(Cid),
+    /// The parent weight of the header isn't the weight of the parent tipset.
+    #[error("parent weight {found} doesn't match the computed {expected}")]
+    ParentWeight {
+        /// The weight computed from the parent tipset.
+        expected: BigInt,
+        /// The parent weight of the header.
+        found: BigInt,
+    },
+    /// The weight of the parent tipset can't be computed.
+    #[error("failed to compute the parent weight: {0}")]
+    Weight(String),
+    /// The beacon entries don't chain.
+    #[error("invalid beacon entries: {0}")]
+    Beacon(String),
+    /// The ticket isn't derived from the parent tipset.
+    #[error("invalid ticket: {0}")]
+    Ticket(String),
+    /// The block isn't signed by the miner.
+    #[error("invalid block signature: {0}")]
+    Signature(String),
+    /// The winning PoSt proof is invalid.
+    #[error("invalid winning PoSt proof: {0}")]
+    WinningPoSt(String),
+    /// The chain store error.
+    #[error("chain store error: {0}")]
+    Store(#[from] ChainStoreError),
+}
+
+/// BlockVerifier provides the checks of `validate_block` depending on the chain state and
+/// the proof systems, so that the networks (and tests) can inject their own.
+pub trait BlockVerifier: Beacon {
+    /// Compute the weight of the `tipset`.
+    fn weight(&self, tipset: &Tipset) -> Result<BigInt>;
+
+    /// Verify that the ticket of the block is derived from the `parent` tipset.
+    fn verify_ticket(&self, header: &BlockHeader, parent: &Tipset) -> Result<()>;
+
+    /// Verify that the block is signed by the worker key of the miner.
+    fn verify_block_signature(&self, header: &BlockHeader) -> Result<()>;
+
+    /// Verify the winning PoSt proof of the block.
+    fn verify_winning_post(&self, header: &BlockHeader, parent: &Tipset) -> Result<()>;
+}
+
+/// Compute the messages root committing to the BLS and secp256k1 messages of the block.
+pub fn compute_messages_root(block: &Block) -> Cid {
+    let bls = block
+        .bls_messages
+        .iter()
+        .map(|msg| msg.cid())
+        .collect::<Vec<_>>();
+    let secpk = block
+        .secpk_messages
+        .iter()
+        .map(|msg| msg.cid())
+        .collect::<Vec<_>>();
+    let meta = MsgMeta {
+        bls_messages: list_cid(&bls),
+        secpk_messages: list_cid(&secpk),
+    };
+    meta.cid()
+}
+
+fn list_cid(cids: &[Cid]) -> Cid {
+    let list = ipld::cbor_to_ipld(cids).expect("CBOR list of CIDs is a valid IPLD value");
+    IpfsBlock::new(list).cid().clone()
+}
+
+/// Validate the block on top of the `parent` tipset, returning the first failed check.
+///
+/// The cheap structural checks run first (parents, height, timestamp and messages), then
+/// the checks against the chain store and the parent weight, and the beacon, ticket,
+/// signature and winning PoSt checks of the `verifier` last, as they're the most expensive.
+pub fn validate_block<DS, V>(
+    block: &Block,
+    parent: &Tipset,
+    store: &ChainStore<DS>,
+    verifier: &V,
+) -> Result<(), BlockValidationError>
+where
+    DS: DataStore,
+    V: BlockVerifier,
+{
+    let header = &block.header;
+    if header.parents.as_slice() != parent.cids() {
+        return Err(BlockValidationError::ParentMismatch);
+    }
+    if header.height <= parent.height() {
+        return Err(BlockValidationError::InvalidHeight {
+            height: header.height,
+            parent_height: parent.height(),
+        });
+    }
+    // the heights and the timestamps are untrusted, so the expected timestamp is computed
+    // with checked ops and an overflow never matches.
+    let expected = header
+        .height
+        .checked_sub(parent.height())
+        .and_then(|elapsed| BLOCK_DELAY.checked_mul(elapsed as u64))
+        .and_then(|delay| parent.min_timestamp().checked_add(delay));
+    if expected != Some(header.timestamp) {
+        return Err(BlockValidationError::InvalidTimestamp {
+            expected: expected.unwrap_or(u64::max_value()),
+            found: header.timestamp,
+        });
+    }
+    let messages = block.bls_messages.len() + block.secpk_messages.len();
+    if messages as u64 > BLOCK_MESSAGE_LIMIT {
+        return Err(BlockValidationError::TooManyMessages(messages));
+    }
+    let expected = compute_messages_root(block);
+    if header.messages != expected {
+        return Err(BlockValidationError::MessagesRoot {
+            expected,
+            found: header.messages.clone(),
+        });
+    }
+
+    if !store.has_object(&header.parent_state_root)? {
+        return Err(BlockValidationError::MissingParentState(
+            header.parent_state_root.clone(),
+        ));
+    }
+    let expected = verifier
+        .weight(parent)
+        .map_err(|err| BlockValidationError::Weight(err.to_string()))?;
+    if header.parent_weight != expected {
+        return Err(BlockValidationError::ParentWeight {
+            expected,
+            found: header.parent_weight.clone(),
+        });
+    }
+
+    verify_beacon(header, parent, verifier)
+        .map_err(|err| BlockValidationError::Beacon(err.to_string()))?;
+    verifier
+        .verify_ticket(header, parent)
+        .map_err(|err| BlockValidationError::Ticket(err.to_string()))?;
+    verifier
+        .verify_block_signature(header)
+        .map_err(|err| BlockValidationError::Signature(err.to_string()))?;
+    verifier
+        .verify_winning_post(header, parent)
+        .map_err(|err| BlockValidationError::WinningPoSt(err.to_string()))?;
+    Ok(())
+}
+
+// The entries chain from the latest entry of the parent tipset, or from the first entry
+// of the block when the parent has none.
+fn verify_beacon<B: Beacon>(header: &BlockHeader, parent: &Tipset, beacon: &B) -> Result<()> {
+    let prev = parent
+        .blocks()
+        .iter()
+        .filter_map(|block| block.beacon_entries.last())
+        .max_by_key(|entry| entry.round);
+    match (prev, header.beacon_entries.split_first()) {
+        (Some(prev), _) => verify_beacon_entries(header, prev, beacon),
+        (None, Some((first, rest))) => verify_entries(rest, first, beacon),
+        (None, None) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::ensure;
+    use ipfs_datastore::MapDataStore;
+    use ipld::IpldValue;
+    use plum_address::Address;
+    use plum_block::{BeaconEntry, ElectionProof, Ticket};
+    use plum_crypto::Signature;
+    use plum_message::UnsignedMessage;
+
+    use super::*;
+
+    // Accept the blocks whose proofs are the `good` markers, the weight is the parent height.
+    struct MockVerifier;
+
+    impl Beacon for MockVerifier {
+        fn verify_entry(&self, curr: &BeaconEntry, _prev: &BeaconEntry) -> Result<()> {
+            ensure!(curr.data == b"good beacon", "bad beacon signature");
+            Ok(())
+        }
+    }
+
+    impl BlockVerifier for MockVerifier {
+        fn weight(&self, tipset: &Tipset) -> Result<BigInt> {
+            Ok(BigInt::from(tipset.height()))
+        }
+
+        fn verify_ticket(&self, header: &BlockHeader, _parent: &Tipset) -> Result<()> {
+            ensure!(header.ticket.vrf_proof == b"good ticket", "bad ticket");
+            Ok(())
+        }
+
+        fn verify_block_signature(&self, header: &BlockHeader) -> Result<()> {
+            ensure!(header.block_sig.as_bytes() == b"good sig", "bad signature");
+            Ok(())
+        }
+
+        fn verify_winning_post(&self, header: &BlockHeader, _parent: &Tipset) -> Result<()> {
+            ensure!(
+                header.election_proof.vrf_proof == b"good proof",
+                "bad proof"
+            );
+            Ok(())
+        }
+    }
+
+    fn new_msg() -> UnsignedMessage {
+        UnsignedMessage {
+            version: 0,
+            to: Address::new_id_addr(100).unwrap(),
+            from: Address::new_id_addr(1000).unwrap(),
+            nonce: 0,
+            value: BigInt::from(0),
+            gas_price: BigInt::from(0),
+            gas_limit: BigInt::from(0),
+            method: 0,
+            params: vec![],
+        }
+    }
+
+    fn header(height: ChainEpoch, state: Cid) -> BlockHeader {
+        BlockHeader {
+            miner: Address::new_id_addr(1000).unwrap(),
+            ticket: Ticket {
+                vrf_proof: b"good ticket".to_vec(),
+            },
+            election_proof: ElectionProof {
+                vrf_proof: b"good proof".to_vec(),
+            },
+            beacon_entries: vec![BeaconEntry::new(height as u64, b"good beacon".to_vec())],
+            win_post_proof: vec![],
+            parents: vec![],
+            parent_message_receipts: state.clone(),
+            bls_aggregate: Signature::new_bls("boo! im a signature"),
+            parent_weight: 0u64.into(),
+            messages: state.clone(),
+            height,
+            parent_state_root: state,
+            timestamp: 1000,
+            block_sig: Signature::new_bls("good sig"),
+            fork_signaling: 0u64,
+        }
+    }
+
+    #[test]
+    fn test_validate_block() {
+        let mut store = ChainStore::new(MapDataStore::new()).unwrap();
+        let state = store.put_object(10, IpldValue::Null).unwrap();
+        let parent = Tipset::new(vec![header(10, state.clone())]).unwrap();
+
+        let mut block = Block {
+            header: header(12, state),
+            bls_messages: vec![],
+            secpk_messages: vec![],
+        };
+        block.header.parents = parent.cids().to_vec();
+        block.header.timestamp = 1000 + 2 * BLOCK_DELAY;
+        block.header.parent_weight = 10u64.into();
+        block.header.messages = compute_messages_root(&block);
+        validate_block(&block, &parent, &store, &MockVerifier).unwrap();
+
+        // every malformed variant trips its own check.
+        type Malform = fn(&mut Block);
+        let cases: Vec<(Malform, fn(&BlockValidationError) -> bool)> = vec![
+            (
+                |block| block.header.parents.clear(),
+                |err| matches!(err, BlockValidationError::ParentMismatch),
+            ),
+            (
+                |block| block.header.height = 10,
+                |err| matches!(err, BlockValidationError::InvalidHeight { .. }),
+            ),
+            (
+                |block| block.header.timestamp += 1,
+                |err| matches!(err, BlockValidationError::InvalidTimestamp { .. }),
+            ),
+            (
+                |block| {
+                    let msg = new_msg();
+                    block.bls_messages = vec![msg; BLOCK_MESSAGE_LIMIT as usize + 1];
+                },
+                |err| matches!(err, BlockValidationError::TooManyMessages(513)),
+            ),
+            (
+                |block| block.header.messages = block.header.parent_message_receipts.clone(),
+                |err| matches!(err, BlockValidationError::MessagesRoot { .. }),
+            ),
+            (
+                |block| block.header.parent_state_root = block.header.messages.clone(),
+                |err| matches!(err, BlockValidationError::MissingParentState(_)),
+            ),
+            (
+                |block| block.header.parent_weight = 11u64.into(),
+                |err| matches!(err, BlockValidationError::ParentWeight { .. }),
+            ),
+            (
+                |block| block.header.beacon_entries[0].data = b"bad beacon".to_vec(),
+                |err| matches!(err, BlockValidationError::Beacon(_)),
+            ),
+            (
+                |block| block.header.ticket.vrf_proof = b"bad ticket".to_vec(),
+                |err| matches!(err, BlockValidationError::Ticket(_)),
+            ),
+            (
+                |block| block.header.block_sig = Signature::new_bls("bad sig"),
+                |err| matches!(err, BlockValidationError::Signature(_)),
+            ),
+            (
+                |block| block.header.election_proof.vrf_proof = b"bad proof".to_vec(),
+                |err| matches!(err, BlockValidationError::WinningPoSt(_)),
+            ),
+        ];
+        for (malform, expected) in cases {
+            let mut malformed = block.clone();
+            malform(&mut malformed);
+            let err = validate_block(&malformed, &parent, &store, &MockVerifier).unwrap_err();
+            assert!(expected(&err), "unexpected error: {}", err);
+        }
+
+        // the expected timestamp overflowing is an invalid timestamp, not a panic.
+        let mut late = header(10, block.header.parent_state_root.clone());
+        late.timestamp = u64::max_value() - BLOCK_DELAY;
+        let parent = Tipset::new(vec![late]).unwrap();
+        block.header.parents = parent.cids().to_vec();
+        let err = validate_block(&block, &parent, &store, &MockVerifier).unwrap_err();
+        assert!(matches!(
+            err,
+            BlockValidationError::InvalidTimestamp {
+                expected: std::u64::MAX,
+                ..
+            }
+        ));
+    }
+}
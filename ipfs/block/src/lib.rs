@@ -39,6 +39,20 @@ impl IpfsBlock {
         Self { cid, data }
     }
 
+    /// Create IPFS(IPLD) block from the binary data and its CID, the CID is not verified.
+    pub fn with_cid(cid: Cid, data: Vec<u8>) -> Self {
+        Self { cid, data }
+    }
+
+    /// Return whether the data hashes to the multihash of the CID.
+    pub fn verify(&self) -> bool {
+        let hash = self.cid.hash();
+        match hash.algorithm().hasher() {
+            Some(hasher) => hasher.digest(&self.data).as_bytes() == hash.as_bytes(),
+            None => false,
+        }
+    }
+
     /// Return the Cid of the IPFS(IPLD) block.
     pub fn cid(&self) -> &Cid {
         &self.cid
@@ -65,3 +79,16 @@ impl Block for IpfsBlock {
         self.cid()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let block = IpfsBlock::new("hello");
+        assert!(block.verify());
+        let forged = IpfsBlock::with_cid(block.cid().clone(), b"world".to_vec());
+        assert!(!forged.verify());
+    }
+}
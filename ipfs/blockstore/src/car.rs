@@ -0,0 +1,492 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use cid::{Cid, Codec};
+use minicbor::{decode, encode, Decoder, Encoder};
+
+use ipfs_block::{Block, IpfsBlock};
+use ipld::IpldValue;
+
+use crate::{BlockStore, BlockStoreError};
+
+/// The version of the supported CAR format.
+const CAR_VERSION: u64 = 1;
+/// The maximum length of a header or section, protects against allocating for bogus lengths.
+const MAX_SECTION_SIZE: u64 = 32 << 20;
+/// The number of the blocks queued per worker by the parallel import, which bounds the memory.
+const IMPORT_QUEUE_DEPTH: usize = 4;
+
+/// The error type used for CAR import and export.
+#[doc(hidden)]
+#[derive(Debug, thiserror::Error)]
+pub enum CarError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid CAR header: {0}")]
+    InvalidHeader(String),
+    #[error("invalid CAR section length: {0}")]
+    InvalidLength(u64),
+    #[error("invalid CID in CAR section: {0}")]
+    InvalidCid(String),
+    #[error("block '{0}' doesn't match its hash")]
+    HashMismatch(Cid),
+    #[error("block store error: {0}")]
+    BlockStore(#[from] BlockStoreError),
+}
+
+/// The header of a CAR (Content Addressable aRchive) v1.
+///
+/// See [CAR](https://github.com/ipld/specs/blob/master/block-layer/content-addressable-archives.md) for details.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CarHeader {
+    /// The roots of the DAGs in the archive.
+    pub roots: Vec<Cid>,
+    /// The version of the archive.
+    pub version: u64,
+}
+
+impl minicbor::Encode for CarHeader {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        // the keys are sorted in the canonical DAG-CBOR order.
+        e.map(2)?.str("roots")?.array(self.roots.len() as u64)?;
+        for root in &self.roots {
+            e.encode(root)?;
+        }
+        e.str("version")?.u64(self.version)?.ok()
+    }
+}
+
+impl<'b> decode::Decode<'b> for CarHeader {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        let map_len = d.map()?;
+        if map_len != Some(2) {
+            return Err(decode::Error::Message("expected a map of 2 entries"));
+        }
+        let mut roots = None;
+        let mut version = None;
+        for _ in 0..2 {
+            match d.str()? {
+                "roots" => {
+                    let array_len = d
+                        .array()?
+                        .ok_or(decode::Error::Message("expected a definite array"))?;
+                    roots = Some(
+                        (0..array_len)
+                            .map(|_| d.decode::<Cid>())
+                            .collect::<Result<Vec<_>, _>>()?,
+                    );
+                }
+                "version" => version = Some(d.u64()?),
+                _ => return Err(decode::Error::Message("unexpected key")),
+            }
+        }
+        Ok(CarHeader {
+            roots: roots.ok_or(decode::Error::Message("missing roots"))?,
+            version: version.ok_or(decode::Error::Message("missing version"))?,
+        })
+    }
+}
+
+/// Import the blocks of the CAR from the `reader` into the block store,
+/// return the header of the CAR.
+///
+/// Every block is verified to hash to its CID before storing.
+pub fn import_car<BS, R>(store: &mut BS, mut reader: R) -> Result<CarHeader, CarError>
+where
+    BS: BlockStore,
+    R: Read,
+{
+    let header = read_header(&mut reader)?;
+    while let Some(section) = read_section(&mut reader)? {
+        let block = read_block(&section)?;
+        if !block.verify() {
+            return Err(CarError::HashMismatch(block.cid().clone()));
+        }
+        store.put(block)?;
+    }
+    Ok(header)
+}
+
+/// Import the blocks of the CAR like `import_car`, but verify the blocks by a pool of
+/// `threads` workers.
+///
+/// The sections are read sequentially and at most `IMPORT_QUEUE_DEPTH` blocks per worker
+/// are queued, so the CAR isn't read into memory at once. A block is stored once it's
+/// verified, in no particular order, and the first mismatched block aborts the import.
+pub fn import_car_parallel<BS, R>(
+    store: &mut BS,
+    mut reader: R,
+    threads: usize,
+) -> Result<CarHeader, CarError>
+where
+    BS: BlockStore,
+    R: Read,
+{
+    let header = read_header(&mut reader)?;
+
+    let threads = threads.max(1);
+    let (jobs, queue) = mpsc::sync_channel::<IpfsBlock>(threads * IMPORT_QUEUE_DEPTH);
+    let queue = Arc::new(Mutex::new(queue));
+    let (verified, results) = mpsc::channel();
+    let workers = (0..threads)
+        .map(|_| {
+            let queue = queue.clone();
+            let verified = verified.clone();
+            thread::spawn(move || loop {
+                let block = match queue.lock().expect("lock shouldn't be poisoned").recv() {
+                    Ok(block) => block,
+                    // all the blocks are read or the import is aborted.
+                    Err(_) => break,
+                };
+                let result = if block.verify() {
+                    Ok(block)
+                } else {
+                    Err(CarError::HashMismatch(block.cid().clone()))
+                };
+                if verified.send(result).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(verified);
+
+    let result = feed_blocks(store, &mut reader, jobs, &results);
+    drop(results);
+    for worker in workers {
+        worker.join().expect("the worker shouldn't panic");
+    }
+    result.map(|_| header)
+}
+
+// Send the blocks read from the `reader` to the workers and store the verified blocks,
+// until the end of input or the first error.
+fn feed_blocks<BS, R>(
+    store: &mut BS,
+    reader: &mut R,
+    jobs: SyncSender<IpfsBlock>,
+    results: &Receiver<Result<IpfsBlock, CarError>>,
+) -> Result<(), CarError>
+where
+    BS: BlockStore,
+    R: Read,
+{
+    while let Some(section) = read_section(reader)? {
+        // blocks while the queue is full.
+        jobs.send(read_block(&section)?)
+            .expect("the workers outlive the queue; qed");
+        for result in results.try_iter() {
+            store.put(result?)?;
+        }
+    }
+    // the workers stop once the queue is drained.
+    drop(jobs);
+    for result in results.iter() {
+        store.put(result?)?;
+    }
+    Ok(())
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<CarHeader, CarError> {
+    let header =
+        read_section(reader)?.ok_or_else(|| CarError::InvalidHeader("empty input".into()))?;
+    let header = minicbor::decode::<CarHeader>(&header)
+        .map_err(|err| CarError::InvalidHeader(err.to_string()))?;
+    if header.version != CAR_VERSION {
+        return Err(CarError::InvalidHeader(format!(
+            "unsupported version {}",
+            header.version
+        )));
+    }
+    Ok(header)
+}
+
+// Split the section into the CID and the data of the block, which isn't verified yet.
+fn read_block(section: &[u8]) -> Result<IpfsBlock, CarError> {
+    let cid_len = cid_len(section)?;
+    let cid =
+        Cid::try_from(&section[..cid_len]).map_err(|err| CarError::InvalidCid(err.to_string()))?;
+    Ok(IpfsBlock::with_cid(cid, section[cid_len..].to_vec()))
+}
+
+/// Export the DAGs from the `roots` in the block store as a CAR into the `writer`.
+///
+/// The blocks are written in depth-first order, and each block is written once.
+pub fn export_car<BS, W>(store: &BS, roots: &[Cid], mut writer: W) -> Result<(), CarError>
+where
+    BS: BlockStore,
+    W: Write,
+{
+    let header = CarHeader {
+        roots: roots.to_vec(),
+        version: CAR_VERSION,
+    };
+    let header =
+        minicbor::to_vec(&header).expect("CBOR serialization of CarHeader shouldn't be failed");
+    write_section(&mut writer, &[&header])?;
+
+    let mut seen = HashSet::new();
+    let mut stack = roots.iter().rev().cloned().collect::<Vec<_>>();
+    while let Some(cid) = stack.pop() {
+        if !seen.insert(cid.clone()) {
+            continue;
+        }
+        let block = store.get(&cid)?;
+        write_section(&mut writer, &[&cid.to_bytes(), block.data()])?;
+
+        let mut links = links_of(&block)?;
+        links.reverse();
+        stack.extend(links);
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Return the links of the DAG-CBOR block in order, other codecs have no link.
+fn links_of(block: &IpfsBlock) -> Result<Vec<Cid>, CarError> {
+    fn collect(value: IpldValue, links: &mut Vec<Cid>) {
+        match value {
+            IpldValue::Link(cid) => links.push(cid),
+            IpldValue::List(list) => list.into_iter().for_each(|v| collect(v, links)),
+            IpldValue::Map(map) => map.into_iter().for_each(|(_, v)| collect(v, links)),
+            _ => {}
+        }
+    }
+
+    let mut links = Vec::new();
+    if block.cid().codec() == Codec::DagCBOR {
+        let value = minicbor::decode::<IpldValue>(block.data())
+            .map_err(|err| BlockStoreError::Custom(format!("{}: {}", block.cid(), err)))?;
+        collect(value, &mut links);
+    }
+    Ok(links)
+}
+
+// Read a varint length-prefixed section, return `None` at the end of input.
+fn read_section<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, CarError> {
+    let len = match read_uvarint(reader)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+    if len == 0 || len > MAX_SECTION_SIZE {
+        return Err(CarError::InvalidLength(len));
+    }
+    let mut section = vec![0u8; len as usize];
+    reader.read_exact(&mut section)?;
+    Ok(Some(section))
+}
+
+fn write_section<W: Write>(writer: &mut W, parts: &[&[u8]]) -> Result<(), CarError> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    writer.write_all(&encode_uvarint(len as u64))?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    Ok(())
+}
+
+fn read_uvarint<R: Read>(reader: &mut R) -> Result<Option<u64>, CarError> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        value |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(CarError::InvalidLength(value))
+}
+
+fn encode_uvarint(mut value: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(10);
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+    buf
+}
+
+// Return the length of the CID at the start of the bytes.
+fn cid_len(bytes: &[u8]) -> Result<usize, CarError> {
+    let truncated = || CarError::InvalidCid("truncated CID".into());
+    // CIDv0 is a bare sha2-256 multihash.
+    if bytes.starts_with(&[0x12, 0x20]) {
+        return if bytes.len() >= 34 {
+            Ok(34)
+        } else {
+            Err(truncated())
+        };
+    }
+    // CIDv1: <version><codec><multihash code><digest size><digest>
+    let mut reader = bytes;
+    for _ in 0..3 {
+        read_uvarint(&mut reader)
+            .ok()
+            .flatten()
+            .ok_or_else(truncated)?;
+    }
+    let digest_len = read_uvarint(&mut reader)
+        .ok()
+        .flatten()
+        .ok_or_else(truncated)?;
+    // the digest size is untrusted, so a bogus one must not overflow the length.
+    let len = usize::try_from(digest_len)
+        .ok()
+        .and_then(|digest_len| (bytes.len() - reader.len()).checked_add(digest_len))
+        .ok_or_else(|| CarError::InvalidCid(format!("invalid digest size {}", digest_len)))?;
+    if len <= bytes.len() {
+        Ok(len)
+    } else {
+        Err(truncated())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryBlockStore;
+
+    // a small DAG: root -> [left, right], left -> [leaf], right -> [leaf]
+    fn dag(store: &mut MemoryBlockStore) -> (Cid, Vec<Cid>) {
+        let leaf = IpfsBlock::new(IpldValue::String("leaf".into()));
+        let left = IpfsBlock::new(IpldValue::List(vec![
+            IpldValue::String("left".into()),
+            IpldValue::Link(leaf.cid().clone()),
+        ]));
+        let right = IpfsBlock::new(IpldValue::List(vec![
+            IpldValue::String("right".into()),
+            IpldValue::Link(leaf.cid().clone()),
+        ]));
+        let root = IpfsBlock::new(IpldValue::List(vec![
+            IpldValue::Link(left.cid().clone()),
+            IpldValue::Link(right.cid().clone()),
+        ]));
+        let cids = vec![
+            root.cid().clone(),
+            left.cid().clone(),
+            leaf.cid().clone(),
+            right.cid().clone(),
+        ];
+        store.put_many(&[leaf, left, right, root.clone()]).unwrap();
+        (root.cid().clone(), cids)
+    }
+
+    #[test]
+    fn test_car_round_trip() {
+        let mut store = MemoryBlockStore::new();
+        let (root, cids) = dag(&mut store);
+        // a block not reachable from the root isn't exported.
+        store
+            .put(IpfsBlock::new(IpldValue::String("orphan".into())))
+            .unwrap();
+
+        let mut car = Vec::new();
+        export_car(&store, &[root.clone()], &mut car).unwrap();
+
+        let mut imported = MemoryBlockStore::new();
+        let header = import_car(&mut imported, car.as_slice()).unwrap();
+        assert_eq!(header.roots, vec![root]);
+        assert_eq!(header.version, CAR_VERSION);
+        assert_eq!(imported.len(), cids.len());
+        for cid in &cids {
+            assert_eq!(imported.get(cid).unwrap(), store.get(cid).unwrap());
+        }
+
+        // the blocks are written in depth-first order.
+        let mut reader = car.as_slice();
+        read_section(&mut reader).unwrap();
+        let mut order = Vec::new();
+        while let Some(section) = read_section(&mut reader).unwrap() {
+            order.push(Cid::try_from(&section[..cid_len(&section).unwrap()]).unwrap());
+        }
+        assert_eq!(order, cids);
+    }
+
+    #[test]
+    fn test_import_malformed_car() {
+        let mut store = MemoryBlockStore::new();
+        let (root, _) = dag(&mut store);
+        let mut car = Vec::new();
+        export_car(&store, &[root], &mut car).unwrap();
+
+        // truncated section
+        let result = import_car(&mut MemoryBlockStore::new(), &car[..car.len() - 1]);
+        assert!(matches!(result, Err(CarError::Io(_))));
+
+        // bogus length
+        let mut bogus = car.clone();
+        bogus.extend_from_slice(&encode_uvarint(MAX_SECTION_SIZE + 1));
+        let result = import_car(&mut MemoryBlockStore::new(), bogus.as_slice());
+        assert!(matches!(result, Err(CarError::InvalidLength(_))));
+
+        // corrupted block data
+        let mut corrupted = car.clone();
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        let result = import_car(&mut MemoryBlockStore::new(), corrupted.as_slice());
+        assert!(matches!(result, Err(CarError::HashMismatch(_))));
+
+        // huge digest size in the CID
+        let mut section = vec![0x01, 0x71, 0x12];
+        section.extend_from_slice(&encode_uvarint(u64::max_value()));
+        section.extend_from_slice(b"data");
+        let mut huge = car.clone();
+        huge.extend_from_slice(&encode_uvarint(section.len() as u64));
+        huge.extend_from_slice(&section);
+        let result = import_car(&mut MemoryBlockStore::new(), huge.as_slice());
+        assert!(matches!(result, Err(CarError::InvalidCid(_))));
+
+        // invalid header
+        let result = import_car(&mut MemoryBlockStore::new(), &[0x01, 0xff][..]);
+        assert!(matches!(result, Err(CarError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_import_car_parallel() {
+        let blocks = (0..64)
+            .map(|i| IpfsBlock::new(IpldValue::String(format!("block {}", i))))
+            .collect::<Vec<_>>();
+        let car = |blocks: &[IpfsBlock]| {
+            let header = CarHeader {
+                roots: vec![blocks[0].cid().clone()],
+                version: CAR_VERSION,
+            };
+            let mut car = Vec::new();
+            write_section(&mut car, &[&minicbor::to_vec(&header).unwrap()]).unwrap();
+            for block in blocks {
+                write_section(&mut car, &[&block.cid().to_bytes(), block.data()]).unwrap();
+            }
+            car
+        };
+
+        let mut store = MemoryBlockStore::new();
+        let header = import_car_parallel(&mut store, car(&blocks).as_slice(), 4).unwrap();
+        assert_eq!(header.roots, vec![blocks[0].cid().clone()]);
+        assert_eq!(store.len(), blocks.len());
+        for block in &blocks {
+            assert_eq!(&store.get(block.cid()).unwrap(), block);
+        }
+
+        // a block in the middle doesn't match its hash.
+        let mut corrupted = blocks.clone();
+        corrupted[40] = IpfsBlock::with_cid(blocks[40].cid().clone(), blocks[41].data().to_vec());
+        let result =
+            import_car_parallel(&mut MemoryBlockStore::new(), car(&corrupted).as_slice(), 4);
+        match result {
+            Err(CarError::HashMismatch(cid)) => assert_eq!(&cid, blocks[40].cid()),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
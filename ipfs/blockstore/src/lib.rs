@@ -4,9 +4,15 @@
 
 #![deny(missing_docs)]
 
+mod car;
+mod memory;
+
+pub use self::car::{export_car, import_car, import_car_parallel, CarError, CarHeader};
+pub use self::memory::MemoryBlockStore;
+
 use cid::Cid;
 
-use ipfs_block::Block;
+use ipfs_block::{Block, IpfsBlock};
 
 /// The error type used for block store.
 #[doc(hidden)]
@@ -14,6 +20,8 @@ use ipfs_block::Block;
 pub enum BlockStoreError {
     #[error("block '{0}' not found")]
     NotFound(Cid),
+    #[error("block '{0}' doesn't match its hash")]
+    HashMismatch(Cid),
     #[error("{0}")]
     Custom(String),
 }
@@ -37,7 +45,7 @@ pub trait BlockStore {
     fn has(&self, cid: &Cid) -> Result<bool>;
 
     /// Retrieve the `block` named by `cid`.
-    fn get<B: Block>(&self, cid: &Cid) -> Result<B>;
+    fn get(&self, cid: &Cid) -> Result<IpfsBlock>;
 
     /// Return the CIDs mapped BlockSize.
     fn get_size(&self, cid: &Cid) -> Result<usize>;
@@ -52,3 +60,33 @@ pub trait BlockStore {
     /// Specifies if every read block should be rehashed to make sure it matches its CID.
     fn hash_on_read(&mut self, enabled: bool);
 }
+
+impl<BS: BlockStore> BlockStore for &mut BS {
+    fn delete_block(&mut self, cid: &Cid) -> Result<()> {
+        (**self).delete_block(cid)
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        (**self).has(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> Result<IpfsBlock> {
+        (**self).get(cid)
+    }
+
+    fn get_size(&self, cid: &Cid) -> Result<usize> {
+        (**self).get_size(cid)
+    }
+
+    fn put<B: Block>(&mut self, block: B) -> Result<()> {
+        (**self).put(block)
+    }
+
+    fn put_many<B: Block>(&mut self, blocks: &[B]) -> Result<()> {
+        (**self).put_many(blocks)
+    }
+
+    fn hash_on_read(&mut self, enabled: bool) {
+        (**self).hash_on_read(enabled)
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::collections::HashMap;
+
+use cid::Cid;
+
+use ipfs_block::{Block, IpfsBlock};
+
+use crate::{BlockStore, BlockStoreError, Result};
+
+/// A block store living in memory, which is generally intended for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBlockStore {
+    blocks: HashMap<Cid, Vec<u8>>,
+    hash_on_read: bool,
+}
+
+impl MemoryBlockStore {
+    /// Create a new empty MemoryBlockStore.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of blocks in the block store.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Return whether the block store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+impl BlockStore for MemoryBlockStore {
+    fn delete_block(&mut self, cid: &Cid) -> Result<()> {
+        self.blocks.remove(cid);
+        Ok(())
+    }
+
+    fn has(&self, cid: &Cid) -> Result<bool> {
+        Ok(self.blocks.contains_key(cid))
+    }
+
+    fn get(&self, cid: &Cid) -> Result<IpfsBlock> {
+        let data = self
+            .blocks
+            .get(cid)
+            .ok_or_else(|| BlockStoreError::NotFound(cid.clone()))?;
+        let block = IpfsBlock::with_cid(cid.clone(), data.clone());
+        if self.hash_on_read && !block.verify() {
+            return Err(BlockStoreError::HashMismatch(cid.clone()));
+        }
+        Ok(block)
+    }
+
+    fn get_size(&self, cid: &Cid) -> Result<usize> {
+        self.blocks
+            .get(cid)
+            .map(|data| data.len())
+            .ok_or_else(|| BlockStoreError::NotFound(cid.clone()))
+    }
+
+    fn put<B: Block>(&mut self, block: B) -> Result<()> {
+        self.blocks
+            .insert(block.cid().clone(), block.data().to_vec());
+        Ok(())
+    }
+
+    fn put_many<B: Block>(&mut self, blocks: &[B]) -> Result<()> {
+        for block in blocks {
+            self.blocks
+                .insert(block.cid().clone(), block.data().to_vec());
+        }
+        Ok(())
+    }
+
+    fn hash_on_read(&mut self, enabled: bool) {
+        self.hash_on_read = enabled;
+    }
+}
@@ -6,7 +6,7 @@
 
 use std::borrow::Borrow;
 
-use ipfs_datastore::{DataStore, DataStoreRead, DataStoreWrite};
+use ipfs_datastore::{DataStore, DataStoreRead, DataStoreWrite, ToRange, ToStream};
 use ipfs_datastore::{DataStoreError, Key, MapDataStore, SyncDataStore};
 
 pub(crate) type Result<T> = std::result::Result<T, DataStoreError>;
@@ -51,6 +51,22 @@ impl DataStoreRead for MemoryDataStore {
     {
         self.datastore.size(key)
     }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.datastore.read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        self.datastore.count(prefix)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        self.datastore.has_prefix(prefix)
+    }
 }
 
 impl DataStoreWrite for MemoryDataStore {
@@ -68,4 +84,64 @@ impl DataStoreWrite for MemoryDataStore {
     {
         self.datastore.delete(key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        self.datastore.increment(key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl ToRange for MemoryDataStore {
+    type Range = <SyncDataStore<MapDataStore> as ToRange>::Range;
+
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.range(start, end)
+    }
+}
+
+impl ToStream for MemoryDataStore {
+    type Stream = <SyncDataStore<MapDataStore> as ToStream>::Stream;
+
+    fn async_stream(&self) -> Self::Stream {
+        self.datastore.async_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_increment() {
+        let store = MemoryDataStore {
+            datastore: SyncDataStore::new(MapDataStore::new()),
+        };
+        let key = Key::new("/counter");
+        let handles = (0..8)
+            .map(|_| {
+                let (mut store, key) = (store.clone(), key.clone());
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.increment(&key, 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get(&key).unwrap(), 800i64.to_be_bytes().to_vec());
+    }
 }
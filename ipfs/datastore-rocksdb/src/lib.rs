@@ -13,26 +13,52 @@ pub use self::rocks::{
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::thread;
+use std::vec;
+
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::SinkExt;
 
 use ipfs_datastore::{
-    DataStore, DataStoreBatch, DataStoreError, DataStoreRead, DataStoreTxn, DataStoreWrite, Key,
-    ToBatch, ToTxn,
+    add_counter, DataStore, DataStoreBatch, DataStoreError, DataStoreRead, DataStoreTxn,
+    DataStoreWrite, Entry, Key, Persistent, ToBatch, ToRange, ToStream, ToTxn, WriteLock,
 };
 
 pub(crate) type Result<T> = std::result::Result<T, DataStoreError>;
 
+// The number of entries buffered between the scanning thread and the stream.
+const STREAM_BUFFER_SIZE: usize = 64;
+
 /// RocksDBDataStore is a datastore with RocksDB as backend.
+///
+/// The writes of the handles sharing the database take its `WriteLock`, so that the
+/// read-modify-write of `increment` is atomic.
 #[derive(Clone)]
 pub struct RocksDBDataStore {
     db: Arc<Database>,
+    write_lock: WriteLock,
 }
 
 impl RocksDBDataStore {
     /// Create a new rocksdb data store.
     pub fn new(config: &DatabaseConfig, path: &str) -> Result<Self> {
         let db = Database::open(config, path)?;
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            db: Arc::new(db),
+            write_lock: WriteLock::new(),
+        })
+    }
+
+    /// Open the rocksdb data store at `path` with the default config, it's created
+    /// if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_str().ok_or_else(|| {
+            DataStoreError::Custom(format!("invalid path: {}", path.as_ref().display()))
+        })?;
+        Self::new(&DatabaseConfig::default(), path)
     }
 
     /// Get the rocksdb handle.
@@ -121,6 +147,20 @@ impl DataStoreRead for RocksDBDataStore {
             .ok_or_else(|| DataStoreError::NotFound(key.to_string()))
             .map(|value| value.len())
     }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let cols = keys.iter().map(key_column).collect::<Vec<_>>();
+        let reads = cols
+            .iter()
+            .zip(keys)
+            .map(|(col, key)| (col.as_str(), key.as_bytes()))
+            .collect::<Vec<_>>();
+        Ok(self.db.get_snapshot(&reads)?)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        prefix_keys(&self.db, prefix)
+    }
 }
 
 impl DataStoreWrite for RocksDBDataStore {
@@ -135,7 +175,7 @@ impl DataStoreWrite for RocksDBDataStore {
 
         let mut txn = self.db.transaction();
         txn.put(&col, key.as_bytes(), value);
-        self.db.write(&txn)?;
+        self.write_lock.write(&key, || self.db.write(&txn))?;
         Ok(())
     }
 
@@ -148,9 +188,39 @@ impl DataStoreWrite for RocksDBDataStore {
 
         let mut txn = self.db.transaction();
         txn.delete(&col, key.as_bytes());
-        self.db.write(&txn)?;
+        self.write_lock.write(key, || self.db.write(&txn))?;
         Ok(())
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // hold the write lock across the read-modify-write.
+        let key = key.borrow();
+        let col = key_column(key);
+        self.write_lock.write(key, || {
+            let current = self.db.get(&col, key.as_bytes())?;
+            let value = add_counter(key, current.as_deref(), delta)?;
+            let mut txn = self.db.transaction();
+            txn.put(&col, key.as_bytes(), value.to_be_bytes().to_vec());
+            self.db.write(&txn)?;
+            Ok(value)
+        })
+    }
+
+    // The writes are only in the WAL and the memtables before the flush.
+    fn fence(&mut self) -> Result<()> {
+        Ok(self.db.flush()?)
+    }
+}
+
+// The live data only, the WAL and the memtables which aren't flushed yet are excluded.
+impl Persistent for RocksDBDataStore {
+    fn disk_usage(&self) -> Result<u64> {
+        Ok(self.db.total_sst_files_size()?)
+    }
 }
 
 impl ToBatch for RocksDBDataStore {
@@ -159,7 +229,11 @@ impl ToBatch for RocksDBDataStore {
     fn batch(&self) -> Result<Self::Batch> {
         let db = self.db.clone();
         let txn = db.transaction();
-        Ok(RocksDBBatchDataStore { db, txn })
+        Ok(RocksDBBatchDataStore {
+            db,
+            txn,
+            write_lock: self.write_lock.clone(),
+        })
     }
 }
 
@@ -169,7 +243,62 @@ impl ToTxn for RocksDBDataStore {
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         let db = self.db.clone();
         let txn = db.transaction();
-        Ok(RocksDBTxnDataStore { db, txn })
+        Ok(RocksDBTxnDataStore {
+            db,
+            txn,
+            write_lock: self.write_lock.clone(),
+        })
+    }
+}
+
+impl ToStream for RocksDBDataStore {
+    type Stream = mpsc::Receiver<Result<Entry>>;
+
+    fn async_stream(&self) -> Self::Stream {
+        let (mut tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let db = self.db.clone();
+        // drive the cursor on a blocking thread, which is parked while the channel is full.
+        thread::spawn(move || {
+            let res = db.for_each(DEFAULT_COLUMN_NAME, |key, value| {
+                let entry = std::str::from_utf8(key)
+                    .map(|key| Entry::new(Key::new(key), value.to_vec()))
+                    .map_err(|_| DataStoreError::Corruption("invalid utf-8 key".into()));
+                // stop the iteration once the stream is dropped.
+                block_on(tx.send(entry)).is_ok()
+            });
+            if let Err(err) = res {
+                let _ = block_on(tx.send(Err(err.into())));
+            }
+        });
+        rx
+    }
+}
+
+impl ToRange for RocksDBDataStore {
+    type Range = vec::IntoIter<Result<Entry>>;
+
+    // The native cursor orders the keys by their bytes.
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        let (start, end) = (start.borrow().as_bytes(), end.borrow().as_bytes());
+        let mut entries = Vec::new();
+        if start >= end {
+            return Ok(entries.into_iter());
+        }
+        self.db
+            .for_each_from(DEFAULT_COLUMN_NAME, start, |key, value| {
+                if key >= end {
+                    return false;
+                }
+                let entry = std::str::from_utf8(key)
+                    .map(|key| Entry::new(Key::new(key), value.to_vec()))
+                    .map_err(|_| DataStoreError::Corruption("invalid utf-8 key".into()));
+                entries.push(entry);
+                true
+            })?;
+        Ok(entries.into_iter())
     }
 }
 
@@ -180,6 +309,7 @@ impl ToTxn for RocksDBDataStore {
 pub struct RocksDBBatchDataStore {
     db: Arc<Database>,
     txn: DBTransaction,
+    write_lock: WriteLock,
 }
 
 impl RocksDBBatchDataStore {
@@ -190,6 +320,7 @@ impl RocksDBBatchDataStore {
         Ok(Self {
             db: Arc::new(db),
             txn,
+            write_lock: WriteLock::new(),
         })
     }
 
@@ -234,6 +365,10 @@ impl DataStoreRead for RocksDBBatchDataStore {
             .ok_or_else(|| DataStoreError::NotFound(key.to_string()))
             .map(|value| value.len())
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        prefix_keys(&self.db, prefix)
+    }
 }
 
 impl DataStoreWrite for RocksDBBatchDataStore {
@@ -264,7 +399,7 @@ impl DataStoreWrite for RocksDBBatchDataStore {
 
 impl DataStoreBatch for RocksDBBatchDataStore {
     fn commit(&mut self) -> Result<()> {
-        self.db.write(&self.txn)?;
+        self.write_lock.write_all(|| self.db.write(&self.txn))?;
         self.txn.clear();
         Ok(())
     }
@@ -277,6 +412,7 @@ impl ToTxn for RocksDBBatchDataStore {
         Ok(RocksDBTxnDataStore {
             db: self.db.clone(),
             txn: self.txn.clone(),
+            write_lock: self.write_lock.clone(),
         })
     }
 }
@@ -288,6 +424,7 @@ impl ToTxn for RocksDBBatchDataStore {
 pub struct RocksDBTxnDataStore {
     db: Arc<Database>,
     txn: DBTransaction,
+    write_lock: WriteLock,
 }
 
 impl RocksDBTxnDataStore {
@@ -298,6 +435,7 @@ impl RocksDBTxnDataStore {
         Ok(Self {
             db: Arc::new(db),
             txn,
+            write_lock: WriteLock::new(),
         })
     }
 
@@ -342,6 +480,10 @@ impl DataStoreRead for RocksDBTxnDataStore {
             .ok_or_else(|| DataStoreError::NotFound(key.to_string()))
             .map(|value| value.len())
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        prefix_keys(&self.db, prefix)
+    }
 }
 
 impl DataStoreWrite for RocksDBTxnDataStore {
@@ -372,7 +514,7 @@ impl DataStoreWrite for RocksDBTxnDataStore {
 
 impl DataStoreBatch for RocksDBTxnDataStore {
     fn commit(&mut self) -> Result<()> {
-        self.db.write(&self.txn)?;
+        self.write_lock.write_all(|| self.db.write(&self.txn))?;
         self.txn.clear();
         Ok(())
     }
@@ -390,3 +532,186 @@ impl DataStoreTxn for RocksDBTxnDataStore {
 fn key_column(_key: &Key) -> String {
     DEFAULT_COLUMN_NAME.to_string()
 }
+
+// List the keys equal to or under the `prefix`, the keys sharing the prefix bytes are
+// contiguous in the native cursor, so the scan starts from the prefix and stops after them.
+fn prefix_keys(db: &Database, prefix: &Key) -> Result<Vec<Key>> {
+    let start = if prefix.is_root() {
+        &[][..]
+    } else {
+        prefix.as_bytes()
+    };
+    let mut keys = Vec::new();
+    let mut invalid = false;
+    db.for_each_from(DEFAULT_COLUMN_NAME, start, |key, _| {
+        if !key.starts_with(start) {
+            return false;
+        }
+        // skip the siblings sharing the prefix bytes, e.g. `/ab` of the prefix `/a`.
+        if key.len() > start.len() && key[start.len()] != b'/' {
+            return true;
+        }
+        match std::str::from_utf8(key) {
+            Ok(key) => keys.push(Key::new(key)),
+            Err(_) => invalid = true,
+        }
+        !invalid
+    })?;
+    if invalid {
+        return Err(DataStoreError::Corruption("invalid utf-8 key".into()));
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+
+    use ipfs_datastore::PrefixScan;
+
+    use super::*;
+
+    #[test]
+    fn test_async_stream() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        let path = tempdir.path().to_str().unwrap();
+        let mut store = RocksDBDataStore::new(&DatabaseConfig::default(), path).unwrap();
+        let count = STREAM_BUFFER_SIZE * 3;
+        for i in 0..count {
+            store
+                .put(Key::new(format!("/{:04}", i)), i.to_be_bytes().to_vec())
+                .unwrap();
+        }
+
+        let mut stream = store.async_stream();
+        let mut seen = HashSet::new();
+        block_on(async {
+            while let Some(entry) = stream.next().await {
+                let entry = entry.unwrap();
+                assert!(seen.insert(entry.key), "entry yielded more than once");
+                // a slow consumer.
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        assert_eq!(seen.len(), count);
+    }
+
+    // reopens the database on disk, run with `--features integration-tests`.
+    #[cfg(feature = "integration-tests")]
+    #[test]
+    fn test_persistence() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        {
+            let mut store = RocksDBDataStore::open(tempdir.path()).unwrap();
+            store.put(Key::new("/c"), vec![3]).unwrap();
+            let mut batch = store.batch().unwrap();
+            for i in 0..10u8 {
+                batch
+                    .put(Key::new(format!("/{}", i)), vec![i; 100])
+                    .unwrap();
+            }
+            batch.delete(&Key::new("/c")).unwrap();
+            // nothing is written before the commit.
+            assert!(!store.has(&Key::new("/0")).unwrap());
+            batch.commit().unwrap();
+            store.close().unwrap();
+        }
+
+        let store = RocksDBDataStore::open(tempdir.path()).unwrap();
+        for i in 0..10u8 {
+            assert_eq!(
+                store.get(&Key::new(format!("/{}", i))).unwrap(),
+                vec![i; 100]
+            );
+        }
+        assert!(store.get(&Key::new("/c")).unwrap_err().is_not_found());
+        // the recovered writes may stay in the memtables after reopening.
+        store.db.flush().unwrap();
+        assert!(store.disk_usage().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_range() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        let path = tempdir.path().to_str().unwrap();
+        let mut store = RocksDBDataStore::new(&DatabaseConfig::default(), path).unwrap();
+        for i in (0..20u8).rev() {
+            store
+                .put(Key::new(format!("/height/{:02}", i)), vec![i])
+                .unwrap();
+        }
+        store.put(Key::new("/other/05"), vec![]).unwrap();
+
+        let entries = store
+            .range(&Key::new("/height/05"), &Key::new("/height/10"))
+            .unwrap()
+            .map(|entry| entry.unwrap().value[0])
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![5, 6, 7, 8, 9]);
+
+        let reversed = store.range(&Key::new("/height/10"), &Key::new("/height/05"));
+        assert_eq!(reversed.unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_keys() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        let mut store = RocksDBDataStore::open(tempdir.path()).unwrap();
+        for key in &["/a", "/a/2", "/a/1", "/a/b/3", "/ab/4", "/c/5"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+
+        let keys = store.keys(&Key::new("/a")).unwrap();
+        let expected = ["/a", "/a/1", "/a/2", "/a/b/3"];
+        assert_eq!(keys, expected.iter().map(Key::new).collect::<Vec<_>>());
+        assert_eq!(store.keys(&Key::new("/")).unwrap().len(), 6);
+        assert_eq!(store.count(&Key::new("/ab")).unwrap(), 1);
+        assert!(!store.has_prefix(&Key::new("/b")).unwrap());
+
+        assert_eq!(store.delete_prefix(&Key::new("/a")).unwrap(), 3);
+        assert_eq!(store.keys(&Key::new("/a")).unwrap(), vec![Key::new("/a")]);
+    }
+
+    #[test]
+    fn test_fence() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        let mut store = RocksDBDataStore::open(tempdir.path()).unwrap();
+        for i in 0..10u8 {
+            store
+                .put(Key::new(format!("/{}", i)), vec![i; 100])
+                .unwrap();
+        }
+        assert_eq!(store.disk_usage().unwrap(), 0);
+        // the fenced writes are in the SST files.
+        store.fence().unwrap();
+        assert!(store.disk_usage().unwrap() > 0);
+
+        store.close().unwrap();
+        assert!(store.fence().is_err());
+    }
+
+    #[test]
+    fn test_concurrent_increment() {
+        let tempdir = tempfile::Builder::new().prefix("").tempdir().unwrap();
+        let store = RocksDBDataStore::open(tempdir.path()).unwrap();
+        let key = Key::new("/counter");
+        let handles = (0..8)
+            .map(|_| {
+                let (mut store, key) = (store.clone(), key.clone());
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.increment(&key, 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get(&key).unwrap(), 800i64.to_be_bytes().to_vec());
+    }
+}
@@ -18,8 +18,8 @@ use std::path::Path;
 use log::warn;
 use parking_lot::RwLock;
 use rocksdb::{
-    BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, Error, Options, ReadOptions,
-    WriteBatch, WriteOptions, DB,
+    BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, Direction, Error, IteratorMode,
+    Options, ReadOptions, WriteBatch, WriteOptions, DB,
 };
 
 pub use self::compact::CompactionProfile;
@@ -298,6 +298,21 @@ impl Database {
         }
     }
 
+    /// Flush the memtables of all the columns into the SST files, so that the written data
+    /// is durable without replaying the WAL.
+    pub fn flush(&self) -> io::Result<()> {
+        match *self.db.read() {
+            Some(ref cfs) => {
+                for col in &cfs.column_names {
+                    let res = cfs.db.flush_cf(cfs.cf(col));
+                    check_for_corruption(&self.path, res)?;
+                }
+                Ok(())
+            }
+            None => Err(other_io_err("Database is closed")),
+        }
+    }
+
     /// Get value by key.
     pub fn get(&self, col: &str, key: &[u8]) -> io::Result<Option<DBValue>> {
         match *self.db.read() {
@@ -324,6 +339,78 @@ impl Database {
         }
     }
 
+    /// Get the values of the `(column, key)` pairs from a single snapshot of the database,
+    /// so that all the reads observe the same state.
+    pub fn get_snapshot(&self, reads: &[(&str, &[u8])]) -> io::Result<Vec<Option<DBValue>>> {
+        match *self.db.read() {
+            Some(ref cfs) => {
+                if let Some((col, _)) = reads
+                    .iter()
+                    .find(|(col, _)| !cfs.column_names.contains(*col))
+                {
+                    return Err(other_io_err(format!("non-existing column {}", col)));
+                }
+                let snapshot = cfs.db.snapshot();
+                self.stats.tally_reads(reads.len() as u64);
+                reads
+                    .iter()
+                    .map(|(col, key)| {
+                        let value = snapshot.get_cf(cfs.cf(col), *key).map_err(other_io_err)?;
+                        let len = value.as_ref().map_or(0, |value| value.len());
+                        self.stats.tally_bytes_read((key.len() + len) as u64);
+                        Ok(value)
+                    })
+                    .collect()
+            }
+            None => Ok(vec![None; reads.len()]),
+        }
+    }
+
+    /// Iterate over all the key-value pairs of the column in key order,
+    /// stop the iteration once `f` returns `false`.
+    ///
+    /// The database can't be closed until the iteration is finished.
+    pub fn for_each<F>(&self, col: &str, f: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        self.for_each_with_mode(col, IteratorMode::Start, f)
+    }
+
+    /// Iterate over the key-value pairs of the column in key order, starting at the first key
+    /// greater than or equal to `from`, stop the iteration once `f` returns `false`.
+    ///
+    /// The database can't be closed until the iteration is finished.
+    pub fn for_each_from<F>(&self, col: &str, from: &[u8], f: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        self.for_each_with_mode(col, IteratorMode::From(from, Direction::Forward), f)
+    }
+
+    fn for_each_with_mode<F>(&self, col: &str, mode: IteratorMode, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        match *self.db.read() {
+            Some(ref cfs) => {
+                if !cfs.column_names.contains(col) {
+                    return Err(other_io_err("non-existing column"));
+                }
+                for (key, value) in cfs.db.iterator_cf(cfs.cf(col), mode) {
+                    self.stats.tally_reads(1);
+                    self.stats
+                        .tally_bytes_read((key.len() + value.len()) as u64);
+                    if !f(&key, &value) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
     /// Close the database
     pub fn close(&self) {
         *self.db.write() = None;
@@ -360,6 +447,25 @@ impl Database {
         }
     }
 
+    /// The total size of the live SST files of all the columns, in bytes.
+    pub fn total_sst_files_size(&self) -> io::Result<u64> {
+        const TOTAL_SST_FILES_SIZE: &str = "rocksdb.total-sst-files-size";
+        match *self.db.read() {
+            Some(ref cfs) => {
+                let mut total = 0;
+                for col in &cfs.column_names {
+                    let cf = cfs.cf(col);
+                    match cfs.db.property_int_value_cf(cf, TOTAL_SST_FILES_SIZE) {
+                        Ok(size) => total += size.unwrap_or_default(),
+                        Err(err) => return Err(other_io_err(err)),
+                    }
+                }
+                Ok(total)
+            }
+            None => Ok(0),
+        }
+    }
+
     /// Add a new column family to the DB.
     pub fn add_column(&self, col: String) -> io::Result<()> {
         match *self.db.write() {
@@ -38,6 +38,42 @@ fn put_and_get() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn for_each_in_key_order() -> io::Result<()> {
+    let db = open_temp_db(vec!["0".into()])?;
+    let mut transaction = db.transaction();
+    transaction.put("0", b"key3", b"cat".to_vec());
+    transaction.put("0", b"key1", b"horse".to_vec());
+    transaction.put("0", b"key2", b"dog".to_vec());
+    db.write(&transaction)?;
+
+    let mut keys = Vec::new();
+    db.for_each("0", |key, _value| {
+        keys.push(key.to_vec());
+        true
+    })?;
+    assert_eq!(
+        keys,
+        vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec()]
+    );
+
+    let mut count = 0;
+    db.for_each("0", |_key, _value| {
+        count += 1;
+        false
+    })?;
+    assert_eq!(count, 1);
+    assert!(db.for_each("1", |_key, _value| true).is_err());
+
+    let mut keys = Vec::new();
+    db.for_each_from("0", b"key15", |key, _value| {
+        keys.push(key.to_vec());
+        true
+    })?;
+    assert_eq!(keys, vec![b"key2".to_vec(), b"key3".to_vec()]);
+    Ok(())
+}
+
 #[test]
 fn delete_and_get() -> io::Result<()> {
     let db = open_temp_db(vec!["0".into()])?;
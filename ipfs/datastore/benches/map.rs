@@ -0,0 +1,62 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use ipfs_datastore::{DataStore, Key};
+use ipfs_datastore::{MapDataStore, RcuMapDataStore, SyncDataStore};
+
+const ENTRIES: usize = 1024;
+const READS_PER_THREAD: usize = 10_000;
+
+fn populate<DS: DataStore>(mut store: DS) -> (DS, Vec<Key>) {
+    let keys = (0..ENTRIES)
+        .map(|i| Key::new(format!("/key/{}", i)))
+        .collect::<Vec<_>>();
+    for key in &keys {
+        store.put(key.clone(), vec![0u8; 32]).unwrap();
+    }
+    (store, keys)
+}
+
+// Every reader thread reads all the keys in turn until it completes its reads.
+fn concurrent_reads<DS: DataStore + Send + 'static>(store: &DS, keys: &[Key], readers: usize) {
+    let handles = (0..readers)
+        .map(|offset| {
+            let store = store.clone();
+            let keys = keys.to_vec();
+            thread::spawn(move || {
+                for i in 0..READS_PER_THREAD {
+                    let key = &keys[(offset + i) % keys.len()];
+                    criterion::black_box(store.get(key).unwrap());
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_concurrent_reads(c: &mut Criterion) {
+    let (mutex, keys) = populate(SyncDataStore::new(MapDataStore::new()));
+    let (rcu, _) = populate(RcuMapDataStore::new());
+
+    let mut group = c.benchmark_group("concurrent_reads");
+    for readers in [1, 4, 16].iter() {
+        group.throughput(Throughput::Elements((readers * READS_PER_THREAD) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("mutex", readers),
+            readers,
+            |b, &readers| b.iter(|| concurrent_reads(&mutex, &keys, readers)),
+        );
+        group.bench_with_input(BenchmarkId::new("rcu", readers), readers, |b, &readers| {
+            b.iter(|| concurrent_reads(&rcu, &keys, readers))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_concurrent_reads);
+criterion_main!(benches);
@@ -1,5 +1,9 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+use std::io::ErrorKind;
+
+use crate::key::Key;
+
 pub(crate) type Result<T> = std::result::Result<T, DataStoreError>;
 
 /// The error type used for data store.
@@ -8,13 +12,66 @@ pub(crate) type Result<T> = std::result::Result<T, DataStoreError>;
 pub enum DataStoreError {
     #[error("key '{0}' not found")]
     NotFound(String),
+    #[error("operation timed out: {0}")]
+    Timeout(String),
+    #[error("datastore disconnected: {0}")]
+    Disconnected(String),
+    #[error("data corruption: {0}")]
+    Corruption(String),
+    #[error("value of key '{key}' is corrupted")]
+    Corrupted { key: Key },
+    #[error("counter overflow: {0}")]
+    Overflow(String),
+    #[error("datastore is read-only: {0}")]
+    ReadOnly(String),
+    #[error("transaction conflict on key '{0}'")]
+    Conflict(String),
     #[error("{0}")]
     Custom(String),
 }
 
+impl DataStoreError {
+    /// Return whether the error is transient, like a timeout or a lost connection,
+    /// so that retrying the failed operation may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DataStoreError::Timeout(_) | DataStoreError::Disconnected(_) => true,
+            DataStoreError::NotFound(_)
+            | DataStoreError::Corruption(_)
+            | DataStoreError::Corrupted { .. }
+            | DataStoreError::Overflow(_)
+            | DataStoreError::ReadOnly(_)
+            | DataStoreError::Conflict(_)
+            | DataStoreError::Custom(_) => false,
+        }
+    }
+
+    /// Return whether retrying the failed operation may succeed.
+    #[deprecated(note = "use `is_transient` instead")]
+    pub fn is_retryable(&self) -> bool {
+        self.is_transient()
+    }
+
+    /// Return whether the error is caused by the missing key.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DataStoreError::NotFound(_))
+    }
+}
+
 impl From<std::io::Error> for DataStoreError {
     fn from(err: std::io::Error) -> Self {
-        DataStoreError::Custom(err.to_string())
+        match err.kind() {
+            ErrorKind::TimedOut => DataStoreError::Timeout(err.to_string()),
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe => DataStoreError::Disconnected(err.to_string()),
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                DataStoreError::Corruption(err.to_string())
+            }
+            _ => DataStoreError::Custom(err.to_string()),
+        }
     }
 }
 
@@ -23,3 +80,48 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for DataStoreError {
         DataStoreError::Custom(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::DataStoreError;
+    use crate::key::Key;
+
+    #[test]
+    fn test_classification() {
+        let cases = vec![
+            (DataStoreError::NotFound("/a".into()), false, true),
+            (DataStoreError::Timeout("get".into()), true, false),
+            (DataStoreError::Disconnected("get".into()), true, false),
+            (DataStoreError::Corruption("/a".into()), false, false),
+            (
+                DataStoreError::Corrupted {
+                    key: Key::new("/a"),
+                },
+                false,
+                false,
+            ),
+            (DataStoreError::Overflow("/a".into()), false, false),
+            (DataStoreError::ReadOnly("/a".into()), false, false),
+            (DataStoreError::Conflict("/a".into()), false, false),
+            (DataStoreError::Custom("custom".into()), false, false),
+        ];
+        for (err, transient, not_found) in cases {
+            assert_eq!(err.is_transient(), transient, "{:?}", err);
+            assert_eq!(err.is_not_found(), not_found, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_from_io_error() {
+        let err = DataStoreError::from(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+        assert!(matches!(err, DataStoreError::Timeout(_)));
+        let err = DataStoreError::from(io::Error::new(io::ErrorKind::BrokenPipe, "broken"));
+        assert!(matches!(err, DataStoreError::Disconnected(_)));
+        let err = DataStoreError::from(io::Error::new(io::ErrorKind::InvalidData, "invalid"));
+        assert!(matches!(err, DataStoreError::Corruption(_)));
+        let err = DataStoreError::from(io::Error::other("other"));
+        assert!(!err.is_transient());
+    }
+}
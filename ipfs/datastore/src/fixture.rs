@@ -0,0 +1,95 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::fs;
+use std::path::Path;
+
+use futures::executor::block_on_stream;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DataStoreError, Result};
+use crate::key::Key;
+use crate::store::{DataStoreWrite, StreamDataStore};
+
+// An entry of the fixture, the value is encoded in base64.
+#[derive(Debug, Serialize, Deserialize)]
+struct FixtureEntry {
+    key: Key,
+    value: String,
+}
+
+/// Populate the `store` with the fixture, a JSON array of `{"key": .., "value": ..}`
+/// objects whose values are encoded in base64, return the number of the loaded entries.
+///
+/// The entries are put in the order of the fixture, so a later duplicated key wins.
+pub fn load_fixture<D: DataStoreWrite>(store: &mut D, fixture: &[u8]) -> Result<usize> {
+    let entries = serde_json::from_slice::<Vec<FixtureEntry>>(fixture)
+        .map_err(|err| DataStoreError::Corruption(format!("malformed fixture: {}", err)))?;
+    let count = entries.len();
+    for entry in entries {
+        let value = base64::decode(&entry.value).map_err(|err| {
+            DataStoreError::Corruption(format!("the value of {}: {}", entry.key, err))
+        })?;
+        store.put(entry.key, value)?;
+    }
+    Ok(count)
+}
+
+/// Populate the `store` with the fixture file at `path`, see `load_fixture`.
+pub fn load_fixture_file<D, P>(store: &mut D, path: P) -> Result<usize>
+where
+    D: DataStoreWrite,
+    P: AsRef<Path>,
+{
+    let fixture = fs::read(path)?;
+    load_fixture(store, &fixture)
+}
+
+/// Snapshot the contents of the `store` into the fixture format of `load_fixture`.
+///
+/// The entries are sorted by key, so that the same contents always dump to the same bytes.
+pub fn dump_fixture<D: StreamDataStore>(store: &D) -> Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    for entry in block_on_stream(store.async_stream()) {
+        let entry = entry?;
+        entries.push(FixtureEntry {
+            key: entry.key,
+            value: base64::encode(&entry.value),
+        });
+    }
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    let fixture = serde_json::to_vec_pretty(&entries)
+        .expect("JSON serialization of fixture entries shouldn't be failed");
+    Ok(fixture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::MapDataStore;
+    use crate::store::DataStoreRead;
+
+    #[test]
+    fn test_fixture_round_trip() {
+        let fixture = br#"[
+            {"key": "/b", "value": "AAEC"},
+            {"key": "/a/c", "value": "aGVsbG8="},
+            {"key": "/a", "value": ""}
+        ]"#;
+        let mut store = MapDataStore::new();
+        assert_eq!(load_fixture(&mut store, fixture).unwrap(), 3);
+        assert_eq!(store.get(&Key::new("/b")).unwrap(), vec![0, 1, 2]);
+        assert_eq!(store.get(&Key::new("/a/c")).unwrap(), b"hello".to_vec());
+        assert!(store.get(&Key::new("/a")).unwrap().is_empty());
+
+        // the dump loads into an equal store and dumps to the same bytes.
+        let dump = dump_fixture(&store).unwrap();
+        let mut other = MapDataStore::new();
+        assert_eq!(load_fixture(&mut other, &dump).unwrap(), 3);
+        assert_eq!(dump_fixture(&other).unwrap(), dump);
+
+        let err = load_fixture(&mut other, br#"[{"key": "/a", "value": "!"}]"#).unwrap_err();
+        assert!(matches!(err, DataStoreError::Corruption(_)));
+        let err = load_fixture(&mut other, b"{").unwrap_err();
+        assert!(matches!(err, DataStoreError::Corruption(_)));
+    }
+}
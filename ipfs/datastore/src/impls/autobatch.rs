@@ -0,0 +1,474 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{read_snapshot_through, BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{add_counter, overlay_keys, ToBatch, ToTxn};
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+
+/// The default number of the buffered writes which triggers a flush.
+pub const DEFAULT_AUTOBATCH_THRESHOLD: usize = 128;
+
+/// BatchTuner adapts the flush threshold of `AutoBatchDataStore` to the observed commit
+/// latency: the threshold is halved when a commit exceeds the `target` latency, and grows
+/// by a quarter when a commit takes less than half of it, within `[min, max]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchTuner {
+    /// The latency budget of a single commit.
+    pub target: Duration,
+    /// The smallest flush threshold.
+    pub min: usize,
+    /// The largest flush threshold.
+    pub max: usize,
+}
+
+impl BatchTuner {
+    /// Create a new BatchTuner targeting the commit latency, the threshold is kept in
+    /// `[1, 16 * DEFAULT_AUTOBATCH_THRESHOLD]`.
+    pub fn new(target: Duration) -> Self {
+        Self {
+            target,
+            min: 1,
+            max: 16 * DEFAULT_AUTOBATCH_THRESHOLD,
+        }
+    }
+
+    // Return the threshold adapted to the latency of the commit.
+    fn adapt(&self, threshold: usize, latency: Duration) -> usize {
+        let threshold = if latency > self.target {
+            threshold / 2
+        } else if latency < self.target / 2 {
+            threshold + (threshold / 4).max(1)
+        } else {
+            threshold
+        };
+        threshold.max(self.min).min(self.max)
+    }
+}
+
+#[derive(Clone)]
+enum Op {
+    Put(Vec<u8>),
+    Delete,
+}
+
+impl Op {
+    fn is_put(&self) -> bool {
+        match self {
+            Op::Put(_) => true,
+            Op::Delete => false,
+        }
+    }
+
+    fn size(&self, key: &Key) -> usize {
+        match self {
+            Op::Put(value) => key.as_bytes().len() + value.len(),
+            Op::Delete => key.as_bytes().len(),
+        }
+    }
+}
+
+struct AutoBatch<DS: DataStore + ToBatch> {
+    ops: HashMap<Key, Op>,
+    bytes: usize,
+    threshold: usize,
+    max_bytes: Option<usize>,
+    tuner: Option<BatchTuner>,
+    datastore: DS,
+}
+
+impl<DS: DataStore + ToBatch> AutoBatch<DS> {
+    // The later operation on a key replaces the buffered one, so a delete buffered after
+    // a put is the only operation flushed for the key.
+    fn buffer(&mut self, key: Key, op: Op) -> Result<()> {
+        self.bytes += op.size(&key);
+        if let Some(old) = self.ops.get(&key) {
+            self.bytes -= old.size(&key);
+        }
+        self.ops.insert(key, op);
+        let over_bytes = self.max_bytes.is_some_and(|max| self.bytes >= max);
+        if self.ops.len() >= self.threshold || over_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write the buffered operations to the datastore as a single batch, the operations
+    // stay buffered when the commit fails.
+    fn flush(&mut self) -> Result<()> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        let start = Instant::now();
+        let mut batch = self.datastore.batch()?;
+        for (key, op) in &self.ops {
+            match op {
+                Op::Put(value) => batch.put(key.clone(), value.clone())?,
+                Op::Delete => batch.delete(key)?,
+            }
+        }
+        batch.commit()?;
+        self.ops.clear();
+        self.bytes = 0;
+        if let Some(tuner) = self.tuner {
+            self.threshold = tuner.adapt(self.threshold, start.elapsed());
+        }
+        Ok(())
+    }
+}
+
+impl<DS: DataStore + ToBatch> Drop for AutoBatch<DS> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("failed to flush the buffered writes on drop: {}", err);
+        }
+    }
+}
+
+/// AutoBatchDataStore buffers the writes and writes them to the backing datastore as a
+/// single batch once the number of the buffered writes reaches the flush threshold, or
+/// their total size reaches the optional byte limit.
+///
+/// The reads see the buffered writes. The buffer is flushed on `sync`, `fence`, `close` and
+/// when the last clone is dropped, the writes still buffered are lost if the process crashes.
+///
+/// With a `BatchTuner`, the threshold grows while the commits are cheap and shrinks when
+/// they're slow, so the commits stay within the latency budget across workloads.
+pub struct AutoBatchDataStore<DS: DataStore + ToBatch> {
+    autobatch: Arc<Mutex<AutoBatch<DS>>>,
+}
+
+impl<DS: DataStore + ToBatch> Clone for AutoBatchDataStore<DS> {
+    fn clone(&self) -> Self {
+        Self {
+            autobatch: self.autobatch.clone(),
+        }
+    }
+}
+
+impl<DS: DataStore + ToBatch> AutoBatchDataStore<DS> {
+    /// Create a new AutoBatchDataStore flushing every `max_buffered_ops` buffered writes.
+    pub fn new(datastore: DS, max_buffered_ops: usize) -> Self {
+        Self::create(datastore, max_buffered_ops.max(1), None)
+    }
+
+    /// Create a new AutoBatchDataStore whose flush threshold is tuned by the `tuner`,
+    /// starting from `threshold`.
+    pub fn with_tuner(datastore: DS, threshold: usize, tuner: BatchTuner) -> Self {
+        let threshold = threshold.max(tuner.min).min(tuner.max);
+        Self::create(datastore, threshold, Some(tuner))
+    }
+
+    fn create(datastore: DS, threshold: usize, tuner: Option<BatchTuner>) -> Self {
+        Self {
+            autobatch: Arc::new(Mutex::new(AutoBatch {
+                ops: HashMap::new(),
+                bytes: 0,
+                threshold,
+                max_bytes: None,
+                tuner,
+                datastore,
+            })),
+        }
+    }
+
+    /// Also flush once the total size of the buffered keys and values reaches `max_bytes`.
+    pub fn max_buffered_bytes(self, max_bytes: usize) -> Self {
+        self.autobatch.lock().max_bytes = Some(max_bytes.max(1));
+        self
+    }
+
+    /// Return the current flush threshold.
+    pub fn threshold(&self) -> usize {
+        self.autobatch.lock().threshold
+    }
+
+    /// Return the number of the buffered writes.
+    pub fn buffered_len(&self) -> usize {
+        self.autobatch.lock().ops.len()
+    }
+
+    /// Return the total size of the buffered keys and values.
+    pub fn buffered_bytes(&self) -> usize {
+        self.autobatch.lock().bytes
+    }
+
+    /// Write the buffered writes to the backing datastore.
+    pub fn flush(&self) -> Result<()> {
+        self.autobatch.lock().flush()
+    }
+}
+
+impl<DS: DataStore + ToBatch> DataStore for AutoBatchDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let mut autobatch = self.autobatch.lock();
+        autobatch.flush()?;
+        autobatch.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let mut autobatch = self.autobatch.lock();
+        autobatch.flush()?;
+        autobatch.datastore.close()
+    }
+}
+
+impl<DS: DataStore + ToBatch> DataStoreRead for AutoBatchDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        let autobatch = self.autobatch.lock();
+        match autobatch.ops.get(key) {
+            Some(Op::Put(value)) => Ok(value.clone()),
+            Some(Op::Delete) => Err(DataStoreError::NotFound(key.to_string())),
+            None => autobatch.datastore.get(key),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let autobatch = self.autobatch.lock();
+        match autobatch.ops.get(key.borrow()) {
+            Some(Op::Put(_)) => Ok(true),
+            Some(Op::Delete) => Ok(false),
+            None => autobatch.datastore.has(key),
+        }
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        let autobatch = self.autobatch.lock();
+        match autobatch.ops.get(key) {
+            Some(Op::Put(value)) => Ok(value.len()),
+            Some(Op::Delete) => Err(DataStoreError::NotFound(key.to_string())),
+            None => autobatch.datastore.size(key),
+        }
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let autobatch = self.autobatch.lock();
+        read_snapshot_through(
+            keys,
+            |key| match autobatch.ops.get(key)? {
+                Op::Put(value) => Some(Some(value.clone())),
+                Op::Delete => Some(None),
+            },
+            &autobatch.datastore,
+        )
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let autobatch = self.autobatch.lock();
+        let keys = autobatch.datastore.keys(prefix)?;
+        let pending = autobatch.ops.iter().map(|(key, op)| (key, op.is_put()));
+        Ok(overlay_keys(keys, pending, prefix))
+    }
+}
+
+impl<DS: DataStore + ToBatch> DataStoreWrite for AutoBatchDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.autobatch
+            .lock()
+            .buffer(key.into(), Op::Put(value.into()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.autobatch
+            .lock()
+            .buffer(key.borrow().clone(), Op::Delete)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the latest value may be buffered, read-modify-write it under the lock.
+        let key = key.borrow();
+        let mut autobatch = self.autobatch.lock();
+        let current = match autobatch.ops.get(key) {
+            Some(Op::Put(value)) => Some(value.clone()),
+            Some(Op::Delete) => None,
+            None => autobatch
+                .datastore
+                .read_snapshot(std::slice::from_ref(key))?
+                .remove(0),
+        };
+        let value = add_counter(key, current.as_deref(), delta)?;
+        autobatch.buffer(key.clone(), Op::Put(value.to_be_bytes().to_vec()))?;
+        Ok(value)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        let mut autobatch = self.autobatch.lock();
+        autobatch.flush()?;
+        autobatch.datastore.fence()
+    }
+}
+
+impl<DS: CheckedDataStore + ToBatch> Check for AutoBatchDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.autobatch.lock().datastore.check()
+    }
+}
+
+impl<DS: GcDataStore + ToBatch> Gc for AutoBatchDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.autobatch.lock().datastore.collect_garbage()
+    }
+}
+
+impl<DS: PersistentDataStore + ToBatch> Persistent for AutoBatchDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.autobatch.lock().datastore.disk_usage()
+    }
+}
+
+impl<DS: ScrubbedDataStore + ToBatch> Scrub for AutoBatchDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.autobatch.lock().datastore.scrub()
+    }
+}
+
+impl<DS: DataStore + ToBatch> ToBatch for AutoBatchDataStore<DS> {
+    type Batch = BasicBatchDataStore<AutoBatchDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore + ToBatch> ToTxn for AutoBatchDataStore<DS> {
+    type Txn = BasicTxnDataStore<AutoBatchDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{Delay, DelayConfig, DelayDataStore, RcuMapDataStore};
+
+    #[test]
+    fn test_autobatch() {
+        let backing = RcuMapDataStore::new();
+        let mut store = AutoBatchDataStore::new(backing.clone(), 3);
+        let (a, b, c) = (Key::new("/a"), Key::new("/b"), Key::new("/c"));
+
+        store.put(a.clone(), vec![1]).unwrap();
+        store.put(b.clone(), vec![2]).unwrap();
+        store.delete(&a).unwrap();
+        assert_eq!(store.buffered_len(), 2);
+        assert!(!store.has(&a).unwrap());
+        assert_eq!(store.get(&b).unwrap(), vec![2]);
+        assert!(!backing.has(&b).unwrap());
+
+        // the third buffered write triggers the flush.
+        store.put(c.clone(), vec![3]).unwrap();
+        assert_eq!(store.buffered_len(), 0);
+        assert_eq!(backing.get(&b).unwrap(), vec![2]);
+        assert_eq!(backing.get(&c).unwrap(), vec![3]);
+        assert!(!backing.has(&a).unwrap());
+
+        store.put(a.clone(), vec![4]).unwrap();
+        store.sync(&Key::new("/")).unwrap();
+        assert_eq!(backing.get(&a).unwrap(), vec![4]);
+
+        // the delete buffered after the put wins, the flush doesn't re-create the key.
+        store.put(b.clone(), vec![5]).unwrap();
+        store.delete(&b).unwrap();
+        assert_eq!(store.buffered_len(), 1);
+        assert!(store.get(&b).unwrap_err().is_not_found());
+        store.flush().unwrap();
+        assert!(!backing.has(&b).unwrap());
+        assert!(!store.has(&b).unwrap());
+    }
+
+    #[test]
+    fn test_autobatch_bytes_and_drop() {
+        let backing = RcuMapDataStore::new();
+        let mut store = AutoBatchDataStore::new(backing.clone(), 100).max_buffered_bytes(16);
+        let (a, b, c) = (Key::new("/a"), Key::new("/b"), Key::new("/c"));
+
+        store.put(a.clone(), vec![0; 4]).unwrap();
+        store.put(a.clone(), vec![0; 6]).unwrap();
+        assert_eq!(store.buffered_bytes(), 2 + 6);
+        assert!(!backing.has(&a).unwrap());
+
+        // the buffered bytes reach the limit.
+        store.put(b.clone(), vec![0; 6]).unwrap();
+        assert_eq!(store.buffered_bytes(), 0);
+        assert_eq!(backing.get(&a).unwrap(), vec![0; 6]);
+        assert_eq!(backing.get(&b).unwrap(), vec![0; 6]);
+
+        // dropping the last clone flushes the buffer.
+        store.put(c.clone(), vec![1]).unwrap();
+        let clone = store.clone();
+        drop(store);
+        assert!(!backing.has(&c).unwrap());
+        drop(clone);
+        assert_eq!(backing.get(&c).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_autobatch_tuner() {
+        let target = Duration::from_millis(16);
+        // every operation on the backing datastore takes a millisecond.
+        let delay = DelayConfig::uniform(Delay::Fixed(Duration::from_millis(1)));
+        let backing = DelayDataStore::new(delay, RcuMapDataStore::new());
+        let mut store = AutoBatchDataStore::with_tuner(backing, 256, BatchTuner::new(target));
+        assert_eq!(store.threshold(), 256);
+
+        // a commit takes at least a millisecond per write, far over the target at first.
+        for i in 0..1024 {
+            store.put(Key::new(format!("/{}", i)), vec![0; 8]).unwrap();
+        }
+        let threshold = store.threshold();
+        assert!(threshold < 256, "threshold {}", threshold);
+        assert!(threshold <= 16, "threshold {}", threshold);
+
+        // the tuned batches meet the latency target.
+        store.flush().unwrap();
+        for i in 0..threshold - 1 {
+            store.put(Key::new(format!("/{}", i)), vec![1; 8]).unwrap();
+        }
+        let start = Instant::now();
+        store.put(Key::new("/last"), vec![1; 8]).unwrap();
+        assert!(
+            start.elapsed() <= 2 * target,
+            "latency {:?}",
+            start.elapsed()
+        );
+    }
+}
@@ -1,11 +1,15 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::error::Result;
+use parking_lot::{Mutex, ReentrantMutex, ReentrantMutexGuard};
+
+use crate::error::{DataStoreError, Result};
 use crate::key::Key;
-use crate::store::ToTxn;
+use crate::store::{overlay_keys, ToTxn};
 use crate::store::{Check, CheckedDataStore};
 use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
 use crate::store::{Gc, GcDataStore};
@@ -18,22 +22,123 @@ enum Op {
     Delete,       // a single delete operation of batched operations.
 }
 
+impl Op {
+    fn is_put(&self) -> bool {
+        match self {
+            Op::Put(_) => true,
+            Op::Delete => false,
+        }
+    }
+}
+
+impl Op {
+    fn size(&self, key: &Key) -> usize {
+        match self {
+            Op::Put(value) => key.as_bytes().len() + value.len(),
+            Op::Delete => key.as_bytes().len(),
+        }
+    }
+}
+
+/// The limits of a single underlying commit of the `BasicBatchDataStore`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// The maximum number of the operations of a chunk.
+    pub max_ops: usize,
+    /// The maximum total size of the keys and values of a chunk, a single operation
+    /// larger than it makes up a chunk on its own.
+    pub max_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_ops: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
 /// BasicBatchDataStore implements the batch interface for data stores
 /// who do not have any sort of underlying batch support.
+///
+/// The commit writes the operations in key order, split into chunks within the limits
+/// of the `BatchConfig`, with a `fence` after each chunk. The commit is not atomic: if a
+/// chunk fails, the chunks before it remain applied and are dropped from the batch, the
+/// failed chunk and the ones after it stay buffered so that the commit can be retried.
 #[derive(Clone)]
 pub struct BasicBatchDataStore<DS: DataStore> {
     ops: HashMap<Key, Op>,
+    config: BatchConfig,
     datastore: DS,
 }
 
 impl<DS: DataStore> BasicBatchDataStore<DS> {
     /// Create a new basic batching datastore.
     pub fn new(datastore: DS) -> Self {
+        Self::with_config(datastore, BatchConfig::default())
+    }
+
+    /// Create a new basic batching datastore committing in chunks within the `config`.
+    pub fn with_config(datastore: DS, config: BatchConfig) -> Self {
         Self {
             ops: HashMap::new(),
+            config,
             datastore,
         }
     }
+
+    // Split the sorted keys into the chunks within the limits of the config.
+    fn chunks(&self) -> Vec<Vec<Key>> {
+        let mut keys = self.ops.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        let mut chunks = Vec::new();
+        let (mut chunk, mut bytes) = (Vec::new(), 0usize);
+        for key in keys {
+            let size = self.ops[&key].size(&key);
+            let full = chunk.len() >= self.config.max_ops
+                || bytes.saturating_add(size) > self.config.max_bytes;
+            if !chunk.is_empty() && full {
+                chunks.push(std::mem::take(&mut chunk));
+                bytes = 0;
+            }
+            bytes += size;
+            chunk.push(key);
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+}
+
+impl<DS: DataStore> DataStore for BasicBatchDataStore<DS> {
+    /// Write the buffered operations under the `prefix` to the backing datastore before
+    /// syncing it, the root prefix `/` flushes all of them.
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let prefix = prefix.borrow();
+        let keys = self
+            .ops
+            .keys()
+            .filter(|key| *key == prefix || prefix.is_ancestor_of(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            match &self.ops[&key] {
+                Op::Put(value) => self.datastore.put(key.clone(), value.to_owned())?,
+                Op::Delete => self.datastore.delete(&key)?,
+            }
+            self.ops.remove(&key);
+        }
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
 }
 
 impl<DS: DataStore> DataStoreRead for BasicBatchDataStore<DS> {
@@ -44,6 +149,10 @@ impl<DS: DataStore> DataStoreRead for BasicBatchDataStore<DS> {
         self.datastore.get(key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.datastore.read_snapshot(keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
@@ -57,6 +166,10 @@ impl<DS: DataStore> DataStoreRead for BasicBatchDataStore<DS> {
     {
         self.datastore.size(key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
 }
 
 impl<DS: DataStore> DataStoreWrite for BasicBatchDataStore<DS> {
@@ -80,13 +193,18 @@ impl<DS: DataStore> DataStoreWrite for BasicBatchDataStore<DS> {
 
 impl<DS: DataStore> DataStoreBatch for BasicBatchDataStore<DS> {
     fn commit(&mut self) -> Result<()> {
-        for (key, op) in &self.ops {
-            match op {
-                Op::Put(value) => self.datastore.put(key, value.to_owned())?,
-                Op::Delete => self.datastore.delete(&key)?,
+        for chunk in self.chunks() {
+            for key in &chunk {
+                match &self.ops[key] {
+                    Op::Put(value) => self.datastore.put(key, value.to_owned())?,
+                    Op::Delete => self.datastore.delete(key)?,
+                }
+            }
+            self.datastore.fence()?;
+            for key in &chunk {
+                self.ops.remove(key);
             }
         }
-        self.ops.clear();
         Ok(())
     }
 }
@@ -98,7 +216,7 @@ impl<DS: CheckedDataStore> Check for BasicBatchDataStore<DS> {
 }
 
 impl<DS: GcDataStore> Gc for BasicBatchDataStore<DS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.collect_garbage()
     }
 }
@@ -110,7 +228,7 @@ impl<DS: PersistentDataStore> Persistent for BasicBatchDataStore<DS> {
 }
 
 impl<DS: ScrubbedDataStore> Scrub for BasicBatchDataStore<DS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         self.datastore.scrub()
     }
 }
@@ -119,31 +237,222 @@ impl<DS: DataStore> ToTxn for BasicBatchDataStore<DS> {
     type Txn = BasicTxnDataStore<DS>;
 
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
-        Ok(BasicTxnDataStore {
-            datastore: self.datastore.clone(),
-            ops: self.ops.clone(),
-        })
+        Ok(BasicTxnDataStore::with_ops(
+            self.datastore.clone(),
+            None,
+            self.ops.clone(),
+        ))
     }
 }
 
 // ============================================================================
 
+/// WriteLock is shared by the handles of a datastore whose state is shared, see
+/// `BasicTxnDataStore::with_write_lock`.
+///
+/// The plain writes take it and, while a basic transaction is open, record the keys they
+/// write, so that the commit of a `BasicTxnDataStore` excludes them and detects the keys
+/// written since the transaction began. The lock is reentrant, since the commit writes
+/// through the datastore with the lock held.
+#[derive(Clone, Debug, Default)]
+pub struct WriteLock(Arc<ReentrantMutex<RefCell<WriteClock>>>);
+
+#[derive(Debug, Default)]
+struct WriteClock {
+    // the sequence number of the last write.
+    seq: u64,
+    // the number of the open transactions, the keys are only recorded while there are some.
+    txns: usize,
+    // the sequence number of the last write of each key.
+    written: HashMap<Key, u64>,
+    // the sequence number of the last write of unknown keys, like a batch commit.
+    written_all: u64,
+}
+
+impl WriteLock {
+    /// Create a new WriteLock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the write of the `key` with the lock held.
+    pub fn write<T, F: FnOnce() -> T>(&self, key: &Key, f: F) -> T {
+        let guard = self.0.lock();
+        let result = f();
+        let mut clock = guard.borrow_mut();
+        clock.seq += 1;
+        if clock.txns > 0 {
+            let seq = clock.seq;
+            clock.written.insert(key.clone(), seq);
+        }
+        result
+    }
+
+    /// Run the write of unknown keys with the lock held, which conflicts with every open
+    /// transaction.
+    pub fn write_all<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let guard = self.0.lock();
+        let result = f();
+        let mut clock = guard.borrow_mut();
+        clock.seq += 1;
+        clock.written_all = clock.seq;
+        result
+    }
+
+    fn lock(&self) -> ReentrantMutexGuard<'_, RefCell<WriteClock>> {
+        self.0.lock()
+    }
+
+    // Open a transaction, returning the sequence number it begins at.
+    fn begin(&self) -> u64 {
+        let guard = self.0.lock();
+        let mut clock = guard.borrow_mut();
+        clock.txns += 1;
+        clock.seq
+    }
+
+    // Close a transaction, the recorded keys are dropped once no transaction is open.
+    fn end(&self) {
+        let guard = self.0.lock();
+        let mut clock = guard.borrow_mut();
+        clock.txns -= 1;
+        if clock.txns == 0 {
+            clock.written.clear();
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        RefCell::borrow(&*self.0.lock()).seq
+    }
+
+    // Return whether the key has been written after the sequence number `begin`.
+    fn written_since(&self, key: &Key, begin: u64) -> bool {
+        let guard = self.0.lock();
+        let clock = RefCell::borrow(&*guard);
+        clock.written_all > begin || clock.written.get(key).is_some_and(|seq| *seq > begin)
+    }
+}
+
 /// BasicTxnDataStore implements the transaction interface for data stores
 /// who do not have any sort of underlying transaction support.
-#[derive(Clone)]
+///
+/// The transaction buffers its writes and reads its own writes. A key read or written
+/// by the transaction is pinned to the value it had when the transaction first touched
+/// it, and the commit fails with `DataStoreError::Conflict` if any of these keys has
+/// changed in the underlying datastore since the transaction began, so a committed
+/// transaction has only seen the state at its beginning.
+///
+/// If the transaction is created with the `WriteLock` of the datastore, the commit holds it,
+/// so the check and the writes of a commit are not interleaved with the other commits and
+/// plain writes. Without a lock, the observed values are compared instead. If a write fails,
+/// the keys already written are restored.
 pub struct BasicTxnDataStore<DS: DataStore> {
     ops: HashMap<Key, Op>,
+    // the value of each key read or written, as first observed by the transaction.
+    observed: Mutex<HashMap<Key, Option<Vec<u8>>>>,
+    lock: Option<WriteLock>,
+    // the sequence number of the write lock the transaction begins at.
+    begin: u64,
     datastore: DS,
 }
 
+impl<DS: DataStore> Clone for BasicTxnDataStore<DS> {
+    fn clone(&self) -> Self {
+        if let Some(lock) = &self.lock {
+            lock.begin();
+        }
+        Self {
+            ops: self.ops.clone(),
+            observed: Mutex::new(self.observed.lock().clone()),
+            lock: self.lock.clone(),
+            begin: self.begin,
+            datastore: self.datastore.clone(),
+        }
+    }
+}
+
+impl<DS: DataStore> Drop for BasicTxnDataStore<DS> {
+    fn drop(&mut self) {
+        if let Some(lock) = &self.lock {
+            lock.end();
+        }
+    }
+}
+
 impl<DS: DataStore> BasicTxnDataStore<DS> {
-    /// Create a new basic transaction datastore.
+    /// Create a new basic transaction datastore, which detects the conflicts by comparing
+    /// the observed values.
     pub fn new(datastore: DS) -> Self {
+        Self::with_ops(datastore, None, HashMap::new())
+    }
+
+    /// Create a new basic transaction datastore over a datastore whose writes take the
+    /// `lock`, which detects the conflicts by the keys written since the transaction began.
+    pub fn with_write_lock(datastore: DS, lock: WriteLock) -> Self {
+        Self::with_ops(datastore, Some(lock), HashMap::new())
+    }
+
+    fn with_ops(datastore: DS, lock: Option<WriteLock>, ops: HashMap<Key, Op>) -> Self {
+        let begin = lock.as_ref().map_or(0, WriteLock::begin);
         Self {
-            ops: HashMap::new(),
+            ops,
+            observed: Mutex::new(HashMap::new()),
+            lock,
+            begin,
             datastore,
         }
     }
+
+    // Begin the transaction anew after it's committed or discarded.
+    fn restart(&mut self) {
+        if let Some(lock) = &self.lock {
+            self.begin = lock.seq();
+        }
+    }
+
+    // Return the value of the key as seen by the transaction, ignoring its own writes.
+    fn observe(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.observed.lock().get(key) {
+            return Ok(value.clone());
+        }
+        let value = match self.datastore.get(key) {
+            Ok(value) => Some(value),
+            Err(err) if err.is_not_found() => None,
+            Err(err) => return Err(err),
+        };
+        self.observed.lock().insert(key.clone(), value.clone());
+        Ok(value)
+    }
+
+    // Return the value of the key including the writes of the transaction.
+    fn lookup(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        match self.ops.get(key) {
+            Some(Op::Put(value)) => Ok(Some(value.clone())),
+            Some(Op::Delete) => Ok(None),
+            None => self.observe(key),
+        }
+    }
+
+    fn write(&mut self, key: Key, value: Option<&[u8]>) -> Result<()> {
+        match value {
+            Some(value) => self.datastore.put(key, value.to_vec()),
+            None => self.datastore.delete(&key),
+        }
+    }
+}
+
+impl<DS: DataStore> DataStore for BasicTxnDataStore<DS> {
+    // The uncommitted operations of the transaction are not flushed.
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
 }
 
 impl<DS: DataStore> DataStoreRead for BasicTxnDataStore<DS> {
@@ -151,21 +460,29 @@ impl<DS: DataStore> DataStoreRead for BasicTxnDataStore<DS> {
     where
         K: Borrow<Key>,
     {
-        self.datastore.get(key)
+        let key = key.borrow();
+        self.lookup(key)?
+            .ok_or_else(|| DataStoreError::NotFound(key.to_string()))
     }
 
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
     {
-        self.datastore.has(key)
+        Ok(self.lookup(key.borrow())?.is_some())
     }
 
     fn size<K>(&self, key: &K) -> Result<usize>
     where
         K: Borrow<Key>,
     {
-        self.datastore.size(key)
+        self.get(key).map(|value| value.len())
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let keys = self.datastore.keys(prefix)?;
+        let pending = self.ops.iter().map(|(key, op)| (key, op.is_put()));
+        Ok(overlay_keys(keys, pending, prefix))
     }
 }
 
@@ -175,7 +492,9 @@ impl<DS: DataStore> DataStoreWrite for BasicTxnDataStore<DS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.ops.insert(key.into(), Op::Put(value.into()));
+        let key = key.into();
+        self.observe(&key)?;
+        self.ops.insert(key, Op::Put(value.into()));
         Ok(())
     }
 
@@ -183,20 +502,50 @@ impl<DS: DataStore> DataStoreWrite for BasicTxnDataStore<DS> {
     where
         K: Borrow<Key>,
     {
-        self.ops.insert(key.borrow().to_owned(), Op::Delete);
+        let key = key.borrow();
+        self.observe(key)?;
+        self.ops.insert(key.to_owned(), Op::Delete);
         Ok(())
     }
 }
 
 impl<DS: DataStore> DataStoreBatch for BasicTxnDataStore<DS> {
     fn commit(&mut self) -> Result<()> {
-        for (key, op) in &self.ops {
-            match op {
-                Op::Put(value) => self.datastore.put(key, value.to_owned())?,
-                Op::Delete => self.datastore.delete(&key)?,
+        let lock = self.lock.clone();
+        let _guard = lock.as_ref().map(WriteLock::lock);
+        let observed = std::mem::take(&mut *self.observed.lock());
+        let keys = observed.keys().cloned().collect::<Vec<_>>();
+        let current = self.datastore.read_snapshot(&keys)?;
+        for (key, value) in keys.iter().zip(&current) {
+            let written = lock
+                .as_ref()
+                .is_some_and(|lock| lock.written_since(key, self.begin));
+            if written || observed[key] != *value {
+                self.ops.clear();
+                self.restart();
+                return Err(DataStoreError::Conflict(key.to_string()));
             }
         }
-        self.ops.clear();
+
+        let ops = std::mem::take(&mut self.ops);
+        let mut written = Vec::with_capacity(ops.len());
+        for (key, op) in ops {
+            let value = match &op {
+                Op::Put(value) => Some(value.as_slice()),
+                Op::Delete => None,
+            };
+            if let Err(err) = self.write(key.clone(), value) {
+                // roll back the keys already written, as best we can.
+                for key in written {
+                    let value = observed[&key].as_deref();
+                    let _ = self.write(key, value);
+                }
+                self.restart();
+                return Err(err);
+            }
+            written.push(key);
+        }
+        self.restart();
         Ok(())
     }
 }
@@ -204,6 +553,8 @@ impl<DS: DataStore> DataStoreBatch for BasicTxnDataStore<DS> {
 impl<DS: DataStore> DataStoreTxn for BasicTxnDataStore<DS> {
     fn discard(&mut self) -> Result<()> {
         self.ops.clear();
+        self.observed.lock().clear();
+        self.restart();
         Ok(())
     }
 }
@@ -215,7 +566,7 @@ impl<DS: CheckedDataStore> Check for BasicTxnDataStore<DS> {
 }
 
 impl<DS: GcDataStore> Gc for BasicTxnDataStore<DS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.collect_garbage()
     }
 }
@@ -227,7 +578,238 @@ impl<DS: PersistentDataStore> Persistent for BasicTxnDataStore<DS> {
 }
 
 impl<DS: ScrubbedDataStore> Scrub for BasicTxnDataStore<DS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         self.datastore.scrub()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::impls::{FailBuilder, FailDataStore, MapDataStore, SyncDataStore};
+
+    #[test]
+    fn test_batch_sync() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let mut batch = BasicBatchDataStore::new(inner.clone());
+        let (a, ab, b) = (Key::new("/a"), Key::new("/a/b"), Key::new("/b"));
+        batch.put(a.clone(), vec![1]).unwrap();
+        batch.put(ab.clone(), vec![2]).unwrap();
+        batch.put(b.clone(), vec![3]).unwrap();
+        assert!(!inner.has(&a).unwrap());
+
+        // only the writes under the prefix are flushed.
+        batch.sync(&a).unwrap();
+        assert_eq!(inner.get(&a).unwrap(), vec![1]);
+        assert_eq!(inner.get(&ab).unwrap(), vec![2]);
+        assert!(!inner.has(&b).unwrap());
+
+        // the root prefix flushes everything.
+        batch.delete(&a).unwrap();
+        batch.sync(&Key::new("/")).unwrap();
+        assert!(!inner.has(&a).unwrap());
+        assert_eq!(inner.get(&b).unwrap(), vec![3]);
+        assert!(batch.ops.is_empty());
+    }
+
+    #[test]
+    fn test_batch_chunks() {
+        let fences = Arc::new(AtomicUsize::new(0));
+        let counter = fences.clone();
+        let fail_fn = move |op: &str, _: Option<&Key>| {
+            if op == "fence" {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        };
+        let inner = FailDataStore::new(fail_fn, SyncDataStore::new(MapDataStore::new()));
+        let config = BatchConfig {
+            max_ops: 1000,
+            ..Default::default()
+        };
+        let mut batch = BasicBatchDataStore::with_config(inner.clone(), config);
+        for i in 0..10_000 {
+            batch
+                .put(Key::new(format!("/{:05}", i)), vec![0; 8])
+                .unwrap();
+        }
+        batch.commit().unwrap();
+        assert_eq!(fences.load(Ordering::SeqCst), 10);
+        assert!(batch.ops.is_empty());
+        for i in 0..10_000 {
+            assert!(inner.has(&Key::new(format!("/{:05}", i))).unwrap());
+        }
+
+        // a chunk stops at the byte limit too.
+        let config = BatchConfig {
+            max_ops: 1000,
+            max_bytes: 3 * (6 + 8),
+        };
+        let mut batch = BasicBatchDataStore::with_config(inner, config);
+        for i in 0..7 {
+            batch
+                .put(Key::new(format!("/{:05}", i)), vec![1; 8])
+                .unwrap();
+        }
+        assert_eq!(
+            batch.chunks().iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+    }
+
+    #[test]
+    fn test_batch_partial_commit() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let fail_fn = FailBuilder::new()
+            .fail_under("put", "/05000", DataStoreError::Timeout("put".into()))
+            .build();
+        let config = BatchConfig {
+            max_ops: 1000,
+            ..Default::default()
+        };
+        let mut batch =
+            BasicBatchDataStore::with_config(FailDataStore::new(fail_fn, inner.clone()), config);
+        for i in 0..10_000 {
+            batch.put(Key::new(format!("/{:05}", i)), vec![0]).unwrap();
+        }
+
+        // the chunks before the failed one remain applied, the rest stay buffered.
+        assert!(batch.commit().is_err());
+        assert!(inner.has(&Key::new("/04999")).unwrap());
+        assert!(!inner.has(&Key::new("/05000")).unwrap());
+        assert!(!inner.has(&Key::new("/05001")).unwrap());
+        assert_eq!(batch.ops.len(), 5000);
+    }
+
+    // Build a transaction detecting the conflicts by the write lock of the datastore.
+    fn locked_txn(
+        inner: &SyncDataStore<MapDataStore>,
+    ) -> BasicTxnDataStore<SyncDataStore<MapDataStore>> {
+        BasicTxnDataStore::with_write_lock(inner.clone(), inner.write_lock())
+    }
+
+    #[test]
+    fn test_txn_isolation() {
+        let mut inner = SyncDataStore::new(MapDataStore::new());
+        let (a, b) = (Key::new("/a"), Key::new("/b"));
+        inner.put(a.clone(), vec![0]).unwrap();
+
+        // the transaction reads its own writes, which are invisible outside until commit.
+        let mut txn = locked_txn(&inner);
+        txn.put(b.clone(), vec![1]).unwrap();
+        txn.delete(&a).unwrap();
+        assert_eq!(txn.get(&b).unwrap(), vec![1]);
+        assert!(!txn.has(&a).unwrap());
+        assert!(!inner.has(&b).unwrap());
+        txn.commit().unwrap();
+        assert_eq!(inner.get(&b).unwrap(), vec![1]);
+        assert!(!inner.has(&a).unwrap());
+
+        // discard drops everything.
+        let mut txn = locked_txn(&inner);
+        txn.put(a.clone(), vec![2]).unwrap();
+        txn.discard().unwrap();
+        txn.commit().unwrap();
+        assert!(!inner.has(&a).unwrap());
+
+        // the reads are pinned to the first observed value.
+        let txn = locked_txn(&inner);
+        assert_eq!(txn.get(&b).unwrap(), vec![1]);
+        inner.put(b.clone(), vec![3]).unwrap();
+        assert_eq!(txn.get(&b).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_txn_conflict() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let key = Key::new("/a");
+
+        // two concurrent transactions writing the same key, the second commit is rejected.
+        let mut first = locked_txn(&inner);
+        let mut second = locked_txn(&inner);
+        first.put(key.clone(), vec![1]).unwrap();
+        second.put(key.clone(), vec![2]).unwrap();
+        first.commit().unwrap();
+        let err = second.commit().unwrap_err();
+        assert!(matches!(err, DataStoreError::Conflict(_)));
+        assert_eq!(inner.get(&key).unwrap(), vec![1]);
+
+        // a key only read by the transaction conflicts too.
+        let mut reader = locked_txn(&inner);
+        assert_eq!(reader.get(&key).unwrap(), vec![1]);
+        reader.put(Key::new("/b"), vec![0]).unwrap();
+        let mut writer = locked_txn(&inner);
+        writer.put(key.clone(), vec![3]).unwrap();
+        writer.commit().unwrap();
+        assert!(matches!(
+            reader.commit().unwrap_err(),
+            DataStoreError::Conflict(_)
+        ));
+        assert!(!inner.has(&Key::new("/b")).unwrap());
+
+        // the commits from many threads of the same key, exactly one wins.
+        // all the transactions begin before any of them commits.
+        let txns = (0..8u8)
+            .map(|i| {
+                let mut txn = locked_txn(&inner);
+                txn.put(key.clone(), vec![i]).unwrap();
+                txn
+            })
+            .collect::<Vec<_>>();
+        let handles = txns
+            .into_iter()
+            .map(|mut txn| std::thread::spawn(move || txn.commit().is_ok()))
+            .collect::<Vec<_>>();
+        let committed = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or(false))
+            .filter(|ok| *ok)
+            .count();
+        assert_eq!(committed, 1);
+    }
+
+    #[test]
+    fn test_txn_plain_write() {
+        let mut inner = SyncDataStore::new(MapDataStore::new());
+        let key = Key::new("/a");
+        inner.put(key.clone(), vec![0]).unwrap();
+
+        // a plain write restoring the value (ABA) is still a conflict.
+        let mut txn = locked_txn(&inner);
+        assert_eq!(txn.get(&key).unwrap(), vec![0]);
+        inner.put(key.clone(), vec![1]).unwrap();
+        inner.put(key.clone(), vec![0]).unwrap();
+        txn.put(Key::new("/b"), vec![0]).unwrap();
+        assert!(matches!(
+            txn.commit().unwrap_err(),
+            DataStoreError::Conflict(_)
+        ));
+        assert!(!inner.has(&Key::new("/b")).unwrap());
+
+        // the transaction begins anew after the commit.
+        txn.put(key.clone(), vec![2]).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(inner.get(&key).unwrap(), vec![2]);
+
+        // the key written after the transaction began, but before it's first read.
+        let mut txn = locked_txn(&inner);
+        inner.put(key.clone(), vec![3]).unwrap();
+        assert_eq!(txn.get(&key).unwrap(), vec![3]);
+        txn.put(key.clone(), vec![4]).unwrap();
+        assert!(matches!(
+            txn.commit().unwrap_err(),
+            DataStoreError::Conflict(_)
+        ));
+        assert_eq!(inner.get(&key).unwrap(), vec![3]);
+
+        // the keys not touched by the transaction don't conflict.
+        let mut txn = locked_txn(&inner);
+        txn.put(key.clone(), vec![5]).unwrap();
+        inner.put(Key::new("/c"), vec![0]).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(inner.get(&key).unwrap(), vec![5]);
+    }
+}
@@ -0,0 +1,576 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use futures::executor::block_on_stream;
+use parking_lot::RwLock;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite, StreamDataStore};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+/// The default expected number of keys of the Bloom filter.
+pub const DEFAULT_BLOOM_CAPACITY: usize = 1 << 16;
+/// The default false positive rate of the Bloom filter.
+pub const DEFAULT_BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+// A standard Bloom filter using double hashing.
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    // the number of the bits set.
+    set_bits: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = capacity.max(1) as f64;
+        let num_bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / capacity) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            set_bits: 0,
+        }
+    }
+
+    fn indexes(&self, key: &Key) -> impl Iterator<Item = u64> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1));
+        let num_bits = self.num_bits;
+        (0..u64::from(self.num_hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    fn insert(&mut self, key: &Key) {
+        for index in self.indexes(key).collect::<Vec<_>>() {
+            let word = &mut self.bits[(index / 64) as usize];
+            if *word & (1 << (index % 64)) == 0 {
+                *word |= 1 << (index % 64);
+                self.set_bits += 1;
+            }
+        }
+    }
+
+    // The probability that all the bits of an absent key are set.
+    fn false_positive_rate(&self) -> f64 {
+        (self.set_bits as f64 / self.num_bits as f64).powi(self.num_hashes as i32)
+    }
+
+    fn may_contain(&self, key: &Key) -> bool {
+        self.indexes(key)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+}
+
+struct Bloom {
+    filter: BloomFilter,
+    capacity: usize,
+    false_positive_rate: f64,
+    // the number of keys deleted since the last rebuild, which are still set in the filter.
+    deleted: usize,
+}
+
+/// BloomDataStore maintains a Bloom filter of the keys present in the inner datastore.
+///
+/// The filter is consulted first on `get`/`has`/`size`, so a definite-absent key skips
+/// the inner datastore. A possibly-present key always falls through to the inner datastore,
+/// so a false positive never turns into a false "present".
+///
+/// The deleted keys can't be removed from the filter, so `has` may still probe the inner
+/// datastore for them. The filter is rebuilt from the inner datastore once the number of
+/// deletes reaches the rebuild threshold, or the filter saturates past the target false
+/// positive rate.
+pub struct BloomDataStore<DS: StreamDataStore> {
+    bloom: Arc<RwLock<Bloom>>,
+    rebuild_threshold: usize,
+    datastore: DS,
+}
+
+impl<DS: StreamDataStore> Clone for BloomDataStore<DS> {
+    fn clone(&self) -> Self {
+        Self {
+            bloom: self.bloom.clone(),
+            rebuild_threshold: self.rebuild_threshold,
+            datastore: self.datastore.clone(),
+        }
+    }
+}
+
+impl<DS: StreamDataStore> BloomDataStore<DS> {
+    /// Create a new BloomDataStore with the default capacity and false positive rate,
+    /// the filter is built from the keys of the inner datastore.
+    pub fn new(datastore: DS) -> Result<Self> {
+        Self::with_config(
+            datastore,
+            DEFAULT_BLOOM_CAPACITY,
+            DEFAULT_BLOOM_FALSE_POSITIVE_RATE,
+            DEFAULT_BLOOM_CAPACITY / 4,
+        )
+    }
+
+    /// Create a new BloomDataStore with the expected number of keys and the target false
+    /// positive rate, the filter is rebuilt after a quarter of the capacity is deleted.
+    pub fn with_capacity(datastore: DS, capacity: usize, false_positive_rate: f64) -> Result<Self> {
+        Self::with_config(datastore, capacity, false_positive_rate, capacity / 4)
+    }
+
+    /// Create a new BloomDataStore with the expected number of keys, the false positive rate
+    /// and the number of deletes that triggers the rebuild of the filter.
+    pub fn with_config(
+        datastore: DS,
+        capacity: usize,
+        false_positive_rate: f64,
+        rebuild_threshold: usize,
+    ) -> Result<Self> {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false positive rate must be in (0, 1)"
+        );
+        let bloom = Bloom {
+            filter: BloomFilter::new(capacity, false_positive_rate),
+            capacity,
+            false_positive_rate,
+            deleted: 0,
+        };
+        let store = Self {
+            bloom: Arc::new(RwLock::new(bloom)),
+            rebuild_threshold: rebuild_threshold.max(1),
+            datastore,
+        };
+        store.rebuild()?;
+        Ok(store)
+    }
+
+    /// Rebuild the filter from the keys of the inner datastore.
+    pub fn rebuild(&self) -> Result<()> {
+        // hold the lock, the puts during the rebuild must not be missed.
+        self.rebuild_locked(&mut self.bloom.write())
+    }
+
+    // Rebuild the filter with the lock held by the caller, the puts take the same lock
+    // around their inner writes.
+    fn rebuild_locked(&self, bloom: &mut Bloom) -> Result<()> {
+        let mut keys = Vec::new();
+        for entry in block_on_stream(self.datastore.async_stream()) {
+            keys.push(entry?.key);
+        }
+        let capacity = bloom.capacity.max(keys.len() * 2);
+        let mut filter = BloomFilter::new(capacity, bloom.false_positive_rate);
+        for key in &keys {
+            filter.insert(key);
+        }
+        bloom.filter = filter;
+        bloom.capacity = capacity;
+        bloom.deleted = 0;
+        Ok(())
+    }
+
+    /// Return the estimated false positive rate of the filter, from the ratio of its bits set.
+    pub fn false_positive_rate(&self) -> f64 {
+        self.bloom.read().filter.false_positive_rate()
+    }
+
+    /// Return whether the key may be present, `false` means the key is definitely absent.
+    pub fn may_contain(&self, key: &Key) -> bool {
+        self.bloom.read().filter.may_contain(key)
+    }
+}
+
+impl<DS: StreamDataStore> DataStore for BloomDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<DS: StreamDataStore> DataStoreRead for BloomDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        if !self.may_contain(key.borrow()) {
+            return Err(DataStoreError::NotFound(key.borrow().to_string()));
+        }
+        self.datastore.get(key)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        if !self.may_contain(key.borrow()) {
+            return Ok(false);
+        }
+        self.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        if !self.may_contain(key.borrow()) {
+            return Err(DataStoreError::NotFound(key.borrow().to_string()));
+        }
+        self.datastore.size(key)
+    }
+
+    fn has_many(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        let mut flags = vec![false; keys.len()];
+        // only the keys which may be present are checked by the inner datastore.
+        let (indexes, candidates): (Vec<usize>, Vec<Key>) = {
+            let bloom = self.bloom.read();
+            keys.iter()
+                .enumerate()
+                .filter(|(_, key)| bloom.filter.may_contain(key))
+                .map(|(index, key)| (index, key.clone()))
+                .unzip()
+        };
+        if !candidates.is_empty() {
+            let present = self.datastore.has_many(&candidates)?;
+            for (index, present) in indexes.into_iter().zip(present) {
+                flags[index] = present;
+            }
+        }
+        Ok(flags)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = vec![None; keys.len()];
+        // hold the filter lock, the puts insert into the filter and the datastore under it.
+        let bloom = self.bloom.read();
+        let (indexes, candidates): (Vec<usize>, Vec<Key>) = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| bloom.filter.may_contain(key))
+            .map(|(index, key)| (index, key.clone()))
+            .unzip();
+        if !candidates.is_empty() {
+            let read = self.datastore.read_snapshot(&candidates)?;
+            for (index, value) in indexes.into_iter().zip(read) {
+                values[index] = value;
+            }
+        }
+        Ok(values)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+}
+
+impl<DS: StreamDataStore> DataStoreWrite for BloomDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        // the inner write and the insert into the filter are done under the lock, so a
+        // concurrent rebuild either sees the key in the inner datastore or runs after the
+        // insert, never a false negative for readers.
+        let mut bloom = self.bloom.write();
+        self.datastore.put(key.clone(), value)?;
+        bloom.filter.insert(&key);
+        if bloom.filter.false_positive_rate() > bloom.false_positive_rate {
+            self.rebuild_locked(&mut bloom)?;
+        }
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.delete(key)?;
+        let stale = {
+            let mut bloom = self.bloom.write();
+            bloom.deleted += 1;
+            bloom.deleted >= self.rebuild_threshold
+        };
+        if stale {
+            self.rebuild()?;
+        }
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let mut bloom = self.bloom.write();
+        let value = self.datastore.increment(key, delta)?;
+        bloom.filter.insert(key);
+        if bloom.filter.false_positive_rate() > bloom.false_positive_rate {
+            self.rebuild_locked(&mut bloom)?;
+        }
+        Ok(value)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl<DS: StreamDataStore + CheckedDataStore> Check for BloomDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.datastore.check()
+    }
+}
+
+impl<DS: StreamDataStore + GcDataStore> Gc for BloomDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.datastore.collect_garbage()
+    }
+}
+
+impl<DS: StreamDataStore + PersistentDataStore> Persistent for BloomDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<DS: StreamDataStore + ScrubbedDataStore> Scrub for BloomDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.datastore.scrub()
+    }
+}
+
+impl<DS: StreamDataStore> ToBatch for BloomDataStore<DS> {
+    type Batch = BasicBatchDataStore<BloomDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: StreamDataStore> ToTxn for BloomDataStore<DS> {
+    type Txn = BasicTxnDataStore<BloomDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+    use crate::store::ToStream;
+
+    // Count the reads reaching the datastore.
+    #[derive(Clone)]
+    struct CountingDataStore {
+        reads: Arc<AtomicUsize>,
+        datastore: SyncDataStore<MapDataStore>,
+    }
+
+    impl CountingDataStore {
+        fn new() -> Self {
+            Self {
+                reads: Arc::new(AtomicUsize::new(0)),
+                datastore: SyncDataStore::new(MapDataStore::new()),
+            }
+        }
+    }
+
+    impl DataStore for CountingDataStore {
+        fn sync<K: Borrow<Key>>(&mut self, prefix: &K) -> Result<()> {
+            self.datastore.sync(prefix)
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.datastore.close()
+        }
+    }
+
+    impl DataStoreRead for CountingDataStore {
+        fn get<K: Borrow<Key>>(&self, key: &K) -> Result<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.datastore.get(key)
+        }
+
+        fn has<K: Borrow<Key>>(&self, key: &K) -> Result<bool> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.datastore.has(key)
+        }
+
+        fn size<K: Borrow<Key>>(&self, key: &K) -> Result<usize> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.datastore.size(key)
+        }
+    }
+
+    impl DataStoreWrite for CountingDataStore {
+        fn put<K: Into<Key>, V: Into<Vec<u8>>>(&mut self, key: K, value: V) -> Result<()> {
+            self.datastore.put(key, value)
+        }
+
+        fn delete<K: Borrow<Key>>(&mut self, key: &K) -> Result<()> {
+            self.datastore.delete(key)
+        }
+    }
+
+    impl ToStream for CountingDataStore {
+        type Stream = <SyncDataStore<MapDataStore> as ToStream>::Stream;
+
+        fn async_stream(&self) -> Self::Stream {
+            self.datastore.async_stream()
+        }
+    }
+
+    #[test]
+    fn test_no_false_negative() {
+        let mut inner = SyncDataStore::new(MapDataStore::new());
+        inner.put(Key::new("/existing"), b"value".to_vec()).unwrap();
+
+        let mut store = BloomDataStore::with_config(inner, 100, 0.01, 10).unwrap();
+        assert!(store.has(&Key::new("/existing")).unwrap());
+        // exceed the capacity to trigger a rebuild.
+        for i in 0..500 {
+            store.put(Key::new(format!("/{}", i)), vec![0]).unwrap();
+        }
+        for i in 0..500 {
+            assert!(store.has(&Key::new(format!("/{}", i))).unwrap());
+        }
+
+        // the deleted keys are absent, the others are still present after the rebuild.
+        for i in 0..20 {
+            store.delete(&Key::new(format!("/{}", i))).unwrap();
+        }
+        for i in 0..500 {
+            assert_eq!(store.has(&Key::new(format!("/{}", i))).unwrap(), i >= 20);
+        }
+        assert!(store.has(&Key::new("/existing")).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_rebuild() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let store = BloomDataStore::with_config(inner, 1000, 0.01, 1000).unwrap();
+        let rebuilder = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    store.rebuild().unwrap();
+                }
+            })
+        };
+        let mut writer = store.clone();
+        for i in 0..500 {
+            writer.put(Key::new(format!("/{}", i)), vec![0]).unwrap();
+        }
+        rebuilder.join().unwrap();
+        // a rebuild racing with a put never drops the key from the filter.
+        for i in 0..500 {
+            assert!(
+                store.may_contain(&Key::new(format!("/{}", i))),
+                "key /{}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_saturation_rebuild() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let mut store = BloomDataStore::with_capacity(inner, 100, 0.01).unwrap();
+        assert_eq!(store.false_positive_rate(), 0.0);
+
+        // overwriting the same key doesn't saturate the filter.
+        for i in 0..1000 {
+            store.put(Key::new("/same"), vec![i as u8]).unwrap();
+        }
+        assert_eq!(store.bloom.read().capacity, 100);
+
+        // the filter is rebuilt larger once it saturates past the target.
+        for i in 0..1000 {
+            store.put(Key::new(format!("/{}", i)), vec![0]).unwrap();
+            assert!(store.false_positive_rate() <= 0.01);
+        }
+        assert!(store.bloom.read().capacity > 100);
+        for i in 0..1000 {
+            assert!(store.has(&Key::new(format!("/{}", i))).unwrap());
+        }
+        assert!(store.has(&Key::new("/same")).unwrap());
+    }
+
+    #[test]
+    fn test_absent_key_short_circuit() {
+        let inner = CountingDataStore::new();
+        let reads = inner.reads.clone();
+        let mut store = BloomDataStore::new(inner).unwrap();
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+
+        let mut skipped = 0;
+        for i in 0..100 {
+            let key = Key::new(format!("/absent/{}", i));
+            if !store.may_contain(&key) {
+                skipped += 1;
+            }
+            assert!(!store.has(&key).unwrap());
+            assert!(matches!(store.get(&key), Err(DataStoreError::NotFound(_))));
+        }
+        // only the false positives reach the inner datastore.
+        assert_eq!(reads.load(Ordering::SeqCst), (100 - skipped) * 2);
+        assert!(skipped >= 95);
+
+        assert_eq!(store.get(&Key::new("/a")).unwrap(), b"a".to_vec());
+        assert_eq!(reads.load(Ordering::SeqCst), (100 - skipped) * 2 + 1);
+    }
+
+    #[test]
+    fn test_has_many() {
+        let inner = CountingDataStore::new();
+        let reads = inner.reads.clone();
+        let mut store = BloomDataStore::new(inner).unwrap();
+        for i in (0..20).step_by(2) {
+            store.put(Key::new(format!("/{}", i)), vec![0]).unwrap();
+        }
+
+        let keys = (0..20)
+            .map(|i| Key::new(format!("/{}", i)))
+            .collect::<Vec<_>>();
+        let flags = store.has_many(&keys).unwrap();
+        assert_eq!(flags.len(), keys.len());
+        for (i, present) in flags.into_iter().enumerate() {
+            assert_eq!(present, i % 2 == 0, "key /{}", i);
+        }
+        // the definite-absent keys don't reach the inner datastore.
+        let candidates = keys.iter().filter(|key| store.may_contain(key)).count();
+        assert_eq!(reads.load(Ordering::SeqCst), candidates);
+        assert!(candidates < keys.len());
+
+        let plain = SyncDataStore::new(MapDataStore::new());
+        assert_eq!(plain.has_many(&keys).unwrap(), vec![false; keys.len()]);
+        assert!(store.has_many(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore, ChangeOp};
+use crate::key::Key;
+use crate::store::{add_counter, overlay_keys, ToBatch, ToTxn};
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+
+/// ToBranch is an interface for creating copy-on-write branches of data stores.
+pub trait ToBranch: DataStore {
+    /// Create a new branch layered over the datastore.
+    fn branch(&self) -> BranchDataStore<Self> {
+        BranchDataStore::new(self.clone())
+    }
+}
+impl<T: DataStore> ToBranch for T {}
+
+/// BranchDataStore layers a pending overlay over the parent datastore,
+/// reads fall through to the parent and writes go to the overlay.
+///
+/// The overlay is folded into the parent by `commit` or dropped by `discard`.
+/// Unlike the basic txn datastore, the pending writes are visible to the reads of the branch,
+/// and a branch can be branched again.
+///
+/// The clones of a branch share the same overlay,
+/// so the parent should be a datastore whose clones share the same storage.
+pub struct BranchDataStore<DS: DataStore> {
+    overlay: Arc<RwLock<HashMap<Key, ChangeOp>>>,
+    parent: DS,
+}
+
+impl<DS: DataStore> Clone for BranchDataStore<DS> {
+    fn clone(&self) -> Self {
+        Self {
+            overlay: self.overlay.clone(),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+impl<DS: DataStore> BranchDataStore<DS> {
+    /// Create a new branch layered over the `parent` datastore.
+    pub fn new(parent: DS) -> Self {
+        Self {
+            overlay: Arc::new(RwLock::new(HashMap::new())),
+            parent,
+        }
+    }
+
+    /// Return the parent datastore.
+    pub fn parent(&self) -> &DS {
+        &self.parent
+    }
+
+    /// Return the number of the pending writes of the branch.
+    pub fn pending_len(&self) -> usize {
+        self.overlay.read().len()
+    }
+
+    /// Return the pending writes of the branch, ordered by key.
+    pub fn changes(&self) -> Vec<(Key, ChangeOp)> {
+        let mut changes = self
+            .overlay
+            .read()
+            .iter()
+            .map(|(key, op)| (key.clone(), op.clone()))
+            .collect::<Vec<_>>();
+        changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        changes
+    }
+}
+
+impl<DS: DataStore> DataStore for BranchDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        // the pending writes are not durable until committed.
+        self.parent.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.parent.close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for BranchDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        match self.overlay.read().get(key.borrow()) {
+            Some(ChangeOp::Put(value)) => Ok(value.clone()),
+            Some(ChangeOp::Delete) => Err(DataStoreError::NotFound(key.borrow().to_string())),
+            None => self.parent.get(key),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        match self.overlay.read().get(key.borrow()) {
+            Some(ChangeOp::Put(_)) => Ok(true),
+            Some(ChangeOp::Delete) => Ok(false),
+            None => self.parent.has(key),
+        }
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        match self.overlay.read().get(key.borrow()) {
+            Some(ChangeOp::Put(value)) => Ok(value.len()),
+            Some(ChangeOp::Delete) => Err(DataStoreError::NotFound(key.borrow().to_string())),
+            None => self.parent.size(key),
+        }
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let overlay = self.overlay.read();
+        let keys = self.parent.keys(prefix)?;
+        let pending = overlay.iter().map(|(key, op)| match op {
+            ChangeOp::Put(_) => (key, true),
+            ChangeOp::Delete => (key, false),
+        });
+        Ok(overlay_keys(keys, pending, prefix))
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for BranchDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.overlay
+            .write()
+            .insert(key.into(), ChangeOp::Put(value.into()));
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.overlay
+            .write()
+            .insert(key.borrow().to_owned(), ChangeOp::Delete);
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let mut overlay = self.overlay.write();
+        let current = match overlay.get(key) {
+            Some(ChangeOp::Put(value)) => Some(value.clone()),
+            Some(ChangeOp::Delete) => None,
+            None => self
+                .parent
+                .read_snapshot(std::slice::from_ref(key))?
+                .remove(0),
+        };
+        let value = add_counter(key, current.as_deref(), delta)?;
+        overlay.insert(key.clone(), ChangeOp::Put(value.to_be_bytes().to_vec()));
+        Ok(value)
+    }
+
+    // The pending writes stay in the overlay until `commit`, the committed ones are fenced.
+    fn fence(&mut self) -> Result<()> {
+        self.parent.fence()
+    }
+}
+
+impl<DS: DataStore> DataStoreBatch for BranchDataStore<DS> {
+    fn commit(&mut self) -> Result<()> {
+        // hold the lock, the reads of the branch never observe a partial commit.
+        let mut overlay = self.overlay.write();
+        for (key, op) in overlay.iter() {
+            match op {
+                ChangeOp::Put(value) => self.parent.put(key, value.to_owned())?,
+                ChangeOp::Delete => self.parent.delete(key)?,
+            }
+        }
+        overlay.clear();
+        Ok(())
+    }
+}
+
+impl<DS: DataStore> DataStoreTxn for BranchDataStore<DS> {
+    fn discard(&mut self) -> Result<()> {
+        self.overlay.write().clear();
+        Ok(())
+    }
+}
+
+impl<DS: CheckedDataStore> Check for BranchDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.parent.check()
+    }
+}
+
+impl<DS: GcDataStore> Gc for BranchDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.parent.collect_garbage()
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for BranchDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.parent.disk_usage()
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for BranchDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.parent.scrub()
+    }
+}
+
+impl<DS: DataStore> ToBatch for BranchDataStore<DS> {
+    type Batch = BasicBatchDataStore<BranchDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for BranchDataStore<DS> {
+    type Txn = BasicTxnDataStore<BranchDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    #[test]
+    fn test_discard_nested_branch() {
+        let mut root = SyncDataStore::new(MapDataStore::new());
+        root.put(Key::new("/a"), b"1".to_vec()).unwrap();
+
+        let mut outer = root.branch();
+        outer.put(Key::new("/b"), b"2".to_vec()).unwrap();
+
+        let mut inner = outer.branch();
+        assert_eq!(inner.get(&Key::new("/b")).unwrap(), b"2".to_vec());
+        inner.put(Key::new("/a"), b"3".to_vec()).unwrap();
+        inner.delete(&Key::new("/b")).unwrap();
+        inner.put(Key::new("/c"), b"4".to_vec()).unwrap();
+        assert_eq!(inner.get(&Key::new("/a")).unwrap(), b"3".to_vec());
+        assert!(!inner.has(&Key::new("/b")).unwrap());
+        assert_eq!(inner.size(&Key::new("/c")).unwrap(), 1);
+        assert_eq!(
+            inner.keys(&Key::new("/")).unwrap(),
+            vec![Key::new("/a"), Key::new("/c")]
+        );
+        assert_eq!(
+            inner.changes(),
+            vec![
+                (Key::new("/a"), ChangeOp::Put(b"3".to_vec())),
+                (Key::new("/b"), ChangeOp::Delete),
+                (Key::new("/c"), ChangeOp::Put(b"4".to_vec())),
+            ]
+        );
+
+        inner.discard().unwrap();
+        assert_eq!(inner.get(&Key::new("/a")).unwrap(), b"1".to_vec());
+
+        // the outer branch and the root are untouched.
+        assert_eq!(outer.get(&Key::new("/a")).unwrap(), b"1".to_vec());
+        assert_eq!(outer.get(&Key::new("/b")).unwrap(), b"2".to_vec());
+        assert!(!outer.has(&Key::new("/c")).unwrap());
+        assert_eq!(outer.pending_len(), 1);
+        assert_eq!(root.get(&Key::new("/a")).unwrap(), b"1".to_vec());
+        assert!(!root.has(&Key::new("/b")).unwrap());
+        assert!(!root.has(&Key::new("/c")).unwrap());
+    }
+
+    #[test]
+    fn test_commit_nested_branch() {
+        let mut root = SyncDataStore::new(MapDataStore::new());
+        root.put(Key::new("/a"), b"1".to_vec()).unwrap();
+
+        let mut outer = root.branch();
+        let mut inner = outer.branch();
+        inner.delete(&Key::new("/a")).unwrap();
+        inner.put(Key::new("/b"), b"2".to_vec()).unwrap();
+
+        inner.commit().unwrap();
+        assert_eq!(inner.pending_len(), 0);
+        assert!(!outer.has(&Key::new("/a")).unwrap());
+        assert_eq!(outer.get(&Key::new("/b")).unwrap(), b"2".to_vec());
+        assert!(root.has(&Key::new("/a")).unwrap());
+        assert!(!root.has(&Key::new("/b")).unwrap());
+
+        outer.commit().unwrap();
+        assert!(!root.has(&Key::new("/a")).unwrap());
+        assert_eq!(root.get(&Key::new("/b")).unwrap(), b"2".to_vec());
+    }
+}
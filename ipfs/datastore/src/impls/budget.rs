@@ -0,0 +1,530 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::error::Result;
+use crate::impls::{read_snapshot_through, BasicBatchDataStore, BasicTxnDataStore, EvictionPolicy};
+use crate::key::Key;
+use crate::store::{add_counter, overlay_keys, ToBatch, ToTxn};
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+
+/// WriteMode decides when the writes of `BudgetedCacheDataStore` reach the backing datastore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Writes go to the backing datastore immediately.
+    WriteThrough,
+    /// Writes stay in the cache as dirty entries, which are written to the backing
+    /// datastore when they are evicted or flushed.
+    WriteBack,
+}
+
+struct Cached {
+    value: Vec<u8>,
+    dirty: bool,
+    // the order of the write, the dirty entries are flushed in this order.
+    written: u64,
+}
+
+struct Budget<P: EvictionPolicy, DS: DataStore> {
+    max_bytes: usize,
+    bytes: usize,
+    mode: WriteMode,
+    values: HashMap<Key, Cached>,
+    writes: u64,
+    policy: P,
+    datastore: DS,
+}
+
+// The bytes accounted for an entry.
+fn entry_size(key: &Key, value: &[u8]) -> usize {
+    key.as_bytes().len() + value.len()
+}
+
+impl<P: EvictionPolicy, DS: DataStore> Budget<P, DS> {
+    fn get(&mut self, key: &Key) -> Option<Vec<u8>> {
+        let value = self.values.get(key)?.value.clone();
+        self.policy.on_access(key);
+        Some(value)
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Cached> {
+        let cached = self.values.remove(key)?;
+        self.bytes -= entry_size(key, &cached.value);
+        self.policy.on_remove(key);
+        Some(cached)
+    }
+
+    // Cache the entry, evicting the entries chosen by the policy until it fits the budget.
+    // The evicted dirty entries are flushed to the datastore before being dropped, and the
+    // old entry of the key is only replaced once they are, so a failed flush loses nothing.
+    fn insert(&mut self, key: Key, value: Vec<u8>, dirty: bool) -> Result<()> {
+        let size = entry_size(&key, &value);
+        if size > self.max_bytes {
+            // the entry never fits, bypass the cache.
+            if dirty {
+                self.datastore.put(key.clone(), value)?;
+            }
+            self.remove(&key);
+            return Ok(());
+        }
+        let mut old_size = self
+            .values
+            .get(&key)
+            .map_or(0, |cached| entry_size(&key, &cached.value));
+        while self.bytes - old_size + size > self.max_bytes {
+            let candidate = match self.policy.evict_candidate() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            if let Some(cached) = self.values.get(&candidate) {
+                if cached.dirty {
+                    if let Err(err) = self.datastore.put(candidate.clone(), cached.value.clone()) {
+                        // keep the entry tracked, it's still the only copy of the value.
+                        self.policy.on_insert(&candidate);
+                        return Err(err);
+                    }
+                }
+            }
+            if let Some(cached) = self.values.remove(&candidate) {
+                self.bytes -= entry_size(&candidate, &cached.value);
+                if candidate == key {
+                    old_size = 0;
+                }
+            }
+        }
+        self.remove(&key);
+        self.bytes += size;
+        self.policy.on_insert(&key);
+        self.writes += 1;
+        let written = self.writes;
+        self.values.insert(
+            key,
+            Cached {
+                value,
+                dirty,
+                written,
+            },
+        );
+        Ok(())
+    }
+
+    // Write the dirty entries to the datastore in the order they were written.
+    fn flush(&mut self) -> Result<()> {
+        let mut dirty = self
+            .values
+            .iter()
+            .filter(|(_, cached)| cached.dirty)
+            .map(|(key, cached)| (cached.written, key.clone()))
+            .collect::<Vec<_>>();
+        dirty.sort();
+        for (_, key) in dirty {
+            let cached = self.values.get_mut(&key).expect("the entry is cached; qed");
+            self.datastore.put(key, cached.value.clone())?;
+            cached.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// BudgetedCacheDataStore is a read-through cache in front of the backing datastore that
+/// keeps the total bytes (keys and values) of the cached entries under a budget.
+///
+/// Rather than rejecting the writes exceeding the budget, the least valuable entries chosen
+/// by the `EvictionPolicy` are evicted. In `WriteMode::WriteBack` mode, the dirty entries are
+/// flushed to the backing datastore before being evicted, so no write is lost.
+pub struct BudgetedCacheDataStore<P: EvictionPolicy, DS: DataStore> {
+    budget: Arc<Mutex<Budget<P, DS>>>,
+}
+
+impl<P: EvictionPolicy, DS: DataStore> Clone for BudgetedCacheDataStore<P, DS> {
+    fn clone(&self) -> Self {
+        Self {
+            budget: self.budget.clone(),
+        }
+    }
+}
+
+impl<P: EvictionPolicy + Default, DS: DataStore> BudgetedCacheDataStore<P, DS> {
+    /// Create a new BudgetedCacheDataStore which caches at most `max_bytes` bytes.
+    pub fn new(max_bytes: usize, mode: WriteMode, datastore: DS) -> Self {
+        Self::with_policy(max_bytes, mode, P::default(), datastore)
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> BudgetedCacheDataStore<P, DS> {
+    /// Create a new BudgetedCacheDataStore with the given eviction policy.
+    pub fn with_policy(max_bytes: usize, mode: WriteMode, policy: P, datastore: DS) -> Self {
+        Self {
+            budget: Arc::new(Mutex::new(Budget {
+                max_bytes,
+                bytes: 0,
+                mode,
+                values: HashMap::new(),
+                writes: 0,
+                policy,
+                datastore,
+            })),
+        }
+    }
+
+    /// Return whether the `key` is in the cache.
+    pub fn is_cached<K>(&self, key: &K) -> bool
+    where
+        K: Borrow<Key>,
+    {
+        self.budget.lock().values.contains_key(key.borrow())
+    }
+
+    /// Return the total bytes of the cached entries.
+    pub fn cached_bytes(&self) -> usize {
+        self.budget.lock().bytes
+    }
+
+    /// Return the number of the dirty entries, which are not written to the backing datastore yet.
+    pub fn dirty_len(&self) -> usize {
+        self.budget
+            .lock()
+            .values
+            .values()
+            .filter(|cached| cached.dirty)
+            .count()
+    }
+
+    /// Write all the dirty entries to the backing datastore, in the order they were written.
+    pub fn flush(&self) -> Result<()> {
+        self.budget.lock().flush()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStore for BudgetedCacheDataStore<P, DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let mut budget = self.budget.lock();
+        budget.flush()?;
+        budget.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let mut budget = self.budget.lock();
+        budget.flush()?;
+        budget.datastore.close()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStoreRead for BudgetedCacheDataStore<P, DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        let mut budget = self.budget.lock();
+        if let Some(value) = budget.get(key) {
+            return Ok(value);
+        }
+        let value = budget.datastore.get(key)?;
+        budget.insert(key.clone(), value.clone(), false)?;
+        Ok(value)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let budget = self.budget.lock();
+        if budget.values.contains_key(key.borrow()) {
+            return Ok(true);
+        }
+        budget.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let budget = self.budget.lock();
+        if let Some(cached) = budget.values.get(key.borrow()) {
+            return Ok(cached.value.len());
+        }
+        budget.datastore.size(key)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        // the dirty entries are only in the cache, they're read under the same lock.
+        let budget = self.budget.lock();
+        read_snapshot_through(
+            keys,
+            |key| {
+                budget
+                    .values
+                    .get(key)
+                    .map(|cached| Some(cached.value.clone()))
+            },
+            &budget.datastore,
+        )
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        // the dirty entries are only in the cache, they're listed under the same lock.
+        let budget = self.budget.lock();
+        let keys = budget.datastore.keys(prefix)?;
+        let dirty = budget
+            .values
+            .iter()
+            .filter(|(_, cached)| cached.dirty)
+            .map(|(key, _)| (key, true));
+        Ok(overlay_keys(keys, dirty, prefix))
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStoreWrite for BudgetedCacheDataStore<P, DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let (key, value) = (key.into(), value.into());
+        let mut budget = self.budget.lock();
+        match budget.mode {
+            WriteMode::WriteThrough => {
+                budget.datastore.put(key.clone(), value.clone())?;
+                budget.insert(key, value, false)
+            }
+            WriteMode::WriteBack => budget.insert(key, value, true),
+        }
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let mut budget = self.budget.lock();
+        budget.datastore.delete(key)?;
+        budget.remove(key.borrow());
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let mut budget = self.budget.lock();
+        match budget.mode {
+            WriteMode::WriteThrough => {
+                let value = budget.datastore.increment(key, delta)?;
+                budget.insert(key.clone(), value.to_be_bytes().to_vec(), false)?;
+                Ok(value)
+            }
+            // the latest value may be dirty in the cache, read-modify-write it under the lock.
+            WriteMode::WriteBack => {
+                let current = match budget.get(key) {
+                    Some(value) => Some(value),
+                    None => budget
+                        .datastore
+                        .read_snapshot(std::slice::from_ref(key))?
+                        .remove(0),
+                };
+                let value = add_counter(key, current.as_deref(), delta)?;
+                budget.insert(key.clone(), value.to_be_bytes().to_vec(), true)?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        let mut budget = self.budget.lock();
+        budget.flush()?;
+        budget.datastore.fence()
+    }
+}
+
+impl<P: EvictionPolicy, DS: CheckedDataStore> Check for BudgetedCacheDataStore<P, DS> {
+    fn check(&self) -> Result<()> {
+        self.budget.lock().datastore.check()
+    }
+}
+
+impl<P: EvictionPolicy, DS: GcDataStore> Gc for BudgetedCacheDataStore<P, DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.budget.lock().datastore.collect_garbage()
+    }
+}
+
+impl<P: EvictionPolicy, DS: PersistentDataStore> Persistent for BudgetedCacheDataStore<P, DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.budget.lock().datastore.disk_usage()
+    }
+}
+
+impl<P: EvictionPolicy, DS: ScrubbedDataStore> Scrub for BudgetedCacheDataStore<P, DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.budget.lock().datastore.scrub()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> ToBatch for BudgetedCacheDataStore<P, DS> {
+    type Batch = BasicBatchDataStore<BudgetedCacheDataStore<P, DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> ToTxn for BudgetedCacheDataStore<P, DS> {
+    type Txn = BasicTxnDataStore<BudgetedCacheDataStore<P, DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DataStoreError;
+    use crate::impls::{ChangeOp, FailBuilder, FailDataStore, LfuPolicy, LruPolicy};
+    use crate::impls::{MapDataStore, SequencedDataStore, SyncDataStore};
+
+    // "/{i}" and the value of 18 bytes, 20 bytes per entry.
+    fn entry(i: usize) -> (Key, Vec<u8>) {
+        (Key::new(format!("/{}", i)), vec![i as u8; 18])
+    }
+
+    #[test]
+    fn test_budget_write_back() {
+        let backing = SyncDataStore::new(MapDataStore::new());
+        let mut store =
+            BudgetedCacheDataStore::<LfuPolicy, _>::new(100, WriteMode::WriteBack, backing.clone());
+
+        for i in 0..5 {
+            let (key, value) = entry(i);
+            store.put(key, value).unwrap();
+        }
+        assert_eq!(store.cached_bytes(), 100);
+        // the most valuable entries by LFU.
+        for _ in 0..3 {
+            store.get(&entry(0).0).unwrap();
+            store.get(&entry(1).0).unwrap();
+        }
+
+        for i in 5..10 {
+            let (key, value) = entry(i);
+            store.put(key, value).unwrap();
+            assert!(store.cached_bytes() <= 100);
+        }
+        assert!(store.is_cached(&entry(0).0));
+        assert!(store.is_cached(&entry(1).0));
+
+        // the evicted dirty entries are flushed before being dropped.
+        for i in 0..10 {
+            let (key, value) = entry(i);
+            assert_eq!(store.is_cached(&key), backing.get(&key).is_err());
+            assert_eq!(store.get(&key).unwrap(), value);
+            assert!(store.cached_bytes() <= 100);
+        }
+        assert!(store.dirty_len() > 0);
+        // the snapshot reads the dirty entries from the cache, the others from the backing.
+        let mut keys = (0..10).map(|i| entry(i).0).collect::<Vec<_>>();
+        keys.push(Key::new("/absent"));
+        let mut expected = (0..10).map(|i| Some(entry(i).1)).collect::<Vec<_>>();
+        expected.push(None);
+        assert_eq!(store.read_snapshot(&keys).unwrap(), expected);
+        store.flush().unwrap();
+        assert_eq!(store.dirty_len(), 0);
+        for i in 0..10 {
+            let (key, value) = entry(i);
+            assert_eq!(backing.get(&key).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_budget_failed_eviction() {
+        let fail_fn = FailBuilder::new()
+            .fail_under("put", "/0", DataStoreError::Timeout("put".into()))
+            .build();
+        let backing = FailDataStore::new(fail_fn, SyncDataStore::new(MapDataStore::new()));
+        let mut store =
+            BudgetedCacheDataStore::<LruPolicy, _>::new(40, WriteMode::WriteBack, backing);
+        for i in 0..2 {
+            let (key, value) = entry(i);
+            store.put(key, value).unwrap();
+        }
+
+        // growing "/1" evicts "/0", whose flush fails.
+        let (key, value) = entry(1);
+        assert!(store.put(key.clone(), vec![0; 38]).is_err());
+        // both dirty entries are kept, including the old value of the replaced key.
+        assert_eq!(store.dirty_len(), 2);
+        assert_eq!(store.cached_bytes(), 40);
+        assert_eq!(store.get(&key).unwrap(), value);
+        assert_eq!(store.get(&entry(0).0).unwrap(), entry(0).1);
+    }
+
+    #[test]
+    fn test_budget_write_through() {
+        let backing = SyncDataStore::new(MapDataStore::new());
+        let mut store = BudgetedCacheDataStore::<LruPolicy, _>::new(
+            50,
+            WriteMode::WriteThrough,
+            backing.clone(),
+        );
+        for i in 0..4 {
+            let (key, value) = entry(i);
+            store.put(key.clone(), value.clone()).unwrap();
+            assert_eq!(backing.get(&key).unwrap(), value);
+        }
+        assert_eq!(store.cached_bytes(), 40);
+        assert!(!store.is_cached(&entry(0).0));
+        assert!(!store.is_cached(&entry(1).0));
+        assert_eq!(store.dirty_len(), 0);
+
+        // the entry exceeding the whole budget bypasses the cache.
+        store.put(Key::new("/large"), vec![0; 64]).unwrap();
+        assert!(!store.is_cached(&Key::new("/large")));
+        assert_eq!(store.cached_bytes(), 40);
+        assert_eq!(store.size(&Key::new("/large")).unwrap(), 64);
+
+        store.delete(&entry(3).0).unwrap();
+        assert_eq!(store.cached_bytes(), 20);
+        assert!(!store.has(&entry(3).0).unwrap());
+    }
+
+    #[test]
+    fn test_budget_fence_order() {
+        let disk = SyncDataStore::new(MapDataStore::new());
+        let journal = SequencedDataStore::new(disk.clone()).unwrap();
+        let mut store =
+            BudgetedCacheDataStore::<LruPolicy, _>::new(1000, WriteMode::WriteBack, journal);
+
+        for i in (0..5).rev() {
+            let (key, value) = entry(i);
+            store.put(key, value).unwrap();
+        }
+        store.fence().unwrap();
+        assert_eq!(store.dirty_len(), 0);
+        store.put(Key::new("/after"), vec![0; 4]).unwrap();
+        // crash, the write after the fence is lost.
+        drop(store);
+
+        let journal = SequencedDataStore::new(disk).unwrap();
+        let changes = journal.since(0).unwrap();
+        let expected = (0..5).rev().map(entry).collect::<Vec<_>>();
+        assert_eq!(changes.len(), expected.len());
+        for (change, (key, value)) in changes.into_iter().zip(expected) {
+            assert_eq!(change.key, key);
+            assert_eq!(change.op, ChangeOp::Put(value));
+        }
+        assert!(!journal.has(&Key::new("/after")).unwrap());
+    }
+}
@@ -0,0 +1,503 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::error::Result;
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+/// EvictionPolicy decides which entry should be evicted when the cache is full.
+///
+/// The policy is always called under the lock of the cache,
+/// so implementations don't need to do any synchronization.
+pub trait EvictionPolicy: Send {
+    /// Called when the cached `key` is read.
+    fn on_access(&mut self, key: &Key);
+
+    /// Called when the `key` is inserted into the cache, or its value is updated.
+    fn on_insert(&mut self, key: &Key);
+
+    /// Called when the `key` is removed from the cache.
+    fn on_remove(&mut self, key: &Key);
+
+    /// Return the key that should be evicted, the key is no longer tracked by the policy.
+    fn evict_candidate(&mut self) -> Option<Key>;
+}
+
+// Track keys by a monotonic tick, the smallest tick is the eviction candidate.
+#[derive(Clone, Debug, Default)]
+struct TickOrder {
+    tick: u64,
+    ticks: HashMap<Key, u64>,
+    order: BTreeMap<u64, Key>,
+}
+
+impl TickOrder {
+    fn touch(&mut self, key: &Key) {
+        self.remove(key);
+        self.tick += 1;
+        self.ticks.insert(key.clone(), self.tick);
+        self.order.insert(self.tick, key.clone());
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        self.ticks.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn pop_first(&mut self) -> Option<Key> {
+        let tick = *self.order.keys().next()?;
+        let key = self.order.remove(&tick)?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
+}
+
+/// LruPolicy evicts the least recently used entry.
+#[derive(Clone, Debug, Default)]
+pub struct LruPolicy(TickOrder);
+
+impl EvictionPolicy for LruPolicy {
+    fn on_access(&mut self, key: &Key) {
+        self.0.touch(key)
+    }
+
+    fn on_insert(&mut self, key: &Key) {
+        self.0.touch(key)
+    }
+
+    fn on_remove(&mut self, key: &Key) {
+        self.0.remove(key)
+    }
+
+    fn evict_candidate(&mut self) -> Option<Key> {
+        self.0.pop_first()
+    }
+}
+
+/// FifoPolicy evicts the entry that was inserted first, accesses don't affect the order.
+#[derive(Clone, Debug, Default)]
+pub struct FifoPolicy(TickOrder);
+
+impl EvictionPolicy for FifoPolicy {
+    fn on_access(&mut self, _key: &Key) {}
+
+    fn on_insert(&mut self, key: &Key) {
+        // updating the value of a cached key doesn't change its position.
+        if !self.0.contains(key) {
+            self.0.touch(key)
+        }
+    }
+
+    fn on_remove(&mut self, key: &Key) {
+        self.0.remove(key)
+    }
+
+    fn evict_candidate(&mut self) -> Option<Key> {
+        self.0.pop_first()
+    }
+}
+
+/// LfuPolicy evicts the least frequently used entry,
+/// ties are broken by evicting the least recently used one.
+#[derive(Clone, Debug, Default)]
+pub struct LfuPolicy {
+    tick: u64,
+    // key => (frequency, tick)
+    entries: HashMap<Key, (u64, u64)>,
+    order: BTreeSet<(u64, u64, Key)>,
+}
+
+impl LfuPolicy {
+    fn bump(&mut self, key: &Key) {
+        self.tick += 1;
+        let (freq, tick) = self.entries.get(key).copied().unwrap_or((0, 0));
+        self.order.remove(&(freq, tick, key.clone()));
+        self.entries.insert(key.clone(), (freq + 1, self.tick));
+        self.order.insert((freq + 1, self.tick, key.clone()));
+    }
+}
+
+impl EvictionPolicy for LfuPolicy {
+    fn on_access(&mut self, key: &Key) {
+        self.bump(key)
+    }
+
+    fn on_insert(&mut self, key: &Key) {
+        self.bump(key)
+    }
+
+    fn on_remove(&mut self, key: &Key) {
+        if let Some((freq, tick)) = self.entries.remove(key) {
+            self.order.remove(&(freq, tick, key.clone()));
+        }
+    }
+
+    fn evict_candidate(&mut self) -> Option<Key> {
+        let first = self.order.iter().next().cloned()?;
+        self.order.remove(&first);
+        self.entries.remove(&first.2);
+        Some(first.2)
+    }
+}
+
+struct Cache<P: EvictionPolicy> {
+    capacity: usize,
+    values: HashMap<Key, Vec<u8>>,
+    policy: P,
+}
+
+impl<P: EvictionPolicy> Cache<P> {
+    fn get(&mut self, key: &Key) -> Option<Vec<u8>> {
+        let value = self.values.get(key)?.clone();
+        self.policy.on_access(key);
+        Some(value)
+    }
+
+    fn insert(&mut self, key: Key, value: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        // make room before the new key is tracked, so that it can't be the candidate.
+        if !self.values.contains_key(&key) {
+            while self.values.len() >= self.capacity {
+                match self.policy.evict_candidate() {
+                    Some(candidate) => {
+                        self.values.remove(&candidate);
+                    }
+                    None => break,
+                }
+            }
+        }
+        self.policy.on_insert(&key);
+        self.values.insert(key, value);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if self.values.remove(key).is_some() {
+            self.policy.on_remove(key);
+        }
+    }
+}
+
+/// CacheDataStore is a read-through, write-through cache in front of the inner datastore,
+/// entries are evicted by the given `EvictionPolicy` when the capacity is exceeded.
+pub struct CacheDataStore<P: EvictionPolicy, DS: DataStore> {
+    cache: Arc<Mutex<Cache<P>>>,
+    datastore: DS,
+}
+
+impl<P: EvictionPolicy, DS: DataStore> Clone for CacheDataStore<P, DS> {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            datastore: self.datastore.clone(),
+        }
+    }
+}
+
+impl<P: EvictionPolicy + Default, DS: DataStore> CacheDataStore<P, DS> {
+    /// Create a new CacheDataStore which caches at most `capacity` entries.
+    pub fn new(capacity: usize, datastore: DS) -> Self {
+        Self::with_policy(capacity, P::default(), datastore)
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> CacheDataStore<P, DS> {
+    /// Create a new CacheDataStore with the given eviction policy.
+    pub fn with_policy(capacity: usize, policy: P, datastore: DS) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(Cache {
+                capacity,
+                values: HashMap::with_capacity(capacity),
+                policy,
+            })),
+            datastore,
+        }
+    }
+
+    /// Return whether the `key` is in the cache.
+    pub fn is_cached<K>(&self, key: &K) -> bool
+    where
+        K: Borrow<Key>,
+    {
+        self.cache.lock().values.contains_key(key.borrow())
+    }
+
+    /// Return the number of the cached entries.
+    pub fn cached_len(&self) -> usize {
+        self.cache.lock().values.len()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStore for CacheDataStore<P, DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStoreRead for CacheDataStore<P, DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        // hold the lock on miss, avoid caching a stale value of the concurrent `put`.
+        let mut cache = self.cache.lock();
+        if let Some(value) = cache.get(key) {
+            return Ok(value);
+        }
+        let value = self.datastore.get(key)?;
+        cache.insert(key.clone(), value.clone());
+        Ok(value)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        if self.is_cached(key) {
+            return Ok(true);
+        }
+        self.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        if let Some(value) = self.cache.lock().values.get(key.borrow()) {
+            return Ok(value.len());
+        }
+        self.datastore.size(key)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        // hold the lock, the cache and the inner datastore are updated together under it.
+        let cache = self.cache.lock();
+        read_snapshot_through(
+            keys,
+            |key| cache.values.get(key).map(|value| Some(value.clone())),
+            &self.datastore,
+        )
+    }
+
+    // the cache writes through, the inner datastore has every key.
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+}
+
+/// Read the keys from the `cached` writes or values, where `Some(None)` is a cached deletion,
+/// and the missed keys from one snapshot of the datastore.
+pub(crate) fn read_snapshot_through<DS, F>(
+    keys: &[Key],
+    cached: F,
+    datastore: &DS,
+) -> Result<Vec<Option<Vec<u8>>>>
+where
+    DS: DataStore,
+    F: Fn(&Key) -> Option<Option<Vec<u8>>>,
+{
+    let hits = keys.iter().map(cached).collect::<Vec<_>>();
+    let misses = keys
+        .iter()
+        .zip(&hits)
+        .filter(|(_, hit)| hit.is_none())
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    let mut read = if misses.is_empty() {
+        Vec::new().into_iter()
+    } else {
+        datastore.read_snapshot(&misses)?.into_iter()
+    };
+    Ok(hits
+        .into_iter()
+        .map(|hit| match hit {
+            Some(value) => value,
+            None => read.next().expect("a value for each missed key; qed"),
+        })
+        .collect())
+}
+
+impl<P: EvictionPolicy, DS: DataStore> DataStoreWrite for CacheDataStore<P, DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        let value = value.into();
+        // hold the lock so that the cache and the inner datastore are updated atomically.
+        let mut cache = self.cache.lock();
+        self.datastore.put(key.clone(), value.clone())?;
+        cache.insert(key, value);
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let mut cache = self.cache.lock();
+        self.datastore.delete(key)?;
+        cache.remove(key.borrow());
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let mut cache = self.cache.lock();
+        let value = self.datastore.increment(key, delta)?;
+        cache.insert(key.clone(), value.to_be_bytes().to_vec());
+        Ok(value)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl<P: EvictionPolicy, DS: CheckedDataStore> Check for CacheDataStore<P, DS> {
+    fn check(&self) -> Result<()> {
+        self.datastore.check()
+    }
+}
+
+impl<P: EvictionPolicy, DS: GcDataStore> Gc for CacheDataStore<P, DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.datastore.collect_garbage()
+    }
+}
+
+impl<P: EvictionPolicy, DS: PersistentDataStore> Persistent for CacheDataStore<P, DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<P: EvictionPolicy, DS: ScrubbedDataStore> Scrub for CacheDataStore<P, DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.datastore.scrub()
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> ToBatch for CacheDataStore<P, DS> {
+    type Batch = BasicBatchDataStore<CacheDataStore<P, DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<P: EvictionPolicy, DS: DataStore> ToTxn for CacheDataStore<P, DS> {
+    type Txn = BasicTxnDataStore<CacheDataStore<P, DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    fn new_cache<P: EvictionPolicy + Default>() -> CacheDataStore<P, MapDataStore> {
+        CacheDataStore::new(2, MapDataStore::new())
+    }
+
+    // put a, put b, get a, get a, get b, put c
+    fn access_frequently<P: EvictionPolicy>(cache: &mut CacheDataStore<P, MapDataStore>) {
+        cache.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        cache.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        cache.get(&Key::new("/a")).unwrap();
+        cache.get(&Key::new("/a")).unwrap();
+        cache.get(&Key::new("/b")).unwrap();
+        cache.put(Key::new("/c"), b"c".to_vec()).unwrap();
+    }
+
+    // put a, put b, get a, put c
+    fn access_recently<P: EvictionPolicy>(cache: &mut CacheDataStore<P, MapDataStore>) {
+        cache.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        cache.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        cache.get(&Key::new("/a")).unwrap();
+        cache.put(Key::new("/c"), b"c".to_vec()).unwrap();
+    }
+
+    #[test]
+    fn test_lru_policy() {
+        let mut cache = new_cache::<LruPolicy>();
+        access_frequently(&mut cache);
+        assert!(!cache.is_cached(&Key::new("/a")));
+        assert!(cache.is_cached(&Key::new("/b")));
+        assert!(cache.is_cached(&Key::new("/c")));
+
+        let mut cache = new_cache::<LruPolicy>();
+        access_recently(&mut cache);
+        assert!(cache.is_cached(&Key::new("/a")));
+        assert!(!cache.is_cached(&Key::new("/b")));
+        assert!(cache.is_cached(&Key::new("/c")));
+    }
+
+    #[test]
+    fn test_lfu_policy() {
+        // LFU keeps the most accessed key `a` that LRU would drop.
+        let mut cache = new_cache::<LfuPolicy>();
+        access_frequently(&mut cache);
+        assert!(cache.is_cached(&Key::new("/a")));
+        assert!(!cache.is_cached(&Key::new("/b")));
+        assert!(cache.is_cached(&Key::new("/c")));
+    }
+
+    #[test]
+    fn test_fifo_policy() {
+        // FIFO drops `a` even though it was accessed recently.
+        let mut cache = new_cache::<FifoPolicy>();
+        access_recently(&mut cache);
+        assert!(!cache.is_cached(&Key::new("/a")));
+        assert!(cache.is_cached(&Key::new("/b")));
+        assert!(cache.is_cached(&Key::new("/c")));
+    }
+
+    #[test]
+    fn test_read_through() {
+        let mut inner = MapDataStore::new();
+        inner.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        let mut cache = CacheDataStore::<LruPolicy, _>::new(1, inner);
+        assert!(!cache.is_cached(&Key::new("/a")));
+        assert_eq!(cache.get(&Key::new("/a")).unwrap(), b"a".to_vec());
+        assert!(cache.is_cached(&Key::new("/a")));
+
+        cache.delete(&Key::new("/a")).unwrap();
+        assert!(!cache.is_cached(&Key::new("/a")));
+        assert!(!cache.has(&Key::new("/a")).unwrap());
+        assert_eq!(cache.cached_len(), 0);
+    }
+}
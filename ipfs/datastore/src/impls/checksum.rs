@@ -0,0 +1,279 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{increment_counter, Check, DataStore, DataStoreRead, DataStoreWrite, Scrub};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+// The big-endian CRC32 of the value prepended to the stored value.
+const CHECKSUM_LEN: usize = 4;
+
+fn encode(value: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(CHECKSUM_LEN + value.len());
+    bytes.extend_from_slice(&crc32fast::hash(value).to_be_bytes());
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+fn decode(key: &Key, mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.len() < CHECKSUM_LEN {
+        return Err(DataStoreError::Corrupted { key: key.clone() });
+    }
+    let checksum = u32::from_be_bytes(bytes[..CHECKSUM_LEN].try_into().expect("4 bytes; qed"));
+    if crc32fast::hash(&bytes[CHECKSUM_LEN..]) != checksum {
+        return Err(DataStoreError::Corrupted { key: key.clone() });
+    }
+    bytes.drain(..CHECKSUM_LEN);
+    Ok(bytes)
+}
+
+/// ChecksumDataStore stores the CRC32 along with every value in the inner datastore and
+/// verifies it on reading, a mismatch fails with `DataStoreError::Corrupted` of the key.
+///
+/// `scrub` verifies the checksums of all the entries and returns the keys of the mismatched
+/// ones, while `check` only validates that every stored value is long enough to hold the
+/// checksum. Both of them list the keys of the inner datastore, which must support `keys`.
+#[derive(Clone)]
+pub struct ChecksumDataStore<DS: DataStore> {
+    datastore: DS,
+    // serialize the increments of the handles.
+    lock: Arc<Mutex<()>>,
+}
+
+impl<DS: DataStore> ChecksumDataStore<DS> {
+    /// Create a new ChecksumDataStore over the `datastore`.
+    pub fn new(datastore: DS) -> Self {
+        Self {
+            datastore,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Return the inner datastore holding the values with their checksums.
+    pub fn inner(&self) -> &DS {
+        &self.datastore
+    }
+
+    /// Return the inner datastore holding the values with their checksums.
+    pub fn inner_mut(&mut self) -> &mut DS {
+        &mut self.datastore
+    }
+}
+
+impl<DS: DataStore> DataStore for ChecksumDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for ChecksumDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        decode(key, self.datastore.get(key)?)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.datastore.size(key)? {
+            size if size < CHECKSUM_LEN => Err(DataStoreError::Corrupted { key: key.clone() }),
+            size => Ok(size - CHECKSUM_LEN),
+        }
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let values = self.datastore.read_snapshot(keys)?;
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| value.map(|value| decode(key, value)).transpose())
+            .collect()
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        self.datastore.count(prefix)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for ChecksumDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.datastore.put(key, encode(&value.into()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.delete(key)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the stored value carries the checksum, read-modify-write it under the lock.
+        let lock = self.lock.clone();
+        let _guard = lock.lock();
+        increment_counter(self, key.borrow(), delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl<DS: DataStore> Check for ChecksumDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        for key in self.datastore.keys(&Key::new("/"))? {
+            match self.size(&key) {
+                Ok(_) => {}
+                Err(err) if err.is_not_found() => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<DS: DataStore> Scrub for ChecksumDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        let mut corrupted = Vec::new();
+        for key in self.datastore.keys(&Key::new("/"))? {
+            match self.get(&key) {
+                Ok(_) => {}
+                // deleted since the keys were listed.
+                Err(err) if err.is_not_found() => {}
+                Err(DataStoreError::Corrupted { key }) => corrupted.push(key),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(corrupted)
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for ChecksumDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<DS: DataStore> ToBatch for ChecksumDataStore<DS> {
+    type Batch = BasicBatchDataStore<ChecksumDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for ChecksumDataStore<DS> {
+    type Txn = BasicTxnDataStore<ChecksumDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    #[test]
+    fn test_corruption() {
+        let mut store = ChecksumDataStore::new(MapDataStore::new());
+        for i in 0..5u8 {
+            store.put(Key::new(format!("/{}", i)), vec![i; 8]).unwrap();
+        }
+        assert_eq!(store.get(&Key::new("/1")).unwrap(), vec![1; 8]);
+        assert_eq!(store.size(&Key::new("/1")).unwrap(), 8);
+        store.check().unwrap();
+        assert!(store.scrub().unwrap().is_empty());
+
+        // flip a bit of a value in the inner datastore.
+        let (a, b) = (Key::new("/1"), Key::new("/3"));
+        let mut value = store.inner().get(&a).unwrap();
+        value[CHECKSUM_LEN] ^= 1;
+        store.inner_mut().put(a.clone(), value).unwrap();
+        match store.get(&a) {
+            Err(DataStoreError::Corrupted { key }) => assert_eq!(key, a),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(store.scrub().unwrap(), vec![a.clone()]);
+        // the value is still long enough to hold a checksum, so the cheap check passes.
+        store.check().unwrap();
+
+        // truncate a value so that the checksum is lost.
+        store.inner_mut().put(b.clone(), vec![0; 2]).unwrap();
+        match store.check() {
+            Err(DataStoreError::Corrupted { key }) => assert_eq!(key, b),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(store.scrub().unwrap(), vec![a.clone(), b]);
+
+        // overwriting the corrupted value repairs it.
+        store.put(a.clone(), vec![1]).unwrap();
+        assert_eq!(store.get(&a).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_concurrent_increment() {
+        let store = ChecksumDataStore::new(SyncDataStore::new(MapDataStore::new()));
+        let key = Key::new("/counter");
+        // the threads start together to contend on the counter.
+        let barrier = Arc::new(Barrier::new(8));
+        let threads = (0..8)
+            .map(|_| {
+                let (mut store, key, barrier) = (store.clone(), key.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..1000 {
+                        store.increment(&key, 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // the counter is stored with its checksum.
+        assert_eq!(store.get(&key).unwrap(), 8000i64.to_be_bytes().to_vec());
+        assert_eq!(store.inner().size(&key).unwrap(), CHECKSUM_LEN + 8);
+    }
+}
@@ -1,6 +1,14 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::error::Result;
 use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
@@ -9,32 +17,101 @@ use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
 use crate::store::{Persistent, PersistentDataStore};
 use crate::store::{ToBatch, ToTxn};
 
-/// The delay interface for delay operation.
-pub trait Delay: Clone {
-    /// Wait for a period of time duration util time out before applying the operation.
-    fn wait(&self);
+/// The delay applied to an operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Delay {
+    /// Always wait for the duration.
+    Fixed(Duration),
+    /// Wait for a duration picked uniformly from the range.
+    Random(Range<Duration>),
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Delay::Fixed(Duration::from_secs(0))
+    }
+}
+
+impl Delay {
+    fn duration(&self, rng: &Mutex<StdRng>) -> Duration {
+        match self {
+            Delay::Fixed(duration) => *duration,
+            Delay::Random(range) if range.start >= range.end => range.start,
+            Delay::Random(range) => {
+                let (start, end) = (range.start.as_nanos() as u64, range.end.as_nanos() as u64);
+                Duration::from_nanos(rng.lock().gen_range(start, end))
+            }
+        }
+    }
+}
+
+/// The delays of each operation type, `size` is delayed like `get`, `sync` and `fence`
+/// are delayed like `put`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DelayConfig {
+    /// The delay of `get` and `size`.
+    pub get: Delay,
+    /// The delay of `put`, `sync` and `fence`.
+    pub put: Delay,
+    /// The delay of `has`.
+    pub has: Delay,
+    /// The delay of `delete`.
+    pub delete: Delay,
+}
+
+impl DelayConfig {
+    /// Create a new DelayConfig applying the same delay to every operation.
+    pub fn uniform(delay: Delay) -> Self {
+        Self {
+            get: delay.clone(),
+            put: delay.clone(),
+            has: delay.clone(),
+            delete: delay,
+        }
+    }
 }
 
 /// DelayDataStore is an adapter that delays operations on the inner datastore.
 #[derive(Clone)]
-pub struct DelayDataStore<DL: Delay, DS: DataStore> {
-    delay: DL,
+pub struct DelayDataStore<DS: DataStore> {
+    config: DelayConfig,
+    rng: Arc<Mutex<StdRng>>,
     datastore: DS,
 }
 
-impl<DL: Delay, DS: DataStore> DelayDataStore<DL, DS> {
-    /// Create a new DelayDataStore.
-    pub fn new(delay: DL, datastore: DS) -> Self {
-        Self { delay, datastore }
+impl<DS: DataStore> DelayDataStore<DS> {
+    /// Create a new DelayDataStore, the random delays are seeded from the system entropy.
+    pub fn new(config: DelayConfig, datastore: DS) -> Self {
+        Self::with_rng(config, StdRng::from_entropy(), datastore)
+    }
+
+    /// Create a new DelayDataStore whose random delays are reproducible from the `seed`.
+    pub fn with_seed(config: DelayConfig, seed: u64, datastore: DS) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed), datastore)
+    }
+
+    fn with_rng(config: DelayConfig, rng: StdRng, datastore: DS) -> Self {
+        Self {
+            config,
+            rng: Arc::new(Mutex::new(rng)),
+            datastore,
+        }
+    }
+
+    fn wait(&self, delay: &Delay) {
+        let duration = delay.duration(&self.rng);
+        if duration > Duration::from_secs(0) {
+            thread::sleep(duration);
+        }
     }
 }
 
-impl<DL: Delay, DS: DataStore> DataStore for DelayDataStore<DL, DS> {
+impl<DS: DataStore> DataStore for DelayDataStore<DS> {
     fn sync<K>(&mut self, prefix: &K) -> Result<()>
     where
         K: Borrow<Key>,
     {
-        self.delay.wait();
+        self.wait(&self.config.put);
         self.datastore.sync(prefix)
     }
 
@@ -43,12 +120,12 @@ impl<DL: Delay, DS: DataStore> DataStore for DelayDataStore<DL, DS> {
     }
 }
 
-impl<DL: Delay, DS: DataStore> DataStoreRead for DelayDataStore<DL, DS> {
+impl<DS: DataStore> DataStoreRead for DelayDataStore<DS> {
     fn get<K>(&self, key: &K) -> Result<Vec<u8>>
     where
         K: Borrow<Key>,
     {
-        self.delay.wait();
+        self.wait(&self.config.get);
         self.datastore.get(key)
     }
 
@@ -56,7 +133,7 @@ impl<DL: Delay, DS: DataStore> DataStoreRead for DelayDataStore<DL, DS> {
     where
         K: Borrow<Key>,
     {
-        self.delay.wait();
+        self.wait(&self.config.has);
         self.datastore.has(key)
     }
 
@@ -64,18 +141,28 @@ impl<DL: Delay, DS: DataStore> DataStoreRead for DelayDataStore<DL, DS> {
     where
         K: Borrow<Key>,
     {
-        self.delay.wait();
+        self.wait(&self.config.get);
         self.datastore.size(key)
     }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.wait(&self.config.get);
+        self.datastore.read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.wait(&self.config.get);
+        self.datastore.keys(prefix)
+    }
 }
 
-impl<DL: Delay, DS: DataStore> DataStoreWrite for DelayDataStore<DL, DS> {
+impl<DS: DataStore> DataStoreWrite for DelayDataStore<DS> {
     fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
     where
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.delay.wait();
+        self.wait(&self.config.put);
         self.datastore.put(key, value)
     }
 
@@ -83,30 +170,92 @@ impl<DL: Delay, DS: DataStore> DataStoreWrite for DelayDataStore<DL, DS> {
     where
         K: Borrow<Key>,
     {
-        self.delay.wait();
+        self.wait(&self.config.delete);
         self.datastore.delete(key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        self.wait(&self.config.put);
+        self.datastore.increment(key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.wait(&self.config.put);
+        self.datastore.fence()
+    }
 }
 
-impl<DL: Delay, DS: PersistentDataStore> Persistent for DelayDataStore<DL, DS> {
+impl<DS: PersistentDataStore> Persistent for DelayDataStore<DS> {
     fn disk_usage(&self) -> Result<u64> {
-        self.delay.wait();
         self.datastore.disk_usage()
     }
 }
 
-impl<DL: Delay, DS: DataStore> ToBatch for DelayDataStore<DL, DS> {
-    type Batch = BasicBatchDataStore<DelayDataStore<DL, DS>>;
+impl<DS: DataStore> ToBatch for DelayDataStore<DS> {
+    type Batch = BasicBatchDataStore<DelayDataStore<DS>>;
 
     fn batch(&self) -> Result<Self::Batch> {
         Ok(BasicBatchDataStore::new(self.clone()))
     }
 }
 
-impl<DL: Delay, DS: DataStore> ToTxn for DelayDataStore<DL, DS> {
-    type Txn = BasicTxnDataStore<DelayDataStore<DL, DS>>;
+impl<DS: DataStore> ToTxn for DelayDataStore<DS> {
+    type Txn = BasicTxnDataStore<DelayDataStore<DS>>;
 
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         Ok(BasicTxnDataStore::new(self.clone()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    #[test]
+    fn test_delay_per_operation() {
+        let delay = Duration::from_millis(50);
+        let config = DelayConfig {
+            put: Delay::Fixed(delay),
+            ..Default::default()
+        };
+        let mut store = DelayDataStore::new(config, MapDataStore::new());
+        let key = Key::new("/a");
+
+        let start = Instant::now();
+        store.put(key.clone(), vec![1]).unwrap();
+        assert!(start.elapsed() >= delay);
+
+        let start = Instant::now();
+        assert_eq!(store.get(&key).unwrap(), vec![1]);
+        assert!(store.has(&key).unwrap());
+        assert!(start.elapsed() < delay);
+    }
+
+    #[test]
+    fn test_random_delay_seed() {
+        let delay = Delay::Random(Duration::from_millis(1)..Duration::from_millis(100));
+        let durations = |seed| {
+            let store = DelayDataStore::with_seed(
+                DelayConfig::uniform(delay.clone()),
+                seed,
+                MapDataStore::new(),
+            );
+            (0..8)
+                .map(|_| store.config.get.duration(&store.rng))
+                .collect::<Vec<_>>()
+        };
+        let first = durations(7);
+        assert_eq!(durations(7), first);
+        assert_ne!(durations(8), first);
+        assert!(first
+            .iter()
+            .all(|d| *d >= Duration::from_millis(1) && *d < Duration::from_millis(100)));
+    }
+}
@@ -48,6 +48,10 @@ impl DataStoreRead for DummyDataStore {
     {
         Ok(0)
     }
+
+    fn keys(&self, _prefix: &Key) -> Result<Vec<Key>> {
+        Ok(vec![])
+    }
 }
 
 impl DataStoreWrite for DummyDataStore {
@@ -74,8 +78,8 @@ impl Check for DummyDataStore {
 }
 
 impl Gc for DummyDataStore {
-    fn collect_garbage(&self) -> Result<()> {
-        Ok(())
+    fn collect_garbage(&self) -> Result<usize> {
+        Ok(0)
     }
 }
 
@@ -86,8 +90,8 @@ impl Persistent for DummyDataStore {
 }
 
 impl Scrub for DummyDataStore {
-    fn scrub(&self) -> Result<()> {
-        Ok(())
+    fn scrub(&self) -> Result<Vec<Key>> {
+        Ok(vec![])
     }
 }
 
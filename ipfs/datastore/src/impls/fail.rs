@@ -1,8 +1,9 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::sync::Arc;
 
-use crate::error::Result;
+use crate::error::{DataStoreError, Result};
 use crate::key::Key;
 use crate::store::{BatchDataStore, ToBatch, ToTxn, TxnDataStore};
 use crate::store::{Check, CheckedBatchDataStore, CheckedDataStore, CheckedTxnDataStore};
@@ -13,8 +14,97 @@ use crate::store::{
 };
 use crate::store::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
 
-/// The user-provided fail function.
-pub trait FailFn: Clone + Fn(&str) -> Result<()> {}
+/// The user-provided fail function, called with the name of the operation, like `put`,
+/// `batch-put` or `txn-commit`, and the key of the operation if any.
+pub trait FailFn: Clone + Fn(&str, Option<&Key>) -> Result<()> {}
+impl<T: Clone + Fn(&str, Option<&Key>) -> Result<()>> FailFn for T {}
+
+struct FailCondition {
+    op: Option<String>,
+    prefix: Option<Key>,
+    err: DataStoreError,
+}
+
+impl FailCondition {
+    fn matches(&self, op: &str, key: Option<&Key>) -> bool {
+        let op_matches = self.op.as_ref().is_none_or(|expected| expected == op);
+        let key_matches = match (&self.prefix, key) {
+            (None, _) => true,
+            (Some(prefix), Some(key)) => prefix == key || prefix.is_ancestor_of(key),
+            (Some(_), None) => false,
+        };
+        op_matches && key_matches
+    }
+}
+
+/// FailBuilder composes the conditions of a fail function, the operation fails with the
+/// error of the first matching condition and passes when no condition matches.
+///
+/// # Example
+///
+/// ```
+/// use ipfs_datastore::{DataStoreError, FailBuilder};
+/// let fail_fn = FailBuilder::new()
+///     .fail_under("put", "/locked", DataStoreError::Custom("locked".into()))
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct FailBuilder {
+    conditions: Vec<FailCondition>,
+}
+
+impl FailBuilder {
+    /// Create a new FailBuilder without any condition.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the operations named `op` on any key.
+    pub fn fail<S: Into<String>>(mut self, op: S, err: DataStoreError) -> Self {
+        self.conditions.push(FailCondition {
+            op: Some(op.into()),
+            prefix: None,
+            err,
+        });
+        self
+    }
+
+    /// Fail the operations named `op` on the `prefix` key and its descendants.
+    pub fn fail_under<S, K>(mut self, op: S, prefix: K, err: DataStoreError) -> Self
+    where
+        S: Into<String>,
+        K: Into<Key>,
+    {
+        self.conditions.push(FailCondition {
+            op: Some(op.into()),
+            prefix: Some(prefix.into()),
+            err,
+        });
+        self
+    }
+
+    /// Fail every operation on the `prefix` key and its descendants.
+    pub fn fail_all_under<K: Into<Key>>(mut self, prefix: K, err: DataStoreError) -> Self {
+        self.conditions.push(FailCondition {
+            op: None,
+            prefix: Some(prefix.into()),
+            err,
+        });
+        self
+    }
+
+    /// Return the fail function combining the conditions.
+    pub fn build(self) -> impl FailFn {
+        let conditions = Arc::new(self.conditions);
+        move |op: &str, key: Option<&Key>| match conditions
+            .iter()
+            .find(|condition| condition.matches(op, key))
+        {
+            Some(condition) => Err(condition.err.clone()),
+            None => Ok(()),
+        }
+    }
+}
 
 /// FailDataStore is a datastore which fails according to a user-provided function.
 #[derive(Clone)]
@@ -36,7 +126,7 @@ impl<F: FailFn, DS: DataStore> DataStore for FailDataStore<F, DS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("sync")?;
+        (self.fail_fn)("sync", Some(prefix.borrow()))?;
         self.datastore.sync(prefix)
     }
 
@@ -50,7 +140,7 @@ impl<F: FailFn, DS: DataStore> DataStoreRead for FailDataStore<F, DS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("put")?;
+        (self.fail_fn)("get", Some(key.borrow()))?;
         self.datastore.get(key)
     }
 
@@ -58,7 +148,7 @@ impl<F: FailFn, DS: DataStore> DataStoreRead for FailDataStore<F, DS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("has")?;
+        (self.fail_fn)("has", Some(key.borrow()))?;
         self.datastore.has(key)
     }
 
@@ -66,9 +156,19 @@ impl<F: FailFn, DS: DataStore> DataStoreRead for FailDataStore<F, DS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("size")?;
+        (self.fail_fn)("size", Some(key.borrow()))?;
         self.datastore.size(key)
     }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        (self.fail_fn)("read_snapshot", None)?;
+        self.datastore.read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        (self.fail_fn)("keys", Some(prefix))?;
+        self.datastore.keys(prefix)
+    }
 }
 
 impl<F: FailFn, DS: DataStore> DataStoreWrite for FailDataStore<F, DS> {
@@ -77,7 +177,8 @@ impl<F: FailFn, DS: DataStore> DataStoreWrite for FailDataStore<F, DS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        (self.fail_fn)("put")?;
+        let key = key.into();
+        (self.fail_fn)("put", Some(&key))?;
         self.datastore.put(key, value)
     }
 
@@ -85,35 +186,49 @@ impl<F: FailFn, DS: DataStore> DataStoreWrite for FailDataStore<F, DS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("delete")?;
+        (self.fail_fn)("delete", Some(key.borrow()))?;
         self.datastore.delete(key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        (self.fail_fn)("increment", Some(key.borrow()))?;
+        self.datastore.increment(key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        (self.fail_fn)("fence", None)?;
+        self.datastore.fence()
+    }
 }
 
 impl<F: FailFn, DS: CheckedDataStore> Check for FailDataStore<F, DS> {
     fn check(&self) -> Result<()> {
-        (self.fail_fn)("check")?;
+        (self.fail_fn)("check", None)?;
         self.datastore.check()
     }
 }
 
 impl<F: FailFn, DS: GcDataStore> Gc for FailDataStore<F, DS> {
-    fn collect_garbage(&self) -> Result<()> {
-        (self.fail_fn)("collect-garbage")?;
+    fn collect_garbage(&self) -> Result<usize> {
+        (self.fail_fn)("collect-garbage", None)?;
         self.datastore.collect_garbage()
     }
 }
 
 impl<F: FailFn, DS: PersistentDataStore> Persistent for FailDataStore<F, DS> {
     fn disk_usage(&self) -> Result<u64> {
-        (self.fail_fn)("disk-usage")?;
+        (self.fail_fn)("disk-usage", None)?;
         self.datastore.disk_usage()
     }
 }
 
 impl<F: FailFn, DS: ScrubbedDataStore> Scrub for FailDataStore<F, DS> {
-    fn scrub(&self) -> Result<()> {
-        (self.fail_fn)("scrub")?;
+    fn scrub(&self) -> Result<Vec<Key>> {
+        (self.fail_fn)("scrub", None)?;
         self.datastore.scrub()
     }
 }
@@ -162,7 +277,7 @@ impl<F: FailFn, BDS: BatchDataStore> DataStore for FailBatchDataStore<F, BDS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("batch-sync")?;
+        (self.fail_fn)("batch-sync", Some(prefix.borrow()))?;
         self.datastore.sync(prefix)
     }
 
@@ -176,15 +291,20 @@ impl<F: FailFn, BDS: BatchDataStore> DataStoreRead for FailBatchDataStore<F, BDS
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("batch-put")?;
+        (self.fail_fn)("batch-get", Some(key.borrow()))?;
         self.datastore.get(key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        (self.fail_fn)("batch-read_snapshot", None)?;
+        self.datastore.read_snapshot(keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("batch-has")?;
+        (self.fail_fn)("batch-has", Some(key.borrow()))?;
         self.datastore.has(key)
     }
 
@@ -192,9 +312,14 @@ impl<F: FailFn, BDS: BatchDataStore> DataStoreRead for FailBatchDataStore<F, BDS
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("batch-size")?;
+        (self.fail_fn)("batch-size", Some(key.borrow()))?;
         self.datastore.size(key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        (self.fail_fn)("batch-keys", Some(prefix))?;
+        self.datastore.keys(prefix)
+    }
 }
 
 impl<F: FailFn, BDS: BatchDataStore> DataStoreWrite for FailBatchDataStore<F, BDS> {
@@ -203,7 +328,8 @@ impl<F: FailFn, BDS: BatchDataStore> DataStoreWrite for FailBatchDataStore<F, BD
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        (self.fail_fn)("batch-put")?;
+        let key = key.into();
+        (self.fail_fn)("batch-put", Some(&key))?;
         self.datastore.put(key, value)
     }
 
@@ -211,42 +337,51 @@ impl<F: FailFn, BDS: BatchDataStore> DataStoreWrite for FailBatchDataStore<F, BD
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("batch-delete")?;
+        (self.fail_fn)("batch-delete", Some(key.borrow()))?;
         self.datastore.delete(key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        (self.fail_fn)("batch-increment", Some(key.borrow()))?;
+        self.datastore.increment(key, delta)
+    }
 }
 
 impl<F: FailFn, BDS: BatchDataStore> DataStoreBatch for FailBatchDataStore<F, BDS> {
     fn commit(&mut self) -> Result<()> {
-        (self.fail_fn)("batch-commit")?;
+        (self.fail_fn)("batch-commit", None)?;
         self.datastore.commit()
     }
 }
 
 impl<F: FailFn, BDS: CheckedBatchDataStore> Check for FailBatchDataStore<F, BDS> {
     fn check(&self) -> Result<()> {
-        (self.fail_fn)("check")?;
+        (self.fail_fn)("check", None)?;
         self.datastore.check()
     }
 }
 
 impl<F: FailFn, BDS: GcBatchDataStore> Gc for FailBatchDataStore<F, BDS> {
-    fn collect_garbage(&self) -> Result<()> {
-        (self.fail_fn)("collect-garbage")?;
+    fn collect_garbage(&self) -> Result<usize> {
+        (self.fail_fn)("collect-garbage", None)?;
         self.datastore.collect_garbage()
     }
 }
 
 impl<F: FailFn, BDS: PersistentBatchDataStore> Persistent for FailBatchDataStore<F, BDS> {
     fn disk_usage(&self) -> Result<u64> {
-        (self.fail_fn)("disk-usage")?;
+        (self.fail_fn)("disk-usage", None)?;
         self.datastore.disk_usage()
     }
 }
 
 impl<F: FailFn, BDS: ScrubbedBatchDataStore> Scrub for FailBatchDataStore<F, BDS> {
-    fn scrub(&self) -> Result<()> {
-        (self.fail_fn)("scrub")?;
+    fn scrub(&self) -> Result<Vec<Key>> {
+        (self.fail_fn)("scrub", None)?;
         self.datastore.scrub()
     }
 }
@@ -284,7 +419,7 @@ impl<F: FailFn, TDS: TxnDataStore> DataStore for FailTxnDataStore<F, TDS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("txn-sync")?;
+        (self.fail_fn)("txn-sync", Some(prefix.borrow()))?;
         self.datastore.sync(prefix)
     }
 
@@ -298,15 +433,20 @@ impl<F: FailFn, TDS: TxnDataStore> DataStoreRead for FailTxnDataStore<F, TDS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("txn-put")?;
+        (self.fail_fn)("txn-get", Some(key.borrow()))?;
         self.datastore.get(key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        (self.fail_fn)("txn-read_snapshot", None)?;
+        self.datastore.read_snapshot(keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("txn-has")?;
+        (self.fail_fn)("txn-has", Some(key.borrow()))?;
         self.datastore.has(key)
     }
 
@@ -314,9 +454,14 @@ impl<F: FailFn, TDS: TxnDataStore> DataStoreRead for FailTxnDataStore<F, TDS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("txn-size")?;
+        (self.fail_fn)("txn-size", Some(key.borrow()))?;
         self.datastore.size(key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        (self.fail_fn)("txn-keys", Some(prefix))?;
+        self.datastore.keys(prefix)
+    }
 }
 
 impl<F: FailFn, TDS: TxnDataStore> DataStoreWrite for FailTxnDataStore<F, TDS> {
@@ -325,7 +470,8 @@ impl<F: FailFn, TDS: TxnDataStore> DataStoreWrite for FailTxnDataStore<F, TDS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        (self.fail_fn)("txn-put")?;
+        let key = key.into();
+        (self.fail_fn)("txn-put", Some(&key))?;
         self.datastore.put(key, value)
     }
 
@@ -333,49 +479,94 @@ impl<F: FailFn, TDS: TxnDataStore> DataStoreWrite for FailTxnDataStore<F, TDS> {
     where
         K: Borrow<Key>,
     {
-        (self.fail_fn)("txn-delete")?;
+        (self.fail_fn)("txn-delete", Some(key.borrow()))?;
         self.datastore.delete(key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        (self.fail_fn)("txn-increment", Some(key.borrow()))?;
+        self.datastore.increment(key, delta)
+    }
 }
 
 impl<F: FailFn, TDS: TxnDataStore> DataStoreBatch for FailTxnDataStore<F, TDS> {
     fn commit(&mut self) -> Result<()> {
-        (self.fail_fn)("txn-commit")?;
+        (self.fail_fn)("txn-commit", None)?;
         self.datastore.commit()
     }
 }
 
 impl<F: FailFn, TDS: TxnDataStore> DataStoreTxn for FailTxnDataStore<F, TDS> {
     fn discard(&mut self) -> Result<()> {
-        (self.fail_fn)("txn-discard")?;
+        (self.fail_fn)("txn-discard", None)?;
         self.datastore.discard()
     }
 }
 
 impl<F: FailFn, TDS: CheckedTxnDataStore> Check for FailTxnDataStore<F, TDS> {
     fn check(&self) -> Result<()> {
-        (self.fail_fn)("check")?;
+        (self.fail_fn)("check", None)?;
         self.datastore.check()
     }
 }
 
 impl<F: FailFn, TDS: GcTxnDataStore> Gc for FailTxnDataStore<F, TDS> {
-    fn collect_garbage(&self) -> Result<()> {
-        (self.fail_fn)("collect-garbage")?;
+    fn collect_garbage(&self) -> Result<usize> {
+        (self.fail_fn)("collect-garbage", None)?;
         self.datastore.collect_garbage()
     }
 }
 
 impl<F: FailFn, TDS: PersistentTxnDataStore> Persistent for FailTxnDataStore<F, TDS> {
     fn disk_usage(&self) -> Result<u64> {
-        (self.fail_fn)("disk-usage")?;
+        (self.fail_fn)("disk-usage", None)?;
         self.datastore.disk_usage()
     }
 }
 
 impl<F: FailFn, TDS: ScrubbedTxnDataStore> Scrub for FailTxnDataStore<F, TDS> {
-    fn scrub(&self) -> Result<()> {
-        (self.fail_fn)("scrub")?;
+    fn scrub(&self) -> Result<Vec<Key>> {
+        (self.fail_fn)("scrub", None)?;
         self.datastore.scrub()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    #[test]
+    fn test_fail_builder() {
+        let mut inner = MapDataStore::new();
+        inner.put(Key::new("/locked/a"), vec![1]).unwrap();
+        let fail_fn = FailBuilder::new()
+            .fail_under("put", "/locked", DataStoreError::Custom("locked".into()))
+            .fail_under("delete", "/locked", DataStoreError::Custom("locked".into()))
+            .fail("fence", DataStoreError::Timeout("fence".into()))
+            .build();
+        let mut store = FailDataStore::new(fail_fn, inner);
+
+        // the writes under /locked fail.
+        let err = store.put(Key::new("/locked/b"), vec![2]).unwrap_err();
+        assert!(matches!(err, DataStoreError::Custom(_)));
+        assert!(store.put(Key::new("/locked"), vec![2]).is_err());
+        assert!(store.delete(&Key::new("/locked/a")).is_err());
+        assert!(store.fence().unwrap_err().is_transient());
+
+        // the writes elsewhere pass to the inner store untouched.
+        store.put(Key::new("/open/b"), vec![3]).unwrap();
+        store.put(Key::new("/lockedout"), vec![4]).unwrap();
+        assert_eq!(store.get(&Key::new("/open/b")).unwrap(), vec![3]);
+        assert_eq!(store.get(&Key::new("/lockedout")).unwrap(), vec![4]);
+
+        // the reads are unaffected.
+        assert_eq!(store.get(&Key::new("/locked/a")).unwrap(), vec![1]);
+        assert!(store.has(&Key::new("/locked/a")).unwrap());
+        assert!(!store.has(&Key::new("/locked/b")).unwrap());
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore, WriteLock};
+use crate::key::Key;
+use crate::store::{increment_counter, sort_keys, DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, Persistent};
+use crate::store::{ToBatch, ToTxn};
+
+// The deleted entry is kept as a tombstone (`None`) until the garbage is collected.
+type Entries = HashMap<Key, Option<Vec<u8>>>;
+
+/// MapGcDataStore is an in-memory datastore where deleting a key only tombstones it,
+/// and the space is reclaimed by `collect_garbage`, like the datastores on disk which
+/// don't free the space on deletion.
+///
+/// The clones share the entries.
+#[derive(Clone, Debug, Default)]
+pub struct MapGcDataStore {
+    entries: Arc<RwLock<Entries>>,
+    write_lock: WriteLock,
+}
+
+impl MapGcDataStore {
+    /// Create a new MapGcDataStore.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of the tombstones waiting for the garbage collection.
+    pub fn tombstones(&self) -> usize {
+        self.entries
+            .read()
+            .values()
+            .filter(|value| value.is_none())
+            .count()
+    }
+}
+
+impl DataStore for MapGcDataStore {
+    fn sync<K>(&mut self, _prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl DataStoreRead for MapGcDataStore {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.entries.read().get(key) {
+            Some(Some(value)) => Ok(value.clone()),
+            _ => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(matches!(
+            self.entries.read().get(key.borrow()),
+            Some(Some(_))
+        ))
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.entries.read().get(key) {
+            Some(Some(value)) => Ok(value.len()),
+            _ => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let entries = self.entries.read();
+        Ok(keys
+            .iter()
+            .map(|key| entries.get(key).cloned().flatten())
+            .collect())
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut keys = self
+            .entries
+            .read()
+            .iter()
+            .filter(|(key, value)| {
+                value.is_some() && (*key == prefix || prefix.is_ancestor_of(*key))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+}
+
+impl DataStoreWrite for MapGcDataStore {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        // putting a tombstoned key revives it.
+        let key = key.into();
+        self.write_lock.write(&key, || {
+            self.entries.write().insert(key.clone(), Some(value.into()));
+        });
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.write_lock.write(key, || {
+            if let Some(value) = self.entries.write().get_mut(key) {
+                *value = None;
+            }
+        });
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let lock = self.write_lock.clone();
+        lock.write(key, || increment_counter(self, key, delta))
+    }
+}
+
+impl Gc for MapGcDataStore {
+    fn collect_garbage(&self) -> Result<usize> {
+        let mut entries = self.entries.write();
+        let before = entries.len();
+        entries.retain(|_, value| value.is_some());
+        Ok(before - entries.len())
+    }
+}
+
+// The tombstones take the space of their keys until the garbage is collected.
+impl Persistent for MapGcDataStore {
+    fn disk_usage(&self) -> Result<u64> {
+        Ok(self
+            .entries
+            .read()
+            .iter()
+            .map(|(key, value)| {
+                let value_len = value.as_ref().map_or(0, Vec::len);
+                (key.as_bytes().len() + value_len) as u64
+            })
+            .sum())
+    }
+}
+
+impl ToBatch for MapGcDataStore {
+    type Batch = BasicBatchDataStore<MapGcDataStore>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl ToTxn for MapGcDataStore {
+    type Txn = BasicTxnDataStore<MapGcDataStore>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::with_write_lock(
+            self.clone(),
+            self.write_lock.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_garbage() {
+        let mut store = MapGcDataStore::new();
+        for i in 0..5u8 {
+            store.put(Key::new(format!("/{}", i)), vec![i; 10]).unwrap();
+        }
+        let usage = store.disk_usage().unwrap();
+
+        store.delete(&Key::new("/0")).unwrap();
+        store.delete(&Key::new("/1")).unwrap();
+        store.delete(&Key::new("/2")).unwrap();
+        // deleting an absent key leaves no tombstone.
+        store.delete(&Key::new("/9")).unwrap();
+        // the tombstoned key put again before the collection survives.
+        store.put(Key::new("/2"), vec![22]).unwrap();
+
+        assert!(!store.has(&Key::new("/0")).unwrap());
+        assert!(store.get(&Key::new("/1")).unwrap_err().is_not_found());
+        assert_eq!(store.keys(&Key::new("/")).unwrap().len(), 3);
+        assert_eq!(store.tombstones(), 2);
+        assert!(store.disk_usage().unwrap() < usage);
+
+        let usage = store.disk_usage().unwrap();
+        assert_eq!(store.collect_garbage().unwrap(), 2);
+        assert_eq!(store.tombstones(), 0);
+        assert!(store.disk_usage().unwrap() < usage);
+        assert_eq!(store.get(&Key::new("/2")).unwrap(), vec![22]);
+        assert_eq!(store.get(&Key::new("/3")).unwrap(), vec![3; 10]);
+        assert_eq!(store.size(&Key::new("/4")).unwrap(), 10);
+        assert!(!store.has(&Key::new("/0")).unwrap());
+
+        assert_eq!(store.collect_garbage().unwrap(), 0);
+    }
+}
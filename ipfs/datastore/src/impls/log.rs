@@ -1,6 +1,8 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::fmt;
+use std::sync::Arc;
 
 use log::info;
 
@@ -16,11 +18,40 @@ use crate::store::{
 use crate::store::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
 use crate::DataStoreTxn;
 
+/// LogEntry is the structured log of an operation through the `LogDataStore`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The name of the operation, e.g. `get`, `put`.
+    pub op: &'static str,
+    /// The key (or the prefix of `sync`) of the operation, if any.
+    pub key: Option<Key>,
+    /// The length of the value written by `put`, or read by `get` and `size`.
+    pub value_len: Option<usize>,
+    /// The result of the operation, with the message of the error.
+    pub result: std::result::Result<(), String>,
+}
+
+/// LogSink receives the `LogEntry` of each operation through the `LogDataStore`.
+pub type LogSink = Arc<dyn Fn(LogEntry) + Send + Sync>;
+
 /// LogDataStore logs all accesses through the datastore.
-#[derive(Clone, Debug)]
+///
+/// Besides the `log` macros, the accesses can be captured programmatically by a `LogSink`.
+#[derive(Clone)]
 pub struct LogDataStore<DS: DataStore> {
     name: String,
     datastore: DS,
+    sink: Option<LogSink>,
+}
+
+impl<DS: DataStore + fmt::Debug> fmt::Debug for LogDataStore<DS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogDataStore")
+            .field("name", &self.name)
+            .field("datastore", &self.datastore)
+            .field("sink", &self.sink.is_some())
+            .finish()
+    }
 }
 
 impl<DS: DataStore> LogDataStore<DS> {
@@ -29,8 +60,51 @@ impl<DS: DataStore> LogDataStore<DS> {
         Self {
             name: name.into(),
             datastore,
+            sink: None,
         }
     }
+
+    /// Create a new LogDataStore sending the `LogEntry` of each operation to the `sink`.
+    pub fn with_sink<S, F>(name: S, datastore: DS, sink: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(LogEntry) + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            datastore,
+            sink: Some(Arc::new(sink)),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        op: &'static str,
+        key: Option<&Key>,
+        value_len: Option<usize>,
+        result: Result<T>,
+    ) -> Result<T> {
+        record(self.sink.as_ref(), op, key, value_len, result)
+    }
+}
+
+// Send the `LogEntry` of the operation to the sink, if any.
+fn record<T>(
+    sink: Option<&LogSink>,
+    op: &'static str,
+    key: Option<&Key>,
+    value_len: Option<usize>,
+    result: Result<T>,
+) -> Result<T> {
+    if let Some(sink) = sink {
+        sink(LogEntry {
+            op,
+            key: key.cloned(),
+            value_len,
+            result: result.as_ref().map(|_| ()).map_err(|err| err.to_string()),
+        });
+    }
+    result
 }
 
 impl<DS: DataStore> DataStore for LogDataStore<DS> {
@@ -39,12 +113,14 @@ impl<DS: DataStore> DataStore for LogDataStore<DS> {
         K: Borrow<Key>,
     {
         info!("{}: sync {}", self.name, prefix.borrow());
-        self.datastore.sync(prefix)
+        let result = self.datastore.sync(prefix);
+        self.record("sync", Some(prefix.borrow()), None, result)
     }
 
     fn close(&mut self) -> Result<()> {
         info!("{}: close", self.name);
-        self.datastore.close()
+        let result = self.datastore.close();
+        self.record("close", None, None, result)
     }
 }
 
@@ -54,7 +130,9 @@ impl<DS: DataStore> DataStoreRead for LogDataStore<DS> {
         K: Borrow<Key>,
     {
         info!("{}: get {}", self.name, key.borrow());
-        self.datastore.get(key)
+        let result = self.datastore.get(key);
+        let value_len = result.as_ref().ok().map(Vec::len);
+        self.record("get", Some(key.borrow()), value_len, result)
     }
 
     fn has<K>(&self, key: &K) -> Result<bool>
@@ -62,7 +140,8 @@ impl<DS: DataStore> DataStoreRead for LogDataStore<DS> {
         K: Borrow<Key>,
     {
         info!("{}: has {}", self.name, key.borrow());
-        self.datastore.has(key)
+        let result = self.datastore.has(key);
+        self.record("has", Some(key.borrow()), None, result)
     }
 
     fn size<K>(&self, key: &K) -> Result<usize>
@@ -70,7 +149,33 @@ impl<DS: DataStore> DataStoreRead for LogDataStore<DS> {
         K: Borrow<Key>,
     {
         info!("{}: size {}", self.name, key.borrow());
-        self.datastore.size(key)
+        let result = self.datastore.size(key);
+        let value_len = result.as_ref().ok().copied();
+        self.record("size", Some(key.borrow()), value_len, result)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        info!("{}: read_snapshot {} keys", self.name, keys.len());
+        let result = self.datastore.read_snapshot(keys);
+        self.record("read_snapshot", None, None, result)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        info!("{}: keys {}", self.name, prefix);
+        let result = self.datastore.keys(prefix);
+        self.record("keys", Some(prefix), None, result)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        info!("{}: count {}", self.name, prefix);
+        let result = self.datastore.count(prefix);
+        self.record("count", Some(prefix), None, result)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        info!("{}: has_prefix {}", self.name, prefix);
+        let result = self.datastore.has_prefix(prefix);
+        self.record("has_prefix", Some(prefix), None, result)
     }
 }
 
@@ -83,7 +188,9 @@ impl<DS: DataStore> DataStoreWrite for LogDataStore<DS> {
         let key = key.into();
         let value = value.into();
         info!("{}: put {} - {:?}", self.name, key, value);
-        self.datastore.put(key, value)
+        let value_len = value.len();
+        let result = self.datastore.put(key.clone(), value);
+        self.record("put", Some(&key), Some(value_len), result)
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
@@ -91,35 +198,56 @@ impl<DS: DataStore> DataStoreWrite for LogDataStore<DS> {
         K: Borrow<Key>,
     {
         info!("{}: delete {}", self.name, key.borrow());
-        self.datastore.delete(key)
+        let result = self.datastore.delete(key);
+        self.record("delete", Some(key.borrow()), None, result)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        info!("{}: increment {} by {}", self.name, key.borrow(), delta);
+        let result = self.datastore.increment(key, delta);
+        self.record("increment", Some(key.borrow()), None, result)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        info!("{}: fence", self.name);
+        let result = self.datastore.fence();
+        self.record("fence", None, None, result)
     }
 }
 
 impl<DS: CheckedDataStore> Check for LogDataStore<DS> {
     fn check(&self) -> Result<()> {
         info!("{}: check", self.name);
-        self.datastore.check()
+        let result = self.datastore.check();
+        self.record("check", None, None, result)
     }
 }
 
 impl<DS: GcDataStore> Gc for LogDataStore<DS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         info!("{}: collect_garbage", self.name);
-        self.datastore.collect_garbage()
+        let result = self.datastore.collect_garbage();
+        self.record("collect_garbage", None, None, result)
     }
 }
 
 impl<DS: PersistentDataStore> Persistent for LogDataStore<DS> {
     fn disk_usage(&self) -> Result<u64> {
         info!("{}: disk_usage", self.name);
-        self.datastore.disk_usage()
+        let result = self.datastore.disk_usage();
+        self.record("disk_usage", None, None, result)
     }
 }
 
 impl<DS: ScrubbedDataStore> Scrub for LogDataStore<DS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         info!("{}: scrub", self.name);
-        self.datastore.scrub()
+        let result = self.datastore.scrub();
+        self.record("scrub", None, None, result)
     }
 }
 
@@ -128,10 +256,11 @@ impl<BDS: BatchDataStore> ToBatch for LogDataStore<BDS> {
 
     fn batch(&self) -> Result<Self::Batch> {
         info!("{}: batch", self.name);
-        Ok(LogBatchDataStore::new(
-            self.name.clone(),
-            self.datastore.clone(),
-        ))
+        Ok(LogBatchDataStore {
+            name: self.name.clone(),
+            datastore: self.datastore.clone(),
+            sink: self.sink.clone(),
+        })
     }
 }
 
@@ -140,20 +269,24 @@ impl<TDS: TxnDataStore> ToTxn for LogTxnDataStore<TDS> {
 
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         info!("{}: txn", self.name);
-        Ok(LogTxnDataStore::new(
-            self.name.clone(),
-            self.datastore.clone(),
-        ))
+        Ok(LogTxnDataStore {
+            name: self.name.clone(),
+            datastore: self.datastore.clone(),
+            sink: self.sink.clone(),
+        })
     }
 }
 
 // ============================================================================
 
 /// LogBatchDataStore logs all accesses through the batching data store.
+///
+/// Like `LogDataStore`, the accesses can be captured programmatically by a `LogSink`.
 #[derive(Clone)]
 pub struct LogBatchDataStore<BDS: BatchDataStore> {
     name: String,
     datastore: BDS,
+    sink: Option<LogSink>,
 }
 
 impl<BDS: BatchDataStore> LogBatchDataStore<BDS> {
@@ -162,8 +295,32 @@ impl<BDS: BatchDataStore> LogBatchDataStore<BDS> {
         Self {
             name: name.into(),
             datastore,
+            sink: None,
         }
     }
+
+    /// Create a new LogBatchDataStore sending the `LogEntry` of each operation to the `sink`.
+    pub fn with_sink<S, F>(name: S, datastore: BDS, sink: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(LogEntry) + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            datastore,
+            sink: Some(Arc::new(sink)),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        op: &'static str,
+        key: Option<&Key>,
+        value_len: Option<usize>,
+        result: Result<T>,
+    ) -> Result<T> {
+        record(self.sink.as_ref(), op, key, value_len, result)
+    }
 }
 
 impl<BDS: BatchDataStore> DataStore for LogBatchDataStore<BDS> {
@@ -172,12 +329,14 @@ impl<BDS: BatchDataStore> DataStore for LogBatchDataStore<BDS> {
         K: Borrow<Key>,
     {
         info!("{}: batch sync {}", self.name, prefix.borrow());
-        self.datastore.sync(prefix)
+        let result = self.datastore.sync(prefix);
+        self.record("batch-sync", Some(prefix.borrow()), None, result)
     }
 
     fn close(&mut self) -> Result<()> {
         info!("{}: batch close", self.name);
-        self.datastore.close()
+        let result = self.datastore.close();
+        self.record("batch-close", None, None, result)
     }
 }
 
@@ -187,7 +346,15 @@ impl<BDS: BatchDataStore> DataStoreRead for LogBatchDataStore<BDS> {
         K: Borrow<Key>,
     {
         info!("{}: batch get {}", self.name, key.borrow());
-        self.datastore.get(key)
+        let result = self.datastore.get(key);
+        let value_len = result.as_ref().ok().map(Vec::len);
+        self.record("batch-get", Some(key.borrow()), value_len, result)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        info!("{}: batch read_snapshot {} keys", self.name, keys.len());
+        let result = self.datastore.read_snapshot(keys);
+        self.record("batch-read_snapshot", None, None, result)
     }
 
     fn has<K>(&self, key: &K) -> Result<bool>
@@ -195,7 +362,8 @@ impl<BDS: BatchDataStore> DataStoreRead for LogBatchDataStore<BDS> {
         K: Borrow<Key>,
     {
         info!("{}: batch has {}", self.name, key.borrow());
-        self.datastore.has(key)
+        let result = self.datastore.has(key);
+        self.record("batch-has", Some(key.borrow()), None, result)
     }
 
     fn size<K>(&self, key: &K) -> Result<usize>
@@ -203,7 +371,15 @@ impl<BDS: BatchDataStore> DataStoreRead for LogBatchDataStore<BDS> {
         K: Borrow<Key>,
     {
         info!("{}: batch size {}", self.name, key.borrow());
-        self.datastore.size(key)
+        let result = self.datastore.size(key);
+        let value_len = result.as_ref().ok().copied();
+        self.record("batch-size", Some(key.borrow()), value_len, result)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        info!("{}: batch keys {}", self.name, prefix);
+        let result = self.datastore.keys(prefix);
+        self.record("batch-keys", Some(prefix), None, result)
     }
 }
 
@@ -216,7 +392,9 @@ impl<BDS: BatchDataStore> DataStoreWrite for LogBatchDataStore<BDS> {
         let key = key.into();
         let value = value.into();
         info!("{}: batch put {} - {:?}", self.name, key, value);
-        self.datastore.put(key, value)
+        let value_len = value.len();
+        let result = self.datastore.put(key.clone(), value);
+        self.record("batch-put", Some(&key), Some(value_len), result)
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
@@ -224,42 +402,63 @@ impl<BDS: BatchDataStore> DataStoreWrite for LogBatchDataStore<BDS> {
         K: Borrow<Key>,
     {
         info!("{}: batch delete {}", self.name, key.borrow());
-        self.datastore.delete(key)
+        let result = self.datastore.delete(key);
+        self.record("batch-delete", Some(key.borrow()), None, result)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        info!(
+            "{}: batch increment {} by {}",
+            self.name,
+            key.borrow(),
+            delta
+        );
+        let result = self.datastore.increment(key, delta);
+        self.record("batch-increment", Some(key.borrow()), None, result)
     }
 }
 
 impl<BDS: BatchDataStore> DataStoreBatch for LogBatchDataStore<BDS> {
     fn commit(&mut self) -> Result<()> {
         info!("{}: batch commit", self.name);
-        self.commit()
+        let result = self.datastore.commit();
+        self.record("batch-commit", None, None, result)
     }
 }
 
 impl<BDS: CheckedBatchDataStore> Check for LogBatchDataStore<BDS> {
     fn check(&self) -> Result<()> {
         info!("{}: check", self.name);
-        self.datastore.check()
+        let result = self.datastore.check();
+        self.record("check", None, None, result)
     }
 }
 
 impl<BDS: GcBatchDataStore> Gc for LogBatchDataStore<BDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         info!("{}: collect_garbage", self.name);
-        self.datastore.collect_garbage()
+        let result = self.datastore.collect_garbage();
+        self.record("collect_garbage", None, None, result)
     }
 }
 
 impl<BDS: PersistentBatchDataStore> Persistent for LogBatchDataStore<BDS> {
     fn disk_usage(&self) -> Result<u64> {
         info!("{}: disk_usage", self.name);
-        self.datastore.disk_usage()
+        let result = self.datastore.disk_usage();
+        self.record("disk_usage", None, None, result)
     }
 }
 
 impl<BDS: ScrubbedBatchDataStore> Scrub for LogBatchDataStore<BDS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         info!("{}: scrub", self.name);
-        self.datastore.scrub()
+        let result = self.datastore.scrub();
+        self.record("scrub", None, None, result)
     }
 }
 
@@ -268,20 +467,24 @@ impl<TDS: TxnDataStore> ToTxn for LogBatchDataStore<TDS> {
 
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         info!("{}: txn", self.name);
-        Ok(LogTxnDataStore::new(
-            self.name.clone(),
-            self.datastore.clone(),
-        ))
+        Ok(LogTxnDataStore {
+            name: self.name.clone(),
+            datastore: self.datastore.clone(),
+            sink: self.sink.clone(),
+        })
     }
 }
 
 // ============================================================================
 
 /// LogTxnDataStore logs all accesses through the txn data store.
+///
+/// Like `LogDataStore`, the accesses can be captured programmatically by a `LogSink`.
 #[derive(Clone)]
 pub struct LogTxnDataStore<TDS: TxnDataStore> {
     name: String,
     datastore: TDS,
+    sink: Option<LogSink>,
 }
 
 impl<TDS: TxnDataStore> LogTxnDataStore<TDS> {
@@ -290,8 +493,32 @@ impl<TDS: TxnDataStore> LogTxnDataStore<TDS> {
         Self {
             name: name.into(),
             datastore,
+            sink: None,
+        }
+    }
+
+    /// Create a new LogTxnDataStore sending the `LogEntry` of each operation to the `sink`.
+    pub fn with_sink<S, F>(name: S, datastore: TDS, sink: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(LogEntry) + Send + Sync + 'static,
+    {
+        Self {
+            name: name.into(),
+            datastore,
+            sink: Some(Arc::new(sink)),
         }
     }
+
+    fn record<T>(
+        &self,
+        op: &'static str,
+        key: Option<&Key>,
+        value_len: Option<usize>,
+        result: Result<T>,
+    ) -> Result<T> {
+        record(self.sink.as_ref(), op, key, value_len, result)
+    }
 }
 
 impl<TDS: TxnDataStore> DataStore for LogTxnDataStore<TDS> {
@@ -300,12 +527,14 @@ impl<TDS: TxnDataStore> DataStore for LogTxnDataStore<TDS> {
         K: Borrow<Key>,
     {
         info!("{}: txn sync {}", self.name, prefix.borrow());
-        self.datastore.sync(prefix)
+        let result = self.datastore.sync(prefix);
+        self.record("txn-sync", Some(prefix.borrow()), None, result)
     }
 
     fn close(&mut self) -> Result<()> {
         info!("{}: txn close", self.name);
-        self.datastore.close()
+        let result = self.datastore.close();
+        self.record("txn-close", None, None, result)
     }
 }
 
@@ -315,7 +544,15 @@ impl<TDS: TxnDataStore> DataStoreRead for LogTxnDataStore<TDS> {
         K: Borrow<Key>,
     {
         info!("{}: txn get {}", self.name, key.borrow());
-        self.datastore.get(key)
+        let result = self.datastore.get(key);
+        let value_len = result.as_ref().ok().map(Vec::len);
+        self.record("txn-get", Some(key.borrow()), value_len, result)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        info!("{}: txn read_snapshot {} keys", self.name, keys.len());
+        let result = self.datastore.read_snapshot(keys);
+        self.record("txn-read_snapshot", None, None, result)
     }
 
     fn has<K>(&self, key: &K) -> Result<bool>
@@ -323,7 +560,8 @@ impl<TDS: TxnDataStore> DataStoreRead for LogTxnDataStore<TDS> {
         K: Borrow<Key>,
     {
         info!("{}: txn has {}", self.name, key.borrow());
-        self.datastore.has(key)
+        let result = self.datastore.has(key);
+        self.record("txn-has", Some(key.borrow()), None, result)
     }
 
     fn size<K>(&self, key: &K) -> Result<usize>
@@ -331,7 +569,15 @@ impl<TDS: TxnDataStore> DataStoreRead for LogTxnDataStore<TDS> {
         K: Borrow<Key>,
     {
         info!("{}: txn size {}", self.name, key.borrow());
-        self.datastore.size(key)
+        let result = self.datastore.size(key);
+        let value_len = result.as_ref().ok().copied();
+        self.record("txn-size", Some(key.borrow()), value_len, result)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        info!("{}: txn keys {}", self.name, prefix);
+        let result = self.datastore.keys(prefix);
+        self.record("txn-keys", Some(prefix), None, result)
     }
 }
 
@@ -344,7 +590,9 @@ impl<TDS: TxnDataStore> DataStoreWrite for LogTxnDataStore<TDS> {
         let key = key.into();
         let value = value.into();
         info!("{}: txn put {} - {:?}", self.name, key, value);
-        self.datastore.put(key, value)
+        let value_len = value.len();
+        let result = self.datastore.put(key.clone(), value);
+        self.record("txn-put", Some(&key), Some(value_len), result)
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
@@ -352,48 +600,139 @@ impl<TDS: TxnDataStore> DataStoreWrite for LogTxnDataStore<TDS> {
         K: Borrow<Key>,
     {
         info!("{}: txn delete {}", self.name, key.borrow());
-        self.datastore.delete(key)
+        let result = self.datastore.delete(key);
+        self.record("txn-delete", Some(key.borrow()), None, result)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        info!("{}: txn increment {} by {}", self.name, key.borrow(), delta);
+        let result = self.datastore.increment(key, delta);
+        self.record("txn-increment", Some(key.borrow()), None, result)
     }
 }
 
 impl<TDS: TxnDataStore> DataStoreBatch for LogTxnDataStore<TDS> {
     fn commit(&mut self) -> Result<()> {
         info!("{}: txn commit", self.name);
-        self.commit()
+        let result = self.datastore.commit();
+        self.record("txn-commit", None, None, result)
     }
 }
 
 impl<TDS: TxnDataStore> DataStoreTxn for LogTxnDataStore<TDS> {
     fn discard(&mut self) -> Result<()> {
         info!("{}: txn discard", self.name);
-        self.discard()
+        let result = self.datastore.discard();
+        self.record("txn-discard", None, None, result)
     }
 }
 
 impl<TDS: CheckedTxnDataStore> Check for LogTxnDataStore<TDS> {
     fn check(&self) -> Result<()> {
         info!("{}: check", self.name);
-        self.datastore.check()
+        let result = self.datastore.check();
+        self.record("check", None, None, result)
     }
 }
 
 impl<TDS: GcTxnDataStore> Gc for LogTxnDataStore<TDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         info!("{}: collect_garbage", self.name);
-        self.datastore.collect_garbage()
+        let result = self.datastore.collect_garbage();
+        self.record("collect_garbage", None, None, result)
     }
 }
 
 impl<TDS: PersistentTxnDataStore> Persistent for LogTxnDataStore<TDS> {
     fn disk_usage(&self) -> Result<u64> {
         info!("{}: disk_usage", self.name);
-        self.datastore.disk_usage()
+        let result = self.datastore.disk_usage();
+        self.record("disk_usage", None, None, result)
     }
 }
 
 impl<TDS: ScrubbedTxnDataStore> Scrub for LogTxnDataStore<TDS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         info!("{}: scrub", self.name);
-        self.datastore.scrub()
+        let result = self.datastore.scrub();
+        self.record("scrub", None, None, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::error::DataStoreError;
+    use crate::impls::{BasicBatchDataStore, MapDataStore};
+
+    #[test]
+    fn test_log_sink() {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let captured = entries.clone();
+        let mut store = LogDataStore::with_sink("test", MapDataStore::new(), move |entry| {
+            captured.lock().push(entry)
+        });
+
+        store.put(Key::new("/a"), b"abc".to_vec()).unwrap();
+        assert_eq!(store.get(&Key::new("/a")).unwrap(), b"abc".to_vec());
+        assert!(store.get(&Key::new("/b")).is_err());
+        assert!(store.has(&Key::new("/a")).unwrap());
+        store.delete(&Key::new("/a")).unwrap();
+
+        let entry = |op, key: &str, value_len, result| LogEntry {
+            op,
+            key: Some(Key::new(key)),
+            value_len,
+            result,
+        };
+        assert_eq!(
+            *entries.lock(),
+            vec![
+                entry("put", "/a", Some(3), Ok(())),
+                entry("get", "/a", Some(3), Ok(())),
+                entry(
+                    "get",
+                    "/b",
+                    None,
+                    Err(DataStoreError::NotFound("/b".into()).to_string())
+                ),
+                entry("has", "/a", None, Ok(())),
+                entry("delete", "/a", None, Ok(())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_sink_batch() {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let captured = entries.clone();
+        let inner = BasicBatchDataStore::new(MapDataStore::new());
+        let store =
+            LogDataStore::with_sink("test", inner, move |entry| captured.lock().push(entry));
+
+        let mut batch = store.batch().unwrap();
+        batch.put(Key::new("/a"), vec![1, 2]).unwrap();
+        batch.delete(&Key::new("/b")).unwrap();
+        batch.commit().unwrap();
+
+        let ops = entries
+            .lock()
+            .iter()
+            .map(|entry| (entry.op, entry.value_len))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec![
+                ("batch-put", Some(2)),
+                ("batch-delete", None),
+                ("batch-commit", None),
+            ]
+        );
     }
 }
@@ -1,16 +1,24 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::vec;
+
+use futures::stream;
 
 use crate::error::{DataStoreError, Result};
 use crate::key::Key;
-use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::query::{Entry, Query};
+use crate::store::{byte_order, sort_keys, DataStore, DataStoreRead, DataStoreWrite, Persistent};
+use crate::store::{ToRange, ToStream};
 
 /// MapDataStore use HashMap for internal storage.
 #[derive(Clone, Debug, Default)]
 pub struct MapDataStore {
     values: HashMap<Key, Vec<u8>>,
+    // the optional index of the keys sorted by their bytes, used by the range scans.
+    index: Option<BTreeMap<Vec<u8>, Key>>,
 }
 
 impl MapDataStore {
@@ -18,6 +26,15 @@ impl MapDataStore {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Create a new MapDataStore maintaining a sorted index of the keys,
+    /// which makes the range scans cheaper at the cost of slower writes.
+    pub fn with_sorted_index() -> Self {
+        Self {
+            values: HashMap::new(),
+            index: Some(BTreeMap::new()),
+        }
+    }
 }
 
 impl DataStore for MapDataStore {
@@ -62,6 +79,44 @@ impl DataStoreRead for MapDataStore {
             .ok_or_else(|| DataStoreError::NotFound(key.borrow().to_string()))?
             .len())
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut keys = self
+            .values
+            .keys()
+            .filter(|key| *key == prefix || prefix.is_ancestor_of(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        Ok(self
+            .values
+            .keys()
+            .filter(|key| *key == prefix || prefix.is_ancestor_of(*key))
+            .count())
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        Ok(self
+            .values
+            .keys()
+            .any(|key| key == prefix || prefix.is_ancestor_of(key)))
+    }
+
+    fn query(&self, query: Query) -> Result<Vec<Entry>> {
+        // only the entries under the prefix are copied out of the map.
+        let prefix = Key::new(&query.prefix);
+        let entries = self
+            .values
+            .iter()
+            .filter(|(key, _)| query.prefix.is_empty() || prefix.is_ancestor_of(*key))
+            .map(|(key, value)| Entry::new(key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        Ok(query.apply(entries))
+    }
 }
 
 impl DataStoreWrite for MapDataStore {
@@ -70,7 +125,11 @@ impl DataStoreWrite for MapDataStore {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.values.insert(key.into(), value.into());
+        let key = key.into();
+        if let Some(index) = &mut self.index {
+            index.insert(key.as_bytes().to_vec(), key.clone());
+        }
+        self.values.insert(key, value.into());
         Ok(())
     }
 
@@ -78,7 +137,262 @@ impl DataStoreWrite for MapDataStore {
     where
         K: Borrow<Key>,
     {
+        if let Some(index) = &mut self.index {
+            index.remove(key.borrow().as_bytes());
+        }
         self.values.remove(key.borrow());
         Ok(())
     }
 }
+
+// The map is not on disk, the usage is estimated as the total size of the keys and values.
+impl Persistent for MapDataStore {
+    fn disk_usage(&self) -> Result<u64> {
+        Ok(self
+            .values
+            .iter()
+            .map(|(key, value)| (key.as_bytes().len() + value.len()) as u64)
+            .sum())
+    }
+}
+
+impl ToStream for MapDataStore {
+    type Stream = stream::Iter<vec::IntoIter<Result<Entry>>>;
+
+    fn async_stream(&self) -> Self::Stream {
+        // snapshot the entries, the later mutations are not observed by the stream.
+        let entries = self
+            .values
+            .iter()
+            .map(|(key, value)| Ok(Entry::new(key.clone(), value.clone())))
+            .collect::<Vec<_>>();
+        stream::iter(entries)
+    }
+}
+
+impl ToRange for MapDataStore {
+    type Range = vec::IntoIter<Result<Entry>>;
+
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        let (start, end) = (start.borrow(), end.borrow());
+        if byte_order(start, end) != Ordering::Less {
+            return Ok(Vec::new().into_iter());
+        }
+        let keys = match &self.index {
+            Some(index) => index
+                .range(start.as_bytes().to_vec()..end.as_bytes().to_vec())
+                .map(|(_, key)| key.clone())
+                .collect(),
+            None => {
+                let mut keys = self
+                    .values
+                    .keys()
+                    .filter(|key| {
+                        byte_order(key, start) != Ordering::Less
+                            && byte_order(key, end) == Ordering::Less
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                sort_keys(&mut keys);
+                keys
+            }
+        };
+        let entries = keys
+            .into_iter()
+            .map(|key| {
+                let value = self.values[&key].clone();
+                Ok(Entry::new(key, value))
+            })
+            .collect::<Vec<_>>();
+        Ok(entries.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::query::{FilterOp, FilterValueCompare, OrderByKey, OrderByKeyDescending};
+
+    #[test]
+    fn test_async_stream() {
+        let mut store = MapDataStore::new();
+        for i in 0..10 {
+            store.put(Key::new(format!("/{}", i)), vec![i]).unwrap();
+        }
+
+        let mut stream = store.async_stream();
+        // the mutation after creating the stream is not observed.
+        store.put(Key::new("/10"), vec![10]).unwrap();
+
+        let mut seen = HashSet::new();
+        block_on(async {
+            while let Some(entry) = stream.next().await {
+                let entry = entry.unwrap();
+                assert_eq!(entry.key, Key::new(format!("/{}", entry.value[0])));
+                assert!(seen.insert(entry.key), "entry yielded more than once");
+                // a slow consumer.
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        assert_eq!(seen.len(), 10);
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut store = MapDataStore::new();
+        for key in &["/b", "/a/2", "/a/1", "/a", "/ab"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+        let keys = |prefix: &str| store.keys(&Key::new(prefix)).unwrap();
+        assert_eq!(
+            keys("/a"),
+            vec![Key::new("/a"), Key::new("/a/1"), Key::new("/a/2")]
+        );
+        assert_eq!(keys("/").len(), 5);
+        assert!(keys("/c").is_empty());
+    }
+
+    #[test]
+    fn test_count() {
+        let mut store = MapDataStore::new();
+        for key in &["/b", "/a/2", "/a/1", "/a/1/x", "/a", "/ab"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+        let count = |prefix: &str| store.count(&Key::new(prefix)).unwrap();
+        assert_eq!(count("/"), 6);
+        assert_eq!(count("/a"), 4);
+        assert_eq!(count("/a/1"), 2);
+        assert_eq!(count("/ab"), 1);
+        assert_eq!(count("/c"), 0);
+        assert!(store.has_prefix(&Key::new("/a/1")).unwrap());
+        assert!(store.has_prefix(&Key::new("/")).unwrap());
+        assert!(!store.has_prefix(&Key::new("/c")).unwrap());
+        assert!(!MapDataStore::new().has_prefix(&Key::new("/")).unwrap());
+    }
+
+    #[test]
+    fn test_query() {
+        let mut store = MapDataStore::new();
+        for i in 0..10u8 {
+            store
+                .put(Key::new(format!("/blocks/{}", i)), vec![i; i as usize])
+                .unwrap();
+        }
+        store.put(Key::new("/blocks"), vec![]).unwrap();
+        store.put(Key::new("/blocksx/1"), vec![1]).unwrap();
+        store.put(Key::new("/other/1"), vec![1]).unwrap();
+
+        let names = |entries: &[Entry]| {
+            entries
+                .iter()
+                .map(|entry| entry.key.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut query = Query {
+            prefix: "/blocks".into(),
+            filters: vec![],
+            orders: vec![Box::new(OrderByKeyDescending)],
+            limit: 3,
+            offset: 0,
+            keys_only: false,
+            return_expirations: false,
+            return_sizes: true,
+        };
+        // only the strict children of the prefix, sorted before truncated.
+        let entries = store.query(query.clone()).unwrap();
+        assert_eq!(names(&entries), ["9", "8", "7"]);
+        assert_eq!(entries[0].value, vec![9; 9]);
+        assert_eq!(entries[0].size, 9);
+
+        query.offset = 8;
+        assert_eq!(names(&store.query(query.clone()).unwrap()), ["1", "0"]);
+
+        // filtered by the value, and the values are dropped with the sizes kept.
+        query.offset = 0;
+        query.limit = 0;
+        query.orders = vec![Box::new(OrderByKey)];
+        query.filters = vec![Box::new(FilterValueCompare::new(
+            vec![5; 5],
+            FilterOp::LessThan,
+        ))];
+        query.keys_only = true;
+        let entries = store.query(query.clone()).unwrap();
+        assert_eq!(names(&entries), ["0", "1", "2", "3", "4"]);
+        assert!(entries.iter().all(|entry| entry.value.is_empty()));
+        assert_eq!(entries[4].size, 4);
+
+        // the empty prefix selects every key.
+        query.prefix = String::new();
+        query.filters.clear();
+        assert_eq!(store.query(query).unwrap().len(), 13);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let mut store = MapDataStore::new();
+        assert_eq!(store.disk_usage().unwrap(), 0);
+        for i in 0..10 {
+            store
+                .put(Key::new(format!("/{}", i)), vec![0; 100])
+                .unwrap();
+        }
+        assert!(store.disk_usage().unwrap() >= 10 * 100);
+
+        store.delete(&Key::new("/0")).unwrap();
+        store.put(Key::new("/1"), vec![]).unwrap();
+        assert!(store.disk_usage().unwrap() >= 8 * 100);
+        assert!(store.disk_usage().unwrap() < 9 * 100);
+    }
+
+    #[test]
+    fn test_range() {
+        for mut store in [MapDataStore::new(), MapDataStore::with_sorted_index()] {
+            for i in (0..20).rev() {
+                store
+                    .put(Key::new(format!("/height/{:02}", i)), vec![i])
+                    .unwrap();
+            }
+            store.put(Key::new("/other/05"), vec![]).unwrap();
+            store.delete(&Key::new("/height/07")).unwrap();
+
+            let entries = store
+                .range(&Key::new("/height/05"), &Key::new("/height/10"))
+                .unwrap()
+                .map(|entry| entry.unwrap().value[0])
+                .collect::<Vec<_>>();
+            assert_eq!(entries, vec![5, 6, 8, 9]);
+
+            let reversed = store.range(&Key::new("/height/10"), &Key::new("/height/05"));
+            assert_eq!(reversed.unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_byte_order() {
+        for mut store in [MapDataStore::new(), MapDataStore::with_sorted_index()] {
+            for key in &["/a/b", "/a-b", "/a"] {
+                store.put(Key::new(key), vec![]).unwrap();
+            }
+            // `-` sorts before `/`, so `/a-b` is listed before `/a/b` unlike the namespace order.
+            let expected = vec![Key::new("/a"), Key::new("/a-b"), Key::new("/a/b")];
+            assert_eq!(store.keys(&Key::new("/")).unwrap(), expected);
+
+            let ranged = store
+                .range(&Key::new("/a"), &Key::new("/b"))
+                .unwrap()
+                .map(|entry| entry.unwrap().key)
+                .collect::<Vec<_>>();
+            assert_eq!(ranged, expected);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+
+use crate::error::Result;
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::query::Entry;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{StreamDataStore, ToBatch, ToStream, ToTxn};
+
+// The latencies are bucketed by the power of two of their nanoseconds.
+const BUCKETS: usize = 64;
+
+struct Histogram {
+    buckets: Vec<AtomicU64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let bucket = (64 - nanos.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> OpStats {
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let count = buckets.iter().sum::<u64>();
+        // the upper bound of the bucket holding the `q` quantile.
+        let percentile = |q: f64| {
+            let rank = ((count as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (i, n) in buckets.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    let nanos = 1u64.checked_shl(i as u32).unwrap_or(u64::MAX);
+                    return Duration::from_nanos(nanos);
+                }
+            }
+            Duration::from_secs(0)
+        };
+        if count == 0 {
+            return OpStats::default();
+        }
+        OpStats {
+            count,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    get: Histogram,
+    put: Histogram,
+    delete: Histogram,
+    has: Histogram,
+    query: Histogram,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// The count and the latency percentiles of an operation.
+///
+/// The latencies are rounded up to a power of two nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// The number of the operations.
+    pub count: u64,
+    /// The median latency.
+    pub p50: Duration,
+    /// The 90th percentile latency.
+    pub p90: Duration,
+    /// The 99th percentile latency.
+    pub p99: Duration,
+}
+
+/// The snapshot of the metrics recorded by the `MeasureDataStore`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The `get` operations.
+    pub get: OpStats,
+    /// The `put` operations.
+    pub put: OpStats,
+    /// The `delete` operations.
+    pub delete: OpStats,
+    /// The `has` operations.
+    pub has: OpStats,
+    /// The queries, timed from the creation of the stream until it's exhausted or dropped.
+    pub query: OpStats,
+    /// The total size of the values returned by `get` and the queries.
+    pub bytes_read: u64,
+    /// The total size of the values written by `put`.
+    pub bytes_written: u64,
+}
+
+/// MeasureDataStore is an adapter that records the count and the latency of the operations
+/// on the inner datastore, to find out which datastore of a stack is slow.
+///
+/// Recording only touches a few atomic counters, the percentiles are computed by `stats`.
+/// The clones share the metrics.
+#[derive(Clone)]
+pub struct MeasureDataStore<DS: DataStore> {
+    metrics: Arc<Metrics>,
+    datastore: DS,
+}
+
+impl<DS: DataStore> MeasureDataStore<DS> {
+    /// Create a new MeasureDataStore.
+    pub fn new(datastore: DS) -> Self {
+        Self {
+            metrics: Arc::new(Metrics::default()),
+            datastore,
+        }
+    }
+
+    /// Return a snapshot of the recorded metrics.
+    pub fn stats(&self) -> Stats {
+        let metrics = &self.metrics;
+        Stats {
+            get: metrics.get.stats(),
+            put: metrics.put.stats(),
+            delete: metrics.delete.stats(),
+            has: metrics.has.stats(),
+            query: metrics.query.stats(),
+            bytes_read: metrics.bytes_read.load(Ordering::Relaxed),
+            bytes_written: metrics.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<DS: DataStore> DataStore for MeasureDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for MeasureDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let start = Instant::now();
+        let result = self.datastore.get(key);
+        self.metrics.get.record(start.elapsed());
+        if let Ok(value) = &result {
+            self.metrics
+                .bytes_read
+                .fetch_add(value.len() as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let start = Instant::now();
+        let result = self.datastore.has(key);
+        self.metrics.has.record(start.elapsed());
+        result
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.size(key)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let start = Instant::now();
+        let result = self.datastore.read_snapshot(keys);
+        self.metrics.get.record(start.elapsed());
+        if let Ok(values) = &result {
+            let bytes = values
+                .iter()
+                .flatten()
+                .map(|value| value.len() as u64)
+                .sum();
+            self.metrics.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for MeasureDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let value = value.into();
+        let len = value.len() as u64;
+        let start = Instant::now();
+        let result = self.datastore.put(key, value);
+        self.metrics.put.record(start.elapsed());
+        if result.is_ok() {
+            self.metrics.bytes_written.fetch_add(len, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let start = Instant::now();
+        let result = self.datastore.delete(key);
+        self.metrics.delete.record(start.elapsed());
+        result
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let start = Instant::now();
+        let result = self.datastore.increment(key, delta);
+        self.metrics.put.record(start.elapsed());
+        if result.is_ok() {
+            let len = std::mem::size_of::<i64>() as u64;
+            self.metrics.bytes_written.fetch_add(len, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl<DS: CheckedDataStore> Check for MeasureDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.datastore.check()
+    }
+}
+
+impl<DS: GcDataStore> Gc for MeasureDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.datastore.collect_garbage()
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for MeasureDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for MeasureDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.datastore.scrub()
+    }
+}
+
+/// MeasureStream is the stream of the `MeasureDataStore`, which records the bytes read
+/// and the latency of the query.
+pub struct MeasureStream<S> {
+    stream: S,
+    start: Instant,
+    metrics: Arc<Metrics>,
+    done: bool,
+}
+
+impl<S> MeasureStream<S> {
+    fn finish(&mut self) {
+        if !self.done {
+            self.done = true;
+            self.metrics.query.record(self.start.elapsed());
+        }
+    }
+}
+
+impl<S: Stream<Item = Result<Entry>> + Unpin> Stream for MeasureStream<S> {
+    type Item = Result<Entry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.stream).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(entry))) => {
+                self.metrics
+                    .bytes_read
+                    .fetch_add(entry.value.len() as u64, Ordering::Relaxed);
+            }
+            Poll::Ready(None) => self.finish(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for MeasureStream<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl<DS: StreamDataStore> ToStream for MeasureDataStore<DS> {
+    type Stream = MeasureStream<DS::Stream>;
+
+    fn async_stream(&self) -> Self::Stream {
+        MeasureStream {
+            stream: self.datastore.async_stream(),
+            start: Instant::now(),
+            metrics: self.metrics.clone(),
+            done: false,
+        }
+    }
+}
+
+impl<DS: DataStore> ToBatch for MeasureDataStore<DS> {
+    type Batch = BasicBatchDataStore<MeasureDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for MeasureDataStore<DS> {
+    type Txn = BasicTxnDataStore<MeasureDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on_stream;
+
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    #[test]
+    fn test_measure() {
+        let mut store = MeasureDataStore::new(MapDataStore::new());
+        assert_eq!(store.stats(), Stats::default());
+
+        for i in 0..10 {
+            store.put(Key::new(format!("/{}", i)), vec![0; 4]).unwrap();
+        }
+        for i in 0..5 {
+            store.get(&Key::new(format!("/{}", i))).unwrap();
+        }
+        assert!(store.get(&Key::new("/absent")).is_err());
+        assert!(store.has(&Key::new("/0")).unwrap());
+        store.delete(&Key::new("/0")).unwrap();
+        assert_eq!(block_on_stream(store.async_stream()).count(), 9);
+
+        let stats = store.stats();
+        assert_eq!(stats.put.count, 10);
+        assert_eq!(stats.get.count, 6);
+        assert_eq!(stats.has.count, 1);
+        assert_eq!(stats.delete.count, 1);
+        assert_eq!(stats.query.count, 1);
+        assert_eq!(stats.bytes_written, 10 * 4);
+        assert_eq!(stats.bytes_read, 5 * 4 + 9 * 4);
+        assert!(stats.put.p50 > Duration::from_secs(0));
+        assert!(stats.put.p50 <= stats.put.p90 && stats.put.p90 <= stats.put.p99);
+
+        // the clones share the metrics.
+        store.clone().get(&Key::new("/1")).unwrap();
+        assert_eq!(store.stats().get.count, 7);
+    }
+}
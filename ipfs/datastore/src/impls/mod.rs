@@ -1,23 +1,56 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+mod autobatch;
 mod basic;
+mod bloom;
+mod branch;
+mod budget;
+mod cache;
+mod checksum;
 mod delay;
 mod dummy;
 mod fail;
+mod gc;
 mod log;
 mod map;
+mod measure;
+mod quorum;
+mod rcu;
+mod readonly;
+mod retry;
+mod sequence;
+mod shadow;
+mod swap;
 mod sync;
 mod transform;
+mod ttl;
 
-pub use self::basic::{BasicBatchDataStore, BasicTxnDataStore};
-pub use self::delay::{Delay, DelayDataStore};
+pub use self::autobatch::{AutoBatchDataStore, BatchTuner, DEFAULT_AUTOBATCH_THRESHOLD};
+pub use self::basic::{BasicBatchDataStore, BasicTxnDataStore, BatchConfig, WriteLock};
+pub use self::bloom::{BloomDataStore, DEFAULT_BLOOM_CAPACITY, DEFAULT_BLOOM_FALSE_POSITIVE_RATE};
+pub use self::branch::{BranchDataStore, ToBranch};
+pub use self::budget::{BudgetedCacheDataStore, WriteMode};
+pub(crate) use self::cache::read_snapshot_through;
+pub use self::cache::{CacheDataStore, EvictionPolicy, FifoPolicy, LfuPolicy, LruPolicy};
+pub use self::checksum::ChecksumDataStore;
+pub use self::delay::{Delay, DelayConfig, DelayDataStore};
 pub use self::dummy::DummyDataStore;
+pub use self::gc::MapGcDataStore;
 pub use self::map::MapDataStore;
+pub use self::measure::{MeasureDataStore, MeasureStream, OpStats, Stats};
+pub use self::quorum::QuorumDataStore;
+pub use self::rcu::RcuMapDataStore;
+pub use self::readonly::ReadOnlyDataStore;
+pub use self::retry::{RetryDataStore, DEFAULT_RETRY_MAX_DELAY};
+pub use self::sequence::{Change, ChangeOp, SequencedDataStore};
+pub use self::shadow::{ShadowDataStore, DEFAULT_SHADOW_SAMPLE_CAPACITY};
+pub use self::ttl::{TtlMapDataStore, DEFAULT_TTL_SWEEP_INTERVAL};
 
-pub use self::fail::{FailBatchDataStore, FailDataStore, FailFn, FailTxnDataStore};
-pub use self::log::{LogBatchDataStore, LogDataStore, LogTxnDataStore};
+pub use self::fail::{FailBatchDataStore, FailBuilder, FailDataStore, FailFn, FailTxnDataStore};
+pub use self::log::{LogBatchDataStore, LogDataStore, LogEntry, LogSink, LogTxnDataStore};
+pub use self::swap::SwappableDataStore;
 pub use self::sync::{SyncBatchDataStore, SyncDataStore, SyncTxnDataStore};
 pub use self::transform::{
-    KeyMapFn, KeyTransform, KeyTransformPair, PrefixTransform, TransformBatchDataStore,
-    TransformDataStore, TransformTxnDataStore,
+    ComposedTransform, KeyMapFn, KeyTransform, KeyTransformPair, PrefixStream, PrefixTransform,
+    SuffixTransform, TransformBatchDataStore, TransformDataStore, TransformTxnDataStore,
 };
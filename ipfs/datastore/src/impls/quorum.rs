@@ -0,0 +1,441 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{increment_counter, sort_keys, DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+const VALUE_TAG: u8 = 0;
+const TOMBSTONE_TAG: u8 = 1;
+// version (8 bytes) + tag (1 byte).
+const HEADER_LEN: usize = 9;
+
+// The value stored in the replicas, a deleted key is kept as a tombstone
+// so that the deletion wins over the stale values.
+#[derive(Clone, Debug, PartialEq)]
+struct Versioned {
+    version: u64,
+    value: Option<Vec<u8>>,
+}
+
+impl Versioned {
+    fn encode(&self) -> Vec<u8> {
+        let value = self.value.as_deref().unwrap_or_default();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + value.len());
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.push(if self.value.is_some() {
+            VALUE_TAG
+        } else {
+            TOMBSTONE_TAG
+        });
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn decode(key: &Key, bytes: &[u8]) -> Result<Self> {
+        let corruption =
+            || DataStoreError::Corruption(format!("invalid versioned value of {}", key));
+        if bytes.len() < HEADER_LEN {
+            return Err(corruption());
+        }
+        let version = u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes; qed"));
+        let value = match bytes[8] {
+            VALUE_TAG => Some(bytes[HEADER_LEN..].to_vec()),
+            TOMBSTONE_TAG => None,
+            _ => return Err(corruption()),
+        };
+        Ok(Self { version, value })
+    }
+}
+
+/// QuorumDataStore replicates the entries across N replicas.
+///
+/// A write succeeds once W replicas acknowledge it, and a read returns once R replicas
+/// respond, with the newest version on conflict. Choosing W + R > N gives strong consistency,
+/// since every read quorum overlaps every write quorum.
+///
+/// The stale replicas found by a read are repaired with the newest version (read-repair).
+/// The values are versioned in the replicas, so the replicas shouldn't be accessed directly.
+/// The replicas are cloned for read-repair, so their clones must share the storage
+/// (e.g. `SyncDataStore`).
+#[derive(Clone)]
+pub struct QuorumDataStore<DS: DataStore> {
+    replicas: Vec<DS>,
+    write_quorum: usize,
+    read_quorum: usize,
+    // the highest version observed, versions of the new writes are greater than it.
+    clock: Arc<AtomicU64>,
+    // held by `increment`, so the read-modify-writes of the clones don't interleave.
+    lock: Arc<Mutex<()>>,
+}
+
+impl<DS: DataStore> QuorumDataStore<DS> {
+    /// Create a new QuorumDataStore over the `replicas`, with the write quorum W and
+    /// the read quorum R.
+    pub fn new(replicas: Vec<DS>, write_quorum: usize, read_quorum: usize) -> Self {
+        let n = replicas.len();
+        assert!(
+            (1..=n).contains(&write_quorum),
+            "write quorum must be in [1, {}]",
+            n
+        );
+        assert!(
+            (1..=n).contains(&read_quorum),
+            "read quorum must be in [1, {}]",
+            n
+        );
+        Self {
+            replicas,
+            write_quorum,
+            read_quorum,
+            clock: Arc::new(AtomicU64::new(0)),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Return whether W + R > N, i.e. every read observes the latest successful write.
+    pub fn is_strongly_consistent(&self) -> bool {
+        self.write_quorum + self.read_quorum > self.replicas.len()
+    }
+
+    /// Return the replicas.
+    pub fn replicas(&self) -> &[DS] {
+        &self.replicas
+    }
+
+    fn read_replica(&self, index: usize, key: &Key) -> Result<Option<Versioned>> {
+        match self.replicas[index].get(key) {
+            Ok(bytes) => Ok(Some(Versioned::decode(key, &bytes)?)),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Read R replicas, repair the stale ones and return the newest version.
+    fn read(&self, key: &Key) -> Result<Option<Versioned>> {
+        let mut reads = Vec::with_capacity(self.read_quorum);
+        let mut last_err = None;
+        for index in 0..self.replicas.len() {
+            match self.read_replica(index, key) {
+                Ok(versioned) => reads.push((index, versioned)),
+                Err(err) => last_err = Some(err),
+            }
+            if reads.len() == self.read_quorum {
+                break;
+            }
+        }
+        if reads.len() < self.read_quorum {
+            return Err(DataStoreError::Custom(format!(
+                "read quorum not reached for {}: {} replicas responded, {} required, last error: {:?}",
+                key,
+                reads.len(),
+                self.read_quorum,
+                last_err
+            )));
+        }
+
+        let newest = reads
+            .iter()
+            .filter_map(|(_, versioned)| versioned.as_ref())
+            .max_by_key(|versioned| versioned.version)
+            .cloned();
+        if let Some(newest) = &newest {
+            self.clock.fetch_max(newest.version, Ordering::SeqCst);
+            let encoded = newest.encode();
+            for (index, versioned) in &reads {
+                if versioned.as_ref().map(|v| v.version) != Some(newest.version) {
+                    let mut replica = self.replicas[*index].clone();
+                    if let Err(err) = replica.put(key.clone(), encoded.clone()) {
+                        warn!("Failed to repair {} of the replica {}: {}", key, index, err);
+                    }
+                }
+            }
+        }
+        Ok(newest)
+    }
+
+    // Write the value (or the tombstone) to all the replicas, succeed once W acknowledge.
+    fn write(&mut self, key: Key, value: Option<Vec<u8>>) -> Result<()> {
+        let observed = (0..self.replicas.len())
+            .filter_map(|index| self.read_replica(index, &key).ok().flatten())
+            .map(|versioned| versioned.version)
+            .max()
+            .unwrap_or_default();
+        let version = observed.max(self.clock.load(Ordering::SeqCst)) + 1;
+        self.clock.fetch_max(version, Ordering::SeqCst);
+        let encoded = Versioned { version, value }.encode();
+
+        let mut acks = 0;
+        let mut last_err = None;
+        for replica in &mut self.replicas {
+            match replica.put(key.clone(), encoded.clone()) {
+                Ok(()) => acks += 1,
+                Err(err) => last_err = Some(err),
+            }
+        }
+        if acks < self.write_quorum {
+            return Err(DataStoreError::Custom(format!(
+                "write quorum not reached for {}: {} replicas acknowledged, {} required, last error: {:?}",
+                key, acks, self.write_quorum, last_err
+            )));
+        }
+        Ok(())
+    }
+
+    fn for_each_replica<F>(&self, f: F) -> Result<()>
+    where
+        F: FnMut(&DS) -> Result<()>,
+    {
+        self.replicas.iter().try_for_each(f)
+    }
+}
+
+impl<DS: DataStore> DataStore for QuorumDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.replicas
+            .iter_mut()
+            .try_for_each(|replica| replica.sync(prefix))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.replicas
+            .iter_mut()
+            .try_for_each(|replica| replica.close())
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for QuorumDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.read(key)? {
+            Some(Versioned {
+                value: Some(value), ..
+            }) => Ok(value),
+            _ => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        match self.get(key) {
+            Ok(_) => Ok(true),
+            Err(err) if err.is_not_found() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        self.get(key).map(|value| value.len())
+    }
+
+    // List the keys of R replicas, and keep the ones whose newest version isn't a tombstone.
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut listed = HashSet::new();
+        let mut responded = 0;
+        let mut last_err = None;
+        for replica in &self.replicas {
+            match replica.keys(prefix) {
+                Ok(keys) => {
+                    listed.extend(keys);
+                    responded += 1;
+                }
+                Err(err) => last_err = Some(err),
+            }
+            if responded == self.read_quorum {
+                break;
+            }
+        }
+        if responded < self.read_quorum {
+            return Err(DataStoreError::Custom(format!(
+                "read quorum not reached for the keys of {}: {} replicas responded, {} required, last error: {:?}",
+                prefix,
+                responded,
+                self.read_quorum,
+                last_err
+            )));
+        }
+
+        let mut keys = Vec::with_capacity(listed.len());
+        for key in listed {
+            if let Some(Versioned { value: Some(_), .. }) = self.read(&key)? {
+                keys.push(key);
+            }
+        }
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for QuorumDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.write(key.into(), Some(value.into()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.write(key.borrow().clone(), None)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the replicas store the versioned values, read-modify-write them under the lock.
+        let lock = self.lock.clone();
+        let _guard = lock.lock();
+        increment_counter(self, key.borrow(), delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.replicas
+            .iter_mut()
+            .try_for_each(|replica| replica.fence())
+    }
+}
+
+impl<DS: CheckedDataStore> Check for QuorumDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.for_each_replica(|replica| replica.check())
+    }
+}
+
+impl<DS: GcDataStore> Gc for QuorumDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        let mut reclaimed = 0;
+        self.for_each_replica(|replica| {
+            reclaimed += replica.collect_garbage()?;
+            Ok(())
+        })?;
+        Ok(reclaimed)
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for QuorumDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        let mut usage = 0;
+        self.for_each_replica(|replica| {
+            usage += replica.disk_usage()?;
+            Ok(())
+        })?;
+        Ok(usage)
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for QuorumDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        // the keys corrupted in any replica.
+        let mut keys = Vec::new();
+        self.for_each_replica(|replica| {
+            keys.extend(replica.scrub()?);
+            Ok(())
+        })?;
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+impl<DS: DataStore> ToBatch for QuorumDataStore<DS> {
+    type Batch = BasicBatchDataStore<QuorumDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for QuorumDataStore<DS> {
+    type Txn = BasicTxnDataStore<QuorumDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    type Replica = SyncDataStore<MapDataStore>;
+
+    fn new_store() -> QuorumDataStore<Replica> {
+        let replicas = (0..3)
+            .map(|_| SyncDataStore::new(MapDataStore::new()))
+            .collect::<Vec<Replica>>();
+        QuorumDataStore::new(replicas, 2, 2)
+    }
+
+    #[test]
+    fn test_read_repair() {
+        let mut store = new_store();
+        assert!(store.is_strongly_consistent());
+        let key = Key::new("/a");
+
+        store.put(key.clone(), b"v1".to_vec()).unwrap();
+        // the replica 0 misses the second write.
+        let stale = store.replicas()[0].get(&key).unwrap();
+        store.put(key.clone(), b"v2".to_vec()).unwrap();
+        store.replicas()[0].clone().put(key.clone(), stale).unwrap();
+
+        // the read quorum is the replica 0 and 1, the newest version wins.
+        assert_eq!(store.get(&key).unwrap(), b"v2".to_vec());
+        let repaired = store.replicas()[0].get(&key).unwrap();
+        assert_eq!(repaired, store.replicas()[1].get(&key).unwrap());
+
+        // the replica 0 misses the entry completely.
+        store.replicas()[0].clone().delete(&key).unwrap();
+        assert_eq!(store.get(&key).unwrap(), b"v2".to_vec());
+        assert!(store.replicas()[0].has(&key).unwrap());
+    }
+
+    #[test]
+    fn test_delete_wins_over_stale_value() {
+        let mut store = new_store();
+        let key = Key::new("/a");
+
+        store.put(key.clone(), b"v1".to_vec()).unwrap();
+        let stale = store.replicas()[1].get(&key).unwrap();
+        store.delete(&key).unwrap();
+        store.replicas()[1].clone().put(key.clone(), stale).unwrap();
+
+        assert!(!store.has(&key).unwrap());
+        assert!(store.get(&key).unwrap_err().is_not_found());
+
+        // a write after the deletion is newer than the tombstone.
+        store.put(key.clone(), b"v2".to_vec()).unwrap();
+        assert_eq!(store.get(&key).unwrap(), b"v2".to_vec());
+        assert_eq!(store.size(&key).unwrap(), 2);
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::sync::Arc;
+use std::vec;
+
+use arc_swap::ArcSwap;
+use futures::stream;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore, WriteLock};
+use crate::key::Key;
+use crate::query::Entry;
+use crate::store::{byte_order, increment_counter, sort_keys, Persistent};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{ToBatch, ToRange, ToStream, ToTxn};
+
+type Snapshot = im::HashMap<Key, Vec<u8>>;
+
+/// RcuMapDataStore is a thread-safe in-memory datastore using a read-copy-update scheme.
+///
+/// Reads load the current snapshot without taking any lock, writes clone the snapshot,
+/// apply the change and swap the new snapshot in under a write lock. A read always sees
+/// a consistent snapshot, and a write becomes visible atomically.
+///
+/// Cloning the snapshot is cheap since the map is persistent, but writes are still more
+/// expensive than the ones of `SyncDataStore<MapDataStore>`, so prefer it for read-heavy loads.
+#[derive(Clone, Default)]
+pub struct RcuMapDataStore {
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    write_lock: WriteLock,
+}
+
+impl RcuMapDataStore {
+    /// Create a new RcuMapDataStore.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the number of the entries of the current snapshot.
+    pub fn len(&self) -> usize {
+        self.snapshot.load().len()
+    }
+
+    /// Return whether the current snapshot is empty.
+    pub fn is_empty(&self) -> bool {
+        self.snapshot.load().is_empty()
+    }
+
+    // Apply the change to a copy of the snapshot and swap it in, under the write lock
+    // recording the written `key`, or any key if it's unknown.
+    fn update<F: FnOnce(&mut Snapshot)>(&self, key: Option<&Key>, f: F) {
+        let swap = || {
+            let mut snapshot = Snapshot::clone(&self.snapshot.load());
+            f(&mut snapshot);
+            self.snapshot.store(Arc::new(snapshot));
+        };
+        match key {
+            Some(key) => self.write_lock.write(key, swap),
+            None => self.write_lock.write_all(swap),
+        }
+    }
+}
+
+impl DataStore for RcuMapDataStore {
+    fn sync<K>(&mut self, _prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl DataStoreRead for RcuMapDataStore {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(self
+            .snapshot
+            .load()
+            .get(key.borrow())
+            .ok_or_else(|| DataStoreError::NotFound(key.borrow().to_string()))?
+            .to_owned())
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(self.snapshot.load().contains_key(key.borrow()))
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(self
+            .snapshot
+            .load()
+            .get(key.borrow())
+            .ok_or_else(|| DataStoreError::NotFound(key.borrow().to_string()))?
+            .len())
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        // all the keys are read from the same snapshot.
+        let snapshot = self.snapshot.load();
+        Ok(keys.iter().map(|key| snapshot.get(key).cloned()).collect())
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut keys = self
+            .snapshot
+            .load()
+            .keys()
+            .filter(|key| *key == prefix || prefix.is_ancestor_of(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+}
+
+impl DataStoreWrite for RcuMapDataStore {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let (key, value) = (key.into(), value.into());
+        self.update(Some(&key), |snapshot| {
+            snapshot.insert(key.clone(), value);
+        });
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.update(Some(key), |snapshot| {
+            snapshot.remove(key);
+        });
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the write lock is reentrant, the put of the read-modify-write takes it again.
+        let key = key.borrow();
+        let lock = self.write_lock.clone();
+        lock.write(key, || increment_counter(self, key, delta))
+    }
+}
+
+// Like MapDataStore, the usage is estimated as the total size of the keys and values.
+impl Persistent for RcuMapDataStore {
+    fn disk_usage(&self) -> Result<u64> {
+        Ok(self
+            .snapshot
+            .load()
+            .iter()
+            .map(|(key, value)| (key.as_bytes().len() + value.len()) as u64)
+            .sum())
+    }
+}
+
+impl ToBatch for RcuMapDataStore {
+    type Batch = BasicBatchDataStore<RcuMapDataStore>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl ToTxn for RcuMapDataStore {
+    type Txn = BasicTxnDataStore<RcuMapDataStore>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::with_write_lock(
+            self.clone(),
+            self.write_lock.clone(),
+        ))
+    }
+}
+
+impl ToRange for RcuMapDataStore {
+    type Range = vec::IntoIter<Result<Entry>>;
+
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        let (start, end) = (start.borrow(), end.borrow());
+        if byte_order(start, end) != Ordering::Less {
+            return Ok(Vec::new().into_iter());
+        }
+        let snapshot = self.snapshot.load_full();
+        let mut keys = snapshot
+            .keys()
+            .filter(|key| {
+                byte_order(key, start) != Ordering::Less && byte_order(key, end) == Ordering::Less
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_keys(&mut keys);
+        let entries = keys
+            .into_iter()
+            .map(|key| {
+                let value = snapshot[&key].clone();
+                Ok(Entry::new(key, value))
+            })
+            .collect::<Vec<_>>();
+        Ok(entries.into_iter())
+    }
+}
+
+impl ToStream for RcuMapDataStore {
+    type Stream = stream::Iter<vec::IntoIter<Result<Entry>>>;
+
+    fn async_stream(&self) -> Self::Stream {
+        // the stream iterates the current snapshot, the later writes are not observed.
+        let entries = self
+            .snapshot
+            .load()
+            .iter()
+            .map(|(key, value)| Ok(Entry::new(key.clone(), value.clone())))
+            .collect::<Vec<_>>();
+        stream::iter(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::store::DataStoreBatch;
+
+    #[test]
+    fn test_rcu_consistent_snapshot() {
+        let mut store = RcuMapDataStore::new();
+        let keys = (0..16)
+            .map(|i| Key::new(format!("/{}", i)))
+            .collect::<Vec<_>>();
+        for key in &keys {
+            store.put(key.clone(), vec![0]).unwrap();
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let readers = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        // every batch rewrites all the keys with the same value,
+                        // so all the values of a snapshot must be equal.
+                        let snapshot = store.snapshot.load_full();
+                        let values = snapshot.values().collect::<Vec<_>>();
+                        assert_eq!(values.len(), 16);
+                        assert!(values.iter().all(|value| *value == values[0]));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for round in 1..=100u8 {
+            store.update(None, |snapshot| {
+                for key in &keys {
+                    snapshot.insert(key.clone(), vec![round]);
+                }
+            });
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        for key in &keys {
+            assert_eq!(store.get(key).unwrap(), vec![100]);
+        }
+    }
+
+    #[test]
+    fn test_rcu_read_snapshot() {
+        let mut store = RcuMapDataStore::new();
+        let (head, state) = (Key::new("/head"), Key::new("/state"));
+        store.put(head.clone(), vec![0]).unwrap();
+        store.put(state.clone(), vec![0]).unwrap();
+        let keys = vec![head.clone(), state.clone(), Key::new("/absent")];
+
+        let done = Arc::new(AtomicBool::new(false));
+        let readers = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                let done = done.clone();
+                let keys = keys.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        // the head and its state are always updated together.
+                        let values = store.read_snapshot(&keys).unwrap();
+                        assert_eq!(values.len(), 3);
+                        assert!(values[0].is_some());
+                        assert_eq!(values[0], values[1]);
+                        assert_eq!(values[2], None);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for round in 1..=200u8 {
+            store.update(None, |snapshot| {
+                snapshot.insert(head.clone(), vec![round]);
+                snapshot.insert(state.clone(), vec![round]);
+            });
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(
+            store.read_snapshot(&keys).unwrap(),
+            vec![Some(vec![200]), Some(vec![200]), None]
+        );
+    }
+
+    #[test]
+    fn test_rcu_shared_clones() {
+        let mut store = RcuMapDataStore::new();
+        let other = store.clone();
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        assert_eq!(other.get(&Key::new("/a")).unwrap(), b"a".to_vec());
+
+        let mut batch = other.batch().unwrap();
+        batch.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        batch.delete(&Key::new("/a")).unwrap();
+        assert!(!store.has(&Key::new("/b")).unwrap());
+        batch.commit().unwrap();
+        assert!(store.has(&Key::new("/b")).unwrap());
+        assert!(!store.has(&Key::new("/a")).unwrap());
+        assert_eq!(store.len(), 1);
+    }
+}
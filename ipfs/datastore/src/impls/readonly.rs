@@ -0,0 +1,207 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+
+use crate::error::{DataStoreError, Result};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{RangeDataStore, StreamDataStore, ToBatch, ToRange, ToStream, ToTxn};
+
+/// ReadOnlyDataStore is an adapter that exposes the reads of the inner datastore and
+/// rejects every write with `DataStoreError::ReadOnly`.
+///
+/// The batches and the transactions are read-only too, so the wrapper can be handed to
+/// untrusted code without the inner datastore being modified.
+#[derive(Clone)]
+pub struct ReadOnlyDataStore<DS: DataStore> {
+    datastore: DS,
+}
+
+impl<DS: DataStore> ReadOnlyDataStore<DS> {
+    /// Create a new ReadOnlyDataStore.
+    pub fn new(datastore: DS) -> Self {
+        Self { datastore }
+    }
+}
+
+impl<DS: DataStore> DataStore for ReadOnlyDataStore<DS> {
+    fn sync<K>(&mut self, _prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        // nothing is written through the wrapper.
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for ReadOnlyDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.get(key)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.size(key)
+    }
+
+    fn has_many(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        self.datastore.has_many(keys)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.datastore.read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.keys(prefix)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for ReadOnlyDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, _value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        Err(DataStoreError::ReadOnly(format!("put {}", key.into())))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        Err(DataStoreError::ReadOnly(format!("delete {}", key.borrow())))
+    }
+
+    fn increment<K>(&mut self, key: &K, _delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        Err(DataStoreError::ReadOnly(format!(
+            "increment {}",
+            key.borrow()
+        )))
+    }
+}
+
+// The batch can't buffer any write, so there's nothing to commit.
+impl<DS: DataStore> DataStoreBatch for ReadOnlyDataStore<DS> {
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<DS: DataStore> DataStoreTxn for ReadOnlyDataStore<DS> {
+    fn discard(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<DS: CheckedDataStore> Check for ReadOnlyDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.datastore.check()
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for ReadOnlyDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<DS: StreamDataStore> ToStream for ReadOnlyDataStore<DS> {
+    type Stream = DS::Stream;
+
+    fn async_stream(&self) -> Self::Stream {
+        self.datastore.async_stream()
+    }
+}
+
+impl<DS: RangeDataStore> ToRange for ReadOnlyDataStore<DS> {
+    type Range = DS::Range;
+
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.range(start, end)
+    }
+}
+
+impl<DS: DataStore> ToBatch for ReadOnlyDataStore<DS> {
+    type Batch = ReadOnlyDataStore<DS>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(self.clone())
+    }
+}
+
+impl<DS: DataStore> ToTxn for ReadOnlyDataStore<DS> {
+    type Txn = ReadOnlyDataStore<DS>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on_stream;
+
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    fn is_read_only<T>(result: Result<T>) -> bool {
+        matches!(result, Err(DataStoreError::ReadOnly(_)))
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut inner = MapDataStore::new();
+        inner.put(Key::new("/a"), vec![1]).unwrap();
+        let mut store = ReadOnlyDataStore::new(inner);
+        let (a, b) = (Key::new("/a"), Key::new("/b"));
+
+        assert_eq!(store.get(&a).unwrap(), vec![1]);
+        assert!(store.has(&a).unwrap());
+        assert_eq!(store.size(&a).unwrap(), 1);
+        assert_eq!(store.keys(&Key::new("/")).unwrap(), vec![a.clone()]);
+        assert_eq!(block_on_stream(store.async_stream()).count(), 1);
+
+        assert!(is_read_only(store.put(b.clone(), vec![2])));
+        assert!(is_read_only(store.delete(&a)));
+        assert!(is_read_only(store.increment(&b, 1)));
+
+        let mut batch = store.batch().unwrap();
+        assert!(is_read_only(batch.put(b.clone(), vec![2])));
+        assert!(is_read_only(batch.delete(&a)));
+        batch.commit().unwrap();
+
+        let mut txn = store.txn(false).unwrap();
+        assert!(is_read_only(txn.put(b.clone(), vec![2])));
+        assert!(is_read_only(txn.delete(&a)));
+        txn.commit().unwrap();
+
+        assert_eq!(store.get(&a).unwrap(), vec![1]);
+        assert!(!store.has(&b).unwrap());
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+/// The default upper bound of the delay between two retries.
+pub const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+struct Backoff {
+    max_retries: usize,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+/// RetryDataStore is an adapter that retries the operations on the inner datastore which
+/// fail with a transient error, waiting `base_delay * 2^n` (capped by `max_delay`) before
+/// the n-th retry.
+///
+/// The permanent errors, like `NotFound`, are returned immediately, and the last error is
+/// returned once `max_retries` retries have failed.
+#[derive(Clone)]
+pub struct RetryDataStore<DS: DataStore> {
+    backoff: Backoff,
+    datastore: DS,
+}
+
+impl<DS: DataStore> RetryDataStore<DS> {
+    /// Create a new RetryDataStore, the delay is capped by `DEFAULT_RETRY_MAX_DELAY`.
+    pub fn new(datastore: DS, max_retries: usize, base_delay: Duration) -> Self {
+        Self::with_max_delay(datastore, max_retries, base_delay, DEFAULT_RETRY_MAX_DELAY)
+    }
+
+    /// Create a new RetryDataStore whose delay between two retries never exceeds `max_delay`.
+    pub fn with_max_delay(
+        datastore: DS,
+        max_retries: usize,
+        base_delay: Duration,
+        max_delay: Duration,
+    ) -> Self {
+        Self {
+            backoff: Backoff {
+                max_retries,
+                base_delay,
+                max_delay,
+            },
+            datastore,
+        }
+    }
+}
+
+// Double the delay, saturating at the max delay instead of overflowing.
+fn next_delay(delay: Duration, max_delay: Duration) -> Duration {
+    delay.checked_mul(2).unwrap_or(max_delay).min(max_delay)
+}
+
+fn retry<T, F>(backoff: &Backoff, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut delay = backoff.base_delay.min(backoff.max_delay);
+    let mut retries = 0;
+    loop {
+        match f() {
+            Err(err) if err.is_transient() && retries < backoff.max_retries => {
+                thread::sleep(delay);
+                delay = next_delay(delay, backoff.max_delay);
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+impl<DS: DataStore> DataStore for RetryDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let datastore = &mut self.datastore;
+        retry(&self.backoff, || datastore.sync(prefix))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for RetryDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        retry(&self.backoff, || self.datastore.get(key))
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        retry(&self.backoff, || self.datastore.has(key))
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        retry(&self.backoff, || self.datastore.size(key))
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        retry(&self.backoff, || self.datastore.read_snapshot(keys))
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        retry(&self.backoff, || self.datastore.keys(prefix))
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for RetryDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let (key, value) = (key.into(), value.into());
+        let datastore = &mut self.datastore;
+        retry(&self.backoff, || datastore.put(key.clone(), value.clone()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let datastore = &mut self.datastore;
+        retry(&self.backoff, || datastore.delete(key))
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // not retried, the failed increment may have been applied.
+        self.datastore.increment(key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        let datastore = &mut self.datastore;
+        retry(&self.backoff, || datastore.fence())
+    }
+}
+
+impl<DS: CheckedDataStore> Check for RetryDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        retry(&self.backoff, || self.datastore.check())
+    }
+}
+
+impl<DS: GcDataStore> Gc for RetryDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        retry(&self.backoff, || self.datastore.collect_garbage())
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for RetryDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        retry(&self.backoff, || self.datastore.disk_usage())
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for RetryDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        retry(&self.backoff, || self.datastore.scrub())
+    }
+}
+
+impl<DS: DataStore> ToBatch for RetryDataStore<DS> {
+    type Batch = BasicBatchDataStore<RetryDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for RetryDataStore<DS> {
+    type Txn = BasicTxnDataStore<RetryDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::error::DataStoreError;
+    use crate::impls::{FailDataStore, FailFn, MapDataStore};
+
+    // Fail the first `failures` calls with the error, then succeed.
+    fn fail_times(failures: usize, err: DataStoreError) -> (Arc<AtomicUsize>, impl FailFn) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let fail_fn = move |_: &str, _: Option<&Key>| {
+            if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                Err(err.clone())
+            } else {
+                Ok(())
+            }
+        };
+        (calls, fail_fn)
+    }
+
+    #[test]
+    fn test_retry() {
+        let delay = Duration::from_millis(1);
+        let key = Key::new("/a");
+
+        // fail N-1 times then succeed.
+        let (calls, fail_fn) = fail_times(3, DataStoreError::Timeout("put".into()));
+        let mut store =
+            RetryDataStore::new(FailDataStore::new(fail_fn, MapDataStore::new()), 3, delay);
+        store.put(key.clone(), vec![1]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // give up once the retries are used up.
+        let (calls, fail_fn) = fail_times(4, DataStoreError::Disconnected("put".into()));
+        let mut store =
+            RetryDataStore::new(FailDataStore::new(fail_fn, MapDataStore::new()), 3, delay);
+        let err = store.put(key.clone(), vec![1]).unwrap_err();
+        assert!(matches!(err, DataStoreError::Disconnected(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // the permanent errors are not retried.
+        let (calls, fail_fn) = fail_times(0, DataStoreError::Timeout("get".into()));
+        let store = RetryDataStore::new(FailDataStore::new(fail_fn, MapDataStore::new()), 3, delay);
+        assert!(store.get(&key).unwrap_err().is_not_found());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (calls, fail_fn) = fail_times(1, DataStoreError::Corruption("get".into()));
+        let store = RetryDataStore::new(FailDataStore::new(fail_fn, MapDataStore::new()), 3, delay);
+        let err = store.get(&key).unwrap_err();
+        assert!(matches!(err, DataStoreError::Corruption(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff_max_delay() {
+        let max = Duration::from_millis(5);
+        let mut delay = Duration::from_millis(1);
+        let mut delays = vec![];
+        for _ in 0..5 {
+            delay = next_delay(delay, max);
+            delays.push(delay.as_millis());
+        }
+        assert_eq!(delays, vec![2, 4, 5, 5, 5]);
+        // doubling a huge delay saturates instead of overflowing.
+        let huge = Duration::from_secs(u64::MAX);
+        assert_eq!(next_delay(huge, huge), huge);
+
+        // the retries wait at most the max delay.
+        let (calls, fail_fn) = fail_times(3, DataStoreError::Timeout("put".into()));
+        let inner = FailDataStore::new(fail_fn, MapDataStore::new());
+        let mut store = RetryDataStore::with_max_delay(inner, 3, huge, Duration::from_millis(1));
+        store.put(Key::new("/a"), vec![1]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
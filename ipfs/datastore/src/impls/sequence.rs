@@ -0,0 +1,491 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::convert::TryInto;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore, WriteLock};
+use crate::key::Key;
+use crate::store::{increment_counter, DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+// The reserved namespace used to store the change log in the inner datastore.
+const SEQUENCE_PREFIX: &str = "/.sequence";
+const LATEST_KEY: &str = "/.sequence/latest";
+
+const OP_PUT: u8 = 0;
+const OP_DELETE: u8 = 1;
+
+/// The mutation recorded in the change log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// Store the value.
+    Put(Vec<u8>),
+    /// Remove the value.
+    Delete,
+}
+
+/// A mutation with its sequence number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// The sequence number of the mutation.
+    pub seq: u64,
+    /// The key of the mutation.
+    pub key: Key,
+    /// The mutation operation.
+    pub op: ChangeOp,
+}
+
+impl Change {
+    // op (1 byte) + key length (4 bytes, big endian) + key + value
+    fn encode(&self) -> Vec<u8> {
+        let key = self.key.as_bytes();
+        let mut data = Vec::with_capacity(1 + 4 + key.len());
+        match &self.op {
+            ChangeOp::Put(_) => data.push(OP_PUT),
+            ChangeOp::Delete => data.push(OP_DELETE),
+        }
+        data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        data.extend_from_slice(key);
+        if let ChangeOp::Put(value) = &self.op {
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn decode(seq: u64, data: &[u8]) -> Result<Self> {
+        let invalid = || DataStoreError::Custom(format!("invalid change log entry: {}", seq));
+        if data.len() < 5 {
+            return Err(invalid());
+        }
+        let key_len = u32::from_be_bytes(data[1..5].try_into().expect("4 bytes; qed")) as usize;
+        let key = data.get(5..5 + key_len).ok_or_else(invalid)?;
+        let key = std::str::from_utf8(key).map_err(|_| invalid())?;
+        let op = match data[0] {
+            OP_PUT => ChangeOp::Put(data[5 + key_len..].to_vec()),
+            OP_DELETE => ChangeOp::Delete,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            seq,
+            key: Key::new(key),
+            op,
+        })
+    }
+}
+
+fn log_key(seq: u64) -> Key {
+    // zero padded so that the log keys are sorted by the sequence number.
+    Key::new(format!("{}/log/{:020}", SEQUENCE_PREFIX, seq))
+}
+
+/// SequencedDataStore assigns a gap-free, monotonically increasing sequence number to every
+/// mutation, and records the mutation into a change log stored in the inner datastore.
+///
+/// The change log and the latest sequence number are stored under the reserved `/.sequence`
+/// namespace, which is hidden from the reads and can't be written through the
+/// SequencedDataStore, so they survive restart as long as the inner datastore is persistent.
+/// The mutations of the handles cloned from the SequencedDataStore are serialized by their
+/// shared `WriteLock`.
+pub struct SequencedDataStore<DS: DataStore> {
+    datastore: DS,
+    write_lock: WriteLock,
+}
+
+impl<DS: DataStore> Clone for SequencedDataStore<DS> {
+    fn clone(&self) -> Self {
+        Self {
+            datastore: self.datastore.clone(),
+            write_lock: self.write_lock.clone(),
+        }
+    }
+}
+
+impl<DS: DataStore> SequencedDataStore<DS> {
+    /// Create a new SequencedDataStore,
+    /// restore the latest sequence number from the inner datastore.
+    pub fn new(mut datastore: DS) -> Result<Self> {
+        let latest = read_latest(&datastore)?;
+        // the mutation may be logged but not applied if the program crashed, replay it.
+        if let Some(change) = read_change(&datastore, latest + 1)? {
+            apply(&mut datastore, &change)?;
+            datastore.put(Key::new(LATEST_KEY), change.seq.to_be_bytes().to_vec())?;
+        }
+        Ok(Self {
+            datastore,
+            write_lock: WriteLock::new(),
+        })
+    }
+
+    /// Return the sequence number of the latest mutation, `0` means no mutation.
+    pub fn latest_seq(&self) -> Result<u64> {
+        read_latest(&self.datastore)
+    }
+
+    /// Return all the mutations after the given `seq` in order.
+    pub fn since(&self, seq: u64) -> Result<Vec<Change>> {
+        let latest = self.latest_seq()?;
+        let mut changes = Vec::with_capacity(latest.saturating_sub(seq) as usize);
+        for seq in seq + 1..=latest {
+            let change = read_change(&self.datastore, seq)?.ok_or_else(|| {
+                DataStoreError::Custom(format!("change log entry {} is missing", seq))
+            })?;
+            changes.push(change);
+        }
+        Ok(changes)
+    }
+
+    /// Consume the SequencedDataStore and return the inner datastore.
+    pub fn into_inner(self) -> DS {
+        self.datastore
+    }
+
+    fn record(&mut self, key: Key, op: ChangeOp) -> Result<()> {
+        reserved(&key)?;
+        // hold the lock until the mutation is applied, keep the sequence gap-free.
+        let lock = self.write_lock.clone();
+        lock.write(&key.clone(), || self.append(key, op))
+    }
+
+    fn append(&mut self, key: Key, op: ChangeOp) -> Result<()> {
+        let change = Change {
+            seq: self.latest_seq()? + 1,
+            key,
+            op,
+        };
+        self.datastore.put(log_key(change.seq), change.encode())?;
+        apply(&mut self.datastore, &change)?;
+        self.datastore
+            .put(Key::new(LATEST_KEY), change.seq.to_be_bytes().to_vec())
+    }
+}
+
+// Reject the key in the reserved namespace.
+fn is_reserved(key: &Key) -> bool {
+    let prefix = Key::new(SEQUENCE_PREFIX);
+    *key == prefix || prefix.is_ancestor_of(key.clone())
+}
+
+fn reserved(key: &Key) -> Result<()> {
+    if is_reserved(key) {
+        return Err(DataStoreError::Custom(format!(
+            "key {} is in the reserved namespace {}",
+            key, SEQUENCE_PREFIX
+        )));
+    }
+    Ok(())
+}
+
+fn read_latest<DS: DataStore>(datastore: &DS) -> Result<u64> {
+    match datastore.get(&Key::new(LATEST_KEY)) {
+        Ok(data) => decode_seq(&data),
+        Err(DataStoreError::NotFound(_)) => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+fn decode_seq(data: &[u8]) -> Result<u64> {
+    let data: [u8; 8] = data
+        .try_into()
+        .map_err(|_| DataStoreError::Custom("invalid latest sequence number".into()))?;
+    Ok(u64::from_be_bytes(data))
+}
+
+fn read_change<DS: DataStore>(datastore: &DS, seq: u64) -> Result<Option<Change>> {
+    match datastore.get(&log_key(seq)) {
+        Ok(data) => Ok(Some(Change::decode(seq, &data)?)),
+        Err(DataStoreError::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn apply<DS: DataStore>(datastore: &mut DS, change: &Change) -> Result<()> {
+    match &change.op {
+        ChangeOp::Put(value) => datastore.put(change.key.clone(), value.clone()),
+        ChangeOp::Delete => datastore.delete(&change.key),
+    }
+}
+
+impl<DS: DataStore> DataStore for SequencedDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.sync(&Key::new(SEQUENCE_PREFIX))?;
+        self.datastore.sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.datastore.close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for SequencedDataStore<DS> {
+    // the reserved namespace is hidden from the reads, as if it didn't exist.
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        if is_reserved(key) {
+            return Err(DataStoreError::NotFound(key.to_string()));
+        }
+        self.datastore.get(key)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        if is_reserved(key) {
+            return Ok(false);
+        }
+        self.datastore.has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        if is_reserved(key) {
+            return Err(DataStoreError::NotFound(key.to_string()));
+        }
+        self.datastore.size(key)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = self.datastore.read_snapshot(keys)?;
+        for (key, value) in keys.iter().zip(values.iter_mut()) {
+            if is_reserved(key) {
+                *value = None;
+            }
+        }
+        Ok(values)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut keys = self.datastore.keys(prefix)?;
+        keys.retain(|key| !is_reserved(key));
+        Ok(keys)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for SequencedDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.record(key.into(), ChangeOp::Put(value.into()))
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.record(key.borrow().to_owned(), ChangeOp::Delete)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the put of the new value is logged within the read-modify-write, under the lock.
+        let key = key.borrow();
+        reserved(key)?;
+        let lock = self.write_lock.clone();
+        lock.write(key, || increment_counter(self, key, delta))
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
+}
+
+impl<DS: CheckedDataStore> Check for SequencedDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.datastore.check()
+    }
+}
+
+impl<DS: GcDataStore> Gc for SequencedDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.datastore.collect_garbage()
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for SequencedDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.datastore.disk_usage()
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for SequencedDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.datastore.scrub()
+    }
+}
+
+impl<DS: DataStore> ToBatch for SequencedDataStore<DS> {
+    type Batch = BasicBatchDataStore<SequencedDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for SequencedDataStore<DS> {
+    type Txn = BasicTxnDataStore<SequencedDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::with_write_lock(
+            self.clone(),
+            self.write_lock.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    #[test]
+    fn test_since() {
+        let mut store = SequencedDataStore::new(MapDataStore::new()).unwrap();
+        assert_eq!(store.latest_seq().unwrap(), 0);
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        store.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        let seq = store.latest_seq().unwrap();
+        assert_eq!(seq, 2);
+
+        store.delete(&Key::new("/a")).unwrap();
+        store.put(Key::new("/c"), b"c".to_vec()).unwrap();
+        assert_eq!(
+            store.since(seq).unwrap(),
+            vec![
+                Change {
+                    seq: 3,
+                    key: Key::new("/a"),
+                    op: ChangeOp::Delete,
+                },
+                Change {
+                    seq: 4,
+                    key: Key::new("/c"),
+                    op: ChangeOp::Put(b"c".to_vec()),
+                },
+            ]
+        );
+        assert_eq!(store.since(0).unwrap().len(), 4);
+        assert!(store.since(4).unwrap().is_empty());
+        assert!(!store.has(&Key::new("/a")).unwrap());
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut store = SequencedDataStore::new(MapDataStore::new()).unwrap();
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        store.put(Key::new("/b"), b"b".to_vec()).unwrap();
+
+        let mut store = SequencedDataStore::new(store.into_inner()).unwrap();
+        assert_eq!(store.latest_seq().unwrap(), 2);
+        store.put(Key::new("/c"), b"c".to_vec()).unwrap();
+        let changes = store.since(1).unwrap();
+        assert_eq!(
+            changes.iter().map(|change| change.seq).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_replay_unapplied_change() {
+        let mut inner = MapDataStore::new();
+        let change = Change {
+            seq: 1,
+            key: Key::new("/a"),
+            op: ChangeOp::Put(b"a".to_vec()),
+        };
+        // simulate a crash after the change was logged.
+        inner.put(log_key(1), change.encode()).unwrap();
+
+        let store = SequencedDataStore::new(inner).unwrap();
+        assert_eq!(store.latest_seq().unwrap(), 1);
+        assert_eq!(store.get(&Key::new("/a")).unwrap(), b"a".to_vec());
+        assert_eq!(store.since(0).unwrap(), vec![change]);
+    }
+
+    #[test]
+    fn test_reserved_namespace() {
+        let mut store = SequencedDataStore::new(MapDataStore::new()).unwrap();
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        for key in &[
+            SEQUENCE_PREFIX,
+            LATEST_KEY,
+            "/.sequence/log/00000000000000000001",
+        ] {
+            assert!(store.put(Key::new(key), vec![0]).is_err());
+            assert!(store.delete(&Key::new(key)).is_err());
+        }
+        assert_eq!(store.latest_seq().unwrap(), 1);
+        assert_eq!(store.since(0).unwrap().len(), 1);
+        store.put(Key::new("/.sequenced"), vec![0]).unwrap();
+
+        // the reserved keys are hidden from the reads.
+        assert!(store.get(&Key::new(LATEST_KEY)).unwrap_err().is_not_found());
+        assert!(!store.has(&Key::new(LATEST_KEY)).unwrap());
+        assert_eq!(
+            store.read_snapshot(&[Key::new(LATEST_KEY)]).unwrap(),
+            vec![None]
+        );
+        let root = Key::new("/");
+        assert_eq!(
+            store.keys(&root).unwrap(),
+            vec![Key::new("/.sequenced"), Key::new("/a")]
+        );
+        assert_eq!(store.count(&root).unwrap(), 2);
+        assert!(!store.has_prefix(&Key::new(SEQUENCE_PREFIX)).unwrap());
+    }
+
+    #[test]
+    fn test_copied_datastore() {
+        // the copies of the inner datastore have their own logs.
+        let mut store = SequencedDataStore::new(MapDataStore::new()).unwrap();
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        let mut copy = store.clone();
+        copy.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        assert_eq!(store.latest_seq().unwrap(), 1);
+        assert_eq!(store.since(0).unwrap().len(), 1);
+        assert_eq!(copy.since(0).unwrap().len(), 2);
+
+        // the handles sharing the inner datastore share the log.
+        let store = SequencedDataStore::new(SyncDataStore::new(MapDataStore::new())).unwrap();
+        let handles = (0..8u8)
+            .map(|i| {
+                let mut store = store.clone();
+                std::thread::spawn(move || {
+                    for j in 0..10u8 {
+                        store
+                            .put(Key::new(format!("/{}/{}", i, j)), vec![j])
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let changes = store.since(0).unwrap();
+        assert_eq!(changes.len(), 80);
+        assert!(changes
+            .iter()
+            .enumerate()
+            .all(|(i, change)| change.seq == i as u64 + 1));
+    }
+}
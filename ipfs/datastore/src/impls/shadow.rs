@@ -0,0 +1,296 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::error::Result;
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+/// The default number of the mismatching keys kept by `ShadowDataStore`.
+pub const DEFAULT_SHADOW_SAMPLE_CAPACITY: usize = 32;
+
+#[derive(Default)]
+struct Discrepancies {
+    count: AtomicU64,
+    samples: Mutex<Vec<Key>>,
+}
+
+/// ShadowDataStore runs a new datastore in shadow mode alongside the authoritative old one.
+///
+/// Reads are served by the old datastore, and the same read is issued to the new datastore,
+/// any discrepancy is logged and counted without affecting the result. Writes go to both,
+/// the failed writes of the new datastore are counted as discrepancies as well.
+///
+/// The first mismatching keys, up to the sample capacity, are kept for inspection.
+#[derive(Clone)]
+pub struct ShadowDataStore<Old: DataStore, New: DataStore> {
+    old: Old,
+    new: New,
+    sample_capacity: usize,
+    discrepancies: Arc<Discrepancies>,
+}
+
+impl<Old: DataStore, New: DataStore> ShadowDataStore<Old, New> {
+    /// Create a new ShadowDataStore with the authoritative `old` and the shadow `new` datastores.
+    pub fn new(old: Old, new: New) -> Self {
+        Self::with_sample_capacity(old, new, DEFAULT_SHADOW_SAMPLE_CAPACITY)
+    }
+
+    /// Create a new ShadowDataStore keeping up to `sample_capacity` mismatching keys.
+    pub fn with_sample_capacity(old: Old, new: New, sample_capacity: usize) -> Self {
+        Self {
+            old,
+            new,
+            sample_capacity,
+            discrepancies: Arc::new(Discrepancies::default()),
+        }
+    }
+
+    /// Return the number of the discrepancies found so far.
+    pub fn discrepancies(&self) -> u64 {
+        self.discrepancies.count.load(Ordering::SeqCst)
+    }
+
+    /// Return the sample of the mismatching keys, in the order they were found.
+    pub fn mismatched_keys(&self) -> Vec<Key> {
+        self.discrepancies.samples.lock().clone()
+    }
+
+    /// Return the authoritative datastore.
+    pub fn old(&self) -> &Old {
+        &self.old
+    }
+
+    /// Return the shadow datastore.
+    pub fn shadow(&self) -> &New {
+        &self.new
+    }
+
+    fn record(&self, op: &str, key: &Key, detail: String) {
+        warn!("Shadow {} of {} mismatched: {}", op, key, detail);
+        self.discrepancies.count.fetch_add(1, Ordering::SeqCst);
+        let mut samples = self.discrepancies.samples.lock();
+        if samples.len() < self.sample_capacity && !samples.contains(key) {
+            samples.push(key.clone());
+        }
+    }
+
+    // Compare the result of the shadow read with the authoritative one, and return the latter.
+    fn compare<T>(&self, op: &str, key: &Key, old: Result<T>, new: Result<T>) -> Result<T>
+    where
+        T: PartialEq + Debug,
+    {
+        match (&old, &new) {
+            (Ok(old), Ok(new)) if old == new => {}
+            (Err(old), Err(new)) if old.is_not_found() && new.is_not_found() => {}
+            // the authoritative read failed, there is nothing to compare with.
+            (Err(old), _) if !old.is_not_found() => {}
+            _ => self.record(op, key, format!("expected {:?}, got {:?}", old, new)),
+        }
+        old
+    }
+}
+
+impl<Old: DataStore, New: DataStore> DataStore for ShadowDataStore<Old, New> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.old.sync(prefix)?;
+        if let Err(err) = self.new.sync(prefix) {
+            warn!(
+                "Failed to sync {} of the shadow datastore: {}",
+                prefix.borrow(),
+                err
+            );
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.old.close()?;
+        if let Err(err) = self.new.close() {
+            warn!("Failed to close the shadow datastore: {}", err);
+        }
+        Ok(())
+    }
+}
+
+impl<Old: DataStore, New: DataStore> DataStoreRead for ShadowDataStore<Old, New> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.compare("get", key, self.old.get(key), self.new.get(key))
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.compare("has", key, self.old.has(key), self.new.has(key))
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.compare("size", key, self.old.size(key), self.new.size(key))
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.compare("keys", prefix, self.old.keys(prefix), self.new.keys(prefix))
+    }
+}
+
+impl<Old: DataStore, New: DataStore> DataStoreWrite for ShadowDataStore<Old, New> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let (key, value) = (key.into(), value.into());
+        self.old.put(key.clone(), value.clone())?;
+        if let Err(err) = self.new.put(key.clone(), value) {
+            self.record("put", &key, err.to_string());
+        }
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.old.delete(key)?;
+        if let Err(err) = self.new.delete(key) {
+            self.record("delete", key, err.to_string());
+        }
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        let value = self.old.increment(key, delta)?;
+        // mirror the resulting value, so the new datastore converges to the old one.
+        if let Err(err) = self.new.put(key.clone(), value.to_be_bytes().to_vec()) {
+            self.record("increment", key, err.to_string());
+        }
+        Ok(value)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.old.fence()?;
+        if let Err(err) = self.new.fence() {
+            warn!("Failed to fence the shadow datastore: {}", err);
+        }
+        Ok(())
+    }
+}
+
+impl<Old: CheckedDataStore, New: DataStore> Check for ShadowDataStore<Old, New> {
+    fn check(&self) -> Result<()> {
+        self.old.check()
+    }
+}
+
+impl<Old: GcDataStore, New: DataStore> Gc for ShadowDataStore<Old, New> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.old.collect_garbage()
+    }
+}
+
+impl<Old: PersistentDataStore, New: DataStore> Persistent for ShadowDataStore<Old, New> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.old.disk_usage()
+    }
+}
+
+impl<Old: ScrubbedDataStore, New: DataStore> Scrub for ShadowDataStore<Old, New> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.old.scrub()
+    }
+}
+
+impl<Old: DataStore, New: DataStore> ToBatch for ShadowDataStore<Old, New> {
+    type Batch = BasicBatchDataStore<ShadowDataStore<Old, New>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<Old: DataStore, New: DataStore> ToTxn for ShadowDataStore<Old, New> {
+    type Txn = BasicTxnDataStore<ShadowDataStore<Old, New>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    #[test]
+    fn test_shadow_missing_key() {
+        let mut old = SyncDataStore::new(MapDataStore::new());
+        old.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        old.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        let mut new = SyncDataStore::new(MapDataStore::new());
+        new.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        let store = ShadowDataStore::new(old, new);
+
+        assert_eq!(store.get(&Key::new("/a")).unwrap(), b"a".to_vec());
+        assert_eq!(store.discrepancies(), 0);
+
+        // the shadow datastore misses the key, but the authoritative value is returned.
+        assert_eq!(store.get(&Key::new("/b")).unwrap(), b"b".to_vec());
+        assert!(store.has(&Key::new("/b")).unwrap());
+        assert_eq!(store.discrepancies(), 2);
+        assert_eq!(store.mismatched_keys(), vec![Key::new("/b")]);
+
+        // missing from both is not a discrepancy.
+        assert!(store.get(&Key::new("/c")).unwrap_err().is_not_found());
+        assert_eq!(store.discrepancies(), 2);
+    }
+
+    #[test]
+    fn test_shadow_writes_both() {
+        let old = SyncDataStore::new(MapDataStore::new());
+        let new = SyncDataStore::new(MapDataStore::new());
+        let mut store = ShadowDataStore::with_sample_capacity(old, new, 1);
+
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        assert_eq!(store.shadow().get(&Key::new("/a")).unwrap(), b"a".to_vec());
+        store.delete(&Key::new("/a")).unwrap();
+        assert!(!store.shadow().has(&Key::new("/a")).unwrap());
+
+        store.old.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        store.old.put(Key::new("/c"), b"c".to_vec()).unwrap();
+        store.get(&Key::new("/b")).unwrap();
+        store.get(&Key::new("/c")).unwrap();
+        assert_eq!(store.discrepancies(), 2);
+        assert_eq!(store.mismatched_keys(), vec![Key::new("/b")]);
+    }
+}
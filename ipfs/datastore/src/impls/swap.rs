@@ -0,0 +1,235 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use parking_lot::RwLock;
+
+use crate::error::Result;
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::store::{Check, CheckedDataStore};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+use crate::store::{Gc, GcDataStore};
+use crate::store::{Persistent, PersistentDataStore};
+use crate::store::{Scrub, ScrubbedDataStore};
+use crate::store::{ToBatch, ToTxn};
+
+/// SwappableDataStore serves all operations through the current inner datastore,
+/// which can be replaced atomically (e.g. after a background compaction or migration).
+///
+/// Every operation loads the current datastore once, so the in-flight operations
+/// complete against the old datastore, and the new ones hit the new datastore.
+pub struct SwappableDataStore<DS: DataStore> {
+    current: Arc<ArcSwap<RwLock<DS>>>,
+}
+
+impl<DS: DataStore> Clone for SwappableDataStore<DS> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<DS: DataStore> SwappableDataStore<DS> {
+    /// Create a new SwappableDataStore with the initial datastore.
+    pub fn new(datastore: DS) -> Self {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(RwLock::new(datastore))),
+        }
+    }
+
+    /// Replace the current datastore with the given `datastore` atomically,
+    /// return the replaced datastore.
+    ///
+    /// The replaced datastore is shared with the in-flight operations until they complete.
+    pub fn swap(&self, datastore: DS) -> Arc<RwLock<DS>> {
+        self.current.swap(Arc::new(RwLock::new(datastore)))
+    }
+
+    fn load(&self) -> Arc<RwLock<DS>> {
+        self.current.load_full()
+    }
+}
+
+impl<DS: DataStore> DataStore for SwappableDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.load().write().sync(prefix)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.load().write().close()
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for SwappableDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        self.load().read().get(key)
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        self.load().read().has(key)
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        self.load().read().size(key)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.load().read().read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.load().read().keys(prefix)
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for SwappableDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        self.load().write().put(key, value)
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        self.load().write().delete(key)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        self.load().write().increment(key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.load().write().fence()
+    }
+}
+
+impl<DS: CheckedDataStore> Check for SwappableDataStore<DS> {
+    fn check(&self) -> Result<()> {
+        self.load().read().check()
+    }
+}
+
+impl<DS: GcDataStore> Gc for SwappableDataStore<DS> {
+    fn collect_garbage(&self) -> Result<usize> {
+        self.load().read().collect_garbage()
+    }
+}
+
+impl<DS: PersistentDataStore> Persistent for SwappableDataStore<DS> {
+    fn disk_usage(&self) -> Result<u64> {
+        self.load().read().disk_usage()
+    }
+}
+
+impl<DS: ScrubbedDataStore> Scrub for SwappableDataStore<DS> {
+    fn scrub(&self) -> Result<Vec<Key>> {
+        self.load().read().scrub()
+    }
+}
+
+impl<DS: DataStore> ToBatch for SwappableDataStore<DS> {
+    type Batch = BasicBatchDataStore<SwappableDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for SwappableDataStore<DS> {
+    type Txn = BasicTxnDataStore<SwappableDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::impls::MapDataStore;
+
+    fn new_store(value: &[u8]) -> MapDataStore {
+        let mut store = MapDataStore::new();
+        for i in 0..100 {
+            store
+                .put(Key::new(format!("/{}", i)), value.to_vec())
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_swap_under_reads() {
+        let store = SwappableDataStore::new(new_store(b"old"));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let readers = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::SeqCst) {
+                        for i in 0..100 {
+                            let value = store.get(&Key::new(format!("/{}", i))).unwrap();
+                            assert!(value == b"old" || value == b"new");
+                            reads += 1;
+                        }
+                    }
+                    reads
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..100 {
+            let value: &[u8] = if i % 2 == 0 { b"new" } else { b"old" };
+            store.swap(new_store(value));
+        }
+        store.swap(new_store(b"new"));
+        stop.store(true, Ordering::SeqCst);
+
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(store.get(&Key::new("/0")).unwrap(), b"new".to_vec());
+    }
+
+    #[test]
+    fn test_write_after_swap() {
+        let mut store = SwappableDataStore::new(MapDataStore::new());
+        store.put(Key::new("/a"), b"a".to_vec()).unwrap();
+        let old = store.swap(MapDataStore::new());
+        assert!(!store.has(&Key::new("/a")).unwrap());
+        assert!(old.read().has(&Key::new("/a")).unwrap());
+
+        store.put(Key::new("/b"), b"b".to_vec()).unwrap();
+        assert!(!old.read().has(&Key::new("/b")).unwrap());
+    }
+}
@@ -6,7 +6,9 @@ use std::sync::Arc;
 use parking_lot::RwLock;
 
 use crate::error::Result;
+use crate::impls::WriteLock;
 use crate::key::Key;
+use crate::query::{Entry, Query};
 use crate::store::{BatchDataStore, ToBatch, ToTxn, TxnDataStore};
 use crate::store::{Check, CheckedBatchDataStore, CheckedDataStore, CheckedTxnDataStore};
 use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
@@ -14,12 +16,15 @@ use crate::store::{Gc, GcBatchDataStore, GcDataStore, GcTxnDataStore};
 use crate::store::{
     Persistent, PersistentBatchDataStore, PersistentDataStore, PersistentTxnDataStore,
 };
+use crate::store::{RangeDataStore, ToRange};
 use crate::store::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
+use crate::store::{StreamDataStore, ToStream};
 
 /// SyncDataStore contains a datastore wrapper using rwlock.
 #[derive(Clone)]
 pub struct SyncDataStore<DS: DataStore> {
     datastore: Arc<RwLock<DS>>,
+    write_lock: WriteLock,
 }
 
 impl<DS: DataStore> SyncDataStore<DS> {
@@ -28,8 +33,15 @@ impl<DS: DataStore> SyncDataStore<DS> {
     pub fn new(datastore: DS) -> Self {
         Self {
             datastore: Arc::new(RwLock::new(datastore)),
+            write_lock: WriteLock::new(),
         }
     }
+
+    /// Return the lock taken by the writes of all the handles of the datastore, for the
+    /// `BasicTxnDataStore` built over it.
+    pub fn write_lock(&self) -> WriteLock {
+        self.write_lock.clone()
+    }
 }
 
 impl<DS: DataStore> DataStore for SyncDataStore<DS> {
@@ -66,6 +78,32 @@ impl<DS: DataStore> DataStoreRead for SyncDataStore<DS> {
     {
         self.datastore.read().size(key)
     }
+
+    fn has_many(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        // take the lock once for all the keys.
+        self.datastore.read().has_many(keys)
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        // take the lock once for all the keys.
+        self.datastore.read().read_snapshot(keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.read().keys(prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        self.datastore.read().count(prefix)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        self.datastore.read().has_prefix(prefix)
+    }
+
+    fn query(&self, query: Query) -> Result<Vec<Entry>> {
+        self.datastore.read().query(query)
+    }
 }
 
 impl<DS: DataStore> DataStoreWrite for SyncDataStore<DS> {
@@ -74,14 +112,33 @@ impl<DS: DataStore> DataStoreWrite for SyncDataStore<DS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.datastore.write().put(key, value)
+        let key = key.into();
+        self.write_lock
+            .write(&key, || self.datastore.write().put(key.clone(), value))
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
     where
         K: Borrow<Key>,
     {
-        self.datastore.write().delete(key)
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().delete(key))
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // hold the write lock across the read-modify-write.
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().increment(key, delta))
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.write().fence()
     }
 }
 
@@ -92,7 +149,7 @@ impl<DS: CheckedDataStore> Check for SyncDataStore<DS> {
 }
 
 impl<DS: GcDataStore> Gc for SyncDataStore<DS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.read().collect_garbage()
     }
 }
@@ -104,17 +161,37 @@ impl<DS: PersistentDataStore> Persistent for SyncDataStore<DS> {
 }
 
 impl<DS: ScrubbedDataStore> Scrub for SyncDataStore<DS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         self.datastore.read().scrub()
     }
 }
 
+impl<DS: StreamDataStore> ToStream for SyncDataStore<DS> {
+    type Stream = DS::Stream;
+
+    fn async_stream(&self) -> Self::Stream {
+        self.datastore.read().async_stream()
+    }
+}
+
+impl<DS: RangeDataStore> ToRange for SyncDataStore<DS> {
+    type Range = DS::Range;
+
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>,
+    {
+        self.datastore.read().range(start, end)
+    }
+}
+
 impl<BDS: BatchDataStore> ToBatch for SyncDataStore<BDS> {
     type Batch = SyncBatchDataStore<BDS>;
 
     fn batch(&self) -> Result<Self::Batch> {
         Ok(SyncBatchDataStore {
             datastore: self.datastore.clone(),
+            write_lock: self.write_lock.clone(),
         })
     }
 }
@@ -125,6 +202,7 @@ impl<TDS: TxnDataStore> ToTxn for SyncDataStore<TDS> {
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         Ok(SyncTxnDataStore {
             datastore: self.datastore.clone(),
+            write_lock: self.write_lock.clone(),
         })
     }
 }
@@ -135,6 +213,7 @@ impl<TDS: TxnDataStore> ToTxn for SyncDataStore<TDS> {
 #[derive(Clone)]
 pub struct SyncBatchDataStore<BDS: BatchDataStore> {
     datastore: Arc<RwLock<BDS>>,
+    write_lock: WriteLock,
 }
 
 impl<BDS: BatchDataStore> SyncBatchDataStore<BDS> {
@@ -143,6 +222,7 @@ impl<BDS: BatchDataStore> SyncBatchDataStore<BDS> {
     pub fn new(datastore: BDS) -> Self {
         Self {
             datastore: Arc::new(RwLock::new(datastore)),
+            write_lock: WriteLock::new(),
         }
     }
 }
@@ -168,6 +248,10 @@ impl<BDS: BatchDataStore> DataStoreRead for SyncBatchDataStore<BDS> {
         self.datastore.read().get(key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.datastore.read().read_snapshot(keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
@@ -181,6 +265,10 @@ impl<BDS: BatchDataStore> DataStoreRead for SyncBatchDataStore<BDS> {
     {
         self.datastore.read().size(key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.read().keys(prefix)
+    }
 }
 
 impl<BDS: BatchDataStore> DataStoreWrite for SyncBatchDataStore<BDS> {
@@ -189,20 +277,35 @@ impl<BDS: BatchDataStore> DataStoreWrite for SyncBatchDataStore<BDS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.datastore.write().put(key, value)
+        let key = key.into();
+        self.write_lock
+            .write(&key, || self.datastore.write().put(key.clone(), value))
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
     where
         K: Borrow<Key>,
     {
-        self.datastore.write().delete(key)
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().delete(key))
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().increment(key, delta))
     }
 }
 
 impl<BDS: BatchDataStore> DataStoreBatch for SyncBatchDataStore<BDS> {
     fn commit(&mut self) -> Result<()> {
-        self.datastore.write().commit()
+        self.write_lock
+            .write_all(|| self.datastore.write().commit())
     }
 }
 
@@ -213,7 +316,7 @@ impl<BDS: CheckedBatchDataStore> Check for SyncBatchDataStore<BDS> {
 }
 
 impl<BDS: GcBatchDataStore> Gc for SyncBatchDataStore<BDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.read().collect_garbage()
     }
 }
@@ -225,7 +328,7 @@ impl<BDS: PersistentBatchDataStore> Persistent for SyncBatchDataStore<BDS> {
 }
 
 impl<BDS: ScrubbedBatchDataStore> Scrub for SyncBatchDataStore<BDS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         self.datastore.read().scrub()
     }
 }
@@ -236,6 +339,7 @@ impl<TDS: TxnDataStore> ToTxn for SyncBatchDataStore<TDS> {
     fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
         Ok(SyncTxnDataStore {
             datastore: self.datastore.clone(),
+            write_lock: self.write_lock.clone(),
         })
     }
 }
@@ -246,6 +350,7 @@ impl<TDS: TxnDataStore> ToTxn for SyncBatchDataStore<TDS> {
 #[derive(Clone)]
 pub struct SyncTxnDataStore<TDS: TxnDataStore> {
     datastore: Arc<RwLock<TDS>>,
+    write_lock: WriteLock,
 }
 
 impl<TDS: TxnDataStore> SyncTxnDataStore<TDS> {
@@ -254,6 +359,7 @@ impl<TDS: TxnDataStore> SyncTxnDataStore<TDS> {
     pub fn new(datastore: TDS) -> Self {
         Self {
             datastore: Arc::new(RwLock::new(datastore)),
+            write_lock: WriteLock::new(),
         }
     }
 }
@@ -279,6 +385,10 @@ impl<TDS: TxnDataStore> DataStoreRead for SyncTxnDataStore<TDS> {
         self.datastore.read().get(key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.datastore.read().read_snapshot(keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
@@ -292,6 +402,10 @@ impl<TDS: TxnDataStore> DataStoreRead for SyncTxnDataStore<TDS> {
     {
         self.datastore.read().size(key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        self.datastore.read().keys(prefix)
+    }
 }
 
 impl<TDS: TxnDataStore> DataStoreWrite for SyncTxnDataStore<TDS> {
@@ -300,20 +414,35 @@ impl<TDS: TxnDataStore> DataStoreWrite for SyncTxnDataStore<TDS> {
         K: Into<Key>,
         V: Into<Vec<u8>>,
     {
-        self.datastore.write().put(key, value)
+        let key = key.into();
+        self.write_lock
+            .write(&key, || self.datastore.write().put(key.clone(), value))
     }
 
     fn delete<K>(&mut self, key: &K) -> Result<()>
     where
         K: Borrow<Key>,
     {
-        self.datastore.write().delete(key)
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().delete(key))
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = key.borrow();
+        self.write_lock
+            .write(key, || self.datastore.write().increment(key, delta))
     }
 }
 
 impl<TDS: TxnDataStore> DataStoreBatch for SyncTxnDataStore<TDS> {
     fn commit(&mut self) -> Result<()> {
-        self.datastore.write().commit()
+        self.write_lock
+            .write_all(|| self.datastore.write().commit())
     }
 }
 
@@ -330,7 +459,7 @@ impl<TDS: CheckedTxnDataStore> Check for SyncTxnDataStore<TDS> {
 }
 
 impl<TDS: GcTxnDataStore> Gc for SyncTxnDataStore<TDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.read().collect_garbage()
     }
 }
@@ -342,7 +471,44 @@ impl<TDS: PersistentTxnDataStore> Persistent for SyncTxnDataStore<TDS> {
 }
 
 impl<TDS: ScrubbedTxnDataStore> Scrub for SyncTxnDataStore<TDS> {
-    fn scrub(&self) -> Result<()> {
+    fn scrub(&self) -> Result<Vec<Key>> {
         self.datastore.read().scrub()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::error::DataStoreError;
+    use crate::impls::MapDataStore;
+
+    #[test]
+    fn test_concurrent_increment() {
+        let store = SyncDataStore::new(MapDataStore::new());
+        let key = Key::new("/counter");
+        let threads = (0..16)
+            .map(|_| {
+                let mut store = store.clone();
+                let key = key.clone();
+                thread::spawn(move || store.increment(&key, 1).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(store.get(&key).unwrap(), 16i64.to_be_bytes().to_vec());
+
+        let mut store = store;
+        assert_eq!(store.increment(&key, -20).unwrap(), -4);
+        store
+            .put(key.clone(), i64::MAX.to_be_bytes().to_vec())
+            .unwrap();
+        assert!(matches!(
+            store.increment(&key, 1),
+            Err(DataStoreError::Overflow(_))
+        ));
+        assert_eq!(store.get(&key).unwrap(), i64::MAX.to_be_bytes().to_vec());
+    }
+}
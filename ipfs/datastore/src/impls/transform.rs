@@ -1,9 +1,15 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
 
 use crate::error::Result;
 use crate::key::Key;
+use crate::query::Entry;
+use crate::store::{sort_keys, StreamDataStore, ToStream};
 use crate::store::{BatchDataStore, ToBatch, ToTxn, TxnDataStore};
 use crate::store::{Check, CheckedBatchDataStore, CheckedDataStore, CheckedTxnDataStore};
 use crate::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
@@ -14,12 +20,72 @@ use crate::store::{
 use crate::store::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
 
 /// KeyTransform is an data store with a pair of functions for transforming keys invertibly.
+///
+/// `invert_key` reverses `convert_key`, so `invert_key(convert_key(key)) == key` for any key.
 pub trait KeyTransform: Clone {
     /// Convert `origin` key into `target` key.
     fn convert_key<K: Borrow<Key>>(&self, key: &K) -> Key;
 
     /// Invert `target` key into `origin` key
     fn invert_key<K: Borrow<Key>>(&self, key: &K) -> Key;
+
+    /// Convert `origin` prefix into the `target` prefix that the converted keys under the
+    /// prefix are under, or `None` if there isn't one, then listing the prefix scans and
+    /// filters all the `target` keys.
+    fn convert_prefix<K: Borrow<Key>>(&self, _prefix: &K) -> Option<Key> {
+        None
+    }
+
+    /// Invert `target` key into `origin` key, or `None` if the key isn't converted by the
+    /// transform.
+    fn try_invert_key<K: Borrow<Key>>(&self, key: &K) -> Option<Key> {
+        Some(self.invert_key(key))
+    }
+}
+
+// List the keys under the `origin` prefix through the transform.
+fn transform_keys<KT, DS>(transform: &KT, datastore: &DS, prefix: &Key) -> Result<Vec<Key>>
+where
+    KT: KeyTransform,
+    DS: DataStore,
+{
+    let mut keys = match transform.convert_prefix(prefix) {
+        Some(target) => datastore
+            .keys(&target)?
+            .iter()
+            .map(|key| transform.invert_key(key))
+            .collect::<Vec<_>>(),
+        None => datastore
+            .keys(&Key::new("/"))?
+            .iter()
+            .filter_map(|key| transform.try_invert_key(key))
+            .filter(|key| key == prefix || prefix.is_ancestor_of(key.clone()))
+            .collect::<Vec<_>>(),
+    };
+    sort_keys(&mut keys);
+    Ok(keys)
+}
+
+fn transform_count<KT, DS>(transform: &KT, datastore: &DS, prefix: &Key) -> Result<usize>
+where
+    KT: KeyTransform,
+    DS: DataStore,
+{
+    match transform.convert_prefix(prefix) {
+        Some(target) => datastore.count(&target),
+        None => Ok(transform_keys(transform, datastore, prefix)?.len()),
+    }
+}
+
+fn transform_has_prefix<KT, DS>(transform: &KT, datastore: &DS, prefix: &Key) -> Result<bool>
+where
+    KT: KeyTransform,
+    DS: DataStore,
+{
+    match transform.convert_prefix(prefix) {
+        Some(target) => datastore.has_prefix(&target),
+        None => Ok(!transform_keys(transform, datastore, prefix)?.is_empty()),
+    }
 }
 
 /// TransformDataStore is a datastore with a pair of KeyTransform functions.
@@ -44,7 +110,10 @@ impl<KT: KeyTransform, DS: DataStore> DataStore for TransformDataStore<KT, DS> {
     where
         K: Borrow<Key>,
     {
-        let key = self.transform.convert_key(prefix);
+        let key = self
+            .transform
+            .convert_prefix(prefix)
+            .unwrap_or_else(|| Key::new("/"));
         self.datastore.sync(&key)
     }
 
@@ -77,6 +146,26 @@ impl<KT: KeyTransform, DS: DataStore> DataStoreRead for TransformDataStore<KT, D
         let key = self.transform.convert_key(key);
         self.datastore.size(&key)
     }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys = keys
+            .iter()
+            .map(|key| self.transform.convert_key(key))
+            .collect::<Vec<_>>();
+        self.datastore.read_snapshot(&keys)
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        transform_keys(&self.transform, &self.datastore, prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        transform_count(&self.transform, &self.datastore, prefix)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        transform_has_prefix(&self.transform, &self.datastore, prefix)
+    }
 }
 
 impl<KT: KeyTransform, DS: DataStore> DataStoreWrite for TransformDataStore<KT, DS> {
@@ -96,6 +185,20 @@ impl<KT: KeyTransform, DS: DataStore> DataStoreWrite for TransformDataStore<KT,
         let key = self.transform.convert_key(key);
         self.datastore.delete(&key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // only the key is transformed, so the increment of the inner datastore stays atomic.
+        let key = self.transform.convert_key(key);
+        self.datastore.increment(&key, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        self.datastore.fence()
+    }
 }
 
 impl<KT: KeyTransform, DS: CheckedDataStore> Check for TransformDataStore<KT, DS> {
@@ -105,7 +208,7 @@ impl<KT: KeyTransform, DS: CheckedDataStore> Check for TransformDataStore<KT, DS
 }
 
 impl<KT: KeyTransform, DS: GcDataStore> Gc for TransformDataStore<KT, DS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.collect_garbage()
     }
 }
@@ -117,8 +220,13 @@ impl<KT: KeyTransform, DS: PersistentDataStore> Persistent for TransformDataStor
 }
 
 impl<KT: KeyTransform, DS: ScrubbedDataStore> Scrub for TransformDataStore<KT, DS> {
-    fn scrub(&self) -> Result<()> {
-        self.datastore.scrub()
+    fn scrub(&self) -> Result<Vec<Key>> {
+        let mut keys = self.datastore.scrub()?;
+        for key in keys.iter_mut() {
+            *key = self.transform.invert_key(key);
+        }
+        keys.sort();
+        Ok(keys)
     }
 }
 
@@ -168,7 +276,10 @@ impl<KT: KeyTransform, BDS: BatchDataStore> DataStore for TransformBatchDataStor
     where
         K: Borrow<Key>,
     {
-        let key = self.transform.convert_key(prefix);
+        let key = self
+            .transform
+            .convert_prefix(prefix)
+            .unwrap_or_else(|| Key::new("/"));
         self.datastore.sync(&key)
     }
 
@@ -186,6 +297,14 @@ impl<KT: KeyTransform, BDS: BatchDataStore> DataStoreRead for TransformBatchData
         self.datastore.get(&key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys = keys
+            .iter()
+            .map(|key| self.transform.convert_key(key))
+            .collect::<Vec<_>>();
+        self.datastore.read_snapshot(&keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
@@ -201,6 +320,18 @@ impl<KT: KeyTransform, BDS: BatchDataStore> DataStoreRead for TransformBatchData
         let key = self.transform.convert_key(key);
         self.datastore.size(&key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        transform_keys(&self.transform, &self.datastore, prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        transform_count(&self.transform, &self.datastore, prefix)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        transform_has_prefix(&self.transform, &self.datastore, prefix)
+    }
 }
 
 impl<KT: KeyTransform, BDS: BatchDataStore> DataStoreWrite for TransformBatchDataStore<KT, BDS> {
@@ -220,6 +351,15 @@ impl<KT: KeyTransform, BDS: BatchDataStore> DataStoreWrite for TransformBatchDat
         let key = self.transform.convert_key(key);
         self.datastore.delete(&key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = self.transform.convert_key(key);
+        self.datastore.increment(&key, delta)
+    }
 }
 
 impl<KT: KeyTransform, BDS: BatchDataStore> DataStoreBatch for TransformBatchDataStore<KT, BDS> {
@@ -235,7 +375,7 @@ impl<KT: KeyTransform, BDS: CheckedBatchDataStore> Check for TransformBatchDataS
 }
 
 impl<KT: KeyTransform, BDS: GcBatchDataStore> Gc for TransformBatchDataStore<KT, BDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.collect_garbage()
     }
 }
@@ -249,8 +389,13 @@ impl<KT: KeyTransform, BDS: PersistentBatchDataStore> Persistent
 }
 
 impl<KT: KeyTransform, BDS: ScrubbedBatchDataStore> Scrub for TransformBatchDataStore<KT, BDS> {
-    fn scrub(&self) -> Result<()> {
-        self.datastore.scrub()
+    fn scrub(&self) -> Result<Vec<Key>> {
+        let mut keys = self.datastore.scrub()?;
+        for key in keys.iter_mut() {
+            *key = self.transform.invert_key(key);
+        }
+        keys.sort();
+        Ok(keys)
     }
 }
 
@@ -289,7 +434,10 @@ impl<KT: KeyTransform, TDS: TxnDataStore> DataStore for TransformTxnDataStore<KT
     where
         K: Borrow<Key>,
     {
-        let key = self.transform.convert_key(prefix);
+        let key = self
+            .transform
+            .convert_prefix(prefix)
+            .unwrap_or_else(|| Key::new("/"));
         self.datastore.sync(&key)
     }
 
@@ -307,6 +455,14 @@ impl<KT: KeyTransform, TDS: TxnDataStore> DataStoreRead for TransformTxnDataStor
         self.datastore.get(&key)
     }
 
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys = keys
+            .iter()
+            .map(|key| self.transform.convert_key(key))
+            .collect::<Vec<_>>();
+        self.datastore.read_snapshot(&keys)
+    }
+
     fn has<K>(&self, key: &K) -> Result<bool>
     where
         K: Borrow<Key>,
@@ -322,6 +478,18 @@ impl<KT: KeyTransform, TDS: TxnDataStore> DataStoreRead for TransformTxnDataStor
         let key = self.transform.convert_key(key);
         self.datastore.size(&key)
     }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        transform_keys(&self.transform, &self.datastore, prefix)
+    }
+
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        transform_count(&self.transform, &self.datastore, prefix)
+    }
+
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        transform_has_prefix(&self.transform, &self.datastore, prefix)
+    }
 }
 
 impl<KT: KeyTransform, TDS: TxnDataStore> DataStoreWrite for TransformTxnDataStore<KT, TDS> {
@@ -341,6 +509,15 @@ impl<KT: KeyTransform, TDS: TxnDataStore> DataStoreWrite for TransformTxnDataSto
         let key = self.transform.convert_key(key);
         self.datastore.delete(&key)
     }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let key = self.transform.convert_key(key);
+        self.datastore.increment(&key, delta)
+    }
 }
 
 impl<KT: KeyTransform, TDS: TxnDataStore> DataStoreBatch for TransformTxnDataStore<KT, TDS> {
@@ -362,7 +539,7 @@ impl<KT: KeyTransform, TDS: CheckedTxnDataStore> Check for TransformTxnDataStore
 }
 
 impl<KT: KeyTransform, TDS: GcTxnDataStore> Gc for TransformTxnDataStore<KT, TDS> {
-    fn collect_garbage(&self) -> Result<()> {
+    fn collect_garbage(&self) -> Result<usize> {
         self.datastore.collect_garbage()
     }
 }
@@ -374,8 +551,13 @@ impl<KT: KeyTransform, TDS: PersistentTxnDataStore> Persistent for TransformTxnD
 }
 
 impl<KT: KeyTransform, TDS: ScrubbedTxnDataStore> Scrub for TransformTxnDataStore<KT, TDS> {
-    fn scrub(&self) -> Result<()> {
-        self.datastore.scrub()
+    fn scrub(&self) -> Result<Vec<Key>> {
+        let mut keys = self.datastore.scrub()?;
+        for key in keys.iter_mut() {
+            *key = self.transform.invert_key(key);
+        }
+        keys.sort();
+        Ok(keys)
     }
 }
 
@@ -383,6 +565,7 @@ impl<KT: KeyTransform, TDS: ScrubbedTxnDataStore> Scrub for TransformTxnDataStor
 
 /// KeyMapFn is a function that maps one key to another.
 pub trait KeyMapFn: Clone + Fn(&Key) -> Key {}
+impl<T: Clone + Fn(&Key) -> Key> KeyMapFn for T {}
 
 //// KeyTransformPair is a convince struct for constructing a key transform.
 #[doc(hidden)]
@@ -424,6 +607,10 @@ impl KeyTransform for PrefixTransform {
         if self.prefix.is_root() {
             return key.to_owned();
         }
+        // the inverse of converting the root key.
+        if &self.prefix == key {
+            return Key::new("/");
+        }
 
         if self.prefix.is_ancestor_of(key) {
             let prefix_len = self.prefix.as_str().len();
@@ -432,4 +619,306 @@ impl KeyTransform for PrefixTransform {
             panic!("expected prefix not found");
         }
     }
+
+    fn convert_prefix<K: Borrow<Key>>(&self, prefix: &K) -> Option<Key> {
+        Some(self.convert_key(prefix))
+    }
+
+    fn try_invert_key<K: Borrow<Key>>(&self, key: &K) -> Option<Key> {
+        let key = key.borrow();
+        if &self.prefix == key || self.prefix.is_ancestor_of(key.clone()) {
+            Some(self.invert_key(key))
+        } else {
+            None
+        }
+    }
+}
+
+/// PrefixStream yields the entries of the inner stream under the prefix of the
+/// `PrefixTransform`, with the prefix stripped from their keys.
+pub struct PrefixStream<S> {
+    transform: PrefixTransform,
+    stream: S,
+}
+
+impl<S: Stream<Item = Result<Entry>> + Unpin> Stream for PrefixStream<S> {
+    type Item = Result<Entry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(mut entry))) => {
+                    let prefix = &self.transform.prefix;
+                    if prefix == &entry.key || prefix.is_ancestor_of(&entry.key) {
+                        entry.key = self.transform.invert_key(&entry.key);
+                        return Poll::Ready(Some(Ok(entry)));
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+impl<DS: StreamDataStore> ToStream for TransformDataStore<PrefixTransform, DS> {
+    type Stream = PrefixStream<DS::Stream>;
+
+    fn async_stream(&self) -> Self::Stream {
+        PrefixStream {
+            transform: self.transform.clone(),
+            stream: self.datastore.async_stream(),
+        }
+    }
+}
+
+///  SuffixTransform constructs a KeyTransform with a pair of functions that
+///  append or remove the given suffix key.
+///
+/// # Panics
+///
+/// Inverting key will panic if suffix not found when it should be there.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct SuffixTransform {
+    pub suffix: Key,
+}
+
+impl KeyTransform for SuffixTransform {
+    fn convert_key<K: Borrow<Key>>(&self, key: &K) -> Key {
+        key.borrow().child(&self.suffix)
+    }
+
+    fn invert_key<K: Borrow<Key>>(&self, key: &K) -> Key {
+        let key = key.borrow();
+        if self.suffix.is_root() {
+            return key.to_owned();
+        }
+        // the inverse of converting the root key.
+        if &self.suffix == key {
+            return Key::new("/");
+        }
+
+        let (key_str, suffix) = (key.as_str(), self.suffix.as_str());
+        if key_str.len() > suffix.len()
+            && key_str.ends_with(suffix)
+            && key.as_bytes()[key_str.len() - suffix.len()] == b'/'
+        {
+            unsafe { Key::new_unchecked(&key_str[..key_str.len() - suffix.len()]) }
+        } else {
+            panic!("expected suffix not found");
+        }
+    }
+
+    // the converted keys under a prefix don't share a prefix, so they're listed by a scan.
+    fn try_invert_key<K: Borrow<Key>>(&self, key: &K) -> Option<Key> {
+        let key = key.borrow();
+        let (key_str, suffix) = (key.as_str(), self.suffix.as_str());
+        let converted = self.suffix.is_root()
+            || &self.suffix == key
+            || (key_str.len() > suffix.len()
+                && key_str.ends_with(suffix)
+                && key.as_bytes()[key_str.len() - suffix.len()] == b'/');
+        if converted {
+            Some(self.invert_key(key))
+        } else {
+            None
+        }
+    }
+}
+
+/// ComposedTransform nests two KeyTransforms, the keys are converted by the `inner`
+/// transform then by the `outer` one, and inverted in the reverse order.
+#[derive(Clone)]
+pub struct ComposedTransform<Outer: KeyTransform, Inner: KeyTransform> {
+    outer: Outer,
+    inner: Inner,
+}
+
+impl<Outer: KeyTransform, Inner: KeyTransform> ComposedTransform<Outer, Inner> {
+    /// Create a new ComposedTransform.
+    pub fn new(outer: Outer, inner: Inner) -> Self {
+        Self { outer, inner }
+    }
+}
+
+impl<Outer: KeyTransform, Inner: KeyTransform> KeyTransform for ComposedTransform<Outer, Inner> {
+    fn convert_key<K: Borrow<Key>>(&self, key: &K) -> Key {
+        self.outer.convert_key(&self.inner.convert_key(key))
+    }
+
+    fn invert_key<K: Borrow<Key>>(&self, key: &K) -> Key {
+        self.inner.invert_key(&self.outer.invert_key(key))
+    }
+
+    fn convert_prefix<K: Borrow<Key>>(&self, prefix: &K) -> Option<Key> {
+        let prefix = self.inner.convert_prefix(prefix)?;
+        self.outer.convert_prefix(&prefix)
+    }
+
+    fn try_invert_key<K: Borrow<Key>>(&self, key: &K) -> Option<Key> {
+        let key = self.outer.try_invert_key(key)?;
+        self.inner.try_invert_key(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use rand::Rng;
+
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+
+    // Return the root key and random keys of up to four namespaces, some of them typed.
+    fn random_keys() -> Vec<Key> {
+        let mut rng = rand::thread_rng();
+        let mut keys = vec![Key::new("/")];
+        for _ in 0..100 {
+            let namespaces = (0..rng.gen_range(1, 5))
+                .map(|_| {
+                    let name = Key::random().name().to_owned();
+                    if rng.gen() {
+                        format!("Type:{}", name)
+                    } else {
+                        name
+                    }
+                })
+                .collect::<Vec<_>>();
+            keys.push(Key::with_namespaces(namespaces));
+        }
+        keys
+    }
+
+    fn assert_round_trip<KT: KeyTransform>(transform: &KT) {
+        for key in random_keys() {
+            let converted = transform.convert_key(&key);
+            assert_eq!(transform.invert_key(&converted), key, "{}", converted);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let prefix = PrefixTransform {
+            prefix: Key::new("/prefix/a"),
+        };
+        let suffix = SuffixTransform {
+            suffix: Key::new("/suffix/b"),
+        };
+        assert_round_trip(&prefix);
+        assert_round_trip(&suffix);
+        assert_round_trip(&PrefixTransform {
+            prefix: Key::new("/"),
+        });
+        assert_round_trip(&SuffixTransform {
+            suffix: Key::new("/"),
+        });
+
+        let reverse = KeyTransformPair {
+            convert: |key: &Key| key.reverse(),
+            invert: |key: &Key| key.reverse(),
+        };
+        assert_round_trip(&reverse);
+
+        let nested = ComposedTransform::new(prefix.clone(), suffix.clone());
+        assert_eq!(
+            nested.convert_key(&Key::new("/k")),
+            Key::new("/prefix/a/k/suffix/b")
+        );
+        assert_round_trip(&nested);
+        assert_round_trip(&ComposedTransform::new(reverse, nested));
+        assert_round_trip(&ComposedTransform::new(suffix, prefix));
+    }
+
+    #[test]
+    fn test_prefix_keys() {
+        let mut inner = MapDataStore::new();
+        inner.put(Key::new("/other/a"), vec![]).unwrap();
+        let transform = PrefixTransform {
+            prefix: Key::new("/internal"),
+        };
+        let mut store = TransformDataStore::new(transform, inner);
+        for key in &["/a", "/a/b", "/c"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+
+        // the listed keys are in the caller's namespace.
+        let keys = store.keys(&Key::new("/")).unwrap();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/a/b"), Key::new("/c")]);
+        let keys = store.keys(&Key::new("/a")).unwrap();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/a/b")]);
+        assert!(store.keys(&Key::new("/other")).unwrap().is_empty());
+        assert_eq!(store.count(&Key::new("/")).unwrap(), 3);
+        assert_eq!(store.count(&Key::new("/a")).unwrap(), 2);
+        assert!(!store.has_prefix(&Key::new("/other")).unwrap());
+    }
+
+    #[test]
+    fn test_suffix_keys() {
+        let mut inner = SyncDataStore::new(MapDataStore::new());
+        inner.put(Key::new("/other/a"), vec![]).unwrap();
+        let suffix = SuffixTransform {
+            suffix: Key::new("/meta"),
+        };
+        let mut store = TransformDataStore::new(suffix.clone(), inner.clone());
+        for key in &["/a", "/a/b", "/c"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+
+        // the keys without the suffix are skipped, the others are filtered by the prefix.
+        let keys = store.keys(&Key::new("/")).unwrap();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/a/b"), Key::new("/c")]);
+        let keys = store.keys(&Key::new("/a")).unwrap();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/a/b")]);
+        assert!(store.keys(&Key::new("/other")).unwrap().is_empty());
+        assert_eq!(store.count(&Key::new("/")).unwrap(), 3);
+        assert_eq!(store.count(&Key::new("/a")).unwrap(), 2);
+        assert!(store.has_prefix(&Key::new("/c")).unwrap());
+        assert!(!store.has_prefix(&Key::new("/other")).unwrap());
+
+        // nested under a prefix.
+        let prefix = PrefixTransform {
+            prefix: Key::new("/internal"),
+        };
+        let mut nested =
+            TransformDataStore::new(ComposedTransform::new(prefix, suffix), inner.clone());
+        nested.put(Key::new("/a"), vec![]).unwrap();
+        nested.put(Key::new("/d"), vec![]).unwrap();
+        assert!(inner.has(&Key::new("/internal/a/meta")).unwrap());
+        let keys = nested.keys(&Key::new("/")).unwrap();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/d")]);
+        assert_eq!(nested.count(&Key::new("/d")).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_increment() {
+        let inner = SyncDataStore::new(MapDataStore::new());
+        let transform = PrefixTransform {
+            prefix: Key::new("/prefix"),
+        };
+        let store = TransformDataStore::new(transform, inner.clone());
+        let key = Key::new("/counter");
+        // the threads start together to contend on the counter.
+        let barrier = Arc::new(Barrier::new(8));
+        let threads = (0..8)
+            .map(|_| {
+                let (mut store, key, barrier) = (store.clone(), key.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..1000 {
+                        store.increment(&key, 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // no increment is lost, and the counter is stored under the transformed key.
+        assert_eq!(store.get(&key).unwrap(), 8000i64.to_be_bytes().to_vec());
+        assert_eq!(
+            inner.get(&Key::new("/prefix/counter")).unwrap(),
+            8000i64.to_be_bytes().to_vec()
+        );
+    }
 }
@@ -0,0 +1,332 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use parking_lot::RwLock;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore, WriteLock};
+use crate::key::Key;
+use crate::query::{Entry, Query};
+use crate::store::{increment_counter, sort_keys, ToBatch, ToTxn, Ttl};
+use crate::store::{DataStore, DataStoreRead, DataStoreWrite};
+
+/// The default interval of sweeping the expired keys.
+pub const DEFAULT_TTL_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+struct TtlValue {
+    value: Vec<u8>,
+    expiration: Option<Instant>,
+}
+
+impl TtlValue {
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expiration, Some(expiration) if expiration <= now)
+    }
+}
+
+type Entries = HashMap<Key, TtlValue>;
+
+/// TtlMapDataStore is an in-memory datastore whose entries may expire.
+///
+/// An expired key reads as absent right away, and it's removed from the map by a background
+/// thread sweeping the expired keys periodically. The thread exits once all the clones of
+/// the datastore are dropped. The clones share the entries.
+#[derive(Clone, Debug)]
+pub struct TtlMapDataStore {
+    entries: Arc<RwLock<Entries>>,
+    write_lock: WriteLock,
+}
+
+impl Default for TtlMapDataStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL_SWEEP_INTERVAL)
+    }
+}
+
+impl TtlMapDataStore {
+    /// Create a new TtlMapDataStore, sweeping the expired keys every `sweep_interval`.
+    pub fn new(sweep_interval: Duration) -> Self {
+        let entries = Arc::new(RwLock::new(Entries::new()));
+        let weak = Arc::downgrade(&entries);
+        thread::spawn(move || sweep_task(weak, sweep_interval));
+        Self {
+            entries,
+            write_lock: WriteLock::new(),
+        }
+    }
+
+    /// Remove the expired keys and return the number of the removed keys.
+    pub fn sweep(&self) -> usize {
+        sweep(&self.entries)
+    }
+
+    fn live<K, T, F>(&self, key: &K, f: F) -> Result<T>
+    where
+        K: Borrow<Key>,
+        F: FnOnce(&TtlValue) -> T,
+    {
+        let key = key.borrow();
+        match self.entries.read().get(key) {
+            Some(value) if !value.is_expired(Instant::now()) => Ok(f(value)),
+            _ => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+}
+
+fn sweep(entries: &RwLock<Entries>) -> usize {
+    let now = Instant::now();
+    let mut entries = entries.write();
+    let before = entries.len();
+    entries.retain(|_, value| !value.is_expired(now));
+    before - entries.len()
+}
+
+fn sweep_task(entries: Weak<RwLock<Entries>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match entries.upgrade() {
+            Some(entries) => {
+                let swept = sweep(&entries);
+                if swept > 0 {
+                    debug!("Swept {} expired keys", swept);
+                }
+            }
+            // all the clones of the datastore are dropped.
+            None => break,
+        }
+    }
+}
+
+impl DataStore for TtlMapDataStore {
+    fn sync<K>(&mut self, _prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl DataStoreRead for TtlMapDataStore {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        self.live(key, |value| value.value.clone())
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        match self.live(key, |_| ()) {
+            Ok(()) => Ok(true),
+            Err(err) if err.is_not_found() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        self.live(key, |value| value.value.len())
+    }
+
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        let now = Instant::now();
+        let entries = self.entries.read();
+        Ok(keys
+            .iter()
+            .map(|key| match entries.get(key) {
+                Some(value) if !value.is_expired(now) => Some(value.value.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let now = Instant::now();
+        let mut keys = self
+            .entries
+            .read()
+            .iter()
+            .filter(|(key, value)| {
+                !value.is_expired(now) && (*key == prefix || prefix.is_ancestor_of(*key))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+
+    fn query(&self, query: Query) -> Result<Vec<Entry>> {
+        // the entries carry their expirations.
+        let now = Instant::now();
+        let entries = self
+            .entries
+            .read()
+            .iter()
+            .filter(|(_, value)| !value.is_expired(now))
+            .map(|(key, value)| {
+                let mut entry = Entry::new(key.clone(), value.value.clone());
+                entry.expiration = value.expiration;
+                entry
+            })
+            .collect::<Vec<_>>();
+        Ok(query.apply(entries))
+    }
+}
+
+impl DataStoreWrite for TtlMapDataStore {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let value = TtlValue {
+            value: value.into(),
+            expiration: None,
+        };
+        let key = key.into();
+        self.write_lock.write(&key, || {
+            self.entries.write().insert(key.clone(), value);
+        });
+        Ok(())
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        self.write_lock.write(key, || {
+            self.entries.write().remove(key);
+        });
+        Ok(())
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        // the counter is put without expiration, like any plain put.
+        let key = key.borrow();
+        let lock = self.write_lock.clone();
+        lock.write(key, || increment_counter(self, key, delta))
+    }
+}
+
+impl Ttl for TtlMapDataStore {
+    fn put_with_ttl<K, V>(&mut self, key: K, value: V, ttl: Duration) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let value = TtlValue {
+            value: value.into(),
+            expiration: Some(Instant::now() + ttl),
+        };
+        let key = key.into();
+        self.write_lock.write(&key, || {
+            self.entries.write().insert(key.clone(), value);
+        });
+        Ok(())
+    }
+
+    fn set_ttl<K>(&mut self, key: &K, ttl: Duration) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        let now = Instant::now();
+        self.write_lock
+            .write(key, || match self.entries.write().get_mut(key) {
+                Some(value) if !value.is_expired(now) => {
+                    value.expiration = Some(now + ttl);
+                    Ok(())
+                }
+                _ => Err(DataStoreError::NotFound(key.to_string())),
+            })
+    }
+
+    fn get_expiration<K>(&self, key: &K) -> Result<Duration>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.live(key, |value| value.expiration)? {
+            Some(expiration) => Ok(expiration.saturating_duration_since(Instant::now())),
+            None => Err(DataStoreError::Custom(format!(
+                "key '{}' has no time-to-live",
+                key
+            ))),
+        }
+    }
+}
+
+impl ToBatch for TtlMapDataStore {
+    type Batch = BasicBatchDataStore<TtlMapDataStore>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl ToTxn for TtlMapDataStore {
+    type Txn = BasicTxnDataStore<TtlMapDataStore>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::with_write_lock(
+            self.clone(),
+            self.write_lock.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_expiry() {
+        let mut store = TtlMapDataStore::new(Duration::from_millis(10));
+        let (a, b, c) = (Key::new("/a"), Key::new("/b"), Key::new("/c"));
+        store
+            .put_with_ttl(a.clone(), vec![1], Duration::from_millis(50))
+            .unwrap();
+        store.put(b.clone(), vec![2]).unwrap();
+        store.put(c.clone(), vec![3]).unwrap();
+        store.set_ttl(&c, Duration::from_secs(60)).unwrap();
+
+        assert_eq!(store.get(&a).unwrap(), vec![1]);
+        assert!(store.get_expiration(&a).unwrap() <= Duration::from_millis(50));
+        assert!(store.get_expiration(&c).unwrap() > Duration::from_secs(50));
+        // the key without time-to-live.
+        assert!(store.get_expiration(&b).is_err());
+        assert!(store
+            .set_ttl(&Key::new("/d"), Duration::from_secs(1))
+            .unwrap_err()
+            .is_not_found());
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(store.get(&a).unwrap_err().is_not_found());
+        assert!(!store.has(&a).unwrap());
+        assert!(store.get_expiration(&a).unwrap_err().is_not_found());
+        assert_eq!(store.keys(&Key::new("/")).unwrap(), vec![b.clone(), c]);
+        // the expired key is swept out of the map by the background thread.
+        assert!(!store.entries.read().contains_key(&a));
+        assert_eq!(store.get(&b).unwrap(), vec![2]);
+    }
+}
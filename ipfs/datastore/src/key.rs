@@ -120,6 +120,21 @@ impl Key {
         Self(key)
     }
 
+    /// Create a new key from arbitrary input, collapsing the duplicate slashes, stripping the
+    /// trailing slash and ensuring the leading slash, like go-datastore's `NewKey`.
+    /// It's the same as `new` method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipfs_datastore::Key;
+    /// assert_eq!(Key::new_clean("//foo///bar/").as_str(), "/foo/bar");
+    /// assert_eq!(Key::new_clean("").as_str(), "/");
+    /// ```
+    pub fn new_clean(s: &str) -> Self {
+        Self::new(s)
+    }
+
     /// Create a new key without safety checking the input.
     ///
     /// # Safety
@@ -277,6 +292,20 @@ impl Key {
         namespace_type(self.base_namespace())
     }
 
+    /// Return the "type" of this key.
+    /// It's the same as `r#type` method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ipfs_datastore::Key;
+    /// let key = Key::new("/Comedy/MontyPython/Actor:JohnCleese");
+    /// assert_eq!(key.type_(), "Actor");
+    /// ```
+    pub fn type_(&self) -> &str {
+        self.r#type()
+    }
+
     /// Return the "name" of this key (field of last namespace).
     ///
     /// # Example
@@ -482,6 +511,69 @@ mod tests {
         assert_eq!(k1.path(), k2.parent().path());
     }
 
+    #[test]
+    fn test_namespaces() {
+        let root = Key::new("/");
+        assert_eq!(root.parent(), root);
+        assert_eq!(root.name(), "");
+        assert_eq!(root.type_(), "");
+        assert_eq!(root.list(), Vec::<&str>::new());
+        assert_eq!(root.child("A"), Key::new("/A"));
+        assert!(root.is_ancestor_of("/A"));
+        assert!(!root.is_ancestor_of(&root));
+
+        let single = Key::new("/Actor:JohnCleese");
+        assert_eq!(single.parent(), root);
+        assert_eq!(single.name(), "JohnCleese");
+        assert_eq!(single.type_(), "Actor");
+        assert_eq!(single.list(), vec!["Actor:JohnCleese"]);
+        assert_eq!(single.child("B"), Key::new("/Actor:JohnCleese/B"));
+        assert!(!single.is_ancestor_of(&root));
+
+        let multi = Key::new("/Comedy/MontyPython/Sketch:CheeseShop");
+        assert_eq!(multi.parent(), Key::new("/Comedy/MontyPython"));
+        assert_eq!(multi.parent().parent().parent(), root);
+        assert_eq!(multi.name(), "CheeseShop");
+        assert_eq!(multi.type_(), "Sketch");
+        assert_eq!(
+            multi.list(),
+            vec!["Comedy", "MontyPython", "Sketch:CheeseShop"]
+        );
+        assert_eq!(multi.parent().child("Sketch:CheeseShop"), multi);
+        assert!(Key::new("/Comedy").is_ancestor_of(&multi));
+        assert!(!Key::new("/Com").is_ancestor_of(&multi));
+
+        // a namespace without type is its own name.
+        assert_eq!(Key::new("/Comedy").name(), "Comedy");
+        assert_eq!(Key::new("/Comedy").type_(), "");
+    }
+
+    #[test]
+    fn test_clean() {
+        let cases = vec![
+            ("", "/"),
+            ("/", "/"),
+            ("//", "/"),
+            ("foo", "/foo"),
+            ("/foo/", "/foo"),
+            ("//foo///bar/", "/foo/bar"),
+            ("foo/bar", "/foo/bar"),
+            ("/foo/./bar", "/foo/bar"),
+            ("/foo/../bar", "/bar"),
+        ];
+        for (input, expected) in cases {
+            let key = Key::new_clean(input);
+            assert_eq!(key.as_str(), expected, "input {:?}", input);
+            // cleaning is idempotent.
+            assert_eq!(Key::new_clean(key.as_str()), key);
+        }
+
+        let key = Key::random();
+        assert_eq!(key.list().len(), 1);
+        assert_eq!(Key::new_clean(key.as_str()), key);
+        assert_ne!(Key::random(), key);
+    }
+
     #[test]
     fn test_less() {
         fn assert_less<A: Into<Key>, B: Into<Key>>(a: A, b: B) {
@@ -5,36 +5,57 @@
 #![deny(missing_docs)]
 
 mod error;
+mod fixture;
 mod impls;
 mod key;
-// TODO: mount
-// mod mount;
+mod mount;
+pub mod namespace;
 mod query;
 mod store;
 
 pub use self::error::DataStoreError;
+pub use self::fixture::{dump_fixture, load_fixture, load_fixture_file};
 pub use self::key::{namespace_type, namespace_value, Key};
+pub use self::mount::{Mount, MountDataStore};
 pub use self::query::*;
 
+pub use self::store::{add_counter, increment_counter};
 pub use self::store::{BatchDataStore, ToBatch, ToTxn, TxnDataStore};
 pub use self::store::{DataStore, DataStoreBatch, DataStoreRead, DataStoreTxn, DataStoreWrite};
 
 pub use self::store::{Check, CheckedBatchDataStore, CheckedDataStore, CheckedTxnDataStore};
 pub use self::store::{Gc, GcBatchDataStore, GcDataStore, GcTxnDataStore};
+pub use self::store::{MerkleRoot, PrefixScan};
 pub use self::store::{
     Persistent, PersistentBatchDataStore, PersistentDataStore, PersistentTxnDataStore,
 };
+pub use self::store::{RangeDataStore, ToRange};
 pub use self::store::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
+pub use self::store::{StreamDataStore, ToStream};
 pub use self::store::{Ttl, TtlBatchDataStore, TtlDataStore, TtlTxnDataStore};
 
-pub use self::impls::{BasicBatchDataStore, BasicTxnDataStore};
-pub use self::impls::{Delay, DelayDataStore};
-pub use self::impls::{DummyDataStore, MapDataStore};
-
-pub use self::impls::{FailBatchDataStore, FailDataStore, FailFn, FailTxnDataStore};
+pub use self::impls::ChecksumDataStore;
+pub use self::impls::QuorumDataStore;
+pub use self::impls::ReadOnlyDataStore;
+pub use self::impls::{AutoBatchDataStore, BatchTuner, DEFAULT_AUTOBATCH_THRESHOLD};
+pub use self::impls::{BasicBatchDataStore, BasicTxnDataStore, BatchConfig, WriteLock};
+pub use self::impls::{BloomDataStore, DEFAULT_BLOOM_CAPACITY, DEFAULT_BLOOM_FALSE_POSITIVE_RATE};
+pub use self::impls::{BranchDataStore, ToBranch};
+pub use self::impls::{BudgetedCacheDataStore, WriteMode};
+pub use self::impls::{CacheDataStore, EvictionPolicy, FifoPolicy, LfuPolicy, LruPolicy};
+pub use self::impls::{Change, ChangeOp, SequencedDataStore};
+pub use self::impls::{Delay, DelayConfig, DelayDataStore};
+pub use self::impls::{DummyDataStore, MapDataStore, MapGcDataStore, RcuMapDataStore};
+pub use self::impls::{MeasureDataStore, MeasureStream, OpStats, Stats};
+pub use self::impls::{RetryDataStore, DEFAULT_RETRY_MAX_DELAY};
+pub use self::impls::{ShadowDataStore, DEFAULT_SHADOW_SAMPLE_CAPACITY};
+pub use self::impls::{TtlMapDataStore, DEFAULT_TTL_SWEEP_INTERVAL};
+
+pub use self::impls::SwappableDataStore;
 pub use self::impls::{
-    KeyMapFn, KeyTransform, KeyTransformPair, PrefixTransform, TransformBatchDataStore,
-    TransformDataStore, TransformTxnDataStore,
+    ComposedTransform, KeyMapFn, KeyTransform, KeyTransformPair, PrefixStream, PrefixTransform,
+    SuffixTransform, TransformBatchDataStore, TransformDataStore, TransformTxnDataStore,
 };
-pub use self::impls::{LogBatchDataStore, LogDataStore, LogTxnDataStore};
+pub use self::impls::{FailBatchDataStore, FailBuilder, FailDataStore, FailFn, FailTxnDataStore};
+pub use self::impls::{LogBatchDataStore, LogDataStore, LogEntry, LogSink, LogTxnDataStore};
 pub use self::impls::{SyncBatchDataStore, SyncDataStore, SyncTxnDataStore};
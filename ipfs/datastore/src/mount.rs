@@ -1 +1,343 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+
+use crate::error::{DataStoreError, Result};
+use crate::impls::{BasicBatchDataStore, BasicTxnDataStore};
+use crate::key::Key;
+use crate::query::{Entry, Query};
+use crate::store::{sort_keys, DataStore, DataStoreRead, DataStoreWrite, ToBatch, ToTxn};
+
+/// Mount is a datastore mounted at a key prefix of the `MountDataStore`.
+#[derive(Clone, Debug)]
+pub struct Mount<DS> {
+    /// The prefix the datastore is mounted at.
+    pub prefix: Key,
+    /// The mounted datastore.
+    pub datastore: DS,
+}
+
+impl<DS> Mount<DS> {
+    /// Create a new Mount of the `datastore` at the `prefix`.
+    pub fn new<K: Into<Key>>(prefix: K, datastore: DS) -> Self {
+        Self {
+            prefix: prefix.into(),
+            datastore,
+        }
+    }
+
+    // Return the key under the mount, the prefix of `key` is stripped.
+    fn strip(&self, key: &Key) -> Option<Key> {
+        if self.prefix.is_root() {
+            Some(key.clone())
+        } else if *key == self.prefix {
+            Some(Key::new("/"))
+        } else if self.prefix.is_ancestor_of(key) {
+            Some(Key::new(&key.as_str()[self.prefix.as_str().len()..]))
+        } else {
+            None
+        }
+    }
+}
+
+/// MountDataStore composes the datastores by key prefix, every operation is routed to
+/// the datastore mounted at the longest prefix of the key.
+///
+/// The mount prefix is stripped from the keys handed to the mounted datastore, and added
+/// back to the keys listed or queried from it. The operations on a key not covered by
+/// any mount fail, `get` and `size` with `DataStoreError::NotFound`.
+#[derive(Clone, Debug)]
+pub struct MountDataStore<DS: DataStore> {
+    // sorted by the prefix length descending, so that the longest prefix matches first.
+    mounts: Vec<Mount<DS>>,
+}
+
+impl<DS: DataStore> MountDataStore<DS> {
+    /// Create a new MountDataStore with the `mounts`.
+    pub fn new(mut mounts: Vec<Mount<DS>>) -> Self {
+        mounts.sort_by_key(|mount| Reverse(mount.prefix.as_str().len()));
+        Self { mounts }
+    }
+
+    /// Return the mounts, the longest prefix first.
+    pub fn mounts(&self) -> &[Mount<DS>] {
+        &self.mounts
+    }
+
+    // Return the index of the mount covering `key` and the key under the mount.
+    fn lookup(&self, key: &Key) -> Option<(usize, Key)> {
+        self.mounts
+            .iter()
+            .enumerate()
+            .find_map(|(index, mount)| mount.strip(key).map(|key| (index, key)))
+    }
+
+    fn lookup_mut(&mut self, key: &Key) -> Result<(&mut DS, Key)> {
+        match self.lookup(key) {
+            Some((index, key)) => Ok((&mut self.mounts[index].datastore, key)),
+            None => Err(no_mount(key)),
+        }
+    }
+
+    // Return the index of every mount which may hold keys under `prefix`,
+    // along with the prefix under the mount.
+    fn lookup_prefix(&self, prefix: &Key) -> Vec<(usize, Key)> {
+        self.mounts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, mount)| match mount.strip(prefix) {
+                Some(inner) => Some((index, inner)),
+                None if prefix.is_ancestor_of(&mount.prefix) => Some((index, Key::new("/"))),
+                None => None,
+            })
+            .collect()
+    }
+
+    // Return whether `key` of the mount `index` isn't shadowed by a longer mount.
+    fn is_routed_to(&self, key: &Key, index: usize) -> bool {
+        matches!(self.lookup(key), Some((routed, _)) if routed == index)
+    }
+}
+
+fn no_mount(key: &Key) -> DataStoreError {
+    DataStoreError::Custom(format!("no datastore mounted for key '{}'", key))
+}
+
+impl<DS: DataStore> DataStore for MountDataStore<DS> {
+    fn sync<K>(&mut self, prefix: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        for (index, inner) in self.lookup_prefix(prefix.borrow()) {
+            self.mounts[index].datastore.sync(&inner)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        // close every mount even if some of them fail, and report the first failure.
+        let mut result = Ok(());
+        for mount in &mut self.mounts {
+            let closed = mount.datastore.close();
+            if result.is_ok() {
+                result = closed;
+            }
+        }
+        result
+    }
+}
+
+impl<DS: DataStore> DataStoreRead for MountDataStore<DS> {
+    fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.lookup(key) {
+            Some((index, inner)) => self.mounts[index].datastore.get(&inner),
+            None => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn has<K>(&self, key: &K) -> Result<bool>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.lookup(key) {
+            Some((index, inner)) => self.mounts[index].datastore.has(&inner),
+            None => Err(no_mount(key)),
+        }
+    }
+
+    fn size<K>(&self, key: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let key = key.borrow();
+        match self.lookup(key) {
+            Some((index, inner)) => self.mounts[index].datastore.size(&inner),
+            None => Err(DataStoreError::NotFound(key.to_string())),
+        }
+    }
+
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        let mut keys = Vec::new();
+        for (index, inner) in self.lookup_prefix(prefix) {
+            let mount = &self.mounts[index];
+            for key in mount.datastore.keys(&inner)? {
+                let key = mount.prefix.child(key);
+                if self.is_routed_to(&key, index) && (key == *prefix || prefix.is_ancestor_of(&key))
+                {
+                    keys.push(key);
+                }
+            }
+        }
+        sort_keys(&mut keys);
+        Ok(keys)
+    }
+
+    fn query(&self, query: Query) -> Result<Vec<Entry>> {
+        // only the prefix is pushed down to the mounts, since the filters and orders
+        // apply to the full keys.
+        let prefix = Key::new(&query.prefix);
+        let mut entries = Vec::new();
+        for (index, inner) in self.lookup_prefix(&prefix) {
+            let mount = &self.mounts[index];
+            let inner_query = Query {
+                prefix: inner.to_string(),
+                filters: vec![],
+                orders: vec![],
+                limit: 0,
+                offset: 0,
+                keys_only: false,
+                return_expirations: query.return_expirations,
+                return_sizes: query.return_sizes,
+            };
+            for mut entry in mount.datastore.query(inner_query)? {
+                entry.key = mount.prefix.child(&entry.key);
+                if self.is_routed_to(&entry.key, index) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(query.apply(entries))
+    }
+}
+
+impl<DS: DataStore> DataStoreWrite for MountDataStore<DS> {
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: Into<Key>,
+        V: Into<Vec<u8>>,
+    {
+        let (datastore, inner) = self.lookup_mut(&key.into())?;
+        datastore.put(inner, value)
+    }
+
+    fn delete<K>(&mut self, key: &K) -> Result<()>
+    where
+        K: Borrow<Key>,
+    {
+        let (datastore, inner) = self.lookup_mut(key.borrow())?;
+        datastore.delete(&inner)
+    }
+
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        let (datastore, inner) = self.lookup_mut(key.borrow())?;
+        datastore.increment(&inner, delta)
+    }
+
+    fn fence(&mut self) -> Result<()> {
+        for mount in &mut self.mounts {
+            mount.datastore.fence()?;
+        }
+        Ok(())
+    }
+}
+
+impl<DS: DataStore> ToBatch for MountDataStore<DS> {
+    type Batch = BasicBatchDataStore<MountDataStore<DS>>;
+
+    fn batch(&self) -> Result<Self::Batch> {
+        Ok(BasicBatchDataStore::new(self.clone()))
+    }
+}
+
+impl<DS: DataStore> ToTxn for MountDataStore<DS> {
+    type Txn = BasicTxnDataStore<MountDataStore<DS>>;
+
+    fn txn(&self, _read_only: bool) -> Result<Self::Txn> {
+        Ok(BasicTxnDataStore::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{MapDataStore, SyncDataStore};
+    use crate::query::OrderByKey;
+
+    #[test]
+    fn test_mount() {
+        let foo = SyncDataStore::new(MapDataStore::new());
+        let bar = SyncDataStore::new(MapDataStore::new());
+        let mut store = MountDataStore::new(vec![
+            Mount::new("/foo", foo.clone()),
+            Mount::new("/bar", bar.clone()),
+        ]);
+
+        store.put(Key::new("/foo/a"), vec![1]).unwrap();
+        store.put(Key::new("/bar/a"), vec![2]).unwrap();
+        store.put(Key::new("/bar/b/c"), vec![3]).unwrap();
+
+        // the mount prefix is stripped in the mounted datastores.
+        assert_eq!(foo.get(&Key::new("/a")).unwrap(), vec![1]);
+        assert_eq!(bar.get(&Key::new("/a")).unwrap(), vec![2]);
+        assert!(!foo.has(&Key::new("/b/c")).unwrap());
+        assert_eq!(store.get(&Key::new("/foo/a")).unwrap(), vec![1]);
+        assert_eq!(store.get(&Key::new("/bar/a")).unwrap(), vec![2]);
+        assert_eq!(store.size(&Key::new("/bar/b/c")).unwrap(), 1);
+
+        // the mounts are isolated.
+        store.delete(&Key::new("/foo/a")).unwrap();
+        assert!(!store.has(&Key::new("/foo/a")).unwrap());
+        assert!(store.has(&Key::new("/bar/a")).unwrap());
+
+        // the keys not covered by any mount.
+        assert!(store.get(&Key::new("/baz/a")).unwrap_err().is_not_found());
+        assert!(store.has(&Key::new("/foobar")).is_err());
+        assert!(store.put(Key::new("/baz/a"), vec![]).is_err());
+        assert!(store.delete(&Key::new("/")).is_err());
+
+        // the listed and queried keys are prefixed with the mount.
+        store.put(Key::new("/foo/x"), vec![4]).unwrap();
+        assert_eq!(
+            store.keys(&Key::new("/")).unwrap(),
+            vec![Key::new("/bar/a"), Key::new("/bar/b/c"), Key::new("/foo/x")]
+        );
+        assert_eq!(store.count(&Key::new("/bar")).unwrap(), 2);
+        let query = Query {
+            prefix: "/bar".into(),
+            filters: vec![],
+            orders: vec![Box::new(OrderByKey)],
+            limit: 1,
+            offset: 1,
+            keys_only: false,
+            return_expirations: false,
+            return_sizes: false,
+        };
+        let entries = store.query(query).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, Key::new("/bar/b/c"));
+        assert_eq!(entries[0].value, vec![3]);
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let root = SyncDataStore::new(MapDataStore::new());
+        let nested = SyncDataStore::new(MapDataStore::new());
+        let mut store = MountDataStore::new(vec![
+            Mount::new("/", root.clone()),
+            Mount::new("/foo/bar", nested.clone()),
+        ]);
+
+        store.put(Key::new("/foo/bar/a"), vec![1]).unwrap();
+        store.put(Key::new("/foo/a"), vec![2]).unwrap();
+        assert_eq!(nested.get(&Key::new("/a")).unwrap(), vec![1]);
+        assert_eq!(root.get(&Key::new("/foo/a")).unwrap(), vec![2]);
+        assert!(!root.has(&Key::new("/foo/bar/a")).unwrap());
+
+        // the keys of the root shadowed by the nested mount aren't listed.
+        root.clone().put(Key::new("/foo/bar/b"), vec![3]).unwrap();
+        assert_eq!(
+            store.keys(&Key::new("/foo")).unwrap(),
+            vec![Key::new("/foo/a"), Key::new("/foo/bar/a")]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! Scope a datastore under a fixed namespace, like go-datastore's `namespace` package.
+
+use crate::impls::{PrefixTransform, TransformDataStore};
+use crate::key::Key;
+use crate::store::DataStore;
+
+/// Return a datastore which stores all the keys of the `datastore` under the `prefix`.
+///
+/// The keys returned by the listings and the queries have the prefix stripped.
+///
+/// # Example
+///
+/// ```
+/// use ipfs_datastore::{namespace, DataStoreRead, DataStoreWrite, Key, MapDataStore};
+/// let mut store = namespace::wrap(MapDataStore::new(), "/myapp");
+/// store.put(Key::new("/config"), b"value".to_vec()).unwrap();
+/// assert_eq!(store.get(&Key::new("/config")).unwrap(), b"value".to_vec());
+/// assert_eq!(store.keys(&Key::new("/")).unwrap(), vec![Key::new("/config")]);
+/// ```
+pub fn wrap<DS: DataStore>(datastore: DS, prefix: &str) -> TransformDataStore<PrefixTransform, DS> {
+    let transform = PrefixTransform {
+        prefix: Key::new(prefix),
+    };
+    TransformDataStore::new(transform, datastore)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on_stream;
+
+    use super::*;
+    use crate::impls::MapDataStore;
+    use crate::store::{DataStoreRead, DataStoreWrite, ToStream};
+
+    #[test]
+    fn test_wrap() {
+        let mut inner = MapDataStore::new();
+        inner.put(Key::new("/other/a"), vec![0]).unwrap();
+        let mut store = wrap(inner, "/myapp");
+        store.put(Key::new("/a"), vec![1]).unwrap();
+        store.put(Key::new("/b/c"), vec![2]).unwrap();
+        assert!(!store.has(&Key::new("/other/a")).unwrap());
+
+        let mut keys = block_on_stream(store.async_stream())
+            .map(|entry| entry.unwrap().key)
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec![Key::new("/a"), Key::new("/b/c")]);
+    }
+}
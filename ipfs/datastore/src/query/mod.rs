@@ -7,7 +7,9 @@ pub use self::filter::{Filter, FilterKeyCompare, FilterKeyPrefix, FilterOp, Filt
 pub use self::order::{
     Order, OrderByFunction, OrderByKey, OrderByKeyDescending, OrderByValue, OrderByValueDescending,
 };
+pub use self::order::{OrderByValueKeyFn, ValueKeyFn};
 
+use std::cmp::Ordering;
 use std::error;
 use std::fmt;
 use std::future::Future;
@@ -109,16 +111,67 @@ impl fmt::Display for Query {
     }
 }
 
+impl Query {
+    /// Apply the query to the `entries` naively in memory, for the datastores which can't
+    /// perform the query natively.
+    ///
+    /// The entries are selected by the prefix and filters, then all of them are buffered
+    /// and sorted by the orders, and finally the offset and limit (if not 0) are applied.
+    pub fn apply<I>(&self, entries: I) -> Vec<Entry>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        let prefix = Key::new(&self.prefix);
+        let mut entries = entries
+            .into_iter()
+            .filter(|entry| self.prefix.is_empty() || entry.key.is_descendant_of(prefix.clone()))
+            .filter(|entry| self.filters.iter().all(|filter| filter.filter(entry)))
+            .collect::<Vec<_>>();
+        if !self.orders.is_empty() {
+            entries.sort_by(|lhs, rhs| {
+                self.orders
+                    .iter()
+                    .map(|order| order.compare(lhs, rhs))
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        let entries = entries.into_iter().skip(self.offset);
+        let mut entries = if self.limit > 0 {
+            entries.take(self.limit).collect::<Vec<_>>()
+        } else {
+            entries.collect::<Vec<_>>()
+        };
+        if self.keys_only {
+            entries.iter_mut().for_each(|entry| entry.value.clear());
+        }
+        entries
+    }
+}
+
 /// The query result entry.
 #[doc(hidden)]
 #[derive(Clone, Debug)]
 pub struct Entry {
     pub key: Key,
     pub value: Vec<u8>,
-    pub expiration: Instant,
+    pub expiration: Option<Instant>,
     pub size: usize,
 }
 
+impl Entry {
+    /// Create a new entry without expiration.
+    pub fn new(key: Key, value: Vec<u8>) -> Self {
+        let size = value.len();
+        Self {
+            key,
+            value,
+            expiration: None,
+            size,
+        }
+    }
+}
+
 /// The query result.
 pub type QueryResult = Result<Entry, Box<dyn error::Error>>;
 
@@ -139,3 +192,60 @@ pub trait QueryResults {
     ///
     fn close(&self) -> Result<(), Box<dyn error::Error>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(orders: Vec<Box<dyn Order>>, limit: usize) -> Query {
+        Query {
+            prefix: "/msgs".into(),
+            filters: vec![],
+            orders,
+            limit,
+            offset: 0,
+            keys_only: false,
+            return_expirations: false,
+            return_sizes: false,
+        }
+    }
+
+    #[test]
+    fn test_order_by_value_key_fn() {
+        // the values embed the gas premium as the big-endian u64 after a 1-byte tag.
+        let entries = [(1, 300u64), (2, 100), (3, 500), (4, 200)]
+            .iter()
+            .map(|(i, premium)| {
+                let mut value = vec![0xff - *i as u8];
+                value.extend_from_slice(&premium.to_be_bytes());
+                Entry::new(Key::new(format!("/msgs/{}", i)), value)
+            })
+            .chain(std::iter::once(Entry::new(
+                Key::new("/other/1"),
+                vec![0; 9],
+            )))
+            .collect::<Vec<_>>();
+        let premium = |value: &[u8]| value[1..9].to_vec();
+        let names = |entries: Vec<Entry>| {
+            entries
+                .iter()
+                .map(|entry| entry.key.name().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let ascending = query(vec![Box::new(OrderByValueKeyFn::new(premium))], 0);
+        assert_eq!(
+            names(ascending.apply(entries.clone())),
+            ["2", "4", "1", "3"]
+        );
+        let descending = query(vec![Box::new(OrderByValueKeyFn::descending(premium))], 0);
+        assert_eq!(
+            names(descending.apply(entries.clone())),
+            ["3", "1", "4", "2"]
+        );
+
+        // sorted before truncated.
+        let top = query(vec![Box::new(OrderByValueKeyFn::descending(premium))], 2);
+        assert_eq!(names(top.apply(entries)), ["3", "1"]);
+    }
+}
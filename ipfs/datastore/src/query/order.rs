@@ -2,6 +2,7 @@
 
 use std::cmp::Ordering;
 use std::fmt;
+use std::sync::Arc;
 
 use crate::query::Entry;
 
@@ -145,3 +146,65 @@ impl Order for OrderByFunction {
         (self.0)(lhs, rhs)
     }
 }
+
+/// The function deriving the sort key from the value.
+pub type ValueKeyFn = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// A object used to order data by the sort key derived from the value, e.g. a field
+/// inside the value, without maintaining a separate index.
+#[derive(Clone)]
+pub struct OrderByValueKeyFn {
+    func: ValueKeyFn,
+    descending: bool,
+}
+
+impl OrderByValueKeyFn {
+    /// Create a new OrderByValueKeyFn instance ordering by the derived key ascending.
+    pub fn new<F>(func: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        Self {
+            func: Arc::new(func),
+            descending: false,
+        }
+    }
+
+    /// Create a new OrderByValueKeyFn instance ordering by the derived key descending.
+    pub fn descending<F>(func: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        Self {
+            func: Arc::new(func),
+            descending: true,
+        }
+    }
+}
+
+impl fmt::Debug for OrderByValueKeyFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OrderByValueKeyFn: {:p}", Arc::as_ptr(&self.func))
+    }
+}
+
+impl fmt::Display for OrderByValueKeyFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.descending {
+            f.write_str("desc(VALUE_KEY_FN)")
+        } else {
+            f.write_str("VALUE_KEY_FN")
+        }
+    }
+}
+
+impl Order for OrderByValueKeyFn {
+    fn compare(&self, lhs: &Entry, rhs: &Entry) -> Ordering {
+        let ordering = (self.func)(&lhs.value).cmp(&(self.func)(&rhs.value));
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
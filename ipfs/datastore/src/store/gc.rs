@@ -5,8 +5,9 @@ use crate::store::{BatchDataStore, DataStore, TxnDataStore};
 
 /// An interface that free disk space.
 pub trait Gc {
-    /// Free disk space.
-    fn collect_garbage(&self) -> Result<()>;
+    /// Free disk space, return the number of the reclaimed entries, or 0 if the
+    /// datastore can't tell.
+    fn collect_garbage(&self) -> Result<usize>;
 }
 
 /// GcDataStore is an interface that should be implemented by data stores
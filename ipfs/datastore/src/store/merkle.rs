@@ -0,0 +1,120 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+
+use futures::executor::block_on_stream;
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+use crate::key::Key;
+use crate::store::StreamDataStore;
+
+/// MerkleRoot computes a hash tree over the entries under a key prefix, so that two
+/// datastores can cheaply check whether their contents under the prefix are the same.
+pub trait MerkleRoot {
+    /// Return the SHA-256 Merkle root of the entries whose keys are descendants of the
+    /// `prefix`, which only depends on the entries rather than the order they were written.
+    ///
+    /// The leaves are `H(0x00 || len(key) || key || value)` sorted by key, the interior nodes
+    /// are `H(0x01 || left || right)` and the odd node of a level is promoted to the next
+    /// level. The root of no entries is all zeros.
+    fn merkle_root<K>(&self, prefix: &K) -> Result<[u8; 32]>
+    where
+        K: Borrow<Key>;
+}
+
+impl<T: StreamDataStore> MerkleRoot for T {
+    fn merkle_root<K>(&self, prefix: &K) -> Result<[u8; 32]>
+    where
+        K: Borrow<Key>,
+    {
+        let prefix = prefix.borrow();
+        let mut entries = Vec::new();
+        for entry in block_on_stream(self.async_stream()) {
+            let entry = entry?;
+            if prefix.is_ancestor_of(&entry.key) {
+                entries.push((entry.key, entry.value));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let leaves = entries
+            .iter()
+            .map(|(key, value)| leaf_hash(key, value))
+            .collect();
+        Ok(root_of(leaves))
+    }
+}
+
+fn leaf_hash(key: &Key, value: &[u8]) -> [u8; 32] {
+    let key = key.as_bytes();
+    let mut hasher = Sha256::new();
+    hasher.input([0u8]);
+    hasher.input((key.len() as u64).to_be_bytes());
+    hasher.input(key);
+    hasher.input(value);
+    digest(hasher)
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input([1u8]);
+    hasher.input(left);
+    hasher.input(right);
+    digest(hasher)
+}
+
+fn digest(hasher: Sha256) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.result());
+    hash
+}
+
+fn root_of(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [odd] => *odd,
+                _ => unreachable!("chunks of 2 are never empty; qed"),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::MapDataStore;
+    use crate::store::DataStoreWrite;
+
+    #[test]
+    fn test_merkle_root() {
+        let pairs = (0..7)
+            .map(|i| (Key::new(format!("/a/{}", i)), vec![i as u8; i]))
+            .collect::<Vec<_>>();
+        let mut forward = MapDataStore::new();
+        let mut backward = MapDataStore::new();
+        for (key, value) in &pairs {
+            forward.put(key.clone(), value.clone()).unwrap();
+        }
+        for (key, value) in pairs.iter().rev() {
+            backward.put(key.clone(), value.clone()).unwrap();
+        }
+        // the entries out of the prefix don't count.
+        backward.put(Key::new("/b/0"), vec![0]).unwrap();
+
+        let prefix = Key::new("/a");
+        let root = forward.merkle_root(&prefix).unwrap();
+        assert_eq!(backward.merkle_root(&prefix).unwrap(), root);
+        assert_ne!(root, [0u8; 32]);
+        assert_eq!(forward.merkle_root(&Key::new("/c")).unwrap(), [0u8; 32]);
+
+        backward.put(Key::new("/a/3"), vec![4; 3]).unwrap();
+        assert_ne!(backward.merkle_root(&prefix).unwrap(), root);
+    }
+}
@@ -1,9 +1,11 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
+use std::convert::TryInto;
 
-use crate::error::Result;
+use crate::error::{DataStoreError, Result};
 use crate::key::Key;
+use crate::query::{Entry, Query};
 
 /// DataStore represents storage for any key-value pair.
 ///
@@ -57,9 +59,80 @@ pub trait DataStoreRead {
     where
         K: Borrow<Key>;
 
-    // Query searches the datastore and returns a query result. This function
-    // may return before the query actually runs.
-    // TODO: query
+    /// Return whether each of the `keys` is mapped to a `value`, the flags are in the
+    /// same order as the `keys`.
+    ///
+    /// The default implementation calls `has` for every key, the datastores which can
+    /// check many keys at once should override it.
+    fn has_many(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        keys.iter().map(|key| self.has(key)).collect()
+    }
+
+    /// Return the values of the `keys` as of a single consistent point, `None` for the
+    /// absent keys, so that the read isn't torn by a concurrent write.
+    ///
+    /// The default implementation calls `get` for every key, which isn't atomic, the
+    /// datastores shared between threads should override it.
+    fn read_snapshot(&self, keys: &[Key]) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter()
+            .map(|key| match self.get(key) {
+                Ok(value) => Ok(Some(value)),
+                Err(err) if err.is_not_found() => Ok(None),
+                Err(err) => Err(err),
+            })
+            .collect()
+    }
+
+    /// Return the keys equal to or under the `prefix` in the byte order, the same as the range
+    /// scans of `ToRange`, the root prefix `/` lists every key.
+    ///
+    /// The default implementation returns an error, the datastores which can enumerate
+    /// their keys should override it.
+    fn keys(&self, prefix: &Key) -> Result<Vec<Key>> {
+        Err(DataStoreError::Custom(format!(
+            "listing the keys under '{}' is not supported",
+            prefix
+        )))
+    }
+
+    /// Return the number of the keys equal to or under the `prefix`, the root prefix `/`
+    /// counts every key.
+    ///
+    /// The default implementation lists the keys, the datastores which can count
+    /// without materializing them should override it.
+    fn count(&self, prefix: &Key) -> Result<usize> {
+        Ok(self.keys(prefix)?.len())
+    }
+
+    /// Return whether any key is equal to or under the `prefix`.
+    ///
+    /// The default implementation counts the keys, the datastores which can stop at
+    /// the first key should override it.
+    fn has_prefix(&self, prefix: &Key) -> Result<bool> {
+        Ok(self.count(prefix)? > 0)
+    }
+
+    /// Search the datastore with the `query` and return the matched entries,
+    /// see `Query` for the order the operations are applied in.
+    ///
+    /// The default implementation reads every key under the query prefix and applies
+    /// the query in memory, the datastores which can query natively should override it.
+    fn query(&self, query: Query) -> Result<Vec<Entry>> {
+        let prefix = if query.prefix.is_empty() {
+            Key::new("/")
+        } else {
+            Key::new(&query.prefix)
+        };
+        let entries = self
+            .keys(&prefix)?
+            .into_iter()
+            .map(|key| {
+                let value = self.get(&key)?;
+                Ok(Entry::new(key, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(query.apply(entries))
+    }
 }
 
 /// DataStoreWrite is the write-side of the DataStore trait.
@@ -83,6 +156,65 @@ pub trait DataStoreWrite {
     fn delete<K>(&mut self, key: &K) -> Result<()>
     where
         K: Borrow<Key>;
+
+    /// Add `delta` to the counter named by `key` and return the new value.
+    ///
+    /// The value is a big-endian i64 and an absent key counts as 0. When the result
+    /// overflows, `DataStoreError::Overflow` is returned and the counter is untouched.
+    ///
+    /// The default implementation is a plain read-modify-write, the datastores shared
+    /// between threads should override it to be atomic. The wrappers forward it to the
+    /// wrapped datastore, or do the read-modify-write under their own lock if they change
+    /// the stored value.
+    fn increment<K>(&mut self, key: &K, delta: i64) -> Result<i64>
+    where
+        K: Borrow<Key>,
+        Self: DataStoreRead + Sized,
+    {
+        increment_counter(self, key.borrow(), delta)
+    }
+
+    /// Flush the buffered writes down to the underlying datastore, in the order they
+    /// were written, so that the writes before the fence are never reordered after it.
+    ///
+    /// The wrappers forward it to the wrapped datastore, it's a no-op for the
+    /// datastores which don't buffer writes.
+    fn fence(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Add `delta` to the counter named by `key` with a plain read-modify-write, as the default
+/// `DataStoreWrite::increment` does, for the datastores running it under their own lock.
+pub fn increment_counter<DS>(datastore: &mut DS, key: &Key, delta: i64) -> Result<i64>
+where
+    DS: DataStoreRead + DataStoreWrite,
+{
+    let current = match datastore.get(key) {
+        Ok(value) => Some(value),
+        Err(err) if err.is_not_found() => None,
+        Err(err) => return Err(err),
+    };
+    let value = add_counter(key, current.as_deref(), delta)?;
+    datastore.put(key.clone(), value.to_be_bytes().to_vec())?;
+    Ok(value)
+}
+
+/// Add `delta` to the `current` counter value of `key`, as `DataStoreWrite::increment` does,
+/// for the datastores overriding it.
+pub fn add_counter(key: &Key, current: Option<&[u8]>, delta: i64) -> Result<i64> {
+    let current = match current {
+        Some(value) => {
+            let bytes: [u8; 8] = value.try_into().map_err(|_| {
+                DataStoreError::Custom(format!("the value of {} is not a counter", key))
+            })?;
+            i64::from_be_bytes(bytes)
+        }
+        None => 0,
+    };
+    current
+        .checked_add(delta)
+        .ok_or_else(|| DataStoreError::Overflow(format!("{} + {} of {}", current, delta, key)))
 }
 
 /// DataStoreBatch is a interface that needs to be implemented by `BatchDataStore`
@@ -136,14 +268,24 @@ pub trait ToTxn {
 
 mod check;
 mod gc;
+mod merkle;
 mod persistent;
+mod prefix;
+mod range;
 mod scrub;
+mod stream;
 mod ttl;
 
 pub use self::check::{Check, CheckedBatchDataStore, CheckedDataStore, CheckedTxnDataStore};
 pub use self::gc::{Gc, GcBatchDataStore, GcDataStore, GcTxnDataStore};
+pub use self::merkle::MerkleRoot;
 pub use self::persistent::{
     Persistent, PersistentBatchDataStore, PersistentDataStore, PersistentTxnDataStore,
 };
+pub(crate) use self::prefix::overlay_keys;
+pub use self::prefix::PrefixScan;
+pub(crate) use self::range::{byte_order, sort_keys};
+pub use self::range::{RangeDataStore, ToRange};
 pub use self::scrub::{Scrub, ScrubbedBatchDataStore, ScrubbedDataStore, ScrubbedTxnDataStore};
+pub use self::stream::{StreamDataStore, ToStream};
 pub use self::ttl::{Ttl, TtlBatchDataStore, TtlDataStore, TtlTxnDataStore};
@@ -0,0 +1,115 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+use crate::error::Result;
+use crate::key::Key;
+use crate::store::{sort_keys, DataStore, DataStoreWrite};
+
+/// PrefixScan encapsulates the methods that deal with all the entries under a key prefix.
+pub trait PrefixScan {
+    /// Return the sorted keys which are descendants of the `prefix`, listed by `keys`.
+    fn keys_with_prefix<K>(&self, prefix: &K) -> Result<Vec<Key>>
+    where
+        K: Borrow<Key>;
+
+    /// Remove all the entries whose keys are descendants of the `prefix`,
+    /// return the number of removed entries.
+    fn delete_prefix<K>(&mut self, prefix: &K) -> Result<usize>
+    where
+        K: Borrow<Key>;
+}
+
+impl<T: DataStore> PrefixScan for T {
+    fn keys_with_prefix<K>(&self, prefix: &K) -> Result<Vec<Key>>
+    where
+        K: Borrow<Key>,
+    {
+        let prefix = prefix.borrow();
+        let mut keys = self.keys(prefix)?;
+        keys.retain(|key| key != prefix);
+        Ok(keys)
+    }
+
+    fn delete_prefix<K>(&mut self, prefix: &K) -> Result<usize>
+    where
+        K: Borrow<Key>,
+    {
+        let keys = self.keys_with_prefix(prefix)?;
+        for key in &keys {
+            DataStoreWrite::delete(self, key)?;
+        }
+        Ok(keys.len())
+    }
+}
+
+/// Apply the pending writes of an overlay to the `keys` listed from the datastore beneath it,
+/// each pending write is a key with whether it puts (`true`) or deletes (`false`) the key.
+/// Only the pending keys equal to or under the `prefix` are applied, the result is in the
+/// byte order.
+pub(crate) fn overlay_keys<'a, I>(keys: Vec<Key>, pending: I, prefix: &Key) -> Vec<Key>
+where
+    I: IntoIterator<Item = (&'a Key, bool)>,
+{
+    let mut keys = keys.into_iter().collect::<HashSet<_>>();
+    for (key, put) in pending {
+        if key != prefix && !prefix.is_ancestor_of(key) {
+            continue;
+        }
+        if put {
+            keys.insert(key.clone());
+        } else {
+            keys.remove(key);
+        }
+    }
+    let mut keys = keys.into_iter().collect::<Vec<_>>();
+    sort_keys(&mut keys);
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::{AutoBatchDataStore, MapDataStore, RcuMapDataStore};
+    use crate::store::DataStoreRead;
+
+    #[test]
+    fn test_delete_prefix() {
+        let mut store = MapDataStore::new();
+        for key in &["/a/1", "/a/2", "/a/b/3", "/ab/4", "/c/5"] {
+            store.put(Key::new(key), vec![]).unwrap();
+        }
+
+        let mut keys = store.keys_with_prefix(&Key::new("/a")).unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![Key::new("/a/1"), Key::new("/a/2"), Key::new("/a/b/3")]
+        );
+
+        assert_eq!(store.delete_prefix(&Key::new("/a")).unwrap(), 3);
+        assert!(store.keys_with_prefix(&Key::new("/a")).unwrap().is_empty());
+        assert!(store.has(&Key::new("/ab/4")).unwrap());
+        assert!(store.has(&Key::new("/c/5")).unwrap());
+    }
+
+    #[test]
+    fn test_delete_prefix_with_pending_writes() {
+        let mut datastore = RcuMapDataStore::new();
+        datastore.put(Key::new("/a/1"), vec![]).unwrap();
+        datastore.put(Key::new("/a/2"), vec![]).unwrap();
+
+        let mut store = AutoBatchDataStore::new(datastore.clone(), 16);
+        store.put(Key::new("/a/3"), vec![]).unwrap();
+        store.delete(&Key::new("/a/1")).unwrap();
+        assert_eq!(
+            store.keys_with_prefix(&Key::new("/a")).unwrap(),
+            vec![Key::new("/a/2"), Key::new("/a/3")]
+        );
+
+        assert_eq!(store.delete_prefix(&Key::new("/a")).unwrap(), 2);
+        store.flush().unwrap();
+        assert_eq!(datastore.count(&Key::new("/")).unwrap(), 0);
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use crate::error::Result;
+use crate::key::Key;
+use crate::query::Entry;
+use crate::store::DataStore;
+
+/// ToRange is an interface that should be implemented by data stores that
+/// support scanning the entries of a key range in order.
+pub trait ToRange {
+    /// The iterator type returned by the `range` method.
+    type Range: Iterator<Item = Result<Entry>> + Send;
+
+    /// Return the entries whose keys are in the range `[start, end)`, in the byte order of
+    /// the keys.
+    ///
+    /// The bounds are compared by the bytes of the keys as well, which is the native order of
+    /// the ordered backends, e.g. RocksDB, rather than the `Ord` of `Key` comparing the
+    /// namespaces: `/a-b` is before `/a/b` in a range, but after it by the namespaces.
+    ///
+    /// A reversed range (`start` >= `end`) yields nothing rather than an error.
+    fn range<K>(&self, start: &K, end: &K) -> Result<Self::Range>
+    where
+        K: Borrow<Key>;
+}
+
+/// RangeDataStore is an interface that should be implemented by data stores
+/// that support scanning the entries of a key range in order.
+pub trait RangeDataStore: ToRange + DataStore {}
+impl<T: ToRange + DataStore> RangeDataStore for T {}
+
+/// Compare the keys by their bytes, the order of the range scans and the key listings.
+pub(crate) fn byte_order(lhs: &Key, rhs: &Key) -> Ordering {
+    lhs.as_bytes().cmp(rhs.as_bytes())
+}
+
+/// Sort the keys in the byte order.
+pub(crate) fn sort_keys(keys: &mut [Key]) {
+    keys.sort_by(byte_order);
+}
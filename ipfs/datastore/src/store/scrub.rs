@@ -1,12 +1,14 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use crate::error::Result;
+use crate::key::Key;
 use crate::store::{BatchDataStore, DataStore, TxnDataStore};
 
 /// An interface that check data integrity and/or error correction.
 pub trait Scrub {
-    /// Check data integrity and/or error correction.
-    fn scrub(&self) -> Result<()>;
+    /// Check data integrity and/or error correction, and return the sorted keys of the
+    /// corrupted entries which couldn't be corrected.
+    fn scrub(&self) -> Result<Vec<Key>>;
 }
 
 /// ScrubbedDataStore is an interface that should be implemented by data stores
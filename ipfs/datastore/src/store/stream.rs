@@ -0,0 +1,25 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use futures::stream::Stream;
+
+use crate::error::Result;
+use crate::query::Entry;
+use crate::store::DataStore;
+
+/// ToStream is an interface that should be implemented by data stores that
+/// support scanning all the entries asynchronously.
+pub trait ToStream {
+    /// The stream type returned by the `async_stream` method.
+    type Stream: Stream<Item = Result<Entry>> + Send + Unpin;
+
+    /// Return a stream yielding every entry of the datastore exactly once.
+    ///
+    /// The entries are produced as the stream is polled, so that a slow consumer
+    /// doesn't force the whole datastore into memory.
+    fn async_stream(&self) -> Self::Stream;
+}
+
+/// StreamDataStore is an interface that should be implemented by data stores
+/// that support scanning all the entries asynchronously.
+pub trait StreamDataStore: ToStream + DataStore {}
+impl<T: ToStream + DataStore> StreamDataStore for T {}
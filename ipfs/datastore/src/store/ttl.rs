@@ -1,7 +1,7 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
 use std::borrow::Borrow;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use crate::error::Result;
 use crate::key::Key;
@@ -9,17 +9,19 @@ use crate::store::{BatchDataStore, DataStore, TxnDataStore};
 
 /// Ttl encapsulates the methods that deal with entries with time-to-live.
 pub trait Ttl {
-    /// Store the object `value` named by `key` with time-to-live.
-    fn put_with_ttl<K, V>(&mut self, key: K, value: V) -> Result<()>
+    /// Store the object `value` named by `key`, which expires after `ttl`.
+    fn put_with_ttl<K, V>(&mut self, key: K, value: V, ttl: Duration) -> Result<()>
     where
         K: Into<Key>,
         V: Into<Vec<u8>>;
 
-    /// Set the duration of time-to-live.
-    fn set_ttl(&mut self, ttl: Duration) -> Result<()>;
+    /// Set the time-to-live of the existing `key`, it expires after `ttl` from now on.
+    fn set_ttl<K>(&mut self, key: &K, ttl: Duration) -> Result<()>
+    where
+        K: Borrow<Key>;
 
-    /// Get the next expiration of time-to-live of the `key`.
-    fn get_expiration<K>(&self, key: &K) -> Result<Instant>
+    /// Return the remaining time-to-live of the `key`.
+    fn get_expiration<K>(&self, key: &K) -> Result<Duration>
     where
         K: Borrow<Key>;
 }
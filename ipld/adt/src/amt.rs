@@ -0,0 +1,447 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use cid::Cid;
+use ipfs_blockstore::BlockStore;
+use minicbor::{decode, encode, Decoder, Encoder};
+
+use crate::{load_node, store_node, AdtError};
+
+/// The number of the children of an AMT node.
+pub const AMT_WIDTH: u64 = 8;
+/// The maximum index (exclusive) of the AMT.
+pub const AMT_MAX_INDEX: u64 = 1 << 48;
+
+/// The array mapped trie storing a sparse array in the block store.
+///
+/// See [go-amt-ipld](https://github.com/filecoin-project/go-amt-ipld) for the layout.
+/// The modified nodes are cached in memory until `flush`.
+pub struct Amt<BS, V> {
+    store: BS,
+    height: u64,
+    count: u64,
+    root: Node<V>,
+}
+
+impl<BS, V> Amt<BS, V>
+where
+    BS: BlockStore,
+    V: minicbor::Encode + for<'b> minicbor::Decode<'b> + Clone,
+{
+    /// Create an empty AMT.
+    pub fn new(store: BS) -> Self {
+        Self {
+            store,
+            height: 0,
+            count: 0,
+            root: Node::default(),
+        }
+    }
+
+    /// Load the AMT from the `root`.
+    pub fn load(store: BS, root: &Cid) -> Result<Self, AdtError> {
+        let Root(height, count, root) = load_node(&store, root)?;
+        Ok(Self {
+            store,
+            height,
+            count,
+            root,
+        })
+    }
+
+    /// Return the underlying block store.
+    pub fn store(&self) -> &BS {
+        &self.store
+    }
+
+    /// Return the number of the values in the AMT.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Return the value at the index `i`, `None` if the value doesn't exist.
+    pub fn get(&self, i: u64) -> Result<Option<V>, AdtError> {
+        if i >= AMT_MAX_INDEX {
+            return Err(AdtError::IndexOutOfRange(i));
+        }
+        if i >= nodes_for_height(self.height + 1) {
+            return Ok(None);
+        }
+        self.root.get(&self.store, self.height, i)
+    }
+
+    /// Set the value at the index `i`, replacing the previous one.
+    pub fn set(&mut self, i: u64, value: V) -> Result<(), AdtError> {
+        if i >= AMT_MAX_INDEX {
+            return Err(AdtError::IndexOutOfRange(i));
+        }
+        while i >= nodes_for_height(self.height + 1) {
+            // grow the tree, the old root becomes the first child of the new root.
+            if !self.root.is_empty() {
+                let root = std::mem::take(&mut self.root);
+                self.root.links[0] = Some(Link::Cached(Box::new(root)));
+            }
+            self.height += 1;
+        }
+        if self.root.set(&self.store, self.height, i, value)? {
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Delete the value at the index `i`, return whether the value existed.
+    pub fn delete(&mut self, i: u64) -> Result<bool, AdtError> {
+        if i >= AMT_MAX_INDEX {
+            return Err(AdtError::IndexOutOfRange(i));
+        }
+        if i >= nodes_for_height(self.height + 1) {
+            return Ok(false);
+        }
+        if !self.root.delete(&self.store, self.height, i)? {
+            return Ok(false);
+        }
+        self.count -= 1;
+
+        // shrink the tree while the root has only the first child.
+        while self.height > 0 && self.root.bitmap() == 1 {
+            self.root.load_child(&self.store, 0)?;
+            self.root = match self.root.links[0].take() {
+                Some(Link::Cached(child)) => *child,
+                _ => unreachable!("the child is loaded above; qed"),
+            };
+            self.height -= 1;
+        }
+        Ok(true)
+    }
+
+    /// Call `f` with every index and value of the AMT in the ascending order of the indexes.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), AdtError>
+    where
+        F: FnMut(u64, &V) -> Result<(), AdtError>,
+    {
+        self.root.for_each(&self.store, self.height, 0, &mut f)
+    }
+
+    /// Store the modified nodes into the block store, return the CID of the root.
+    pub fn flush(&mut self) -> Result<Cid, AdtError> {
+        self.root.flush(&mut self.store)?;
+        let root = Root(self.height, self.count, std::mem::take(&mut self.root));
+        let cid = store_node(&mut self.store, &root);
+        self.root = root.2;
+        cid
+    }
+}
+
+// The number of the indexes covered by a node at the `height`.
+fn nodes_for_height(height: u64) -> u64 {
+    let bits = height * 3;
+    if bits >= 64 {
+        u64::max_value()
+    } else {
+        1 << bits
+    }
+}
+
+enum Link<V> {
+    Cid(Cid),
+    // the child node loaded from the link, which is modified but not flushed.
+    Cached(Box<Node<V>>),
+}
+
+// The leaf nodes (height 0) have values, the others have links.
+struct Node<V> {
+    links: Vec<Option<Link<V>>>,
+    values: Vec<Option<V>>,
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            links: (0..AMT_WIDTH).map(|_| None).collect(),
+            values: (0..AMT_WIDTH).map(|_| None).collect(),
+        }
+    }
+}
+
+impl<V> Node<V> {
+    fn bitmap(&self) -> u8 {
+        let mut bitmap = 0;
+        for i in 0..AMT_WIDTH as usize {
+            if self.links[i].is_some() || self.values[i].is_some() {
+                bitmap |= 1 << i;
+            }
+        }
+        bitmap
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bitmap() == 0
+    }
+}
+
+impl<V> Node<V>
+where
+    V: minicbor::Encode + for<'b> minicbor::Decode<'b> + Clone,
+{
+    // Load the linked child at `index` into the cache for modification.
+    fn load_child<BS: BlockStore>(&mut self, store: &BS, index: usize) -> Result<(), AdtError> {
+        if let Some(Link::Cid(cid)) = &self.links[index] {
+            let child = load_node(store, cid)?;
+            self.links[index] = Some(Link::Cached(Box::new(child)));
+        }
+        Ok(())
+    }
+
+    fn get<BS: BlockStore>(&self, store: &BS, height: u64, i: u64) -> Result<Option<V>, AdtError> {
+        if height == 0 {
+            return Ok(self.values[i as usize].clone());
+        }
+        let range = nodes_for_height(height);
+        match &self.links[(i / range) as usize] {
+            None => Ok(None),
+            Some(Link::Cid(cid)) => {
+                load_node::<_, Node<V>>(store, cid)?.get(store, height - 1, i % range)
+            }
+            Some(Link::Cached(child)) => child.get(store, height - 1, i % range),
+        }
+    }
+
+    // Set the value, return whether the value is added rather than replaced.
+    fn set<BS: BlockStore>(
+        &mut self,
+        store: &BS,
+        height: u64,
+        i: u64,
+        value: V,
+    ) -> Result<bool, AdtError> {
+        if height == 0 {
+            return Ok(self.values[i as usize].replace(value).is_none());
+        }
+        let range = nodes_for_height(height);
+        let index = (i / range) as usize;
+        self.load_child(store, index)?;
+        let child = self.links[index].get_or_insert_with(|| Link::Cached(Box::default()));
+        match child {
+            Link::Cached(child) => child.set(store, height - 1, i % range, value),
+            Link::Cid(_) => unreachable!("the child is loaded above; qed"),
+        }
+    }
+
+    fn delete<BS: BlockStore>(
+        &mut self,
+        store: &BS,
+        height: u64,
+        i: u64,
+    ) -> Result<bool, AdtError> {
+        if height == 0 {
+            return Ok(self.values[i as usize].take().is_some());
+        }
+        let range = nodes_for_height(height);
+        let index = (i / range) as usize;
+        self.load_child(store, index)?;
+        let child = match &mut self.links[index] {
+            None => return Ok(false),
+            Some(Link::Cached(child)) => child,
+            Some(Link::Cid(_)) => unreachable!("the child is loaded above; qed"),
+        };
+        if !child.delete(store, height - 1, i % range)? {
+            return Ok(false);
+        }
+        if child.is_empty() {
+            self.links[index] = None;
+        }
+        Ok(true)
+    }
+
+    fn for_each<BS, F>(
+        &self,
+        store: &BS,
+        height: u64,
+        offset: u64,
+        f: &mut F,
+    ) -> Result<(), AdtError>
+    where
+        BS: BlockStore,
+        F: FnMut(u64, &V) -> Result<(), AdtError>,
+    {
+        if height == 0 {
+            for (i, value) in self.values.iter().enumerate() {
+                if let Some(value) = value {
+                    f(offset + i as u64, value)?;
+                }
+            }
+            return Ok(());
+        }
+        let range = nodes_for_height(height);
+        for (i, link) in self.links.iter().enumerate() {
+            let offset = offset + i as u64 * range;
+            match link {
+                None => {}
+                Some(Link::Cid(cid)) => {
+                    load_node::<_, Node<V>>(store, cid)?.for_each(store, height - 1, offset, f)?
+                }
+                Some(Link::Cached(child)) => child.for_each(store, height - 1, offset, f)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Store the modified children into the block store, the node itself is stored by the parent
+    // or the root.
+    fn flush<BS: BlockStore>(&mut self, store: &mut BS) -> Result<(), AdtError> {
+        for link in self.links.iter_mut() {
+            if let Some(Link::Cached(child)) = link {
+                child.flush(store)?;
+                *link = Some(Link::Cid(store_node(store, &**child)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Root: [height, count, node]
+struct Root<V>(u64, u64, Node<V>);
+
+impl<V: minicbor::Encode> minicbor::Encode for Root<V> {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(3)?.u64(self.0)?.u64(self.1)?.encode(&self.2)?.ok()
+    }
+}
+
+impl<'b, V: minicbor::Decode<'b>> decode::Decode<'b> for Root<V> {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        if d.array()? != Some(3) {
+            return Err(decode::Error::Message("expected a root of 3 fields"));
+        }
+        Ok(Root(d.u64()?, d.u64()?, d.decode()?))
+    }
+}
+
+// Node: [bitmap, [link, ...], [value, ...]], the bitmap is a byte whose bit i is set
+// if the child i exists.
+impl<V: minicbor::Encode> minicbor::Encode for Node<V> {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(3)?.bytes(&[self.bitmap()])?;
+        let links = self.links.iter().flatten().collect::<Vec<_>>();
+        e.array(links.len() as u64)?;
+        for link in links {
+            match link {
+                Link::Cid(cid) => e.encode(cid)?,
+                Link::Cached(_) => panic!("the cached node should be flushed before encoding"),
+            };
+        }
+        let values = self.values.iter().flatten().collect::<Vec<_>>();
+        e.array(values.len() as u64)?;
+        for value in values {
+            e.encode(value)?;
+        }
+        e.ok()
+    }
+}
+
+impl<'b, V: minicbor::Decode<'b>> decode::Decode<'b> for Node<V> {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        if d.array()? != Some(3) {
+            return Err(decode::Error::Message("expected a node of 3 fields"));
+        }
+        let bitmap = match d.bytes()? {
+            [bitmap] => *bitmap,
+            _ => return Err(decode::Error::Message("expected a bitmap of 1 byte")),
+        };
+        let indexes = (0..AMT_WIDTH as usize)
+            .filter(|i| bitmap & (1 << i) != 0)
+            .collect::<Vec<_>>();
+        let mut node = Node::default();
+
+        let links_len = d
+            .array()?
+            .ok_or(decode::Error::Message("expected a definite array"))?;
+        if links_len != 0 && links_len != indexes.len() as u64 {
+            return Err(decode::Error::Message("bitmap doesn't match the links"));
+        }
+        for i in indexes.iter().take(links_len as usize) {
+            node.links[*i] = Some(Link::Cid(d.decode::<Cid>()?));
+        }
+        let values_len = d
+            .array()?
+            .ok_or(decode::Error::Message("expected a definite array"))?;
+        if values_len + links_len != indexes.len() as u64 {
+            return Err(decode::Error::Message("bitmap doesn't match the values"));
+        }
+        for i in indexes.iter().take(values_len as usize) {
+            node.values[*i] = Some(d.decode::<V>()?);
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs_blockstore::MemoryBlockStore;
+
+    use super::*;
+
+    #[test]
+    fn test_empty_root() {
+        let mut amt = Amt::<_, u64>::new(MemoryBlockStore::new());
+        assert_eq!(
+            amt.flush().unwrap().to_string(),
+            "bafy2bzacedswlcz5ddgqnyo3sak3jmhmkxashisnlpq6ujgyhe4mlobzpnhs6"
+        );
+    }
+
+    #[test]
+    fn test_populated_root() {
+        // the indexes 0..10 fill a leaf and a half under the root of height 1.
+        let mut amt = Amt::<_, u64>::new(MemoryBlockStore::new());
+        for i in 0..10 {
+            amt.set(i, i * 10).unwrap();
+        }
+        assert_eq!(
+            amt.flush().unwrap().to_string(),
+            "bafy2bzacebhaqcazknck6fvul7fed5uooeafs5rjqc6yvdogozx7kecicjdpy"
+        );
+    }
+
+    #[test]
+    fn test_flush_and_load() {
+        let mut store = MemoryBlockStore::new();
+        let mut amt = Amt::<_, String>::new(&mut store);
+        let indexes = [0u64, 1, 7, 8, 64, 1000, 123_456];
+        for i in &indexes {
+            amt.set(*i, format!("value {}", i)).unwrap();
+        }
+        amt.set(7, "seven".into()).unwrap();
+        assert_eq!(amt.count(), indexes.len() as u64);
+        let root = amt.flush().unwrap();
+
+        let mut amt = Amt::<_, String>::load(&mut store, &root).unwrap();
+        assert_eq!(amt.count(), indexes.len() as u64);
+        assert_eq!(amt.get(7).unwrap(), Some("seven".to_string()));
+        assert_eq!(amt.get(123_456).unwrap(), Some("value 123456".to_string()));
+        assert_eq!(amt.get(2).unwrap(), None);
+        assert_eq!(amt.get(1 << 40).unwrap(), None);
+        assert!(amt.get(AMT_MAX_INDEX).is_err());
+        let mut visited = vec![];
+        amt.for_each(|i, _| {
+            visited.push(i);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(visited, indexes.to_vec());
+
+        // the tree shrinks back after deleting the large indexes.
+        assert!(amt.delete(123_456).unwrap());
+        assert!(amt.delete(1000).unwrap());
+        assert!(!amt.delete(1000).unwrap());
+        let mut small = Amt::<_, String>::new(MemoryBlockStore::new());
+        for i in &indexes[..5] {
+            let value = if *i == 7 {
+                "seven".into()
+            } else {
+                format!("value {}", i)
+            };
+            small.set(*i, value).unwrap();
+        }
+        assert_eq!(amt.flush().unwrap(), small.flush().unwrap());
+    }
+}
@@ -0,0 +1,22 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use cid::Cid;
+use ipfs_blockstore::BlockStoreError;
+
+/// The error type used for HAMT and AMT.
+#[doc(hidden)]
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum AdtError {
+    #[error("block store error: {0}")]
+    BlockStore(#[from] BlockStoreError),
+    #[error("invalid node '{0}': {1}")]
+    Decode(Cid, String),
+    #[error("invalid key: {0:?}")]
+    InvalidKey(Vec<u8>),
+    #[error("malformed HAMT: {0}")]
+    MalformedHamt(&'static str),
+    #[error("HAMT maximum depth exceeded")]
+    MaxDepth,
+    #[error("index {0} is out of range of AMT")]
+    IndexOutOfRange(u64),
+}
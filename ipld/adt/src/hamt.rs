@@ -0,0 +1,478 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::marker::PhantomData;
+
+use cid::Cid;
+use ipfs_blockstore::BlockStore;
+use minicbor::{decode, encode, Decoder, Encoder};
+
+use crate::{load_node, store_node, AdtError, HamtKey};
+
+/// The bit width of the HAMT used by the builtin actors.
+pub const HAMT_BIT_WIDTH: u32 = 5;
+/// The maximum number of the key-value pairs in a bucket before it's split into a child node.
+pub const HAMT_MAX_ARRAY_WIDTH: usize = 3;
+
+/// The hash array mapped trie storing a map in the block store.
+///
+/// The keys are hashed with sha256, and each node consumes `bit_width` bits of the hash,
+/// see [go-hamt-ipld](https://github.com/ipfs/go-hamt-ipld) for the layout.
+/// The modified nodes are cached in memory until `flush`.
+pub struct Hamt<BS, K, V> {
+    store: BS,
+    bit_width: u32,
+    root: Node<V>,
+    _key: PhantomData<K>,
+}
+
+impl<BS, K, V> Hamt<BS, K, V>
+where
+    BS: BlockStore,
+    K: HamtKey,
+    V: minicbor::Encode + for<'b> minicbor::Decode<'b> + Clone,
+{
+    /// Create an empty HAMT with the default bit width.
+    pub fn new(store: BS) -> Self {
+        Self::new_with_bit_width(store, HAMT_BIT_WIDTH)
+    }
+
+    /// Create an empty HAMT with the `bit_width`, which is at most 6.
+    pub fn new_with_bit_width(store: BS, bit_width: u32) -> Self {
+        assert!(bit_width > 0 && bit_width <= 6, "unsupported bit width");
+        Self {
+            store,
+            bit_width,
+            root: Node::default(),
+            _key: PhantomData,
+        }
+    }
+
+    /// Load the HAMT with the default bit width from the `root`.
+    pub fn load(store: BS, root: &Cid) -> Result<Self, AdtError> {
+        Self::load_with_bit_width(store, root, HAMT_BIT_WIDTH)
+    }
+
+    /// Load the HAMT with the `bit_width` from the `root`.
+    pub fn load_with_bit_width(store: BS, root: &Cid, bit_width: u32) -> Result<Self, AdtError> {
+        let mut hamt = Self::new_with_bit_width(store, bit_width);
+        hamt.root = load_node(&hamt.store, root)?;
+        Ok(hamt)
+    }
+
+    /// Return the underlying block store.
+    pub fn store(&self) -> &BS {
+        &self.store
+    }
+
+    /// Return the value of the `key`, `None` if the key doesn't exist.
+    pub fn get(&self, key: &K) -> Result<Option<V>, AdtError> {
+        let key = key.to_key_bytes();
+        let hash = HashBits::new(&key, self.bit_width);
+        self.root.get(&self.store, &key, &hash, 0)
+    }
+
+    /// Set the value of the `key`, replacing the previous one.
+    pub fn set(&mut self, key: K, value: V) -> Result<(), AdtError> {
+        let key = key.to_key_bytes();
+        let hash = HashBits::new(&key, self.bit_width);
+        self.root.set(&self.store, key, value, &hash, 0)
+    }
+
+    /// Delete the `key`, return whether the key existed.
+    pub fn delete(&mut self, key: &K) -> Result<bool, AdtError> {
+        let key = key.to_key_bytes();
+        let hash = HashBits::new(&key, self.bit_width);
+        self.root.delete(&self.store, &key, &hash, 0)
+    }
+
+    /// Call `f` with every key-value pair of the HAMT, in the order of the key hashes.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), AdtError>
+    where
+        F: FnMut(K, &V) -> Result<(), AdtError>,
+    {
+        self.root.for_each(&self.store, &mut f)
+    }
+
+    /// Store the modified nodes into the block store, return the CID of the root.
+    pub fn flush(&mut self) -> Result<Cid, AdtError> {
+        self.root.flush(&mut self.store)
+    }
+}
+
+// The bits of the sha256 hash of a key, consumed from the most significant bit.
+struct HashBits {
+    digest: [u8; 32],
+    bit_width: u32,
+}
+
+impl HashBits {
+    fn new(key: &[u8], bit_width: u32) -> Self {
+        Self {
+            digest: plum_hashing::sha256(key),
+            bit_width,
+        }
+    }
+
+    // Return the index of the child at the `depth`.
+    fn index(&self, depth: u32) -> Result<u32, AdtError> {
+        let start = depth * self.bit_width;
+        if start + self.bit_width > 256 {
+            return Err(AdtError::MaxDepth);
+        }
+        let mut index = 0;
+        for bit in start..start + self.bit_width {
+            let byte = self.digest[(bit / 8) as usize];
+            index = (index << 1) | u32::from((byte >> (7 - bit % 8)) & 1);
+        }
+        Ok(index)
+    }
+}
+
+type KeyValue<V> = (Vec<u8>, V);
+
+enum Pointer<V> {
+    // the bucket of the key-value pairs sorted by key.
+    Values(Vec<KeyValue<V>>),
+    Link(Cid),
+    // the child node loaded from the link, which is modified but not flushed.
+    Cached(Box<Node<V>>),
+}
+
+struct Node<V> {
+    bitfield: u64,
+    pointers: Vec<Pointer<V>>,
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            bitfield: 0,
+            pointers: vec![],
+        }
+    }
+}
+
+impl<V> Node<V>
+where
+    V: minicbor::Encode + for<'b> minicbor::Decode<'b> + Clone,
+{
+    // Return the position of the pointer of the child index and whether the child exists.
+    fn position(&self, index: u32) -> (usize, bool) {
+        let bit = 1u64 << index;
+        let pos = (self.bitfield & (bit - 1)).count_ones() as usize;
+        (pos, self.bitfield & bit != 0)
+    }
+
+    // Load the linked child of the pointer at `pos` into the cache for modification.
+    fn load_child<BS: BlockStore>(&mut self, store: &BS, pos: usize) -> Result<(), AdtError> {
+        if let Pointer::Link(cid) = &self.pointers[pos] {
+            let child = load_node(store, cid)?;
+            self.pointers[pos] = Pointer::Cached(Box::new(child));
+        }
+        Ok(())
+    }
+
+    fn get<BS: BlockStore>(
+        &self,
+        store: &BS,
+        key: &[u8],
+        hash: &HashBits,
+        depth: u32,
+    ) -> Result<Option<V>, AdtError> {
+        let (pos, exists) = self.position(hash.index(depth)?);
+        if !exists {
+            return Ok(None);
+        }
+        match &self.pointers[pos] {
+            Pointer::Values(kvs) => Ok(kvs
+                .iter()
+                .find(|(k, _)| k.as_slice() == key)
+                .map(|(_, v)| v.clone())),
+            Pointer::Link(cid) => {
+                load_node::<_, Node<V>>(store, cid)?.get(store, key, hash, depth + 1)
+            }
+            Pointer::Cached(child) => child.get(store, key, hash, depth + 1),
+        }
+    }
+
+    fn set<BS: BlockStore>(
+        &mut self,
+        store: &BS,
+        key: Vec<u8>,
+        value: V,
+        hash: &HashBits,
+        depth: u32,
+    ) -> Result<(), AdtError> {
+        let index = hash.index(depth)?;
+        let (pos, exists) = self.position(index);
+        if !exists {
+            self.bitfield |= 1 << index;
+            self.pointers
+                .insert(pos, Pointer::Values(vec![(key, value)]));
+            return Ok(());
+        }
+
+        self.load_child(store, pos)?;
+        let kvs = match &mut self.pointers[pos] {
+            Pointer::Cached(child) => return child.set(store, key, value, hash, depth + 1),
+            Pointer::Values(kvs) => kvs,
+            Pointer::Link(_) => unreachable!("the child is loaded above; qed"),
+        };
+        match kvs.binary_search_by(|(k, _)| k.as_slice().cmp(&key)) {
+            Ok(i) => kvs[i].1 = value,
+            Err(i) if kvs.len() < HAMT_MAX_ARRAY_WIDTH => kvs.insert(i, (key, value)),
+            Err(_) => {
+                // split the full bucket into a child node.
+                let mut child = Node::default();
+                for (k, v) in kvs.drain(..).chain(std::iter::once((key, value))) {
+                    let hash = HashBits::new(&k, hash.bit_width);
+                    child.set(store, k, v, &hash, depth + 1)?;
+                }
+                self.pointers[pos] = Pointer::Cached(Box::new(child));
+            }
+        }
+        Ok(())
+    }
+
+    fn delete<BS: BlockStore>(
+        &mut self,
+        store: &BS,
+        key: &[u8],
+        hash: &HashBits,
+        depth: u32,
+    ) -> Result<bool, AdtError> {
+        let index = hash.index(depth)?;
+        let (pos, exists) = self.position(index);
+        if !exists {
+            return Ok(false);
+        }
+
+        self.load_child(store, pos)?;
+        match &mut self.pointers[pos] {
+            Pointer::Cached(child) => {
+                if !child.delete(store, key, hash, depth + 1)? {
+                    return Ok(false);
+                }
+                self.clean_child(pos)?;
+            }
+            Pointer::Values(kvs) => {
+                let i = match kvs.iter().position(|(k, _)| k.as_slice() == key) {
+                    Some(i) => i,
+                    None => return Ok(false),
+                };
+                kvs.remove(i);
+                if kvs.is_empty() {
+                    self.bitfield &= !(1 << index);
+                    self.pointers.remove(pos);
+                }
+            }
+            Pointer::Link(_) => unreachable!("the child is loaded above; qed"),
+        }
+        Ok(true)
+    }
+
+    // Collapse the child at `pos` into a bucket if its key-value pairs fit in one,
+    // which keeps the HAMT canonical after deletions.
+    fn clean_child(&mut self, pos: usize) -> Result<(), AdtError> {
+        let child = match &mut self.pointers[pos] {
+            Pointer::Cached(child) => child,
+            _ => return Ok(()),
+        };
+        if child.pointers.is_empty() {
+            return Err(AdtError::MalformedHamt("node without pointers"));
+        }
+        if child.pointers.len() > HAMT_MAX_ARRAY_WIDTH {
+            return Ok(());
+        }
+        let mut kvs = Vec::with_capacity(HAMT_MAX_ARRAY_WIDTH);
+        for pointer in &child.pointers {
+            match pointer {
+                Pointer::Values(values) => kvs.extend(values.iter().cloned()),
+                _ => return Ok(()),
+            }
+        }
+        if kvs.len() > HAMT_MAX_ARRAY_WIDTH {
+            return Ok(());
+        }
+        kvs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.pointers[pos] = Pointer::Values(kvs);
+        Ok(())
+    }
+
+    fn for_each<BS, K, F>(&self, store: &BS, f: &mut F) -> Result<(), AdtError>
+    where
+        BS: BlockStore,
+        K: HamtKey,
+        F: FnMut(K, &V) -> Result<(), AdtError>,
+    {
+        for pointer in &self.pointers {
+            match pointer {
+                Pointer::Values(kvs) => {
+                    for (k, v) in kvs {
+                        let key =
+                            K::from_key_bytes(k).ok_or_else(|| AdtError::InvalidKey(k.clone()))?;
+                        f(key, v)?;
+                    }
+                }
+                Pointer::Link(cid) => load_node::<_, Node<V>>(store, cid)?.for_each(store, f)?,
+                Pointer::Cached(child) => child.for_each(store, f)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush<BS: BlockStore>(&mut self, store: &mut BS) -> Result<Cid, AdtError> {
+        for pointer in &mut self.pointers {
+            if let Pointer::Cached(child) = pointer {
+                *pointer = Pointer::Link(child.flush(store)?);
+            }
+        }
+        store_node(store, self)
+    }
+}
+
+// Node: [bitfield, [pointer, ...]], the bitfield is the big-endian bytes of the big integer.
+// Pointer: {"0": link} or {"1": [[key, value], ...]}.
+impl<V: minicbor::Encode> minicbor::Encode for Node<V> {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        let bitfield = self.bitfield.to_be_bytes();
+        let leading_zeros = (self.bitfield.leading_zeros() / 8) as usize;
+        e.array(2)?.bytes(&bitfield[leading_zeros..])?;
+        e.array(self.pointers.len() as u64)?;
+        for pointer in &self.pointers {
+            match pointer {
+                Pointer::Values(kvs) => {
+                    e.map(1)?.str("1")?.array(kvs.len() as u64)?;
+                    for (k, v) in kvs {
+                        e.array(2)?.bytes(k)?.encode(v)?;
+                    }
+                }
+                Pointer::Link(cid) => {
+                    e.map(1)?.str("0")?.encode(cid)?;
+                }
+                Pointer::Cached(_) => panic!("the cached node should be flushed before encoding"),
+            }
+        }
+        e.ok()
+    }
+}
+
+impl<'b, V: minicbor::Decode<'b>> decode::Decode<'b> for Node<V> {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        if d.array()? != Some(2) {
+            return Err(decode::Error::Message("expected a node of 2 fields"));
+        }
+        let bitfield = d.bytes()?;
+        if bitfield.len() > 8 {
+            return Err(decode::Error::Message("bitfield is too large"));
+        }
+        let bitfield = bitfield
+            .iter()
+            .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+        let len = d
+            .array()?
+            .ok_or(decode::Error::Message("expected a definite array"))?;
+        if u64::from(bitfield.count_ones()) != len {
+            return Err(decode::Error::Message(
+                "bitfield doesn't match the pointers",
+            ));
+        }
+        let mut pointers = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            if d.map()? != Some(1) {
+                return Err(decode::Error::Message("expected a pointer of 1 entry"));
+            }
+            let pointer = match d.str()? {
+                "0" => Pointer::Link(d.decode::<Cid>()?),
+                "1" => {
+                    let len = d
+                        .array()?
+                        .ok_or(decode::Error::Message("expected a definite array"))?;
+                    let mut kvs = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        if d.array()? != Some(2) {
+                            return Err(decode::Error::Message("expected a key-value pair"));
+                        }
+                        kvs.push((d.bytes()?.to_vec(), d.decode::<V>()?));
+                    }
+                    Pointer::Values(kvs)
+                }
+                _ => return Err(decode::Error::Message("unexpected pointer key")),
+            };
+            pointers.push(pointer);
+        }
+        Ok(Node { bitfield, pointers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs_blockstore::MemoryBlockStore;
+
+    use super::*;
+
+    #[test]
+    fn test_empty_root() {
+        let mut hamt = Hamt::<_, String, u64>::new(MemoryBlockStore::new());
+        assert_eq!(
+            hamt.flush().unwrap().to_string(),
+            "bafy2bzaceamp42wmmgr2g2ymg46euououzfyck7szknvfacqscohrvaikwfay"
+        );
+    }
+
+    #[test]
+    fn test_populated_root() {
+        let mut hamt = Hamt::<_, String, u64>::new(MemoryBlockStore::new());
+        for i in 0..12u64 {
+            hamt.set(i.to_string(), i).unwrap();
+        }
+        // the keys "3", "4", "10" and "11" share the index 9 of the root, split into a child.
+        assert_eq!(
+            hamt.flush().unwrap().to_string(),
+            "bafy2bzaced6wtzglwuvzeexajrceijy4hmfuvfpbt6icx3k54qwv3hwjsqi3o"
+        );
+
+        // the child collapses into the bucket sorted by key, not by the indexes in the child.
+        assert!(hamt.delete(&"11".to_string()).unwrap());
+        assert_eq!(
+            hamt.flush().unwrap().to_string(),
+            "bafy2bzacecfnvselqtdt3xe4uyfjrloet25ckjrfjalwq6t3v6irkpgvuiyja"
+        );
+    }
+
+    #[test]
+    fn test_flush_and_load() {
+        let mut store = MemoryBlockStore::new();
+        let mut hamt = Hamt::<_, u64, String>::new(&mut store);
+        for i in 0..200u64 {
+            hamt.set(i, format!("value {}", i)).unwrap();
+        }
+        hamt.set(7, "seven".into()).unwrap();
+        assert!(hamt.delete(&8).unwrap());
+        assert!(!hamt.delete(&1000).unwrap());
+        let root = hamt.flush().unwrap();
+
+        let mut hamt = Hamt::<_, u64, String>::load(&mut store, &root).unwrap();
+        assert_eq!(hamt.get(&7).unwrap(), Some("seven".to_string()));
+        assert_eq!(hamt.get(&8).unwrap(), None);
+        assert_eq!(hamt.get(&199).unwrap(), Some("value 199".to_string()));
+        let mut entries = vec![];
+        hamt.for_each(|k, v| {
+            entries.push((k, v.clone()));
+            Ok(())
+        })
+        .unwrap();
+        entries.sort();
+        assert_eq!(entries.len(), 199);
+        assert_eq!(entries[7], (7, "seven".to_string()));
+
+        // the root is canonical, independent of the history of the modifications.
+        for i in 200..300u64 {
+            hamt.set(i, "temporary".into()).unwrap();
+        }
+        for i in 200..300u64 {
+            assert!(hamt.delete(&i).unwrap());
+        }
+        assert_eq!(hamt.flush().unwrap(), root);
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use plum_address::Address;
+
+/// The key of the HAMT, which is stored as bytes.
+///
+/// The encodings match the keys of the Lotus actors (`adt.AddrKey`, `adt.UIntKey`, ...).
+pub trait HamtKey: Sized {
+    /// Return the bytes stored for the key.
+    fn to_key_bytes(&self) -> Vec<u8>;
+
+    /// Parse the key from the stored bytes, `None` if the bytes are invalid.
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl HamtKey for Vec<u8> {
+    fn to_key_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl HamtKey for String {
+    fn to_key_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// The unsigned varint encoding.
+impl HamtKey for u64 {
+    fn to_key_bytes(&self) -> Vec<u8> {
+        let mut value = *self;
+        let mut bytes = Vec::with_capacity(10);
+        while value >= 0x80 {
+            bytes.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut value = 0u64;
+        for (i, byte) in bytes.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return if i + 1 == bytes.len() {
+                    Some(value)
+                } else {
+                    None
+                };
+            }
+        }
+        None
+    }
+}
+
+/// The zigzag varint encoding.
+impl HamtKey for i64 {
+    fn to_key_bytes(&self) -> Vec<u8> {
+        (((*self << 1) ^ (*self >> 63)) as u64).to_key_bytes()
+    }
+
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self> {
+        let value = u64::from_key_bytes(bytes)?;
+        Some(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+}
+
+impl HamtKey for Address {
+    fn to_key_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn from_key_bytes(bytes: &[u8]) -> Option<Self> {
+        Address::new_from_bytes(bytes).ok()
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! The HAMT and AMT used by the Filecoin builtin actors to store their maps and arrays
+//! in a block store, the node layouts are compatible with Lotus so that the roots match.
+
+#![deny(missing_docs)]
+
+mod amt;
+mod error;
+mod hamt;
+mod key;
+
+pub use self::amt::{Amt, AMT_MAX_INDEX, AMT_WIDTH};
+pub use self::error::AdtError;
+pub use self::hamt::{Hamt, HAMT_BIT_WIDTH, HAMT_MAX_ARRAY_WIDTH};
+pub use self::key::HamtKey;
+
+use cid::Cid;
+use ipfs_block::IpfsBlock;
+use ipfs_blockstore::BlockStore;
+
+// Load the CBOR encoded node named by `cid` from the block store.
+fn load_node<BS, T>(store: &BS, cid: &Cid) -> Result<T, AdtError>
+where
+    BS: BlockStore,
+    T: for<'b> minicbor::Decode<'b>,
+{
+    let block = store.get(cid)?;
+    minicbor::decode(block.data()).map_err(|err| AdtError::Decode(cid.clone(), err.to_string()))
+}
+
+// Store the CBOR encoded node into the block store, return its CID.
+fn store_node<BS, T>(store: &mut BS, node: &T) -> Result<Cid, AdtError>
+where
+    BS: BlockStore,
+    T: minicbor::Encode,
+{
+    let block = IpfsBlock::new(node);
+    let cid = block.cid().clone();
+    store.put(block)?;
+    Ok(cid)
+}
@@ -1,38 +1,90 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use futures::Async;
 use libp2p::core::identity::Keypair;
 use libp2p::core::PeerId;
-use libp2p::gossipsub::{Gossipsub, GossipsubConfig, GossipsubEvent, MessageId, Topic, TopicHash};
+use libp2p::gossipsub::{
+    Gossipsub, GossipsubConfigBuilder, GossipsubEvent, MessageId, Topic, TopicHash,
+};
 use libp2p::identify::{Identify, IdentifyEvent};
 use libp2p::kad::{record::store::MemoryStore, Kademlia, KademliaEvent};
+use libp2p::kad::{GetClosestPeersError, GetClosestPeersOk};
 use libp2p::mdns::{Mdns, MdnsEvent};
 use libp2p::ping::{Ping, PingEvent};
-use libp2p::swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess};
+use libp2p::swarm::{NetworkBehaviour as _, NetworkBehaviourAction, NetworkBehaviourEventProcess};
 use libp2p::tokio_io::{AsyncRead, AsyncWrite};
-use libp2p::NetworkBehaviour;
-use log::debug;
+use libp2p::{Multiaddr, NetworkBehaviour};
+use log::{debug, warn};
+
+use plum_block::BlockMsg;
+use plum_message::SignedMessage;
 
-use crate::config::{generate_kad_config, HELLO_TOPIC};
-use crate::rpc::{RPCEvent, RPCMessage, RPC};
+use crate::config::{
+    generate_kad_config, genesis_hash, BLOCKS_TOPIC, MAX_GOSSIP_SIZE, MESSAGES_TOPIC,
+};
+use crate::filter::{AddressFilter, FilteredBehaviour};
+use crate::peer_store::PeerMetadataRecorder;
+use crate::rpc::{
+    HelloMessage, LatencyMessage, RPCErrorResponse, RPCEvent, RPCMessage, RPCRequest, RPCResponse,
+    RPC,
+};
 
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "BehaviourEvent", poll_method = "poll")]
 pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
     pub rpc: RPC<TSubstream>,
-    pub kad: Kademlia<TSubstream, MemoryStore>,
+    pub kad: FilteredBehaviour<Kademlia<TSubstream, MemoryStore>>,
     pub ping: Ping<TSubstream>,
-    pub mdns: Mdns<TSubstream>,
+    pub mdns: FilteredBehaviour<Mdns<TSubstream>>,
     pub identify: Identify<TSubstream>,
     pub gossipsub: Gossipsub<TSubstream>,
     #[behaviour(ignore)]
     events: Vec<BehaviourEvent>,
+    #[behaviour(ignore)]
+    address_filter: AddressFilter,
+    #[behaviour(ignore)]
+    peer_metadata: Option<Box<dyn PeerMetadataRecorder>>,
+    #[behaviour(ignore)]
+    head: HelloMessage,
+    #[behaviour(ignore)]
+    lookups: HashMap<PeerId, Lookup>,
+    #[behaviour(ignore)]
+    next_query_id: u64,
+}
+
+/// The identifier of a peer lookup started by `Behaviour::find_peer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QueryId(u64);
+
+// The pending lookup of a peer, with the addresses of the peer discovered so far.
+struct Lookup {
+    id: QueryId,
+    addresses: Vec<Multiaddr>,
 }
 
 pub enum BehaviourEvent {
     RPC(PeerId, RPCEvent),
-    HelloSubscribed(PeerId),
-    DiscoveredPeer(PeerId),
+    /// The peer speaks no compatible RPC protocol version.
+    ProtocolMismatch(PeerId),
+    /// The peer with the same genesis said hello with its heaviest tipset.
+    Hello(PeerId, HelloMessage),
+    /// The peer subscribed the gossipsub topic.
+    PeerSubscribed(PeerId, TopicHash),
+    /// The peer is identified, the listen addresses allowed by the address filter are
+    /// added into the kademlia routing table.
+    Identified {
+        peer_id: PeerId,
+        agent_version: String,
+        listen_addrs: Vec<Multiaddr>,
+        /// Our address observed by the peer.
+        observed_addr: Multiaddr,
+    },
+    /// The peer discovered with the addresses allowed by the address filter.
+    DiscoveredPeer(PeerId, Vec<Multiaddr>),
     ExpiredPeer(PeerId),
     GossipMessage {
         id: MessageId,
@@ -40,6 +92,17 @@ pub enum BehaviourEvent {
         topics: Vec<TopicHash>,
         data: Vec<u8>,
     },
+    /// The DHT lookup found the peer with the addresses allowed by the address filter.
+    PeerFound {
+        query_id: QueryId,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    },
+    /// The DHT lookup finished without any address of the peer.
+    PeerNotFound {
+        query_id: QueryId,
+        peer_id: PeerId,
+    },
 }
 
 impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<RPCMessage>
@@ -47,15 +110,38 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<RPCMessage
 {
     fn inject_event(&mut self, event: RPCMessage) {
         match event {
-            RPCMessage::PeerDialed(_peer_id) => {
-                // self.events.push(BehaviourEvent::PeerDialed(peer_id))
+            RPCMessage::PeerConnected(peer_id) => {
+                self.rpc.say_hello(peer_id, self.head.clone());
             }
             RPCMessage::PeerDisconnected(_peer_id) => {
                 // self.events.push(BehaviourEvent::PeerDisconnected(peer_id))
             }
+            RPCMessage::RPC(peer_id, RPCEvent::Request(id, RPCRequest::Hello(hello))) => {
+                // the latency is responded whatever the genesis of the peer, the same as Lotus.
+                let t_arrival = unix_nanos();
+                self.accept_hello(peer_id.clone(), hello);
+                let latency = LatencyMessage {
+                    t_arrival,
+                    t_sent: unix_nanos(),
+                };
+                let response = RPCErrorResponse::Success(RPCResponse::Latency(latency));
+                self.rpc.send_rpc(peer_id, RPCEvent::Response(id, response));
+            }
+            RPCMessage::RPC(
+                peer_id,
+                RPCEvent::Response(_, RPCErrorResponse::Success(RPCResponse::Latency(latency))),
+            ) => {
+                debug!(
+                    "Peer {:?} received the hello at {} and responded at {}",
+                    peer_id, latency.t_arrival, latency.t_sent
+                );
+            }
             RPCMessage::RPC(peer_id, rpc_event) => {
                 self.events.push(BehaviourEvent::RPC(peer_id, rpc_event))
             }
+            RPCMessage::ProtocolMismatch(peer_id) => {
+                self.events.push(BehaviourEvent::ProtocolMismatch(peer_id))
+            }
         }
     }
 }
@@ -66,8 +152,20 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<MdnsEvent>
     fn inject_event(&mut self, event: MdnsEvent) {
         match event {
             MdnsEvent::Discovered(list) => {
-                for (peer, _) in list {
-                    self.events.push(BehaviourEvent::DiscoveredPeer(peer))
+                let mut discovered = Vec::<(PeerId, Vec<Multiaddr>)>::new();
+                for (peer, addr) in list {
+                    if !self.address_filter.is_allowed(&addr) {
+                        debug!("Skip the filtered address {} of peer {:?}", addr, peer);
+                        continue;
+                    }
+                    match discovered.iter_mut().find(|(p, _)| p == &peer) {
+                        Some((_, addrs)) => addrs.push(addr),
+                        None => discovered.push((peer, vec![addr])),
+                    }
+                }
+                for (peer, addrs) in discovered {
+                    self.events
+                        .push(BehaviourEvent::DiscoveredPeer(peer, addrs))
                 }
             }
             MdnsEvent::Expired(list) => {
@@ -84,8 +182,35 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<MdnsEvent>
 impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<KademliaEvent>
     for Behaviour<TSubstream>
 {
-    fn inject_event(&mut self, _event: KademliaEvent) {
-        // TODO: PeerDiscovered via kad bootstrap.
+    fn inject_event(&mut self, event: KademliaEvent) {
+        match event {
+            KademliaEvent::Discovered {
+                peer_id, addresses, ..
+            } => {
+                if let Some(lookup) = self.lookups.get_mut(&peer_id) {
+                    for addr in addresses {
+                        if !lookup.addresses.contains(&addr) {
+                            lookup.addresses.push(addr);
+                        }
+                    }
+                }
+            }
+            KademliaEvent::GetClosestPeersResult(result) => {
+                let (key, peers) = match result {
+                    Ok(GetClosestPeersOk { key, peers }) => (key, peers),
+                    Err(GetClosestPeersError::Timeout { key, peers }) => (key, peers),
+                };
+                let peer_id = match PeerId::from_bytes(key) {
+                    Ok(peer_id) => peer_id,
+                    Err(_) => return,
+                };
+                if let Some(lookup) = self.lookups.remove(&peer_id) {
+                    self.finish_lookup(peer_id, lookup, peers.contains(&peer_id));
+                }
+            }
+            // TODO: PeerDiscovered via kad bootstrap.
+            _ => {}
+        }
     }
 }
 
@@ -103,9 +228,8 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<GossipsubE
                 })
             }
             GossipsubEvent::Subscribed { peer_id, topic } => {
-                if topic == TopicHash::from_raw(HELLO_TOPIC) {
-                    self.events.push(BehaviourEvent::HelloSubscribed(peer_id));
-                }
+                self.events
+                    .push(BehaviourEvent::PeerSubscribed(peer_id, topic));
             }
             GossipsubEvent::Unsubscribed { .. } => {}
         }
@@ -134,6 +258,29 @@ impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<IdentifyEv
                 debug!("listening_ addresses {:?}", info.listen_addrs);
                 debug!("observed_address {:?}", observed_addr);
                 debug!("protocols {:?}", info.protocols);
+                if let Some(recorder) = self.peer_metadata.as_mut() {
+                    if let Err(err) =
+                        recorder.record_identify(&peer_id, &info.agent_version, &info.protocols)
+                    {
+                        warn!(
+                            "Failed to record the metadata of peer {:?}: {}",
+                            peer_id, err
+                        );
+                    }
+                }
+                let listen_addrs = self
+                    .address_filter
+                    .filter(info.listen_addrs)
+                    .collect::<Vec<_>>();
+                for addr in &listen_addrs {
+                    self.kad.add_address(&peer_id, addr.clone());
+                }
+                self.events.push(BehaviourEvent::Identified {
+                    peer_id,
+                    agent_version: info.agent_version,
+                    listen_addrs,
+                    observed_addr,
+                });
             }
             IdentifyEvent::Sent { .. } => (),
             IdentifyEvent::Error { .. } => (),
@@ -154,18 +301,138 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
 }
 
 impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
-    pub fn new(local_key: &Keypair) -> Self {
+    pub fn new(local_key: &Keypair, address_filter: AddressFilter) -> Self {
         let local_peer_id = local_key.public().into_peer_id();
         let (kad_cfg, kad_store) = generate_kad_config(&local_peer_id);
         Self {
             rpc: RPC::new(),
-            kad: Kademlia::with_config(local_peer_id.clone(), kad_store, kad_cfg),
+            kad: FilteredBehaviour::new(
+                Kademlia::with_config(local_peer_id.clone(), kad_store, kad_cfg),
+                address_filter.clone(),
+            ),
             ping: Ping::default(),
-            mdns: Mdns::new().expect("Failed to create mDNS service"),
+            mdns: FilteredBehaviour::new(
+                Mdns::new().expect("Failed to create mDNS service"),
+                address_filter.clone(),
+            ),
             events: vec![],
+            address_filter,
+            peer_metadata: None,
+            head: HelloMessage {
+                heaviest_tip_set: vec![],
+                heaviest_tip_set_height: 0,
+                heaviest_tip_set_weight: 0u64.into(),
+                genesis_hash: genesis_hash(),
+            },
+            lookups: HashMap::new(),
+            next_query_id: 0,
             identify: Identify::new("plum/libp2p".into(), "0.0.1".into(), local_key.public()),
-            gossipsub: Gossipsub::new(local_peer_id, GossipsubConfig::default()),
+            gossipsub: Gossipsub::new(
+                local_peer_id,
+                GossipsubConfigBuilder::new()
+                    .max_transmit_size(MAX_GOSSIP_SIZE)
+                    .build(),
+            ),
+        }
+    }
+
+    /// Return the filter applied to the addresses before dialing.
+    pub fn address_filter(&self) -> &AddressFilter {
+        &self.address_filter
+    }
+
+    /// Record the metadata of the identified peers with the `recorder`,
+    /// e.g. a `PeerMetadataStore`.
+    pub fn set_peer_metadata_recorder<R>(&mut self, recorder: R)
+    where
+        R: PeerMetadataRecorder + 'static,
+    {
+        self.peer_metadata = Some(Box::new(recorder));
+    }
+
+    /// Return the local heaviest tipset said to the peers in the hello handshake.
+    pub fn head(&self) -> &HelloMessage {
+        &self.head
+    }
+
+    /// Update the local heaviest tipset said to the peers in the hello handshake,
+    /// the head starts at the genesis.
+    pub fn set_head(&mut self, head: HelloMessage) {
+        self.head = head;
+    }
+
+    // Report the hello of the peer, unless the peer is on another chain.
+    fn accept_hello(&mut self, peer_id: PeerId, hello: HelloMessage) {
+        if hello.genesis_hash != self.head.genesis_hash {
+            warn!(
+                "Peer {:?} has a different genesis {}",
+                peer_id, hello.genesis_hash
+            );
+            return;
+        }
+        debug!("Received hello from peer {:?}: {}", peer_id, hello);
+        self.events.push(BehaviourEvent::Hello(peer_id, hello));
+    }
+
+    /// Add the address of the peer into the kademlia routing table,
+    /// the address blocked by the address filter is skipped.
+    pub fn add_address(&mut self, peer_id: &PeerId, addr: Multiaddr) {
+        if self.address_filter.is_allowed(&addr) {
+            self.kad.add_address(peer_id, addr);
+        } else {
+            debug!("Skip the filtered address {} of peer {:?}", addr, peer_id);
+        }
+    }
+
+    /// Look up the addresses of the peer in the kademlia DHT, the result is reported by
+    /// `BehaviourEvent::PeerFound` or `BehaviourEvent::PeerNotFound` with the returned id.
+    ///
+    /// The found addresses are added into the routing table, looking up a peer which is
+    /// already being looked up returns the id of the pending lookup.
+    pub fn find_peer(&mut self, peer_id: PeerId) -> QueryId {
+        if let Some(lookup) = self.lookups.get(&peer_id) {
+            return lookup.id;
+        }
+        let id = QueryId(self.next_query_id);
+        self.next_query_id += 1;
+        self.kad.get_closest_peers(peer_id.clone());
+        self.lookups.insert(
+            peer_id,
+            Lookup {
+                id,
+                addresses: vec![],
+            },
+        );
+        id
+    }
+
+    // Report the finished lookup, `reached` is whether the peer was among the closest peers.
+    fn finish_lookup(&mut self, peer_id: PeerId, lookup: Lookup, reached: bool) {
+        let mut addresses = lookup.addresses;
+        if reached {
+            for addr in self.kad.addresses_of_peer(&peer_id) {
+                if !addresses.contains(&addr) {
+                    addresses.push(addr);
+                }
+            }
+        }
+        let addresses = self.address_filter.filter(addresses).collect::<Vec<_>>();
+        if addresses.is_empty() {
+            debug!("No address of peer {:?} is found", peer_id);
+            self.events.push(BehaviourEvent::PeerNotFound {
+                query_id: lookup.id,
+                peer_id,
+            });
+            return;
         }
+        for addr in &addresses {
+            self.add_address(&peer_id, addr.clone());
+        }
+        self.events.push(BehaviourEvent::PeerFound {
+            query_id: lookup.id,
+            peer_id,
+            addresses,
+        });
     }
 
     /// Sends an RPC Request/Response via the RPC protocol.
@@ -182,4 +449,62 @@ impl<TSubstream: AsyncRead + AsyncWrite> Behaviour<TSubstream> {
     pub fn subscribe(&mut self, topic: Topic) -> bool {
         self.gossipsub.subscribe(topic)
     }
+
+    /// Publish the CBOR encoded block message to the blocks topic.
+    pub fn publish_block(&mut self, block: &BlockMsg) -> Result<(), PublishError> {
+        let data =
+            minicbor::to_vec(block).expect("CBOR serialization of BlockMsg shouldn't be failed");
+        self.publish_checked(BLOCKS_TOPIC, data)
+    }
+
+    /// Publish the CBOR encoded signed message to the messages topic.
+    pub fn publish_message(&mut self, msg: &SignedMessage) -> Result<(), PublishError> {
+        let data =
+            minicbor::to_vec(msg).expect("CBOR serialization of SignedMessage shouldn't be failed");
+        self.publish_checked(MESSAGES_TOPIC, data)
+    }
+
+    fn publish_checked(&mut self, topic: &str, data: Vec<u8>) -> Result<(), PublishError> {
+        if data.len() > MAX_GOSSIP_SIZE {
+            return Err(PublishError::MessageTooLarge {
+                size: data.len(),
+                max: MAX_GOSSIP_SIZE,
+            });
+        }
+        debug!("Publishing {} bytes to topic {}", data.len(), topic);
+        self.gossipsub.publish(&Topic::new(topic.into()), data);
+        Ok(())
+    }
+}
+
+// Return the current unix time in nanoseconds, the time unit of the latency message.
+fn unix_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as i64)
+        .unwrap_or_default()
 }
+
+/// The error returned when publishing a gossip message fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PublishError {
+    /// The encoded message exceeds the gossip size limit.
+    MessageTooLarge {
+        /// The size of the encoded message in bytes.
+        size: usize,
+        /// The gossip size limit in bytes.
+        max: usize,
+    },
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::MessageTooLarge { size, max } => {
+                write!(f, "gossip message size {} exceeds the limit {}", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
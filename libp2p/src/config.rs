@@ -1,21 +1,35 @@
 // Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
 
+use std::time::Duration;
+
 use cid::{Cid, Codec, IntoExt};
 use libp2p::gossipsub::Topic;
 use libp2p::kad::{record::store::MemoryStore, KademliaConfig};
 use libp2p::{Multiaddr, PeerId};
 
+use crate::filter::AddressFilter;
+
 pub const GENESIS: &[u8] = b"filecoin plum";
 
-pub const HELLO_TOPIC: &str = "/fil/hello";
 pub const BLOCKS_TOPIC: &str = "/fil/blocks";
 pub const MESSAGES_TOPIC: &str = "/fil/messages";
 
+/// The maximum size of a message that can be published to the gossipsub topics.
+pub const MAX_GOSSIP_SIZE: usize = 1 << 20;
+
+/// The default timeout of the authentication and multiplexing upgrade of the transport.
+pub const DEFAULT_UPGRADE_TIMEOUT: Duration = Duration::from_secs(20);
+
 #[derive(Debug)]
 pub struct Libp2pConfig {
     pub listen_address: Multiaddr,
     pub bootnodes: Vec<Multiaddr>,
     pub pubsub_topics: Vec<Topic>,
+    /// The timeout of the authentication and multiplexing upgrade of the transport,
+    /// peers that stall the handshake past it are dropped.
+    pub upgrade_timeout: Duration,
+    /// The filter applied to the addresses before dialing.
+    pub address_filter: AddressFilter,
 }
 
 impl Default for Libp2pConfig {
@@ -24,10 +38,11 @@ impl Default for Libp2pConfig {
             listen_address: "/ip4/0.0.0.0/tcp/0".parse::<Multiaddr>().unwrap(),
             bootnodes: vec![],
             pubsub_topics: vec![
-                Topic::new(HELLO_TOPIC.into()),
                 Topic::new(BLOCKS_TOPIC.into()),
                 Topic::new(MESSAGES_TOPIC.into()),
             ],
+            upgrade_timeout: DEFAULT_UPGRADE_TIMEOUT,
+            address_filter: AddressFilter::default(),
         }
     }
 }
@@ -39,7 +54,7 @@ pub fn genesis_hash() -> Cid {
 
 pub fn generate_kad_config(peer_id: &PeerId) -> (KademliaConfig, MemoryStore) {
     let mut cfg = KademliaConfig::default();
-    cfg.set_query_timeout(std::time::Duration::from_secs(5 * 60));
+    cfg.set_query_timeout(Duration::from_secs(5 * 60));
     let store = MemoryStore::new(peer_id.clone());
     (cfg, store)
 }
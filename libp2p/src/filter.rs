@@ -0,0 +1,292 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::error::Error;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use futures::Async;
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    IntoProtocolsHandler, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+    ProtocolsHandler,
+};
+use libp2p::{Multiaddr, PeerId};
+use log::debug;
+
+/// The policy deciding which addresses may be dialed.
+///
+/// Blocked addresses are silently skipped, and the next candidate address of the peer is tried.
+#[derive(Clone)]
+pub enum AddressFilter {
+    /// Dial any address.
+    AllowAll,
+    /// Don't dial the private, loopback, link-local or unspecified IP addresses.
+    /// The DNS addresses are allowed.
+    DenyPrivate,
+    /// Only dial the publicly routable IP addresses.
+    AllowOnlyPublic,
+    /// Dial the addresses accepted by the predicate.
+    Custom(Arc<dyn Fn(&Multiaddr) -> bool + Send + Sync>),
+}
+
+impl Default for AddressFilter {
+    fn default() -> Self {
+        AddressFilter::AllowAll
+    }
+}
+
+impl fmt::Debug for AddressFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFilter::AllowAll => f.write_str("AllowAll"),
+            AddressFilter::DenyPrivate => f.write_str("DenyPrivate"),
+            AddressFilter::AllowOnlyPublic => f.write_str("AllowOnlyPublic"),
+            AddressFilter::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl AddressFilter {
+    /// Create a filter with the custom predicate.
+    pub fn custom<F>(predicate: F) -> Self
+    where
+        F: Fn(&Multiaddr) -> bool + Send + Sync + 'static,
+    {
+        AddressFilter::Custom(Arc::new(predicate))
+    }
+
+    /// Return whether the address may be dialed.
+    pub fn is_allowed(&self, addr: &Multiaddr) -> bool {
+        match self {
+            AddressFilter::AllowAll => true,
+            AddressFilter::DenyPrivate => match addr.iter().next() {
+                Some(Protocol::Ip4(ip)) => !is_private_ipv4(&ip),
+                Some(Protocol::Ip6(ip)) => !is_private_ipv6(&ip),
+                _ => true,
+            },
+            AddressFilter::AllowOnlyPublic => match addr.iter().next() {
+                Some(Protocol::Ip4(ip)) => is_public_ipv4(&ip),
+                Some(Protocol::Ip6(ip)) => is_public_ipv6(&ip),
+                _ => false,
+            },
+            AddressFilter::Custom(predicate) => predicate(addr),
+        }
+    }
+
+    /// Return the addresses that may be dialed, in the original order.
+    pub fn filter<'a, I>(&'a self, addrs: I) -> impl Iterator<Item = Multiaddr> + 'a
+    where
+        I: IntoIterator<Item = Multiaddr>,
+        I::IntoIter: 'a,
+    {
+        addrs.into_iter().filter(move |addr| self.is_allowed(addr))
+    }
+}
+
+/// The network behaviour whose addresses are checked by the address filter before dialing.
+///
+/// The swarm dials a peer with the addresses known by the behaviours, e.g. the addresses learned
+/// by kademlia from the other peers, so the blocked ones are removed here rather than at every
+/// place the addresses are discovered.
+pub struct FilteredBehaviour<TBehaviour> {
+    inner: TBehaviour,
+    filter: AddressFilter,
+}
+
+impl<TBehaviour> FilteredBehaviour<TBehaviour> {
+    /// Create the behaviour dialing only the addresses of `inner` allowed by the `filter`.
+    pub fn new(inner: TBehaviour, filter: AddressFilter) -> Self {
+        Self { inner, filter }
+    }
+}
+
+impl<TBehaviour> Deref for FilteredBehaviour<TBehaviour> {
+    type Target = TBehaviour;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<TBehaviour> DerefMut for FilteredBehaviour<TBehaviour> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<TBehaviour: NetworkBehaviour> NetworkBehaviour for FilteredBehaviour<TBehaviour> {
+    type ProtocolsHandler = TBehaviour::ProtocolsHandler;
+    type OutEvent = TBehaviour::OutEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.inner.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let addrs = self.inner.addresses_of_peer(peer_id);
+        self.filter.filter(addrs).collect()
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        self.inner.inject_connected(peer_id, endpoint)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+        self.inner.inject_disconnected(peer_id, endpoint)
+    }
+
+    fn inject_replaced(
+        &mut self,
+        peer_id: PeerId,
+        closed_endpoint: ConnectedPoint,
+        new_endpoint: ConnectedPoint,
+    ) {
+        self.inner
+            .inject_replaced(peer_id, closed_endpoint, new_endpoint)
+    }
+
+    fn inject_node_event(
+        &mut self,
+        peer_id: PeerId,
+        event: <<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent,
+    ) {
+        self.inner.inject_node_event(peer_id, event)
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn Error,
+    ) {
+        self.inner.inject_addr_reach_failure(peer_id, addr, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.inner.inject_dial_failure(peer_id)
+    }
+
+    fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
+        self.inner.inject_new_listen_addr(addr)
+    }
+
+    fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
+        self.inner.inject_expired_listen_addr(addr)
+    }
+
+    fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
+        self.inner.inject_new_external_addr(addr)
+    }
+
+    fn poll(
+        &mut self,
+        params: &mut impl PollParameters,
+    ) -> Async<
+        NetworkBehaviourAction<
+            <<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    >{
+        loop {
+            match self.inner.poll(params) {
+                Async::Ready(NetworkBehaviourAction::DialAddress { address })
+                    if !self.filter.is_allowed(&address) =>
+                {
+                    debug!("Skip dialing the filtered address {}", address);
+                }
+                action => return action,
+            }
+        }
+    }
+}
+
+fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
+    ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    // shared address space, 100.64.0.0/10
+    let shared = octets[0] == 100 && (octets[1] & 0b1100_0000) == 0b0100_0000;
+    !(is_private_ipv4(ip)
+        || shared
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation())
+}
+
+fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
+    let segment = ip.segments()[0];
+    // unique local, fc00::/7
+    let unique_local = (segment & 0xfe00) == 0xfc00;
+    // unicast link-local, fe80::/10
+    let link_local = (segment & 0xffc0) == 0xfe80;
+    ip.is_loopback() || ip.is_unspecified() || unique_local || link_local
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    // documentation, 2001:db8::/32
+    let documentation = ip.segments()[0] == 0x2001 && ip.segments()[1] == 0xdb8;
+    !(is_private_ipv6(ip) || ip.is_multicast() || documentation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_presets() {
+        let loopback = addr("/ip4/127.0.0.1/tcp/1347");
+        let private = addr("/ip4/192.168.1.2/tcp/1347");
+        let private_v6 = addr("/ip6/fd00::1/tcp/1347");
+        let public = addr("/ip4/8.8.8.8/tcp/1347");
+        let public_v6 = addr("/ip6/2606:4700::1111/tcp/1347");
+        let dns = addr("/dns4/bootstrap.filecoin.io/tcp/1347");
+
+        let all = AddressFilter::default();
+        for addr in &[&loopback, &private, &private_v6, &public, &public_v6, &dns] {
+            assert!(all.is_allowed(addr), "{}", addr);
+        }
+
+        let deny_private = AddressFilter::DenyPrivate;
+        assert!(!deny_private.is_allowed(&loopback));
+        assert!(!deny_private.is_allowed(&private));
+        assert!(!deny_private.is_allowed(&private_v6));
+        assert!(deny_private.is_allowed(&public));
+        assert!(deny_private.is_allowed(&public_v6));
+        assert!(deny_private.is_allowed(&dns));
+
+        let only_public = AddressFilter::AllowOnlyPublic;
+        assert!(!only_public.is_allowed(&loopback));
+        assert!(!only_public.is_allowed(&addr("/ip4/100.64.0.1/tcp/1347")));
+        assert!(only_public.is_allowed(&public));
+        assert!(only_public.is_allowed(&public_v6));
+        assert!(!only_public.is_allowed(&dns));
+
+        let allowed = addr("/ip4/10.0.0.1/tcp/1347");
+        let whitelist = {
+            let allowed = allowed.clone();
+            AddressFilter::custom(move |addr| addr == &allowed)
+        };
+        assert!(whitelist.is_allowed(&allowed));
+        assert!(!whitelist.is_allowed(&public));
+    }
+
+    #[test]
+    fn test_dial_candidates_under_deny_private() {
+        let loopback = addr("/ip4/127.0.0.1/tcp/1347");
+        let public = addr("/ip4/1.2.3.4/tcp/1347");
+        let filter = AddressFilter::DenyPrivate;
+        let candidates = filter
+            .filter(vec![loopback, public.clone()])
+            .collect::<Vec<_>>();
+        assert_eq!(candidates, vec![public]);
+    }
+}
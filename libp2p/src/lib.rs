@@ -2,10 +2,14 @@
 
 pub mod behaviour;
 pub mod config;
+pub mod filter;
+pub mod peer_store;
 pub mod rpc;
 pub mod service;
 
 pub use config::Libp2pConfig;
+pub use filter::AddressFilter;
+pub use peer_store::{PeerMetadata, PeerMetadataStore};
 
 // Reexport for avoiding the multiple version issues.
 pub use libp2p::gossipsub::{MessageId, TopicHash};
@@ -0,0 +1,203 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ipfs_datastore::{DataStore, DataStoreError, DataStoreRead, DataStoreWrite, Key, PrefixScan};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+const METADATA_PREFIX: &str = "/peers/metadata";
+
+/// The metadata of a peer, learned from the identify protocol or set by the user.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerMetadata {
+    /// The agent version reported by the peer.
+    pub agent_version: String,
+    /// The protocols supported by the peer.
+    pub protocols: Vec<String>,
+    /// The unix timestamp in seconds when the peer was first seen.
+    pub first_seen: u64,
+    /// The unix timestamp in seconds when the peer was last seen.
+    pub last_seen: u64,
+    /// The tag of the peer set by the user.
+    pub tag: Option<String>,
+}
+
+/// The error type of the peer metadata store.
+#[derive(Debug)]
+pub enum PeerStoreError {
+    /// The stored data cannot be decoded.
+    Decode(Key, String),
+    /// The underlying datastore error.
+    DataStore(DataStoreError),
+}
+
+impl fmt::Display for PeerStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerStoreError::Decode(key, err) => {
+                write!(f, "invalid data stored under {}: {}", key, err)
+            }
+            PeerStoreError::DataStore(err) => write!(f, "datastore error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PeerStoreError {}
+
+impl From<DataStoreError> for PeerStoreError {
+    fn from(err: DataStoreError) -> Self {
+        PeerStoreError::DataStore(err)
+    }
+}
+
+/// PeerMetadataStore persists the metadata of the peers keyed by `PeerId`.
+///
+/// The metadata of a peer is stored CBOR encoded under `/peers/metadata/<base58 peer id>`.
+#[derive(Clone)]
+pub struct PeerMetadataStore<DS: DataStore> {
+    datastore: DS,
+}
+
+impl<DS: DataStore> PeerMetadataStore<DS> {
+    /// Create a peer metadata store backed by the `datastore`.
+    pub fn new(datastore: DS) -> Self {
+        Self { datastore }
+    }
+
+    /// Return the metadata of the peer, `None` if the peer is unknown.
+    pub fn get(&self, peer_id: &PeerId) -> Result<Option<PeerMetadata>, PeerStoreError> {
+        let key = peer_key(peer_id);
+        match self.datastore.get(&key) {
+            Ok(bytes) => decode(&key, &bytes).map(Some),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Store the metadata of the peer, replacing the previous one.
+    pub fn set(&mut self, peer_id: &PeerId, metadata: &PeerMetadata) -> Result<(), PeerStoreError> {
+        let bytes = serde_cbor::to_vec(metadata)
+            .expect("CBOR serialization of PeerMetadata shouldn't be failed");
+        Ok(self.datastore.put(peer_key(peer_id), bytes)?)
+    }
+
+    /// Set the user tag of the peer, the peer is recorded if it's unknown.
+    pub fn set_tag(&mut self, peer_id: &PeerId, tag: Option<String>) -> Result<(), PeerStoreError> {
+        let mut metadata = self.get(peer_id)?.unwrap_or_default();
+        metadata.tag = tag;
+        self.set(peer_id, &metadata)
+    }
+
+    /// Return all the peers with their metadata.
+    pub fn list(&self) -> Result<Vec<(PeerId, PeerMetadata)>, PeerStoreError> {
+        let mut peers = Vec::new();
+        for key in self
+            .datastore
+            .keys_with_prefix(&Key::new(METADATA_PREFIX))?
+        {
+            let peer_id = match key.name().parse::<PeerId>() {
+                Ok(peer_id) => peer_id,
+                Err(_) => return Err(PeerStoreError::Decode(key, "bad peer id".into())),
+            };
+            let metadata = decode(&key, &self.datastore.get(&key)?)?;
+            peers.push((peer_id, metadata));
+        }
+        Ok(peers)
+    }
+
+    /// Record the agent version and protocols of the identified peer seen at `now`,
+    /// the first seen timestamp and the user tag are kept.
+    pub fn record_identify_at(
+        &mut self,
+        peer_id: &PeerId,
+        agent_version: &str,
+        protocols: &[String],
+        now: u64,
+    ) -> Result<(), PeerStoreError> {
+        let mut metadata = self.get(peer_id)?.unwrap_or(PeerMetadata {
+            first_seen: now,
+            ..Default::default()
+        });
+        metadata.agent_version = agent_version.to_string();
+        metadata.protocols = protocols.to_vec();
+        metadata.last_seen = now;
+        self.set(peer_id, &metadata)
+    }
+}
+
+/// The recorder of the metadata of the identified peers, used by the behaviour.
+pub trait PeerMetadataRecorder: Send {
+    /// Record the agent version and protocols of the identified peer.
+    fn record_identify(
+        &mut self,
+        peer_id: &PeerId,
+        agent_version: &str,
+        protocols: &[String],
+    ) -> Result<(), PeerStoreError>;
+}
+
+impl<DS: DataStore + Send> PeerMetadataRecorder for PeerMetadataStore<DS> {
+    fn record_identify(
+        &mut self,
+        peer_id: &PeerId,
+        agent_version: &str,
+        protocols: &[String],
+    ) -> Result<(), PeerStoreError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        self.record_identify_at(peer_id, agent_version, protocols, now)
+    }
+}
+
+fn decode(key: &Key, bytes: &[u8]) -> Result<PeerMetadata, PeerStoreError> {
+    serde_cbor::from_slice(bytes)
+        .map_err(|err| PeerStoreError::Decode(key.clone(), err.to_string()))
+}
+
+// The base58 encoding of the peer id is stable and can be parsed back.
+fn peer_key(peer_id: &PeerId) -> Key {
+    Key::new(format!("{}/{}", METADATA_PREFIX, peer_id.to_base58()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs_datastore::{MapDataStore, SyncDataStore};
+
+    use super::*;
+
+    #[test]
+    fn test_peer_metadata_round_trip() {
+        let datastore = SyncDataStore::new(MapDataStore::new());
+        let mut store = PeerMetadataStore::new(datastore.clone());
+        let peer_id = PeerId::random();
+        let protocols = vec!["/ipfs/id/1.0.0".to_string(), "/meshsub/1.0.0".to_string()];
+
+        store
+            .record_identify_at(&peer_id, "lotus/0.4.1", &protocols, 100)
+            .unwrap();
+        store.set_tag(&peer_id, Some("bootstrap".into())).unwrap();
+        store
+            .record_identify_at(&peer_id, "lotus/0.4.2", &protocols[..1], 200)
+            .unwrap();
+
+        // reload from the datastore.
+        let store = PeerMetadataStore::new(datastore);
+        let expected = PeerMetadata {
+            agent_version: "lotus/0.4.2".into(),
+            protocols: protocols[..1].to_vec(),
+            first_seen: 100,
+            last_seen: 200,
+            tag: Some("bootstrap".into()),
+        };
+        assert_eq!(store.get(&peer_id).unwrap(), Some(expected.clone()));
+        assert_eq!(store.list().unwrap(), vec![(peer_id.clone(), expected)]);
+        assert_eq!(store.get(&PeerId::random()).unwrap(), None);
+
+        let key = peer_key(&peer_id);
+        assert_eq!(key.name().parse::<PeerId>().unwrap(), peer_id);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! Serving the `BlockSyncRequest` with the locally available blocks.
+
+use cid::Cid;
+use plum_block::BlockHeader;
+
+use crate::rpc::methods::{BlockSyncRequest, BlockSyncResponse, BlockSyncStatus};
+
+/// The default cap of the total size of the block headers in a single response, in bytes.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+/// BlockProvider looks up the block headers served by the `BlockSyncResponder`.
+pub trait BlockProvider {
+    /// Return the block header of the `cid`, or `None` if it's not available locally.
+    fn get_header(&self, cid: &Cid) -> Option<BlockHeader>;
+}
+
+/// BlockSyncResponder serves the `BlockSyncRequest`, walking from the start tipset
+/// towards the genesis.
+///
+/// Rather than failing the whole request, it returns the leading tipsets it has up to the
+/// response size cap, and marks the response as `Partial` so that the requester can
+/// re-request the missing tail (see `BlockSyncResponse::remaining`).
+pub struct BlockSyncResponder<P: BlockProvider> {
+    provider: P,
+    max_response_size: usize,
+}
+
+impl<P: BlockProvider> BlockSyncResponder<P> {
+    /// Create a new responder with the default response size cap.
+    pub fn new(provider: P) -> Self {
+        Self::with_max_response_size(provider, DEFAULT_MAX_RESPONSE_SIZE)
+    }
+
+    /// Create a new responder with the given response size cap.
+    pub fn with_max_response_size(provider: P, max_response_size: usize) -> Self {
+        Self {
+            provider,
+            max_response_size,
+        }
+    }
+
+    /// Build the response of the `request`.
+    ///
+    /// At least one tipset is returned if available, even if it exceeds the size cap,
+    /// so that the requester always makes progress.
+    pub fn respond(&self, request: &BlockSyncRequest) -> BlockSyncResponse {
+        let mut tipsets = Vec::new();
+        let mut size = 0;
+        let mut cursor = request.start.clone();
+        let mut complete = true;
+
+        while (tipsets.len() as u64) < request.length && !cursor.is_empty() {
+            let headers = cursor
+                .iter()
+                .map(|cid| self.provider.get_header(cid))
+                .collect::<Option<Vec<_>>>();
+            let headers = match headers {
+                Some(headers) => headers,
+                None => {
+                    complete = false;
+                    break;
+                }
+            };
+
+            let encoded = headers
+                .iter()
+                .map(|header| {
+                    minicbor::to_vec(header)
+                        .expect("CBOR serialization of BlockHeader shouldn't be failed")
+                })
+                .collect::<Vec<_>>();
+            let tipset_size = encoded.iter().map(Vec::len).sum::<usize>();
+            if !tipsets.is_empty() && size + tipset_size > self.max_response_size {
+                complete = false;
+                break;
+            }
+            size += tipset_size;
+            tipsets.push(encoded);
+            cursor = headers[0].parents.clone();
+        }
+
+        let status = if complete {
+            BlockSyncStatus::Complete
+        } else if tipsets.is_empty() {
+            BlockSyncStatus::NotFound
+        } else {
+            BlockSyncStatus::Partial
+        };
+        BlockSyncResponse { tipsets, status }
+    }
+}
+
+impl BlockSyncResponse {
+    /// Return the request of the missing tail if this response to the `request` is partial.
+    pub fn remaining(&self, request: &BlockSyncRequest) -> Option<BlockSyncRequest> {
+        if self.status != BlockSyncStatus::Partial {
+            return None;
+        }
+        let last = self.tipsets.last()?.first()?;
+        let header = minicbor::decode::<BlockHeader>(last).ok()?;
+        Some(BlockSyncRequest {
+            start: header.parents,
+            length: request.length - self.tipsets.len() as u64,
+            options: request.options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use plum_address::Address;
+    use plum_block::{ElectionProof, Ticket};
+    use plum_crypto::Signature;
+
+    use super::*;
+
+    impl BlockProvider for HashMap<Cid, BlockHeader> {
+        fn get_header(&self, cid: &Cid) -> Option<BlockHeader> {
+            self.get(cid).cloned()
+        }
+    }
+
+    fn header(height: i64, parents: Vec<Cid>) -> BlockHeader {
+        let cid: Cid = "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+            .parse()
+            .unwrap();
+        BlockHeader {
+            miner: Address::new_id_addr(1000).unwrap(),
+            ticket: Ticket {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            election_proof: ElectionProof {
+                vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+            },
+            beacon_entries: vec![],
+            win_post_proof: vec![],
+            parents,
+            parent_message_receipts: cid.clone(),
+            bls_aggregate: Signature::new_bls("boo! im a signature"),
+            parent_weight: 0u64.into(),
+            messages: cid.clone(),
+            height,
+            parent_state_root: cid,
+            timestamp: 0u64,
+            block_sig: Signature::new_bls("boo! im a signature"),
+            fork_signaling: 0u64,
+        }
+    }
+
+    // Return the chain from the genesis to the height 9.
+    fn chain() -> Vec<BlockHeader> {
+        let mut chain: Vec<BlockHeader> = Vec::new();
+        for height in 0..10 {
+            let parents = chain.last().map(|h| vec![h.cid()]).unwrap_or_default();
+            chain.push(header(height, parents));
+        }
+        chain
+    }
+
+    #[test]
+    fn test_partial_response() {
+        let chain = chain();
+        // the responder only has the 6 most recent blocks.
+        let provider = chain[4..]
+            .iter()
+            .map(|h| (h.cid(), h.clone()))
+            .collect::<HashMap<_, _>>();
+        let responder = BlockSyncResponder::new(provider);
+
+        let request = BlockSyncRequest {
+            start: vec![chain[9].cid()],
+            length: 10,
+            options: 0,
+        };
+        let response = responder.respond(&request);
+        assert_eq!(response.status, BlockSyncStatus::Partial);
+        let heights = response
+            .tipsets
+            .iter()
+            .map(|tipset| minicbor::decode::<BlockHeader>(&tipset[0]).unwrap().height)
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![9, 8, 7, 6, 5, 4]);
+
+        let remaining = response.remaining(&request).unwrap();
+        assert_eq!(remaining.start, vec![chain[3].cid()]);
+        assert_eq!(remaining.length, 4);
+        let response = responder.respond(&remaining);
+        assert_eq!(response.status, BlockSyncStatus::NotFound);
+        assert!(response.tipsets.is_empty());
+    }
+
+    #[test]
+    fn test_response_size_cap() {
+        let chain = chain();
+        let provider = chain
+            .iter()
+            .map(|h| (h.cid(), h.clone()))
+            .collect::<HashMap<_, _>>();
+        let size = minicbor::to_vec(&chain[9]).unwrap().len();
+        let responder = BlockSyncResponder::with_max_response_size(provider, size * 3);
+
+        let request = BlockSyncRequest {
+            start: vec![chain[9].cid()],
+            length: 10,
+            options: 0,
+        };
+        let response = responder.respond(&request);
+        assert_eq!(response.status, BlockSyncStatus::Partial);
+        assert_eq!(response.tipsets.len(), 3);
+
+        // the whole chain down to the genesis.
+        let request = BlockSyncRequest {
+            start: vec![chain[2].cid()],
+            length: 10,
+            options: 0,
+        };
+        let response = responder.respond(&request);
+        assert_eq!(response.status, BlockSyncStatus::Complete);
+        assert_eq!(response.tipsets.len(), 3);
+        assert!(response.remaining(&request).is_none());
+    }
+}
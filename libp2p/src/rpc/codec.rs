@@ -3,24 +3,91 @@
 use libp2p::bytes::BytesMut;
 use tokio::codec::{Decoder, Encoder};
 
-use crate::rpc::protocol::RPCError;
+use crate::rpc::methods::{HelloMessage, LatencyMessage, RPCResponse};
+use crate::rpc::protocol::{ProtocolVersion, RPCError};
 use crate::rpc::{RPCErrorResponse, RPCRequest};
 
-fn encode_to<T: serde::Serialize>(item: T, dst: &mut BytesMut) -> Result<(), serde_cbor::Error> {
+/// The wire format of the messages on a negotiated protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// The plum RPC messages in the negotiated version.
+    Versioned(ProtocolVersion),
+    /// The CBOR tuples of the Lotus hello protocol.
+    Hello,
+}
+
+fn encode_to<T: serde::Serialize>(
+    version: ProtocolVersion,
+    item: T,
+    dst: &mut BytesMut,
+) -> Result<(), RPCError> {
     let encoded = serde_cbor::to_vec(&item)?;
     dst.clear();
+    match version {
+        ProtocolVersion::V1 => {}
+        // the V2 messages are self-describing, prefixed with the version tag.
+        ProtocolVersion::V2 => dst.extend_from_slice(&[version.tag()]),
+    }
+    dst.extend_from_slice(&encoded);
+    Ok(())
+}
+
+fn decode_from<T: serde::de::DeserializeOwned>(
+    version: ProtocolVersion,
+    src: &BytesMut,
+) -> Result<T, RPCError> {
+    let payload = match version {
+        ProtocolVersion::V1 => &src[..],
+        ProtocolVersion::V2 => match src.first() {
+            Some(tag) if *tag == version.tag() => &src[1..],
+            tag => {
+                return Err(RPCError::ProtocolMismatch(format!(
+                    "expected the message of version {}, got the tag {:?}",
+                    version, tag
+                )))
+            }
+        },
+    };
+    Ok(serde_cbor::from_slice(payload)?)
+}
+
+fn encode_tuple<T: minicbor::Encode>(item: &T, dst: &mut BytesMut) -> Result<(), RPCError> {
+    let encoded = minicbor::to_vec(item)
+        .map_err(|err| RPCError::Custom(format!("Error while encoding cbor: {}", err)))?;
+    dst.clear();
     dst.extend_from_slice(&encoded);
     Ok(())
 }
 
-pub struct InboundCodec;
+fn decode_tuple<'b, T: minicbor::Decode<'b>>(src: &'b BytesMut) -> Result<T, RPCError> {
+    minicbor::decode(&src[..])
+        .map_err(|err| RPCError::Custom(format!("Error while decoding cbor: {}", err)))
+}
+
+pub struct InboundCodec {
+    encoding: Encoding,
+}
+
+impl InboundCodec {
+    pub fn new(encoding: Encoding) -> Self {
+        Self { encoding }
+    }
+}
 
 impl Encoder for InboundCodec {
     type Item = RPCErrorResponse;
     type Error = RPCError;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        encode_to(item, dst).map_err(Into::into)
+        match (self.encoding, item) {
+            (Encoding::Versioned(version), item) => encode_to(version, item, dst),
+            (Encoding::Hello, RPCErrorResponse::Success(RPCResponse::Latency(latency))) => {
+                encode_tuple(&latency, dst)
+            }
+            (Encoding::Hello, _) => Err(RPCError::InvalidProtocol(
+                "only the latency is responded on the hello protocol",
+            )),
+        }
     }
 }
 
@@ -29,19 +96,35 @@ impl Decoder for InboundCodec {
     type Error = RPCError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let value: Self::Item = serde_cbor::from_slice(src)?;
-        Ok(Some(value))
+        match self.encoding {
+            Encoding::Versioned(version) => Ok(Some(decode_from(version, src)?)),
+            Encoding::Hello => Ok(Some(RPCRequest::Hello(decode_tuple::<HelloMessage>(src)?))),
+        }
     }
 }
 
-pub struct OutboundCodec;
+pub struct OutboundCodec {
+    encoding: Encoding,
+}
+
+impl OutboundCodec {
+    pub fn new(encoding: Encoding) -> Self {
+        Self { encoding }
+    }
+}
 
 impl Encoder for OutboundCodec {
     type Item = RPCRequest;
     type Error = RPCError;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        encode_to(item, dst).map_err(Into::into)
+        match (self.encoding, item) {
+            (Encoding::Versioned(version), item) => encode_to(version, item, dst),
+            (Encoding::Hello, RPCRequest::Hello(hello)) => encode_tuple(&hello, dst),
+            (Encoding::Hello, _) => Err(RPCError::InvalidProtocol(
+                "only the hello is requested on the hello protocol",
+            )),
+        }
     }
 }
 
@@ -50,7 +133,113 @@ impl Decoder for OutboundCodec {
     type Error = RPCError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let value: Self::Item = serde_cbor::from_slice(src)?;
-        Ok(Some(value))
+        match self.encoding {
+            Encoding::Versioned(version) => Ok(Some(decode_from(version, src)?)),
+            Encoding::Hello => {
+                let latency = decode_tuple::<LatencyMessage>(src)?;
+                Ok(Some(RPCErrorResponse::Success(RPCResponse::Latency(
+                    latency,
+                ))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::genesis_hash;
+    use crate::rpc::methods::BlockSyncRequest;
+
+    fn request() -> RPCRequest {
+        RPCRequest::BlockSyncRequest(BlockSyncRequest {
+            start: vec![],
+            length: 10,
+            options: 0,
+        })
+    }
+
+    #[test]
+    fn test_versioned_codec() {
+        for version in &[ProtocolVersion::V1, ProtocolVersion::V2] {
+            let mut buf = BytesMut::new();
+            OutboundCodec::new(Encoding::Versioned(*version))
+                .encode(request(), &mut buf)
+                .unwrap();
+            let decoded = InboundCodec::new(Encoding::Versioned(*version))
+                .decode(&mut buf)
+                .unwrap();
+            assert_eq!(decoded, Some(request()));
+        }
+
+        // the V1 message is rejected by the V2 codec rather than misparsed.
+        let mut buf = BytesMut::new();
+        OutboundCodec::new(Encoding::Versioned(ProtocolVersion::V1))
+            .encode(request(), &mut buf)
+            .unwrap();
+        match InboundCodec::new(Encoding::Versioned(ProtocolVersion::V2)).decode(&mut buf) {
+            Err(RPCError::ProtocolMismatch(_)) => {}
+            other => panic!("expected the protocol mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hello_codec() {
+        let hello = HelloMessage {
+            heaviest_tip_set: vec![genesis_hash(), genesis_hash()],
+            heaviest_tip_set_height: 42,
+            heaviest_tip_set_weight: u128::max_value().into(),
+            genesis_hash: genesis_hash(),
+        };
+        let mut buf = BytesMut::new();
+        OutboundCodec::new(Encoding::Hello)
+            .encode(RPCRequest::Hello(hello.clone()), &mut buf)
+            .unwrap();
+        let decoded = InboundCodec::new(Encoding::Hello).decode(&mut buf).unwrap();
+        assert_eq!(decoded, Some(RPCRequest::Hello(hello)));
+
+        // the hello is the Lotus tuple `[tipset, height, weight, genesis]`, the zero weight is
+        // the empty bytes of the BigInt.
+        let hello = HelloMessage {
+            heaviest_tip_set: vec![],
+            heaviest_tip_set_height: 1,
+            heaviest_tip_set_weight: 0u64.into(),
+            genesis_hash: genesis_hash(),
+        };
+        let mut buf = BytesMut::new();
+        OutboundCodec::new(Encoding::Hello)
+            .encode(RPCRequest::Hello(hello), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..6], &[0x84, 0x80, 0x01, 0x40, 0xd8, 0x2a]);
+
+        // the responder answers with the latency tuple `[t_arrival, t_sent]`.
+        let mut buf = BytesMut::new();
+        let latency = LatencyMessage {
+            t_arrival: 1,
+            t_sent: 2,
+        };
+        InboundCodec::new(Encoding::Hello)
+            .encode(
+                RPCErrorResponse::Success(RPCResponse::Latency(latency.clone())),
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(&buf[..], &[0x82, 0x01, 0x02]);
+        match OutboundCodec::new(Encoding::Hello)
+            .decode(&mut buf)
+            .unwrap()
+        {
+            Some(RPCErrorResponse::Success(RPCResponse::Latency(decoded))) => {
+                assert_eq!(decoded, latency)
+            }
+            other => panic!("expected the latency response, got {:?}", other),
+        }
+
+        // the other messages are not spoken on the hello protocol.
+        let mut buf = BytesMut::new();
+        match OutboundCodec::new(Encoding::Hello).encode(request(), &mut buf) {
+            Err(RPCError::InvalidProtocol(_)) => {}
+            other => panic!("expected the invalid protocol, got {:?}", other),
+        }
     }
 }
@@ -6,7 +6,7 @@
 use core::marker::PhantomData;
 use fnv::FnvHashMap;
 use futures::prelude::*;
-use libp2p::core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeError};
+use libp2p::core::upgrade::{InboundUpgrade, NegotiationError, OutboundUpgrade, UpgradeError};
 use libp2p::swarm::protocols_handler::{
     KeepAlive, ProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr, SubstreamProtocol,
 };
@@ -18,7 +18,7 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::timer::{delay_queue, DelayQueue};
 
 use super::methods::{ErrorMessage, RPCErrorResponse, RequestId, ResponseTermination};
-use super::protocol::{RPCError, RPCProtocol, RPCRequest};
+use super::protocol::{RPCError, RPCProtocol, RPCRequest, VersionedRequest};
 use super::RPCEvent;
 use crate::rpc::protocol::{InboundFramed, OutboundFramed};
 
@@ -251,7 +251,7 @@ where
     type Error = ProtocolsHandlerUpgrErr<RPCError>;
     type Substream = TSubstream;
     type InboundProtocol = RPCProtocol;
-    type OutboundProtocol = RPCRequest;
+    type OutboundProtocol = VersionedRequest;
     type OutboundOpenInfo = RPCEvent; // Keep track of the id and the request
 
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
@@ -292,7 +292,7 @@ where
 
     fn inject_fully_negotiated_outbound(
         &mut self,
-        out: <RPCRequest as OutboundUpgrade<TSubstream>>::Output,
+        out: <VersionedRequest as OutboundUpgrade<TSubstream>>::Output,
         rpc_event: Self::OutboundOpenInfo,
     ) {
         self.dial_negotiated -= 1;
@@ -476,6 +476,18 @@ where
                         RPCEvent::Error(request_id, err),
                     )));
                 }
+                ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(
+                    NegotiationError::Failed,
+                )) => {
+                    // The peer speaks none of our protocol versions.
+                    debug!("Peer doesn't support any of the RPC protocol versions");
+                    return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                        RPCEvent::Error(
+                            request_id,
+                            RPCError::ProtocolMismatch("no common protocol version".into()),
+                        ),
+                    )));
+                }
                 ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)) => {
                     // Error during negotiation
                     return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
@@ -730,7 +742,10 @@ where
             if let RPCEvent::Request(id, req) = rpc_event {
                 return Ok(Async::Ready(
                     ProtocolsHandlerEvent::OutboundSubstreamRequest {
-                        protocol: SubstreamProtocol::new(req.clone()),
+                        protocol: SubstreamProtocol::new(VersionedRequest::new(
+                            req.clone(),
+                            self.listen_protocol.upgrade().versions().to_vec(),
+                        )),
                         info: RPCEvent::Request(id, req),
                     },
                 ));
@@ -2,15 +2,91 @@
 
 //! Available RPC methods types and ids.
 
+use minicbor::{decode, encode, Decoder, Encoder};
 use serde::{Deserialize, Serialize};
 
+use plum_bigint::{bigint_json, BigInt, BigIntRefWrapper, BigIntWrapper};
+use plum_types::ChainEpoch;
+
 /* Request/Response data structures for RPC methods */
 
 /* Requests */
 
 pub type RequestId = usize;
 
-// TODO: HelloMessage, https://github.com/filecoin-project/lotus/blob/e7a1be4dde/node/hello/hello.go#L30
+/// The HELLO handshake message, carrying the heaviest tipset of the node.
+///
+/// See https://github.com/filecoin-project/lotus/blob/e7a1be4dde/node/hello/hello.go#L30
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HelloMessage {
+    pub heaviest_tip_set: Vec<cid::Cid>,
+    pub heaviest_tip_set_height: ChainEpoch,
+    #[serde(with = "bigint_json")]
+    pub heaviest_tip_set_weight: BigInt,
+    pub genesis_hash: cid::Cid,
+}
+
+// Implement CBOR serialization for HelloMessage, the tuple of the Lotus hello protocol.
+impl encode::Encode for HelloMessage {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(4)?
+            .encode(&self.heaviest_tip_set)?
+            .i64(self.heaviest_tip_set_height)?
+            .encode(BigIntRefWrapper::from(&self.heaviest_tip_set_weight))?
+            .encode(&self.genesis_hash)?
+            .ok()
+    }
+}
+
+// Implement CBOR deserialization for HelloMessage.
+impl<'b> decode::Decode<'b> for HelloMessage {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        if d.array()? != Some(4) {
+            return Err(decode::Error::Message(
+                "expected the hello tuple of 4 fields",
+            ));
+        }
+        Ok(HelloMessage {
+            heaviest_tip_set: d.decode::<Vec<cid::Cid>>()?,
+            heaviest_tip_set_height: d.i64()?,
+            heaviest_tip_set_weight: d.decode::<BigIntWrapper>()?.into_inner(),
+            genesis_hash: d.decode::<cid::Cid>()?,
+        })
+    }
+}
+
+/// The response to the HELLO message, carrying the arrival time of the hello and the sending
+/// time of the response in unix nanoseconds, for the dialer to estimate the latency.
+///
+/// See https://github.com/filecoin-project/lotus/blob/e7a1be4dde/node/hello/hello.go#L40
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LatencyMessage {
+    pub t_arrival: i64,
+    pub t_sent: i64,
+}
+
+// Implement CBOR serialization for LatencyMessage.
+impl encode::Encode for LatencyMessage {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        e.array(2)?.i64(self.t_arrival)?.i64(self.t_sent)?.ok()
+    }
+}
+
+// Implement CBOR deserialization for LatencyMessage.
+impl<'b> decode::Decode<'b> for LatencyMessage {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        if d.array()? != Some(2) {
+            return Err(decode::Error::Message(
+                "expected the latency tuple of 2 fields",
+            ));
+        }
+        Ok(LatencyMessage {
+            t_arrival: d.i64()?,
+            t_sent: d.i64()?,
+        })
+    }
+}
+
 /// The STATUS request/response handshake message.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct StatusMessage {
@@ -65,18 +141,45 @@ pub struct BlockSyncRequest {
     pub options: u64,
 }
 
+/* Responses */
+
+/// The completeness of a block sync response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockSyncStatus {
+    /// All the requested blocks are returned.
+    Complete,
+    /// Only a leading part of the requested blocks is returned, because the responder
+    /// doesn't have the rest or the response size cap is reached.
+    Partial,
+    /// None of the requested blocks is found.
+    NotFound,
+}
+
+/// The blocks returned for a `BlockSyncRequest`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockSyncResponse {
+    /// The CBOR encoded block headers of each tipset, from the start of the request
+    /// towards the genesis.
+    pub tipsets: Vec<Vec<Vec<u8>>>,
+    /// The completeness of the response.
+    pub status: BlockSyncStatus,
+}
+
 /* RPC Handling and Grouping */
 // Collection of enums and structs used by the Codecs to encode/decode RPC messages
 
 // TODO: https://github.com/filecoin-project/lotus/blob/e7a1be4dde/chain/blocksync/blocksync.go#L67
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RPCResponse {
-    /// A HELLO message.
+    /// A STATUS message.
     Status(StatusMessage),
 
+    /// The response to the HELLO message.
+    Latency(LatencyMessage),
+
     /// A response to a get BLOCK_SYNC_REQUEST request. A None response signifies the end of the
     /// batch.
-    BlockSyncRequest(Vec<u8>),
+    BlockSyncRequest(BlockSyncResponse),
 }
 
 /// Indicates which response is being terminated by a stream termination response.
@@ -139,6 +242,7 @@ impl RPCErrorResponse {
         match self {
             RPCErrorResponse::Success(resp) => match resp {
                 RPCResponse::Status(_) => false,
+                RPCResponse::Latency(_) => false,
                 RPCResponse::BlockSyncRequest(_) => true,
             },
             RPCErrorResponse::InvalidRequest(_) => true,
@@ -181,11 +285,34 @@ impl std::fmt::Display for StatusMessage {
     }
 }
 
+impl std::fmt::Display for HelloMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Hello Message: heaviest_tip_set: {:?}, heaviest_tip_set_height: {}, heaviest_tip_set_weight: {}, genesis_hash: {}",
+            self.heaviest_tip_set,
+            self.heaviest_tip_set_height,
+            self.heaviest_tip_set_weight,
+            self.genesis_hash
+        )
+    }
+}
+
 impl std::fmt::Display for RPCResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RPCResponse::Status(status) => write!(f, "{}", status),
-            RPCResponse::BlockSyncRequest(_) => write!(f, "<BlockSyncRequest>"),
+            RPCResponse::Latency(latency) => write!(
+                f,
+                "Latency Message: t_arrival: {}, t_sent: {}",
+                latency.t_arrival, latency.t_sent
+            ),
+            RPCResponse::BlockSyncRequest(resp) => write!(
+                f,
+                "<BlockSyncRequest>, tipsets: {}, status: {:?}",
+                resp.tipsets.len(),
+                resp.status
+            ),
         }
     }
 }
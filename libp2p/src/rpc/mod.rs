@@ -8,19 +8,29 @@ use libp2p::swarm::{
     SubstreamProtocol,
 };
 use libp2p::{Multiaddr, PeerId};
+use log::debug;
 use std::marker::PhantomData;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 
+mod blocksync;
 mod codec;
 mod handler;
 pub mod methods;
+mod pending;
 mod protocol;
 
+pub use blocksync::{BlockProvider, BlockSyncResponder, DEFAULT_MAX_RESPONSE_SIZE};
 pub use methods::{
-    ErrorMessage, RPCErrorResponse, RPCResponse, RequestId, ResponseTermination, StatusMessage,
+    BlockSyncRequest, BlockSyncResponse, BlockSyncStatus, ErrorMessage, HelloMessage,
+    LatencyMessage, RPCErrorResponse, RPCResponse, RequestId, ResponseTermination, StatusMessage,
 };
-pub use protocol::{RPCError, RPCProtocol, RPCRequest};
+pub use pending::RequestHandle;
+pub use protocol::{
+    ProtocolVersion, RPCError, RPCProtocol, RPCRequest, VersionedRequest, SUPPORTED_VERSIONS,
+};
+
+use pending::PendingRequests;
 
 /// The return type used in the behaviour and the resultant event from the protocols handler.
 #[derive(Debug)]
@@ -61,18 +71,66 @@ impl std::fmt::Display for RPCEvent {
 pub struct RPC<TSubstream> {
     /// Queue of events to processed.
     events: Vec<NetworkBehaviourAction<RPCEvent, RPCMessage>>,
+    /// The outbound block sync requests sent by `request_blocks`.
+    requests: PendingRequests,
+    /// The id of the next request sent by `request_blocks` or `say_hello`.
+    next_request_id: RequestId,
+    /// The protocol versions spoken with the peers, in the order of preference.
+    versions: Vec<ProtocolVersion>,
     /// Pins the generic substream.
     marker: PhantomData<TSubstream>,
 }
 
 impl<TSubstream> RPC<TSubstream> {
     pub fn new() -> Self {
+        Self::with_versions(SUPPORTED_VERSIONS.to_vec())
+    }
+
+    /// Create the RPC speaking the protocol `versions`, in the order of preference.
+    pub fn with_versions(versions: Vec<ProtocolVersion>) -> Self {
+        assert!(
+            !versions.is_empty(),
+            "at least one protocol version is required"
+        );
         Self {
             events: Vec::new(),
+            requests: PendingRequests::default(),
+            next_request_id: 0,
+            versions,
             marker: PhantomData,
         }
     }
 
+    /// Submits a block sync request, return the handle for cancelling it.
+    ///
+    /// The peer must be connected for this to succeed.
+    pub fn request_blocks(&mut self, peer_id: PeerId, request: BlockSyncRequest) -> RequestHandle {
+        let id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        let handle = self.requests.insert(id, peer_id.clone());
+        self.send_rpc(
+            peer_id,
+            RPCEvent::Request(id, RPCRequest::BlockSyncRequest(request)),
+        );
+        handle
+    }
+
+    /// Submits a hello request carrying the local heaviest tipset, the peer responds
+    /// with the latency.
+    ///
+    /// The peer must be connected for this to succeed.
+    pub fn say_hello(&mut self, peer_id: PeerId, hello: HelloMessage) -> RequestId {
+        let id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        self.send_rpc(peer_id, RPCEvent::Request(id, RPCRequest::Hello(hello)));
+        id
+    }
+
+    /// Return the number of the block sync requests awaiting the responses.
+    pub fn pending_requests(&self) -> usize {
+        self.requests.len()
+    }
+
     /// Submits an RPC request.
     ///
     /// The peer must be connected for this to succeed.
@@ -92,7 +150,10 @@ where
     type OutEvent = RPCMessage;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        RPCHandler::new(SubstreamProtocol::new(RPCProtocol), Duration::from_secs(30))
+        RPCHandler::new(
+            SubstreamProtocol::new(RPCProtocol::new(self.versions.clone())),
+            Duration::from_secs(30),
+        )
     }
 
     // handled by discovery
@@ -100,13 +161,12 @@ where
         Vec::new()
     }
 
-    fn inject_connected(&mut self, peer_id: PeerId, connected_point: ConnectedPoint) {
-        // if initialised the connection, report this upwards to send the HELLO request
-        if let ConnectedPoint::Dialer { .. } = connected_point {
-            self.events.push(NetworkBehaviourAction::GenerateEvent(
-                RPCMessage::PeerDialed(peer_id),
-            ));
-        }
+    fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+        // both ends of the connection say hello, the same as Lotus, so report this upwards
+        // to send the HELLO request.
+        self.events.push(NetworkBehaviourAction::GenerateEvent(
+            RPCMessage::PeerConnected(peer_id),
+        ));
     }
 
     fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
@@ -121,6 +181,15 @@ where
         source: PeerId,
         event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
+        if !self.requests.on_event(&event) {
+            debug!("Discard the {} of the cancelled request", event);
+            return;
+        }
+        if let RPCEvent::Error(_, RPCError::ProtocolMismatch(_)) = &event {
+            self.events.push(NetworkBehaviourAction::GenerateEvent(
+                RPCMessage::ProtocolMismatch(source.clone()),
+            ));
+        }
         // send the event to the user
         self.events
             .push(NetworkBehaviourAction::GenerateEvent(RPCMessage::RPC(
@@ -137,16 +206,38 @@ where
             Self::OutEvent,
         >,
     > {
-        if !self.events.is_empty() {
-            return Async::Ready(self.events.remove(0));
+        match self.next_event() {
+            Some(event) => Async::Ready(event),
+            None => Async::NotReady,
+        }
+    }
+}
+
+impl<TSubstream> RPC<TSubstream> {
+    fn next_event(&mut self) -> Option<NetworkBehaviourAction<RPCEvent, RPCMessage>> {
+        while !self.events.is_empty() {
+            let event = self.events.remove(0);
+            // drop the requests cancelled before being sent.
+            if let NetworkBehaviourAction::SendEvent {
+                event: RPCEvent::Request(id, _),
+                ..
+            } = &event
+            {
+                if self.requests.take_unsent(*id) {
+                    continue;
+                }
+            }
+            return Some(event);
         }
-        Async::NotReady
+        None
     }
 }
 
 /// Messages sent to the user from the RPC protocol.
 pub enum RPCMessage {
     RPC(PeerId, RPCEvent),
-    PeerDialed(PeerId),
+    PeerConnected(PeerId),
     PeerDisconnected(PeerId),
+    /// The peer speaks no compatible protocol version.
+    ProtocolMismatch(PeerId),
 }
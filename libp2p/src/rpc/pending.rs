@@ -0,0 +1,211 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! Tracking the outbound block sync requests, which can be cancelled through their handles.
+
+use std::sync::{Arc, Mutex};
+
+use fnv::{FnvHashMap, FnvHashSet};
+use libp2p::PeerId;
+
+use super::methods::{RPCErrorResponse, RequestId};
+use super::RPCEvent;
+
+#[derive(Default)]
+struct Requests {
+    // the requests awaiting the responses.
+    pending: FnvHashMap<RequestId, PeerId>,
+    // the cancelled requests, whose late responses are discarded.
+    cancelled: FnvHashSet<RequestId>,
+}
+
+/// The outbound requests shared between the `RPC` behaviour and the request handles.
+#[derive(Clone, Default)]
+pub(crate) struct PendingRequests {
+    inner: Arc<Mutex<Requests>>,
+}
+
+impl PendingRequests {
+    /// Start tracking the request `id` sent to `peer_id`.
+    pub(crate) fn insert(&self, id: RequestId, peer_id: PeerId) -> RequestHandle {
+        let mut requests = self.inner.lock().expect("lock shouldn't be poisoned");
+        requests.pending.insert(id, peer_id.clone());
+        RequestHandle {
+            id,
+            peer_id,
+            requests: self.clone(),
+        }
+    }
+
+    /// Return the number of the requests awaiting the responses.
+    pub(crate) fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .pending
+            .len()
+    }
+
+    /// Stop tracking the cancelled request `id` which is not sent yet, so that
+    /// no response will arrive for it.
+    ///
+    /// Return whether the request is cancelled.
+    pub(crate) fn take_unsent(&self, id: RequestId) -> bool {
+        self.inner
+            .lock()
+            .expect("lock shouldn't be poisoned")
+            .cancelled
+            .remove(&id)
+    }
+
+    /// Update the tracked requests with the `event` from the handler.
+    ///
+    /// Return whether the event should be reported, i.e. it doesn't belong to a cancelled request.
+    pub(crate) fn on_event(&self, event: &RPCEvent) -> bool {
+        let (id, terminal) = match event {
+            RPCEvent::Request(..) => return true,
+            RPCEvent::Response(id, response) => (*id, is_terminal(response)),
+            RPCEvent::Error(id, _) => (*id, true),
+        };
+        let mut requests = self.inner.lock().expect("lock shouldn't be poisoned");
+        if requests.cancelled.contains(&id) {
+            if terminal {
+                requests.cancelled.remove(&id);
+            }
+            return false;
+        }
+        if terminal {
+            requests.pending.remove(&id);
+        }
+        true
+    }
+
+    fn cancel(&self, id: RequestId) -> bool {
+        let mut requests = self.inner.lock().expect("lock shouldn't be poisoned");
+        if requests.pending.remove(&id).is_some() {
+            requests.cancelled.insert(id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Whether the response is the last one of the request.
+fn is_terminal(response: &RPCErrorResponse) -> bool {
+    response.is_error() || !response.multiple_responses()
+}
+
+/// The handle of an outbound block sync request, see `RPC::request_blocks`.
+pub struct RequestHandle {
+    id: RequestId,
+    peer_id: PeerId,
+    requests: PendingRequests,
+}
+
+impl RequestHandle {
+    /// Return the id of the request.
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// Return the peer the request is sent to.
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    /// Cancel the request.
+    ///
+    /// The request is dropped if it's not sent yet, and the responses arriving later are
+    /// discarded instead of being reported. Return false if the request is already completed
+    /// or cancelled.
+    pub fn cancel(&self) -> bool {
+        self.requests.cancel(self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction};
+    use tokio::net::TcpStream;
+
+    use super::super::methods::{
+        BlockSyncRequest, BlockSyncResponse, BlockSyncStatus, RPCResponse, ResponseTermination,
+    };
+    use super::super::{RPCMessage, RPC};
+    use super::*;
+
+    fn request() -> BlockSyncRequest {
+        BlockSyncRequest {
+            start: vec![],
+            length: 10,
+            options: 0,
+        }
+    }
+
+    fn response() -> RPCErrorResponse {
+        RPCErrorResponse::Success(RPCResponse::BlockSyncRequest(BlockSyncResponse {
+            tipsets: vec![],
+            status: BlockSyncStatus::Complete,
+        }))
+    }
+
+    fn termination() -> RPCErrorResponse {
+        RPCErrorResponse::StreamTermination(ResponseTermination::BlockSyncRequest)
+    }
+
+    #[test]
+    fn test_cancel_in_flight_request() {
+        let mut rpc = RPC::<TcpStream>::new();
+        let peer_id = PeerId::random();
+        let handle = rpc.request_blocks(peer_id.clone(), request());
+        assert_eq!(rpc.pending_requests(), 1);
+        // the request is sent, the slow responder hasn't responded yet.
+        assert!(rpc.next_event().is_some());
+
+        assert!(handle.cancel());
+        assert!(!handle.cancel());
+        assert_eq!(rpc.pending_requests(), 0);
+
+        // the late response is discarded.
+        rpc.inject_node_event(peer_id.clone(), RPCEvent::Response(handle.id(), response()));
+        rpc.inject_node_event(peer_id, RPCEvent::Response(handle.id(), termination()));
+        assert!(rpc.next_event().is_none());
+        assert!(rpc.requests.inner.lock().unwrap().cancelled.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_unsent_request() {
+        let mut rpc = RPC::<TcpStream>::new();
+        let peer_id = PeerId::random();
+        let cancelled = rpc.request_blocks(peer_id.clone(), request());
+        let handle = rpc.request_blocks(peer_id.clone(), request());
+        assert!(cancelled.cancel());
+
+        // the cancelled request is dropped before being sent.
+        match rpc.next_event() {
+            Some(NetworkBehaviourAction::SendEvent {
+                event: RPCEvent::Request(id, _),
+                ..
+            }) => assert_eq!(id, handle.id()),
+            _ => panic!("expected the request to be sent"),
+        }
+        assert!(rpc.next_event().is_none());
+        assert!(rpc.requests.inner.lock().unwrap().cancelled.is_empty());
+
+        // the response of the other request is still reported.
+        rpc.inject_node_event(peer_id.clone(), RPCEvent::Response(handle.id(), response()));
+        assert_eq!(rpc.pending_requests(), 1);
+        rpc.inject_node_event(peer_id, RPCEvent::Response(handle.id(), termination()));
+        assert_eq!(rpc.pending_requests(), 0);
+        let reported = std::iter::from_fn(|| rpc.next_event())
+            .filter(|event| match event {
+                NetworkBehaviourAction::GenerateEvent(RPCMessage::RPC(_, event)) => {
+                    event.id() == handle.id()
+                }
+                _ => false,
+            })
+            .count();
+        assert_eq!(reported, 2);
+        assert!(!handle.cancel());
+    }
+}
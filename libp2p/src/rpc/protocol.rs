@@ -6,6 +6,7 @@ use futures::{
 };
 use libp2p::core::{upgrade, InboundUpgrade, OutboundUpgrade, ProtocolName, UpgradeInfo};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io;
 use std::time::Duration;
 use tokio::codec::Framed;
@@ -17,7 +18,7 @@ use tokio_io_timeout::TimeoutStream;
 
 use super::methods::*;
 use crate::rpc::{
-    codec::{InboundCodec, OutboundCodec},
+    codec::{Encoding, InboundCodec, OutboundCodec},
     methods::ResponseTermination,
 };
 
@@ -26,6 +27,8 @@ use crate::rpc::{
 const MAX_RPC_SIZE: usize = 4_194_304; // 4M
 /// The protocol prefix the RPC protocol id.
 const PROTOCOL_PREFIX: &str = "/fil/plum/req";
+/// The protocol id of the Lotus hello protocol, which is not versioned by the plum RPC.
+pub const HELLO_PROTOCOL_ID: &str = "/fil/hello/1.0.0";
 /// Time allowed for the first byte of a request to arrive before we time out (Time To First Byte).
 const TTFB_TIMEOUT: u64 = 5;
 /// The number of seconds to wait for the first bytes of a request once a protocol has been
@@ -35,26 +38,115 @@ const REQUEST_TIMEOUT: u64 = 15;
 /// Protocol names to be used.
 /// The Status protocol name.
 pub const RPC_STATUS: &str = "status";
+/// The Hello protocol name.
+pub const RPC_HELLO: &str = "hello";
 /// The Goodbye protocol name.
 pub const RPC_GOODBYE: &str = "goodbye";
 /// The `BlockSyncRequest` protocol name.
 pub const RPC_BLOCK_SYNC_REQUEST: &str = "plum_block_sync_request";
 
 const CBOR: &str = "cbor";
+/// The encoding of the hello protocol, the CBOR tuples of Lotus.
+const TUPLE_CBOR: &str = "tuple-cbor";
+
+/// The version of the RPC protocols, carried in the protocol id.
+///
+/// The version is negotiated along with the protocol, and the codecs encode/decode the
+/// messages according to the negotiated version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProtocolVersion {
+    /// The messages are plain CBOR.
+    V1,
+    /// The messages are CBOR prefixed with the version tag, so that a message of
+    /// another version is rejected rather than misparsed.
+    V2,
+}
+
+/// The protocol versions supported by this crate, in the order of preference.
+pub const SUPPORTED_VERSIONS: [ProtocolVersion; 2] = [ProtocolVersion::V2, ProtocolVersion::V1];
+
+impl ProtocolVersion {
+    /// Return the version string in the protocol id.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "1",
+            ProtocolVersion::V2 => "2",
+        }
+    }
+
+    /// The tag prefixed to the self-describing messages.
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
 
+    /// Return the version negotiated between the dialer and the listener, which is the first
+    /// version proposed by the dialer that the listener supports, the same as multistream-select.
+    ///
+    /// `None` means the peers have no version in common.
+    pub fn negotiate(
+        dialer: &[ProtocolVersion],
+        listener: &[ProtocolVersion],
+    ) -> Option<ProtocolVersion> {
+        dialer
+            .iter()
+            .find(|version| listener.contains(version))
+            .copied()
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The inbound RPC protocols, accepting the configured versions.
 #[derive(Debug, Clone)]
-pub struct RPCProtocol;
+pub struct RPCProtocol {
+    versions: Vec<ProtocolVersion>,
+}
+
+impl Default for RPCProtocol {
+    fn default() -> Self {
+        Self::new(SUPPORTED_VERSIONS.to_vec())
+    }
+}
+
+impl RPCProtocol {
+    /// Create the protocols speaking the `versions`, in the order of preference.
+    pub fn new(versions: Vec<ProtocolVersion>) -> Self {
+        assert!(
+            !versions.is_empty(),
+            "at least one protocol version is required"
+        );
+        Self { versions }
+    }
+
+    /// Return the supported versions, in the order of preference.
+    pub fn versions(&self) -> &[ProtocolVersion] {
+        &self.versions
+    }
+}
 
 impl UpgradeInfo for RPCProtocol {
     type Info = ProtocolId;
     type InfoIter = Vec<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        vec![
-            ProtocolId::new(RPC_STATUS, "1", CBOR),
-            ProtocolId::new(RPC_GOODBYE, "1", CBOR),
-            ProtocolId::new(RPC_BLOCK_SYNC_REQUEST, "1", CBOR),
-        ]
+        self.versions
+            .iter()
+            .flat_map(|version| {
+                vec![
+                    ProtocolId::new(RPC_STATUS, *version, CBOR),
+                    ProtocolId::new(RPC_GOODBYE, *version, CBOR),
+                    ProtocolId::new(RPC_BLOCK_SYNC_REQUEST, *version, CBOR),
+                ]
+            })
+            .chain(std::iter::once(ProtocolId::hello()))
+            .collect()
     }
 }
 
@@ -65,7 +157,7 @@ pub struct ProtocolId {
     pub message_name: String,
 
     /// The version of the RPC.
-    pub version: String,
+    pub version: ProtocolVersion,
 
     /// The encoding of the RPC.
     pub encoding: String,
@@ -76,7 +168,7 @@ pub struct ProtocolId {
 
 /// An RPC protocol ID.
 impl ProtocolId {
-    pub fn new(message_name: &str, version: &str, encoding: &str) -> Self {
+    pub fn new(message_name: &str, version: ProtocolVersion, encoding: &str) -> Self {
         let protocol_id = format!(
             "{}/{}/{}/{}",
             PROTOCOL_PREFIX, message_name, version, encoding
@@ -84,11 +176,31 @@ impl ProtocolId {
 
         ProtocolId {
             message_name: message_name.into(),
-            version: version.into(),
+            version,
             encoding: encoding.into(),
             protocol_id,
         }
     }
+
+    /// The protocol id of the Lotus hello protocol.
+    ///
+    /// The hello protocol is not versioned by the plum RPC, the version is always `V1`.
+    pub fn hello() -> Self {
+        ProtocolId {
+            message_name: RPC_HELLO.into(),
+            version: ProtocolVersion::V1,
+            encoding: TUPLE_CBOR.into(),
+            protocol_id: HELLO_PROTOCOL_ID.into(),
+        }
+    }
+
+    // Return the codec of the encoding.
+    fn encoding(&self) -> Encoding {
+        match self.encoding.as_str() {
+            TUPLE_CBOR => Encoding::Hello,
+            _ => Encoding::Versioned(self.version),
+        }
+    }
 }
 
 impl ProtocolName for ProtocolId {
@@ -130,24 +242,20 @@ where
         socket: upgrade::Negotiated<TSocket>,
         protocol: ProtocolId,
     ) -> Self::Future {
-        match protocol.encoding.as_str() {
-            CBOR | _ => {
-                let mut timed_socket = TimeoutStream::new(socket);
-                timed_socket.set_read_timeout(Some(Duration::from_secs(TTFB_TIMEOUT)));
-                Framed::new(timed_socket, InboundCodec)
-                    .into_future()
-                    .timeout(Duration::from_secs(REQUEST_TIMEOUT))
-                    .map_err(RPCError::from as FnMapErr<TSocket>)
-                    .and_then({
-                        |(req, stream)| match req {
-                            Some(req) => futures::future::ok((req, stream)),
-                            None => futures::future::err(RPCError::Custom(
-                                "Stream terminated early".into(),
-                            )),
-                        }
-                    } as FnAndThen<TSocket>)
-            }
-        }
+        let mut timed_socket = TimeoutStream::new(socket);
+        timed_socket.set_read_timeout(Some(Duration::from_secs(TTFB_TIMEOUT)));
+        Framed::new(timed_socket, InboundCodec::new(protocol.encoding()))
+            .into_future()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .map_err(RPCError::from as FnMapErr<TSocket>)
+            .and_then({
+                |(req, stream)| match req {
+                    Some(req) => futures::future::ok((req, stream)),
+                    None => {
+                        futures::future::err(RPCError::Custom("Stream terminated early".into()))
+                    }
+                }
+            } as FnAndThen<TSocket>)
     }
 }
 
@@ -159,30 +267,32 @@ where
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RPCRequest {
     Status(StatusMessage),
+    Hello(HelloMessage),
     Goodbye(GoodbyeReason),
     BlockSyncRequest(BlockSyncRequest),
 }
 
-impl UpgradeInfo for RPCRequest {
-    type Info = ProtocolId;
-    type InfoIter = Vec<Self::Info>;
-
-    // add further protocols as we support more encodings/versions
-    fn protocol_info(&self) -> Self::InfoIter {
-        self.supported_protocols()
-    }
-}
-
 /// Implements the encoding per supported protocol for RPCRequest.
 impl RPCRequest {
-    pub fn supported_protocols(&self) -> Vec<ProtocolId> {
+    /// Return the protocols of the request in the given `versions`.
+    pub fn supported_protocols(&self, versions: &[ProtocolVersion]) -> Vec<ProtocolId> {
+        if let RPCRequest::Hello(_) = self {
+            return vec![ProtocolId::hello()];
+        }
+        // add more encodings when they are supported
+        versions
+            .iter()
+            .map(|version| ProtocolId::new(self.message_name(), *version, CBOR))
+            .collect()
+    }
+
+    /// Return the protocol name of the request.
+    pub fn message_name(&self) -> &'static str {
         match self {
-            // add more protocols when versions/encodings are supported
-            RPCRequest::Status(_) => vec![ProtocolId::new(RPC_STATUS, "1", CBOR)],
-            RPCRequest::Goodbye(_) => vec![ProtocolId::new(RPC_GOODBYE, "1", CBOR)],
-            RPCRequest::BlockSyncRequest(_) => {
-                vec![ProtocolId::new(RPC_BLOCK_SYNC_REQUEST, "1", CBOR)]
-            }
+            RPCRequest::Status(_) => RPC_STATUS,
+            RPCRequest::Hello(_) => RPC_HELLO,
+            RPCRequest::Goodbye(_) => RPC_GOODBYE,
+            RPCRequest::BlockSyncRequest(_) => RPC_BLOCK_SYNC_REQUEST,
         }
     }
 
@@ -193,6 +303,7 @@ impl RPCRequest {
     pub fn expect_response(&self) -> bool {
         match self {
             RPCRequest::Status(_) => true,
+            RPCRequest::Hello(_) => true,
             RPCRequest::Goodbye(_) => false,
             RPCRequest::BlockSyncRequest(_) => true,
         }
@@ -203,6 +314,7 @@ impl RPCRequest {
     pub fn multiple_responses(&self) -> bool {
         match self {
             RPCRequest::Status(_) => false,
+            RPCRequest::Hello(_) => false,
             RPCRequest::Goodbye(_) => false,
             RPCRequest::BlockSyncRequest(_) => true,
         }
@@ -216,6 +328,7 @@ impl RPCRequest {
             // variants that have `multiple_responses()` can have values.
             RPCRequest::BlockSyncRequest(_) => ResponseTermination::BlockSyncRequest,
             RPCRequest::Status(_) => unreachable!(),
+            RPCRequest::Hello(_) => unreachable!(),
             RPCRequest::Goodbye(_) => unreachable!(),
         }
     }
@@ -223,11 +336,33 @@ impl RPCRequest {
 
 /* RPC Response type - used for outbound upgrades */
 
+/// An outbound request proposing the protocol versions in the order of preference.
+#[derive(Debug, Clone)]
+pub struct VersionedRequest {
+    pub request: RPCRequest,
+    pub versions: Vec<ProtocolVersion>,
+}
+
+impl VersionedRequest {
+    pub fn new(request: RPCRequest, versions: Vec<ProtocolVersion>) -> Self {
+        Self { request, versions }
+    }
+}
+
+impl UpgradeInfo for VersionedRequest {
+    type Info = ProtocolId;
+    type InfoIter = Vec<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.request.supported_protocols(&self.versions)
+    }
+}
+
 /* Outbound upgrades */
 
 pub type OutboundFramed<TSocket> = Framed<upgrade::Negotiated<TSocket>, OutboundCodec>;
 
-impl<TSocket> OutboundUpgrade<TSocket> for RPCRequest
+impl<TSocket> OutboundUpgrade<TSocket> for VersionedRequest
 where
     TSocket: AsyncRead + AsyncWrite,
 {
@@ -239,9 +374,7 @@ where
         socket: upgrade::Negotiated<TSocket>,
         protocol: Self::Info,
     ) -> Self::Future {
-        match protocol.encoding.as_str() {
-            CBOR | _ => Framed::new(socket, OutboundCodec).send(self),
-        }
+        Framed::new(socket, OutboundCodec::new(protocol.encoding())).send(self.request)
     }
 }
 
@@ -254,6 +387,9 @@ pub enum RPCError {
     CborDecodeError(serde_cbor::Error),
     /// Invalid Protocol ID.
     InvalidProtocol(&'static str),
+    /// The peer speaks no compatible protocol version, or a message of another version
+    /// is received.
+    ProtocolMismatch(String),
     /// IO Error.
     IoError(io::Error),
     /// Waiting for a request/response timed out, or timer error'd.
@@ -306,6 +442,7 @@ impl std::fmt::Display for RPCError {
             RPCError::ReadError(ref err) => write!(f, "Error while reading from socket: {}", err),
             RPCError::CborDecodeError(ref err) => write!(f, "Error while decoding cbor: {:?}", err),
             RPCError::InvalidProtocol(ref err) => write!(f, "Invalid Protocol: {}", err),
+            RPCError::ProtocolMismatch(ref err) => write!(f, "Protocol Mismatch: {}", err),
             RPCError::IoError(ref err) => write!(f, "IO Error: {}", err),
             RPCError::RPCErrorResponse => write!(f, "RPC Response Error"),
             RPCError::StreamTimeout => write!(f, "Stream Timeout"),
@@ -320,6 +457,7 @@ impl std::error::Error for RPCError {
             RPCError::ReadError(ref err) => Some(err),
             RPCError::CborDecodeError(_) => None,
             RPCError::InvalidProtocol(_) => None,
+            RPCError::ProtocolMismatch(_) => None,
             RPCError::IoError(ref err) => Some(err),
             RPCError::StreamTimeout => None,
             RPCError::RPCErrorResponse => None,
@@ -332,8 +470,83 @@ impl std::fmt::Display for RPCRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RPCRequest::Status(status) => write!(f, "Status Message: {}", status),
+            RPCRequest::Hello(hello) => write!(f, "{}", hello),
             RPCRequest::Goodbye(reason) => write!(f, "Goodbye: {}", reason),
             RPCRequest::BlockSyncRequest(req) => write!(f, "Block sync request: {}", req),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol_names<I: IntoIterator<Item = ProtocolId>>(info: I) -> Vec<String> {
+        info.into_iter()
+            .map(|id| String::from_utf8(id.protocol_name().to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_protocol_versions() {
+        let request = RPCRequest::Goodbye(GoodbyeReason::ClientShutdown);
+        let dialer = VersionedRequest::new(request, SUPPORTED_VERSIONS.to_vec());
+        assert_eq!(
+            protocol_names(dialer.protocol_info()),
+            vec![
+                "/fil/plum/req/goodbye/2/cbor",
+                "/fil/plum/req/goodbye/1/cbor",
+            ]
+        );
+
+        // the dialer speaking both versions downgrades to the version of the old listener.
+        let listener = RPCProtocol::new(vec![ProtocolVersion::V1]);
+        let listened = protocol_names(listener.protocol_info());
+        let negotiated = protocol_names(dialer.protocol_info())
+            .into_iter()
+            .find(|name| listened.contains(name));
+        assert_eq!(negotiated.as_deref(), Some("/fil/plum/req/goodbye/1/cbor"));
+        assert_eq!(
+            ProtocolVersion::negotiate(&dialer.versions, listener.versions()),
+            Some(ProtocolVersion::V1)
+        );
+
+        // the peers with no version in common report a mismatch.
+        let listener = RPCProtocol::new(vec![ProtocolVersion::V2]);
+        assert_eq!(
+            ProtocolVersion::negotiate(&[ProtocolVersion::V1], listener.versions()),
+            None
+        );
+        assert_eq!(
+            ProtocolVersion::negotiate(&SUPPORTED_VERSIONS, &SUPPORTED_VERSIONS),
+            Some(ProtocolVersion::V2)
+        );
+    }
+
+    #[test]
+    fn test_hello_protocol() {
+        // the hello is spoken on the Lotus protocol id whatever the versions.
+        let hello = RPCRequest::Hello(HelloMessage {
+            heaviest_tip_set: vec![],
+            heaviest_tip_set_height: 0,
+            heaviest_tip_set_weight: 0u64.into(),
+            genesis_hash: crate::config::genesis_hash(),
+        });
+        let dialer = VersionedRequest::new(hello, SUPPORTED_VERSIONS.to_vec());
+        assert_eq!(
+            protocol_names(dialer.protocol_info()),
+            vec![HELLO_PROTOCOL_ID]
+        );
+
+        let listener = RPCProtocol::new(vec![ProtocolVersion::V2]);
+        let listened = protocol_names(listener.protocol_info());
+        assert_eq!(
+            listened
+                .iter()
+                .filter(|name| name.as_str() == HELLO_PROTOCOL_ID)
+                .count(),
+            1
+        );
+        assert!(!listened.iter().any(|name| name.contains("/hello/2/")));
+    }
+}
@@ -14,9 +14,9 @@ use log::{error, info};
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
 
-use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::behaviour::{Behaviour, BehaviourEvent, QueryId};
 use crate::config::Libp2pConfig;
-use crate::rpc::RPCEvent;
+use crate::rpc::{HelloMessage, RPCEvent};
 
 type Libp2pStream = Boxed<(PeerId, StreamMuxerBox), Error>;
 type Libp2pBehaviour = Behaviour<Substream<StreamMuxerBox>>;
@@ -34,10 +34,10 @@ impl Libp2pService {
 
         info!("Local peer id: {:?}", peer_id);
 
-        let transport = build_transport(net_keypair.clone());
+        let transport = build_transport(net_keypair.clone(), config.upgrade_timeout);
 
         let mut swarm = {
-            let behaviour = Behaviour::new(&net_keypair);
+            let behaviour = Behaviour::new(&net_keypair, config.address_filter.clone());
             Swarm::new(transport, behaviour, peer_id.clone())
         };
 
@@ -54,7 +54,7 @@ impl Libp2pService {
             };
         };
 
-        for node in config.bootnodes.clone() {
+        for node in config.address_filter.filter(config.bootnodes.clone()) {
             dial_addr(node);
         }
 
@@ -81,16 +81,55 @@ impl Stream for Libp2pService {
         loop {
             match self.swarm.poll() {
                 Ok(Async::Ready(Some(event))) => match event {
-                    BehaviourEvent::DiscoveredPeer(peer) => {
-                        libp2p::Swarm::dial(&mut self.swarm, peer);
+                    BehaviourEvent::DiscoveredPeer(peer, addrs) => {
+                        info!("Dialing libp2p peer {:?}, addresses: {:?}", peer, addrs);
+                        // the swarm tries the addresses of the peer in turn until one is
+                        // reached, which are checked by the address filter of the behaviour.
+                        Swarm::dial(&mut self.swarm, peer);
                     }
-                    BehaviourEvent::HelloSubscribed(peer) => {
-                        return Ok(Async::Ready(Some(Libp2pEvent::HelloSubscribed(peer))));
+                    BehaviourEvent::Hello(peer, hello) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::Hello(peer, hello))));
+                    }
+                    BehaviourEvent::PeerSubscribed(peer, topic) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerSubscribed(peer, topic))));
+                    }
+                    BehaviourEvent::Identified {
+                        peer_id,
+                        agent_version,
+                        listen_addrs,
+                        observed_addr,
+                    } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::Identified {
+                            peer_id,
+                            agent_version,
+                            listen_addrs,
+                            observed_addr,
+                        })));
                     }
                     BehaviourEvent::RPC(peer, rpc_event) => {
                         return Ok(Async::Ready(Some(Libp2pEvent::RPC(peer, rpc_event))));
                     }
+                    BehaviourEvent::ProtocolMismatch(peer) => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::ProtocolMismatch(peer))));
+                    }
                     BehaviourEvent::ExpiredPeer(_) => {}
+                    BehaviourEvent::PeerFound {
+                        query_id,
+                        peer_id,
+                        addresses,
+                    } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerFound {
+                            query_id,
+                            peer_id,
+                            addresses,
+                        })));
+                    }
+                    BehaviourEvent::PeerNotFound { query_id, peer_id } => {
+                        return Ok(Async::Ready(Some(Libp2pEvent::PeerNotFound {
+                            query_id,
+                            peer_id,
+                        })));
+                    }
                     BehaviourEvent::GossipMessage {
                         id,
                         source,
@@ -122,11 +161,41 @@ pub enum Libp2pEvent {
         topics: Vec<TopicHash>,
         data: Vec<u8>,
     },
-    HelloSubscribed(PeerId),
+    /// The peer said hello with its heaviest tipset.
+    Hello(PeerId, HelloMessage),
+    /// The peer subscribed the gossipsub topic.
+    PeerSubscribed(PeerId, TopicHash),
+    /// The peer is identified with its listen addresses, which are known to the kademlia
+    /// routing table afterwards.
+    Identified {
+        peer_id: PeerId,
+        agent_version: String,
+        listen_addrs: Vec<Multiaddr>,
+        /// Our address observed by the peer.
+        observed_addr: Multiaddr,
+    },
     RPC(PeerId, RPCEvent),
+    /// The peer shares no RPC protocol version with us.
+    ProtocolMismatch(PeerId),
+    /// The lookup started by `Behaviour::find_peer` found the addresses of the peer.
+    PeerFound {
+        query_id: QueryId,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    },
+    /// The lookup started by `Behaviour::find_peer` found no address of the peer.
+    PeerNotFound {
+        query_id: QueryId,
+        peer_id: PeerId,
+    },
 }
 
-pub fn build_transport(local_key: Keypair) -> Boxed<(PeerId, StreamMuxerBox), Error> {
+/// Build the transport, the authentication and multiplexing upgrade must be finished
+/// within the `upgrade_timeout`.
+pub fn build_transport(
+    local_key: Keypair,
+    upgrade_timeout: Duration,
+) -> Boxed<(PeerId, StreamMuxerBox), Error> {
     let transport = libp2p::tcp::TcpConfig::new().nodelay(true);
     let transport = libp2p::dns::DnsConfig::new(transport);
 
@@ -138,7 +207,7 @@ pub fn build_transport(local_key: Keypair) -> Boxed<(PeerId, StreamMuxerBox), Er
             mplex::MplexConfig::new(),
         ))
         .map(|(peer, muxer), _| (peer, core::muxing::StreamMuxerBox::new(muxer)))
-        .timeout(Duration::from_secs(20))
+        .timeout(upgrade_timeout)
         .map_err(|err| Error::new(ErrorKind::Other, err))
         .boxed()
 }
@@ -152,3 +221,99 @@ fn generate_new_keypair() -> Keypair {
 
     generated_keypair
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use futures::future;
+    use tokio::timer::Timeout;
+
+    use super::*;
+    use crate::filter::AddressFilter;
+
+    #[test]
+    fn test_upgrade_timeout() {
+        // a peer that accepts the connection but never answers the handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(10));
+        });
+
+        let timeout = Duration::from_millis(500);
+        let transport = build_transport(Keypair::generate_ed25519(), timeout);
+        let addr = format!("/ip4/127.0.0.1/tcp/{}", port)
+            .parse::<Multiaddr>()
+            .unwrap();
+        let dial = transport.dial(addr).unwrap();
+
+        let start = Instant::now();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(dial);
+        assert!(result.is_err());
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_blocked_address_not_dialed() {
+        let blocked = TcpListener::bind("127.0.0.1:0").unwrap();
+        let allowed = TcpListener::bind("127.0.0.1:0").unwrap();
+        blocked.set_nonblocking(true).unwrap();
+        allowed.set_nonblocking(true).unwrap();
+        let tcp_addr = |listener: &TcpListener| {
+            format!(
+                "/ip4/127.0.0.1/tcp/{}",
+                listener.local_addr().unwrap().port()
+            )
+            .parse::<Multiaddr>()
+            .unwrap()
+        };
+        let (blocked_addr, allowed_addr) = (tcp_addr(&blocked), tcp_addr(&allowed));
+
+        let config = Libp2pConfig {
+            listen_address: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            address_filter: {
+                let blocked_addr = blocked_addr.clone();
+                AddressFilter::custom(move |addr| addr != &blocked_addr)
+            },
+            ..Default::default()
+        };
+        let mut service = Libp2pService::new(&config);
+
+        // the addresses learned by kademlia skip the filter of `Behaviour::add_address`.
+        let blocked_peer = Keypair::generate_ed25519().public().into_peer_id();
+        let allowed_peer = Keypair::generate_ed25519().public().into_peer_id();
+        service.swarm.kad.add_address(&blocked_peer, blocked_addr);
+        service.swarm.kad.add_address(&allowed_peer, allowed_addr);
+        Swarm::dial(&mut service.swarm, blocked_peer);
+        Swarm::dial(&mut service.swarm, allowed_peer);
+
+        let dialed = future::poll_fn(move || -> Result<Async<()>, ()> {
+            while let Async::Ready(Some(_)) = service.poll()? {}
+            match allowed.accept() {
+                Ok(_) => Ok(Async::Ready(())),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    // poll the service again shortly.
+                    futures::task::current().notify();
+                    Ok(Async::NotReady)
+                }
+                Err(err) => panic!("failed to accept: {}", err),
+            }
+        });
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(Timeout::new(dialed, Duration::from_secs(10)))
+            .expect("the allowed address should be dialed");
+
+        match blocked.accept() {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            other => panic!("the blocked address shouldn't be dialed, got {:?}", other),
+        }
+    }
+}
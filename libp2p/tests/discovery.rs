@@ -0,0 +1,58 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::time::Duration;
+
+use futures::{future, Async, Stream};
+use libp2p::Swarm;
+use tokio::timer::Timeout;
+
+use plum_libp2p::config::Libp2pConfig;
+use plum_libp2p::service::{Libp2pEvent, Libp2pService};
+use plum_libp2p::{Multiaddr, PeerId};
+
+fn service(port: u16) -> (Libp2pService, PeerId, Multiaddr) {
+    let config = Libp2pConfig {
+        listen_address: format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap(),
+        ..Default::default()
+    };
+    let service = Libp2pService::new(&config);
+    let peer_id = Swarm::local_peer_id(&service.swarm).clone();
+    (service, peer_id, config.listen_address)
+}
+
+#[test]
+fn test_find_peer() {
+    // a only knows b, b only knows c.
+    let (mut a, _, _) = service(34571);
+    let (mut b, b_id, b_addr) = service(34572);
+    let (mut c, c_id, c_addr) = service(34573);
+    a.swarm.add_address(&b_id, b_addr);
+    b.swarm.add_address(&c_id, c_addr.clone());
+
+    let query_id = a.swarm.find_peer(c_id.clone());
+    let found = future::poll_fn(move || -> Result<Async<(PeerId, Vec<Multiaddr>)>, ()> {
+        while let Async::Ready(Some(_)) = b.poll()? {}
+        while let Async::Ready(Some(_)) = c.poll()? {}
+        while let Async::Ready(Some(event)) = a.poll()? {
+            match event {
+                Libp2pEvent::PeerFound {
+                    query_id: id,
+                    peer_id,
+                    addresses,
+                } if id == query_id => return Ok(Async::Ready((peer_id, addresses))),
+                Libp2pEvent::PeerNotFound { query_id: id, .. } if id == query_id => {
+                    panic!("the peer should be found via the middle peer")
+                }
+                _ => {}
+            }
+        }
+        Ok(Async::NotReady)
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (peer_id, addresses) = runtime
+        .block_on(Timeout::new(found, Duration::from_secs(30)))
+        .expect("the lookup should finish");
+    assert_eq!(peer_id, c_id);
+    assert!(addresses.contains(&c_addr));
+}
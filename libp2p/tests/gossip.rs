@@ -0,0 +1,96 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+//! The libp2p crate is excluded from the workspace, so these tests aren't run by
+//! `cargo test --all`, run them with `make test-libp2p`.
+
+use std::time::Duration;
+
+use cid::Cid;
+use futures::{future, Async, Stream};
+use tokio::timer::Timeout;
+
+use plum_address::Address;
+use plum_block::{BlockHeader, BlockMsg, ElectionProof, Ticket};
+use plum_crypto::Signature;
+use plum_libp2p::config::{Libp2pConfig, BLOCKS_TOPIC};
+use plum_libp2p::service::{Libp2pEvent, Libp2pService};
+use plum_libp2p::TopicHash;
+
+fn dummy_block_msg() -> BlockMsg {
+    let cid: Cid = "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+        .parse()
+        .unwrap();
+
+    let header = BlockHeader {
+        miner: Address::new_id_addr(12_512_063).unwrap(),
+        ticket: Ticket {
+            vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+        },
+        election_proof: ElectionProof {
+            vrf_proof: b"vrf proof0000000vrf proof0000000".to_vec(),
+        },
+        beacon_entries: vec![],
+        win_post_proof: vec![],
+        parents: vec![cid.clone(), cid.clone()],
+        parent_message_receipts: cid.clone(),
+        bls_aggregate: Signature::new_bls("boo! im a signature"),
+        parent_weight: 123_125_126_212u64.into(),
+        messages: cid.clone(),
+        height: 85_919_298_723,
+        parent_state_root: cid.clone(),
+        timestamp: 0u64,
+        block_sig: Signature::new_bls("boo! im a signature"),
+        fork_signaling: 0u64,
+    };
+
+    BlockMsg {
+        header,
+        bls_messages: vec![cid.clone()],
+        secpk_messages: vec![cid],
+    }
+}
+
+#[test]
+fn test_publish_block() {
+    let config_a = Libp2pConfig {
+        listen_address: "/ip4/127.0.0.1/tcp/34561".parse().unwrap(),
+        ..Default::default()
+    };
+    let config_b = Libp2pConfig {
+        listen_address: "/ip4/127.0.0.1/tcp/34562".parse().unwrap(),
+        bootnodes: vec![config_a.listen_address.clone()],
+        ..Default::default()
+    };
+    let mut sender = Libp2pService::new(&config_a);
+    let mut receiver = Libp2pService::new(&config_b);
+
+    let block = dummy_block_msg();
+    let expected = block.clone();
+    let mut published = false;
+    let received = future::poll_fn(move || -> Result<Async<BlockMsg>, ()> {
+        // publish the block once the receiver has subscribed the blocks topic.
+        while let Async::Ready(Some(event)) = sender.poll()? {
+            if let Libp2pEvent::PeerSubscribed(_, topic) = event {
+                if topic == TopicHash::from_raw(BLOCKS_TOPIC) && !published {
+                    sender.swarm.publish_block(&block).unwrap();
+                    published = true;
+                }
+            }
+        }
+        while let Async::Ready(Some(event)) = receiver.poll()? {
+            if let Libp2pEvent::PubsubMessage { topics, data, .. } = event {
+                if topics.contains(&TopicHash::from_raw(BLOCKS_TOPIC)) {
+                    let block = minicbor::decode::<BlockMsg>(&data).unwrap();
+                    return Ok(Async::Ready(block));
+                }
+            }
+        }
+        Ok(Async::NotReady)
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let received = runtime
+        .block_on(Timeout::new(received, Duration::from_secs(30)))
+        .expect("the receiver should receive the published block");
+    assert_eq!(received, expected);
+}
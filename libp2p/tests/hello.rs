@@ -0,0 +1,62 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::time::Duration;
+
+use futures::{future, Async, Stream};
+use tokio::timer::Timeout;
+
+use plum_libp2p::config::{genesis_hash, Libp2pConfig};
+use plum_libp2p::rpc::HelloMessage;
+use plum_libp2p::service::{Libp2pEvent, Libp2pService};
+
+#[test]
+fn test_hello_on_connecting() {
+    let config_a = Libp2pConfig {
+        listen_address: "/ip4/127.0.0.1/tcp/34581".parse().unwrap(),
+        ..Default::default()
+    };
+    let config_b = Libp2pConfig {
+        listen_address: "/ip4/127.0.0.1/tcp/34582".parse().unwrap(),
+        bootnodes: vec![config_a.listen_address.clone()],
+        ..Default::default()
+    };
+    let mut a = Libp2pService::new(&config_a);
+    let mut b = Libp2pService::new(&config_b);
+    let head_b = HelloMessage {
+        heaviest_tip_set: vec![genesis_hash()],
+        heaviest_tip_set_height: 10,
+        heaviest_tip_set_weight: 100u64.into(),
+        genesis_hash: genesis_hash(),
+    };
+    b.swarm.set_head(head_b.clone());
+    let head_a = a.swarm.head().clone();
+
+    // b dials a, both ends say hello with their heads and answer with the latency.
+    let (mut hello_a, mut hello_b) = (None, None);
+    let exchanged = future::poll_fn(move || -> Result<Async<_>, ()> {
+        while let Async::Ready(Some(event)) = a.poll()? {
+            if let Libp2pEvent::Hello(_, hello) = event {
+                hello_a = Some(hello);
+            }
+        }
+        while let Async::Ready(Some(event)) = b.poll()? {
+            if let Libp2pEvent::Hello(_, hello) = event {
+                hello_b = Some(hello);
+            }
+        }
+        if hello_a.is_some() && hello_b.is_some() {
+            return Ok(Async::Ready((
+                hello_a.take().unwrap(),
+                hello_b.take().unwrap(),
+            )));
+        }
+        Ok(Async::NotReady)
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (received_a, received_b) = runtime
+        .block_on(Timeout::new(exchanged, Duration::from_secs(30)))
+        .expect("the peers should say hello to each other");
+    assert_eq!(received_a, head_b);
+    assert_eq!(received_b, head_a);
+}
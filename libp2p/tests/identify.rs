@@ -0,0 +1,75 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::time::Duration;
+
+use futures::{future, Async, Stream};
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::Swarm;
+use tokio::timer::Timeout;
+
+use plum_libp2p::config::Libp2pConfig;
+use plum_libp2p::service::{Libp2pEvent, Libp2pService};
+use plum_libp2p::{Multiaddr, PeerId};
+
+fn service(port: u16, bootnodes: Vec<Multiaddr>) -> (Libp2pService, PeerId, Multiaddr) {
+    let config = Libp2pConfig {
+        listen_address: format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap(),
+        bootnodes,
+        ..Default::default()
+    };
+    let service = Libp2pService::new(&config);
+    let peer_id = Swarm::local_peer_id(&service.swarm).clone();
+    (service, peer_id, config.listen_address)
+}
+
+#[test]
+fn test_identify_addresses() {
+    // b dials a, a doesn't know the listen address of b until identified.
+    let (mut a, a_id, a_addr) = service(34591, vec![]);
+    let (mut b, b_id, b_addr) = service(34592, vec![a_addr.clone()]);
+    assert!(a.swarm.addresses_of_peer(&b_id).is_empty());
+
+    let (expected_a, expected_b) = (a_addr.clone(), b_addr.clone());
+    let (mut identified_a, mut identified_b) = (None, None);
+    let identified = future::poll_fn(move || -> Result<Async<_>, ()> {
+        while let Async::Ready(Some(event)) = a.poll()? {
+            if let Libp2pEvent::Identified {
+                peer_id,
+                listen_addrs,
+                ..
+            } = event
+            {
+                assert_eq!(peer_id, b_id);
+                assert!(listen_addrs.contains(&expected_b));
+                identified_a = Some(a.swarm.addresses_of_peer(&b_id));
+            }
+        }
+        while let Async::Ready(Some(event)) = b.poll()? {
+            if let Libp2pEvent::Identified {
+                peer_id,
+                listen_addrs,
+                ..
+            } = event
+            {
+                assert_eq!(peer_id, a_id);
+                assert!(listen_addrs.contains(&expected_a));
+                identified_b = Some(b.swarm.addresses_of_peer(&a_id));
+            }
+        }
+        if identified_a.is_some() && identified_b.is_some() {
+            return Ok(Async::Ready((
+                identified_a.take().unwrap(),
+                identified_b.take().unwrap(),
+            )));
+        }
+        Ok(Async::NotReady)
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (known_by_a, known_by_b) = runtime
+        .block_on(Timeout::new(identified, Duration::from_secs(30)))
+        .expect("the peers should identify each other");
+    // the identified listen addresses are known to the routing table.
+    assert!(known_by_a.contains(&b_addr));
+    assert!(known_by_b.contains(&a_addr));
+}
@@ -4,24 +4,20 @@ use anyhow::Result;
 use futures::future::Future;
 use futures::stream::Stream;
 use log::{debug, error};
-use plum_libp2p::rpc::methods::BlockSyncRequest;
-use plum_libp2p::rpc::{RPCEvent, RPCRequest, RequestId, StatusMessage};
+use plum_libp2p::rpc::{HelloMessage, RPCEvent, RPCRequest, RequestId};
 use plum_libp2p::{
-    config::{BLOCKS_TOPIC, HELLO_TOPIC, MESSAGES_TOPIC},
+    config::{BLOCKS_TOPIC, MESSAGES_TOPIC},
     MessageId, PeerId, TopicHash,
 };
 use tokio::sync::mpsc;
 
-use crate::service::NetworkMessage;
-
-pub struct MessageHandler {
-    network_send: mpsc::UnboundedSender<NetworkMessage>,
-}
+pub struct MessageHandler;
 
 /// Types of messages the handler can receive.
 #[derive(Debug)]
 pub enum HandlerMessage {
-    SayHello(PeerId),
+    /// The peer said hello with its heaviest tipset.
+    Hello(PeerId, HelloMessage),
     RPC(PeerId, RPCEvent),
     PubsubMessage {
         id: MessageId,
@@ -33,13 +29,12 @@ pub enum HandlerMessage {
 
 impl MessageHandler {
     pub fn spawn(
-        network_send: mpsc::UnboundedSender<NetworkMessage>,
         executor: &tokio::runtime::TaskExecutor,
     ) -> Result<mpsc::UnboundedSender<HandlerMessage>> {
         let (handler_send, handler_recv) = mpsc::unbounded_channel();
 
         // generate the Message handler
-        let mut handler = MessageHandler { network_send };
+        let mut handler = MessageHandler;
 
         // TODO: spawn another sync thread
 
@@ -63,6 +58,8 @@ impl MessageHandler {
                     peer, id, status
                 );
             }
+            // answered by the libp2p behaviour, and reported as `HandlerMessage::Hello`.
+            RPCRequest::Hello(_) => {}
             RPCRequest::Goodbye(goodbye) => {
                 debug!(
                     "handling RPC Goodbye message, peer:{:?}, id: {}, request: {:?}",
@@ -78,46 +75,10 @@ impl MessageHandler {
         }
     }
 
-    fn on_say_hello(&mut self, peer: PeerId) {
-        // TODO: https://github.com/filecoin-project/lotus/blob/e7a1be4dde/node/hello/hello.go#L114
-        let dummy_status_msg = StatusMessage {
-            heaviest_tip_set: Vec::new(),
-            heaviest_tip_set_weight: 8888u128,
-            genesis_hash: plum_libp2p::config::genesis_hash(),
-        };
-
-        if self
-            .network_send
-            .try_send(NetworkMessage::RPC(
-                peer,
-                RPCEvent::Request(0usize, RPCRequest::Status(dummy_status_msg)),
-            ))
-            .is_err()
-        {
-            error!("Failed to send RPC Block Request message");
-        }
-    }
-
-    fn process_hello_message(&mut self, _id: MessageId, source: PeerId, _data: Vec<u8>) {
-        // TODO:
+    fn on_hello(&mut self, peer: PeerId, hello: HelloMessage) {
+        // TODO: sync to the heavier tipset of the peer,
         // https://github.com/filecoin-project/lotus/blob/e7a1be4dde/node/hello/hello.go#L62
-
-        let dummy_request = RPCRequest::BlockSyncRequest(BlockSyncRequest {
-            start: Vec::new(),
-            length: 777u64,
-            options: 111u64,
-        });
-
-        if self
-            .network_send
-            .try_send(NetworkMessage::RPC(
-                source,
-                RPCEvent::Request(0usize, dummy_request),
-            ))
-            .is_err()
-        {
-            error!("Failed to send RPC Block Request message");
-        }
+        debug!("handling Hello message, peer: {:?}, hello: {}", peer, hello);
     }
 
     fn process_blocks_message(&mut self, _id: MessageId, _source: PeerId, _data: Vec<u8>) {
@@ -139,11 +100,9 @@ impl MessageHandler {
             "handling PubsubMessage, id: {:?}, source: {:?}, topics: {:?}, data: {:?}",
             id, source, topics, data
         );
-        // Dispatch hello/blocks/messages message
+        // Dispatch blocks/messages message
         for topic in topics {
-            if topic == TopicHash::from_raw(HELLO_TOPIC) {
-                self.process_hello_message(id.clone(), source.clone(), data.clone());
-            } else if topic == TopicHash::from_raw(BLOCKS_TOPIC) {
+            if topic == TopicHash::from_raw(BLOCKS_TOPIC) {
                 self.process_blocks_message(id.clone(), source.clone(), data.clone());
             } else if topic == TopicHash::from_raw(MESSAGES_TOPIC) {
                 self.process_messages_message(id.clone(), source.clone(), data.clone());
@@ -173,7 +132,7 @@ impl MessageHandler {
 
     fn handle_message(&mut self, message: HandlerMessage) {
         match message {
-            HandlerMessage::SayHello(peer) => self.on_say_hello(peer),
+            HandlerMessage::Hello(peer, hello) => self.on_hello(peer, hello),
             HandlerMessage::RPC(peer, rpc_event) => self.on_rpc(peer, rpc_event),
             HandlerMessage::PubsubMessage {
                 id,
@@ -4,10 +4,12 @@ use futures::stream::Stream;
 use futures::{Async, Future};
 use libp2p::gossipsub::Topic;
 use log::{debug, info, warn};
+use plum_bigint::BigInt;
 use plum_libp2p::config::Libp2pConfig;
-use plum_libp2p::rpc::RPCEvent;
+use plum_libp2p::rpc::{HelloMessage, RPCEvent};
 use plum_libp2p::service::{Libp2pEvent, Libp2pService};
 use plum_libp2p::PeerId;
+use plum_tipset::Tipset;
 use std::sync::{Arc, Mutex};
 use tokio::runtime::TaskExecutor;
 use tokio::sync::mpsc;
@@ -15,8 +17,16 @@ use tokio::sync::mpsc;
 use crate::message_handler::{HandlerMessage, MessageHandler};
 
 pub enum NetworkMessage {
-    PubsubMessage { topics: Topic, message: Vec<u8> },
+    PubsubMessage {
+        topics: Topic,
+        message: Vec<u8>,
+    },
     RPC(PeerId, RPCEvent),
+    /// The heaviest tipset of the chain changed, it's said to the peers in the hello handshake.
+    HeadChanged {
+        tipset: Tipset,
+        weight: BigInt,
+    },
 }
 
 pub struct Service {
@@ -34,8 +44,8 @@ impl Service {
     ) {
         let (network_send, network_recv) = mpsc::unbounded_channel::<NetworkMessage>();
 
-        let message_handler_send = MessageHandler::spawn(network_send.clone(), executor)
-            .expect("Failed to spawn message handler thread");
+        let message_handler_send =
+            MessageHandler::spawn(executor).expect("Failed to spawn message handler thread");
 
         let libp2p_service = Arc::new(Mutex::new(Libp2pService::new(config)));
 
@@ -106,6 +116,21 @@ fn network_service(
                             .swarm
                             .publish(&topics, message);
                     }
+                    NetworkMessage::HeadChanged { tipset, weight } => {
+                        debug!(
+                            "Updating the head, height: {}, weight: {}",
+                            tipset.height(),
+                            weight
+                        );
+                        let mut libp2p_service = libp2p_service.lock().unwrap();
+                        let head = HelloMessage {
+                            heaviest_tip_set: tipset.cids().to_vec(),
+                            heaviest_tip_set_height: tipset.height(),
+                            heaviest_tip_set_weight: weight,
+                            genesis_hash: libp2p_service.swarm.head().genesis_hash.clone(),
+                        };
+                        libp2p_service.swarm.set_head(head);
+                    }
                 },
                 Ok(Async::NotReady) => break,
                 _ => break,
@@ -145,14 +170,36 @@ fn network_service(
                             warn!("Failed to send RPC HandlerMessage from {}", peer);
                         }
                     }
-                    Libp2pEvent::HelloSubscribed(peer) => {
+                    Libp2pEvent::Hello(peer, hello) => {
                         if message_handler_send
-                            .try_send(HandlerMessage::SayHello(peer.clone()))
+                            .try_send(HandlerMessage::Hello(peer.clone(), hello))
                             .is_err()
                         {
-                            warn!("Failed to send SayHello HandlerMessage from {}", peer);
+                            warn!("Failed to send Hello HandlerMessage from {}", peer);
                         }
                     }
+                    Libp2pEvent::PeerSubscribed(..) => {}
+                    Libp2pEvent::Identified {
+                        peer_id,
+                        agent_version,
+                        ..
+                    } => {
+                        info!(
+                            "Identified peer {}, agent version: {}",
+                            peer_id, agent_version
+                        );
+                    }
+                    Libp2pEvent::ProtocolMismatch(peer) => {
+                        warn!("No common RPC protocol version with {}", peer);
+                    }
+                    Libp2pEvent::PeerFound {
+                        peer_id, addresses, ..
+                    } => {
+                        debug!("Found peer {}, addresses: {:?}", peer_id, addresses);
+                    }
+                    Libp2pEvent::PeerNotFound { peer_id, .. } => {
+                        debug!("No address of peer {} is found", peer_id);
+                    }
                 },
                 Ok(Async::Ready(None)) => unreachable!("Stream never ends"),
                 Ok(Async::NotReady) => break,
@@ -11,6 +11,9 @@ use crate::errors::AddressError;
 use crate::network::{Network, NETWORK_DEFAULT, NETWORK_MAINNET_PREFIX, NETWORK_TESTNET_PREFIX};
 use crate::protocol::Protocol;
 
+// The tag of the uncompressed secp256k1 public key.
+const SECP256K1_FULL_PUBLIC_KEY_TAG: u8 = 0x04;
+
 /// The general address structure.
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
 pub struct Address {
@@ -64,6 +67,33 @@ impl Address {
         Self::new(Protocol::Secp256k1, address_hash(pubkey))
     }
 
+    /// Derive the `Secp256k1` address of the public key, the same as the one computed by Lotus.
+    ///
+    /// The address is the hash of the uncompressed public key, so the 64 bytes raw key is
+    /// accepted and prefixed with the `0x04` tag before hashing. The compressed key is rejected,
+    /// since hashing it directly would derive a different address.
+    pub fn from_secp_pubkey(pubkey: &[u8]) -> Result<Self, AddressError> {
+        match pubkey.len() {
+            constant::SECP256K1_FULL_PUBLIC_KEY_LEN
+                if pubkey[0] == SECP256K1_FULL_PUBLIC_KEY_TAG =>
+            {
+                Self::new_secp256k1_addr(pubkey)
+            }
+            constant::SECP256K1_RAW_PUBLIC_KEY_LEN => {
+                let mut full = Vec::with_capacity(constant::SECP256K1_FULL_PUBLIC_KEY_LEN);
+                full.push(SECP256K1_FULL_PUBLIC_KEY_TAG);
+                full.extend_from_slice(pubkey);
+                Self::new_secp256k1_addr(&full)
+            }
+            _ => Err(AddressError::InvalidPayload),
+        }
+    }
+
+    /// Derive the `BLS` address of the public key, the same as the one computed by Lotus.
+    pub fn from_bls_pubkey(pubkey: &[u8]) -> Result<Self, AddressError> {
+        Self::new_bls_addr(pubkey)
+    }
+
     /// Create an address using the `Actor` protocol.
     pub fn new_actor_addr(data: &[u8]) -> Result<Self, AddressError> {
         Self::new(Protocol::Actor, address_hash(data))
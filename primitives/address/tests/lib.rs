@@ -248,6 +248,45 @@ fn test_bls_address() {
     }
 }
 
+#[test]
+fn test_address_from_pubkey() {
+    unsafe {
+        set_network(Network::Test);
+    }
+    let secp_pubkey = [
+        4, 222, 253, 208, 16, 1, 239, 184, 110, 1, 222, 213, 206, 52, 248, 71, 167, 58, 20, 129,
+        158, 230, 65, 188, 182, 11, 185, 41, 147, 89, 111, 5, 220, 45, 96, 95, 41, 133, 248, 209,
+        37, 129, 45, 172, 65, 99, 163, 150, 52, 155, 35, 193, 28, 194, 255, 53, 157, 229, 75, 226,
+        135, 234, 98, 49, 155,
+    ];
+    let addr = Address::from_secp_pubkey(&secp_pubkey).unwrap();
+    assert_eq!(addr.protocol(), Protocol::Secp256k1);
+    assert_eq!(addr.to_string(), "t1wbxhu3ypkuo6eyp6hjx6davuelxaxrvwb2kuwva");
+    // the raw key without the tag derives the same address.
+    assert_eq!(Address::from_secp_pubkey(&secp_pubkey[1..]).unwrap(), addr);
+
+    let seckey = secp256k1::SecretKey::random(&mut rand::rngs::OsRng);
+    let pubkey = secp256k1::PublicKey::from_secret_key(&seckey);
+    assert_eq!(
+        Address::from_secp_pubkey(&pubkey.serialize()).unwrap(),
+        Address::new_secp256k1_addr(&pubkey.serialize()).unwrap()
+    );
+    assert!(Address::from_secp_pubkey(&pubkey.serialize_compressed()).is_err());
+
+    let bls_pubkey = [
+        134, 180, 84, 37, 140, 88, 148, 117, 247, 209, 111, 90, 172, 1, 138, 121, 246, 193, 22,
+        157, 32, 252, 51, 146, 29, 216, 181, 206, 28, 172, 108, 52, 143, 144, 163, 96, 54, 36, 246,
+        174, 185, 27, 100, 81, 140, 46, 128, 149,
+    ];
+    let addr = Address::from_bls_pubkey(&bls_pubkey).unwrap();
+    assert_eq!(addr.protocol(), Protocol::Bls);
+    assert_eq!(
+        addr.to_string(),
+        "t3q22fijmmlckhl56rn5nkyamkph3mcfu5ed6dheq53c244hfmnq2i7efdma3cj5voxenwiummf2ajlsbxc65a"
+    );
+    assert!(Address::from_bls_pubkey(&bls_pubkey[1..]).is_err());
+}
+
 #[test]
 fn test_invalid_string_address() {
     unsafe {
@@ -12,7 +12,7 @@ use plum_address::Address;
 use plum_bigint::{bigint_json, BigInt, BigIntRefWrapper, BigIntWrapper};
 use plum_crypto::Signature;
 use plum_sector::PoStProof;
-use plum_types::ChainEpoch;
+use plum_types::{ChainEpoch, Versioned};
 
 /// The header part of the block.
 #[derive(Eq, PartialEq, Debug, Clone, Hash, Serialize, Deserialize)]
@@ -130,12 +130,59 @@ impl<'b> decode::Decode<'b> for BlockHeader {
     }
 }
 
+// The persisted layouts of BlockHeader:
+//
+// - v1: `[miner, ticket, election_proof, parents, parent_weight, height, parent_state_root,
+//   parent_message_receipts, messages, bls_aggregate, timestamp, block_sig]`.
+// - v2: the current layout, `beacon_entries` and `win_post_proof` are inserted after
+//   `election_proof` and `fork_signaling` is appended.
+//
+// The fields of the older tuples are mapped by name rather than by position, and the
+// fields missing in them get the defaults: no beacon entries, no PoSt proof and no fork
+// signaling.
+impl Versioned for BlockHeader {
+    const VERSION: u64 = 2;
+
+    fn decode_version(version: u64, d: &mut Decoder<'_>) -> Result<Self, decode::Error> {
+        match version {
+            1 => decode_v1(d),
+            2 => d.decode(),
+            _ => Err(decode::Error::Message("unknown BlockHeader layout version")),
+        }
+    }
+}
+
+fn decode_v1(d: &mut Decoder<'_>) -> Result<BlockHeader, decode::Error> {
+    if d.array()? != Some(12) {
+        return Err(decode::Error::Message("expected the v1 BlockHeader array"));
+    }
+    Ok(BlockHeader {
+        miner: d.decode::<Address>()?,
+        ticket: d.decode::<Ticket>()?,
+        election_proof: d.decode::<ElectionProof>()?,
+        beacon_entries: vec![],
+        win_post_proof: vec![],
+        parents: d.decode::<Vec<Cid>>()?,
+        parent_weight: d.decode::<BigIntWrapper>()?.into_inner(),
+        height: d.i64()?,
+        parent_state_root: d.decode::<Cid>()?,
+        parent_message_receipts: d.decode::<Cid>()?,
+        messages: d.decode::<Cid>()?,
+        bls_aggregate: d.decode::<Signature>()?,
+        timestamp: d.u64()?,
+        block_sig: d.decode::<Signature>()?,
+        fork_signaling: 0,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use cid::Cid;
 
     use plum_address::{set_network, Address, Network};
+    use plum_bigint::BigIntRefWrapper;
     use plum_crypto::Signature;
+    use plum_types::{decode_versioned, encode_versioned};
 
     use super::BlockHeader;
     use crate::election_proof::ElectionProof;
@@ -221,9 +268,9 @@ mod tests {
                 \"ParentStateRoot\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
                 \"ParentMessageReceipts\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
                 \"Messages\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
-                \"BLSAggregate\":{\"Type\":\"bls\",\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
+                \"BLSAggregate\":{\"Type\":2,\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
                 \"Timestamp\":0,\
-                \"BlockSig\":{\"Type\":\"bls\",\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
+                \"BlockSig\":{\"Type\":2,\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
                 \"ForkSignaling\":0\
             }";
         let ser = serde_json::to_string(&header).unwrap();
@@ -231,4 +278,55 @@ mod tests {
         let de = serde_json::from_str::<BlockHeader>(&ser).unwrap();
         assert_eq!(de, header);
     }
+
+    #[test]
+    fn block_header_versioned_upgrade() {
+        let mut header = dummy_block_header();
+        let mut v1 = Vec::new();
+        {
+            let mut e = minicbor::Encoder::new(&mut v1);
+            e.array(2).unwrap().u64(1).unwrap();
+            e.array(12)
+                .unwrap()
+                .encode(&header.miner)
+                .unwrap()
+                .encode(&header.ticket)
+                .unwrap()
+                .encode(&header.election_proof)
+                .unwrap()
+                .encode(&header.parents)
+                .unwrap()
+                .encode(BigIntRefWrapper::from(&header.parent_weight))
+                .unwrap()
+                .i64(header.height)
+                .unwrap()
+                .encode(&header.parent_state_root)
+                .unwrap()
+                .encode(&header.parent_message_receipts)
+                .unwrap()
+                .encode(&header.messages)
+                .unwrap()
+                .encode(&header.bls_aggregate)
+                .unwrap()
+                .u64(header.timestamp)
+                .unwrap()
+                .encode(&header.block_sig)
+                .unwrap();
+        }
+
+        // the fields added by v2 get the defaults.
+        let de = decode_versioned::<BlockHeader>(&v1).unwrap();
+        assert_eq!(de, header);
+        assert!(de.beacon_entries.is_empty());
+        assert!(de.win_post_proof.is_empty());
+
+        // the current layout round trips, the newer layouts are rejected.
+        header.fork_signaling = 1;
+        header.beacon_entries = vec![crate::BeaconEntry::new(1, vec![1, 2, 3])];
+        let v2 = encode_versioned(&header);
+        assert_eq!(decode_versioned::<BlockHeader>(&v2).unwrap(), header);
+        let mut v3 = v2.clone();
+        v3[1] = 3;
+        assert!(decode_versioned::<BlockHeader>(&v3).is_err());
+    }
 }
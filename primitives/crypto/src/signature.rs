@@ -47,11 +47,40 @@ impl From<SignatureType> for u8 {
     }
 }
 
-/// The general signature structure.
+// The JSON type tag of the signature is the number of the signature type, as Lotus does,
+// unlike the name used by the other JSON APIs taking a signature type.
+mod type_json {
+    use std::convert::TryFrom;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::SignatureType;
+
+    pub fn serialize<S>(ty: &SignatureType, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        u8::from(*ty).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SignatureType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let ty = u8::deserialize(deserializer)?;
+        SignatureType::try_from(ty).map_err(de::Error::custom)
+    }
+}
+
+/// The general signature structure, the signature bytes tagged with the signature type.
+///
+/// The CBOR encoding is the bytes of the type byte followed by the signature bytes,
+/// the JSON encoding is `{"Type": <type number>, "Data": <base64 of the signature bytes>}`.
 #[derive(Eq, PartialEq, Debug, Clone, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Signature {
     /// The signature type.
+    #[serde(with = "type_json")]
     r#type: SignatureType,
     /// Tha actual signature bytes.
     /// secp256k1: signature (64 bytes) + recovery_id (1 byte)
@@ -138,7 +167,9 @@ impl Signature {
             (SignatureType::Secp256k1, Protocol::Secp256k1) => {
                 let hashed_msg = blake2b_256(msg);
                 let message = secp256k1::Message::parse(&hashed_msg);
-                assert_eq!(self.data.len(), secp256k1::util::SIGNATURE_SIZE + 1);
+                if self.data.len() != secp256k1::util::SIGNATURE_SIZE + 1 {
+                    return Err(CryptoError::Secp256k1(secp256k1::Error::InvalidSignature));
+                }
                 let mut signature = [0u8; secp256k1::util::SIGNATURE_SIZE];
                 signature.copy_from_slice(&self.data[..secp256k1::util::SIGNATURE_SIZE]);
                 let signature = secp256k1::Signature::parse(&signature);
@@ -175,7 +206,9 @@ impl Signature {
     {
         let hashed_msg = blake2b_256(msg);
         let message = secp256k1::Message::parse(&hashed_msg);
-        assert_eq!(self.data.len(), secp256k1::util::SIGNATURE_SIZE + 1);
+        if self.data.len() != secp256k1::util::SIGNATURE_SIZE + 1 {
+            return Err(CryptoError::Secp256k1(secp256k1::Error::InvalidSignature));
+        }
         let signature = &self.data[..secp256k1::util::SIGNATURE_SIZE];
         let signature = secp256k1::Signature::parse_slice(&signature)?;
         let pubkey = secp256k1::PublicKey::parse_slice(pubkey.as_ref(), None)?;
@@ -222,7 +255,10 @@ impl encode::Encode for Signature {
 impl<'b> decode::Decode<'b> for Signature {
     fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
         let bytes = d.bytes()?;
-        let r#type = SignatureType::try_from(bytes[0])
+        let ty = *bytes
+            .first()
+            .ok_or(decode::Error::Message("expected signature type"))?;
+        let r#type = SignatureType::try_from(ty)
             .map_err(|_| decode::Error::Message("expected signature type"))?;
         Ok(Signature {
             r#type,
@@ -245,6 +281,8 @@ mod tests {
         let signature = Signature::sign_secp256k1(privkey, msg).unwrap();
         let res = signature.verify_secp256k1(&pubkey, msg);
         assert_eq!(res, Ok(true));
+        assert_eq!(signature.verify_raw(&pubkey, msg), Ok(true));
+        assert_eq!(signature.verify_raw(&pubkey, "hello, plum"), Ok(false));
 
         let addr = Address::new_secp256k1_addr(&pubkey).unwrap();
         let res = signature.verify(&addr, msg);
@@ -260,6 +298,8 @@ mod tests {
         let signature = Signature::sign_bls(privkey, msg).unwrap();
         let res = signature.verify_bls(&pubkey, msg);
         assert_eq!(res, Ok(true));
+        assert_eq!(signature.verify_raw(&pubkey, msg), Ok(true));
+        assert_eq!(signature.verify_raw(&pubkey, "hello, plum"), Ok(false));
 
         let addr = Address::new_bls_addr(&pubkey).unwrap();
         let res = signature.verify(&addr, msg);
@@ -268,16 +308,28 @@ mod tests {
 
     #[test]
     fn signature_cbor_serde() {
-        let cases = vec![(
-            Signature {
-                r#type: SignatureType::Bls,
-                data: b"boo! im a signature".to_vec(),
-            },
-            vec![
-                84, 2, 98, 111, 111, 33, 32, 105, 109, 32, 97, 32, 115, 105, 103, 110, 97, 116,
-                117, 114, 101,
-            ],
-        )];
+        let cases = vec![
+            (
+                Signature {
+                    r#type: SignatureType::Bls,
+                    data: b"boo! im a signature".to_vec(),
+                },
+                vec![
+                    84, 2, 98, 111, 111, 33, 32, 105, 109, 32, 97, 32, 115, 105, 103, 110, 97, 116,
+                    117, 114, 101,
+                ],
+            ),
+            (
+                Signature {
+                    r#type: SignatureType::Secp256k1,
+                    data: b"boo! im a signature".to_vec(),
+                },
+                vec![
+                    84, 1, 98, 111, 111, 33, 32, 105, 109, 32, 97, 32, 115, 105, 103, 110, 97, 116,
+                    117, 114, 101,
+                ],
+            ),
+        ];
 
         for (signature, expected) in cases {
             let ser = minicbor::to_vec(&signature).unwrap();
@@ -285,17 +337,30 @@ mod tests {
             let de = minicbor::decode::<Signature>(&ser).unwrap();
             assert_eq!(signature, de);
         }
+
+        // the empty bytes and the unknown type are rejected.
+        assert!(minicbor::decode::<Signature>(&[64]).is_err());
+        assert!(minicbor::decode::<Signature>(&[66, 3, 0]).is_err());
     }
 
     #[test]
     fn signature_json_serde() {
-        let cases = vec![(
-            Signature {
-                r#type: SignatureType::Bls,
-                data: b"boo! im a signature".to_vec(),
-            },
-            r#"{"Type":"bls","Data":"Ym9vISBpbSBhIHNpZ25hdHVyZQ=="}"#,
-        )];
+        let cases = vec![
+            (
+                Signature {
+                    r#type: SignatureType::Bls,
+                    data: b"boo! im a signature".to_vec(),
+                },
+                r#"{"Type":2,"Data":"Ym9vISBpbSBhIHNpZ25hdHVyZQ=="}"#,
+            ),
+            (
+                Signature {
+                    r#type: SignatureType::Secp256k1,
+                    data: b"boo! im a signature".to_vec(),
+                },
+                r#"{"Type":1,"Data":"Ym9vISBpbSBhIHNpZ25hdHVyZQ=="}"#,
+            ),
+        ];
 
         for (signature, expected) in cases {
             let ser = serde_json::to_string(&signature).unwrap();
@@ -303,5 +368,8 @@ mod tests {
             let de = serde_json::from_str::<Signature>(&ser).unwrap();
             assert_eq!(signature, de);
         }
+
+        let unknown = r#"{"Type":3,"Data":"Ym9vISBpbSBhIHNpZ25hdHVyZQ=="}"#;
+        assert!(serde_json::from_str::<Signature>(unknown).is_err());
     }
 }
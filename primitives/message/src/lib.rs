@@ -9,5 +9,5 @@ mod signed_message;
 mod unsigned_message;
 
 pub use self::message_receipt::MessageReceipt;
-pub use self::signed_message::SignedMessage;
+pub use self::signed_message::{verify_secp_batch, SignedMessage};
 pub use self::unsigned_message::UnsignedMessage;
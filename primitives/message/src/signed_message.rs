@@ -5,6 +5,7 @@ use minicbor::{decode, encode, Decoder, Encoder};
 use serde::{Deserialize, Serialize};
 
 use plum_crypto::{Signature, SignatureType};
+use plum_types::Versioned;
 
 use crate::unsigned_message::UnsignedMessage;
 
@@ -55,6 +56,38 @@ impl SignedMessage {
     }
 }
 
+/// The length of a secp256k1 signature: signature (64 bytes) + recovery_id (1 byte).
+const SECP256K1_SIGNATURE_LEN: usize = 65;
+
+/// Verify the secp256k1 signatures of the messages, which sign the CID bytes of the
+/// unsigned messages.
+///
+/// Every message is verified rather than stopping at the first failure, and the indices of
+/// the messages whose signature is invalid (or not a secp256k1 signature) are returned.
+/// An empty batch is trivially valid.
+pub fn verify_secp_batch(messages: &[SignedMessage]) -> Result<(), Vec<usize>> {
+    // libsecp256k1 doesn't support batch verification, so verify them one by one.
+    let failed = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, msg)| {
+            let signature = &msg.signature;
+            let valid = signature.r#type() == SignatureType::Secp256k1
+                && signature.as_bytes().len() == SECP256K1_SIGNATURE_LEN
+                && signature
+                    .verify(&msg.message.from, msg.message.cid().to_bytes())
+                    .unwrap_or(false);
+            !valid
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed)
+    }
+}
+
 // Implement CBOR serialization for SignedMessage.
 impl encode::Encode for SignedMessage {
     fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
@@ -76,3 +109,58 @@ impl<'b> decode::Decode<'b> for SignedMessage {
         })
     }
 }
+
+// The signed message has a single persisted layout so far.
+impl Versioned for SignedMessage {
+    const VERSION: u64 = 1;
+
+    fn decode_version(version: u64, d: &mut Decoder<'_>) -> Result<Self, decode::Error> {
+        match version {
+            1 => d.decode(),
+            _ => Err(decode::Error::Message(
+                "unknown SignedMessage layout version",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plum_address::Address;
+    use plum_crypto::{PrivateKey, PublicKey, Signature};
+
+    use super::{verify_secp_batch, SignedMessage};
+    use crate::unsigned_message::UnsignedMessage;
+
+    fn new_signed_message(nonce: u64) -> SignedMessage {
+        let privkey = PrivateKey::generate_secp256k1_privkey();
+        let pubkey = PublicKey::from_privkey(&privkey);
+        let from = Address::new_secp256k1_addr(&pubkey.into_vec()).unwrap();
+        let message = UnsignedMessage {
+            version: 0,
+            to: Address::new_id_addr(1000).unwrap(),
+            from,
+            nonce,
+            value: Default::default(),
+            gas_limit: 126_723u64.into(),
+            gas_price: 1_776_234u64.into(),
+            method: 0,
+            params: vec![],
+        };
+        let signature =
+            Signature::sign_secp256k1(privkey.into_vec(), message.cid().to_bytes()).unwrap();
+        SignedMessage { message, signature }
+    }
+
+    #[test]
+    fn verify_secp_batch_reports_failed_indices() {
+        assert_eq!(verify_secp_batch(&[]), Ok(()));
+
+        let mut messages = (0..5).map(new_signed_message).collect::<Vec<_>>();
+        assert_eq!(verify_secp_batch(&messages), Ok(()));
+
+        // the signature of the message 2 signs the other message.
+        messages[2].signature = messages[3].signature.clone();
+        assert_eq!(verify_secp_batch(&messages), Err(vec![2]));
+    }
+}
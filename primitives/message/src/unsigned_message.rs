@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 
 use plum_address::Address;
 use plum_bigint::{bigint_json, BigInt, BigIntRefWrapper, BigIntWrapper};
-use plum_types::{Gas, MethodNum};
+use plum_types::{Gas, MethodNum, Versioned};
 
 /// The unsigned message.
 #[derive(Eq, PartialEq, Clone, Debug, Hash, Serialize, Deserialize)]
@@ -135,6 +135,20 @@ impl<'b> decode::Decode<'b> for UnsignedMessage {
     }
 }
 
+// The message has a single persisted layout so far.
+impl Versioned for UnsignedMessage {
+    const VERSION: u64 = 1;
+
+    fn decode_version(version: u64, d: &mut Decoder<'_>) -> Result<Self, decode::Error> {
+        match version {
+            1 => d.decode(),
+            _ => Err(decode::Error::Message(
+                "unknown UnsignedMessage layout version",
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use plum_address::{set_network, Address, Network};
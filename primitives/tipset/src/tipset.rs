@@ -273,9 +273,9 @@ mod tests {
                 \"ParentStateRoot\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
                 \"ParentMessageReceipts\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
                 \"Messages\":{\"/\":\"bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i\"},\
-                \"BLSAggregate\":{\"Type\":\"bls\",\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
+                \"BLSAggregate\":{\"Type\":2,\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
                 \"Timestamp\":0,\
-                \"BlockSig\":{\"Type\":\"bls\",\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
+                \"BlockSig\":{\"Type\":2,\"Data\":\"Ym9vISBpbSBhIHNpZ25hdHVyZQ==\"},\
                 \"ForkSignaling\":0\
             }],\
             \"Height\":1\
@@ -12,8 +12,10 @@ use plum_bigint::{bigint_json, BigInt, BigIntWrapper};
 use plum_hash::H256;
 
 mod constants;
+mod versioned;
 
 pub use self::constants::*;
+pub use self::versioned::{decode_versioned, encode_versioned, Versioned};
 
 /// A sequential number assigned to an actor when created by the InitActor.
 /// This ID is embedded in ID-type addresses.
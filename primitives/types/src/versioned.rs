@@ -0,0 +1,37 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use minicbor::{decode, encode, Decoder};
+
+/// Versioned is implemented by the chain types persisted in the versioned envelope,
+/// a CBOR array of `[version, value]`, so that the data persisted by the older crate
+/// versions still decodes after the type gains fields.
+///
+/// The envelope is only for persisting, the network and CID encoding of the types is
+/// the bare current layout.
+pub trait Versioned: encode::Encode + Sized {
+    /// The version of the current layout.
+    const VERSION: u64;
+
+    /// Decode the value persisted with the layout of the `version`, upgrading the older
+    /// layouts to the current shape.
+    fn decode_version(version: u64, d: &mut Decoder<'_>) -> Result<Self, decode::Error>;
+}
+
+/// Encode the value into the versioned envelope with the current version.
+pub fn encode_versioned<T: Versioned>(value: &T) -> Vec<u8> {
+    minicbor::to_vec((T::VERSION, value)).expect("CBOR serialization shouldn't be failed")
+}
+
+/// Decode the value from the versioned envelope, the layout newer than the current
+/// version is rejected.
+pub fn decode_versioned<T: Versioned>(data: &[u8]) -> Result<T, decode::Error> {
+    let mut d = Decoder::new(data);
+    if d.array()? != Some(2) {
+        return Err(decode::Error::Message("expected a versioned envelope"));
+    }
+    let version = d.u64()?;
+    if version > T::VERSION {
+        return Err(decode::Error::Message("unknown layout version"));
+    }
+    T::decode_version(version, &mut d)
+}
@@ -0,0 +1,299 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use std::fmt;
+
+use ipfs_datastore::{ChangeOp, DataStore, DataStoreError, DataStoreTxn, Key, ToBranch};
+use ipfs_datastore::{DataStoreBatch, DataStoreRead, DataStoreWrite};
+use plum_actor::{MethodSend, OutOfGas, SYS_ERR_OUT_OF_GAS};
+use plum_address::Address;
+use plum_message::{MessageReceipt, UnsignedMessage};
+use plum_types::{Actor, ChainEpoch, Gas};
+
+use crate::charger::GasCharger;
+use crate::gas::{pricelist_by_epoch, Pricelist};
+
+/// The exit code of the message whose sender doesn't exist.
+pub const SYS_ERR_SENDER_INVALID: u8 = 1;
+/// The exit code of the message whose nonce doesn't match the sender.
+pub const SYS_ERR_SENDER_STATE_INVALID: u8 = 2;
+/// The exit code of the message invoking a method not supported.
+pub const SYS_ERR_INVALID_METHOD: u8 = 3;
+/// The exit code of the message whose receiver doesn't exist.
+pub const SYS_ERR_INVALID_RECEIVER: u8 = 5;
+/// The exit code of the message whose sender can't afford it.
+pub const SYS_ERR_INSUFFICIENT_FUNDS: u8 = 6;
+
+const ACTORS_PREFIX: &str = "/actors";
+
+/// The error returned when the message can't be applied because of the state store.
+#[derive(Debug)]
+pub enum ApplyError {
+    /// The stored actor cannot be decoded.
+    Decode(Key, String),
+    /// The underlying datastore error.
+    DataStore(DataStoreError),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Decode(key, err) => {
+                write!(f, "invalid actor stored under {}: {}", key, err)
+            }
+            ApplyError::DataStore(err) => write!(f, "datastore error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+impl From<DataStoreError> for ApplyError {
+    fn from(err: DataStoreError) -> Self {
+        ApplyError::DataStore(err)
+    }
+}
+
+/// Return the key of the actor of `addr` in the state store.
+pub fn actor_key(addr: &Address) -> Key {
+    Key::new(format!("{}/{}", ACTORS_PREFIX, addr))
+}
+
+/// Load the actor of `addr` from the state store, `None` if the actor doesn't exist.
+pub fn load_actor<DS: DataStoreRead>(
+    store: &DS,
+    addr: &Address,
+) -> Result<Option<Actor>, ApplyError> {
+    let key = actor_key(addr);
+    match store.get(&key) {
+        Ok(bytes) => minicbor::decode(&bytes)
+            .map(Some)
+            .map_err(|err| ApplyError::Decode(key, err.to_string())),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Store the actor of `addr` into the state store.
+pub fn store_actor<DS: DataStoreWrite>(
+    store: &mut DS,
+    addr: &Address,
+    actor: &Actor,
+) -> Result<(), ApplyError> {
+    let bytes = minicbor::to_vec(actor).expect("CBOR serialization of Actor shouldn't be failed");
+    Ok(store.put(actor_key(addr), bytes)?)
+}
+
+/// Apply the message to the actors of the state `store` at `epoch`, return its receipt.
+///
+/// Only the value transfers (`MethodSend`) between existing actors are executed, invoking other
+/// methods aborts with `SYS_ERR_INVALID_METHOD`. The gas limit is deducted from the sender
+/// upfront and the unused gas is refunded; when the execution aborts, its state changes are
+/// reverted but the gas used is still charged.
+///
+/// The clones of the `store` must share the same storage, the execution runs on a branch of it.
+pub fn apply_message<DS: DataStore>(
+    store: &mut DS,
+    msg: &UnsignedMessage,
+    epoch: ChainEpoch,
+) -> Result<MessageReceipt, ApplyError> {
+    let pricelist = pricelist_by_epoch(epoch);
+    let msg_size = minicbor::to_vec(msg)
+        .expect("CBOR serialization of UnsignedMessage shouldn't be failed")
+        .len();
+    let on_chain_message = pricelist.on_chain_message(msg_size);
+    if on_chain_message > msg.gas_limit {
+        return Ok(receipt(SYS_ERR_OUT_OF_GAS, Gas::from(0)));
+    }
+
+    // validate the sender before charging any gas.
+    let mut sender = match load_actor(store, &msg.from)? {
+        Some(sender) => sender,
+        None => return Ok(receipt(SYS_ERR_SENDER_INVALID, Gas::from(0))),
+    };
+    if sender.nonce != msg.nonce {
+        return Ok(receipt(SYS_ERR_SENDER_STATE_INVALID, Gas::from(0)));
+    }
+    if sender.balance < msg.required_funds() {
+        return Ok(receipt(SYS_ERR_INSUFFICIENT_FUNDS, Gas::from(0)));
+    }
+
+    let mut charger = GasCharger::new(pricelist, msg.gas_limit.clone());
+    charger
+        .charge("OnChainMessage", on_chain_message)
+        .expect("the gas limit covers the message; qed");
+    sender.nonce += 1;
+    sender.balance -= &msg.gas_limit * &msg.gas_price;
+    store_actor(store, &msg.from, &sender)?;
+
+    let mut execution = store.branch();
+    let exit_code = match execute(&mut execution, &mut charger, pricelist, msg) {
+        Ok(0) => {
+            execution.commit()?;
+            0
+        }
+        Ok(exit_code) => {
+            execution.discard()?;
+            exit_code
+        }
+        Err(ExecutionError::OutOfGas(err)) => {
+            execution.discard()?;
+            err.exit_code()
+        }
+        Err(ExecutionError::Apply(err)) => return Err(err),
+    };
+
+    // refund the unused gas.
+    let gas_used = charger.gas_used().clone();
+    let mut sender = load_actor(store, &msg.from)?.expect("the sender is stored above; qed");
+    sender.balance += (&msg.gas_limit - &gas_used) * &msg.gas_price;
+    store_actor(store, &msg.from, &sender)?;
+    Ok(receipt(exit_code, gas_used))
+}
+
+/// Apply the message to a branch of the `base_state` at `epoch` without committing it,
+/// return the receipt and the state changes the message would make, ordered by key.
+///
+/// This is the dry run used by the gas estimation, the `base_state` is never modified.
+pub fn apply_message_dry<DS: DataStore>(
+    msg: &UnsignedMessage,
+    base_state: &DS,
+    epoch: ChainEpoch,
+) -> Result<(MessageReceipt, Vec<(Key, ChangeOp)>), ApplyError> {
+    let mut branch = base_state.branch();
+    let receipt = apply_message(&mut branch, msg, epoch)?;
+    let changes = branch.changes();
+    branch.discard()?;
+    Ok((receipt, changes))
+}
+
+enum ExecutionError {
+    OutOfGas(OutOfGas),
+    Apply(ApplyError),
+}
+
+impl From<OutOfGas> for ExecutionError {
+    fn from(err: OutOfGas) -> Self {
+        ExecutionError::OutOfGas(err)
+    }
+}
+
+impl From<ApplyError> for ExecutionError {
+    fn from(err: ApplyError) -> Self {
+        ExecutionError::Apply(err)
+    }
+}
+
+// Invoke the method of the message, return the exit code.
+fn execute<DS: DataStore, P: Pricelist>(
+    store: &mut DS,
+    charger: &mut GasCharger<P>,
+    pricelist: &P,
+    msg: &UnsignedMessage,
+) -> Result<u8, ExecutionError> {
+    charger.charge(
+        "OnMethodInvocation",
+        pricelist.on_method_invocation(msg.value.clone(), msg.method),
+    )?;
+    if msg.method != MethodSend {
+        return Ok(SYS_ERR_INVALID_METHOD);
+    }
+    if load_actor(store, &msg.to)?.is_none() {
+        return Ok(SYS_ERR_INVALID_RECEIVER);
+    }
+
+    let mut sender = load_actor(store, &msg.from)?.expect("the sender is stored; qed");
+    sender.balance -= &msg.value;
+    store_actor(store, &msg.from, &sender)?;
+    // load the receiver after storing the sender, which may be the same actor.
+    let mut receiver = load_actor(store, &msg.to)?.expect("the receiver exists; qed");
+    receiver.balance += &msg.value;
+    store_actor(store, &msg.to, &receiver)?;
+    Ok(0)
+}
+
+fn receipt(exit_code: u8, gas_used: Gas) -> MessageReceipt {
+    MessageReceipt {
+        exit_code,
+        r#return: vec![],
+        gas_used,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::Cid;
+    use ipfs_datastore::{MapDataStore, SyncDataStore};
+    use plum_bigint::BigInt;
+
+    use super::*;
+
+    fn actor(balance: u64) -> Actor {
+        let cid: Cid = "bafyreicmaj5hhoy5mgqvamfhgexxyergw7hdeshizghodwkjg6qmpoco7i"
+            .parse()
+            .unwrap();
+        Actor {
+            code: cid.clone(),
+            head: cid,
+            nonce: 0,
+            balance: BigInt::from(balance),
+        }
+    }
+
+    #[test]
+    fn test_apply_message_dry() {
+        let from = Address::new_id_addr(100).unwrap();
+        let to = Address::new_id_addr(101).unwrap();
+        let mut base_state = SyncDataStore::new(MapDataStore::new());
+        store_actor(&mut base_state, &from, &actor(1_000_000_000)).unwrap();
+        store_actor(&mut base_state, &to, &actor(0)).unwrap();
+        let before = (
+            base_state.get(&actor_key(&from)).unwrap(),
+            base_state.get(&actor_key(&to)).unwrap(),
+        );
+
+        let msg = UnsignedMessage {
+            version: 0,
+            to: to.clone(),
+            from: from.clone(),
+            nonce: 0,
+            value: BigInt::from(100),
+            gas_price: BigInt::from(1),
+            gas_limit: BigInt::from(1_000_000),
+            method: MethodSend,
+            params: vec![],
+        };
+        let (receipt, changes) = apply_message_dry(&msg, &base_state, 0).unwrap();
+        assert_eq!(receipt.exit_code, 0);
+        let pricelist = pricelist_by_epoch(0);
+        let msg_size = minicbor::to_vec(&msg).unwrap().len();
+        let expected_gas = pricelist.on_chain_message(msg_size)
+            + pricelist.on_method_invocation(msg.value.clone(), MethodSend);
+        assert_eq!(receipt.gas_used, expected_gas);
+        assert!(receipt.gas_used > BigInt::from(0));
+
+        // the would-be changes pay the gas and transfer the value.
+        let keys = changes
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![actor_key(&from), actor_key(&to)]);
+        let decode = |op: &ChangeOp| match op {
+            ChangeOp::Put(bytes) => minicbor::decode::<Actor>(bytes).unwrap(),
+            ChangeOp::Delete => panic!("the actor shouldn't be deleted"),
+        };
+        let sender = decode(&changes[0].1);
+        assert_eq!(sender.nonce, 1);
+        assert_eq!(
+            sender.balance,
+            BigInt::from(1_000_000_000 - 100) - &receipt.gas_used
+        );
+        assert_eq!(decode(&changes[1].1).balance, BigInt::from(100));
+
+        // the base state is unchanged.
+        let after = (
+            base_state.get(&actor_key(&from)).unwrap(),
+            base_state.get(&actor_key(&to)).unwrap(),
+        );
+        assert_eq!(before, after);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2019-2020 PolkaX Authors. Licensed under GPL-3.0.
+
+use plum_actor::{ChargeGas, OutOfGas};
+use plum_crypto::SignatureType;
+use plum_sector::{SealVerifyInfo, WindowPoStVerifyInfo};
+use plum_types::Gas;
+
+use crate::gas::{Pricelist, Size};
+
+/// GasCharger charges the gas of the operations against the gas budget of a message,
+/// the prices come from the pricelist.
+///
+/// Once an operation exhausts the budget, the whole budget is used up
+/// and the invocation should abort with `SYS_ERR_OUT_OF_GAS`.
+pub struct GasCharger<'a, P: Pricelist> {
+    pricelist: &'a P,
+    limit: Gas,
+    used: Gas,
+}
+
+impl<'a, P: Pricelist> GasCharger<'a, P> {
+    /// Create a new gas charger with the gas budget `limit`.
+    pub fn new(pricelist: &'a P, limit: Gas) -> Self {
+        Self {
+            pricelist,
+            limit,
+            used: Gas::from(0),
+        }
+    }
+
+    /// Return the gas used so far.
+    pub fn gas_used(&self) -> &Gas {
+        &self.used
+    }
+
+    /// Return the remaining gas of the budget.
+    pub fn gas_available(&self) -> Gas {
+        &self.limit - &self.used
+    }
+
+    /// Charge the `gas` of the `operation`.
+    pub fn charge(&mut self, operation: &'static str, gas: Gas) -> Result<(), OutOfGas> {
+        let available = self.gas_available();
+        if gas > available {
+            self.used = self.limit.clone();
+            return Err(OutOfGas {
+                operation,
+                required: gas,
+                available,
+            });
+        }
+        self.used += gas;
+        Ok(())
+    }
+
+    /// Charge the gas of loading an object of `data_size` from the IPLD store.
+    pub fn charge_ipld_get(&mut self, data_size: Size) -> Result<(), OutOfGas> {
+        let gas = self.pricelist.on_ipld_get(data_size);
+        self.charge("OnIpldGet", gas)
+    }
+
+    /// Charge the gas of storing an object of `data_size` into the IPLD store.
+    pub fn charge_ipld_put(&mut self, data_size: Size) -> Result<(), OutOfGas> {
+        let gas = self.pricelist.on_ipld_put(data_size);
+        self.charge("OnIpldPut", gas)
+    }
+
+    /// Charge the gas of verifying a signature over `plain_text_size` bytes.
+    pub fn charge_verify_signature(
+        &mut self,
+        sig_type: SignatureType,
+        plain_text_size: Size,
+    ) -> Result<(), OutOfGas> {
+        let gas = self
+            .pricelist
+            .on_verify_signature(sig_type, plain_text_size);
+        self.charge("OnVerifySignature", gas)
+    }
+
+    /// Charge the gas of hashing `data_size` bytes.
+    pub fn charge_hashing(&mut self, data_size: Size) -> Result<(), OutOfGas> {
+        let gas = self.pricelist.on_hashing(data_size);
+        self.charge("OnHashing", gas)
+    }
+
+    /// Charge the gas of verifying a seal proof.
+    pub fn charge_verify_seal(&mut self, info: SealVerifyInfo) -> Result<(), OutOfGas> {
+        let gas = self.pricelist.on_verify_seal(info);
+        self.charge("OnVerifySeal", gas)
+    }
+
+    /// Charge the gas of verifying a window PoSt proof.
+    pub fn charge_verify_post(&mut self, info: WindowPoStVerifyInfo) -> Result<(), OutOfGas> {
+        let gas = self.pricelist.on_verify_post(info);
+        self.charge("OnVerifyPost", gas)
+    }
+}
+
+impl<'a, P: Pricelist> ChargeGas for GasCharger<'a, P> {
+    fn charge_ipld_get(&mut self, data_size: Size) -> Result<(), OutOfGas> {
+        GasCharger::charge_ipld_get(self, data_size)
+    }
+
+    fn charge_ipld_put(&mut self, data_size: Size) -> Result<(), OutOfGas> {
+        GasCharger::charge_ipld_put(self, data_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plum_actor::SYS_ERR_OUT_OF_GAS;
+
+    use super::*;
+    use crate::gas::pricelist_by_epoch;
+
+    #[test]
+    fn test_out_of_gas() {
+        let pricelist = pricelist_by_epoch(0);
+        let mut charger = GasCharger::new(pricelist, Gas::from(100));
+
+        // load the state (10 + 10), hash it (5 + 2 * 10), store the state (20 + 2 * 10)
+        charger.charge_ipld_get(10).unwrap();
+        charger.charge_hashing(10).unwrap();
+        charger.charge_ipld_put(10).unwrap();
+        assert_eq!(charger.gas_used(), &Gas::from(85));
+
+        // the second store exhausts the budget.
+        let err = charger.charge_ipld_put(10).unwrap_err();
+        assert_eq!(err.operation, "OnIpldPut");
+        assert_eq!(err.required, Gas::from(40));
+        assert_eq!(err.available, Gas::from(15));
+        assert_eq!(err.exit_code(), SYS_ERR_OUT_OF_GAS);
+        assert_eq!(charger.gas_used(), &Gas::from(100));
+        assert!(charger.charge_ipld_get(0).is_err());
+    }
+}
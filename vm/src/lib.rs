@@ -4,9 +4,18 @@
 
 #![deny(missing_docs)]
 
+mod apply;
+mod charger;
 mod gas;
 mod gas_v0;
 mod types;
 
+pub use self::apply::{
+    actor_key, apply_message, apply_message_dry, load_actor, store_actor, ApplyError,
+    SYS_ERR_INSUFFICIENT_FUNDS, SYS_ERR_INVALID_METHOD, SYS_ERR_INVALID_RECEIVER,
+    SYS_ERR_SENDER_INVALID, SYS_ERR_SENDER_STATE_INVALID,
+};
+pub use self::charger::GasCharger;
 pub use self::gas::*;
 pub use self::types::ExecutionResult;
+pub use plum_actor::{OutOfGas, SYS_ERR_OUT_OF_GAS};